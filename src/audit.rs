@@ -0,0 +1,96 @@
+use crate::config::Config;
+
+/// A destructive operation reported to the team audit webhook.
+pub struct AuditEvent<'a> {
+    pub action: AuditAction,
+    pub database: &'a str,
+    pub backend: &'a str,
+    pub branches: &'a [String],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AuditAction {
+    BranchDeleted,
+    BranchesCleanedUp,
+    BranchesPruned,
+    ProjectDestroyed,
+}
+
+impl AuditAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::BranchDeleted => "branch.deleted",
+            Self::BranchesCleanedUp => "branches.cleaned_up",
+            Self::BranchesPruned => "branches.pruned",
+            Self::ProjectDestroyed => "project.destroyed",
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Self::BranchDeleted => "deleted",
+            Self::BranchesCleanedUp => "cleaned up",
+            Self::BranchesPruned => "pruned",
+            Self::ProjectDestroyed => "destroyed",
+        }
+    }
+}
+
+/// Send an audit event if `audit.webhook` is configured.
+///
+/// Delivery failures are logged and never fail the operation itself.
+pub async fn record(config: &Config, event: AuditEvent<'_>) {
+    let Some(url) = config.audit.as_ref().map(|a| a.webhook.as_str()) else {
+        return;
+    };
+
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let host = hostname();
+
+    let target = match event.action {
+        AuditAction::ProjectDestroyed => format!("database '{}'", event.database),
+        _ => format!(
+            "{} from database '{}'",
+            event.branches.join(", "),
+            event.database
+        ),
+    };
+    let text = format!(
+        "pgbranch: {}@{} {} {} ({})",
+        user,
+        host,
+        event.action.verb(),
+        target,
+        event.backend
+    );
+
+    let body = serde_json::json!({
+        "text": text,
+        "event": event.action.as_str(),
+        "database": event.database,
+        "backend": event.backend,
+        "branches": event.branches,
+        "user": user,
+        "host": host,
+        "timestamp": chrono::Utc::now(),
+    });
+
+    if let Err(e) = crate::webhook::post_json(url, &body).await {
+        log::warn!("Failed to send audit event: {}", e);
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|h| h.trim().to_string())
+        })
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -1,4 +1,7 @@
-use super::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport};
+use super::{
+    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, PlannedAction,
+};
+use crate::errors::coded;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -130,6 +133,37 @@ impl DBLabBackend {
             .ok_or_else(|| anyhow::anyhow!("No snapshots available"))
     }
 
+    /// Snapshot a new clone starts from: the parent clone's, or the latest one.
+    async fn source_snapshot(&self, from_branch: Option<&str>) -> Result<String> {
+        let snapshot_id = if let Some(from) = from_branch {
+            let clones = self.list_branches().await?;
+            clones
+                .into_iter()
+                .find(|c| c.name == from)
+                .map(|c| c.database_name)
+        } else {
+            None
+        };
+
+        match snapshot_id {
+            Some(id) => Ok(id),
+            None => self.get_latest_snapshot().await,
+        }
+    }
+
+    async fn find_clone(&self, branch_name: &str) -> Result<DBLabClone> {
+        let clone_name = Self::normalize_clone_name(branch_name);
+
+        let clones: ListClonesResponse = self
+            .make_request(reqwest::Method::GET, "/api/clones", None::<&()>)
+            .await?;
+        clones
+            .clones
+            .into_iter()
+            .find(|c| c.name == clone_name)
+            .ok_or_else(|| coded!(BranchNotFound, "Clone '{}' not found", branch_name))
+    }
+
     fn normalize_clone_name(branch_name: &str) -> String {
         branch_name
             .to_lowercase()
@@ -155,21 +189,7 @@ impl DatabaseBranchingBackend for DBLabBackend {
         from_branch: Option<&str>,
     ) -> Result<BranchInfo> {
         let clone_name = Self::normalize_clone_name(branch_name);
-
-        let snapshot_id = if let Some(from) = from_branch {
-            let clones = self.list_branches().await?;
-            clones
-                .into_iter()
-                .find(|c| c.name == from)
-                .map(|c| c.database_name)
-        } else {
-            None
-        };
-
-        let snapshot_id = match snapshot_id {
-            Some(id) => id,
-            None => self.get_latest_snapshot().await?,
-        };
+        let snapshot_id = self.source_snapshot(from_branch).await?;
 
         let request = CreateCloneRequest {
             clone_name: clone_name.clone(),
@@ -186,20 +206,13 @@ impl DatabaseBranchingBackend for DBLabBackend {
             parent_branch: from_branch.map(|s| s.to_string()),
             database_name: response.clone.snapshot_id,
             state: Some("running".to_string()),
+            health: None,
+            last_used_at: None,
         })
     }
 
     async fn delete_branch(&self, branch_name: &str) -> Result<()> {
-        let clone_name = Self::normalize_clone_name(branch_name);
-
-        let clones: ListClonesResponse = self
-            .make_request(reqwest::Method::GET, "/api/clones", None::<&()>)
-            .await?;
-        let clone = clones
-            .clones
-            .into_iter()
-            .find(|c| c.name == clone_name)
-            .ok_or_else(|| anyhow::anyhow!("Clone '{}' not found", branch_name))?;
+        let clone = self.find_clone(branch_name).await?;
 
         let path = format!("/api/clones/{}", clone.id);
         let _: serde_json::Value = self
@@ -209,6 +222,31 @@ impl DatabaseBranchingBackend for DBLabBackend {
         Ok(())
     }
 
+    async fn plan_create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        let snapshot_id = self.source_snapshot(from_branch).await?;
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!(
+                "POST {}/api/clones (cloneName: {}, snapshotID: {})",
+                self.api_url,
+                Self::normalize_clone_name(branch_name),
+                snapshot_id
+            ),
+        )])
+    }
+
+    async fn plan_delete_branch(&self, branch_name: &str) -> Result<Vec<PlannedAction>> {
+        let clone = self.find_clone(branch_name).await?;
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!("DELETE {}/api/clones/{}", self.api_url, clone.id),
+        )])
+    }
+
     async fn list_branches(&self) -> Result<Vec<BranchInfo>> {
         let response: ListClonesResponse = self
             .make_request(reqwest::Method::GET, "/api/clones", None::<&()>)
@@ -223,6 +261,8 @@ impl DatabaseBranchingBackend for DBLabBackend {
                 parent_branch: None,
                 database_name: clone.snapshot_id,
                 state: Some("running".to_string()),
+                health: None,
+                last_used_at: None,
             })
             .collect();
 
@@ -241,7 +281,7 @@ impl DatabaseBranchingBackend for DBLabBackend {
         branches
             .into_iter()
             .find(|b| b.name == clone_name)
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' does not exist", branch_name))
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' does not exist", branch_name))
     }
 
     async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo> {
@@ -254,7 +294,7 @@ impl DatabaseBranchingBackend for DBLabBackend {
             .clones
             .into_iter()
             .find(|c| c.name == clone_name)
-            .ok_or_else(|| anyhow::anyhow!("Clone '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Clone '{}' not found", branch_name))?;
 
         let db = clone.db;
         let connection_string = format!(
@@ -300,4 +340,8 @@ impl DatabaseBranchingBackend for DBLabBackend {
     fn backend_name(&self) -> &'static str {
         "Database Lab Engine"
     }
+
+    fn backend_type(&self) -> &'static str {
+        "dblab"
+    }
 }
@@ -2,6 +2,8 @@
 use super::dblab::DBLabBackend;
 #[cfg(feature = "backend-local")]
 use super::local::LocalBackend;
+#[cfg(feature = "backend-mock")]
+use super::mock::MockBackend;
 #[cfg(feature = "backend-neon")]
 use super::neon::NeonBackend;
 #[cfg(feature = "backend-postgres-template")]
@@ -9,57 +11,165 @@ use super::postgres_template::PostgresTemplateBackend;
 #[cfg(feature = "backend-xata")]
 use super::xata::XataBackend;
 use super::DatabaseBranchingBackend;
-use crate::config::{Config, NamedBackendConfig};
-use anyhow::{Context, Result};
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum BackendType {
-    #[cfg(feature = "backend-local")]
-    Local,
-    #[cfg(feature = "backend-postgres-template")]
-    PostgresTemplate,
-    #[cfg(feature = "backend-neon")]
-    Neon,
-    #[cfg(feature = "backend-dblab")]
-    DBLab,
-    #[cfg(feature = "backend-xata")]
-    Xata,
-}
-
-impl BackendType {
-    pub fn from_str(s: &str) -> Result<Self> {
-        match s.to_lowercase().as_str() {
-            #[cfg(feature = "backend-local")]
-            "local" | "docker" => Ok(BackendType::Local),
-            #[cfg(not(feature = "backend-local"))]
-            "local" | "docker" => anyhow::bail!("Local backend not compiled. Rebuild with --features backend-local"),
-
-            #[cfg(feature = "backend-postgres-template")]
-            "postgres_template" | "postgres" | "postgresql" => Ok(BackendType::PostgresTemplate),
-            #[cfg(not(feature = "backend-postgres-template"))]
-            "postgres_template" | "postgres" | "postgresql" => anyhow::bail!("PostgreSQL template backend not compiled. Rebuild with --features backend-postgres-template"),
-
-            #[cfg(feature = "backend-neon")]
-            "neon" => Ok(BackendType::Neon),
-            #[cfg(not(feature = "backend-neon"))]
-            "neon" => anyhow::bail!("Neon backend not compiled. Rebuild with --features backend-neon"),
-
-            #[cfg(feature = "backend-dblab")]
-            "dblab" | "database_lab" => Ok(BackendType::DBLab),
-            #[cfg(not(feature = "backend-dblab"))]
-            "dblab" | "database_lab" => anyhow::bail!("DBLab backend not compiled. Rebuild with --features backend-dblab"),
-
-            #[cfg(feature = "backend-xata")]
-            "xata" | "xata_lite" => Ok(BackendType::Xata),
-            #[cfg(not(feature = "backend-xata"))]
-            "xata" | "xata_lite" => anyhow::bail!("Xata backend not compiled. Rebuild with --features backend-xata"),
-
-            _ => anyhow::bail!("Unknown backend type: {}. Valid types: local, postgres_template, neon, dblab, xata", s),
+use crate::config::{Config, EnvConfig, NamedBackendConfig};
+use crate::errors::coded;
+#[cfg(any(
+    feature = "backend-local",
+    feature = "backend-mock",
+    feature = "backend-postgres-template",
+    feature = "backend-neon",
+    feature = "backend-dblab",
+    feature = "backend-xata"
+))]
+use anyhow::Context;
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+
+/// Builds a backend from its configuration.
+pub type CreateFn = for<'a> fn(
+    &'a Config,
+    &'a NamedBackendConfig,
+) -> BoxFuture<'a, Result<Box<dyn DatabaseBranchingBackend>>>;
+
+/// `Some` of the constructor `$create` when `$feature` is compiled in.
+macro_rules! register {
+    ($feature:literal, $create:ident) => {{
+        #[cfg(feature = $feature)]
+        let create: Option<CreateFn> = Some(|config, named| Box::pin($create(config, named)));
+        #[cfg(not(feature = $feature))]
+        let create: Option<CreateFn> = None;
+        create
+    }};
+}
+
+/// A backend type pgbranch knows about, and how to build it if this build
+/// includes it.
+#[derive(Clone, Copy)]
+pub struct BackendInfo {
+    /// Canonical name, as written in `.pgbranch.yml`
+    pub name: &'static str,
+    /// Other spellings accepted for `name`
+    pub aliases: &'static [&'static str],
+    /// Cargo feature that compiles it in
+    pub feature: &'static str,
+    /// `None` when the feature is off
+    pub create: Option<CreateFn>,
+}
+
+/// Every backend type, in the order they're listed to users.
+pub const BACKENDS: [BackendInfo; 5] = [
+    BackendInfo {
+        name: "local",
+        aliases: &["docker"],
+        feature: "backend-local",
+        create: register!("backend-local", create_local),
+    },
+    BackendInfo {
+        name: "postgres_template",
+        aliases: &["postgres", "postgresql"],
+        feature: "backend-postgres-template",
+        create: register!("backend-postgres-template", create_postgres_template),
+    },
+    BackendInfo {
+        name: "neon",
+        aliases: &[],
+        feature: "backend-neon",
+        create: register!("backend-neon", create_neon),
+    },
+    BackendInfo {
+        name: "dblab",
+        aliases: &["database_lab"],
+        feature: "backend-dblab",
+        create: register!("backend-dblab", create_dblab),
+    },
+    BackendInfo {
+        name: "xata",
+        aliases: &["xata_lite"],
+        feature: "backend-xata",
+        create: register!("backend-xata", create_xata),
+    },
+];
+
+/// Backend types for testing pgbranch itself, never listed to users.
+pub const HIDDEN_BACKENDS: [BackendInfo; 1] = [BackendInfo {
+    name: "mock",
+    aliases: &[],
+    feature: "backend-mock",
+    create: register!("backend-mock", create_mock),
+}];
+
+impl BackendInfo {
+    /// The backend type named `s` or one of its aliases, ignoring case.
+    pub fn find(s: &str) -> Option<&'static BackendInfo> {
+        let s = s.to_lowercase();
+        BACKENDS
+            .iter()
+            .chain(&HIDDEN_BACKENDS)
+            .find(|info| info.name == s || info.aliases.contains(&s.as_str()))
+    }
+
+    /// The backend type named `s`, failing if it's unknown or not compiled in.
+    pub fn resolve(s: &str) -> Result<&'static BackendInfo> {
+        let Some(info) = Self::find(s) else {
+            let valid: Vec<&str> = BACKENDS.iter().map(|info| info.name).collect();
+            return Err(coded!(
+                UnknownBackend,
+                "Unknown backend type: {}. Valid types: {}",
+                s,
+                valid.join(", ")
+            ));
+        };
+        if !info.is_compiled() {
+            return Err(info.not_compiled_error());
+        }
+        Ok(info)
+    }
+
+    /// Backend types this build includes.
+    pub fn available() -> impl Iterator<Item = &'static BackendInfo> {
+        BACKENDS.iter().filter(|info| info.is_compiled())
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        self.create.is_some()
+    }
+
+    /// Normalize a backend type string, resolving aliases (e.g. `docker` → `local`).
+    pub fn canonical_name(s: &str) -> String {
+        match Self::find(s) {
+            Some(info) => info.name.to_string(),
+            None => s.to_lowercase(),
         }
     }
 
     pub fn is_local(s: &str) -> bool {
-        matches!(s.to_lowercase().as_str(), "local" | "docker")
+        Self::find(s).is_some_and(|info| info.name == "local")
+    }
+
+    /// Error for a backend type this build doesn't include, saying how to get it.
+    pub fn not_compiled_error(&self) -> anyhow::Error {
+        let available: Vec<&str> = Self::available().map(|info| info.name).collect();
+        coded!(
+            UnknownBackend,
+            "The {} backend isn't included in this build of pgbranch (built without the '{}' feature). Rebuild it with that feature, e.g. 'cargo install --path . --features {}'{}",
+            self.name,
+            self.feature,
+            self.feature,
+            if available.is_empty() {
+                String::new()
+            } else {
+                format!(", or use one of the backends it has: {}", available.join(", "))
+            }
+        )
+    }
+}
+
+/// Error for when backend type `name` is needed but not compiled in.
+#[allow(dead_code)] // only reached in builds missing a backend
+pub fn not_compiled(name: &str) -> anyhow::Error {
+    match BackendInfo::find(name) {
+        Some(info) => info.not_compiled_error(),
+        None => coded!(UnknownBackend, "Unknown backend type: {}", name),
     }
 }
 
@@ -73,64 +183,88 @@ pub async fn create_backend_from_named_config(
     config: &Config,
     named: &NamedBackendConfig,
 ) -> Result<Box<dyn DatabaseBranchingBackend>> {
-    let backend_type = BackendType::from_str(&named.backend_type)?;
-
-    match backend_type {
-        #[cfg(feature = "backend-local")]
-        BackendType::Local => {
-            let local_config = named.local.as_ref();
-            let backend = LocalBackend::new(&named.name, config, local_config)
-                .await
-                .context("Failed to create local backend")?;
-            Ok(Box::new(backend))
-        }
-        #[cfg(feature = "backend-postgres-template")]
-        BackendType::PostgresTemplate => {
-            let backend = PostgresTemplateBackend::new(config)
-                .await
-                .context("Failed to create PostgreSQL template backend")?;
-            Ok(Box::new(backend))
-        }
-        #[cfg(feature = "backend-neon")]
-        BackendType::Neon => {
-            if let Some(ref neon_config) = named.neon {
-                let backend = NeonBackend::new(
-                    resolve_env_var(&neon_config.api_key)?,
-                    resolve_env_var(&neon_config.project_id)?,
-                    Some(neon_config.base_url.clone()),
-                )?;
-                Ok(Box::new(backend))
-            } else {
-                anyhow::bail!("Neon backend selected but no neon configuration provided");
-            }
-        }
-        #[cfg(feature = "backend-dblab")]
-        BackendType::DBLab => {
-            if let Some(ref dblab_config) = named.dblab {
-                let backend = DBLabBackend::new(
-                    resolve_env_var(&dblab_config.api_url)?,
-                    resolve_env_var(&dblab_config.auth_token)?,
-                )?;
-                Ok(Box::new(backend))
-            } else {
-                anyhow::bail!("DBLab backend selected but no dblab configuration provided");
-            }
-        }
-        #[cfg(feature = "backend-xata")]
-        BackendType::Xata => {
-            if let Some(ref xata_config) = named.xata {
-                let backend = XataBackend::new(
-                    resolve_env_var(&xata_config.api_key)?,
-                    resolve_env_var(&xata_config.organization_id)?,
-                    resolve_env_var(&xata_config.project_id)?,
-                    Some(xata_config.base_url.clone()),
-                )?;
-                Ok(Box::new(backend))
-            } else {
-                anyhow::bail!("Xata backend selected but no xata configuration provided");
-            }
-        }
-    }
+    let info = BackendInfo::resolve(&named.backend_type)?;
+    let create = info.create.ok_or_else(|| info.not_compiled_error())?;
+    create(config, named).await
+}
+
+#[cfg(feature = "backend-local")]
+async fn create_local(
+    config: &Config,
+    named: &NamedBackendConfig,
+) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    let backend = LocalBackend::new(&named.name, config, named.local.as_ref())
+        .await
+        .context("Failed to create local backend")?;
+    Ok(Box::new(backend))
+}
+
+#[cfg(feature = "backend-mock")]
+async fn create_mock(
+    config: &Config,
+    named: &NamedBackendConfig,
+) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    let backend = MockBackend::new(&named.name, config).context("Failed to create mock backend")?;
+    Ok(Box::new(backend))
+}
+
+#[cfg(feature = "backend-postgres-template")]
+async fn create_postgres_template(
+    config: &Config,
+    _named: &NamedBackendConfig,
+) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    let backend = PostgresTemplateBackend::new(config)
+        .await
+        .context("Failed to create PostgreSQL template backend")?;
+    Ok(Box::new(backend))
+}
+
+#[cfg(feature = "backend-neon")]
+async fn create_neon(
+    _config: &Config,
+    named: &NamedBackendConfig,
+) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    let Some(ref neon_config) = named.neon else {
+        anyhow::bail!("Neon backend selected but no neon configuration provided");
+    };
+    let backend = NeonBackend::new(
+        resolve_secret(&neon_config.api_key, "neon")?,
+        resolve_env_var(&neon_config.project_id)?,
+        Some(neon_config.base_url.clone()),
+    )?;
+    Ok(Box::new(backend))
+}
+
+#[cfg(feature = "backend-dblab")]
+async fn create_dblab(
+    _config: &Config,
+    named: &NamedBackendConfig,
+) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    let Some(ref dblab_config) = named.dblab else {
+        anyhow::bail!("DBLab backend selected but no dblab configuration provided");
+    };
+    let backend = DBLabBackend::new(
+        resolve_env_var(&dblab_config.api_url)?,
+        resolve_secret(&dblab_config.auth_token, "dblab")?,
+    )?;
+    Ok(Box::new(backend))
+}
+
+#[cfg(feature = "backend-xata")]
+async fn create_xata(
+    _config: &Config,
+    named: &NamedBackendConfig,
+) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    let Some(ref xata_config) = named.xata else {
+        anyhow::bail!("Xata backend selected but no xata configuration provided");
+    };
+    let backend = XataBackend::new(
+        resolve_secret(&xata_config.api_key, "xata")?,
+        resolve_env_var(&xata_config.organization_id)?,
+        resolve_env_var(&xata_config.project_id)?,
+        Some(xata_config.base_url.clone()),
+    )?;
+    Ok(Box::new(backend))
 }
 
 /// Resolve a single backend by name (or the default).
@@ -142,10 +276,13 @@ pub async fn resolve_backend(config: &Config, backend_name: Option<&str>) -> Res
     // If backends list is populated, use it
     if !backends.is_empty() {
         let named = if let Some(name) = backend_name {
-            backends
-                .iter()
-                .find(|b| b.name == name)
-                .ok_or_else(|| anyhow::anyhow!("Backend '{}' not found in configuration", name))?
+            backends.iter().find(|b| b.name == name).ok_or_else(|| {
+                coded!(
+                    DatabaseNotFound,
+                    "Backend '{}' not found in configuration",
+                    name
+                )
+            })?
         } else {
             backends
                 .iter()
@@ -163,7 +300,10 @@ pub async fn resolve_backend(config: &Config, backend_name: Option<&str>) -> Res
 
     // No backends or backend config — fall back to auto-detection
     if backend_name.is_some() {
-        anyhow::bail!("--database specified but no backends configured");
+        return Err(coded!(
+            DatabaseNotFound,
+            "--database specified but no backends configured"
+        ));
     }
 
     let backend = create_backend_default(config).await?;
@@ -202,48 +342,87 @@ pub async fn create_all_backends(config: &Config) -> Result<Vec<NamedBackend>> {
 
 /// Auto-detect backend when no config section is present.
 async fn create_backend_default(config: &Config) -> Result<Box<dyn DatabaseBranchingBackend>> {
+    // PGBRANCH_BACKEND and the local settings from the environment stand in
+    // for the missing config section
+    let env_config = EnvConfig::load_from_env()?;
+    let default_named = |name: String, backend_type: &str| NamedBackendConfig {
+        name,
+        backend_type: backend_type.to_string(),
+        default: true,
+        local: None,
+        neon: None,
+        dblab: None,
+        xata: None,
+        branch_filter: None,
+    };
+    let forced_local = match env_config.backend_type.as_deref() {
+        Some(backend_type) if BackendInfo::is_local(backend_type) => true,
+        Some(backend_type) => {
+            let named = default_named("default".to_string(), backend_type);
+            return create_backend_from_named_config(config, &named).await;
+        }
+        None => false,
+    };
+
     // Backward compatibility: if database config differs from defaults,
     // use postgres_template backend
-    #[cfg(feature = "backend-postgres-template")]
-    if config.database.host != "localhost"
-        || config.database.port != 5432
-        || config.database.template_database != "template0"
+    if !forced_local
+        && (config.database.host != "localhost"
+            || config.database.port != 5432
+            || config.database.template_database != "template0")
     {
-        let backend = PostgresTemplateBackend::new(config)
-            .await
-            .context("Failed to create PostgreSQL template backend")?;
-        return Ok(Box::new(backend));
+        let named = default_named("default".to_string(), "postgres_template");
+        return create_backend_from_named_config(config, &named).await;
     }
 
-    #[cfg(not(feature = "backend-postgres-template"))]
-    if config.database.host != "localhost"
-        || config.database.port != 5432
-        || config.database.template_database != "template0"
-    {
-        anyhow::bail!("PostgreSQL template backend not compiled. Rebuild with --features backend-postgres-template");
-    }
+    // Default to local backend, named after the project directory (the one
+    // holding the config, so it's the same from any subdirectory)
+    let project_dir = Config::find_config_file()
+        .ok()
+        .flatten()
+        .and_then(|p| p.parent().map(std::path::Path::to_path_buf))
+        .or_else(|| std::env::current_dir().ok());
+    let default_name = project_dir
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "default".to_string());
+    let named = NamedBackendConfig {
+        local: env_config.apply_local_overrides(None),
+        ..default_named(default_name, "local")
+    };
+    create_backend_from_named_config(config, &named).await
+}
 
-    // Default to local backend — derive name from cwd for backward compatibility
-    #[cfg(feature = "backend-local")]
-    {
-        let default_name = std::env::current_dir()
-            .ok()
-            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
-            .unwrap_or_else(|| "default".to_string());
-        let backend = LocalBackend::new(&default_name, config, None)
-            .await
-            .context("Failed to create local backend")?;
-        Ok(Box::new(backend))
+/// Resolve an API credential, falling back to `pgbranch auth login` storage when
+/// the config leaves it empty.
+#[cfg(any(
+    feature = "backend-neon",
+    feature = "backend-dblab",
+    feature = "backend-xata"
+))]
+fn resolve_secret(value: &str, provider: &str) -> Result<String> {
+    if !value.is_empty() {
+        return resolve_env_var(value);
     }
-
-    #[cfg(not(feature = "backend-local"))]
-    {
-        anyhow::bail!("Local backend not compiled. Rebuild with --features backend-local");
+    match crate::credentials::get(provider)? {
+        Some((secret, _)) => Ok(secret),
+        None => Err(coded!(
+            CredentialsMissing,
+            "No {} credentials configured. Run 'pgbranch auth login {}' or set them in .pgbranch.yml",
+            provider,
+            provider
+        )),
     }
 }
 
+#[cfg(any(
+    feature = "backend-neon",
+    feature = "backend-dblab",
+    feature = "backend-xata"
+))]
 fn resolve_env_var(value: &str) -> Result<String> {
-    if value.starts_with("${") && value.ends_with('}') {
+    if crate::secret_ref::is_reference(value) {
+        crate::secret_ref::resolve(value)
+    } else if value.starts_with("${") && value.ends_with('}') {
         let env_var = &value[2..value.len() - 1];
         std::env::var(env_var)
             .with_context(|| format!("Environment variable {} not found", env_var))
@@ -0,0 +1,276 @@
+//! Manifests written before `destroy`, and `pgbranch adopt` to rebuild the
+//! branch records from one. `destroy` can't be undone, but when the data
+//! directories survive it (or come back from a backup), the manifest has
+//! everything else needed to use them again.
+//!
+//! `pgbranch adopt --pruned` recovers from containers removed outside
+//! pgbranch instead, e.g. by `docker system prune`: the records and data
+//! are still there, so it starts the branches that were running again.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::model::{self, BranchState};
+use super::reconcile::{CONTAINER_AND_DATA_REMOVED, CONTAINER_REMOVED};
+use super::{pgpass, LocalBackend};
+use crate::backends::{AdoptReport, DatabaseBranchingBackend, SkippedBranch};
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// The records of a project as they were before it was destroyed.
+#[derive(Debug, Serialize, Deserialize)]
+struct DestroyManifest {
+    version: u32,
+    destroyed_at: DateTime<Utc>,
+    project: model::Project,
+    /// Oldest first, so parents come before their clones
+    branches: Vec<model::Branch>,
+    snapshots: Vec<model::Snapshot>,
+    containers: Vec<ContainerSpec>,
+}
+
+/// How a branch's container was set up; `start` recreates it the same way.
+#[derive(Debug, Serialize, Deserialize)]
+struct ContainerSpec {
+    branch: String,
+    container_name: String,
+    image: String,
+    port: u16,
+    data_dir: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pg_settings: Vec<(String, String)>,
+}
+
+impl LocalBackend {
+    /// Write the project's records to `<data_root>/destroyed/`. `None` if
+    /// there's no project to destroy.
+    pub(super) fn write_destroy_manifest(&self) -> Result<Option<PathBuf>> {
+        let Some(project) = self.store().get_project_by_name(&self.project_name)? else {
+            return Ok(None);
+        };
+        let mut branches = self.store().list_branches(&project.id)?;
+        branches.reverse();
+        let mut snapshots = Vec::new();
+        for branch in &branches {
+            snapshots.extend(self.store().list_snapshots(&branch.id)?);
+        }
+        let containers = branches
+            .iter()
+            .map(|branch| {
+                let spec = self.start_spec(&project, branch);
+                ContainerSpec {
+                    branch: branch.name.clone(),
+                    container_name: spec.container_name,
+                    image: spec.image,
+                    port: spec.port,
+                    data_dir: branch.data_dir.clone(),
+                    pg_settings: spec.pg_settings,
+                }
+            })
+            .collect();
+        let destroyed_at = Utc::now();
+        let manifest = DestroyManifest {
+            version: MANIFEST_VERSION,
+            destroyed_at,
+            project,
+            branches,
+            snapshots,
+            containers,
+        };
+
+        let dir = self.data_root.join("destroyed");
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(format!(
+            "{}-{}.json",
+            self.project_name,
+            destroyed_at.format("%Y%m%dT%H%M%S")
+        ));
+        let json = serde_json::to_string_pretty(&manifest)?;
+        std::fs::write(&path, json)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        // It holds the branches' passwords
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(Some(path))
+    }
+
+    /// Record again the branches of a destroy manifest whose data is still
+    /// on disk. They come back stopped; `start` creates their containers.
+    pub(super) async fn adopt_manifest(&self, path: &Path) -> Result<AdoptReport> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut manifest: DestroyManifest = serde_json::from_str(&text)
+            .with_context(|| format!("{} is not a pgbranch destroy manifest", path.display()))?;
+        if manifest.version > MANIFEST_VERSION {
+            anyhow::bail!(
+                "{} was written by a newer pgbranch (manifest version {})",
+                path.display(),
+                manifest.version
+            );
+        }
+
+        // Adopted under this database's project name, whatever it was called then
+        manifest.project.name = self.project_name.clone();
+        let restore_project = match self.store().get_project_by_name(&self.project_name)? {
+            None => true,
+            // Left by 'destroy --keep-project'
+            Some(existing) if existing.id == manifest.project.id => false,
+            Some(_) => anyhow::bail!(
+                "Project '{}' already exists; adopt only restores a destroyed project",
+                self.project_name
+            ),
+        };
+        let project = &manifest.project;
+
+        let recorded = self.store().list_all_branches()?;
+        let taken_names: HashSet<String> =
+            recorded.iter().map(|b| b.container_name.clone()).collect();
+        let taken_ports: HashSet<u16> = recorded.iter().map(|b| b.port).collect();
+        let mut next_port = self.store().next_port()?.max(self.port_range_start);
+
+        let mut adopted: Vec<model::Branch> = Vec::new();
+        let mut skipped = Vec::new();
+        for mut branch in std::mem::take(&mut manifest.branches) {
+            let reason = if recorded
+                .iter()
+                .any(|b| b.project_id == project.id && b.name == branch.name)
+            {
+                Some("already recorded".to_string())
+            } else if !Path::new(&branch.data_dir).join("PG_VERSION").exists() {
+                Some(format!("no data in {}", branch.data_dir))
+            } else if taken_names.contains(&branch.container_name) {
+                Some(format!(
+                    "container name '{}' belongs to another branch",
+                    branch.container_name
+                ))
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                skipped.push(SkippedBranch {
+                    name: branch.name,
+                    reason,
+                });
+                continue;
+            }
+
+            if taken_ports.contains(&branch.port) {
+                branch.port = next_port;
+                next_port += 1;
+            }
+            // A parent that wasn't adopted can't be reset to
+            if let Some(parent_id) = &branch.parent_branch_id {
+                if !adopted.iter().any(|b| &b.id == parent_id) {
+                    branch.parent_branch_id = None;
+                }
+            }
+            branch.project_id = project.id.clone();
+            branch.state = BranchState::Stopped;
+            branch.health = None;
+            adopted.push(branch);
+        }
+
+        let snapshots: Vec<model::Snapshot> = manifest
+            .snapshots
+            .into_iter()
+            .filter(|snapshot| adopted.iter().any(|b| b.id == snapshot.branch_id))
+            .filter(|snapshot| {
+                project.storage_backend == model::StorageBackend::Zfs
+                    || self
+                        .storage
+                        .snapshot_data_dir(project, &snapshot.branch_id, &snapshot.id)
+                        .exists()
+            })
+            .collect();
+        for branch in &mut adopted {
+            if let Some(snapshot_id) = &branch.parent_snapshot_id {
+                if !snapshots.iter().any(|s| &s.id == snapshot_id) {
+                    branch.parent_snapshot_id = None;
+                }
+            }
+        }
+
+        self.store()
+            .restore_records(restore_project.then_some(project), &adopted, &snapshots)?;
+
+        if self.pgpass {
+            for branch in &adopted {
+                let credentials = self.branch_credentials(branch);
+                if let Err(e) = pgpass::upsert_entry(
+                    &self.pgpass_key(&branch.name),
+                    &self.runtime.client_host().to_string(),
+                    branch.port,
+                    &credentials.db,
+                    &credentials.user,
+                    &credentials.password,
+                ) {
+                    log::warn!("Failed to update .pgpass: {}", e);
+                }
+            }
+        }
+
+        Ok(AdoptReport {
+            project: self.project_name.clone(),
+            adopted: adopted.into_iter().map(|b| b.name).collect(),
+            skipped,
+        })
+    }
+
+    /// Start the branches whose containers were removed while they were
+    /// running, as recorded in the drift events. Branches that were stopped
+    /// get a new container on their next `start` anyway.
+    pub(super) async fn adopt_pruned(&self) -> Result<AdoptReport> {
+        let project = self.ensure_project().await?;
+        self.reconcile_project(&project).await?;
+
+        // Branches whose last drift is their running container going away
+        let mut pruned: Vec<String> = Vec::new();
+        for drift in self.store().list_drift(&project.id, 0)? {
+            pruned.retain(|name| name != &drift.branch_name);
+            if drift.from_state == BranchState::Running
+                && (drift.reason == CONTAINER_REMOVED || drift.reason == CONTAINER_AND_DATA_REMOVED)
+            {
+                pruned.push(drift.branch_name);
+            }
+        }
+
+        let mut adopted = Vec::new();
+        let mut skipped = Vec::new();
+        for name in pruned {
+            let Some(branch) = self.store().get_branch_by_name(&project.id, &name)? else {
+                continue;
+            };
+            if branch.state == BranchState::Running {
+                continue;
+            }
+            if !Path::new(&branch.data_dir).join("PG_VERSION").exists() {
+                skipped.push(SkippedBranch {
+                    name,
+                    reason: format!("no data in {}", branch.data_dir),
+                });
+                continue;
+            }
+            match self.start_branch(&name).await {
+                Ok(()) => adopted.push(name),
+                Err(e) => skipped.push(SkippedBranch {
+                    name,
+                    reason: format!("{:#}", e),
+                }),
+            }
+        }
+
+        Ok(AdoptReport {
+            project: self.project_name.clone(),
+            adopted,
+            skipped,
+        })
+    }
+}
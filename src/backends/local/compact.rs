@@ -0,0 +1,83 @@
+//! `pgbranch compact`. Branches that had data deleted or rewritten keep
+//! the space: PostgreSQL only returns it with VACUUM FULL, zero-filled
+//! blocks stay allocated, and rewritten files no longer share blocks with
+//! the parent they were cloned from.
+
+use anyhow::Result;
+
+use super::storage::space;
+use super::LocalBackend;
+use crate::backends::{CompactReport, DatabaseBranchingBackend};
+
+impl LocalBackend {
+    pub(super) async fn compact(
+        &self,
+        branch_name: &str,
+        vacuum_full: bool,
+    ) -> Result<CompactReport> {
+        let (project, branch) = self.existing_branch(branch_name)?;
+        let parent = match &branch.parent_branch_id {
+            Some(parent_id) => self
+                .store()
+                .list_branches(&project.id)?
+                .into_iter()
+                .find(|b| &b.id == parent_id),
+            None => None,
+        };
+        let used_before = self
+            .storage
+            .branch_usage(&project, &branch)
+            .await?
+            .used_bytes;
+        let free_before = space::flushed_free_space(&self.data_root).await?;
+
+        let mut steps = Vec::new();
+        if vacuum_full {
+            let branch = self.running_branch(branch_name).await?;
+            let credentials = self.branch_credentials(&branch);
+            let databases = self.branch_databases(&credentials);
+            for database in &databases {
+                self.runtime
+                    .query(
+                        &branch.container_name,
+                        &credentials.user,
+                        database,
+                        "VACUUM FULL",
+                    )
+                    .await?;
+            }
+            steps.push(format!("Ran VACUUM FULL in {}", databases.join(", ")));
+        }
+
+        let running = self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            == super::docker::ContainerStatus::Running;
+        let stop = running && self.storage.compact_needs_stop(&project);
+        if stop {
+            self.stop_branch(branch_name).await?;
+        }
+        let compacted = self
+            .storage
+            .compact_branch_data(&project, &branch, parent.as_ref())
+            .await;
+        if stop {
+            self.start_branch(branch_name).await?;
+        }
+        steps.extend(compacted?);
+
+        let used_after = self
+            .storage
+            .branch_usage(&project, &branch)
+            .await?
+            .used_bytes;
+        let free_after = space::flushed_free_space(&self.data_root).await?;
+        Ok(CompactReport {
+            steps,
+            used_before_bytes: used_before,
+            used_after_bytes: used_after,
+            reclaimed_bytes: free_after.saturating_sub(free_before),
+        })
+    }
+}
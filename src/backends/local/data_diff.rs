@@ -0,0 +1,104 @@
+//! Row counts and checksums of a branch's tables, for `pgbranch diff --data`.
+//! A checksum is the md5 of the sorted md5s of the table's rows, so two
+//! tables with the same rows match whatever order they were written in.
+
+use anyhow::{Context, Result};
+
+use super::LocalBackend;
+use crate::backends::TableData;
+
+/// Separates the columns of a result row; table names never contain it.
+const FIELD_SEPARATOR: char = '\u{1f}';
+/// Display and quoted name of each table; partitions are counted through
+/// their parent.
+const LIST_TABLES: &str = "SELECT concat_ws(chr(31), n.nspname || '.' || c.relname, \
+     quote_ident(n.nspname) || '.' || quote_ident(c.relname)) \
+     FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+     WHERE c.relkind IN ('r', 'p') AND NOT c.relispartition \
+     AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+     AND n.nspname NOT LIKE 'pg_toast%' \
+     ORDER BY 1";
+
+impl LocalBackend {
+    pub(super) async fn branch_table_data(
+        &self,
+        branch_name: &str,
+        tables: &[String],
+        checksum: bool,
+    ) -> Result<Vec<TableData>> {
+        let branch = self.running_branch(branch_name).await?;
+        let credentials = self.branch_credentials(&branch);
+
+        let listed = self
+            .runtime
+            .query(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                LIST_TABLES,
+            )
+            .await
+            .with_context(|| format!("failed to list the tables of branch '{}'", branch_name))?;
+        let selected: Vec<(&str, &str)> = listed
+            .lines()
+            .filter_map(|line| line.split_once(FIELD_SEPARATOR))
+            .filter(|(name, _)| tables.is_empty() || tables.iter().any(|t| table_matches(name, t)))
+            .collect();
+        if selected.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let selects: Vec<String> = selected
+            .iter()
+            .map(|(name, quoted)| {
+                let name = name.replace('\'', "''");
+                if checksum {
+                    format!(
+                        "SELECT concat_ws(chr(31), '{name}', count(*), \
+                         md5(coalesce(string_agg(md5(t::text), '' ORDER BY md5(t::text)), ''))) \
+                         FROM {quoted} t"
+                    )
+                } else {
+                    format!("SELECT concat_ws(chr(31), '{name}', count(*)) FROM {quoted} t")
+                }
+            })
+            .collect();
+        let output = self
+            .runtime
+            .query(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                &selects.join(" UNION ALL "),
+            )
+            .await
+            .with_context(|| format!("failed to read the tables of branch '{}'", branch_name))?;
+        output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(parse_row)
+            .collect()
+    }
+}
+
+/// `schema.table`, or a bare table name in any schema.
+fn table_matches(name: &str, wanted: &str) -> bool {
+    name == wanted
+        || name
+            .split_once('.')
+            .is_some_and(|(_, table)| table == wanted)
+}
+
+fn parse_row(line: &str) -> Result<TableData> {
+    let mut fields = line.split(FIELD_SEPARATOR);
+    let (Some(table), Some(rows)) = (fields.next(), fields.next()) else {
+        anyhow::bail!("unexpected table row: {}", line);
+    };
+    Ok(TableData {
+        table: table.to_string(),
+        rows: rows
+            .parse()
+            .with_context(|| format!("unexpected row count in: {}", line))?,
+        checksum: fields.next().map(str::to_string),
+    })
+}
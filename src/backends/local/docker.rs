@@ -1,20 +1,40 @@
 use std::collections::HashMap;
-use std::{collections::HashSet, path::PathBuf, time::Duration};
+use std::io::IsTerminal;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context};
 use bollard::exec::StartExecOptions;
 use bollard::models::{
-    ContainerCreateBody, ContainerStateStatusEnum, ExecConfig, HostConfig, PortBinding, PortMap,
+    ContainerCreateBody, ContainerStateStatusEnum, ContainerUpdateBody, EventMessage, ExecConfig,
+    HostConfig, PortBinding, PortMap, RestartPolicy, RestartPolicyNameEnum,
 };
 use bollard::query_parameters::{
-    CreateContainerOptions, CreateImageOptions, ListContainersOptions, RemoveContainerOptions,
-    StopContainerOptions,
+    CreateContainerOptions, CreateImageOptions, EventsOptions, ListContainersOptions, LogsOptions,
+    RemoveContainerOptions, StopContainerOptions,
 };
 use bollard::Docker;
-use futures_util::TryStreamExt;
+use chrono::{DateTime, FixedOffset};
+use futures_util::{Stream, TryStreamExt};
+use serde::Serialize;
 use tokio::time::{sleep, Instant};
 
+use super::docker_endpoint::{self, DockerEndpoint};
+use super::docker_retry::{CallKind, RetryPolicy};
+use crate::backends::{ContainerLimits, DoctorCheck, HostCommand};
+use crate::config::{ContainerEngine, PullPolicy};
+
 const PGDATA_CONTAINER_PATH: &str = "/var/lib/postgresql/data";
+const PORT_LABEL: &str = "pgbranch.port";
+/// Set with `prune_protection`, for prune filters to skip the container
+pub const PROTECTED_LABEL: &str = "pgbranch.protected";
+// Docker rejects longer container names
+const MAX_CONTAINER_NAME_LEN: usize = 128;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ContainerStatus {
@@ -25,10 +45,93 @@ pub enum ContainerStatus {
     Other(String),
 }
 
+/// How far a branch container got before it stopped making progress towards readiness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessPhase {
+    ContainerStart,
+    Initdb,
+    Crashed,
+    AuthMismatch,
+    Startup,
+}
+
+impl ReadinessPhase {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::ContainerStart => "the container never started",
+            Self::Initdb => "initdb was still initializing the data directory",
+            Self::Crashed => "PostgreSQL exited during startup",
+            Self::AuthMismatch => {
+                "the existing data directory does not match the configured user, password or database"
+            }
+            Self::Startup => "PostgreSQL started but never accepted connections",
+        }
+    }
+}
+
+/// Returned by [`DockerRuntime::wait_ready`] when a branch container never becomes ready.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessError {
+    pub container_name: String,
+    pub phase: ReadinessPhase,
+    pub waited_secs: u64,
+    pub exit_code: Option<i64>,
+    pub oom_killed: bool,
+    /// Last lines of the container log, oldest first
+    pub log_tail: Vec<String>,
+    pub suggestions: Vec<String>,
+}
+
+impl std::fmt::Display for ReadinessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "container '{}' was not ready after {}s: {}",
+            self.container_name,
+            self.waited_secs,
+            self.phase.describe()
+        )?;
+        if let Some(code) = self.exit_code {
+            write!(
+                f,
+                " (exit code {code}{})",
+                if self.oom_killed { ", OOM killed" } else { "" }
+            )?;
+        }
+        if !self.log_tail.is_empty() {
+            write!(f, "\n\nLast {} log lines:", self.log_tail.len())?;
+            for line in &self.log_tail {
+                write!(f, "\n  {line}")?;
+            }
+        }
+        if !self.suggestions.is_empty() {
+            write!(f, "\n\nSuggestions:")?;
+            for suggestion in &self.suggestions {
+                write!(f, "\n  - {suggestion}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ReadinessError {}
+
+const READINESS_LOG_LINES: usize = 50;
+
+/// Container lifecycle events worth reporting.
+const CONTAINER_EVENTS: [&str; 11] = [
+    "create", "start", "restart", "die", "oom", "kill", "stop", "pause", "unpause", "rename",
+    "destroy",
+];
+
 #[derive(Debug, Clone)]
 pub struct ReserveBranchSpec {
     pub project_name: String,
+    pub project_id: String,
     pub branch_name: String,
+    /// Container names already recorded for other branches
+    pub taken_names: HashSet<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +141,7 @@ pub struct ReservedBranchRuntime {
 
 #[derive(Debug, Clone)]
 pub struct StartBranchSpec {
+    pub project_id: String,
     pub image: String,
     pub container_name: String,
     pub data_dir: PathBuf,
@@ -45,6 +149,20 @@ pub struct StartBranchSpec {
     pub pg_user: String,
     pub pg_password: String,
     pub pg_db: String,
+    /// Passed to the server as `-c key=value`; fixed when the container is created
+    pub pg_settings: Vec<(String, String)>,
+}
+
+/// A container pgbranch created, as Docker lists it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagedContainer {
+    pub name: String,
+    pub project_id: String,
+    pub image: String,
+    pub port: Option<u16>,
+    pub state: String,
+    /// Docker's summary, e.g. "Up 3 hours"
+    pub status: String,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -56,32 +174,149 @@ pub struct DockerDoctorResult {
 
 pub struct DockerRuntime {
     client: Docker,
+    engine: ContainerEngine,
+    endpoint: DockerEndpoint,
+    retry: RetryPolicy,
+    pull_policy: PullPolicy,
+    prune_protection: bool,
+    bind_address: IpAddr,
+    /// Images pulled by this process, so `always` pulls each once per command
+    pulled: Mutex<HashSet<String>>,
 }
 
 impl DockerRuntime {
-    pub fn new() -> anyhow::Result<Self> {
-        let client =
-            Docker::connect_with_local_defaults().context("failed to connect to Docker daemon")?;
-        Ok(Self { client })
+    /// Connect to `docker_host`, or to the engine [`docker_endpoint::resolve`]
+    /// finds, starting from `docker_context` if one is given.
+    pub fn new(
+        engine: ContainerEngine,
+        docker_host: Option<&str>,
+        docker_context: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let endpoint = docker_endpoint::resolve(engine, docker_host, docker_context)?;
+        let client = Docker::connect_with_host(&endpoint.host).with_context(|| {
+            format!(
+                "failed to connect to {} at {} (from {})",
+                engine.label(),
+                endpoint.host,
+                endpoint.source
+            )
+        })?;
+        Ok(Self {
+            client,
+            engine,
+            endpoint,
+            retry: RetryPolicy::default(),
+            pull_policy: PullPolicy::default(),
+            prune_protection: false,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            pulled: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn with_pull_policy(mut self, pull_policy: PullPolicy) -> Self {
+        self.pull_policy = pull_policy;
+        self
+    }
+
+    pub fn pull_policy(&self) -> PullPolicy {
+        self.pull_policy
+    }
+
+    pub fn prune_protection(&self) -> bool {
+        self.prune_protection
+    }
+
+    /// Publish branch ports on `bind_address` rather than on loopback.
+    pub fn with_bind_address(mut self, bind_address: IpAddr) -> Self {
+        self.bind_address = bind_address;
+        self
+    }
+
+    pub fn bind_address(&self) -> IpAddr {
+        self.bind_address
+    }
+
+    /// Where clients on this machine reach the published ports: the bind
+    /// address, or loopback when it's every interface.
+    pub fn client_host(&self) -> IpAddr {
+        match self.bind_address {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        }
+    }
+
+    /// Create containers that come back when the engine restarts and carry
+    /// [`PROTECTED_LABEL`], so `docker system prune --filter` can leave them.
+    pub fn with_prune_protection(mut self, prune_protection: bool) -> Self {
+        self.prune_protection = prune_protection;
+        self
+    }
+
+    /// Retry transient failures of Docker calls according to `retry`.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
 
     pub fn client(&self) -> &Docker {
         &self.client
     }
 
+    pub fn endpoint(&self) -> &DockerEndpoint {
+        &self.endpoint
+    }
+
+    pub fn engine(&self) -> ContainerEngine {
+        self.engine
+    }
+
+    /// Whether containers need Podman's `keep-id` user namespace. Rootless
+    /// Podman maps container uids to subordinate ids, so without it the
+    /// container user (the host's uid) couldn't write the bind-mounted data.
+    async fn keep_host_ids(&self) -> bool {
+        if self.engine != ContainerEngine::Podman {
+            return false;
+        }
+        match self
+            .retry
+            .run("info", CallKind::Idempotent, || self.client.info())
+            .await
+        {
+            Ok(info) => info
+                .security_options
+                .unwrap_or_default()
+                .iter()
+                .any(|option| option.contains("rootless")),
+            Err(e) => {
+                log::warn!("Failed to ask Podman whether it runs rootless: {}", e);
+                false
+            }
+        }
+    }
+
     pub async fn doctor(&self) -> DockerDoctorResult {
-        match self.client.version().await {
+        match self
+            .retry
+            .run("version", CallKind::Idempotent, || self.client.version())
+            .await
+        {
             Ok(info) => {
                 let version = info.version.unwrap_or_default();
                 DockerDoctorResult {
                     available: true,
-                    detail: "Docker engine reachable".to_string(),
+                    detail: format!("{} engine reachable", self.engine.label()),
                     version: Some(version),
                 }
             }
             Err(err) => DockerDoctorResult {
                 available: false,
-                detail: format!("Docker engine unreachable: {err}"),
+                detail: format!(
+                    "{} engine unreachable at {} (from {}): {err}",
+                    self.engine.label(),
+                    self.endpoint.host,
+                    self.endpoint.source
+                ),
                 version: None,
             },
         }
@@ -91,27 +326,121 @@ impl DockerRuntime {
         &self,
         spec: &ReserveBranchSpec,
     ) -> anyhow::Result<ReservedBranchRuntime> {
+        let branch = sanitize(&spec.branch_name);
         let raw = format!(
-            "pgbranch-{}-{}",
-            sanitize(&spec.project_name),
-            sanitize(&spec.branch_name)
+            "{}{}",
+            container_prefix(&spec.project_name, &spec.project_id),
+            branch
         );
-        // Docker container names must be <= 128 chars
-        let container_name = if raw.len() > 128 {
-            raw[..128].trim_end_matches('-').to_string()
-        } else {
-            raw
-        };
 
-        Ok(ReservedBranchRuntime { container_name })
+        // Sanitizing and truncating are lossy ("feature/a" and "Feature-A" both
+        // become "feature-a"), so those names get a hash of the original
+        let suffix = format!(
+            "-{:08x}",
+            name_hash(&format!("{}/{}", spec.project_name, spec.branch_name)) as u32
+        );
+        let hashed = format!(
+            "{}{}",
+            truncate_name(&raw, MAX_CONTAINER_NAME_LEN - suffix.len()),
+            suffix
+        );
+        let lossy = branch != spec.branch_name || raw.len() > MAX_CONTAINER_NAME_LEN;
+
+        let mut candidates = Vec::new();
+        if !lossy {
+            candidates.push(raw);
+        }
+        candidates.push(hashed.clone());
+        for n in 2..=9 {
+            let counter = format!("{suffix}-{n}");
+            candidates.push(format!(
+                "{}{}",
+                truncate_name(
+                    &hashed[..hashed.len() - suffix.len()],
+                    MAX_CONTAINER_NAME_LEN - counter.len()
+                ),
+                counter
+            ));
+        }
+
+        for candidate in candidates {
+            if spec.taken_names.contains(&candidate) {
+                continue;
+            }
+            // A leftover container would be reused by start_branch with someone else's data
+            if self.container_status(&candidate).await? != ContainerStatus::NotFound {
+                log::debug!("container name '{}' is already in use", candidate);
+                continue;
+            }
+            return Ok(ReservedBranchRuntime {
+                container_name: candidate,
+            });
+        }
+
+        Err(anyhow!(
+            "could not find a free container name for branch '{}'",
+            spec.branch_name
+        ))
     }
 
+    /// Make sure `image` is available according to the pull policy. With
+    /// `always`, a failed pull falls back to the local copy, if there is one.
     pub async fn ensure_image(&self, image: &str) -> anyhow::Result<()> {
-        // Check if image exists locally
-        if self.client.inspect_image(image).await.is_ok() {
-            return Ok(());
+        let local_id = self.local_image_id(image).await;
+        match (self.pull_policy, &local_id) {
+            (PullPolicy::IfNotPresent | PullPolicy::Never, Some(_)) => return Ok(()),
+            (PullPolicy::Never, None) => {
+                return Err(anyhow!(
+                    "image '{image}' isn't available locally and local.pull_policy is 'never'; \
+                     copy it over with 'docker save {image}' and 'docker load', or allow pulling \
+                     with pull_policy 'if-not-present'"
+                ));
+            }
+            (PullPolicy::Always, Some(_)) if self.pulled.lock().unwrap().contains(image) => {
+                return Ok(());
+            }
+            _ => {}
         }
 
+        match self.pull_image(image).await {
+            Ok(()) => {
+                self.pulled.lock().unwrap().insert(image.to_string());
+                if let Some(old_id) = local_id {
+                    if self
+                        .local_image_id(image)
+                        .await
+                        .is_some_and(|id| id != old_id)
+                    {
+                        log::info!("Pulled a newer build of '{}'", image);
+                    }
+                }
+                Ok(())
+            }
+            Err(err) if local_id.is_some() => {
+                log::warn!(
+                    "{:#}; using the local copy of '{}', which may be out of date",
+                    err,
+                    image
+                );
+                self.pulled.lock().unwrap().insert(image.to_string());
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// ID of the local copy of `image`, if there is one.
+    async fn local_image_id(&self, image: &str) -> Option<String> {
+        self.retry
+            .run("inspect image", CallKind::Idempotent, || {
+                self.client.inspect_image(image)
+            })
+            .await
+            .ok()
+            .map(|info| info.id.unwrap_or_default())
+    }
+
+    async fn pull_image(&self, image: &str) -> anyhow::Result<()> {
         // Parse image:tag
         let (from_image, tag) = if let Some((name, tag)) = image.rsplit_once(':') {
             (name.to_string(), Some(tag.to_string()))
@@ -126,22 +455,46 @@ impl DockerRuntime {
         };
 
         // Pull and consume the stream to completion
-        self.client
-            .create_image(Some(options), None, None)
-            .try_collect::<Vec<_>>()
+        self.retry
+            .run("pull", CallKind::Pull, || {
+                self.client
+                    .create_image(Some(options.clone()), None, None)
+                    .try_collect::<Vec<_>>()
+            })
             .await
             .with_context(|| format!("failed to pull docker image '{image}'"))?;
 
         Ok(())
     }
 
+    /// Whether the image new branches need is there or can be pulled.
+    pub async fn image_check(&self, image: &str) -> DoctorCheck {
+        let present = self.local_image_id(image).await.is_some();
+        let policy = self.pull_policy.as_str();
+        DoctorCheck {
+            name: "Image".to_string(),
+            available: present || self.pull_policy != PullPolicy::Never,
+            detail: match (present, self.pull_policy) {
+                (true, _) => format!("{image} is available locally (pull policy {policy})"),
+                (false, PullPolicy::Never) => format!(
+                    "{image} is missing and pull policy is never; load it with 'docker load' before creating branches"
+                ),
+                (false, _) => format!(
+                    "{image} will be pulled when the first branch is created (pull policy {policy})"
+                ),
+            },
+        }
+    }
+
     pub async fn container_status(&self, container_name: &str) -> anyhow::Result<ContainerStatus> {
         match self
-            .client
-            .inspect_container(
-                container_name,
-                None::<bollard::query_parameters::InspectContainerOptions>,
-            )
+            .retry
+            .run("inspect container", CallKind::Idempotent, || {
+                self.client.inspect_container(
+                    container_name,
+                    None::<bollard::query_parameters::InspectContainerOptions>,
+                )
+            })
             .await
         {
             Ok(info) => {
@@ -165,8 +518,6 @@ impl DockerRuntime {
     }
 
     pub async fn start_branch(&self, spec: &StartBranchSpec) -> anyhow::Result<()> {
-        self.ensure_image(&spec.image).await?;
-
         match self.container_status(&spec.container_name).await? {
             ContainerStatus::Running => return Ok(()),
             ContainerStatus::Paused => {
@@ -174,11 +525,13 @@ impl DockerRuntime {
                 return Ok(());
             }
             ContainerStatus::Exited | ContainerStatus::Other(_) => {
-                self.client
-                    .start_container(
-                        &spec.container_name,
-                        None::<bollard::query_parameters::StartContainerOptions>,
-                    )
+                self.retry
+                    .run("start container", CallKind::Mutating, || {
+                        self.client.start_container(
+                            &spec.container_name,
+                            None::<bollard::query_parameters::StartContainerOptions>,
+                        )
+                    })
                     .await
                     .with_context(|| {
                         format!("failed to start container '{}'", spec.container_name)
@@ -188,6 +541,26 @@ impl DockerRuntime {
             ContainerStatus::NotFound => {}
         }
 
+        self.create_container(spec).await?;
+        self.retry
+            .run("start container", CallKind::Mutating, || {
+                self.client.start_container(
+                    &spec.container_name,
+                    None::<bollard::query_parameters::StartContainerOptions>,
+                )
+            })
+            .await
+            .map_err(|e| mount_error(e, &spec.data_dir))
+            .with_context(|| format!("failed to start container '{}'", spec.container_name))?;
+
+        Ok(())
+    }
+
+    /// Create the container for `spec` without starting it.
+    async fn create_container(&self, spec: &StartBranchSpec) -> anyhow::Result<()> {
+        // Only a new container needs the image
+        self.ensure_image(&spec.image).await?;
+
         // Create and start a new container
         let mount = format!("{}:{PGDATA_CONTAINER_PATH}", spec.data_dir.display());
 
@@ -195,13 +568,19 @@ impl DockerRuntime {
         port_bindings.insert(
             "5432/tcp".to_string(),
             Some(vec![PortBinding {
-                host_ip: Some("0.0.0.0".to_string()),
+                host_ip: Some(self.bind_address.to_string()),
                 host_port: Some(spec.port.to_string()),
             }]),
         );
 
         let mut labels = HashMap::new();
         labels.insert("pgbranch.managed".to_string(), "true".to_string());
+        labels.insert("pgbranch.project".to_string(), spec.project_id.clone());
+        // Keeps the port reserved while the container is stopped and publishes nothing
+        labels.insert(PORT_LABEL.to_string(), spec.port.to_string());
+        if self.prune_protection {
+            labels.insert(PROTECTED_LABEL.to_string(), "true".to_string());
+        }
 
         let config = ContainerCreateBody {
             image: Some(spec.image.clone()),
@@ -211,10 +590,23 @@ impl DockerRuntime {
                 format!("POSTGRES_PASSWORD={}", spec.pg_password),
                 format!("POSTGRES_DB={}", spec.pg_db),
             ]),
+            cmd: (!spec.pg_settings.is_empty()).then(|| {
+                let mut cmd = vec!["postgres".to_string()];
+                for (key, value) in &spec.pg_settings {
+                    cmd.push("-c".to_string());
+                    cmd.push(format!("{key}={value}"));
+                }
+                cmd
+            }),
             labels: Some(labels),
             host_config: Some(HostConfig {
                 binds: Some(vec![mount]),
                 port_bindings: Some(port_bindings),
+                userns_mode: self.keep_host_ids().await.then(|| "keep-id".to_string()),
+                restart_policy: self.prune_protection.then(|| RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                    ..Default::default()
+                }),
                 ..Default::default()
             }),
             ..Default::default()
@@ -225,22 +617,30 @@ impl DockerRuntime {
             ..Default::default()
         };
 
-        self.client
-            .create_container(Some(options), config)
+        self.retry
+            .run("create container", CallKind::Mutating, || {
+                self.client
+                    .create_container(Some(options.clone()), config.clone())
+            })
             .await
+            .map_err(|e| mount_error(e, &spec.data_dir))
             .with_context(|| format!("failed to create container '{}'", spec.container_name))?;
 
-        self.client
-            .start_container(
-                &spec.container_name,
-                None::<bollard::query_parameters::StartContainerOptions>,
-            )
-            .await
-            .with_context(|| format!("failed to start container '{}'", spec.container_name))?;
-
         Ok(())
     }
 
+    /// Replace stopped container `from` with one made from `spec`, on the
+    /// same data directory, without starting it. Labels can't be added to an
+    /// existing container, so this is how an older one gets them.
+    pub async fn recreate_container(
+        &self,
+        from: &str,
+        spec: &StartBranchSpec,
+    ) -> anyhow::Result<()> {
+        self.remove_branch(from).await?;
+        self.create_container(spec).await
+    }
+
     pub async fn stop_branch(&self, container_name: &str) -> anyhow::Result<()> {
         match self.container_status(container_name).await? {
             ContainerStatus::NotFound | ContainerStatus::Exited | ContainerStatus::Other(_) => {
@@ -257,8 +657,11 @@ impl DockerRuntime {
             ..Default::default()
         };
 
-        self.client
-            .stop_container(container_name, Some(options))
+        self.retry
+            .run("stop container", CallKind::Mutating, || {
+                self.client
+                    .stop_container(container_name, Some(options.clone()))
+            })
             .await
             .with_context(|| format!("failed to stop container '{container_name}'"))?;
 
@@ -271,8 +674,10 @@ impl DockerRuntime {
             _ => return Ok(()),
         }
 
-        self.client
-            .pause_container(container_name)
+        self.retry
+            .run("pause container", CallKind::Mutating, || {
+                self.client.pause_container(container_name)
+            })
             .await
             .with_context(|| format!("failed to pause container '{container_name}'"))?;
 
@@ -285,8 +690,10 @@ impl DockerRuntime {
             _ => return Ok(()),
         }
 
-        self.client
-            .unpause_container(container_name)
+        self.retry
+            .run("unpause container", CallKind::Mutating, || {
+                self.client.unpause_container(container_name)
+            })
             .await
             .with_context(|| format!("failed to unpause container '{container_name}'"))?;
 
@@ -306,8 +713,11 @@ impl DockerRuntime {
             ..Default::default()
         };
 
-        self.client
-            .remove_container(container_name, Some(options))
+        self.retry
+            .run("remove container", CallKind::Mutating, || {
+                self.client
+                    .remove_container(container_name, Some(options.clone()))
+            })
             .await
             .with_context(|| format!("failed to remove container '{container_name}'"))?;
 
@@ -321,25 +731,35 @@ impl DockerRuntime {
         pg_db: &str,
         timeout: Duration,
     ) -> anyhow::Result<()> {
-        let deadline = Instant::now() + timeout;
+        let started = Instant::now();
+        let deadline = started + timeout;
 
         loop {
             if Instant::now() >= deadline {
-                return Err(anyhow!(
-                    "timed out waiting for postgres readiness in '{container_name}'"
-                ));
+                let diagnosis = self
+                    .diagnose_readiness(container_name, pg_user, pg_db, started.elapsed())
+                    .await;
+                return Err(diagnosis.into());
             }
 
             match self.container_status(container_name).await? {
                 ContainerStatus::NotFound => {
                     return Err(anyhow!("container '{container_name}' does not exist"));
                 }
-                ContainerStatus::Running => {
+                ContainerStatus::Running
                     if self
                         .exec_check(container_name, &["pg_isready", "-U", pg_user, "-d", pg_db])
-                        .await
-                    {
-                        return Ok(());
+                        .await =>
+                {
+                    return Ok(());
+                }
+                // Nothing restarts a dead container, so waiting out the timeout is pointless
+                ContainerStatus::Exited | ContainerStatus::Other(_) => {
+                    let diagnosis = self
+                        .diagnose_readiness(container_name, pg_user, pg_db, started.elapsed())
+                        .await;
+                    if diagnosis.exit_code.is_some() {
+                        return Err(diagnosis.into());
                     }
                 }
                 _ => {}
@@ -349,6 +769,610 @@ impl DockerRuntime {
         }
     }
 
+    /// Why PostgreSQL in a running container isn't accepting connections, or
+    /// `None` if it is. A crash-looping server or a full disk shows up in the
+    /// log long before pg_isready says anything useful, so the most telling
+    /// recent log line is preferred over pg_isready's own output.
+    pub async fn postgres_health(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+    ) -> Option<String> {
+        let probe = self
+            .exec_capture(
+                container_name,
+                &["pg_isready", "-U", pg_user, "-d", pg_db],
+                &[],
+            )
+            .await;
+        let fallback = match probe {
+            Ok((0, _, _)) => return None,
+            Ok((_, stdout, stderr)) => format!("{}{}", stdout.trim(), stderr.trim()),
+            Err(err) => format!("{err:#}"),
+        };
+        let log_tail = self.log_tail(container_name).await;
+        Some(unhealthy_reason(&log_tail).unwrap_or(fallback))
+    }
+
+    /// Work out why a container isn't ready from its state and recent logs.
+    async fn diagnose_readiness(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        waited: Duration,
+    ) -> ReadinessError {
+        let state = self
+            .retry
+            .run("inspect container", CallKind::Idempotent, || {
+                self.client.inspect_container(
+                    container_name,
+                    None::<bollard::query_parameters::InspectContainerOptions>,
+                )
+            })
+            .await
+            .ok()
+            .and_then(|info| info.state);
+        let status = state.as_ref().and_then(|s| s.status);
+        let exited = matches!(
+            status,
+            Some(ContainerStateStatusEnum::EXITED) | Some(ContainerStateStatusEnum::DEAD)
+        );
+        let exit_code = state.as_ref().and_then(|s| s.exit_code).filter(|_| exited);
+        let oom_killed = state.as_ref().and_then(|s| s.oom_killed).unwrap_or(false);
+
+        let log_tail = self.log_tail(container_name).await;
+        let logs = log_tail.join("\n");
+        let existing_data = logs.contains("Skipping initialization");
+        let auth_failure = logs.contains(&format!("role \"{pg_user}\" does not exist"))
+            || logs.contains(&format!("database \"{pg_db}\" does not exist"))
+            || logs.contains("password authentication failed");
+
+        let phase = if status.is_none() || status == Some(ContainerStateStatusEnum::CREATED) {
+            ReadinessPhase::ContainerStart
+        } else if existing_data && auth_failure {
+            ReadinessPhase::AuthMismatch
+        } else if exited {
+            ReadinessPhase::Crashed
+        } else if logs.contains("initdb") && !logs.contains("init process complete") {
+            ReadinessPhase::Initdb
+        } else {
+            ReadinessPhase::Startup
+        };
+
+        let mut suggestions = Vec::new();
+        if oom_killed {
+            suggestions
+                .push("The container ran out of memory; give Docker more memory".to_string());
+        }
+        if logs.contains("has wrong ownership") || logs.contains("Permission denied") {
+            suggestions.push(
+                "The data directory isn't writable by the container user; check ownership of the branch's data directory under data_root"
+                    .to_string(),
+            );
+        }
+        if logs.contains("database files are incompatible with server") {
+            suggestions.push(
+                "The data directory was created by another PostgreSQL major version; set `image` to the version that created it"
+                    .to_string(),
+            );
+        }
+        if logs.contains("No space left on device") {
+            suggestions.push("The disk holding data_root is full".to_string());
+        }
+        if logs.contains("automatic recovery in progress") {
+            suggestions.push(
+                "PostgreSQL is replaying WAL after an unclean shutdown; wait and run 'pgbranch start' again"
+                    .to_string(),
+            );
+        }
+        match phase {
+            ReadinessPhase::ContainerStart => suggestions.push(
+                "Check 'docker ps -a' and the Docker daemon logs; the port may already be taken".to_string(),
+            ),
+            ReadinessPhase::Initdb => suggestions.push(
+                "initdb is slow on this storage; run 'pgbranch start' to keep waiting".to_string(),
+            ),
+            ReadinessPhase::AuthMismatch => suggestions.push(format!(
+                "POSTGRES_USER/POSTGRES_PASSWORD/POSTGRES_DB only apply to an empty data directory; set postgres_user ({pg_user}) and postgres_db back to the values the data was created with, run 'pgbranch branch set-password <branch>' after a password change, or reset the branch"
+            )),
+            ReadinessPhase::Crashed | ReadinessPhase::Startup if suggestions.is_empty() => {
+                suggestions.push(format!(
+                    "Inspect the full log with 'docker logs {container_name}'"
+                ))
+            }
+            _ => {}
+        }
+
+        ReadinessError {
+            container_name: container_name.to_string(),
+            phase,
+            waited_secs: waited.as_secs(),
+            exit_code,
+            oom_killed,
+            log_tail,
+            suggestions,
+        }
+    }
+
+    async fn log_tail(&self, container_name: &str) -> Vec<String> {
+        let options = LogsOptions {
+            stdout: true,
+            stderr: true,
+            tail: READINESS_LOG_LINES.to_string(),
+            ..Default::default()
+        };
+        let chunks: Vec<_> = match self
+            .client
+            .logs(container_name, Some(options))
+            .try_collect()
+            .await
+        {
+            Ok(chunks) => chunks,
+            Err(err) => {
+                log::debug!("failed to read logs of '{}': {}", container_name, err);
+                return Vec::new();
+            }
+        };
+        let text: String = chunks.iter().map(|chunk| chunk.to_string()).collect();
+        let lines: Vec<String> = text.lines().map(|l| l.trim_end().to_string()).collect();
+        lines[lines.len().saturating_sub(READINESS_LOG_LINES)..].to_vec()
+    }
+
+    /// Container events of a project's branches as they happen, leaving out
+    /// the `exec_*` noise of health checks and queries.
+    pub fn events(
+        &self,
+        project_id: &str,
+    ) -> impl Stream<Item = Result<EventMessage, bollard::errors::Error>> + '_ {
+        let filters = HashMap::from([
+            ("type".to_string(), vec!["container".to_string()]),
+            (
+                "label".to_string(),
+                vec![format!("pgbranch.project={project_id}")],
+            ),
+            (
+                "event".to_string(),
+                CONTAINER_EVENTS.iter().map(|e| e.to_string()).collect(),
+            ),
+        ]);
+        self.client.events(Some(EventsOptions {
+            filters: Some(filters),
+            ..Default::default()
+        }))
+    }
+
+    /// The containers pgbranch created, in every project. Only running ones
+    /// unless `all`.
+    pub async fn managed_containers(&self, all: bool) -> anyhow::Result<Vec<ManagedContainer>> {
+        let options = ListContainersOptions {
+            all,
+            filters: Some(HashMap::from([(
+                "label".to_string(),
+                vec!["pgbranch.managed=true".to_string()],
+            )])),
+            ..Default::default()
+        };
+        let containers = self
+            .retry
+            .run("list containers", CallKind::Idempotent, || {
+                self.client.list_containers(Some(options.clone()))
+            })
+            .await
+            .context("failed to list containers")?;
+        Ok(containers
+            .into_iter()
+            .map(|container| {
+                let labels = container.labels.unwrap_or_default();
+                ManagedContainer {
+                    name: container
+                        .names
+                        .and_then(|names| names.into_iter().next())
+                        .map(|name| name.trim_start_matches('/').to_string())
+                        .unwrap_or_default(),
+                    project_id: labels.get("pgbranch.project").cloned().unwrap_or_default(),
+                    image: container.image.unwrap_or_default(),
+                    port: labels.get(PORT_LABEL).and_then(|port| port.parse().ok()),
+                    state: container
+                        .state
+                        .map(|state| state.to_string())
+                        .unwrap_or_default(),
+                    status: container.status.unwrap_or_default(),
+                }
+            })
+            .collect())
+    }
+
+    /// Log lines of a container written after `after`, each with the time
+    /// Docker received it, oldest first.
+    pub async fn logs_after(
+        &self,
+        container_name: &str,
+        after: Option<DateTime<FixedOffset>>,
+    ) -> anyhow::Result<Vec<(DateTime<FixedOffset>, String)>> {
+        let options = LogsOptions {
+            stdout: true,
+            stderr: true,
+            timestamps: true,
+            // Whole seconds only, so lines from that second are filtered below
+            since: after.map_or(0, |t| t.timestamp() as i32),
+            ..Default::default()
+        };
+        let chunks: Vec<_> = self
+            .retry
+            .run("logs", CallKind::Idempotent, || {
+                self.client
+                    .logs(container_name, Some(options.clone()))
+                    .try_collect()
+            })
+            .await
+            .with_context(|| format!("failed to read logs of '{container_name}'"))?;
+        let text: String = chunks.iter().map(|chunk| chunk.to_string()).collect();
+        Ok(text
+            .lines()
+            .filter_map(|line| {
+                let (stamp, message) = line.split_once(' ').unwrap_or((line, ""));
+                let stamp = DateTime::parse_from_rfc3339(stamp).ok()?;
+                Some((stamp, message.trim_end().to_string()))
+            })
+            .filter(|(stamp, _)| after.is_none_or(|after| *stamp > after))
+            .collect())
+    }
+
+    /// Log in over TCP the way clients do, so a password that doesn't match the data
+    /// directory is caught. Returns the server's error message on failure.
+    ///
+    /// Connecting to 127.0.0.1 would hit the image's `trust` rule, so this uses the
+    /// container's own network address.
+    pub async fn check_login(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_password: &str,
+        pg_db: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let script = "host=$(hostname -i 2>/dev/null | cut -d' ' -f1); \
+                      [ -n \"$host\" ] || exit 0; \
+                      exec psql -h \"$host\" -tAc 'select 1'";
+        let (exit_code, stderr) = self
+            .exec_output(
+                container_name,
+                &["sh", "-c", script],
+                &[
+                    ("PGUSER", pg_user),
+                    ("PGPASSWORD", pg_password),
+                    ("PGDATABASE", pg_db),
+                    ("PGCONNECT_TIMEOUT", "5"),
+                ],
+            )
+            .await?;
+        Ok((exit_code != 0).then(|| stderr.trim().trim_start_matches("psql: ").to_string()))
+    }
+
+    /// Set a role's password through the container's trusted local socket.
+    pub async fn set_role_password(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_password: &str,
+    ) -> anyhow::Result<()> {
+        let sql = format!(
+            "ALTER ROLE \"{}\" WITH PASSWORD '{}'",
+            pg_user.replace('"', "\"\""),
+            pg_password.replace('\'', "''")
+        );
+        let (exit_code, stderr) = self
+            .exec_output(
+                container_name,
+                &[
+                    "psql",
+                    "-v",
+                    "ON_ERROR_STOP=1",
+                    "-U",
+                    pg_user,
+                    "-d",
+                    "postgres",
+                    "-c",
+                    &sql,
+                ],
+                &[],
+            )
+            .await?;
+        if exit_code != 0 {
+            return Err(anyhow!(
+                "failed to change the password of role '{pg_user}': {}",
+                stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether new sessions on `pg_db` default to read-only transactions.
+    pub async fn database_read_only(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+    ) -> anyhow::Result<bool> {
+        let sql = format!(
+            "SELECT EXISTS (SELECT FROM pg_db_role_setting s \
+             JOIN pg_database d ON d.oid = s.setdatabase \
+             WHERE d.datname = '{}' AND s.setrole = 0 \
+             AND 'default_transaction_read_only=on' = ANY (s.setconfig))",
+            pg_db.replace('\'', "''")
+        );
+        // From `postgres`, since a read-only database can't be altered from inside
+        Ok(self
+            .query(container_name, pg_user, "postgres", &sql)
+            .await?
+            == "t")
+    }
+
+    pub async fn set_database_read_only(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        read_only: bool,
+    ) -> anyhow::Result<()> {
+        let setting = if read_only {
+            "SET default_transaction_read_only = on"
+        } else {
+            "RESET default_transaction_read_only"
+        };
+        let sql = format!(
+            "ALTER DATABASE \"{}\" {}",
+            pg_db.replace('"', "\"\""),
+            setting
+        );
+        self.query(container_name, pg_user, "postgres", &sql)
+            .await
+            .with_context(|| format!("failed to change read-only mode of database '{pg_db}'"))?;
+        Ok(())
+    }
+
+    pub async fn container_limits(&self, container_name: &str) -> anyhow::Result<ContainerLimits> {
+        let info = self
+            .retry
+            .run("inspect container", CallKind::Idempotent, || {
+                self.client.inspect_container(
+                    container_name,
+                    None::<bollard::query_parameters::InspectContainerOptions>,
+                )
+            })
+            .await
+            .with_context(|| format!("failed to inspect container '{container_name}'"))?;
+        let host_config = info.host_config.unwrap_or_default();
+        Ok(ContainerLimits {
+            memory_bytes: host_config.memory.filter(|m| *m > 0),
+            nano_cpus: host_config.nano_cpus.filter(|c| *c > 0),
+        })
+    }
+
+    /// Change the limits of an existing container without restarting it.
+    pub async fn update_limits(
+        &self,
+        container_name: &str,
+        limits: &ContainerLimits,
+    ) -> anyhow::Result<()> {
+        // 0 lifts a limit; capping swap at the memory limit lets Docker accept a lower one
+        let memory = limits.memory_bytes.unwrap_or(0);
+        let update = ContainerUpdateBody {
+            memory: Some(memory),
+            memory_swap: Some(if memory > 0 { memory } else { -1 }),
+            nano_cpus: Some(limits.nano_cpus.unwrap_or(0)),
+            ..Default::default()
+        };
+        self.retry
+            .run("update container", CallKind::Idempotent, || {
+                self.client.update_container(container_name, update.clone())
+            })
+            .await
+            .with_context(|| format!("failed to update limits of container '{container_name}'"))
+    }
+
+    /// Create any of `databases` that don't exist yet, owned by `pg_user`.
+    pub async fn ensure_databases(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        databases: &[&str],
+    ) -> anyhow::Result<()> {
+        // `\gexec` runs the generated CREATE DATABASE, which can't run in a DO block
+        let sql: String = databases
+            .iter()
+            .map(|db| {
+                let literal = format!("'{}'", db.replace('\'', "''"));
+                format!(
+                    "SELECT format('CREATE DATABASE %I', {literal}) \
+                     WHERE NOT EXISTS (SELECT FROM pg_database WHERE datname = {literal})\\gexec\n"
+                )
+            })
+            .collect();
+        let (exit_code, stderr) = self
+            .exec_output(
+                container_name,
+                &[
+                    "sh",
+                    "-c",
+                    "printf '%s' \"$PGBRANCH_SQL\" | psql -q -v ON_ERROR_STOP=1 -d postgres",
+                ],
+                &[("PGUSER", pg_user), ("PGBRANCH_SQL", &sql)],
+            )
+            .await?;
+        if exit_code != 0 {
+            return Err(anyhow!(
+                "failed to create databases {} in '{container_name}': {}",
+                databases.join(", "),
+                stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Run a SQL statement through the container's trusted local socket and return
+    /// its unaligned, tuples-only output.
+    pub async fn query(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        sql: &str,
+    ) -> anyhow::Result<String> {
+        let (exit_code, stdout, stderr) = self
+            .exec_capture(
+                container_name,
+                &[
+                    "psql",
+                    "-v",
+                    "ON_ERROR_STOP=1",
+                    "-tA",
+                    "-U",
+                    pg_user,
+                    "-d",
+                    pg_db,
+                    "-c",
+                    sql,
+                ],
+                &[],
+            )
+            .await?;
+        if exit_code != 0 {
+            return Err(anyhow!(
+                "query failed in '{container_name}': {}",
+                stderr.trim()
+            ));
+        }
+        Ok(stdout.trim().to_string())
+    }
+
+    /// Run a SQL file inside the container with psql, stopping at the first
+    /// error, and return what it printed. The file is removed afterwards.
+    pub async fn run_sql_file(
+        &self,
+        container_name: &str,
+        pg_user: &str,
+        pg_db: &str,
+        path: &str,
+    ) -> anyhow::Result<String> {
+        let (exit_code, stdout, stderr) = self
+            .exec_capture(
+                container_name,
+                &[
+                    "sh",
+                    "-c",
+                    "psql -X -v ON_ERROR_STOP=1 -f \"$1\"; status=$?; rm -f \"$1\"; exit $status",
+                    "sh",
+                    path,
+                ],
+                &[("PGUSER", pg_user), ("PGDATABASE", pg_db)],
+            )
+            .await?;
+        if exit_code != 0 {
+            return Err(anyhow!("{}", stderr.trim()));
+        }
+        Ok(stdout)
+    }
+
+    /// Run a command inside a container, returning its exit code and stderr.
+    async fn exec_output(
+        &self,
+        container_name: &str,
+        cmd: &[&str],
+        env: &[(&str, &str)],
+    ) -> anyhow::Result<(i64, String)> {
+        let (exit_code, _, stderr) = self.exec_capture(container_name, cmd, env).await?;
+        Ok((exit_code, stderr))
+    }
+
+    /// The engine's own CLI running `cmd` inside a container, attached to
+    /// this terminal, e.g. for an interactive `psql`. It talks to the same
+    /// endpoint as this runtime rather than whatever its context points to.
+    pub fn cli_exec(&self, container_name: &str, cmd: &[String]) -> HostCommand {
+        let (program, host_var) = match self.engine {
+            ContainerEngine::Docker => ("docker", "DOCKER_HOST"),
+            ContainerEngine::Podman => ("podman", "CONTAINER_HOST"),
+        };
+        let mut args = vec!["exec".to_string(), "-i".to_string()];
+        if std::io::stdin().is_terminal() {
+            args.push("-t".to_string());
+        }
+        args.push(container_name.to_string());
+        args.extend(cmd.iter().cloned());
+        HostCommand {
+            program: program.to_string(),
+            args,
+            env: vec![(host_var.to_string(), self.endpoint.host.clone())],
+        }
+    }
+
+    /// Run a command inside a container, returning its exit code, stdout and stderr.
+    async fn exec_capture(
+        &self,
+        container_name: &str,
+        cmd: &[&str],
+        env: &[(&str, &str)],
+    ) -> anyhow::Result<(i64, String, String)> {
+        let config = ExecConfig {
+            cmd: Some(cmd.iter().map(|s| s.to_string()).collect()),
+            env: Some(env.iter().map(|(k, v)| format!("{k}={v}")).collect()),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            ..Default::default()
+        };
+        // An exec does nothing until it's started, so creating one can be repeated
+        let exec = self
+            .retry
+            .run("create exec", CallKind::Idempotent, || {
+                self.client.create_exec(container_name, config.clone())
+            })
+            .await
+            .with_context(|| format!("failed to exec in container '{container_name}'"))?;
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        if let bollard::exec::StartExecResults::Attached { mut output, .. } = self
+            .retry
+            .run("start exec", CallKind::Mutating, || {
+                self.client.start_exec(
+                    &exec.id,
+                    Some(StartExecOptions {
+                        detach: false,
+                        ..Default::default()
+                    }),
+                )
+            })
+            .await?
+        {
+            while let Some(msg) = output.try_next().await? {
+                match msg {
+                    bollard::container::LogOutput::StdOut { message } => {
+                        stdout.extend_from_slice(&message)
+                    }
+                    bollard::container::LogOutput::StdErr { message } => {
+                        stderr.extend_from_slice(&message)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let exit_code = self
+            .retry
+            .run("inspect exec", CallKind::Idempotent, || {
+                self.client.inspect_exec(&exec.id)
+            })
+            .await?
+            .exit_code
+            .unwrap_or(-1);
+        Ok((
+            exit_code,
+            String::from_utf8_lossy(&stdout).into_owned(),
+            String::from_utf8_lossy(&stderr).into_owned(),
+        ))
+    }
+
     /// Run a command inside a container and return true if it exits successfully.
     async fn exec_check(&self, container_name: &str, cmd: &[&str]) -> bool {
         let config = ExecConfig {
@@ -386,7 +1410,42 @@ impl DockerRuntime {
     }
 }
 
-pub async fn pick_available_port(client: &Docker, start_port: u16) -> anyhow::Result<u16> {
+/// Explain Docker Desktop's "Mounts denied" for a data directory it doesn't share.
+fn mount_error(error: bollard::errors::Error, data_dir: &Path) -> anyhow::Error {
+    let message = error.to_string();
+    if message.contains("Mounts denied") || message.contains("is not shared from the host") {
+        anyhow::Error::new(error).context(super::docker_desktop::mount_denied_hint(data_dir))
+    } else {
+        error.into()
+    }
+}
+
+/// The log line that best explains an unhealthy server: a full disk first,
+/// then the latest PANIC or FATAL.
+fn unhealthy_reason(log_tail: &[String]) -> Option<String> {
+    let latest = |needle: &str| log_tail.iter().rev().find(|line| line.contains(needle));
+    latest("No space left on device")
+        .or_else(|| latest("PANIC:"))
+        .or_else(|| latest("FATAL:"))
+        .map(|line| line.trim().to_string())
+}
+
+/// Whether starting a container failed because its published port is
+/// taken, by another container or by an app on the host.
+pub fn is_port_conflict(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let message = cause.to_string();
+        message.contains("port is already allocated") || message.contains("address already in use")
+    })
+}
+
+/// The first port from `start_port` that no container publishes or
+/// reserves and that can be bound on `bind_address`.
+pub async fn pick_available_port(
+    client: &Docker,
+    bind_address: IpAddr,
+    start_port: u16,
+) -> anyhow::Result<u16> {
     let docker_ports = docker_published_ports(client).await;
     let mut port = start_port;
 
@@ -399,7 +1458,7 @@ pub async fn pick_available_port(client: &Docker, start_port: u16) -> anyhow::Re
             continue;
         }
 
-        if is_port_available(port).await {
+        if is_port_available(bind_address, port).await {
             return Ok(port);
         }
 
@@ -414,17 +1473,18 @@ pub async fn pick_available_port(client: &Docker, start_port: u16) -> anyhow::Re
     ))
 }
 
-async fn is_port_available(port: u16) -> bool {
-    if let Ok(listener) = tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+async fn is_port_available(bind_address: IpAddr, port: u16) -> bool {
+    if let Ok(listener) = tokio::net::TcpListener::bind((bind_address, port)).await {
         drop(listener);
         return true;
     }
     false
 }
 
+/// Ports published by running containers, plus those reserved by stopped branch containers.
 async fn docker_published_ports(client: &Docker) -> HashSet<u16> {
     let options = ListContainersOptions {
-        all: false,
+        all: true,
         ..Default::default()
     };
 
@@ -435,6 +1495,14 @@ async fn docker_published_ports(client: &Docker) -> HashSet<u16> {
 
     let mut ports = HashSet::new();
     for container in containers {
+        if let Some(port) = container
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(PORT_LABEL))
+            .and_then(|port| port.parse().ok())
+        {
+            ports.insert(port);
+        }
         if let Some(port_list) = container.ports {
             for port in port_list {
                 if let Some(public_port) = port.public_port {
@@ -468,6 +1536,39 @@ fn get_host_uid_gid() -> Option<String> {
     None
 }
 
+/// Start of the container names of a project. The hash of its id keeps apart
+/// projects with the same name, such as two checkouts called `app` that use
+/// different data roots.
+pub fn container_prefix(project_name: &str, project_id: &str) -> String {
+    format!(
+        "pgbranch-{}-{:08x}-",
+        sanitize(project_name),
+        name_hash(project_id) as u32
+    )
+}
+
+/// Whether a container name has the project's current prefix, allowing for
+/// names that were truncated to make room for a hash suffix.
+pub fn has_container_prefix(container_name: &str, project_name: &str, project_id: &str) -> bool {
+    let prefix = container_prefix(project_name, project_id);
+    container_name.starts_with(truncate_name(&prefix, MAX_CONTAINER_NAME_LEN - 9))
+}
+
+fn truncate_name(name: &str, max_len: usize) -> &str {
+    // sanitize() only emits ASCII, so byte slicing is safe
+    name[..name.len().min(max_len)].trim_end_matches('-')
+}
+
+/// FNV-1a: stable across Rust releases, unlike `DefaultHasher`.
+fn name_hash(input: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in input.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 fn sanitize(input: &str) -> String {
     let mut output = String::with_capacity(input.len());
     for ch in input.chars() {
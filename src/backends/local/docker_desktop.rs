@@ -0,0 +1,149 @@
+//! Docker Desktop file sharing on macOS. Docker Desktop only bind-mounts
+//! host directories listed under Settings > Resources > File sharing
+//! (`/Users`, `/Volumes`, `/private`, `/tmp` and `/var/folders` out of the
+//! box); anything else fails with "Mounts denied". Branch data is bind
+//! mounted, so `data_root` has to be inside one of those directories.
+
+use std::path::{Path, PathBuf};
+
+use crate::backends::DoctorCheck;
+
+/// Docker Desktop's settings, newest format first, relative to the home directory.
+const SETTINGS_FILES: [&str; 2] = [
+    "Library/Group Containers/group.com.docker/settings-store.json",
+    "Library/Group Containers/group.com.docker/settings.json",
+];
+
+/// Folders macOS guards with privacy prompts, relative to the home directory.
+const PROTECTED_FOLDERS: [&str; 4] = [
+    "Desktop",
+    "Documents",
+    "Downloads",
+    "Library/Mobile Documents",
+];
+
+/// Directories Docker Desktop shares with containers, or `None` when not on
+/// macOS or its settings can't be read.
+pub fn shared_paths() -> Option<Vec<PathBuf>> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+    let home = dirs::home_dir()?;
+    SETTINGS_FILES.iter().find_map(|file| {
+        let text = std::fs::read_to_string(home.join(file)).ok()?;
+        let settings: serde_json::Value = serde_json::from_str(&text).ok()?;
+        let dirs = settings
+            .get("FilesharingDirectories")
+            .or_else(|| settings.get("filesharingDirectories"))?
+            .as_array()?;
+        Some(
+            dirs.iter()
+                .filter_map(|dir| dir.as_str())
+                .map(PathBuf::from)
+                .collect(),
+        )
+    })
+}
+
+/// Whether `path` is inside one of the `shared` directories.
+pub fn is_shared(path: &Path, shared: &[PathBuf]) -> bool {
+    // /tmp and /var are symlinks into /private, and either spelling may be listed
+    let resolved = resolve(path);
+    shared
+        .iter()
+        .any(|dir| path.starts_with(dir) || resolved.starts_with(resolve(dir)))
+}
+
+/// `path` with symlinks resolved in the part of it that exists.
+fn resolve(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find_map(|ancestor| {
+            let canonical = ancestor.canonicalize().ok()?;
+            Some(canonical.join(path.strip_prefix(ancestor).ok()?))
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// `preferred` if Docker Desktop can mount it (or its settings are unknown),
+/// else `pgbranch` in the first shared directory it can be created in.
+pub fn compatible_data_root(preferred: PathBuf) -> PathBuf {
+    let Some(shared) = shared_paths() else {
+        return preferred;
+    };
+    if is_shared(&preferred, &shared) {
+        return preferred;
+    }
+    for dir in &shared {
+        let candidate = dir.join("pgbranch");
+        if std::fs::create_dir_all(&candidate).is_ok() {
+            log::info!(
+                "{} isn't shared with Docker Desktop; using {} as data_root",
+                preferred.display(),
+                candidate.display()
+            );
+            return candidate;
+        }
+    }
+    preferred
+}
+
+/// Whether Docker Desktop can mount `data_root`; nothing to check off macOS.
+pub fn doctor_checks(data_root: &Path) -> Vec<DoctorCheck> {
+    if !cfg!(target_os = "macos") {
+        return Vec::new();
+    }
+    let root = data_root.display();
+    let mut checks = Vec::new();
+    match shared_paths() {
+        Some(shared) if is_shared(data_root, &shared) => checks.push(DoctorCheck {
+            name: "Docker Desktop file sharing".to_string(),
+            available: true,
+            detail: format!("{root} is in a directory shared with containers"),
+        }),
+        Some(shared) => checks.push(DoctorCheck {
+            name: "Docker Desktop file sharing".to_string(),
+            available: false,
+            detail: format!(
+                "{root} isn't shared with containers (shared: {}); add it under Docker Desktop > Settings > Resources > File sharing, or set local.data_root inside a shared directory",
+                shared
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }),
+        None => checks.push(DoctorCheck {
+            name: "Docker Desktop file sharing".to_string(),
+            available: true,
+            detail: "Docker Desktop settings not found; can't tell which directories are shared"
+                .to_string(),
+        }),
+    }
+    if let Some(folder) = protected_folder(data_root) {
+        checks.push(DoctorCheck {
+            name: "macOS privacy".to_string(),
+            available: false,
+            detail: format!(
+                "{root} is inside ~/{folder}, which macOS guards; allow Docker Desktop under System Settings > Privacy & Security > Files and Folders, or move data_root out of it"
+            ),
+        });
+    }
+    checks
+}
+
+/// Guidance for Docker's "Mounts denied" error, which Docker Desktop returns
+/// for a directory that isn't shared.
+pub fn mount_denied_hint(data_dir: &Path) -> String {
+    format!(
+        "Docker Desktop can't mount {}; share it under Settings > Resources > File sharing, or set local.data_root inside a shared directory (run 'pgbranch doctor' to see which are)",
+        data_dir.display()
+    )
+}
+
+fn protected_folder(path: &Path) -> Option<&'static str> {
+    let home = dirs::home_dir()?;
+    PROTECTED_FOLDERS
+        .iter()
+        .copied()
+        .find(|folder| path.starts_with(home.join(folder)))
+}
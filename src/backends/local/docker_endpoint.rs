@@ -0,0 +1,226 @@
+//! Which Docker engine to talk to. Colima, OrbStack, Lima and Rancher
+//! Desktop put their sockets under the home directory rather than at
+//! `/var/run/docker.sock`, and point the docker CLI at them with a context,
+//! so the local defaults alone miss them. The context is read from the
+//! docker CLI's own files, the same way `docker ps` picks its engine.
+//!
+//! Podman serves the same API on its own socket, which `podman` finds from
+//! `CONTAINER_HOST` or the rootless and rootful defaults.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::config::ContainerEngine;
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// The context the docker CLI uses when none is selected.
+const DEFAULT_CONTEXT: &str = "default";
+
+/// Sockets of Docker-compatible runtimes, relative to the home directory.
+const RUNTIME_SOCKETS: [(&str, &str); 7] = [
+    (".colima/default/docker.sock", "Colima"),
+    (".colima/docker.sock", "Colima"),
+    (".orbstack/run/docker.sock", "OrbStack"),
+    (".lima/default/sock/docker.sock", "Lima"),
+    (".lima/docker/sock/docker.sock", "Lima"),
+    (".rd/docker.sock", "Rancher Desktop"),
+    (".docker/run/docker.sock", "Docker Desktop"),
+];
+
+/// Podman's API sockets: rootless under `XDG_RUNTIME_DIR`, rootful, and
+/// the `podman machine` VM's on macOS (relative to the home directory).
+const PODMAN_RUNTIME_SOCKET: &str = "podman/podman.sock";
+const PODMAN_ROOTFUL_SOCKET: &str = "/run/podman/podman.sock";
+const PODMAN_MACHINE_SOCKETS: [&str; 2] = [
+    ".local/share/containers/podman/machine/podman.sock",
+    ".local/share/containers/podman/machine/qemu/podman.sock",
+];
+
+/// A Docker engine address and where it was found.
+#[derive(Debug, Clone)]
+pub struct DockerEndpoint {
+    /// `unix://`, `tcp://` or `https://` address
+    pub host: String,
+    pub source: String,
+}
+
+/// The engine `docker ps` would talk to: the configured `docker_host`, else
+/// the configured `docker_context`, `DOCKER_CONTEXT`, `DOCKER_HOST`, then the
+/// docker CLI's current context. With no context selected (or `default`) and
+/// no `/var/run/docker.sock`, the sockets of the runtimes above (plus rootless
+/// Docker's) are tried. Falls back to the default socket even when it's
+/// missing, so the connection error names it.
+///
+/// For Podman, docker contexts don't apply: the configured `docker_host`,
+/// else `CONTAINER_HOST`, else the first Podman socket that exists.
+pub fn resolve(
+    engine: ContainerEngine,
+    configured_host: Option<&str>,
+    configured_context: Option<&str>,
+) -> anyhow::Result<DockerEndpoint> {
+    if let Some(host) = configured_host {
+        return Ok(endpoint(host, "local.docker_host"));
+    }
+    if engine == ContainerEngine::Podman {
+        if let Some(host) = non_empty_env("CONTAINER_HOST") {
+            return Ok(endpoint(&host, "CONTAINER_HOST"));
+        }
+        return Ok(podman_socket().unwrap_or_else(|| {
+            let rootless = std::env::var_os("XDG_RUNTIME_DIR")
+                .map(|dir| PathBuf::from(dir).join(PODMAN_RUNTIME_SOCKET));
+            match rootless {
+                Some(path) => socket(&path, "rootless Podman socket"),
+                None => socket(Path::new(PODMAN_ROOTFUL_SOCKET), "Podman socket"),
+            }
+        }));
+    }
+    if let Some(name) = configured_context {
+        return context_endpoint(name, "local.docker_context");
+    }
+    if let Some(name) = non_empty_env("DOCKER_CONTEXT") {
+        return context_endpoint(&name, "DOCKER_CONTEXT");
+    }
+    if let Some(host) = non_empty_env("DOCKER_HOST") {
+        return Ok(endpoint(&host, "DOCKER_HOST"));
+    }
+    if let Some(name) = current_context() {
+        if name != DEFAULT_CONTEXT {
+            return context_endpoint(&name, "docker CLI config");
+        }
+    }
+    Ok(local_endpoint())
+}
+
+/// The address of the docker context `name`, as `docker context use` would
+/// select it. `default` is `DOCKER_HOST` or the local engine.
+fn context_endpoint(name: &str, selected_by: &str) -> anyhow::Result<DockerEndpoint> {
+    if name == DEFAULT_CONTEXT {
+        return Ok(match non_empty_env("DOCKER_HOST") {
+            Some(host) => endpoint(&host, "DOCKER_HOST"),
+            None => local_endpoint(),
+        });
+    }
+    let host = context_host(name).ok_or_else(|| {
+        anyhow!(
+            "docker context '{name}' set by {selected_by} not found in {}; run 'docker context ls' to see the available contexts",
+            config_dir().join("contexts").display()
+        )
+    })?;
+    Ok(endpoint(
+        &host,
+        &format!("docker context '{name}' set by {selected_by}"),
+    ))
+}
+
+/// The docker CLI's config directory: `DOCKER_CONFIG`, else `~/.docker`.
+fn config_dir() -> PathBuf {
+    match std::env::var_os("DOCKER_CONFIG").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".docker"),
+    }
+}
+
+/// `currentContext` from the docker CLI's `config.json`, set by `docker context use`.
+fn current_context() -> Option<String> {
+    let text = std::fs::read_to_string(config_dir().join("config.json")).ok()?;
+    let config: serde_json::Value = serde_json::from_str(&text).ok()?;
+    config
+        .get("currentContext")?
+        .as_str()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// The docker endpoint of context `name`. Contexts are stored under
+/// `contexts/meta/<sha256 of name>/meta.json`; matching on the `Name` inside
+/// finds the same one without hashing.
+fn context_host(name: &str) -> Option<String> {
+    let entries = std::fs::read_dir(config_dir().join("contexts").join("meta")).ok()?;
+    entries.flatten().find_map(|entry| {
+        let text = std::fs::read_to_string(entry.path().join("meta.json")).ok()?;
+        let meta: serde_json::Value = serde_json::from_str(&text).ok()?;
+        if meta.get("Name")?.as_str()? != name {
+            return None;
+        }
+        meta.pointer("/Endpoints/docker/Host")?
+            .as_str()
+            .map(str::to_string)
+    })
+}
+
+/// `/var/run/docker.sock`, or the socket of another runtime when it's missing.
+fn local_endpoint() -> DockerEndpoint {
+    if cfg!(unix) && !Path::new(DEFAULT_SOCKET).exists() {
+        if let Some(found) = runtime_socket() {
+            return found;
+        }
+    }
+    default_endpoint()
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn runtime_socket() -> Option<DockerEndpoint> {
+    let home = dirs::home_dir()?;
+    let found = RUNTIME_SOCKETS
+        .iter()
+        .map(|(path, runtime)| (home.join(path), format!("{runtime} socket")))
+        .chain(std::env::var_os("XDG_RUNTIME_DIR").map(|dir| {
+            (
+                PathBuf::from(dir).join("docker.sock"),
+                "rootless Docker socket".to_string(),
+            )
+        }))
+        .find(|(path, _)| path.exists())?;
+    Some(socket(&found.0, &found.1))
+}
+
+/// The first Podman socket that exists, rootless before rootful. `doctor`
+/// uses it to suggest `runtime: podman` when Docker isn't there.
+pub fn podman_socket() -> Option<DockerEndpoint> {
+    let rootless = std::env::var_os("XDG_RUNTIME_DIR").map(|dir| {
+        (
+            PathBuf::from(dir).join(PODMAN_RUNTIME_SOCKET),
+            "rootless Podman socket",
+        )
+    });
+    let machine = dirs::home_dir().into_iter().flat_map(|home| {
+        PODMAN_MACHINE_SOCKETS
+            .iter()
+            .map(move |path| (home.join(path), "podman machine socket"))
+    });
+    let found = rootless
+        .into_iter()
+        .chain(std::iter::once((
+            PathBuf::from(PODMAN_ROOTFUL_SOCKET),
+            "Podman socket",
+        )))
+        .chain(machine)
+        .find(|(path, _)| path.exists())?;
+    Some(socket(&found.0, found.1))
+}
+
+fn default_endpoint() -> DockerEndpoint {
+    if cfg!(windows) {
+        endpoint("npipe:////./pipe/docker_engine", "default pipe")
+    } else {
+        socket(Path::new(DEFAULT_SOCKET), "default socket")
+    }
+}
+
+fn socket(path: &Path, source: &str) -> DockerEndpoint {
+    endpoint(&format!("unix://{}", path.display()), source)
+}
+
+fn endpoint(host: &str, source: &str) -> DockerEndpoint {
+    DockerEndpoint {
+        host: host.to_string(),
+        source: source.to_string(),
+    }
+}
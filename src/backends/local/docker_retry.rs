@@ -0,0 +1,202 @@
+//! Retries for Docker API calls. A busy socket, or a daemon that drops the
+//! connection in the middle of a pull, fails a call that works a moment later,
+//! so those errors are retried with jittered backoff. Anything else (a missing
+//! image, a port already taken, a denied mount) surfaces straight away.
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use bollard::errors::Error;
+
+use crate::config::DockerRetryConfig;
+
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const BASE_DELAY: Duration = Duration::from_millis(250);
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Error messages the daemon relays for network trouble rather than a bad request.
+const TRANSIENT_MESSAGES: [&str; 9] = [
+    "unexpected EOF",
+    "connection reset",
+    "broken pipe",
+    "i/o timeout",
+    "TLS handshake timeout",
+    "context deadline exceeded",
+    "temporarily unavailable",
+    "too many requests",
+    "toomanyrequests",
+];
+
+/// What repeating a call could do, which decides which failures are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    /// Reads, and anything else that can be repeated safely
+    Idempotent,
+    /// Changes state; only repeated when the daemon never got the request
+    Mutating,
+    /// Image pulls: safe to repeat, and too slow to put a timeout on
+    Pull,
+}
+
+/// How a failed call went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The request never reached the daemon, or it turned it away unprocessed
+    Unsent,
+    /// The connection broke or timed out; the daemon may have acted on it
+    Interrupted,
+    /// Retrying won't help
+    Fatal,
+}
+
+/// How many times to try a Docker call and how long to wait for each try.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries per call, the first included
+    pub attempts: u32,
+    /// Limit on each try, except for image pulls
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: DEFAULT_ATTEMPTS,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: Option<&DockerRetryConfig>) -> Self {
+        let defaults = Self::default();
+        let Some(config) = config else {
+            return defaults;
+        };
+        Self {
+            attempts: config.attempts.unwrap_or(defaults.attempts).max(1),
+            timeout: config
+                .timeout_secs
+                .map_or(defaults.timeout, Duration::from_secs),
+        }
+    }
+
+    /// Run `call`, trying again after failures that repeating it can't make
+    /// worse: unsent requests always, interrupted ones only if it's not `Mutating`.
+    pub async fn run<T, F, Fut>(&self, what: &str, kind: CallKind, mut call: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = if kind == CallKind::Pull {
+                call().await
+            } else {
+                tokio::time::timeout(self.timeout, call())
+                    .await
+                    .unwrap_or(Err(Error::RequestTimeoutError))
+            };
+            let err = match result {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let retry = match classify(&err) {
+                Failure::Unsent => true,
+                Failure::Interrupted => kind != CallKind::Mutating,
+                Failure::Fatal => false,
+            };
+            if !retry || attempt >= self.attempts {
+                return Err(err);
+            }
+            let delay = backoff(attempt);
+            log::debug!(
+                "Docker {} failed ({}); retrying in {}ms (attempt {}/{})",
+                what,
+                err,
+                delay.as_millis(),
+                attempt + 1,
+                self.attempts
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether `err` is worth retrying, and whether the daemon may have acted on the call.
+pub fn classify(err: &Error) -> Failure {
+    match err {
+        Error::RequestTimeoutError => Failure::Interrupted,
+        Error::DockerResponseServerError {
+            status_code,
+            message,
+        } => match status_code {
+            429 | 503 => Failure::Unsent,
+            502 | 504 => Failure::Interrupted,
+            500 if is_transient_message(message) => Failure::Interrupted,
+            _ => Failure::Fatal,
+        },
+        Error::DockerStreamError { error } if is_transient_message(error) => Failure::Interrupted,
+        _ => {
+            // Connection trouble arrives wrapped in hyper's errors; the
+            // io::Error underneath says at which point it happened
+            let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+            while let Some(current) = source {
+                if let Some(io) = current.downcast_ref::<std::io::Error>() {
+                    return classify_io(io.kind());
+                }
+                source = current.source();
+            }
+            let message = err.to_string();
+            if message.contains("connection closed")
+                || message.contains("IncompleteMessage")
+                || is_transient_message(&message)
+            {
+                Failure::Interrupted
+            } else {
+                Failure::Fatal
+            }
+        }
+    }
+}
+
+fn classify_io(kind: std::io::ErrorKind) -> Failure {
+    use std::io::ErrorKind;
+    match kind {
+        ErrorKind::ConnectionRefused
+        | ErrorKind::WouldBlock
+        | ErrorKind::NotConnected
+        | ErrorKind::AddrNotAvailable => Failure::Unsent,
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe
+        | ErrorKind::UnexpectedEof
+        | ErrorKind::TimedOut
+        | ErrorKind::Interrupted => Failure::Interrupted,
+        // A missing socket or one we may not open won't appear by retrying
+        _ => Failure::Fatal,
+    }
+}
+
+fn is_transient_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    TRANSIENT_MESSAGES
+        .iter()
+        .any(|needle| message.contains(&needle.to_lowercase()))
+}
+
+/// Exponential backoff with jitter, so several pgbranch processes retrying
+/// against the same daemon don't do it in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY);
+    // Each RandomState is seeded differently, which is all the randomness needed
+    let random = RandomState::new().hash_one(attempt);
+    let half = ceiling / 2;
+    half + Duration::from_millis(random % (half.as_millis() as u64 + 1))
+}
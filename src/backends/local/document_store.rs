@@ -0,0 +1,1118 @@
+//! The registry kept as one JSON document instead of SQLite tables, so it
+//! can live where a team shares it: an object in S3 (`state_store:
+//! s3://bucket/key.json`). Every operation reads the document again, and one
+//! that changes it writes it back, so each command sees what teammates
+//! recorded before it.
+//!
+//! A write only goes through if the document is still the version it was
+//! read at (`If-Match` on the ETag, `If-None-Match` for the first write);
+//! otherwise the change is applied again to what a teammate wrote in
+//! between. Limits that remain: S3-compatible stores that ignore those
+//! headers fall back to the later write winning, a change that keeps losing
+//! the race gives up after a few attempts, and only the registry is
+//! guarded, not the work a command does before recording it. A port handed
+//! out by `next_port` is refused on `create_branch` if someone else took it
+//! first, so the command fails instead of two branches sharing it.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use super::model::{now_epoch_millis, Branch, BranchState, Credentials, Project, Snapshot};
+use super::state::{
+    Drift, NewBranch, NewProject, StateStore, UsageSummary, DRIFT_RETENTION_MS, FIRST_BRANCH_PORT,
+};
+
+/// How often a change is applied again after losing a race with another write.
+const UPDATE_ATTEMPTS: u32 = 8;
+
+/// A stored document and the version it was read at.
+pub struct Stored {
+    pub bytes: Vec<u8>,
+    /// Changes with every write, like an S3 ETag
+    pub version: String,
+}
+
+/// Where the document is read from and written to.
+pub trait DocumentLocation: Send {
+    /// The stored document, or `None` before anything was written.
+    fn load(&self) -> anyhow::Result<Option<Stored>>;
+    /// Write `bytes` if the document is still at `version` (`None`: not
+    /// written yet). Returns `false`, writing nothing, if it isn't.
+    fn save(&self, bytes: &[u8], version: Option<&str>) -> anyhow::Result<bool>;
+    /// For error messages, e.g. `s3://bucket/key.json`.
+    fn describe(&self) -> String;
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Document {
+    #[serde(default)]
+    projects: Vec<Project>,
+    #[serde(default)]
+    branches: Vec<Branch>,
+    #[serde(default)]
+    snapshots: Vec<Snapshot>,
+    #[serde(default)]
+    drift: Vec<DriftRecord>,
+    #[serde(default)]
+    usage: Vec<UsageRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DriftRecord {
+    project_id: String,
+    branch_id: String,
+    branch_name: String,
+    from_state: BranchState,
+    to_state: BranchState,
+    reason: String,
+    detected_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UsageRecord {
+    project_name: String,
+    operation: String,
+    storage_backend: Option<String>,
+    detail: Option<String>,
+    duration_ms: i64,
+    created_at: i64,
+}
+
+impl Document {
+    fn branch_mut(&mut self, branch_id: &str) -> Option<&mut Branch> {
+        self.branches.iter_mut().find(|b| b.id == branch_id)
+    }
+
+    fn insert_project(&mut self, project: Project) -> anyhow::Result<()> {
+        if self.projects.iter().any(|p| p.name == project.name) {
+            anyhow::bail!(
+                "failed to insert project: '{}' already exists",
+                project.name
+            );
+        }
+        self.projects.push(project);
+        Ok(())
+    }
+
+    fn insert_branch(&mut self, branch: Branch) -> anyhow::Result<()> {
+        if !self.projects.iter().any(|p| p.id == branch.project_id) {
+            anyhow::bail!("failed to insert branch '{}': no such project", branch.name);
+        }
+        if self.branches.iter().any(|b| {
+            b.id == branch.id || (b.project_id == branch.project_id && b.name == branch.name)
+        }) {
+            anyhow::bail!("failed to insert branch: '{}' already exists", branch.name);
+        }
+        self.branches.push(branch);
+        Ok(())
+    }
+
+    fn insert_snapshot(&mut self, snapshot: Snapshot) -> anyhow::Result<()> {
+        if !self.branches.iter().any(|b| b.id == snapshot.branch_id) {
+            anyhow::bail!(
+                "failed to insert snapshot '{}': no such branch",
+                snapshot.name
+            );
+        }
+        if self.snapshots.iter().any(|s| {
+            s.id == snapshot.id || (s.branch_id == snapshot.branch_id && s.name == snapshot.name)
+        }) {
+            anyhow::bail!(
+                "failed to insert snapshot: '{}' already exists",
+                snapshot.name
+            );
+        }
+        self.snapshots.push(snapshot);
+        Ok(())
+    }
+
+    /// What the SQLite schema's foreign keys do on delete.
+    fn remove_branches(&mut self, removed: impl Fn(&Branch) -> bool) {
+        let ids: Vec<String> = self
+            .branches
+            .iter()
+            .filter(|b| removed(b))
+            .map(|b| b.id.clone())
+            .collect();
+        self.branches.retain(|b| !ids.contains(&b.id));
+        for branch in &mut self.branches {
+            if branch
+                .parent_branch_id
+                .as_ref()
+                .is_some_and(|id| ids.contains(id))
+            {
+                branch.parent_branch_id = None;
+            }
+        }
+        self.drift.retain(|d| !ids.contains(&d.branch_id));
+        self.remove_snapshots(|s| ids.contains(&s.branch_id));
+    }
+
+    fn remove_snapshots(&mut self, removed: impl Fn(&Snapshot) -> bool) {
+        let ids: Vec<String> = self
+            .snapshots
+            .iter()
+            .filter(|s| removed(s))
+            .map(|s| s.id.clone())
+            .collect();
+        self.snapshots.retain(|s| !ids.contains(&s.id));
+        for branch in &mut self.branches {
+            if branch
+                .parent_snapshot_id
+                .as_ref()
+                .is_some_and(|id| ids.contains(id))
+            {
+                branch.parent_snapshot_id = None;
+            }
+        }
+    }
+}
+
+/// The registry as a JSON document at a [`DocumentLocation`].
+pub struct DocumentStore {
+    location: Box<dyn DocumentLocation>,
+}
+
+impl DocumentStore {
+    pub fn new(location: Box<dyn DocumentLocation>) -> Self {
+        Self { location }
+    }
+
+    /// The store a `state_store` setting names.
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        let Some(without_scheme) = url.strip_prefix("s3://") else {
+            anyhow::bail!(
+                "Unsupported state_store '{}': expected s3://bucket/key",
+                url
+            );
+        };
+        let (bucket, key) = without_scheme
+            .split_once('/')
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| anyhow!("Invalid state_store '{}': expected s3://bucket/key", url))?;
+        let store = Self::new(s3_location(bucket, key)?);
+        // Fail now rather than halfway through the first command
+        store.read()?;
+        Ok(store)
+    }
+
+    /// A store that starts empty and keeps the document in memory, to test
+    /// code that records state without a database.
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self::new(Box::new(MemoryDocument::default()))
+    }
+
+    fn read(&self) -> anyhow::Result<Document> {
+        Ok(self.read_versioned()?.0)
+    }
+
+    fn read_versioned(&self) -> anyhow::Result<(Document, Option<String>)> {
+        match self.location.load()? {
+            Some(stored) => {
+                let document = serde_json::from_slice(&stored.bytes).with_context(|| {
+                    format!(
+                        "{} is not a pgbranch state document",
+                        self.location.describe()
+                    )
+                })?;
+                Ok((document, Some(stored.version)))
+            }
+            None => Ok((Document::default(), None)),
+        }
+    }
+
+    /// Apply `change` to the current document and write it back, unless it
+    /// fails. If someone else wrote the document in the meantime, `change`
+    /// is applied again to theirs.
+    fn update<T>(
+        &self,
+        mut change: impl FnMut(&mut Document) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        for attempt in 1..=UPDATE_ATTEMPTS {
+            let (mut document, version) = self.read_versioned()?;
+            let result = change(&mut document)?;
+            let bytes = serde_json::to_vec_pretty(&document)?;
+            let written = self
+                .location
+                .save(&bytes, version.as_deref())
+                .with_context(|| format!("failed to write {}", self.location.describe()))?;
+            if written {
+                return Ok(result);
+            }
+            log::debug!(
+                "{} changed while updating it (attempt {}), trying again",
+                self.location.describe(),
+                attempt
+            );
+            // Spread out teammates retrying at the same moment
+            let jitter = RandomState::new().hash_one(attempt) % 100;
+            std::thread::sleep(Duration::from_millis(50 * u64::from(attempt) + jitter));
+        }
+        anyhow::bail!(
+            "{} kept changing while pgbranch tried to update it; try again",
+            self.location.describe()
+        )
+    }
+
+    fn update_branch(
+        &self,
+        branch_id: &str,
+        mut change: impl FnMut(&mut Branch),
+    ) -> anyhow::Result<()> {
+        self.update(|document| {
+            if let Some(branch) = document.branch_mut(branch_id) {
+                change(branch);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl StateStore for DocumentStore {
+    fn list_projects(&self) -> anyhow::Result<Vec<Project>> {
+        let mut projects = self.read()?.projects;
+        projects.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+        Ok(projects)
+    }
+
+    fn get_project_by_name(&self, name: &str) -> anyhow::Result<Option<Project>> {
+        Ok(self.read()?.projects.into_iter().find(|p| p.name == name))
+    }
+
+    fn create_project(&self, input: NewProject) -> anyhow::Result<Project> {
+        let project = Project {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: input.name,
+            owner: input.owner,
+            image: input.image,
+            storage_backend: input.storage_backend,
+            storage_config: input.storage_config,
+            created_at: now_epoch_millis(),
+        };
+        self.update(|document| document.insert_project(project.clone()))?;
+        Ok(project)
+    }
+
+    fn next_port(&self) -> anyhow::Result<u16> {
+        Ok(self
+            .read()?
+            .branches
+            .iter()
+            .map(|b| b.port)
+            .max()
+            .map(|port| port.saturating_add(1))
+            .unwrap_or(FIRST_BRANCH_PORT))
+    }
+
+    fn list_branches(&self, project_id: &str) -> anyhow::Result<Vec<Branch>> {
+        let mut branches = self.list_all_branches()?;
+        branches.retain(|b| b.project_id == project_id);
+        Ok(branches)
+    }
+
+    fn list_all_branches(&self) -> anyhow::Result<Vec<Branch>> {
+        let mut branches = self.read()?.branches;
+        branches.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+        Ok(branches)
+    }
+
+    fn list_all_snapshots(&self) -> anyhow::Result<Vec<Snapshot>> {
+        let mut snapshots = self.read()?.snapshots;
+        snapshots.sort_by_key(|s| s.created_at);
+        Ok(snapshots)
+    }
+
+    fn get_branch_by_name(
+        &self,
+        project_id: &str,
+        branch_name: &str,
+    ) -> anyhow::Result<Option<Branch>> {
+        Ok(self
+            .read()?
+            .branches
+            .into_iter()
+            .find(|b| b.project_id == project_id && b.name == branch_name))
+    }
+
+    fn create_branch(&self, input: NewBranch) -> anyhow::Result<Branch> {
+        let branch = Branch {
+            id: input.id,
+            project_id: input.project_id,
+            name: input.name,
+            parent_branch_id: input.parent_branch_id,
+            state: input.state,
+            data_dir: input.data_dir,
+            container_name: input.container_name,
+            port: input.port,
+            storage_metadata: input.storage_metadata,
+            created_at: now_epoch_millis(),
+            pg_user: Some(input.credentials.user),
+            pg_password: Some(input.credentials.password),
+            pg_db: Some(input.credentials.db),
+            last_used_at: None,
+            pg_settings: Default::default(),
+            health: None,
+            parent_snapshot_id: input.parent_snapshot_id,
+        };
+        self.update(|document| {
+            // `next_port` was read earlier; a teammate may have taken it since
+            if let Some(other) = document.branches.iter().find(|b| b.port == branch.port) {
+                anyhow::bail!(
+                    "Port {} was just given to branch '{}'; run the command again",
+                    branch.port,
+                    other.name
+                );
+            }
+            document.insert_branch(branch.clone())
+        })?;
+        Ok(branch)
+    }
+
+    fn update_branch_state(&self, branch_id: &str, state: BranchState) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| branch.state = state)
+    }
+
+    fn update_branch_health(&self, branch_id: &str, health: Option<&str>) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| {
+            branch.health = health.map(str::to_string)
+        })
+    }
+
+    fn record_drift(
+        &self,
+        branch: &Branch,
+        to_state: BranchState,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        let now = now_epoch_millis();
+        self.update(|document| {
+            document.drift.push(DriftRecord {
+                project_id: branch.project_id.clone(),
+                branch_id: branch.id.clone(),
+                branch_name: branch.name.clone(),
+                from_state: branch.state,
+                to_state,
+                reason: reason.to_string(),
+                detected_at: now,
+            });
+            document
+                .drift
+                .retain(|d| d.detected_at >= now - DRIFT_RETENTION_MS);
+            Ok(())
+        })
+    }
+
+    fn list_drift(&self, project_id: &str, since: i64) -> anyhow::Result<Vec<Drift>> {
+        let mut drift: Vec<Drift> = self
+            .read()?
+            .drift
+            .into_iter()
+            .filter(|d| d.project_id == project_id && d.detected_at >= since)
+            .map(|d| Drift {
+                branch_name: d.branch_name,
+                from_state: d.from_state,
+                to_state: d.to_state,
+                reason: d.reason,
+                detected_at: d.detected_at,
+            })
+            .collect();
+        drift.sort_by_key(|d| d.detected_at);
+        Ok(drift)
+    }
+
+    fn update_branch_storage_metadata(
+        &self,
+        branch_id: &str,
+        storage_metadata: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| {
+            branch.storage_metadata = storage_metadata.map(str::to_string)
+        })
+    }
+
+    fn update_branch_credentials(
+        &self,
+        branch_id: &str,
+        credentials: &Credentials,
+    ) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| {
+            branch.pg_user = Some(credentials.user.clone());
+            branch.pg_password = Some(credentials.password.clone());
+            branch.pg_db = Some(credentials.db.clone());
+        })
+    }
+
+    fn update_branch_pg_settings(
+        &self,
+        branch_id: &str,
+        settings: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| branch.pg_settings = settings.clone())
+    }
+
+    fn touch_branch(&self, branch_id: &str) -> anyhow::Result<()> {
+        let now = now_epoch_millis();
+        self.update_branch(branch_id, |branch| branch.last_used_at = Some(now))
+    }
+
+    fn update_branch_port(&self, branch_id: &str, port: u16) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| branch.port = port)
+    }
+
+    fn update_branch_container_name(
+        &self,
+        branch_id: &str,
+        container_name: &str,
+    ) -> anyhow::Result<()> {
+        self.update_branch(branch_id, |branch| {
+            branch.container_name = container_name.to_string()
+        })
+    }
+
+    fn delete_branch(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.update(|document| {
+            document.remove_branches(|b| b.id == branch_id);
+            Ok(())
+        })
+    }
+
+    fn create_snapshot(
+        &self,
+        id: &str,
+        branch_id: &str,
+        name: &str,
+        storage_metadata: Option<&str>,
+    ) -> anyhow::Result<Snapshot> {
+        let snapshot = Snapshot {
+            id: id.to_string(),
+            branch_id: branch_id.to_string(),
+            name: name.to_string(),
+            storage_metadata: storage_metadata.map(str::to_string),
+            created_at: now_epoch_millis(),
+        };
+        self.update(|document| document.insert_snapshot(snapshot.clone()))?;
+        Ok(snapshot)
+    }
+
+    fn list_snapshots(&self, branch_id: &str) -> anyhow::Result<Vec<Snapshot>> {
+        let mut snapshots = self.list_all_snapshots()?;
+        snapshots.retain(|s| s.branch_id == branch_id);
+        Ok(snapshots)
+    }
+
+    fn get_snapshot_by_name(
+        &self,
+        branch_id: &str,
+        name: &str,
+    ) -> anyhow::Result<Option<Snapshot>> {
+        Ok(self
+            .read()?
+            .snapshots
+            .into_iter()
+            .find(|s| s.branch_id == branch_id && s.name == name))
+    }
+
+    fn get_snapshot(&self, snapshot_id: &str) -> anyhow::Result<Option<Snapshot>> {
+        Ok(self
+            .read()?
+            .snapshots
+            .into_iter()
+            .find(|s| s.id == snapshot_id))
+    }
+
+    fn list_branches_pinned_to(&self, snapshot_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut names: Vec<String> = self
+            .read()?
+            .branches
+            .into_iter()
+            .filter(|b| b.parent_snapshot_id.as_deref() == Some(snapshot_id))
+            .map(|b| b.name)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn delete_snapshot(&self, snapshot_id: &str) -> anyhow::Result<()> {
+        self.update(|document| {
+            document.remove_snapshots(|s| s.id == snapshot_id);
+            Ok(())
+        })
+    }
+
+    fn record_usage(
+        &self,
+        project_name: &str,
+        operation: &str,
+        storage_backend: Option<&str>,
+        detail: Option<&str>,
+        duration_ms: i64,
+    ) -> anyhow::Result<()> {
+        self.update(|document| {
+            document.usage.push(UsageRecord {
+                project_name: project_name.to_string(),
+                operation: operation.to_string(),
+                storage_backend: storage_backend.map(str::to_string),
+                detail: detail.map(str::to_string),
+                duration_ms,
+                created_at: now_epoch_millis(),
+            });
+            Ok(())
+        })
+    }
+
+    fn usage_summary(&self, project_name: Option<&str>) -> anyhow::Result<Vec<UsageSummary>> {
+        type Key = (String, String, Option<String>, Option<String>);
+        let mut groups: BTreeMap<Key, Vec<UsageRecord>> = BTreeMap::new();
+        for record in self.read()?.usage {
+            if project_name.is_some_and(|name| name != record.project_name) {
+                continue;
+            }
+            let key = (
+                record.project_name.clone(),
+                record.operation.clone(),
+                record.storage_backend.clone(),
+                record.detail.clone(),
+            );
+            groups.entry(key).or_default().push(record);
+        }
+        Ok(groups
+            .into_iter()
+            .map(
+                |((project_name, operation, storage_backend, detail), records)| {
+                    let durations = || records.iter().map(|r| r.duration_ms);
+                    let total_ms: i64 = durations().sum();
+                    UsageSummary {
+                        project_name,
+                        operation,
+                        storage_backend,
+                        detail,
+                        count: records.len() as i64,
+                        avg_ms: total_ms / records.len() as i64,
+                        min_ms: durations().min().unwrap_or_default(),
+                        max_ms: durations().max().unwrap_or_default(),
+                        total_ms,
+                        first_at: records
+                            .iter()
+                            .map(|r| r.created_at)
+                            .min()
+                            .unwrap_or_default(),
+                    }
+                },
+            )
+            .collect())
+    }
+
+    fn restore_records(
+        &self,
+        project: Option<&Project>,
+        branches: &[Branch],
+        snapshots: &[Snapshot],
+    ) -> anyhow::Result<()> {
+        // Nothing is written back unless every record goes in
+        self.update(|document| {
+            if let Some(project) = project {
+                document.insert_project(project.clone())?;
+            }
+            for branch in branches {
+                document.insert_branch(Branch {
+                    parent_snapshot_id: None,
+                    ..branch.clone()
+                })?;
+            }
+            for snapshot in snapshots {
+                document.insert_snapshot(snapshot.clone())?;
+            }
+            for branch in branches {
+                if let Some(snapshot_id) = &branch.parent_snapshot_id {
+                    if let Some(restored) = document.branch_mut(&branch.id) {
+                        restored.parent_snapshot_id = Some(snapshot_id.clone());
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_project(&self, project_id: &str) -> anyhow::Result<()> {
+        self.update(|document| {
+            document.projects.retain(|p| p.id != project_id);
+            document.remove_branches(|b| b.project_id == project_id);
+            Ok(())
+        })
+    }
+}
+
+/// A document in memory; clones share it, like teammates sharing an object.
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct MemoryDocument(std::sync::Arc<std::sync::Mutex<Option<Stored>>>);
+
+#[cfg(test)]
+impl DocumentLocation for MemoryDocument {
+    fn load(&self) -> anyhow::Result<Option<Stored>> {
+        Ok(self.0.lock().unwrap().as_ref().map(|stored| Stored {
+            bytes: stored.bytes.clone(),
+            version: stored.version.clone(),
+        }))
+    }
+
+    fn save(&self, bytes: &[u8], version: Option<&str>) -> anyhow::Result<bool> {
+        let mut stored = self.0.lock().unwrap();
+        if stored.as_ref().map(|s| s.version.as_str()) != version {
+            return Ok(false);
+        }
+        let next = version.map_or(Ok(0), str::parse::<u64>)? + 1;
+        *stored = Some(Stored {
+            bytes: bytes.to_vec(),
+            version: next.to_string(),
+        });
+        Ok(true)
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+}
+
+#[cfg(feature = "seed-s3")]
+fn s3_location(bucket: &str, key: &str) -> anyhow::Result<Box<dyn DocumentLocation>> {
+    Ok(Box::new(S3Document {
+        bucket: super::seed::s3_bucket(bucket)?,
+        bucket_name: bucket.to_string(),
+        key: key.to_string(),
+    }))
+}
+
+#[cfg(not(feature = "seed-s3"))]
+fn s3_location(_bucket: &str, _key: &str) -> anyhow::Result<Box<dyn DocumentLocation>> {
+    anyhow::bail!("S3 support not compiled. Rebuild with --features seed-s3")
+}
+
+/// An object in S3, with credentials and region from the environment as
+/// for seeding from S3.
+#[cfg(feature = "seed-s3")]
+struct S3Document {
+    bucket: Box<s3::Bucket>,
+    bucket_name: String,
+    key: String,
+}
+
+#[cfg(feature = "seed-s3")]
+impl S3Document {
+    /// Run an S3 request to completion from synchronous code, which may
+    /// itself be running on the async runtime.
+    fn block_on<F>(&self, request: F) -> anyhow::Result<F::Output>
+    where
+        F: std::future::Future + Send,
+        F::Output: Send,
+    {
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()?;
+                    Ok(runtime.block_on(request))
+                })
+                .join()
+                .map_err(|_| anyhow!("S3 request for {} panicked", self.describe()))?
+        })
+    }
+}
+
+#[cfg(feature = "seed-s3")]
+impl DocumentLocation for S3Document {
+    fn load(&self) -> anyhow::Result<Option<Stored>> {
+        let response = self
+            .block_on(self.bucket.get_object(&self.key))?
+            .with_context(|| format!("Failed to download {}", self.describe()))?;
+        match response.status_code() {
+            200 => {
+                let version = response
+                    .headers()
+                    .get("etag")
+                    .cloned()
+                    .ok_or_else(|| anyhow!("{} came without an ETag", self.describe()))?;
+                Ok(Some(Stored {
+                    bytes: response.bytes().to_vec(),
+                    version,
+                }))
+            }
+            404 => Ok(None),
+            status => anyhow::bail!("Reading {} failed with status {}", self.describe(), status),
+        }
+    }
+
+    fn save(&self, bytes: &[u8], version: Option<&str>) -> anyhow::Result<bool> {
+        let mut headers = http::HeaderMap::new();
+        match version {
+            Some(etag) => headers.insert(http::header::IF_MATCH, etag.parse()?),
+            None => headers.insert(http::header::IF_NONE_MATCH, "*".parse()?),
+        };
+        let response = self.block_on(self.bucket.put_object_with_headers(
+            &self.key,
+            bytes,
+            Some(headers),
+        ))??;
+        match response.status_code() {
+            200 => Ok(true),
+            // 409: another conditional write to the key is in flight
+            412 | 409 => Ok(false),
+            status => anyhow::bail!("S3 upload failed with status {}", status),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("s3://{}/{}", self.bucket_name, self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::local::model::StorageBackend;
+    use crate::backends::local::state::Store;
+
+    fn project(store: &dyn StateStore, name: &str) -> Project {
+        store
+            .create_project(NewProject {
+                name: name.to_string(),
+                owner: None,
+                image: "postgres:17".to_string(),
+                storage_backend: StorageBackend::Copy,
+                storage_config: None,
+            })
+            .unwrap()
+    }
+
+    fn branch(
+        store: &dyn StateStore,
+        project: &Project,
+        name: &str,
+        parent: Option<&Branch>,
+        port: u16,
+    ) -> Branch {
+        store
+            .create_branch(NewBranch {
+                id: uuid::Uuid::new_v4().to_string(),
+                project_id: project.id.clone(),
+                name: name.to_string(),
+                parent_branch_id: parent.map(|p| p.id.clone()),
+                state: BranchState::Stopped,
+                data_dir: format!("/data/{name}"),
+                container_name: format!("pgbranch-{name}"),
+                port,
+                storage_metadata: None,
+                credentials: Credentials {
+                    user: "postgres".to_string(),
+                    password: "postgres".to_string(),
+                    db: "postgres".to_string(),
+                },
+                parent_snapshot_id: None,
+            })
+            .unwrap()
+    }
+
+    /// Deleting a branch takes its snapshots with it and unlinks what was
+    /// cloned from it, in any store.
+    fn check_delete_branch_cascades(store: &dyn StateStore) {
+        let app = project(store, "app");
+        let main = branch(store, &app, "main", None, 55432);
+        let snapshot = store
+            .create_snapshot("snap-1", &main.id, "before-migration", None)
+            .unwrap();
+        let feature = branch(store, &app, "feature", Some(&main), 55433);
+        store
+            .restore_records(
+                None,
+                &[Branch {
+                    id: "pinned".to_string(),
+                    name: "pinned".to_string(),
+                    parent_snapshot_id: Some(snapshot.id.clone()),
+                    ..feature.clone()
+                }],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            store.list_branches_pinned_to(&snapshot.id).unwrap(),
+            vec!["pinned"]
+        );
+
+        store.delete_branch(&main.id).unwrap();
+
+        assert!(store.list_snapshots(&main.id).unwrap().is_empty());
+        assert!(store.get_snapshot(&snapshot.id).unwrap().is_none());
+        let feature = store
+            .get_branch_by_name(&app.id, "feature")
+            .unwrap()
+            .unwrap();
+        assert_eq!(feature.parent_branch_id, None);
+        let pinned = store
+            .get_branch_by_name(&app.id, "pinned")
+            .unwrap()
+            .unwrap();
+        assert_eq!(pinned.parent_snapshot_id, None);
+    }
+
+    #[test]
+    fn projects_are_found_by_name_and_unique() {
+        let store = DocumentStore::in_memory();
+        let app = project(&store, "app");
+
+        let found = store.get_project_by_name("app").unwrap().unwrap();
+        assert_eq!(found.id, app.id);
+        assert!(store.get_project_by_name("other").unwrap().is_none());
+        assert!(store
+            .create_project(NewProject {
+                name: "app".to_string(),
+                owner: None,
+                image: "postgres:16".to_string(),
+                storage_backend: StorageBackend::Copy,
+                storage_config: None,
+            })
+            .is_err());
+        assert_eq!(store.list_projects().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn branches_get_the_next_free_port_and_keep_updates() {
+        let store = DocumentStore::in_memory();
+        assert_eq!(store.next_port().unwrap(), FIRST_BRANCH_PORT);
+        let app = project(&store, "app");
+        let main = branch(&store, &app, "main", None, store.next_port().unwrap());
+        assert_eq!(store.next_port().unwrap(), FIRST_BRANCH_PORT + 1);
+
+        store
+            .update_branch_state(&main.id, BranchState::Running)
+            .unwrap();
+        store.touch_branch(&main.id).unwrap();
+        let settings = BTreeMap::from([("work_mem".to_string(), "64MB".to_string())]);
+        store
+            .update_branch_pg_settings(&main.id, &settings)
+            .unwrap();
+
+        let main = store.get_branch_by_name(&app.id, "main").unwrap().unwrap();
+        assert_eq!(main.state, BranchState::Running);
+        assert!(main.last_used_at.is_some());
+        assert_eq!(main.pg_settings, settings);
+        assert_eq!(main.credentials().unwrap().user, "postgres");
+    }
+
+    #[test]
+    fn branch_names_are_unique_per_project() {
+        let store = DocumentStore::in_memory();
+        let app = project(&store, "app");
+        let other = project(&store, "other");
+        branch(&store, &app, "main", None, 55432);
+        branch(&store, &other, "main", None, 55433);
+
+        let duplicate = store.create_branch(NewBranch {
+            id: "duplicate".to_string(),
+            project_id: app.id.clone(),
+            name: "main".to_string(),
+            parent_branch_id: None,
+            state: BranchState::Stopped,
+            data_dir: "/data/duplicate".to_string(),
+            container_name: "pgbranch-duplicate".to_string(),
+            port: 55434,
+            storage_metadata: None,
+            credentials: Credentials {
+                user: "postgres".to_string(),
+                password: "postgres".to_string(),
+                db: "postgres".to_string(),
+            },
+            parent_snapshot_id: None,
+        });
+        assert!(duplicate.is_err());
+        assert_eq!(store.list_branches(&app.id).unwrap().len(), 1);
+        assert_eq!(store.list_all_branches().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn delete_branch_cascades_in_memory() {
+        check_delete_branch_cascades(&DocumentStore::in_memory());
+    }
+
+    #[test]
+    fn delete_branch_cascades_in_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        check_delete_branch_cascades(&Store::open(&dir.path().join("state.db")).unwrap());
+    }
+
+    #[test]
+    fn delete_project_removes_its_branches() {
+        let store = DocumentStore::in_memory();
+        let app = project(&store, "app");
+        let other = project(&store, "other");
+        let main = branch(&store, &app, "main", None, 55432);
+        branch(&store, &other, "main", None, 55433);
+        store
+            .record_drift(&main, BranchState::Failed, "container exited")
+            .unwrap();
+
+        store.delete_project(&app.id).unwrap();
+
+        assert!(store.get_project_by_name("app").unwrap().is_none());
+        assert!(store.list_branches(&app.id).unwrap().is_empty());
+        assert!(store.list_drift(&app.id, 0).unwrap().is_empty());
+        assert_eq!(store.list_branches(&other.id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn restore_records_is_all_or_nothing() {
+        let store = DocumentStore::in_memory();
+        let app = project(&store, "app");
+        let main = branch(&store, &app, "main", None, 55432);
+        let feature = Branch {
+            id: "feature".to_string(),
+            name: "feature".to_string(),
+            ..main.clone()
+        };
+
+        // `main` is still there, so putting it back fails and `feature` stays out too
+        assert!(store
+            .restore_records(None, &[feature.clone(), main.clone()], &[])
+            .is_err());
+        assert!(store
+            .get_branch_by_name(&app.id, "feature")
+            .unwrap()
+            .is_none());
+
+        store.restore_records(None, &[feature], &[]).unwrap();
+        assert!(store
+            .get_branch_by_name(&app.id, "feature")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn usage_summary_groups_by_operation() {
+        let store = DocumentStore::in_memory();
+        store
+            .record_usage("app", "create", Some("copy"), None, 100)
+            .unwrap();
+        store
+            .record_usage("app", "create", Some("copy"), None, 300)
+            .unwrap();
+        store.record_usage("app", "reset", None, None, 50).unwrap();
+        store
+            .record_usage("other", "create", None, None, 10)
+            .unwrap();
+
+        let summary = store.usage_summary(Some("app")).unwrap();
+        assert_eq!(summary.len(), 2);
+        let create = &summary[0];
+        assert_eq!(create.operation, "create");
+        assert_eq!(create.count, 2);
+        assert_eq!(
+            (create.min_ms, create.avg_ms, create.max_ms),
+            (100, 200, 300)
+        );
+        assert_eq!(create.total_ms, 400);
+        assert_eq!(summary[1].operation, "reset");
+        assert_eq!(store.usage_summary(None).unwrap().len(), 3);
+    }
+
+    /// Lets a teammate's write land between this store's read and its write.
+    struct Interleaved {
+        shared: MemoryDocument,
+        teammate: std::sync::Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    }
+
+    impl DocumentLocation for Interleaved {
+        fn load(&self) -> anyhow::Result<Option<Stored>> {
+            self.shared.load()
+        }
+
+        fn save(&self, bytes: &[u8], version: Option<&str>) -> anyhow::Result<bool> {
+            if let Some(teammate) = self.teammate.lock().unwrap().take() {
+                teammate();
+            }
+            self.shared.save(bytes, version)
+        }
+
+        fn describe(&self) -> String {
+            "interleaved document".to_string()
+        }
+    }
+
+    /// A store whose first write is preceded by `teammate` writing through
+    /// `other`, a second store on the same document.
+    fn interleaved(
+        other: DocumentStore,
+        shared: MemoryDocument,
+        teammate: impl FnOnce(&DocumentStore) + Send + 'static,
+    ) -> DocumentStore {
+        DocumentStore::new(Box::new(Interleaved {
+            shared,
+            teammate: std::sync::Mutex::new(Some(Box::new(move || teammate(&other)))),
+        }))
+    }
+
+    #[test]
+    fn interleaved_updates_keep_both_changes() {
+        let shared = MemoryDocument::default();
+        let other = DocumentStore::new(Box::new(shared.clone()));
+        let store = interleaved(other, shared, |other| {
+            project(other, "theirs");
+        });
+        project(&store, "mine");
+
+        let names: Vec<_> = store
+            .list_projects()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names.len(), 2, "{names:?}");
+        assert!(names.contains(&"mine".to_string()));
+        assert!(names.contains(&"theirs".to_string()));
+    }
+
+    #[test]
+    fn interleaved_creates_do_not_share_a_port() {
+        let shared = MemoryDocument::default();
+        let other = DocumentStore::new(Box::new(shared.clone()));
+        let app = project(&other, "app");
+        let theirs = app.clone();
+        let store = interleaved(other, shared, move |other| {
+            branch(other, &theirs, "theirs", None, 55432);
+        });
+
+        let mine = |port| NewBranch {
+            id: uuid::Uuid::new_v4().to_string(),
+            project_id: app.id.clone(),
+            name: "mine".to_string(),
+            parent_branch_id: None,
+            state: BranchState::Stopped,
+            data_dir: "/data/mine".to_string(),
+            container_name: "pgbranch-mine".to_string(),
+            port,
+            storage_metadata: None,
+            credentials: Credentials {
+                user: "postgres".to_string(),
+                password: "postgres".to_string(),
+                db: "postgres".to_string(),
+            },
+            parent_snapshot_id: None,
+        };
+        let port = store.next_port().unwrap();
+        let err = store.create_branch(mine(port)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Port 55432 was just given to branch 'theirs'"),
+            "{err}"
+        );
+
+        let port = store.next_port().unwrap();
+        assert_eq!(store.create_branch(mine(port)).unwrap().port, 55433);
+        assert_eq!(store.list_branches(&app.id).unwrap().len(), 2);
+    }
+}
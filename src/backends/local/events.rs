@@ -0,0 +1,181 @@
+//! `pgbranch events`: a live feed of what happens to a project's branches.
+//! Docker's container events say what the engine did; comparing the state
+//! records before and after says what pgbranch made of it, including changes
+//! made by other pgbranch processes.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bollard::models::EventMessage;
+use chrono::{TimeZone, Utc};
+use futures_util::StreamExt;
+
+use super::model::BranchState;
+use super::LocalBackend;
+use crate::backends::BranchEvent;
+
+/// How often the state records are checked for changes made elsewhere.
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Docker events after which the recorded state may be out of date.
+const STATE_CHANGING: [&str; 8] = [
+    "start", "restart", "die", "oom", "stop", "pause", "unpause", "destroy",
+];
+
+/// What the feed remembers of a branch's record, by branch ID.
+#[derive(Debug, Clone, PartialEq)]
+struct Recorded {
+    name: String,
+    container_name: String,
+    state: BranchState,
+    health: Option<String>,
+}
+
+impl LocalBackend {
+    pub(super) async fn watch_project_events(
+        &self,
+        on_event: &mut (dyn FnMut(BranchEvent) + Send),
+    ) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let mut recorded = self.recorded_branches(&project.id)?;
+        let mut events = Box::pin(self.runtime.events(&project.id));
+        let mut poll = tokio::time::interval(STATE_POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = events.next() => {
+                    let Some(event) = event else {
+                        return Ok(());
+                    };
+                    let event = event.context("Docker event stream failed")?;
+                    on_event(docker_event(&event, &recorded));
+                    if event
+                        .action
+                        .as_deref()
+                        .is_some_and(|action| STATE_CHANGING.contains(&action))
+                    {
+                        self.reconcile_project(&project).await?;
+                    }
+                }
+                _ = poll.tick() => {}
+            }
+
+            let current = self.recorded_branches(&project.id)?;
+            for event in state_changes(&recorded, &current) {
+                on_event(event);
+            }
+            recorded = current;
+        }
+    }
+
+    fn recorded_branches(&self, project_id: &str) -> Result<HashMap<String, Recorded>> {
+        Ok(self
+            .store()
+            .list_branches(project_id)?
+            .into_iter()
+            .map(|branch| {
+                let recorded = Recorded {
+                    name: branch.name,
+                    container_name: branch.container_name,
+                    state: branch.state,
+                    health: branch.health,
+                };
+                (branch.id, recorded)
+            })
+            .collect())
+    }
+}
+
+/// A Docker container event, described for people and matched to its branch.
+fn docker_event(event: &EventMessage, recorded: &HashMap<String, Recorded>) -> BranchEvent {
+    let attributes = event
+        .actor
+        .as_ref()
+        .and_then(|actor| actor.attributes.clone())
+        .unwrap_or_default();
+    let container = attributes.get("name").cloned().unwrap_or_default();
+    let with = |key: &str, label: &str| {
+        attributes
+            .get(key)
+            .map(|value| format!(" ({label} {value})"))
+            .unwrap_or_default()
+    };
+    let message = match event.action.as_deref().unwrap_or("unknown") {
+        "create" => "container created".to_string(),
+        "start" => "container started".to_string(),
+        "restart" => "container restarted".to_string(),
+        "die" => format!("container died{}", with("exitCode", "exit code")),
+        "oom" => "container ran out of memory".to_string(),
+        "kill" => format!("container killed{}", with("signal", "signal")),
+        "stop" => "container stopped".to_string(),
+        "pause" => "container paused".to_string(),
+        "unpause" => "container resumed".to_string(),
+        "rename" => format!("container renamed{}", with("oldName", "was")),
+        "destroy" => "container removed".to_string(),
+        other => format!("container {other}"),
+    };
+
+    // Renamed containers report their new name, which the records catch up with
+    let branch = recorded
+        .values()
+        .find(|branch| branch.container_name == container)
+        .map(|branch| branch.name.clone());
+    let message = match branch {
+        Some(_) => message,
+        None => format!("{message}: {container}"),
+    };
+    BranchEvent {
+        time: event
+            .time_nano
+            .map(|nanos| Utc.timestamp_nanos(nanos))
+            .unwrap_or_else(Utc::now),
+        branch,
+        source: "docker".to_string(),
+        message,
+    }
+}
+
+/// Differences between two readings of the state records.
+fn state_changes(
+    before: &HashMap<String, Recorded>,
+    after: &HashMap<String, Recorded>,
+) -> Vec<BranchEvent> {
+    let event = |branch: &Recorded, message: String| BranchEvent {
+        time: Utc::now(),
+        branch: Some(branch.name.clone()),
+        source: "state".to_string(),
+        message,
+    };
+    let mut events = Vec::new();
+    for (id, now) in after {
+        let Some(was) = before.get(id) else {
+            events.push(event(now, format!("recorded as {}", now.state.as_str())));
+            continue;
+        };
+        if was.name != now.name {
+            events.push(event(now, format!("renamed from '{}'", was.name)));
+        }
+        if was.state != now.state {
+            events.push(event(
+                now,
+                format!("state {} → {}", was.state.as_str(), now.state.as_str()),
+            ));
+        }
+        if was.health != now.health {
+            events.push(event(
+                now,
+                match &now.health {
+                    Some(reason) => format!("unhealthy: {reason}"),
+                    None => "healthy again".to_string(),
+                },
+            ));
+        }
+    }
+    for (id, was) in before {
+        if !after.contains_key(id) {
+            events.push(event(was, "record deleted".to_string()));
+        }
+    }
+    events
+}
@@ -0,0 +1,260 @@
+//! `pgbranch fixture export`: the rows of a branch as SQL that loads them
+//! again, e.g. into a fresh clone of its parent in a test setup. With
+//! `since_parent`, only rows the parent doesn't have as they are now, i.e.
+//! the ones the branch added or changed. Rows it deleted aren't represented.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+
+use super::{model, LocalBackend};
+use crate::backends::{FixtureExport, FixtureTable};
+
+/// Separates the columns of a result row; names, md5s and JSON never contain it.
+const FIELD_SEPARATOR: char = '\u{1f}';
+/// Oid, display name, quoted name, column list, primary key and columns
+/// filled from a sequence of each table. Generated columns are left out of
+/// the column list, since they can't be inserted.
+const LIST_TABLES: &str = "SELECT concat_ws(chr(31), c.oid, n.nspname || '.' || c.relname, \
+     quote_ident(n.nspname) || '.' || quote_ident(c.relname), \
+     coalesce((SELECT string_agg(quote_ident(a.attname), ', ' ORDER BY a.attnum) \
+      FROM pg_attribute a WHERE a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped \
+      AND a.attgenerated = ''), ''), \
+     coalesce((SELECT string_agg(quote_ident(a.attname), ', ' \
+      ORDER BY array_position(i.indkey, a.attnum)) \
+      FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) \
+      WHERE i.indrelid = c.oid AND i.indisprimary), ''), \
+     coalesce((SELECT string_agg(a.attname, ',') FROM pg_attribute a \
+      WHERE a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped \
+      AND pg_get_serial_sequence(c.oid::regclass::text, a.attname) IS NOT NULL), '')) \
+     FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+     WHERE c.relkind IN ('r', 'p') AND NOT c.relispartition \
+     AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+     AND n.nspname NOT LIKE 'pg_toast%' \
+     ORDER BY n.nspname, c.relname";
+/// Which table references which, to load referenced rows first.
+const LIST_FOREIGN_KEYS: &str = "SELECT concat_ws(chr(31), conrelid, confrelid) \
+     FROM pg_constraint WHERE contype = 'f' AND conrelid <> confrelid";
+
+struct Table {
+    oid: String,
+    name: String,
+    quoted: String,
+    columns: String,
+    primary_key: Option<String>,
+    /// Unquoted, as `pg_get_serial_sequence` takes them
+    serial_columns: Vec<String>,
+}
+
+impl LocalBackend {
+    pub(super) async fn fixture_export(
+        &self,
+        branch_name: &str,
+        since_parent: bool,
+    ) -> Result<FixtureExport> {
+        let branch = self.running_branch(branch_name).await?;
+        let parent = if since_parent {
+            let project = self.ensure_project().await?;
+            let parent = branch
+                .parent_branch_id
+                .as_ref()
+                .and_then(|id| {
+                    self.store()
+                        .list_branches(&project.id)
+                        .ok()?
+                        .into_iter()
+                        .find(|b| &b.id == id)
+                })
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Branch '{}' has no parent to compare with; export all its rows without --since-parent",
+                        branch_name
+                    )
+                })?;
+            Some(self.running_branch(&parent.name).await?)
+        } else {
+            None
+        };
+
+        let listed = self.fixture_query(&branch, LIST_TABLES).await?;
+        let tables: Vec<Table> = listed
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(FIELD_SEPARATOR).collect();
+                let [oid, name, quoted, columns, primary_key, serial_columns] = fields[..] else {
+                    return None;
+                };
+                Some(Table {
+                    oid: oid.to_string(),
+                    name: name.to_string(),
+                    quoted: quoted.to_string(),
+                    columns: columns.to_string(),
+                    primary_key: (!primary_key.is_empty()).then(|| primary_key.to_string()),
+                    serial_columns: serial_columns
+                        .split(',')
+                        .filter(|c| !c.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                })
+            })
+            .collect();
+        let foreign_keys: Vec<(String, String)> = self
+            .fixture_query(&branch, LIST_FOREIGN_KEYS)
+            .await?
+            .lines()
+            .filter_map(|line| line.split_once(FIELD_SEPARATOR))
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+
+        let mut sql = format!(
+            "-- Fixture of branch '{}'{}\n\nBEGIN;\n",
+            branch_name,
+            match &parent {
+                Some(parent) => format!(", rows added or changed since '{}'", parent.name),
+                None => String::new(),
+            }
+        );
+        let mut exported = Vec::new();
+        for table in load_order(&tables, &foreign_keys) {
+            let known: HashSet<String> = match &parent {
+                Some(parent) => self
+                    .fixture_query(
+                        parent,
+                        &format!("SELECT md5(t::text) FROM {} t", table.quoted),
+                    )
+                    .await
+                    // A table the parent doesn't have yet is all new
+                    .unwrap_or_default()
+                    .lines()
+                    .map(str::to_string)
+                    .collect(),
+                None => HashSet::new(),
+            };
+            let rows = self
+                .fixture_query(
+                    &branch,
+                    &format!(
+                        "SELECT md5(t::text) || chr(31) || row_to_json(t)::text FROM {} t",
+                        table.quoted
+                    ),
+                )
+                .await
+                .with_context(|| format!("failed to read {}", table.name))?;
+            let statements: Vec<String> = rows
+                .lines()
+                .filter_map(|line| line.split_once(FIELD_SEPARATOR))
+                .filter(|(hash, _)| !known.contains(*hash))
+                .map(|(_, json)| insert_statement(table, json))
+                .collect();
+            if statements.is_empty() {
+                continue;
+            }
+            sql.push_str(&format!(
+                "\n-- {} ({} rows)\n",
+                table.name,
+                statements.len()
+            ));
+            for statement in &statements {
+                sql.push_str(statement);
+                sql.push('\n');
+            }
+            // Rows inserted next won't collide with the loaded ids
+            for column in &table.serial_columns {
+                sql.push_str(&format!(
+                    "SELECT setval(pg_get_serial_sequence('{table}', '{column}'), max({quoted_column})) FROM {table} HAVING max({quoted_column}) IS NOT NULL;\n",
+                    table = table.quoted.replace('\'', "''"),
+                    column = column.replace('\'', "''"),
+                    quoted_column = format!("\"{}\"", column.replace('"', "\"\"")),
+                ));
+            }
+            exported.push(FixtureTable {
+                table: table.name.clone(),
+                rows: statements.len(),
+            });
+        }
+        sql.push_str("\nCOMMIT;\n");
+
+        Ok(FixtureExport {
+            sql,
+            tables: exported,
+        })
+    }
+
+    async fn fixture_query(&self, branch: &model::Branch, sql: &str) -> Result<String> {
+        let credentials = self.branch_credentials(branch);
+        self.runtime
+            .query(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                sql,
+            )
+            .await
+    }
+}
+
+/// An upsert when the table has a primary key, so a changed row replaces the
+/// parent's version of it.
+fn insert_statement(table: &Table, json: &str) -> String {
+    let mut statement = format!(
+        "INSERT INTO {table} ({columns}) SELECT {columns} FROM json_populate_record(NULL::{table}, '{json}')",
+        table = table.quoted,
+        columns = table.columns,
+        json = json.replace('\'', "''"),
+    );
+    if let Some(primary_key) = &table.primary_key {
+        let key: HashSet<&str> = primary_key.split(", ").collect();
+        let updates: Vec<String> = table
+            .columns
+            .split(", ")
+            .filter(|column| !key.contains(column))
+            .map(|column| format!("{column} = EXCLUDED.{column}"))
+            .collect();
+        if updates.is_empty() {
+            statement.push_str(&format!(" ON CONFLICT ({primary_key}) DO NOTHING"));
+        } else {
+            statement.push_str(&format!(
+                " ON CONFLICT ({primary_key}) DO UPDATE SET {}",
+                updates.join(", ")
+            ));
+        }
+    }
+    statement.push(';');
+    statement
+}
+
+/// Referenced tables before the ones referencing them; tables in a cycle
+/// keep their name order at the end.
+fn load_order<'a>(tables: &'a [Table], foreign_keys: &[(String, String)]) -> Vec<&'a Table> {
+    let mut depends_on: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for (from, to) in foreign_keys {
+        depends_on
+            .entry(from.as_str())
+            .or_default()
+            .insert(to.as_str());
+    }
+    let mut ordered: Vec<&Table> = Vec::with_capacity(tables.len());
+    let mut placed: HashSet<&str> = HashSet::new();
+    let known: HashSet<&str> = tables.iter().map(|t| t.oid.as_str()).collect();
+    loop {
+        let ready: Vec<&Table> = tables
+            .iter()
+            .filter(|t| !placed.contains(t.oid.as_str()))
+            .filter(|t| {
+                depends_on.get(t.oid.as_str()).is_none_or(|deps| {
+                    deps.iter()
+                        .all(|dep| placed.contains(dep) || !known.contains(dep))
+                })
+            })
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        for table in ready {
+            placed.insert(&table.oid);
+            ordered.push(table);
+        }
+    }
+    ordered.extend(tables.iter().filter(|t| !placed.contains(t.oid.as_str())));
+    ordered
+}
@@ -0,0 +1,71 @@
+//! Copies of branch container logs on the host, in `logs/postgres.log` next
+//! to the branch's data directory. `docker logs` loses everything once the
+//! container is recreated, so with `capture_logs` on, what PostgreSQL said
+//! is appended there whenever a branch stops, fails to start or is found
+//! dead, and before its container is replaced.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+
+use super::{model, LocalBackend};
+
+const LOG_FILE: &str = "postgres.log";
+/// Time of the last line copied, so the next capture picks up after it
+const MARKER_FILE: &str = ".captured-until";
+
+/// `logs` next to a branch's `pgdata`.
+pub fn log_dir(data_dir: &Path) -> PathBuf {
+    data_dir.parent().unwrap_or(data_dir).join("logs")
+}
+
+impl LocalBackend {
+    /// Append the lines the branch's container logged since the last capture,
+    /// if `capture_logs` is on. Only warns on failure, since it runs on the
+    /// way to something else.
+    pub(super) async fn capture_logs(&self, branch: &model::Branch) {
+        if !self.capture_logs {
+            return;
+        }
+        if let Err(e) = self.append_logs(branch).await {
+            log::warn!(
+                "Failed to capture logs of branch '{}': {:#}",
+                branch.name,
+                e
+            );
+        }
+    }
+
+    async fn append_logs(&self, branch: &model::Branch) -> Result<()> {
+        let dir = log_dir(Path::new(&branch.data_dir));
+        let marker = dir.join(MARKER_FILE);
+        let after = std::fs::read_to_string(&marker)
+            .ok()
+            .and_then(|text| DateTime::<FixedOffset>::parse_from_rfc3339(text.trim()).ok());
+        let lines = self
+            .runtime
+            .logs_after(&branch.container_name, after)
+            .await?;
+        let Some((last, _)) = lines.last() else {
+            return Ok(());
+        };
+
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(LOG_FILE);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        for (stamp, line) in &lines {
+            writeln!(file, "{} {}", stamp.to_rfc3339(), line)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        std::fs::write(&marker, last.to_rfc3339())
+            .with_context(|| format!("failed to write {}", marker.display()))?;
+        Ok(())
+    }
+}
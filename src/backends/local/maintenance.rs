@@ -0,0 +1,55 @@
+//! Maintenance after a clone or seed. A seeded branch has no planner
+//! statistics yet, and a clone carries over whatever its parent had, so
+//! queries can get poor plans until autovacuum catches up.
+
+use anyhow::Result;
+
+use super::LocalBackend;
+use crate::backends::DatabaseBranchingBackend;
+use crate::config::MaintenanceTask;
+
+impl LocalBackend {
+    /// Run the configured maintenance tasks in each of the branch's databases.
+    pub(super) async fn run_maintenance_tasks(&self, branch_name: &str) -> Result<Vec<String>> {
+        if self.maintenance.is_empty() {
+            return Ok(vec![]);
+        }
+        let started = std::time::Instant::now();
+        let (_, branch) = self.existing_branch(branch_name)?;
+        let credentials = self.branch_credentials(&branch);
+
+        for database in self.branch_databases(&credentials) {
+            for task in &self.maintenance {
+                self.runtime
+                    .query(
+                        &branch.container_name,
+                        &credentials.user,
+                        database,
+                        &task_sql(*task, database),
+                    )
+                    .await?;
+            }
+        }
+        self.record_phase("maintenance", started);
+        Ok(self.maintenance_tasks())
+    }
+}
+
+pub fn task_name(task: MaintenanceTask) -> &'static str {
+    match task {
+        MaintenanceTask::Analyze => "analyze",
+        MaintenanceTask::Vacuum => "vacuum",
+        MaintenanceTask::Reindex => "reindex",
+    }
+}
+
+fn task_sql(task: MaintenanceTask, database: &str) -> String {
+    match task {
+        MaintenanceTask::Analyze => "ANALYZE".to_string(),
+        MaintenanceTask::Vacuum => "VACUUM".to_string(),
+        // Before PostgreSQL 15 REINDEX DATABASE needs the name
+        MaintenanceTask::Reindex => {
+            format!("REINDEX DATABASE \"{}\"", database.replace('"', "\"\""))
+        }
+    }
+}
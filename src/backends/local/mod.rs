@@ -1,50 +1,141 @@
-pub mod docker;
-pub mod model;
-pub mod reconcile;
-pub mod seed;
-pub mod state;
-pub mod storage;
-
-use std::path::PathBuf;
+pub(crate) mod adopt;
+pub(crate) mod compact;
+pub(crate) mod data_diff;
+pub(crate) mod docker;
+pub(crate) mod docker_desktop;
+pub(crate) mod docker_endpoint;
+pub(crate) mod docker_retry;
+pub(crate) mod document_store;
+pub(crate) mod events;
+pub(crate) mod fixture;
+pub(crate) mod log_capture;
+pub(crate) mod maintenance;
+pub(crate) mod model;
+pub(crate) mod pgpass;
+pub(crate) mod plan;
+pub(crate) mod prune_guard;
+pub(crate) mod query_stats;
+pub(crate) mod reconcile;
+pub(crate) mod schema_only;
+pub(crate) mod seed;
+pub(crate) mod selftest;
+pub(crate) mod session;
+pub(crate) mod shared;
+pub(crate) mod state;
+pub(crate) mod storage;
+pub(crate) mod warmup;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 use super::{
-    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, ProjectInfo,
+    AdoptReport, BranchDataSource, BranchEvent, BranchInfo, CompactReport, ConnectionInfo,
+    ContainerLimits, DatabaseBranchingBackend, DestroyScope, DoctorCheck, DoctorReport, DriftEvent,
+    FixtureExport, HostCommand, PhaseTiming, PlannedAction, ProjectInfo, QueryStat, SnapshotInfo,
+    SpaceUsage, TableData,
 };
-use crate::config::{Config, LocalBackendConfig};
+use crate::config::{
+    host_port, Config, ContainerEngine, LocalBackendConfig, MaintenanceTask, SigningConfig,
+    WarmupConfig,
+};
+use crate::errors::coded;
+use crate::notifier::Notifier;
 use docker::{DockerRuntime, ReserveBranchSpec, StartBranchSpec};
-use model::BranchState;
-use state::{NewBranch, NewProject, Store};
+use document_store::DocumentStore;
+use model::{BranchState, Credentials};
+use state::{NewBranch, NewProject, StateStore, Store};
 use storage::StorageCoordinator;
 
 const DEFAULT_IMAGE: &str = "postgres:17";
 const DEFAULT_PORT_RANGE_START: u16 = 55432;
 const STARTUP_TIMEOUT: Duration = Duration::from_secs(120);
 
+/// Where a new branch's data comes from.
+enum BranchOrigin<'a> {
+    /// Clone the named branch (or `<branch>@<snapshot>`, one of its
+    /// snapshots), or the most recent one; empty if there is none
+    Parent(Option<&'a str>),
+    Empty,
+    /// Clone the data directory of a branch in another local project
+    DataDir(&'a BranchDataSource),
+}
+
+/// What `create_branch` settled on before changing anything.
+struct CreatePlan {
+    branch_id: String,
+    data_dir: PathBuf,
+    container_name: String,
+    port: u16,
+    parent: Option<model::Branch>,
+    /// Snapshot of `parent` to clone instead of its current data
+    snapshot: Option<model::Snapshot>,
+    credentials: Credentials,
+}
+
+/// A row of `pgbranch ps`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ContainerListing {
+    pub project: Option<String>,
+    pub branch: Option<String>,
+    #[serde(flatten)]
+    pub container: docker::ManagedContainer,
+}
+
 pub struct LocalBackend {
     project_name: String,
     image: String,
     port_range_start: u16,
     pg_user: String,
     pg_password: String,
+    pgpass: bool,
+    hide_password: bool,
+    capture_logs: bool,
     pg_db: String,
-    store: Mutex<Store>,
+    extra_databases: Vec<String>,
+    maintenance: Vec<MaintenanceTask>,
+    warmup: Option<WarmupConfig>,
+    store: Mutex<Box<dyn StateStore>>,
     runtime: DockerRuntime,
     storage: StorageCoordinator,
     data_root: PathBuf,
+    shared: bool,
+    notifier: Option<Notifier>,
+    /// Checks dump files before they're restored
+    signing: Option<SigningConfig>,
+    phase_timings: Mutex<Vec<PhaseTiming>>,
 }
 
 impl LocalBackend {
     pub async fn new(
         backend_name: &str,
-        _config: &Config,
+        config: &Config,
+        local_config: Option<&LocalBackendConfig>,
+    ) -> Result<Self> {
+        // The state database lives under the data root
+        Self::create_data_root(local_config).await?;
+        let store = Self::open_store(local_config)?;
+        if Self::is_shared(local_config)
+            && local_config.and_then(|c| c.state_store.as_ref()).is_none()
+        {
+            shared::share_file(&Self::state_db_path(local_config));
+        }
+        Self::with_store(backend_name, config, local_config, store).await
+    }
+
+    /// Like [`Self::new`], but recording state in `store` rather than the
+    /// registry the configuration names.
+    pub async fn with_store(
+        backend_name: &str,
+        config: &Config,
         local_config: Option<&LocalBackendConfig>,
+        store: Box<dyn StateStore>,
     ) -> Result<Self> {
         let image = local_config
             .and_then(|c| c.image.as_deref())
@@ -70,17 +161,118 @@ impl LocalBackend {
             .unwrap_or("postgres")
             .to_string();
 
-        let data_root = if let Some(root) = local_config.and_then(|c| c.data_root.as_deref()) {
-            let expanded = shellexpand(root);
-            PathBuf::from(expanded)
-        } else {
-            dirs::data_local_dir()
-                .unwrap_or_else(|| dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")))
-                .join("pgbranch")
-        };
+        let data_root = Self::data_root(local_config);
+        let shared = Self::is_shared(local_config);
+        let projects_root = Self::create_data_root(local_config).await?;
+
+        let runtime = DockerRuntime::new(
+            local_config.and_then(|c| c.runtime).unwrap_or_default(),
+            local_config.and_then(|c| c.docker_host.as_deref()),
+            local_config.and_then(|c| c.docker_context.as_deref()),
+        )
+        .context("failed to initialize Docker runtime")?
+        .with_retry(docker_retry::RetryPolicy::from_config(
+            local_config.and_then(|c| c.docker_retry.as_ref()),
+        ))
+        .with_pull_policy(local_config.and_then(|c| c.pull_policy).unwrap_or_default())
+        .with_bind_address(
+            local_config
+                .and_then(|c| c.bind_address)
+                .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        )
+        .with_prune_protection(
+            local_config
+                .and_then(|c| c.prune_protection)
+                .unwrap_or(false),
+        );
+        let storage = StorageCoordinator::new(projects_root.clone())
+            .with_zfs_limits(local_config.and_then(|c| c.zfs.clone()).unwrap_or_default());
+
+        let project_name = Self::project_name(backend_name, local_config);
+
+        Ok(Self {
+            project_name,
+            image,
+            port_range_start,
+            pg_user,
+            pg_password,
+            pgpass: local_config.and_then(|c| c.pgpass).unwrap_or(false),
+            hide_password: local_config.and_then(|c| c.hide_password).unwrap_or(false),
+            capture_logs: local_config.and_then(|c| c.capture_logs).unwrap_or(false),
+            pg_db,
+            extra_databases: local_config
+                .and_then(|c| c.extra_databases.clone())
+                .unwrap_or_default(),
+            maintenance: local_config
+                .and_then(|c| c.maintenance.clone())
+                .unwrap_or_else(|| vec![MaintenanceTask::Analyze]),
+            warmup: local_config
+                .and_then(|c| c.warmup.clone())
+                .filter(|w| !w.queries.is_empty()),
+            store: Mutex::new(store),
+            runtime,
+            storage,
+            data_root,
+            shared,
+            notifier: Notifier::from_config(config),
+            signing: config.signing.clone(),
+            phase_timings: Mutex::new(Vec::new()),
+        })
+    }
 
-        // Ensure directories exist
+    /// Create the data root and its projects directory if missing, and
+    /// return the latter.
+    async fn create_data_root(local_config: Option<&LocalBackendConfig>) -> Result<PathBuf> {
+        let data_root = Self::data_root(local_config);
         let projects_root = data_root.join("projects");
+        if Self::is_shared(local_config) {
+            shared::create_dir(&data_root)?;
+            shared::create_dir(&projects_root)?;
+        } else {
+            tokio::fs::create_dir_all(&projects_root)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to create projects root: {}",
+                        projects_root.display()
+                    )
+                })?;
+        }
+        Ok(projects_root)
+    }
+
+    fn data_root(local_config: Option<&LocalBackendConfig>) -> PathBuf {
+        // `--data-root` / PGBRANCH_DATA_ROOT win, also for databases whose
+        // settings come from local state rather than the merged config
+        if let Some(root) = std::env::var_os("PGBRANCH_DATA_ROOT").filter(|r| !r.is_empty()) {
+            PathBuf::from(shellexpand(&root.to_string_lossy()))
+        } else if let Some(root) = local_config.and_then(|c| c.data_root.as_deref()) {
+            PathBuf::from(shellexpand(root))
+        } else if Self::is_shared(local_config) {
+            PathBuf::from(shared::SHARED_DATA_ROOT)
+        } else {
+            docker_desktop::compatible_data_root(crate::paths::data_dir())
+        }
+    }
+
+    fn is_shared(local_config: Option<&LocalBackendConfig>) -> bool {
+        local_config.and_then(|c| c.shared).unwrap_or(false)
+    }
+
+    /// Project a database is kept under; in shared mode every user has their own.
+    pub fn project_name(backend_name: &str, local_config: Option<&LocalBackendConfig>) -> String {
+        if Self::is_shared(local_config) {
+            format!("{}/{}", shared::current_user(), backend_name)
+        } else {
+            backend_name.to_string()
+        }
+    }
+
+    /// Storage coordinator for a local backend configuration, without Docker or state.
+    pub async fn storage_for(
+        local_config: Option<&LocalBackendConfig>,
+    ) -> Result<StorageCoordinator> {
+        let projects_root = Self::data_root(local_config).join("projects");
         tokio::fs::create_dir_all(&projects_root)
             .await
             .with_context(|| {
@@ -89,36 +281,132 @@ impl LocalBackend {
                     projects_root.display()
                 )
             })?;
+        Ok(StorageCoordinator::new(projects_root))
+    }
 
-        let db_path = data_root.join("state.db");
+    /// The registry of a local backend configuration: its `state_store`, or
+    /// the state database under its data root, created if missing.
+    pub fn open_store(local_config: Option<&LocalBackendConfig>) -> Result<Box<dyn StateStore>> {
+        if let Some(url) = local_config.and_then(|c| c.state_store.as_deref()) {
+            return Ok(Box::new(DocumentStore::open(url)?));
+        }
+        let db_path = Self::state_db_path(local_config);
         let store = Store::open(&db_path)
             .with_context(|| format!("failed to open state database: {}", db_path.display()))?;
+        Ok(Box::new(store))
+    }
 
-        let runtime = DockerRuntime::new().context("failed to initialize Docker runtime")?;
-        let storage = StorageCoordinator::new(projects_root.clone());
+    /// Like [`Self::open_store`], but `None` instead of creating a state
+    /// database where there is none yet.
+    pub fn open_existing_store(
+        local_config: Option<&LocalBackendConfig>,
+    ) -> Result<Option<Box<dyn StateStore>>> {
+        if local_config.and_then(|c| c.state_store.as_ref()).is_none()
+            && !Self::state_db_path(local_config).exists()
+        {
+            return Ok(None);
+        }
+        Self::open_store(local_config).map(Some)
+    }
 
-        let project_name = backend_name.to_string();
+    /// Location of the state database for a local backend configuration.
+    pub fn state_db_path(local_config: Option<&LocalBackendConfig>) -> PathBuf {
+        Self::data_root(local_config).join("state.db")
+    }
 
-        Ok(Self {
-            project_name,
-            image,
-            port_range_start,
-            pg_user,
-            pg_password,
-            pg_db,
-            store: Mutex::new(store),
-            runtime,
-            storage,
-            data_root,
-        })
+    /// Recorded state of a branch, read from the state database only (no Docker calls).
+    /// `None` with a shared `state_store`: reading it would be a network round
+    /// trip on every shell prompt.
+    pub fn cached_branch_state(
+        backend_name: &str,
+        local_config: Option<&LocalBackendConfig>,
+        branch_name: &str,
+    ) -> Option<String> {
+        if local_config.is_some_and(|c| c.state_store.is_some()) {
+            return None;
+        }
+        let store = Self::open_existing_store(local_config).ok()??;
+        let project = store
+            .get_project_by_name(&Self::project_name(backend_name, local_config))
+            .ok()??;
+        let branch = store.get_branch_by_name(&project.id, branch_name).ok()??;
+        Some(branch.state.as_str().to_string())
+    }
+
+    /// Containers pgbranch manages across all projects, with the project and
+    /// branch each belongs to when the state database knows it. No project
+    /// needs to be set up.
+    pub async fn list_managed_containers(
+        local_config: Option<&LocalBackendConfig>,
+        all: bool,
+    ) -> Result<Vec<ContainerListing>> {
+        let runtime = DockerRuntime::new(
+            local_config.and_then(|c| c.runtime).unwrap_or_default(),
+            local_config.and_then(|c| c.docker_host.as_deref()),
+            local_config.and_then(|c| c.docker_context.as_deref()),
+        )
+        .context("failed to initialize Docker runtime")?;
+        let containers = runtime.managed_containers(all).await?;
+
+        let (projects, branches) = match Self::open_existing_store(local_config)? {
+            Some(store) => (store.list_projects()?, store.list_all_branches()?),
+            None => Default::default(),
+        };
+        Ok(containers
+            .into_iter()
+            .map(|container| ContainerListing {
+                project: projects
+                    .iter()
+                    .find(|p| p.id == container.project_id)
+                    .map(|p| p.name.clone()),
+                branch: branches
+                    .iter()
+                    .find(|b| b.container_name == container.name)
+                    .map(|b| b.name.clone()),
+                container,
+            })
+            .collect())
     }
 
-    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+    fn store(&self) -> std::sync::MutexGuard<'_, Box<dyn StateStore>> {
         self.store.lock().unwrap()
     }
 
+    /// Close the phase that began at `started` and return the start of the next one.
+    fn record_phase(&self, phase: &str, started: std::time::Instant) -> std::time::Instant {
+        let now = std::time::Instant::now();
+        self.phase_timings.lock().unwrap().push(PhaseTiming {
+            phase: phase.to_string(),
+            duration_ms: now.duration_since(started).as_millis() as u64,
+        });
+        now
+    }
+
+    /// Best effort: stats must never fail the operation they describe.
+    fn record_usage(
+        &self,
+        project: &model::Project,
+        operation: &str,
+        detail: Option<&str>,
+        started: std::time::Instant,
+    ) {
+        if let Err(e) = self.store().record_usage(
+            &project.name,
+            operation,
+            Some(project.storage_backend.as_str()),
+            detail,
+            started.elapsed().as_millis() as i64,
+        ) {
+            log::debug!("Failed to record {} usage: {}", operation, e);
+        }
+    }
+
     async fn ensure_project(&self) -> Result<model::Project> {
-        if let Some(project) = self.store().get_project_by_name(&self.project_name)? {
+        let existing = self.store().get_project_by_name(&self.project_name)?;
+        if let Some(project) = existing {
+            if let Err(e) = self.migrate_container_names(&project).await {
+                log::warn!("Failed to rename containers of '{}': {:#}", project.name, e);
+            }
             return Ok(project);
         }
 
@@ -127,6 +415,7 @@ impl LocalBackend {
 
         let project = self.store().create_project(NewProject {
             name: self.project_name.clone(),
+            owner: self.shared.then(shared::current_user),
             image: self.image.clone(),
             storage_backend: selection.backend,
             storage_config: selection.config,
@@ -140,55 +429,391 @@ impl LocalBackend {
         Ok(project)
     }
 
+    /// Give branches created before container names carried the project id
+    /// hash their current names. Their containers are recreated under the
+    /// new name, so they also get the port label that keeps a stopped
+    /// branch's port reserved. That would cut open connections, so running
+    /// containers keep their old name until they've been stopped.
+    async fn migrate_container_names(&self, project: &model::Project) -> Result<()> {
+        let outdated: Vec<model::Branch> = self
+            .store()
+            .list_branches(&project.id)?
+            .into_iter()
+            .filter(|b| {
+                !docker::has_container_prefix(&b.container_name, &project.name, &project.id)
+            })
+            .collect();
+
+        for branch in outdated {
+            let status = self
+                .runtime
+                .container_status(&branch.container_name)
+                .await?;
+            if matches!(
+                status,
+                docker::ContainerStatus::Running | docker::ContainerStatus::Paused
+            ) {
+                log::info!(
+                    "Leaving running container '{}' under its old name until it's stopped",
+                    branch.container_name
+                );
+                continue;
+            }
+            let taken_names = self
+                .store()
+                .list_all_branches()?
+                .into_iter()
+                .map(|b| b.container_name)
+                .collect();
+            let reserved = self
+                .runtime
+                .reserve_branch(&ReserveBranchSpec {
+                    project_name: project.name.clone(),
+                    project_id: project.id.clone(),
+                    branch_name: branch.name.clone(),
+                    taken_names,
+                })
+                .await?;
+            if status != docker::ContainerStatus::NotFound {
+                eprintln!(
+                    "Recreating stopped container '{}' as '{}'",
+                    branch.container_name, reserved.container_name
+                );
+                let renamed = model::Branch {
+                    container_name: reserved.container_name.clone(),
+                    ..branch.clone()
+                };
+                self.runtime
+                    .recreate_container(&branch.container_name, &self.start_spec(project, &renamed))
+                    .await?;
+            }
+            self.store()
+                .update_branch_container_name(&branch.id, &reserved.container_name)?;
+        }
+        Ok(())
+    }
+
     async fn reconcile_project(&self, project: &model::Project) -> Result<()> {
         // Read branches from store (sync, releases lock before await)
-        let branches = self.store().list_branches(&project.id)?;
+        let branches: Vec<_> = self
+            .store()
+            .list_branches(&project.id)?
+            .into_iter()
+            .map(|branch| {
+                let credentials = self.branch_credentials(&branch);
+                (branch, credentials)
+            })
+            .collect();
 
         // Compute state changes (async, no store reference held)
-        let changes = reconcile::compute_state_changes(&self.runtime, branches).await;
+        let changes = reconcile::compute_state_changes(&self.runtime, branches.clone()).await;
+
+        // A branch found stopped behind our back may have crashed
+        for change in &changes {
+            if change.state != BranchState::Stopped {
+                continue;
+            }
+            if let Some((branch, _)) = branches.iter().find(|(b, _)| b.id == change.branch_id) {
+                if branch.state == BranchState::Running {
+                    self.capture_logs(branch).await;
+                }
+            }
+        }
 
         // Apply changes (sync)
         if !changes.is_empty() {
             let store = self.store();
-            for (branch_id, new_state) in changes {
-                store.update_branch_state(&branch_id, new_state)?;
+            for change in changes {
+                if let (Some(reason), Some((branch, _))) = (
+                    &change.drift,
+                    branches.iter().find(|(b, _)| b.id == change.branch_id),
+                ) {
+                    log::info!(
+                        "Branch '{}' went from {} to {} outside pgbranch: {}",
+                        branch.name,
+                        branch.state.as_str(),
+                        change.state.as_str(),
+                        reason
+                    );
+                    store.record_drift(branch, change.state, reason)?;
+                }
+                store.update_branch_state(&change.branch_id, change.state)?;
+                store.update_branch_health(&change.branch_id, change.health.as_deref())?;
             }
         }
 
         Ok(())
     }
 
-    fn connection_uri(&self, port: u16) -> String {
+    fn configured_credentials(&self) -> Credentials {
+        Credentials {
+            user: self.pg_user.clone(),
+            password: self.pg_password.clone(),
+            db: self.pg_db.clone(),
+        }
+    }
+
+    /// Credentials stored when the branch was created, so later config changes
+    /// don't break existing branches. Older branches fall back to the config.
+    fn branch_credentials(&self, branch: &model::Branch) -> Credentials {
+        branch
+            .credentials()
+            .unwrap_or_else(|| self.configured_credentials())
+    }
+
+    /// The project and a branch of it that must exist, without reconciling.
+    fn existing_branch(&self, branch_name: &str) -> Result<(model::Project, model::Branch)> {
+        let not_found = || coded!(BranchNotFound, "Branch '{}' not found", branch_name);
+        let project = self
+            .store()
+            .get_project_by_name(&self.project_name)?
+            .ok_or_else(not_found)?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(not_found)?;
+        Ok((project, branch))
+    }
+
+    /// The databases of a branch: `postgres_db` and the `extra_databases`.
+    fn branch_databases<'a>(&'a self, credentials: &'a Credentials) -> Vec<&'a str> {
+        let mut databases = vec![credentials.db.as_str()];
+        databases.extend(
+            self.extra_databases
+                .iter()
+                .map(String::as_str)
+                .filter(|db| *db != credentials.db),
+        );
+        databases
+    }
+
+    fn start_spec(&self, project: &model::Project, branch: &model::Branch) -> StartBranchSpec {
+        let credentials = self.branch_credentials(branch);
+        StartBranchSpec {
+            project_id: project.id.clone(),
+            image: project.image.clone(),
+            container_name: branch.container_name.clone(),
+            data_dir: PathBuf::from(&branch.data_dir),
+            port: branch.port,
+            pg_user: credentials.user,
+            pg_password: credentials.password,
+            pg_db: credentials.db,
+            pg_settings: branch
+                .pg_settings
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    fn connection_uri(&self, port: u16, credentials: &Credentials) -> String {
+        let address = host_port(&self.runtime.client_host().to_string(), port);
+        if self.hide_password {
+            return format!(
+                "postgresql://{}@{}/{}",
+                credentials.user, address, credentials.db
+            );
+        }
         format!(
-            "postgresql://{}:{}@127.0.0.1:{}/{}",
-            self.pg_user, self.pg_password, port, self.pg_db
+            "postgresql://{}:{}@{}/{}",
+            credentials.user, credentials.password, address, credentials.db
         )
     }
-}
 
-#[async_trait]
-impl DatabaseBranchingBackend for LocalBackend {
-    async fn create_branch(
-        &self,
-        branch_name: &str,
-        from_branch: Option<&str>,
-    ) -> Result<BranchInfo> {
+    /// Wait for PostgreSQL to accept connections, then check that the branch's
+    /// credentials match the ones its data directory was created with.
+    async fn wait_branch_ready(&self, branch: &model::Branch) -> Result<()> {
+        let branch_name = &branch.name;
+        let credentials = self.branch_credentials(branch);
+        if let Err(e) = self
+            .runtime
+            .wait_ready(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                STARTUP_TIMEOUT,
+            )
+            .await
+        {
+            self.capture_logs(branch).await;
+            return Err(e);
+        }
+
+        // The image only creates POSTGRES_DB, and only for an empty data directory
+        let databases = self.branch_databases(&credentials);
+        if let Err(e) = self
+            .runtime
+            .ensure_databases(&branch.container_name, &credentials.user, &databases)
+            .await
+        {
+            // A credential mismatch is reported more helpfully below
+            log::debug!("{}", e);
+        }
+
+        let Some(error) = self
+            .runtime
+            .check_login(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.password,
+                &credentials.db,
+            )
+            .await?
+        else {
+            if branch.credentials().is_none() {
+                self.store()
+                    .update_branch_credentials(&branch.id, &credentials)?;
+            }
+            return Ok(());
+        };
+        let fix = if error.contains("password authentication failed") {
+            format!(
+                "The branch's data was created with a different password than postgres_password. \
+                 Run 'pgbranch branch set-password {}' to apply the configured password, \
+                 or reset the branch.",
+                branch_name
+            )
+        } else {
+            "The branch's data was created with a different postgres_user or postgres_db. \
+             Set them back to the original values, or reset the branch."
+                .to_string()
+        };
+        anyhow::bail!(
+            "Branch '{}' is running, but logging in as '{}' to database '{}' failed: {}\n{}",
+            branch_name,
+            credentials.user,
+            credentials.db,
+            error,
+            fix
+        )
+    }
+
+    /// A branch whose container has to be running for what comes next.
+    async fn running_branch(&self, branch_name: &str) -> Result<model::Branch> {
         let project = self.ensure_project().await?;
-        self.reconcile_project(&project).await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
-        // Check if branch already exists
-        if let Some(existing) = self.store().get_branch_by_name(&project.id, branch_name)? {
-            if existing.state == BranchState::Running {
-                return Ok(BranchInfo {
-                    name: existing.name,
-                    created_at: None,
-                    parent_branch: None,
-                    database_name: self.pg_db.clone(),
-                    state: Some(existing.state.as_str().to_string()),
-                });
+        if self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            != docker::ContainerStatus::Running
+        {
+            anyhow::bail!(
+                "Branch '{}' is not running; start it with 'pgbranch start {}' first",
+                branch_name,
+                branch_name
+            );
+        }
+        Ok(branch)
+    }
+
+    /// Record a use of the branch. Losing the timestamp isn't worth failing
+    /// the operation over, so errors are only logged.
+    fn touch_branch(&self, branch: &model::Branch) {
+        if let Err(e) = self.store().touch_branch(&branch.id) {
+            log::warn!("Failed to record use of branch '{}': {:#}", branch.name, e);
+        }
+    }
+
+    /// Run SQL in a running branch's database as the branch's user.
+    pub async fn query_branch(&self, branch_name: &str, sql: &str) -> Result<String> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        let credentials = self.branch_credentials(&branch);
+        self.runtime
+            .query(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                sql,
+            )
+            .await
+    }
+
+    /// Start a branch's container, moving the branch to a free port when
+    /// its own was taken while it was stopped, e.g. by another app after a
+    /// reboot. The published port is fixed, so the container is recreated.
+    async fn start_container(
+        &self,
+        project: &model::Project,
+        branch: &mut model::Branch,
+    ) -> Result<()> {
+        let error = match self
+            .runtime
+            .start_branch(&self.start_spec(project, branch))
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) if docker::is_port_conflict(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        let start_port = self.store().next_port()?.max(self.port_range_start);
+        let port = docker::pick_available_port(
+            self.runtime.client(),
+            self.runtime.bind_address(),
+            start_port,
+        )
+        .await?;
+        log::warn!(
+            "Port {} of branch '{}' is taken ({:#}); moving it to port {}",
+            branch.port,
+            branch.name,
+            error,
+            port
+        );
+        self.runtime.remove_branch(&branch.container_name).await?;
+        self.store().update_branch_port(&branch.id, port)?;
+        branch.port = port;
+        if self.pgpass {
+            if let Err(e) = self.write_pgpass_entry(&branch.name) {
+                log::warn!("Failed to update .pgpass: {}", e);
             }
         }
+        self.runtime
+            .start_branch(&self.start_spec(project, branch))
+            .await
+    }
+
+    fn pgpass_key(&self, branch_name: &str) -> String {
+        format!("{}/{}", self.project_name, branch_name)
+    }
+
+    fn write_pgpass_entry(&self, branch_name: &str) -> Result<()> {
+        let project = self
+            .store()
+            .get_project_by_name(&self.project_name)?
+            .ok_or_else(|| anyhow::anyhow!("Project '{}' not found", self.project_name))?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        let credentials = self.branch_credentials(&branch);
+        pgpass::upsert_entry(
+            &self.pgpass_key(branch_name),
+            &self.runtime.client_host().to_string(),
+            branch.port,
+            &credentials.db,
+            &credentials.user,
+            &credentials.password,
+        )
+    }
 
+    /// Pick the id, data directory, container name, port and parent of a new
+    /// branch. Only reads state, so `--dry-run` can show it as well.
+    async fn plan_create(
+        &self,
+        project: &model::Project,
+        branch_name: &str,
+        origin: &BranchOrigin<'_>,
+    ) -> Result<CreatePlan> {
         let branch_id = Uuid::new_v4().to_string();
         let data_dir = self
             .data_root
@@ -199,107 +824,925 @@ impl DatabaseBranchingBackend for LocalBackend {
             .join("pgdata");
 
         // Reserve container name and find port
+        let taken_names = self
+            .store()
+            .list_all_branches()?
+            .into_iter()
+            .map(|b| b.container_name)
+            .collect();
         let reserved = self
             .runtime
             .reserve_branch(&ReserveBranchSpec {
                 project_name: self.project_name.clone(),
+                project_id: project.id.clone(),
                 branch_name: branch_name.to_string(),
+                taken_names,
             })
             .await?;
 
         let start_port = self.store().next_port()?.max(self.port_range_start);
-        let port = docker::pick_available_port(self.runtime.client(), start_port).await?;
+        let port = docker::pick_available_port(
+            self.runtime.client(),
+            self.runtime.bind_address(),
+            start_port,
+        )
+        .await?;
+
+        let (parent, snapshot) = match origin {
+            BranchOrigin::Parent(Some(from_name)) => self.resolve_parent(&project.id, from_name)?,
+            BranchOrigin::Parent(None) => {
+                // Try to clone from most recent branch
+                let branches = self.store().list_branches(&project.id)?;
+                let parent = branches
+                    .into_iter()
+                    .find(|b| b.state == BranchState::Running || b.state == BranchState::Stopped);
+                (parent, None)
+            }
+            BranchOrigin::Empty | BranchOrigin::DataDir(_) => (None, None),
+        };
 
-        // Clone or create empty
-        let parent = if let Some(from_name) = from_branch {
-            self.store().get_branch_by_name(&project.id, from_name)?
-        } else {
-            // Try to clone from most recent branch
-            let branches = self.store().list_branches(&project.id)?;
-            branches
-                .into_iter()
-                .find(|b| b.state == BranchState::Running || b.state == BranchState::Stopped)
+        // A clone keeps the roles and password of the data it was cloned from
+        let credentials = match (&parent, origin) {
+            (Some(parent_branch), _) => self.branch_credentials(parent_branch),
+            (None, BranchOrigin::DataDir(source)) => Credentials {
+                user: source.pg_user.clone(),
+                password: source.pg_password.clone(),
+                db: source.pg_db.clone(),
+            },
+            _ => self.configured_credentials(),
         };
 
-        let storage_metadata = if let Some(ref parent_branch) = parent {
-            // Pause parent if running
-            let parent_running = self
-                .runtime
-                .container_status(&parent_branch.container_name)
-                .await?
-                == docker::ContainerStatus::Running;
+        Ok(CreatePlan {
+            branch_id,
+            data_dir,
+            container_name: reserved.container_name,
+            port,
+            parent,
+            snapshot,
+            credentials,
+        })
+    }
 
-            if parent_running {
-                self.runtime
-                    .pause_branch(&parent_branch.container_name)
-                    .await?;
-            }
+    /// The branch `--from` names and, for `<branch>@<snapshot>`, the snapshot
+    /// of it to clone. A branch whose name contains `@` itself wins.
+    fn resolve_parent(
+        &self,
+        project_id: &str,
+        from_name: &str,
+    ) -> Result<(Option<model::Branch>, Option<model::Snapshot>)> {
+        if let Some(parent) = self.store().get_branch_by_name(project_id, from_name)? {
+            return Ok((Some(parent), None));
+        }
+        let Some((parent_name, snapshot_name)) = from_name.rsplit_once('@') else {
+            return Ok((None, None));
+        };
+        let Some(parent) = self.store().get_branch_by_name(project_id, parent_name)? else {
+            return Ok((None, None));
+        };
+        let snapshot = self
+            .store()
+            .get_snapshot_by_name(&parent.id, snapshot_name)?
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Branch '{}' has no snapshot '{}'; create it with 'pgbranch snapshot create {} {}'",
+                    parent_name,
+                    snapshot_name,
+                    parent_name,
+                    snapshot_name
+                )
+            })?;
+        Ok((Some(parent), Some(snapshot)))
+    }
 
-            let result = self
-                .storage
-                .clone_branch_from_parent(&project, parent_branch, &branch_id, &data_dir)
+    /// The snapshot a branch was created from with `--from <parent>@<snapshot>`,
+    /// while it still exists.
+    fn pinned_snapshot(&self, branch: &model::Branch) -> Result<Option<model::Snapshot>> {
+        match &branch.parent_snapshot_id {
+            Some(snapshot_id) => self.store().get_snapshot(snapshot_id),
+            None => Ok(None),
+        }
+    }
+
+    async fn create_branch_inner(
+        &self,
+        branch_name: &str,
+        origin: BranchOrigin<'_>,
+    ) -> Result<BranchInfo> {
+        let started = std::time::Instant::now();
+        let project = self.ensure_project().await?;
+        self.reconcile_project(&project).await?;
+        let phase = self.record_phase("prepare", started);
+
+        // Check if branch already exists
+        if let Some(existing) = self.store().get_branch_by_name(&project.id, branch_name)? {
+            if existing.state == BranchState::Running {
+                return Ok(BranchInfo {
+                    database_name: self.branch_credentials(&existing).db,
+                    created_at: DateTime::from_timestamp_millis(existing.created_at),
+                    name: existing.name,
+                    parent_branch: None,
+                    state: Some(existing.state.as_str().to_string()),
+                    health: existing.health,
+                    last_used_at: existing
+                        .last_used_at
+                        .and_then(DateTime::from_timestamp_millis),
+                });
+            }
+        }
+
+        let CreatePlan {
+            branch_id,
+            data_dir,
+            container_name,
+            port,
+            parent,
+            snapshot,
+            credentials,
+        } = self.plan_create(&project, branch_name, &origin).await?;
+        let needed = match (&parent, &snapshot, &origin) {
+            (Some(_), Some(snapshot), _) => {
+                let source =
+                    self.storage
+                        .snapshot_data_dir(&project, &snapshot.branch_id, &snapshot.id);
+                self.storage
+                    .clone_size_estimate(&project, &source, false)
+                    .await
+            }
+            (Some(parent_branch), None, _) => {
+                self.storage
+                    .clone_size_estimate(&project, Path::new(&parent_branch.data_dir), false)
+                    .await
+            }
+            (None, _, BranchOrigin::DataDir(source)) => {
+                self.storage
+                    .clone_size_estimate(&project, &source.data_dir, true)
+                    .await
+            }
+            _ => 0,
+        };
+        self.storage.ensure_space(&project, needed).await?;
+        let phase = self.record_phase("reserve", phase);
+
+        // Record the branch before touching storage, so an interrupted create
+        // leaves a provisioning row to roll back from
+        let mut branch = self.store().create_branch(NewBranch {
+            id: branch_id.clone(),
+            project_id: project.id.clone(),
+            name: branch_name.to_string(),
+            parent_branch_id: parent.as_ref().map(|p| p.id.clone()),
+            state: BranchState::Provisioning,
+            data_dir: data_dir.to_string_lossy().to_string(),
+            container_name,
+            port,
+            storage_metadata: None,
+            credentials,
+            parent_snapshot_id: snapshot.as_ref().map(|s| s.id.clone()),
+        })?;
+
+        // Clone or create empty
+        let storage: Result<Option<String>> = async {
+            Ok(if let Some(ref snapshot) = snapshot {
+                // Snapshots don't change, so the parent keeps running
+                self.storage
+                    .clone_branch_from_snapshot(&project, snapshot, &branch_id, &data_dir)
+                    .await?
+            } else if let Some(ref parent_branch) = parent {
+                // Pause parent if running
+                let parent_running = self
+                    .runtime
+                    .container_status(&parent_branch.container_name)
+                    .await?
+                    == docker::ContainerStatus::Running;
+
+                if parent_running {
+                    self.runtime
+                        .pause_branch(&parent_branch.container_name)
+                        .await?;
+                }
+
+                let result = self
+                    .storage
+                    .clone_branch_from_parent(&project, parent_branch, &branch_id, &data_dir)
+                    .await;
+
+                if parent_running {
+                    self.runtime
+                        .unpause_branch(&parent_branch.container_name)
+                        .await?;
+                }
+
+                result?
+            } else if let BranchOrigin::DataDir(source) = origin {
+                let source_running = self
+                    .runtime
+                    .container_status(&source.container_name)
+                    .await?
+                    == docker::ContainerStatus::Running;
+
+                if source_running {
+                    self.runtime.pause_branch(&source.container_name).await?;
+                }
+
+                let result = self
+                    .storage
+                    .clone_branch_from_dir(&project, &source.data_dir, &branch_id, &data_dir)
+                    .await;
+
+                if source_running {
+                    self.runtime.unpause_branch(&source.container_name).await?;
+                }
+
+                result?
+            } else {
+                self.storage
+                    .create_empty_branch(&project, &branch_id, &data_dir)
+                    .await?
+            })
+        }
+        .await;
+        // A clone of the whole branch directory brings the parent's captured logs along
+        let _ = std::fs::remove_dir_all(log_capture::log_dir(&data_dir));
+        match storage {
+            Ok(Some(metadata)) => {
+                self.store()
+                    .update_branch_storage_metadata(&branch.id, Some(&metadata))?;
+                branch.storage_metadata = Some(metadata);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.store().delete_branch(&branch.id)?;
+                return Err(e);
+            }
+        }
+        let cloned = parent.is_some() || matches!(origin, BranchOrigin::DataDir(_));
+        if cloned {
+            self.record_usage(&project, "clone", None, phase);
+        }
+        let phase = self.record_phase(if cloned { "clone" } else { "init_storage" }, phase);
+
+        self.runtime.ensure_image(&project.image).await?;
+        let phase = self.record_phase("image", phase);
+
+        // Start container
+        self.runtime
+            .start_branch(&self.start_spec(&project, &branch))
+            .await?;
+        let phase = self.record_phase("container_start", phase);
+
+        // Wait for readiness
+        self.wait_branch_ready(&branch).await?;
+        let phase = self.record_phase("readiness", phase);
+        self.warm_up(&branch, phase).await;
+        if cloned {
+            self.reset_inherited_query_stats(&branch).await;
+        }
+
+        // Update state
+        self.store()
+            .update_branch_state(&branch.id, BranchState::Running)?;
+        self.record_usage(
+            &project,
+            "create",
+            Some(if cloned { "cloned" } else { "empty" }),
+            started,
+        );
+
+        Ok(BranchInfo {
+            name: branch_name.to_string(),
+            created_at: Some(Utc::now()),
+            parent_branch: parent.as_ref().map(|p| match &snapshot {
+                Some(snapshot) => format!("{}@{}", p.name, snapshot.name),
+                None => p.name.clone(),
+            }),
+            database_name: self.branch_credentials(&branch).db,
+            state: Some("running".to_string()),
+            health: None,
+            last_used_at: None,
+        })
+    }
+
+    async fn reset_branch_inner(
+        &self,
+        branch_name: &str,
+        to_snapshot: Option<&str>,
+        keep_snapshots: bool,
+    ) -> Result<()> {
+        let project = self.ensure_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+
+        let (target, discarded) =
+            self.reset_target(&project, &branch, to_snapshot, keep_snapshots)?;
+
+        let started = std::time::Instant::now();
+        let was_running = branch.state == BranchState::Running;
+
+        // Stop container
+        self.runtime.stop_branch(&branch.container_name).await?;
+        self.capture_logs(&branch).await;
+        let phase = self.record_phase("stop", started);
+
+        let method = if let Some(target) = &target {
+            self.storage
+                .restore_snapshot(&project, &branch, target)
+                .await?;
+            "snapshot"
+        } else {
+            let reset_in_place = match self.storage.reset_in_place(&project, &branch).await {
+                Ok(done) => done,
+                Err(e) => {
+                    log::warn!(
+                        "In-place reset of '{}' failed, re-cloning from parent: {}",
+                        branch_name,
+                        e
+                    );
+                    false
+                }
+            };
+            if !reset_in_place {
+                self.reclone_from_parent(&project, &branch).await?;
+                "reclone"
+            } else {
+                "in_place"
+            }
+        };
+        let phase = self.record_phase(method, phase);
+        // Re-cloning may have replaced the data (and its credentials) with the parent's
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .unwrap_or(branch);
+
+        let phase = if !keep_snapshots && !discarded.is_empty() {
+            for snapshot in &discarded {
+                if let Err(e) = self.storage.delete_snapshot_data(&project, snapshot).await {
+                    log::warn!("Failed to delete snapshot '{}': {}", snapshot.name, e);
+                }
+                self.store().delete_snapshot(&snapshot.id)?;
+            }
+            self.record_phase("discard_snapshots", phase)
+        } else {
+            phase
+        };
+
+        // Restart if it was running
+        if was_running {
+            self.runtime
+                .start_branch(&self.start_spec(&project, &branch))
+                .await?;
+            let phase = self.record_phase("container_start", phase);
+
+            self.wait_branch_ready(&branch).await?;
+            let phase = self.record_phase("readiness", phase);
+            self.warm_up(&branch, phase).await;
+            self.store()
+                .update_branch_state(&branch.id, BranchState::Running)?;
+        } else {
+            self.store()
+                .update_branch_state(&branch.id, BranchState::Stopped)?;
+        }
+        self.record_usage(&project, "reset", Some(method), started);
+
+        Ok(())
+    }
+
+    /// The snapshot a reset goes back to, and the snapshots it would discard.
+    fn reset_target(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+        to_snapshot: Option<&str>,
+        keep_snapshots: bool,
+    ) -> Result<(Option<model::Snapshot>, Vec<model::Snapshot>)> {
+        let branch_name = &branch.name;
+        let snapshots = self.store().list_snapshots(&branch.id)?;
+        let target = match to_snapshot {
+            Some(name) => Some(
+                snapshots
+                    .iter()
+                    .find(|s| s.name == name)
+                    .cloned()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Snapshot '{}' not found for branch '{}'",
+                            name,
+                            branch_name
+                        )
+                    })?,
+            ),
+            None => None,
+        };
+
+        // Snapshots that no longer describe an ancestor of the reset state
+        let discarded: Vec<model::Snapshot> = match &target {
+            Some(target) => snapshots
+                .into_iter()
+                .filter(|s| s.created_at > target.created_at)
+                .collect(),
+            None => snapshots,
+        };
+
+        if keep_snapshots
+            && !discarded.is_empty()
+            && project.storage_backend == model::StorageBackend::Zfs
+        {
+            anyhow::bail!(
+                "--keep-snapshots is not supported on ZFS storage: resetting branch '{}' destroys {} newer snapshot(s)",
+                branch_name,
+                discarded.len()
+            );
+        }
+
+        Ok((target, discarded))
+    }
+
+    async fn branch_created(
+        &self,
+        branch_name: &str,
+        started: std::time::Instant,
+        result: Result<BranchInfo>,
+    ) -> Result<BranchInfo> {
+        if result.is_ok() && self.pgpass {
+            if let Err(e) = self.write_pgpass_entry(branch_name) {
+                log::warn!("Failed to update .pgpass: {}", e);
+            }
+        }
+        if let Some(ref notifier) = self.notifier {
+            notifier
+                .operation_finished("Creating branch", branch_name, started, &result)
                 .await;
+        }
+        result
+    }
 
-            if parent_running {
-                self.runtime
-                    .unpause_branch(&parent_branch.container_name)
-                    .await?;
+    /// Replace the branch's data with a fresh clone of its parent, if it has one.
+    async fn reclone_from_parent(
+        &self,
+        project: &model::Project,
+        branch: &model::Branch,
+    ) -> Result<()> {
+        if let Some(parent_id) = &branch.parent_branch_id {
+            let parent = self
+                .store()
+                .list_branches(&project.id)?
+                .into_iter()
+                .find(|b| &b.id == parent_id);
+
+            if let Some(parent_branch) = parent {
+                let data_dir = PathBuf::from(&branch.data_dir);
+                let new_metadata = if let Some(snapshot) = self.pinned_snapshot(branch)? {
+                    self.storage
+                        .clone_branch_from_snapshot(project, &snapshot, &branch.id, &data_dir)
+                        .await?
+                } else {
+                    let parent_running = self
+                        .runtime
+                        .container_status(&parent_branch.container_name)
+                        .await?
+                        == docker::ContainerStatus::Running;
+
+                    if parent_running {
+                        self.runtime
+                            .pause_branch(&parent_branch.container_name)
+                            .await?;
+                    }
+
+                    let result = self
+                        .storage
+                        .clone_branch_from_parent(project, &parent_branch, &branch.id, &data_dir)
+                        .await;
+
+                    if parent_running {
+                        self.runtime
+                            .unpause_branch(&parent_branch.container_name)
+                            .await?;
+                    }
+                    result?
+                };
+
+                if let Some(metadata) = &new_metadata {
+                    self.store()
+                        .update_branch_storage_metadata(&branch.id, Some(metadata))?;
+                }
+                self.store().update_branch_credentials(
+                    &branch.id,
+                    &self.branch_credentials(&parent_branch),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `destroy --containers-only`: stop and remove every branch's container.
+    /// The data and records stay, so `start` brings a branch back.
+    async fn remove_containers(&self, branches: &[model::Branch]) -> Result<Vec<String>> {
+        for branch in branches {
+            self.runtime.stop_branch(&branch.container_name).await?;
+            self.capture_logs(branch).await;
+            self.runtime.remove_branch(&branch.container_name).await?;
+            self.store()
+                .update_branch_state(&branch.id, BranchState::Stopped)?;
+        }
+        Ok(branches.iter().map(|b| b.name.clone()).collect())
+    }
+
+    /// `destroy --keep-project`: delete every branch, newest first so clones
+    /// go before the branches they were cloned from.
+    async fn delete_all_branches(&self, branches: &[model::Branch]) -> Result<Vec<String>> {
+        let mut deleted = Vec::new();
+        let mut failed = Vec::new();
+        for branch in branches {
+            match self.delete_branch(&branch.name).await {
+                Ok(()) => deleted.push(branch.name.clone()),
+                Err(e) => {
+                    log::warn!("Failed to delete branch '{}': {:#}", branch.name, e);
+                    failed.push(branch.name.clone());
+                }
+            }
+        }
+        if !failed.is_empty() {
+            anyhow::bail!(
+                "Failed to delete branches {} (deleted: {})",
+                failed.join(", "),
+                if deleted.is_empty() {
+                    "none".to_string()
+                } else {
+                    deleted.join(", ")
+                }
+            );
+        }
+        Ok(deleted)
+    }
+
+    async fn create_snapshot_inner(
+        &self,
+        branch_name: &str,
+        snapshot_name: &str,
+        replace: bool,
+    ) -> Result<()> {
+        let project = self.ensure_project().await?;
+
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+
+        let existing = self
+            .store()
+            .get_snapshot_by_name(&branch.id, snapshot_name)?;
+        if existing.is_some() && !replace {
+            anyhow::bail!(
+                "Snapshot '{}' already exists for branch '{}'; use --replace to take it again",
+                snapshot_name,
+                branch_name
+            );
+        }
+
+        // Pause so the copy is consistent, same as when cloning a parent
+        let running = self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?
+            == docker::ContainerStatus::Running;
+
+        if running {
+            self.runtime.pause_branch(&branch.container_name).await?;
+        }
+
+        let snapshot_id = Uuid::new_v4().to_string();
+        let result = self
+            .storage
+            .snapshot_branch(&project, &branch, &snapshot_id)
+            .await;
+
+        if running {
+            self.runtime.unpause_branch(&branch.container_name).await?;
+        }
+
+        let storage_metadata = result?;
+        if let Some(old) = existing {
+            // Branches created from the old one keep their data; on ZFS its
+            // destruction waits for them
+            let pinned = self.store().list_branches_pinned_to(&old.id)?;
+            if !pinned.is_empty() {
+                log::info!(
+                    "Branches {} keep the data of the replaced snapshot '{}'; where they can't roll back in place, reset re-clones the parent's current data",
+                    pinned.join(", "),
+                    snapshot_name
+                );
+            }
+            if let Err(e) = self.storage.delete_snapshot_data(&project, &old).await {
+                log::warn!("Failed to delete snapshot '{}': {}", old.name, e);
+            }
+            self.store().delete_snapshot(&old.id)?;
+        }
+        self.store().create_snapshot(
+            &snapshot_id,
+            &branch.id,
+            snapshot_name,
+            storage_metadata.as_deref(),
+        )?;
+
+        Ok(())
+    }
+
+    async fn list_snapshots_inner(&self, branch_name: Option<&str>) -> Result<Vec<SnapshotInfo>> {
+        let project = self.ensure_project().await?;
+        let branches = match branch_name {
+            Some(name) => vec![self
+                .store()
+                .get_branch_by_name(&project.id, name)?
+                .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", name))?],
+            None => self.store().list_branches(&project.id)?,
+        };
+
+        let mut snapshots = Vec::new();
+        for branch in &branches {
+            for snapshot in self.store().list_snapshots(&branch.id)? {
+                snapshots.push(SnapshotInfo {
+                    branch: branch.name.clone(),
+                    name: snapshot.name,
+                    created_at: DateTime::from_timestamp_millis(snapshot.created_at),
+                });
+            }
+        }
+        snapshots.sort_by_key(|s| s.created_at);
+        Ok(snapshots)
+    }
+}
+
+#[async_trait]
+impl DatabaseBranchingBackend for LocalBackend {
+    async fn create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<BranchInfo> {
+        let started = std::time::Instant::now();
+        let result = self
+            .create_branch_inner(branch_name, BranchOrigin::Parent(from_branch))
+            .await;
+        self.branch_created(branch_name, started, result).await
+    }
+
+    async fn create_branch_from_source(
+        &self,
+        branch_name: &str,
+        source: &str,
+    ) -> Result<BranchInfo> {
+        if self.branch_exists(branch_name).await? {
+            return Err(coded!(
+                BranchExists,
+                "Branch '{}' already exists",
+                branch_name
+            ));
+        }
+
+        let started = std::time::Instant::now();
+        let result = self
+            .create_branch_inner(branch_name, BranchOrigin::Empty)
+            .await;
+        let info = self.branch_created(branch_name, started, result).await?;
+        self.seed_from_source(branch_name, source).await?;
+        Ok(info)
+    }
+
+    async fn create_schema_only_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<BranchInfo> {
+        let info = self.create_branch(branch_name, from_branch).await?;
+        if let Err(e) = self.truncate_branch_data(branch_name).await {
+            if let Err(cleanup) = self.delete_branch(branch_name).await {
+                log::warn!(
+                    "Failed to remove branch '{}' after emptying it failed: {:#}",
+                    branch_name,
+                    cleanup
+                );
             }
+            return Err(e.context(format!("failed to empty branch '{}'", branch_name)));
+        }
+        Ok(info)
+    }
+
+    fn maintenance_tasks(&self) -> Vec<String> {
+        self.maintenance
+            .iter()
+            .map(|task| maintenance::task_name(*task).to_string())
+            .collect()
+    }
+
+    async fn run_maintenance(&self, branch_name: &str) -> Result<Vec<String>> {
+        self.run_maintenance_tasks(branch_name).await
+    }
+
+    async fn top_queries(
+        &self,
+        branch_name: &str,
+        limit: usize,
+        reset: bool,
+    ) -> Result<Vec<QueryStat>> {
+        self.query_stats_top(branch_name, limit, reset).await
+    }
+
+    async fn enable_query_stats(&self, branch_name: &str) -> Result<()> {
+        self.query_stats_enable(branch_name).await
+    }
+
+    async fn table_data(
+        &self,
+        branch_name: &str,
+        tables: &[String],
+        checksum: bool,
+    ) -> Result<Vec<TableData>> {
+        self.branch_table_data(branch_name, tables, checksum).await
+    }
+
+    async fn export_fixture(&self, branch_name: &str, since_parent: bool) -> Result<FixtureExport> {
+        self.fixture_export(branch_name, since_parent).await
+    }
+
+    async fn compact_branch(&self, branch_name: &str, vacuum_full: bool) -> Result<CompactReport> {
+        self.compact(branch_name, vacuum_full).await
+    }
+
+    async fn exec_sql(&self, branch_name: &str, sql: &str, record: bool) -> Result<String> {
+        self.exec_in_branch(branch_name, sql, record).await
+    }
+
+    async fn session_script(&self, branch_name: &str) -> Result<PathBuf> {
+        self.branch_session_path(branch_name).await
+    }
+
+    async fn watch_events(&self, on_event: &mut (dyn FnMut(BranchEvent) + Send)) -> Result<()> {
+        self.watch_project_events(on_event).await
+    }
+
+    async fn recent_drift(&self, since: DateTime<Utc>) -> Result<Vec<DriftEvent>> {
+        let Some(project) = self.store().get_project_by_name(&self.project_name)? else {
+            return Ok(Vec::new());
+        };
+        Ok(self
+            .store()
+            .list_drift(&project.id, since.timestamp_millis())?
+            .into_iter()
+            .map(|drift| DriftEvent {
+                branch: drift.branch_name,
+                from_state: drift.from_state.as_str().to_string(),
+                to_state: drift.to_state.as_str().to_string(),
+                reason: drift.reason,
+                detected_at: DateTime::from_timestamp_millis(drift.detected_at).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn container_psql_command(
+        &self,
+        branch_name: &str,
+        args: &[String],
+    ) -> Result<Option<HostCommand>> {
+        let branch = self.running_branch(branch_name).await?;
+        self.touch_branch(&branch);
+        let credentials = self.branch_credentials(&branch);
+        let mut cmd = vec![
+            "psql".to_string(),
+            "-U".to_string(),
+            credentials.user,
+            "-d".to_string(),
+            credentials.db,
+        ];
+        cmd.extend(args.iter().cloned());
+        Ok(Some(self.runtime.cli_exec(&branch.container_name, &cmd)))
+    }
+
+    async fn project_space(&self) -> Result<Option<SpaceUsage>> {
+        let Some(project) = self.store().get_project_by_name(&self.project_name)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .storage
+            .project_usage(&project)
+            .await?
+            .map(|usage| space_usage(None, usage)))
+    }
+
+    async fn branch_space(&self) -> Result<Vec<SpaceUsage>> {
+        let project = self.ensure_project().await?;
+        let branches = self.store().list_branches(&project.id)?;
+        let mut usage = Vec::new();
+        for branch in branches {
+            let branch_usage = self
+                .storage
+                .branch_usage(&project, &branch)
+                .await
+                .with_context(|| format!("failed to measure branch '{}'", branch.name))?;
+            usage.push(space_usage(Some(branch.name), branch_usage));
+        }
+        Ok(usage)
+    }
+
+    async fn recover_interrupted(&self, branch_name: &str) -> Result<Option<String>> {
+        let Some(project) = self.store().get_project_by_name(&self.project_name)? else {
+            return Ok(None);
+        };
+        let Some(branch) = self.store().get_branch_by_name(&project.id, branch_name)? else {
+            // Interrupted before the branch was recorded; only a paused parent is left
+            self.reconcile_project(&project).await?;
+            return Ok(None);
+        };
+
+        if branch.state == BranchState::Provisioning {
+            let removed: Result<()> = async {
+                self.runtime.remove_branch(&branch.container_name).await?;
+                self.storage.delete_branch_data(&project, &branch).await?;
+                self.store().delete_branch(&branch.id)
+            }
+            .await;
+            if let Err(e) = removed {
+                self.store()
+                    .update_branch_state(&branch.id, BranchState::Failed)?;
+                return Err(e.context(format!(
+                    "failed to remove the half-created branch '{}'",
+                    branch_name
+                )));
+            }
+            self.reconcile_project(&project).await?;
+            return Ok(Some(format!(
+                "Removed the half-created branch '{}'",
+                branch_name
+            )));
+        }
 
-            result?
-        } else {
-            self.storage
-                .create_empty_branch(&project, &branch_id, &data_dir)
-                .await?
-        };
+        self.reconcile_project(&project).await?;
+        let state = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .map(|b| b.state);
+        Ok(match state {
+            Some(BranchState::Running) | None => None,
+            Some(state) => Some(format!(
+                "Branch '{}' is {}; run the command again or 'pgbranch delete {}'",
+                branch_name,
+                state.as_str(),
+                branch_name
+            )),
+        })
+    }
 
-        // Persist to state
-        let branch = self.store().create_branch(NewBranch {
-            id: branch_id,
-            project_id: project.id.clone(),
-            name: branch_name.to_string(),
-            parent_branch_id: parent.as_ref().map(|p| p.id.clone()),
-            state: BranchState::Provisioning,
-            data_dir: data_dir.to_string_lossy().to_string(),
-            container_name: reserved.container_name.clone(),
-            port,
-            storage_metadata,
-        })?;
+    async fn branch_data_source(&self, branch_name: &str) -> Result<Option<BranchDataSource>> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+
+        let credentials = self.branch_credentials(&branch);
+        Ok(Some(BranchDataSource {
+            data_root: self.data_root.clone(),
+            data_dir: PathBuf::from(&branch.data_dir),
+            container_name: branch.container_name,
+            image: project.image,
+            pg_user: credentials.user,
+            pg_password: credentials.password,
+            pg_db: credentials.db,
+        }))
+    }
 
-        // Start container
-        self.runtime
-            .start_branch(&StartBranchSpec {
-                image: project.image.clone(),
-                container_name: reserved.container_name.clone(),
-                data_dir,
-                port,
-                pg_user: self.pg_user.clone(),
-                pg_password: self.pg_password.clone(),
-                pg_db: self.pg_db.clone(),
-            })
-            .await?;
+    async fn create_branch_from_data(
+        &self,
+        branch_name: &str,
+        source: &BranchDataSource,
+    ) -> Result<Option<BranchInfo>> {
+        let project = self.ensure_project().await?;
 
-        // Wait for readiness
-        self.runtime
-            .wait_ready(
-                &reserved.container_name,
-                &self.pg_user,
-                &self.pg_db,
-                STARTUP_TIMEOUT,
-            )
-            .await?;
+        // PGDATA can only be shared by the same server version, and cloning
+        // only pays off on the same filesystem; its credentials come along
+        let same_root = std::fs::canonicalize(&source.data_root).ok()
+            == std::fs::canonicalize(&self.data_root).ok();
+        if !same_root || source.image != project.image {
+            return Ok(None);
+        }
 
-        // Update state
-        self.store()
-            .update_branch_state(&branch.id, BranchState::Running)?;
+        if self.branch_exists(branch_name).await? {
+            return Err(coded!(
+                BranchExists,
+                "Branch '{}' already exists",
+                branch_name
+            ));
+        }
 
-        Ok(BranchInfo {
-            name: branch_name.to_string(),
-            created_at: Some(Utc::now()),
-            parent_branch: parent.as_ref().map(|p| p.name.clone()),
-            database_name: self.pg_db.clone(),
-            state: Some("running".to_string()),
-        })
+        let started = std::time::Instant::now();
+        let result = self
+            .create_branch_inner(branch_name, BranchOrigin::DataDir(source))
+            .await;
+        self.branch_created(branch_name, started, result)
+            .await
+            .map(Some)
     }
 
     async fn delete_branch(&self, branch_name: &str) -> Result<()> {
@@ -308,17 +1751,24 @@ impl DatabaseBranchingBackend for LocalBackend {
         let branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
         // Remove container
         self.runtime.remove_branch(&branch.container_name).await?;
 
         // Delete storage data
         self.storage.delete_branch_data(&project, &branch).await?;
+        let _ = std::fs::remove_file(self.session_path(&branch));
 
         // Delete from state
         self.store().delete_branch(&branch.id)?;
 
+        if self.pgpass {
+            if let Err(e) = pgpass::remove_entry(&self.pgpass_key(branch_name)) {
+                log::warn!("Failed to update .pgpass: {}", e);
+            }
+        }
+
         Ok(())
     }
 
@@ -338,14 +1788,16 @@ impl DatabaseBranchingBackend for LocalBackend {
             .iter()
             .map(|b| BranchInfo {
                 name: b.name.clone(),
-                created_at: None,
+                created_at: DateTime::from_timestamp_millis(b.created_at),
                 parent_branch: b
                     .parent_branch_id
                     .as_deref()
                     .and_then(|pid| id_to_name.get(pid))
                     .map(|name| name.to_string()),
-                database_name: self.pg_db.clone(),
+                database_name: self.branch_credentials(b).db,
                 state: Some(b.state.as_str().to_string()),
+                health: b.health.clone(),
+                last_used_at: b.last_used_at.and_then(DateTime::from_timestamp_millis),
             })
             .collect())
     }
@@ -362,43 +1814,30 @@ impl DatabaseBranchingBackend for LocalBackend {
         let project = self.ensure_project().await?;
         self.reconcile_project(&project).await?;
 
-        let branch = self
+        let mut branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
         // Start if stopped
         if branch.state == BranchState::Stopped {
-            self.runtime
-                .start_branch(&StartBranchSpec {
-                    image: project.image.clone(),
-                    container_name: branch.container_name.clone(),
-                    data_dir: PathBuf::from(&branch.data_dir),
-                    port: branch.port,
-                    pg_user: self.pg_user.clone(),
-                    pg_password: self.pg_password.clone(),
-                    pg_db: self.pg_db.clone(),
-                })
-                .await?;
+            self.start_container(&project, &mut branch).await?;
 
-            self.runtime
-                .wait_ready(
-                    &branch.container_name,
-                    &self.pg_user,
-                    &self.pg_db,
-                    STARTUP_TIMEOUT,
-                )
-                .await?;
+            self.wait_branch_ready(&branch).await?;
+            self.warm_up(&branch, std::time::Instant::now()).await;
             self.store()
                 .update_branch_state(&branch.id, BranchState::Running)?;
         }
+        self.touch_branch(&branch);
 
         Ok(BranchInfo {
+            database_name: self.branch_credentials(&branch).db,
+            created_at: DateTime::from_timestamp_millis(branch.created_at),
             name: branch.name,
-            created_at: None,
             parent_branch: None,
-            database_name: self.pg_db.clone(),
             state: Some("running".to_string()),
+            health: None,
+            last_used_at: Some(Utc::now()),
         })
     }
 
@@ -408,48 +1847,35 @@ impl DatabaseBranchingBackend for LocalBackend {
         let branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
+        self.touch_branch(&branch);
+        let credentials = self.branch_credentials(&branch);
         Ok(ConnectionInfo {
-            host: "127.0.0.1".to_string(),
+            host: self.runtime.client_host().to_string(),
             port: branch.port,
-            database: self.pg_db.clone(),
-            user: self.pg_user.clone(),
-            password: Some(self.pg_password.clone()),
-            connection_string: Some(self.connection_uri(branch.port)),
+            connection_string: Some(self.connection_uri(branch.port, &credentials)),
+            database: credentials.db,
+            user: credentials.user,
+            password: (!self.hide_password).then_some(credentials.password),
         })
     }
 
     async fn start_branch(&self, branch_name: &str) -> Result<()> {
         let project = self.ensure_project().await?;
 
-        let branch = self
+        let mut branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
-        self.runtime
-            .start_branch(&StartBranchSpec {
-                image: project.image.clone(),
-                container_name: branch.container_name.clone(),
-                data_dir: PathBuf::from(&branch.data_dir),
-                port: branch.port,
-                pg_user: self.pg_user.clone(),
-                pg_password: self.pg_password.clone(),
-                pg_db: self.pg_db.clone(),
-            })
-            .await?;
+        self.start_container(&project, &mut branch).await?;
 
-        self.runtime
-            .wait_ready(
-                &branch.container_name,
-                &self.pg_user,
-                &self.pg_db,
-                STARTUP_TIMEOUT,
-            )
-            .await?;
+        self.wait_branch_ready(&branch).await?;
+        self.warm_up(&branch, std::time::Instant::now()).await;
         self.store()
             .update_branch_state(&branch.id, BranchState::Running)?;
+        self.touch_branch(&branch);
 
         Ok(())
     }
@@ -460,102 +1886,241 @@ impl DatabaseBranchingBackend for LocalBackend {
         let branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
         self.runtime.stop_branch(&branch.container_name).await?;
+        self.capture_logs(&branch).await;
         self.store()
             .update_branch_state(&branch.id, BranchState::Stopped)?;
 
         Ok(())
     }
 
-    async fn reset_branch(&self, branch_name: &str) -> Result<()> {
-        let project = self.ensure_project().await?;
+    async fn reset_branch(
+        &self,
+        branch_name: &str,
+        to_snapshot: Option<&str>,
+        keep_snapshots: bool,
+    ) -> Result<()> {
+        let started = std::time::Instant::now();
+        let result = self
+            .reset_branch_inner(branch_name, to_snapshot, keep_snapshots)
+            .await;
+        if let Some(ref notifier) = self.notifier {
+            notifier
+                .operation_finished("Resetting branch", branch_name, started, &result)
+                .await;
+        }
+        result
+    }
 
-        let branch = self
-            .store()
-            .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+    async fn plan_create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        self.plan_create_actions(branch_name, from_branch).await
+    }
 
-        let was_running = branch.state == BranchState::Running;
+    async fn plan_create_schema_only_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        let mut actions = self.plan_create_actions(branch_name, from_branch).await?;
+        actions.push(PlannedAction::new(
+            "postgres",
+            format!(
+                "Truncate every table except the migration history ({})",
+                schema_only::KEPT_TABLES.join(", ")
+            ),
+        ));
+        Ok(actions)
+    }
 
-        // Stop container
-        self.runtime.stop_branch(&branch.container_name).await?;
+    async fn plan_delete_branch(&self, branch_name: &str) -> Result<Vec<PlannedAction>> {
+        self.plan_delete_actions(branch_name).await
+    }
 
-        // Re-clone from parent if available
-        if let Some(parent_id) = &branch.parent_branch_id {
-            let parent = self
-                .store()
-                .list_branches(&project.id)?
-                .into_iter()
-                .find(|b| &b.id == parent_id);
+    async fn plan_reset_branch(
+        &self,
+        branch_name: &str,
+        to_snapshot: Option<&str>,
+        keep_snapshots: bool,
+    ) -> Result<Vec<PlannedAction>> {
+        self.plan_reset_actions(branch_name, to_snapshot, keep_snapshots)
+            .await
+    }
 
-            if let Some(parent_branch) = parent {
-                let parent_running = self
-                    .runtime
-                    .container_status(&parent_branch.container_name)
-                    .await?
-                    == docker::ContainerStatus::Running;
+    async fn plan_seed_from_source(
+        &self,
+        branch_name: &str,
+        source: &str,
+    ) -> Result<Vec<PlannedAction>> {
+        self.plan_seed_actions(branch_name, source).await
+    }
 
-                if parent_running {
-                    self.runtime
-                        .pause_branch(&parent_branch.container_name)
-                        .await?;
-                }
+    fn supports_lifecycle(&self) -> bool {
+        true
+    }
 
-                let data_dir = PathBuf::from(&branch.data_dir);
-                let new_metadata = self
-                    .storage
-                    .clone_branch_from_parent(&project, &parent_branch, &branch.id, &data_dir)
-                    .await?;
+    fn supports_proxy(&self) -> bool {
+        true
+    }
 
-                if parent_running {
-                    self.runtime
-                        .unpause_branch(&parent_branch.container_name)
-                        .await?;
-                }
+    async fn create_snapshot(
+        &self,
+        branch_name: &str,
+        snapshot_name: &str,
+        replace: bool,
+    ) -> Result<()> {
+        let started = std::time::Instant::now();
+        let result = self
+            .create_snapshot_inner(branch_name, snapshot_name, replace)
+            .await;
+        if let Some(ref notifier) = self.notifier {
+            notifier
+                .operation_finished("Snapshotting branch", branch_name, started, &result)
+                .await;
+        }
+        result
+    }
 
-                if let Some(metadata) = &new_metadata {
-                    self.store()
-                        .update_branch_storage_metadata(&branch.id, Some(metadata))?;
-                }
+    async fn list_snapshots(&self, branch_name: Option<&str>) -> Result<Vec<SnapshotInfo>> {
+        self.list_snapshots_inner(branch_name).await
+    }
+
+    fn supports_snapshots(&self) -> bool {
+        true
+    }
+
+    async fn branch_read_only(&self, branch_name: &str) -> Result<bool> {
+        let branch = self.running_branch(branch_name).await?;
+        let credentials = self.branch_credentials(&branch);
+        self.runtime
+            .database_read_only(&branch.container_name, &credentials.user, &credentials.db)
+            .await
+    }
+
+    async fn set_branch_read_only(&self, branch_name: &str, read_only: bool) -> Result<()> {
+        let branch = self.running_branch(branch_name).await?;
+        let credentials = self.branch_credentials(&branch);
+        self.runtime
+            .set_database_read_only(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                read_only,
+            )
+            .await
+    }
+
+    fn supports_read_only(&self) -> bool {
+        true
+    }
+
+    async fn branch_limits(&self, branch_name: &str) -> Result<ContainerLimits> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        self.runtime.container_limits(&branch.container_name).await
+    }
+
+    async fn set_branch_limits(&self, branch_name: &str, limits: &ContainerLimits) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        self.runtime
+            .update_limits(&branch.container_name, limits)
+            .await
+    }
+
+    fn supports_limits(&self) -> bool {
+        true
+    }
+
+    async fn set_branch_pg_settings(
+        &self,
+        branch_name: &str,
+        settings: &[(String, String)],
+    ) -> Result<()> {
+        let project = self.ensure_project().await?;
+        let mut branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+
+        for (key, value) in settings {
+            if value.is_empty() {
+                branch.pg_settings.remove(key);
+            } else {
+                branch.pg_settings.insert(key.clone(), value.clone());
             }
         }
+        self.store()
+            .update_branch_pg_settings(&branch.id, &branch.pg_settings)?;
 
-        // Restart if it was running
-        if was_running {
-            self.runtime
-                .start_branch(&StartBranchSpec {
-                    image: project.image.clone(),
-                    container_name: branch.container_name.clone(),
-                    data_dir: PathBuf::from(&branch.data_dir),
-                    port: branch.port,
-                    pg_user: self.pg_user.clone(),
-                    pg_password: self.pg_password.clone(),
-                    pg_db: self.pg_db.clone(),
-                })
-                .await?;
-
+        // A container's command is fixed, so recreate it; the data is a bind mount
+        let status = self
+            .runtime
+            .container_status(&branch.container_name)
+            .await?;
+        if status == docker::ContainerStatus::NotFound {
+            return Ok(());
+        }
+        if status == docker::ContainerStatus::Running {
+            self.runtime.stop_branch(&branch.container_name).await?;
+        }
+        self.capture_logs(&branch).await;
+        self.runtime.remove_branch(&branch.container_name).await?;
+        if status == docker::ContainerStatus::Running {
             self.runtime
-                .wait_ready(
-                    &branch.container_name,
-                    &self.pg_user,
-                    &self.pg_db,
-                    STARTUP_TIMEOUT,
-                )
+                .start_branch(&self.start_spec(&project, &branch))
                 .await?;
-            self.store()
-                .update_branch_state(&branch.id, BranchState::Running)?;
-        } else {
-            self.store()
-                .update_branch_state(&branch.id, BranchState::Stopped)?;
+            self.wait_branch_ready(&branch).await?;
         }
-
         Ok(())
     }
 
-    fn supports_lifecycle(&self) -> bool {
-        true
+    async fn set_branch_password(&self, branch_name: &str) -> Result<()> {
+        let branch = self.running_branch(branch_name).await?;
+        let credentials = Credentials {
+            password: self.pg_password.clone(),
+            ..self.branch_credentials(&branch)
+        };
+        self.runtime
+            .set_role_password(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.password,
+            )
+            .await?;
+        if let Some(error) = self
+            .runtime
+            .check_login(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.password,
+                &credentials.db,
+            )
+            .await?
+        {
+            anyhow::bail!(
+                "Password was changed, but logging in still fails: {}",
+                error
+            );
+        }
+        self.store()
+            .update_branch_credentials(&branch.id, &credentials)?;
+
+        if self.pgpass {
+            self.write_pgpass_entry(branch_name)?;
+        }
+        Ok(())
     }
 
     async fn test_connection(&self) -> Result<()> {
@@ -569,15 +2134,29 @@ impl DatabaseBranchingBackend for LocalBackend {
     async fn doctor(&self) -> Result<DoctorReport> {
         let mut checks = vec![];
 
-        // Docker check
+        // Container engine check
         let docker_result = self.runtime.doctor().await;
+        let endpoint = self.runtime.endpoint();
+        let engine = self.runtime.engine();
         checks.push(DoctorCheck {
-            name: "Docker".to_string(),
+            name: engine.label().to_string(),
             available: docker_result.available,
             detail: if let Some(version) = docker_result.version {
-                format!("Docker {} available", version)
+                format!(
+                    "{} {} available at {} (from {})",
+                    engine.label(),
+                    version,
+                    endpoint.host,
+                    endpoint.source
+                )
             } else {
-                docker_result.detail
+                match docker_endpoint::podman_socket() {
+                    Some(podman) if engine == ContainerEngine::Docker => format!(
+                        "{}; Podman is available at {} (from {}), set 'runtime: podman' under 'local' to use it",
+                        docker_result.detail, podman.host, podman.source
+                    ),
+                    _ => docker_result.detail,
+                }
             },
         });
 
@@ -602,12 +2181,25 @@ impl DatabaseBranchingBackend for LocalBackend {
             ),
         });
 
+        // Image, which can't be fetched when pulling is off
+        if docker_result.available {
+            checks.push(self.runtime.image_check(&self.image).await);
+        }
+
+        // Cron jobs that would prune the containers
+        checks.extend(prune_guard::checks(self.runtime.prune_protection()));
+
         // State database
         checks.push(DoctorCheck {
             name: "State database".to_string(),
             available: true,
             detail: format!("{}/state.db", self.data_root.display()),
         });
+        checks.extend(self.storage.space_checks().await);
+        checks.extend(docker_desktop::doctor_checks(&self.data_root));
+        if self.shared {
+            checks.extend(shared::doctor_checks(&self.data_root));
+        }
 
         Ok(DoctorReport { checks })
     }
@@ -622,17 +2214,41 @@ impl DatabaseBranchingBackend for LocalBackend {
         let branch = self
             .store()
             .get_branch_by_name(&project.id, branch_name)?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        let credentials = self.branch_credentials(&branch);
         let parsed = seed::parse_source(source)?;
-        seed::seed_branch(
+        // Restoring a dump takes at least as much room as the dump itself
+        let needed = match &parsed {
+            seed::SeedSource::LocalFile(path) => std::fs::metadata(path).map_or(0, |m| m.len()),
+            _ => 0,
+        };
+        self.storage.ensure_space(&project, needed).await?;
+        let started = std::time::Instant::now();
+        let result = seed::seed_branch(
             self.runtime.client(),
             &parsed,
             &branch.container_name,
-            &self.pg_user,
-            &self.pg_db,
+            &credentials.user,
+            &credentials.db,
             &self.image,
+            self.signing.as_ref(),
         )
-        .await
+        .await;
+        if result.is_ok() {
+            self.record_phase("seed", started);
+            let kind = match parsed {
+                seed::SeedSource::PostgresUrl(_) => "postgres",
+                seed::SeedSource::LocalFile(_) => "file",
+                seed::SeedSource::S3Object { .. } => "s3",
+            };
+            self.record_usage(&project, "seed", Some(kind), started);
+        }
+        if let Some(ref notifier) = self.notifier {
+            notifier
+                .operation_finished("Seeding branch", branch_name, started, &result)
+                .await;
+        }
+        result
     }
 
     fn project_info(&self) -> Option<ProjectInfo> {
@@ -651,6 +2267,14 @@ impl DatabaseBranchingBackend for LocalBackend {
         "Local (Docker + CoW)"
     }
 
+    fn take_phase_timings(&self) -> Vec<PhaseTiming> {
+        std::mem::take(&mut *self.phase_timings.lock().unwrap())
+    }
+
+    fn backend_type(&self) -> &'static str {
+        "local"
+    }
+
     fn supports_cleanup(&self) -> bool {
         true
     }
@@ -675,7 +2299,25 @@ impl DatabaseBranchingBackend for LocalBackend {
         Ok(Some((project.name.clone(), branch_names)))
     }
 
-    async fn destroy_project(&self) -> Result<Vec<String>> {
+    async fn write_destroy_manifest(&self) -> Result<Option<PathBuf>> {
+        LocalBackend::write_destroy_manifest(self)
+    }
+
+    async fn adopt_manifest(&self, path: &Path) -> Result<AdoptReport> {
+        LocalBackend::adopt_manifest(self, path).await
+    }
+
+    async fn adopt_pruned(&self) -> Result<AdoptReport> {
+        LocalBackend::adopt_pruned(self).await
+    }
+
+    async fn prune_guard(&self) -> Result<DoctorReport> {
+        Ok(DoctorReport {
+            checks: prune_guard::checks(self.runtime.prune_protection()),
+        })
+    }
+
+    async fn destroy_project(&self, scope: DestroyScope) -> Result<Vec<String>> {
         let project = self
             .store()
             .get_project_by_name(&self.project_name)?
@@ -684,6 +2326,12 @@ impl DatabaseBranchingBackend for LocalBackend {
         let branches = self.store().list_branches(&project.id)?;
         let branch_names: Vec<String> = branches.iter().map(|b| b.name.clone()).collect();
 
+        match scope {
+            DestroyScope::All => {}
+            DestroyScope::Containers => return self.remove_containers(&branches).await,
+            DestroyScope::Branches => return self.delete_all_branches(&branches).await,
+        }
+
         // 1. Remove all Docker containers (best-effort)
         for branch in &branches {
             if let Err(e) = self.runtime.remove_branch(&branch.container_name).await {
@@ -701,10 +2349,27 @@ impl DatabaseBranchingBackend for LocalBackend {
         // 3. Delete project from SQLite (cascades to branches)
         self.store().delete_project(&project.id)?;
 
+        if self.pgpass {
+            for name in &branch_names {
+                if let Err(e) = pgpass::remove_entry(&self.pgpass_key(name)) {
+                    log::warn!("Failed to update .pgpass: {}", e);
+                }
+            }
+        }
+
         Ok(branch_names)
     }
 }
 
+fn space_usage(branch: Option<String>, usage: storage::space::Usage) -> SpaceUsage {
+    SpaceUsage {
+        branch,
+        used_bytes: usage.used_bytes,
+        quota_bytes: usage.quota_bytes,
+        available_bytes: usage.available_bytes,
+    }
+}
+
 fn shellexpand(path: &str) -> String {
     if let Some(stripped) = path.strip_prefix("~/") {
         if let Some(home) = dirs::home_dir() {
@@ -713,3 +2378,77 @@ fn shellexpand(path: &str) -> String {
     }
     path.to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DockerRetryConfig;
+    use crate::errors::{code_of, ErrorCode};
+
+    /// A backend recording state in memory, with a Docker endpoint that
+    /// doesn't exist.
+    async fn backend_without_docker(data_root: &Path) -> LocalBackend {
+        let local = LocalBackendConfig {
+            data_root: Some(data_root.display().to_string()),
+            // Nothing listens on port 1
+            docker_host: Some("tcp://127.0.0.1:1".to_string()),
+            docker_retry: Some(DockerRetryConfig {
+                attempts: Some(1),
+                timeout_secs: Some(5),
+            }),
+            ..Default::default()
+        };
+        LocalBackend::with_store(
+            "app",
+            &Config::default(),
+            Some(&local),
+            Box::new(DocumentStore::in_memory()),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn branches_are_kept_in_the_given_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend_without_docker(dir.path()).await;
+
+        // Without a container for it, the branch isn't recorded
+        assert!(backend.create_branch("feature", None).await.is_err());
+        let project = backend.store().get_project_by_name("app").unwrap().unwrap();
+        assert!(backend
+            .store()
+            .get_branch_by_name(&project.id, "feature")
+            .unwrap()
+            .is_none());
+
+        // A create interrupted once the branch was recorded
+        backend
+            .store()
+            .create_branch(NewBranch {
+                id: "main".to_string(),
+                project_id: project.id.clone(),
+                name: "main".to_string(),
+                parent_branch_id: None,
+                state: BranchState::Provisioning,
+                data_dir: dir.path().join("main").display().to_string(),
+                container_name: "pgbranch-app-main".to_string(),
+                port: 55432,
+                storage_metadata: None,
+                credentials: backend.configured_credentials(),
+                parent_snapshot_id: None,
+            })
+            .unwrap();
+        let branches = backend.list_branches().await.unwrap();
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].name, "main");
+        assert_eq!(branches[0].state.as_deref(), Some("stopped"));
+        assert!(!dir.path().join("state.db").exists());
+
+        // The record stays until its container is known to be gone
+        assert!(backend.delete_branch("main").await.is_err());
+        assert!(backend.branch_exists("main").await.unwrap());
+        let missing = backend.delete_branch("missing").await.unwrap_err();
+        assert_eq!(code_of(&missing), ErrorCode::BranchNotFound);
+    }
+}
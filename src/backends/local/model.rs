@@ -1,9 +1,14 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Project {
     pub id: String,
     pub name: String,
+    /// User who created the project in shared mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
     pub image: String,
     pub storage_backend: StorageBackend,
     pub storage_config: Option<String>,
@@ -22,6 +27,48 @@ pub struct Branch {
     pub port: u16,
     pub storage_metadata: Option<String>,
     pub created_at: i64,
+    /// Login the data directory was initialized with; `None` for branches
+    /// recorded before credentials were stored
+    pub pg_user: Option<String>,
+    pub pg_password: Option<String>,
+    pub pg_db: Option<String>,
+    /// Last switch, connection or start; `None` if it hasn't been used since it was created
+    pub last_used_at: Option<i64>,
+    /// PostgreSQL settings passed to this branch's server as `-c key=value`
+    pub pg_settings: BTreeMap<String, String>,
+    /// Why PostgreSQL isn't accepting connections although the container
+    /// runs, as found by the last reconciliation; `None` when healthy
+    pub health: Option<String>,
+    /// Snapshot of the parent the branch was cloned from (`--from
+    /// <parent>@<snapshot>`); resets re-clone from it rather than the parent
+    pub parent_snapshot_id: Option<String>,
+}
+
+impl Branch {
+    pub fn credentials(&self) -> Option<Credentials> {
+        Some(Credentials {
+            user: self.pg_user.clone()?,
+            password: self.pg_password.clone()?,
+            db: self.pg_db.clone()?,
+        })
+    }
+}
+
+/// User, password and database to connect to a branch with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+    pub db: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub branch_id: String,
+    pub name: String,
+    pub storage_metadata: Option<String>,
+    pub created_at: i64,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
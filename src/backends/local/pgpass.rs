@@ -0,0 +1,97 @@
+//! Managed entries in the user's `.pgpass` file.
+//!
+//! Each entry is preceded by a `# pgbranch:<key>` comment line so it can be
+//! found and removed again without touching entries written by hand.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+const MARKER_PREFIX: &str = "# pgbranch:";
+
+/// `$PGPASSFILE` if set, otherwise `~/.pgpass` (the same lookup libpq uses).
+fn pgpass_path() -> Result<PathBuf> {
+    if let Ok(path) = std::env::var("PGPASSFILE") {
+        return Ok(PathBuf::from(path));
+    }
+    Ok(dirs::home_dir()
+        .context("Failed to get home directory")?
+        .join(".pgpass"))
+}
+
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace(':', "\\:")
+}
+
+/// Add or replace the entry identified by `key`.
+pub fn upsert_entry(
+    key: &str,
+    host: &str,
+    port: u16,
+    database: &str,
+    user: &str,
+    password: &str,
+) -> Result<()> {
+    let path = pgpass_path()?;
+    let mut lines = read_lines_without(&path, key)?;
+    lines.push(format!("{}{}", MARKER_PREFIX, key));
+    lines.push(format!(
+        "{}:{}:{}:{}:{}",
+        escape(host),
+        port,
+        escape(database),
+        escape(user),
+        escape(password)
+    ));
+    write_lines(&path, &lines)
+}
+
+/// Remove the entry identified by `key`, if present.
+pub fn remove_entry(key: &str) -> Result<()> {
+    let path = pgpass_path()?;
+    if !path.exists() {
+        return Ok(());
+    }
+    let lines = read_lines_without(&path, key)?;
+    write_lines(&path, &lines)
+}
+
+fn read_lines_without(path: &PathBuf, key: &str) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let marker = format!("{}{}", MARKER_PREFIX, key);
+    let mut lines = Vec::new();
+    let mut skip_next = false;
+    for line in content.lines() {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        if line == marker {
+            skip_next = true;
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+    Ok(lines)
+}
+
+fn write_lines(path: &PathBuf, lines: &[String]) -> Result<()> {
+    let mut content = lines.join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    // libpq ignores a .pgpass that is readable by group or others
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
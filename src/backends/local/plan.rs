@@ -0,0 +1,337 @@
+//! Dry runs of the mutating operations: the same decisions the operations
+//! make, described as steps instead of carried out.
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use super::docker::ContainerStatus;
+use super::model::{self, Credentials};
+use super::{seed, shared, BranchOrigin, LocalBackend};
+use crate::backends::PlannedAction;
+use crate::config::PullPolicy;
+
+impl LocalBackend {
+    pub(super) async fn plan_create_actions(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        let (project, mut actions) = self.planned_project().await?;
+
+        if let Some(existing) = self.store().get_branch_by_name(&project.id, branch_name)? {
+            if existing.state == model::BranchState::Running {
+                return Ok(vec![PlannedAction::new(
+                    "state",
+                    format!("Nothing to do: branch '{}' is already running", branch_name),
+                )]);
+            }
+        }
+
+        let origin = BranchOrigin::Parent(from_branch);
+        let plan = self.plan_create(&project, branch_name, &origin).await?;
+        match (&plan.parent, &plan.snapshot) {
+            (Some(_), Some(snapshot)) => actions.extend(
+                self.storage
+                    .plan_clone_branch_from_snapshot(
+                        &project,
+                        snapshot,
+                        &plan.branch_id,
+                        &plan.data_dir,
+                    )?
+                    .into_iter()
+                    .map(|command| PlannedAction::new("storage", command)),
+            ),
+            (Some(parent), None) => {
+                let commands = self.storage.plan_clone_branch_from_parent(
+                    &project,
+                    parent,
+                    &plan.branch_id,
+                    &plan.data_dir,
+                )?;
+                self.plan_paused(&parent.container_name, commands, &mut actions)
+                    .await?;
+            }
+            (None, _) => actions.extend(
+                self.storage
+                    .plan_create_empty_branch(&project, &plan.branch_id, &plan.data_dir)?
+                    .into_iter()
+                    .map(|command| PlannedAction::new("storage", command)),
+            ),
+        }
+
+        actions.push(PlannedAction::new(
+            "state",
+            format!(
+                "Record branch '{}' ({}) in {}",
+                branch_name,
+                plan.branch_id,
+                self.data_root.join("state.db").display()
+            ),
+        ));
+        self.plan_start(
+            &project.image,
+            &plan.container_name,
+            &plan.data_dir.display().to_string(),
+            plan.port,
+            &plan.credentials,
+            &mut actions,
+        );
+        if self.pgpass {
+            actions.push(PlannedAction::new(
+                "state",
+                format!(
+                    "Add a .pgpass entry for {}:{}",
+                    self.runtime.client_host(),
+                    plan.port
+                ),
+            ));
+        }
+        Ok(actions)
+    }
+
+    pub(super) async fn plan_delete_actions(
+        &self,
+        branch_name: &str,
+    ) -> Result<Vec<PlannedAction>> {
+        let (project, branch) = self.existing_branch(branch_name)?;
+        let mut actions = vec![PlannedAction::new(
+            "docker",
+            format!("Remove container '{}'", branch.container_name),
+        )];
+        actions.extend(
+            self.storage
+                .plan_delete_branch_data(&project, &branch)?
+                .into_iter()
+                .map(|command| PlannedAction::new("storage", command)),
+        );
+        actions.push(PlannedAction::new(
+            "state",
+            format!(
+                "Remove branch '{}' and its snapshots from {}",
+                branch_name,
+                self.data_root.join("state.db").display()
+            ),
+        ));
+        if self.pgpass {
+            actions.push(PlannedAction::new(
+                "state",
+                format!(
+                    "Remove the .pgpass entry for {}:{}",
+                    self.runtime.client_host(),
+                    branch.port
+                ),
+            ));
+        }
+        Ok(actions)
+    }
+
+    pub(super) async fn plan_reset_actions(
+        &self,
+        branch_name: &str,
+        to_snapshot: Option<&str>,
+        keep_snapshots: bool,
+    ) -> Result<Vec<PlannedAction>> {
+        let (project, branch) = self.existing_branch(branch_name)?;
+        let (target, discarded) =
+            self.reset_target(&project, &branch, to_snapshot, keep_snapshots)?;
+        let was_running = branch.state == model::BranchState::Running;
+
+        let mut actions = vec![PlannedAction::new(
+            "docker",
+            format!("Stop container '{}'", branch.container_name),
+        )];
+        if let Some(target) = &target {
+            actions.extend(
+                self.storage
+                    .plan_restore_snapshot(&project, &branch, target)?
+                    .into_iter()
+                    .map(|command| PlannedAction::new("storage", command)),
+            );
+        } else if let Some(command) = self.storage.plan_reset_in_place(&project, &branch)? {
+            actions.push(PlannedAction::new(
+                "storage",
+                format!(
+                    "{} (re-clones from the parent if the rollback fails)",
+                    command
+                ),
+            ));
+        } else {
+            let parent = branch.parent_branch_id.as_ref().and_then(|parent_id| {
+                self.store()
+                    .list_branches(&project.id)
+                    .ok()?
+                    .into_iter()
+                    .find(|b| &b.id == parent_id)
+            });
+            match (parent, self.pinned_snapshot(&branch)?) {
+                (Some(_), Some(snapshot)) => actions.extend(
+                    self.storage
+                        .plan_clone_branch_from_snapshot(
+                            &project,
+                            &snapshot,
+                            &branch.id,
+                            std::path::Path::new(&branch.data_dir),
+                        )?
+                        .into_iter()
+                        .map(|command| PlannedAction::new("storage", command)),
+                ),
+                (Some(parent), None) => {
+                    let commands = self.storage.plan_clone_branch_from_parent(
+                        &project,
+                        &parent,
+                        &branch.id,
+                        std::path::Path::new(&branch.data_dir),
+                    )?;
+                    self.plan_paused(&parent.container_name, commands, &mut actions)
+                        .await?;
+                }
+                (None, _) => actions.push(PlannedAction::new(
+                    "storage",
+                    format!(
+                        "Keep the data: branch '{}' has no parent to reset to",
+                        branch_name
+                    ),
+                )),
+            }
+        }
+
+        if !keep_snapshots {
+            for snapshot in &discarded {
+                actions.push(PlannedAction::new(
+                    "storage",
+                    self.storage.plan_delete_snapshot_data(&project, snapshot)?,
+                ));
+                actions.push(PlannedAction::new(
+                    "state",
+                    format!("Forget snapshot '{}'", snapshot.name),
+                ));
+            }
+        }
+
+        if was_running {
+            self.plan_start(
+                &project.image,
+                &branch.container_name,
+                &branch.data_dir,
+                branch.port,
+                &self.branch_credentials(&branch),
+                &mut actions,
+            );
+        }
+        Ok(actions)
+    }
+
+    pub(super) async fn plan_seed_actions(
+        &self,
+        branch_name: &str,
+        source: &str,
+    ) -> Result<Vec<PlannedAction>> {
+        let (_, branch) = self.existing_branch(branch_name)?;
+        let credentials = self.branch_credentials(&branch);
+        let parsed = seed::parse_source(source)?;
+        Ok(seed::plan_seed(
+            &parsed,
+            &branch.container_name,
+            &credentials.user,
+            &credentials.db,
+            &self.image,
+        ))
+    }
+
+    /// The project to plan against. One that doesn't exist yet is described, not created.
+    async fn planned_project(&self) -> Result<(model::Project, Vec<PlannedAction>)> {
+        if let Some(project) = self.store().get_project_by_name(&self.project_name)? {
+            return Ok((project, Vec::new()));
+        }
+
+        let selection = self.storage.select_for_new_project().await;
+        let project = model::Project {
+            id: Uuid::new_v4().to_string(),
+            name: self.project_name.clone(),
+            owner: self.shared.then(shared::current_user),
+            image: self.image.clone(),
+            storage_backend: selection.backend,
+            storage_config: selection.config,
+            created_at: model::now_epoch_millis(),
+        };
+        let action = PlannedAction::new(
+            "state",
+            format!(
+                "Register project '{}' ({} storage) in {}",
+                project.name,
+                project.storage_backend.as_str(),
+                self.data_root.join("state.db").display()
+            ),
+        );
+        Ok((project, vec![action]))
+    }
+
+    /// Storage commands that copy a branch's data, paused around them if it's running.
+    async fn plan_paused(
+        &self,
+        container_name: &str,
+        commands: Vec<String>,
+        actions: &mut Vec<PlannedAction>,
+    ) -> Result<()> {
+        let running =
+            self.runtime.container_status(container_name).await? == ContainerStatus::Running;
+        if running {
+            actions.push(PlannedAction::new(
+                "docker",
+                format!(
+                    "Pause container '{}' while its data is copied",
+                    container_name
+                ),
+            ));
+        }
+        actions.extend(
+            commands
+                .into_iter()
+                .map(|command| PlannedAction::new("storage", command)),
+        );
+        if running {
+            actions.push(PlannedAction::new(
+                "docker",
+                format!("Unpause container '{}'", container_name),
+            ));
+        }
+        Ok(())
+    }
+
+    fn plan_start(
+        &self,
+        image: &str,
+        container_name: &str,
+        data_dir: &str,
+        port: u16,
+        credentials: &Credentials,
+        actions: &mut Vec<PlannedAction>,
+    ) {
+        actions.push(PlannedAction::new(
+            "docker",
+            match self.runtime.pull_policy() {
+                PullPolicy::IfNotPresent => format!("Pull image '{}' if it isn't present", image),
+                PullPolicy::Always => format!("Pull image '{}'", image),
+                PullPolicy::Never => format!("Use image '{}', which must be present", image),
+            },
+        ));
+        actions.push(PlannedAction::new(
+            "docker",
+            format!(
+                "Start container '{}' on {} with data directory {}",
+                container_name,
+                std::net::SocketAddr::new(self.runtime.bind_address(), port),
+                data_dir
+            ),
+        ));
+        let databases = self.branch_databases(credentials);
+        actions.push(PlannedAction::new(
+            "postgres",
+            format!(
+                "Wait until PostgreSQL is ready, create missing database(s) {} and log in as '{}'",
+                databases.join(", "),
+                credentials.user
+            ),
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+//! `pgbranch guard`: cron jobs that would take the branches' containers
+//! with them. `docker system prune` and `docker container prune` remove
+//! every stopped container, including branches stopped with `pgbranch
+//! stop`, unless a label filter such as `--filter label!=pgbranch.managed`
+//! leaves them out. The data directories survive, since they're bind
+//! mounted, and `pgbranch adopt --pruned` brings the running ones back.
+
+use std::path::{Path, PathBuf};
+
+use super::docker::PROTECTED_LABEL;
+use crate::backends::DoctorCheck;
+
+/// System crontabs, and the folders of scripts cron runs on a schedule.
+const CRON_FILES: [&str; 1] = ["/etc/crontab"];
+const CRON_DIRS: [&str; 5] = [
+    "/etc/cron.d",
+    "/etc/cron.hourly",
+    "/etc/cron.daily",
+    "/etc/cron.weekly",
+    "/etc/cron.monthly",
+];
+
+/// A scheduled command that removes stopped containers without a label filter.
+#[derive(Debug, Clone)]
+struct ScheduledPrune {
+    /// `crontab -l` or the file it's in
+    source: String,
+    line: usize,
+    command: String,
+}
+
+/// Scheduled prunes in the user's crontab and the system's cron files it can read.
+fn scheduled_prunes() -> Vec<ScheduledPrune> {
+    let mut prunes = Vec::new();
+    if let Ok(output) = std::process::Command::new("crontab").arg("-l").output() {
+        if output.status.success() {
+            scan(
+                "crontab -l",
+                &String::from_utf8_lossy(&output.stdout),
+                &mut prunes,
+            );
+        }
+    }
+    let mut files: Vec<PathBuf> = CRON_FILES.iter().map(PathBuf::from).collect();
+    for dir in CRON_DIRS {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut scripts: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        scripts.sort();
+        files.extend(scripts);
+    }
+    for file in files {
+        if let Ok(text) = std::fs::read_to_string(&file) {
+            scan(&file.display().to_string(), &text, &mut prunes);
+        }
+    }
+    prunes
+}
+
+fn scan(source: &str, text: &str, prunes: &mut Vec<ScheduledPrune>) {
+    for (index, line) in text.lines().enumerate() {
+        if removes_branch_containers(line) {
+            prunes.push(ScheduledPrune {
+                source: source.to_string(),
+                line: index + 1,
+                command: line.trim().to_string(),
+            });
+        }
+    }
+}
+
+/// A `docker`/`podman` `system prune` or `container prune` that doesn't
+/// filter on a label. Volume, image and network prunes leave containers alone.
+fn removes_branch_containers(line: &str) -> bool {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return false;
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let prunes = words.windows(3).any(|w| {
+        (Path::new(w[0]).ends_with("docker") || Path::new(w[0]).ends_with("podman"))
+            && matches!(w[1], "system" | "container")
+            && w[2] == "prune"
+    });
+    prunes && !line.contains("label")
+}
+
+/// The guard's findings as doctor checks: whether containers are created
+/// protected, and one failed check per scheduled prune.
+pub fn checks(prune_protection: bool) -> Vec<DoctorCheck> {
+    let mut checks = vec![DoctorCheck {
+        name: "Prune protection".to_string(),
+        available: true,
+        detail: if prune_protection {
+            format!(
+                "on: new branch containers restart with the engine and carry the {} label",
+                PROTECTED_LABEL
+            )
+        } else {
+            "off; set 'prune_protection: true' under 'local' to create branch containers that restart with the engine".to_string()
+        },
+    }];
+
+    let prunes = scheduled_prunes();
+    if prunes.is_empty() {
+        checks.push(DoctorCheck {
+            name: "Scheduled prunes".to_string(),
+            available: true,
+            detail: "No cron job prunes containers without a label filter".to_string(),
+        });
+    }
+    for prune in prunes {
+        checks.push(DoctorCheck {
+            name: "Scheduled prune".to_string(),
+            available: false,
+            detail: format!(
+                "{}:{} removes stopped branch containers: {}; add --filter label!=pgbranch.managed",
+                prune.source, prune.line, prune.command
+            ),
+        });
+    }
+    checks
+}
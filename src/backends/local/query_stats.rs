@@ -0,0 +1,168 @@
+//! Query statistics from pg_stat_statements, for `pgbranch top`. The
+//! extension lives in the `postgres` database so it never shows up in the
+//! app's schema; its view covers every database of the branch anyway.
+
+use anyhow::{Context, Result};
+
+use super::{model, LocalBackend};
+use crate::backends::QueryStat;
+
+const EXTENSION: &str = "pg_stat_statements";
+/// Database holding the extension.
+const STATS_DATABASE: &str = "postgres";
+/// Separates the columns of a result row; query text never contains it.
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+impl LocalBackend {
+    pub(super) async fn query_stats_top(
+        &self,
+        branch_name: &str,
+        limit: usize,
+        reset: bool,
+    ) -> Result<Vec<QueryStat>> {
+        let branch = self.running_branch(branch_name).await?;
+        if !self.query_stats_loaded(&branch).await? {
+            anyhow::bail!(
+                "pg_stat_statements isn't enabled in branch '{}'. Run 'pgbranch top {} --enable' to enable it (restarts the branch)",
+                branch_name,
+                branch_name
+            );
+        }
+        self.stats_query(
+            &branch,
+            &format!("CREATE EXTENSION IF NOT EXISTS {EXTENSION}"),
+        )
+        .await?;
+
+        let credentials = self.branch_credentials(&branch);
+        let databases = self
+            .branch_databases(&credentials)
+            .iter()
+            .map(|db| format!("'{}'", db.replace('\'', "''")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql = format!(
+            "SELECT concat_ws(chr(31), d.datname, s.calls, round(s.total_exec_time::numeric, 2), \
+             round(s.mean_exec_time::numeric, 2), s.rows, regexp_replace(s.query, '\\s+', ' ', 'g')) \
+             FROM {EXTENSION} s JOIN pg_database d ON d.oid = s.dbid \
+             WHERE d.datname IN ({databases}) \
+             ORDER BY s.total_exec_time DESC LIMIT {limit}"
+        );
+        let output = self.stats_query(&branch, &sql).await?;
+        let stats = output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(parse_row)
+            .collect::<Result<Vec<_>>>()?;
+
+        if reset {
+            self.stats_query(&branch, "SELECT pg_stat_statements_reset()")
+                .await?;
+        }
+        Ok(stats)
+    }
+
+    /// Add pg_stat_statements to `shared_preload_libraries` in the branch's
+    /// data directory and restart it, so it counts from now on.
+    pub(super) async fn query_stats_enable(&self, branch_name: &str) -> Result<()> {
+        let branch = self.running_branch(branch_name).await?;
+        let project = self.ensure_project().await?;
+        if !self.query_stats_loaded(&branch).await? {
+            let current = self
+                .stats_query(&branch, "SHOW shared_preload_libraries")
+                .await?;
+            let libraries = match current.trim() {
+                "" => EXTENSION.to_string(),
+                current => format!("{current},{EXTENSION}"),
+            };
+            self.stats_query(
+                &branch,
+                &format!(
+                    "ALTER SYSTEM SET shared_preload_libraries = '{}'",
+                    libraries.replace('\'', "''")
+                ),
+            )
+            .await?;
+
+            self.runtime.stop_branch(&branch.container_name).await?;
+            self.runtime
+                .start_branch(&self.start_spec(&project, &branch))
+                .await?;
+            self.wait_branch_ready(&branch).await?;
+        }
+        self.stats_query(
+            &branch,
+            &format!("CREATE EXTENSION IF NOT EXISTS {EXTENSION}"),
+        )
+        .await?;
+        self.stats_query(&branch, "SELECT pg_stat_statements_reset()")
+            .await?;
+        Ok(())
+    }
+
+    /// Clear counters a clone inherited from its parent, so a branch's
+    /// statistics start at its creation.
+    pub(super) async fn reset_inherited_query_stats(&self, branch: &model::Branch) {
+        match self.query_stats_loaded(branch).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                log::debug!("Could not check pg_stat_statements: {:#}", e);
+                return;
+            }
+        }
+        let reset = async {
+            self.stats_query(
+                branch,
+                &format!("CREATE EXTENSION IF NOT EXISTS {EXTENSION}"),
+            )
+            .await?;
+            self.stats_query(branch, "SELECT pg_stat_statements_reset()")
+                .await
+        };
+        if let Err(e) = reset.await {
+            log::warn!(
+                "Failed to reset the query statistics of branch '{}': {:#}",
+                branch.name,
+                e
+            );
+        }
+    }
+
+    async fn query_stats_loaded(&self, branch: &model::Branch) -> Result<bool> {
+        let libraries = self
+            .stats_query(branch, "SHOW shared_preload_libraries")
+            .await?;
+        Ok(libraries
+            .split(',')
+            .any(|library| library.trim().trim_matches('"') == EXTENSION))
+    }
+
+    async fn stats_query(&self, branch: &model::Branch, sql: &str) -> Result<String> {
+        let credentials = self.branch_credentials(branch);
+        self.runtime
+            .query(
+                &branch.container_name,
+                &credentials.user,
+                STATS_DATABASE,
+                sql,
+            )
+            .await
+    }
+}
+
+fn parse_row(line: &str) -> Result<QueryStat> {
+    let fields: Vec<&str> = line.splitn(6, FIELD_SEPARATOR).collect();
+    let [database, calls, total_ms, mean_ms, rows, query] = fields[..] else {
+        anyhow::bail!("unexpected pg_stat_statements row: {}", line);
+    };
+    let number = || format!("unexpected number in pg_stat_statements row: {}", line);
+    Ok(QueryStat {
+        database: database.to_string(),
+        calls: calls.parse().with_context(number)?,
+        total_ms: total_ms.parse().with_context(number)?,
+        mean_ms: mean_ms.parse().with_context(number)?,
+        rows: rows.parse().with_context(number)?,
+        query: query.to_string(),
+    })
+}
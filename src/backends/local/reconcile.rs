@@ -1,12 +1,28 @@
 use super::docker::{ContainerStatus, DockerRuntime};
-use super::model::{Branch, BranchState};
+use super::model::{Branch, BranchState, Credentials};
 
-/// Determine state changes needed by checking Docker container states.
-/// Returns a list of (branch_id, new_state) pairs.
+/// Drift reasons of containers gone from the engine, which `adopt --pruned`
+/// looks for.
+pub const CONTAINER_REMOVED: &str = "container removed";
+pub const CONTAINER_AND_DATA_REMOVED: &str = "container and data directory removed";
+
+/// A branch whose recorded state or health no longer matches its container.
+pub struct StateChange {
+    pub branch_id: String,
+    pub state: BranchState,
+    /// Why PostgreSQL is unhealthy in a running container; `None` when it's fine
+    pub health: Option<String>,
+    /// What happened to the container outside pgbranch, when the state
+    /// changed from one pgbranch left it in
+    pub drift: Option<String>,
+}
+
+/// Determine state changes needed by checking Docker container states and,
+/// for running containers, whether PostgreSQL accepts connections.
 pub async fn compute_state_changes(
     runtime: &DockerRuntime,
-    branches: Vec<Branch>,
-) -> Vec<(String, BranchState)> {
+    branches: Vec<(Branch, Credentials)>,
+) -> Vec<StateChange> {
     if branches.is_empty() {
         return vec![];
     }
@@ -22,14 +38,24 @@ pub async fn compute_state_changes(
 
         return branches
             .into_iter()
-            .filter(|b| b.state == BranchState::Provisioning)
-            .map(|b| (b.id, BranchState::Stopped))
+            .filter(|(b, _)| b.state == BranchState::Provisioning)
+            .map(|(b, _)| StateChange {
+                branch_id: b.id,
+                state: BranchState::Stopped,
+                health: None,
+                drift: None,
+            })
             .collect();
     }
 
     let mut changes = vec![];
-    for branch in branches {
-        let next_state = match runtime.container_status(&branch.container_name).await {
+    for (branch, credentials) in branches {
+        let status = runtime.container_status(&branch.container_name).await;
+        let drift = match &status {
+            Ok(status) => drift_reason(&branch, status),
+            Err(_) => None,
+        };
+        let next_state = match status {
             Ok(ContainerStatus::Running) => BranchState::Running,
             Ok(ContainerStatus::Paused) => {
                 match runtime.unpause_branch(&branch.container_name).await {
@@ -62,11 +88,49 @@ pub async fn compute_state_changes(
             }
         };
 
-        if next_state != branch.state {
-            changes.push((branch.id, next_state));
+        let health = if next_state == BranchState::Running {
+            runtime
+                .postgres_health(&branch.container_name, &credentials.user, &credentials.db)
+                .await
+        } else {
+            None
+        };
+
+        if next_state != branch.state || health != branch.health {
+            if let Some(reason) = &health {
+                log::warn!("Branch '{}' is unhealthy: {}", branch.name, reason);
+            }
+            changes.push(StateChange {
+                branch_id: branch.id,
+                state: next_state,
+                health,
+                drift: drift.filter(|_| next_state != branch.state),
+            });
         }
     }
 
     log::info!("Reconciliation completed: {} state changes", changes.len());
     changes
 }
+
+/// Why a branch pgbranch left running or stopped has a container in another
+/// state, e.g. one stopped by `docker stop` or removed by `docker system prune`.
+fn drift_reason(branch: &Branch, status: &ContainerStatus) -> Option<String> {
+    match (branch.state, status) {
+        (BranchState::Running, ContainerStatus::Exited) => Some("container exited".to_string()),
+        (BranchState::Running | BranchState::Stopped, ContainerStatus::NotFound) => {
+            Some(if std::path::Path::new(&branch.data_dir).exists() {
+                CONTAINER_REMOVED.to_string()
+            } else {
+                CONTAINER_AND_DATA_REMOVED.to_string()
+            })
+        }
+        (BranchState::Running, ContainerStatus::Other(state)) => {
+            Some(format!("container is {}", state))
+        }
+        (BranchState::Stopped, ContainerStatus::Running | ContainerStatus::Paused) => {
+            Some("container started outside pgbranch".to_string())
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,60 @@
+//! Schema-only branches: a clone of the parent with every table emptied, for
+//! testing migrations without waiting on the data.
+
+use anyhow::Result;
+
+use super::LocalBackend;
+
+/// Tables that record which migrations ran. They keep their rows, so the
+/// migration tool only applies the ones that are new on this branch.
+pub const KEPT_TABLES: &[&str] = &[
+    "schema_migrations",
+    "ar_internal_metadata",
+    "django_migrations",
+    "alembic_version",
+    "_prisma_migrations",
+    "flyway_schema_history",
+    "knex_migrations",
+    "knex_migrations_lock",
+    "goose_db_version",
+    "_sqlx_migrations",
+    "__diesel_schema_migrations",
+    "__EFMigrationsHistory",
+];
+
+impl LocalBackend {
+    /// Truncate every user table of the branch's databases, except the
+    /// migration history. The branch must be running.
+    pub(super) async fn truncate_branch_data(&self, branch_name: &str) -> Result<()> {
+        let (_, branch) = self.existing_branch(branch_name)?;
+        let credentials = self.branch_credentials(&branch);
+
+        let sql = truncate_sql();
+        for database in self.branch_databases(&credentials) {
+            self.runtime
+                .query(&branch.container_name, &credentials.user, database, &sql)
+                .await?;
+            log::debug!("Emptied the tables of '{}' in '{}'", database, branch_name);
+        }
+        Ok(())
+    }
+}
+
+fn truncate_sql() -> String {
+    let kept = KEPT_TABLES
+        .iter()
+        .map(|table| format!("'{}'", table))
+        .collect::<Vec<_>>()
+        .join(", ");
+    // One TRUNCATE for all tables, so foreign keys between them don't get in the way
+    format!(
+        "DO $$ DECLARE tables text; BEGIN \
+         SELECT string_agg(format('%I.%I', schemaname, tablename), ', ') INTO tables \
+         FROM pg_tables \
+         WHERE schemaname NOT IN ('pg_catalog', 'information_schema') \
+         AND schemaname NOT LIKE 'pg_toast%' \
+         AND tablename NOT IN ({kept}); \
+         IF tables IS NOT NULL THEN EXECUTE 'TRUNCATE ' || tables || ' RESTART IDENTITY'; END IF; \
+         END $$"
+    )
+}
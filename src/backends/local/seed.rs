@@ -8,6 +8,10 @@ use bollard::Docker;
 use futures_util::TryStreamExt;
 use std::path::PathBuf;
 
+use crate::backends::PlannedAction;
+use crate::config::SigningConfig;
+use crate::signing::{self, Verification};
+
 #[derive(Debug)]
 pub enum SeedSource {
     PostgresUrl(url::Url),
@@ -21,6 +25,9 @@ pub fn parse_source(from: &str) -> Result<SeedSource> {
             url::Url::parse(from).with_context(|| format!("Invalid PostgreSQL URL: {}", from))?;
         Ok(SeedSource::PostgresUrl(url))
     } else if let Some(without_scheme) = from.strip_prefix("s3://") {
+        if !cfg!(feature = "seed-s3") {
+            return Err(s3_not_compiled());
+        }
         let (bucket, key) = without_scheme
             .split_once('/')
             .ok_or_else(|| anyhow!("Invalid S3 URL: expected s3://bucket/key"))?;
@@ -37,6 +44,64 @@ pub fn parse_source(from: &str) -> Result<SeedSource> {
     }
 }
 
+/// The steps `seed_branch` takes, for `--dry-run`.
+pub fn plan_seed(
+    source: &SeedSource,
+    container_name: &str,
+    pg_user: &str,
+    pg_db: &str,
+    image: &str,
+) -> Vec<PlannedAction> {
+    let restore = |tool: &str| {
+        PlannedAction::new(
+            "postgres",
+            format!("{tool} -U {pg_user} -d {pg_db} in container '{container_name}'"),
+        )
+    };
+    match source {
+        SeedSource::PostgresUrl(url) => {
+            let mut shown = url.clone();
+            if shown.password().is_some() {
+                let _ = shown.set_password(Some("***"));
+            }
+            vec![
+                PlannedAction::new(
+                    "docker",
+                    format!("pg_dump -Fc {shown} in a temporary '{image}' container"),
+                ),
+                PlannedAction::new(
+                    "docker",
+                    format!("Copy the dump into container '{container_name}'"),
+                ),
+                restore("pg_restore --no-owner"),
+            ]
+        }
+        SeedSource::LocalFile(path) => vec![
+            PlannedAction::new(
+                "docker",
+                format!("Copy {} into container '{container_name}'", path.display()),
+            ),
+            restore(if is_plain_sql(path) {
+                "psql -f"
+            } else {
+                "pg_restore --no-owner"
+            }),
+        ],
+        SeedSource::S3Object { bucket, key } => vec![
+            PlannedAction::new("api", format!("Download s3://{bucket}/{key}")),
+            PlannedAction::new(
+                "docker",
+                format!("Copy the download into container '{container_name}'"),
+            ),
+            restore(if is_plain_sql(std::path::Path::new(key)) {
+                "psql -f"
+            } else {
+                "pg_restore --no-owner"
+            }),
+        ],
+    }
+}
+
 pub async fn seed_branch(
     docker: &Docker,
     source: &SeedSource,
@@ -44,16 +109,17 @@ pub async fn seed_branch(
     pg_user: &str,
     pg_db: &str,
     image: &str,
+    signing: Option<&SigningConfig>,
 ) -> Result<()> {
     match source {
         SeedSource::PostgresUrl(url) => {
             seed_from_postgres(docker, url, container_name, pg_user, pg_db, image).await
         }
         SeedSource::LocalFile(path) => {
-            seed_from_file(docker, path, container_name, pg_user, pg_db).await
+            seed_from_file(docker, path, container_name, pg_user, pg_db, signing).await
         }
         SeedSource::S3Object { bucket, key } => {
-            seed_from_s3(docker, bucket, key, container_name, pg_user, pg_db, image).await
+            seed_from_s3(docker, bucket, key, container_name, pg_user, pg_db, signing).await
         }
     }
 }
@@ -120,7 +186,7 @@ async fn docker_exec(docker: &Docker, container_name: &str, cmd: &[&str]) -> Res
 }
 
 /// Upload a file into a container at the given path using the Docker API.
-async fn upload_file_to_container(
+pub(super) async fn upload_file_to_container(
     docker: &Docker,
     container_name: &str,
     container_dir: &str,
@@ -315,6 +381,7 @@ async fn seed_from_file(
     container_name: &str,
     pg_user: &str,
     pg_db: &str,
+    signing: Option<&SigningConfig>,
 ) -> Result<()> {
     let abs_path = if path.is_absolute() {
         path.to_path_buf()
@@ -325,6 +392,9 @@ async fn seed_from_file(
     if !abs_path.exists() {
         anyhow::bail!("Seed file not found: {}", abs_path.display());
     }
+    if let Verification::Verified { signer } = signing::verify(signing, &abs_path)? {
+        eprintln!("Verified the signature by {}", signer);
+    }
 
     let container_path = "/tmp/pgbranch_seed_dump";
 
@@ -382,6 +452,7 @@ async fn seed_from_file(
     Ok(())
 }
 
+#[cfg(feature = "seed-s3")]
 async fn seed_from_s3(
     docker: &Docker,
     bucket: &str,
@@ -389,20 +460,9 @@ async fn seed_from_s3(
     container_name: &str,
     pg_user: &str,
     pg_db: &str,
-    _image: &str,
+    signing: Option<&SigningConfig>,
 ) -> Result<()> {
-    let region = std::env::var("AWS_DEFAULT_REGION")
-        .or_else(|_| std::env::var("AWS_REGION"))
-        .unwrap_or_else(|_| "us-east-1".to_string());
-
-    let s3_bucket = s3::Bucket::new(
-        bucket,
-        s3::Region::Custom {
-            region: region.clone(),
-            endpoint: format!("https://s3.{}.amazonaws.com", region),
-        },
-        s3::creds::Credentials::from_env()?,
-    )?;
+    let s3_bucket = s3_bucket(bucket)?;
 
     let temp_dir = tempfile::tempdir().context("Failed to create temp directory")?;
 
@@ -424,6 +484,57 @@ async fn seed_from_s3(
         .await
         .context("Failed to write S3 object to temp file")?;
 
+    // The signature, if the object has one next to it, goes next to the download
+    if let Some(signing) = signing {
+        let signature = signing::signature_path(signing, &temp_path);
+        let extension = signature.extension().unwrap_or_default().to_string_lossy();
+        if let Ok(response) = s3_bucket
+            .get_object(&format!("{}.{}", key, extension))
+            .await
+        {
+            if response.status_code() == 200 {
+                tokio::fs::write(&signature, response.bytes())
+                    .await
+                    .context("Failed to write the S3 object's signature to a temp file")?;
+            }
+        }
+    }
+
     // Delegate to file-based seeding
-    seed_from_file(docker, &temp_path, container_name, pg_user, pg_db).await
+    seed_from_file(docker, &temp_path, container_name, pg_user, pg_db, signing).await
+}
+
+/// A bucket in the region of `AWS_DEFAULT_REGION` or `AWS_REGION`, with
+/// credentials from the environment.
+#[cfg(feature = "seed-s3")]
+pub(crate) fn s3_bucket(bucket: &str) -> Result<Box<s3::Bucket>> {
+    let region = std::env::var("AWS_DEFAULT_REGION")
+        .or_else(|_| std::env::var("AWS_REGION"))
+        .unwrap_or_else(|_| "us-east-1".to_string());
+
+    Ok(s3::Bucket::new(
+        bucket,
+        s3::Region::Custom {
+            region: region.clone(),
+            endpoint: format!("https://s3.{}.amazonaws.com", region),
+        },
+        s3::creds::Credentials::from_env()?,
+    )?)
+}
+
+#[cfg(not(feature = "seed-s3"))]
+async fn seed_from_s3(
+    _docker: &Docker,
+    _bucket: &str,
+    _key: &str,
+    _container_name: &str,
+    _pg_user: &str,
+    _pg_db: &str,
+    _signing: Option<&SigningConfig>,
+) -> Result<()> {
+    Err(s3_not_compiled())
+}
+
+fn s3_not_compiled() -> anyhow::Error {
+    anyhow!("S3 seeding not compiled. Rebuild with --features seed-s3")
 }
@@ -0,0 +1,221 @@
+use std::future::Future;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::LocalBackend;
+use crate::backends::{DatabaseBranchingBackend, DestroyScope};
+use crate::config::{Config, LocalBackendConfig};
+
+const PARENT: &str = "main";
+const CHILD: &str = "child";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// Outcome of one `pgbranch selftest` step.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelftestStep {
+    pub name: &'static str,
+    pub status: StepStatus,
+    pub elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a `pgbranch selftest` run.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelftestReport {
+    pub project: String,
+    pub passed: bool,
+    pub steps: Vec<SelftestStep>,
+}
+
+/// Run the local pipeline end to end in a throwaway project: create a branch,
+/// clone it, write to the clone, check the parent didn't see it, reset and delete.
+/// The project is destroyed afterwards whether or not the steps passed.
+///
+/// `on_step` is called as each step finishes, so progress can be printed live.
+pub async fn run(
+    config: &Config,
+    local_config: Option<&LocalBackendConfig>,
+    mut on_step: impl FnMut(&SelftestStep),
+) -> Result<SelftestReport> {
+    // Same storage, image and ports as real branches, but no side effects outside the sandbox
+    let mut config = config.clone();
+    config.notifications = None;
+    config.audit = None;
+    let local_config = local_config.map(|c| LocalBackendConfig {
+        pgpass: None,
+        ..c.clone()
+    });
+
+    let project = format!(
+        "pgbranch-selftest-{}",
+        &Uuid::new_v4().simple().to_string()[..8]
+    );
+
+    let mut steps = Steps {
+        steps: Vec::new(),
+        failed: false,
+        on_step: &mut on_step,
+    };
+
+    let Some(backend) = steps
+        .run(
+            "connect to Docker",
+            LocalBackend::new(&project, &config, local_config.as_ref()),
+        )
+        .await
+    else {
+        return Ok(SelftestReport {
+            project,
+            passed: false,
+            steps: steps.steps,
+        });
+    };
+
+    steps
+        .run("create branch", async {
+            backend.create_branch(PARENT, None).await?;
+            Ok(())
+        })
+        .await;
+    steps
+        .run("write data", async {
+            backend
+                .query_branch(
+                    PARENT,
+                    "CREATE TABLE pgbranch_selftest (id int); \
+                     INSERT INTO pgbranch_selftest VALUES (1)",
+                )
+                .await?;
+            Ok(())
+        })
+        .await;
+    steps
+        .run("clone child branch", async {
+            backend.create_branch(CHILD, Some(PARENT)).await?;
+            expect_rows(&backend, CHILD, 1, "the clone is missing the parent's data").await
+        })
+        .await;
+    steps
+        .run("write to child", async {
+            backend
+                .query_branch(CHILD, "INSERT INTO pgbranch_selftest VALUES (2)")
+                .await?;
+            expect_rows(&backend, CHILD, 2, "the child's write didn't stick").await
+        })
+        .await;
+    steps
+        .run("verify parent isolation", async {
+            expect_rows(
+                &backend,
+                PARENT,
+                1,
+                "the child's write leaked into the parent",
+            )
+            .await
+        })
+        .await;
+    steps
+        .run("reset child", async {
+            backend.reset_branch(CHILD, None, false).await?;
+            expect_rows(&backend, CHILD, 1, "the reset kept the child's write").await
+        })
+        .await;
+    steps
+        .run("delete branches", async {
+            backend.delete_branch(CHILD).await?;
+            backend.delete_branch(PARENT).await?;
+            if backend.branch_exists(CHILD).await? || backend.branch_exists(PARENT).await? {
+                return Err(anyhow!("branches are still recorded after deleting them"));
+            }
+            Ok(())
+        })
+        .await;
+
+    // Whatever a failed step left behind still has to go
+    let passed = !steps.failed;
+    if backend.destroy_preview().await?.is_some() {
+        steps.failed = false;
+        steps
+            .run("clean up sandbox", async {
+                backend.destroy_project(DestroyScope::All).await?;
+                Ok(())
+            })
+            .await;
+    }
+
+    Ok(SelftestReport {
+        passed: passed && !steps.failed,
+        project,
+        steps: steps.steps,
+    })
+}
+
+/// Runs steps in order, skipping the rest once one fails.
+struct Steps<'a, F> {
+    steps: Vec<SelftestStep>,
+    failed: bool,
+    on_step: &'a mut F,
+}
+
+impl<F: FnMut(&SelftestStep)> Steps<'_, F> {
+    async fn run<T>(
+        &mut self,
+        name: &'static str,
+        step: impl Future<Output = Result<T>>,
+    ) -> Option<T> {
+        let started = Instant::now();
+        let (output, status, error) = if self.failed {
+            (None, StepStatus::Skipped, None)
+        } else {
+            match step.await {
+                Ok(output) => (Some(output), StepStatus::Passed, None),
+                Err(e) => {
+                    self.failed = true;
+                    (None, StepStatus::Failed, Some(format!("{:#}", e)))
+                }
+            }
+        };
+        let step = SelftestStep {
+            name,
+            status,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            error,
+        };
+        (self.on_step)(&step);
+        self.steps.push(step);
+        output
+    }
+}
+
+/// Check the number of rows the selftest table has in `branch_name`.
+async fn expect_rows(
+    backend: &LocalBackend,
+    branch_name: &str,
+    expected: u64,
+    problem: &str,
+) -> Result<()> {
+    let count = backend
+        .query_branch(branch_name, "SELECT count(*) FROM pgbranch_selftest")
+        .await?;
+    if count != expected.to_string() {
+        return Err(anyhow!(
+            "{}: expected {} row(s) in '{}', found {}",
+            problem,
+            expected,
+            branch_name,
+            count
+        ));
+    }
+    Ok(())
+}
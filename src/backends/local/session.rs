@@ -0,0 +1,102 @@
+//! `pgbranch exec` and its session scripts. SQL run with `--record` is
+//! appended to a script per branch, kept outside the branch's data so resets
+//! don't lose it, which `pgbranch replay` can run against another branch.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use uuid::Uuid;
+
+use super::{model, seed, LocalBackend};
+use crate::errors::coded;
+
+impl LocalBackend {
+    pub(super) async fn exec_in_branch(
+        &self,
+        branch_name: &str,
+        sql: &str,
+        record: bool,
+    ) -> Result<String> {
+        let branch = self.running_branch(branch_name).await?;
+        let credentials = self.branch_credentials(&branch);
+
+        let filename = format!("pgbranch_exec_{}.sql", Uuid::new_v4().simple());
+        seed::upload_file_to_container(
+            self.runtime.client(),
+            &branch.container_name,
+            "/tmp",
+            &filename,
+            sql.as_bytes(),
+        )
+        .await?;
+        let output = self
+            .runtime
+            .run_sql_file(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                &format!("/tmp/{filename}"),
+            )
+            .await
+            .with_context(|| format!("SQL failed in branch '{}'", branch_name))?;
+        self.touch_branch(&branch);
+
+        if record {
+            self.record_session(&branch, sql)?;
+        }
+        Ok(output)
+    }
+
+    /// `sessions/<branch id>.sql` in the project's directory.
+    pub(super) fn session_path(&self, branch: &model::Branch) -> PathBuf {
+        self.data_root
+            .join("projects")
+            .join(&branch.project_id)
+            .join("sessions")
+            .join(format!("{}.sql", branch.id))
+    }
+
+    pub(super) async fn branch_session_path(&self, branch_name: &str) -> Result<PathBuf> {
+        let project = self.ensure_project().await?;
+        let branch = self
+            .store()
+            .get_branch_by_name(&project.id, branch_name)?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        Ok(self.session_path(&branch))
+    }
+
+    fn record_session(&self, branch: &model::Branch, sql: &str) -> Result<()> {
+        let path = self.session_path(branch);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        writeln!(
+            file,
+            "-- {} in branch '{}'\n{}\n",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            branch.name,
+            terminated(sql)
+        )
+        .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// `sql` with a closing semicolon, so the next recorded statement doesn't
+/// run into it. psql meta-commands end at the line break instead.
+fn terminated(sql: &str) -> String {
+    let sql = sql.trim_end();
+    let last_line = sql.lines().last().unwrap_or("").trim_start();
+    if sql.ends_with(';') || last_line.starts_with('\\') || last_line.starts_with("--") {
+        sql.to_string()
+    } else {
+        format!("{sql};")
+    }
+}
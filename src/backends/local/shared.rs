@@ -0,0 +1,164 @@
+//! Shared mode: several users of one host keep their projects in a common
+//! `data_root` and state database, owned by a group they all belong to.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::backends::DoctorCheck;
+
+/// `data_root` in shared mode unless one is configured.
+pub const SHARED_DATA_ROOT: &str = "/var/lib/pgbranch";
+
+/// Group-writable with setgid, so new files keep the directory's group.
+#[cfg(unix)]
+const SHARED_DIR_MODE: u32 = 0o2775;
+
+/// Login name of the user running pgbranch.
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|u| !u.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Create a shared directory that other members of its group can write to.
+/// Directories that already exist are left alone; `pgbranch doctor` checks them.
+pub fn create_dir(path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(path).with_context(|| {
+        format!(
+            "failed to create shared directory {} (see 'pgbranch doctor' for the setup)",
+            path.display()
+        )
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(SHARED_DIR_MODE))
+            .with_context(|| format!("failed to make {} group-writable", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Let the rest of the group write to the state database, if we own it.
+pub fn share_file(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return;
+        };
+        let mode = metadata.mode() & 0o7777;
+        if mode & 0o060 != 0o060 && current_uid() == Some(metadata.uid()) {
+            let shared = std::fs::Permissions::from_mode(mode | 0o060);
+            if let Err(e) = std::fs::set_permissions(path, shared) {
+                log::warn!("Failed to make {} group-writable: {}", path.display(), e);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Checks that every user of the host can work in `data_root`, with the
+/// commands that fix whatever isn't set up.
+pub fn doctor_checks(data_root: &Path) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    let root = data_root.display();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let setup = format!(
+            "sudo groupadd -f pgbranch && sudo install -d -m 2775 -g pgbranch {root} && sudo usermod -aG pgbranch $USER"
+        );
+        let Ok(metadata) = std::fs::metadata(data_root) else {
+            checks.push(DoctorCheck {
+                name: "Shared data root".to_string(),
+                available: false,
+                detail: format!("{root} does not exist; create it with: {setup}"),
+            });
+            return checks;
+        };
+        let mode = metadata.mode();
+        checks.push(DoctorCheck {
+            name: "Shared data root".to_string(),
+            available: mode & 0o2070 == 0o2070,
+            detail: if mode & 0o2070 == 0o2070 {
+                format!("{root} is group-writable with setgid")
+            } else {
+                format!(
+                    "{root} has mode {:o}; other users can't add projects. Fix with: sudo chmod 2775 {root}",
+                    mode & 0o7777
+                )
+            },
+        });
+
+        let in_group = current_groups().contains(&metadata.gid());
+        checks.push(DoctorCheck {
+            name: "Shared group".to_string(),
+            available: in_group,
+            detail: if in_group {
+                format!("{} belongs to the group of {root}", current_user())
+            } else {
+                format!(
+                    "{} is not in the group of {root} (gid {}). Fix with: sudo usermod -aG <group> $USER, then log in again",
+                    current_user(),
+                    metadata.gid()
+                )
+            },
+        });
+
+        let state_db = data_root.join("state.db");
+        if let Ok(metadata) = std::fs::metadata(&state_db) {
+            let writable = metadata.mode() & 0o060 == 0o060;
+            checks.push(DoctorCheck {
+                name: "Shared state database".to_string(),
+                available: writable,
+                detail: if writable {
+                    format!("{} is group-writable", state_db.display())
+                } else {
+                    format!(
+                        "{} isn't group-writable. Fix with: sudo chmod g+rw {}",
+                        state_db.display(),
+                        state_db.display()
+                    )
+                },
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    checks.push(DoctorCheck {
+        name: "Shared data root".to_string(),
+        available: data_root.exists(),
+        detail: format!("{root}; shared mode permissions are only checked on Unix"),
+    });
+
+    checks
+}
+
+#[cfg(unix)]
+fn current_uid() -> Option<u32> {
+    let output = std::process::Command::new("id").arg("-u").output().ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn current_groups() -> Vec<u32> {
+    std::process::Command::new("id")
+        .arg("-G")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .filter_map(|gid| gid.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
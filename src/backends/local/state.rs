@@ -1,13 +1,45 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
 
-use super::model::{now_epoch_millis, Branch, BranchState, Project, StorageBackend};
+use super::model::{
+    now_epoch_millis, Branch, BranchState, Credentials, Project, Snapshot, StorageBackend,
+};
+
+/// Aggregated timings of one operation for `pgbranch stats`.
+#[derive(Debug, Serialize)]
+pub struct UsageSummary {
+    pub project_name: String,
+    pub operation: String,
+    pub storage_backend: Option<String>,
+    pub detail: Option<String>,
+    pub count: i64,
+    pub avg_ms: i64,
+    pub min_ms: i64,
+    pub max_ms: i64,
+    pub total_ms: i64,
+    pub first_at: i64,
+}
+
+/// A branch whose container changed state outside pgbranch, as reconciling
+/// found it.
+#[derive(Debug)]
+pub struct Drift {
+    pub branch_name: String,
+    pub from_state: BranchState,
+    pub to_state: BranchState,
+    pub reason: String,
+    /// When reconciling noticed it, not when it happened
+    pub detected_at: i64,
+}
 
 #[derive(Debug)]
 pub struct NewProject {
     pub name: String,
+    pub owner: Option<String>,
     pub image: String,
     pub storage_backend: StorageBackend,
     pub storage_config: Option<String>,
@@ -24,72 +56,287 @@ pub struct NewBranch {
     pub container_name: String,
     pub port: u16,
     pub storage_metadata: Option<String>,
+    pub credentials: Credentials,
+    /// Snapshot of the parent the branch was cloned from, if pinned to one
+    pub parent_snapshot_id: Option<String>,
+}
+
+/// The project/branch registry the local backend keeps. `Store`, a SQLite
+/// database under the data root, is the default; `state_store` in the local
+/// backend's settings swaps in a JSON document shared by a team.
+pub trait StateStore: Send {
+    fn list_projects(&self) -> anyhow::Result<Vec<Project>>;
+    fn get_project_by_name(&self, name: &str) -> anyhow::Result<Option<Project>>;
+    fn create_project(&self, input: NewProject) -> anyhow::Result<Project>;
+    /// One past the highest port any branch uses, or the first of the default range.
+    fn next_port(&self) -> anyhow::Result<u16>;
+    /// A project's branches, newest first.
+    fn list_branches(&self, project_id: &str) -> anyhow::Result<Vec<Branch>>;
+    fn list_all_branches(&self) -> anyhow::Result<Vec<Branch>>;
+    fn list_all_snapshots(&self) -> anyhow::Result<Vec<Snapshot>>;
+    fn get_branch_by_name(
+        &self,
+        project_id: &str,
+        branch_name: &str,
+    ) -> anyhow::Result<Option<Branch>>;
+    fn create_branch(&self, input: NewBranch) -> anyhow::Result<Branch>;
+    fn update_branch_state(&self, branch_id: &str, state: BranchState) -> anyhow::Result<()>;
+    fn update_branch_health(&self, branch_id: &str, health: Option<&str>) -> anyhow::Result<()>;
+    fn record_drift(
+        &self,
+        branch: &Branch,
+        to_state: BranchState,
+        reason: &str,
+    ) -> anyhow::Result<()>;
+    /// Drift in a project's branches noticed since `since` (epoch millis), oldest first.
+    fn list_drift(&self, project_id: &str, since: i64) -> anyhow::Result<Vec<Drift>>;
+    fn update_branch_storage_metadata(
+        &self,
+        branch_id: &str,
+        storage_metadata: Option<&str>,
+    ) -> anyhow::Result<()>;
+    fn update_branch_credentials(
+        &self,
+        branch_id: &str,
+        credentials: &Credentials,
+    ) -> anyhow::Result<()>;
+    fn update_branch_pg_settings(
+        &self,
+        branch_id: &str,
+        settings: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()>;
+    /// Record that a branch was just switched to, connected to or started.
+    fn touch_branch(&self, branch_id: &str) -> anyhow::Result<()>;
+    fn update_branch_port(&self, branch_id: &str, port: u16) -> anyhow::Result<()>;
+    fn update_branch_container_name(
+        &self,
+        branch_id: &str,
+        container_name: &str,
+    ) -> anyhow::Result<()>;
+    /// Delete a branch with its snapshots; branches cloned from it keep
+    /// their data but lose the link to their parent.
+    fn delete_branch(&self, branch_id: &str) -> anyhow::Result<()>;
+    fn create_snapshot(
+        &self,
+        id: &str,
+        branch_id: &str,
+        name: &str,
+        storage_metadata: Option<&str>,
+    ) -> anyhow::Result<Snapshot>;
+    /// Snapshots of a branch, oldest first.
+    fn list_snapshots(&self, branch_id: &str) -> anyhow::Result<Vec<Snapshot>>;
+    fn get_snapshot_by_name(&self, branch_id: &str, name: &str)
+        -> anyhow::Result<Option<Snapshot>>;
+    fn get_snapshot(&self, snapshot_id: &str) -> anyhow::Result<Option<Snapshot>>;
+    /// Branches cloned from the snapshot with `--from <parent>@<snapshot>`.
+    fn list_branches_pinned_to(&self, snapshot_id: &str) -> anyhow::Result<Vec<String>>;
+    fn delete_snapshot(&self, snapshot_id: &str) -> anyhow::Result<()>;
+    /// Record how long an operation took. Kept by project name so stats survive `destroy`.
+    fn record_usage(
+        &self,
+        project_name: &str,
+        operation: &str,
+        storage_backend: Option<&str>,
+        detail: Option<&str>,
+        duration_ms: i64,
+    ) -> anyhow::Result<()>;
+    fn usage_summary(&self, project_name: Option<&str>) -> anyhow::Result<Vec<UsageSummary>>;
+    /// Put back records written to a destroy manifest, all or nothing: the
+    /// project (unless it's still there), its branches parents first, their
+    /// snapshots, then which snapshots branches were created from.
+    fn restore_records(
+        &self,
+        project: Option<&Project>,
+        branches: &[Branch],
+        snapshots: &[Snapshot],
+    ) -> anyhow::Result<()>;
+    /// Delete a project with everything recorded about its branches.
+    fn delete_project(&self, project_id: &str) -> anyhow::Result<()>;
 }
 
 pub struct Store {
     conn: Connection,
 }
 
+/// Ordered schema migrations; the schema version is the number applied so far.
+/// Never edit or reorder an entry once released, only append new ones.
+const MIGRATIONS: &[fn(&Connection) -> anyhow::Result<()>] = &[
+    migrate_v1,
+    migrate_v2,
+    migrate_v3,
+    migrate_v4,
+    migrate_v5,
+    migrate_v6,
+    migrate_v7,
+    migrate_v8,
+    migrate_v9,
+    migrate_v10,
+];
+
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Port of the first branch recorded.
+pub(super) const FIRST_BRANCH_PORT: u16 = 55432;
+
+/// How long drift events are kept.
+pub(super) const DRIFT_RETENTION_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
 impl Store {
     pub fn open(path: &Path) -> anyhow::Result<Self> {
         let conn = Connection::open(path)
             .with_context(|| format!("failed to open SQLite db at {}", path.display()))?;
 
         let store = Self { conn };
-        store.init_schema()?;
+        store.migrate(path)?;
         Ok(store)
     }
 
-    fn init_schema(&self) -> anyhow::Result<()> {
+    pub fn schema_version(&self) -> anyhow::Result<i64> {
+        let has_table: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_table {
+            return Ok(0);
+        }
+        let version: Option<i64> =
+            self.conn
+                .query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(version.unwrap_or(0))
+    }
+
+    fn migrate(&self, path: &Path) -> anyhow::Result<()> {
         self.conn
-            .execute_batch(
-                r#"
-            PRAGMA foreign_keys = ON;
-
-            CREATE TABLE IF NOT EXISTS projects (
-              id TEXT PRIMARY KEY,
-              name TEXT NOT NULL UNIQUE,
-              image TEXT NOT NULL,
-              storage_backend TEXT NOT NULL DEFAULT 'copy',
-              storage_config TEXT NULL,
-              created_at INTEGER NOT NULL
+            .execute_batch("PRAGMA foreign_keys = ON;")
+            .context("failed to enable foreign keys")?;
+
+        let current = self.schema_version()?;
+        if current > SCHEMA_VERSION {
+            anyhow::bail!(
+                "state database {} has schema version {} but this pgbranch only supports up to {}; upgrade pgbranch",
+                path.display(),
+                current,
+                SCHEMA_VERSION
             );
+        }
+        if current == SCHEMA_VERSION {
+            return Ok(());
+        }
 
-            CREATE TABLE IF NOT EXISTS branches (
-              id TEXT PRIMARY KEY,
-              project_id TEXT NOT NULL,
-              name TEXT NOT NULL,
-              parent_branch_id TEXT NULL,
-              state TEXT NOT NULL,
-              data_dir TEXT NOT NULL,
-              container_name TEXT NOT NULL,
-              port INTEGER NOT NULL,
-              storage_metadata TEXT NULL,
-              created_at INTEGER NOT NULL,
-              UNIQUE(project_id, name),
-              FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE,
-              FOREIGN KEY(parent_branch_id) REFERENCES branches(id) ON DELETE SET NULL
+        // Databases created before versioning report 0 but still hold data worth keeping
+        let has_data: bool = self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'projects')",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_data {
+            let backup = backup_path(path, &format!("pre-v{SCHEMA_VERSION}"));
+            self.backup_to(&backup)?;
+            log::info!(
+                "Backed up state database to {} before migrating to schema version {}",
+                backup.display(),
+                SCHEMA_VERSION
             );
-            "#,
+        }
+
+        self.conn
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_version (
+                   version INTEGER PRIMARY KEY,
+                   applied_at INTEGER NOT NULL
+                 );",
             )
-            .context("failed to apply SQLite schema")?;
+            .context("failed to create schema_version table")?;
 
-        ensure_column(
-            &self.conn,
-            "projects",
-            "storage_backend",
-            "TEXT NOT NULL DEFAULT 'copy'",
-        )?;
-        ensure_column(&self.conn, "projects", "storage_config", "TEXT NULL")?;
-        ensure_column(&self.conn, "branches", "storage_metadata", "TEXT NULL")?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+            let version = index as i64 + 1;
+            let tx = self.conn.unchecked_transaction()?;
+            migration(&tx)
+                .with_context(|| format!("failed to apply state schema migration {version}"))?;
+            tx.execute(
+                "INSERT INTO schema_version (version, applied_at) VALUES (?1, ?2)",
+                rusqlite::params![version, now_epoch_millis()],
+            )?;
+            tx.commit()?;
+        }
 
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn list_projects(&self) -> anyhow::Result<Vec<Project>> {
+    /// Write a consistent copy of the database to `dest`, which must not exist yet.
+    pub fn backup_to(&self, dest: &Path) -> anyhow::Result<()> {
+        if dest.exists() {
+            anyhow::bail!("backup destination {} already exists", dest.display());
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        self.conn
+            .execute("VACUUM INTO ?1", [dest.to_string_lossy()])
+            .with_context(|| format!("failed to back up state database to {}", dest.display()))?;
+        Ok(())
+    }
+
+    /// Replace the database at `path` with `source`, backing up the current one first.
+    /// Returns the path of that backup, if there was a database to back up.
+    pub fn restore(path: &Path, source: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let candidate = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open {}", source.display()))?;
+        let integrity: String = candidate
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .with_context(|| format!("{} is not a SQLite database", source.display()))?;
+        if integrity != "ok" {
+            anyhow::bail!("{} failed integrity check: {}", source.display(), integrity);
+        }
+        let candidate = Self { conn: candidate };
+        if candidate.schema_version()? > SCHEMA_VERSION {
+            anyhow::bail!(
+                "{} was written by a newer pgbranch (schema version {})",
+                source.display(),
+                candidate.schema_version()?
+            );
+        }
+        let has_projects: bool = candidate.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'projects')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_projects {
+            anyhow::bail!("{} is not a pgbranch state database", source.display());
+        }
+        drop(candidate);
+
+        let previous = if path.exists() {
+            let backup = backup_path(path, "pre-restore");
+            Self::open(path)?.backup_to(&backup)?;
+            Some(backup)
+        } else {
+            None
+        };
+
+        std::fs::copy(source, path).with_context(|| {
+            format!("failed to copy {} to {}", source.display(), path.display())
+        })?;
+        for suffix in ["-wal", "-shm"] {
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(suffix);
+            let _ = std::fs::remove_file(PathBuf::from(sidecar));
+        }
+
+        // Bring an older backup up to the current schema
+        Self::open(path)?;
+        Ok(previous)
+    }
+}
+
+impl StateStore for Store {
+    fn list_projects(&self) -> anyhow::Result<Vec<Project>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, name, image, storage_backend, storage_config, created_at FROM projects ORDER BY created_at DESC"
+            "SELECT id, name, image, storage_backend, storage_config, created_at, owner FROM projects ORDER BY created_at DESC"
         )?;
 
         let rows = stmt.query_map([], |row| {
@@ -103,6 +350,7 @@ impl Store {
                 storage_backend,
                 storage_config: row.get(4)?,
                 created_at: row.get(5)?,
+                owner: row.get(6)?,
             })
         })?;
 
@@ -110,9 +358,9 @@ impl Store {
             .context("failed to list projects")
     }
 
-    pub fn get_project_by_name(&self, name: &str) -> anyhow::Result<Option<Project>> {
+    fn get_project_by_name(&self, name: &str) -> anyhow::Result<Option<Project>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, name, image, storage_backend, storage_config, created_at FROM projects WHERE name = ?1"
+            "SELECT id, name, image, storage_backend, storage_config, created_at, owner FROM projects WHERE name = ?1"
         )?;
 
         let mut rows = stmt.query([name])?;
@@ -127,24 +375,26 @@ impl Store {
                 storage_backend,
                 storage_config: row.get(4)?,
                 created_at: row.get(5)?,
+                owner: row.get(6)?,
             }));
         }
 
         Ok(None)
     }
 
-    pub fn create_project(&self, input: NewProject) -> anyhow::Result<Project> {
+    fn create_project(&self, input: NewProject) -> anyhow::Result<Project> {
         let created_at = now_epoch_millis();
         let id = uuid::Uuid::new_v4().to_string();
 
         self.conn.execute(
-            "INSERT INTO projects(id, name, image, storage_backend, storage_config, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            rusqlite::params![id, input.name, input.image, input.storage_backend.as_str(), input.storage_config, created_at],
+            "INSERT INTO projects(id, name, image, storage_backend, storage_config, created_at, owner) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![id, input.name, input.image, input.storage_backend.as_str(), input.storage_config, created_at, input.owner],
         ).context("failed to insert project")?;
 
         Ok(Project {
             id,
             name: input.name,
+            owner: input.owner,
             image: input.image,
             storage_backend: input.storage_backend,
             storage_config: input.storage_config,
@@ -152,19 +402,22 @@ impl Store {
         })
     }
 
-    pub fn next_port(&self) -> anyhow::Result<u16> {
+    fn next_port(&self) -> anyhow::Result<u16> {
         let max_port: Option<u16> = self
             .conn
             .query_row("SELECT MAX(port) FROM branches", [], |row| row.get(0))
             .context("failed to compute next branch port")?;
 
-        Ok(max_port.map(|v| v.saturating_add(1)).unwrap_or(55432))
+        Ok(max_port
+            .map(|v| v.saturating_add(1))
+            .unwrap_or(FIRST_BRANCH_PORT))
     }
 
-    pub fn list_branches(&self, project_id: &str) -> anyhow::Result<Vec<Branch>> {
+    fn list_branches(&self, project_id: &str) -> anyhow::Result<Vec<Branch>> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at
+            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at,
+                   pg_user, pg_password, pg_db, last_used_at, pg_settings, health, parent_snapshot_id
             FROM branches
             WHERE project_id = ?1
             ORDER BY created_at DESC
@@ -176,11 +429,11 @@ impl Store {
             .context("failed to list branches")
     }
 
-    #[allow(dead_code)]
-    pub fn list_all_branches(&self) -> anyhow::Result<Vec<Branch>> {
+    fn list_all_branches(&self) -> anyhow::Result<Vec<Branch>> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at
+            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at,
+                   pg_user, pg_password, pg_db, last_used_at, pg_settings, health, parent_snapshot_id
             FROM branches
             ORDER BY created_at DESC
             "#,
@@ -191,14 +444,24 @@ impl Store {
             .context("failed to list all branches")
     }
 
-    pub fn get_branch_by_name(
+    fn list_all_snapshots(&self) -> anyhow::Result<Vec<Snapshot>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, branch_id, name, storage_metadata, created_at FROM snapshots ORDER BY created_at",
+        )?;
+        let rows = stmt.query_map([], map_snapshot_row)?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to list all snapshots")
+    }
+
+    fn get_branch_by_name(
         &self,
         project_id: &str,
         branch_name: &str,
     ) -> anyhow::Result<Option<Branch>> {
         let mut stmt = self.conn.prepare(
             r#"
-            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at
+            SELECT id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at,
+                   pg_user, pg_password, pg_db, last_used_at, pg_settings, health, parent_snapshot_id
             FROM branches
             WHERE project_id = ?1 AND name = ?2
             "#,
@@ -212,18 +475,21 @@ impl Store {
         Ok(None)
     }
 
-    pub fn create_branch(&self, input: NewBranch) -> anyhow::Result<Branch> {
+    fn create_branch(&self, input: NewBranch) -> anyhow::Result<Branch> {
         let created_at = now_epoch_millis();
 
         self.conn.execute(
             r#"
-            INSERT INTO branches(id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            INSERT INTO branches(id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at,
+                                 pg_user, pg_password, pg_db, parent_snapshot_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
             "#,
             rusqlite::params![
                 input.id, input.project_id, input.name, input.parent_branch_id,
                 input.state.as_str(), input.data_dir, input.container_name, input.port,
                 input.storage_metadata, created_at,
+                input.credentials.user, input.credentials.password, input.credentials.db,
+                input.parent_snapshot_id,
             ],
         ).context("failed to insert branch")?;
 
@@ -238,10 +504,17 @@ impl Store {
             port: input.port,
             storage_metadata: input.storage_metadata,
             created_at,
+            pg_user: Some(input.credentials.user),
+            pg_password: Some(input.credentials.password),
+            pg_db: Some(input.credentials.db),
+            last_used_at: None,
+            pg_settings: Default::default(),
+            health: None,
+            parent_snapshot_id: input.parent_snapshot_id,
         })
     }
 
-    pub fn update_branch_state(&self, branch_id: &str, state: BranchState) -> anyhow::Result<()> {
+    fn update_branch_state(&self, branch_id: &str, state: BranchState) -> anyhow::Result<()> {
         self.conn
             .execute(
                 "UPDATE branches SET state = ?1 WHERE id = ?2",
@@ -251,7 +524,68 @@ impl Store {
         Ok(())
     }
 
-    pub fn update_branch_storage_metadata(
+    fn update_branch_health(&self, branch_id: &str, health: Option<&str>) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET health = ?1 WHERE id = ?2",
+                rusqlite::params![health, branch_id],
+            )
+            .context("failed to update branch health")?;
+        Ok(())
+    }
+
+    fn record_drift(
+        &self,
+        branch: &Branch,
+        to_state: BranchState,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO drift_events (project_id, branch_id, branch_name, from_state, to_state, reason, detected_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                rusqlite::params![
+                    branch.project_id,
+                    branch.id,
+                    branch.name,
+                    branch.state.as_str(),
+                    to_state.as_str(),
+                    reason,
+                    now_epoch_millis()
+                ],
+            )
+            .context("failed to record drift")?;
+        // `status` only looks back a day; a month is plenty for digging
+        self.conn
+            .execute(
+                "DELETE FROM drift_events WHERE detected_at < ?1",
+                [now_epoch_millis() - DRIFT_RETENTION_MS],
+            )
+            .context("failed to prune drift")?;
+        Ok(())
+    }
+
+    fn list_drift(&self, project_id: &str, since: i64) -> anyhow::Result<Vec<Drift>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT branch_name, from_state, to_state, reason, detected_at FROM drift_events WHERE project_id = ?1 AND detected_at >= ?2 ORDER BY detected_at, id",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![project_id, since], |row| {
+            let state = |index: usize| -> rusqlite::Result<BranchState> {
+                let value: String = row.get(index)?;
+                Ok(BranchState::from_str(&value).unwrap_or(BranchState::Failed))
+            };
+            Ok(Drift {
+                branch_name: row.get(0)?,
+                from_state: state(1)?,
+                to_state: state(2)?,
+                reason: row.get(3)?,
+                detected_at: row.get(4)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to list drift")
+    }
+
+    fn update_branch_storage_metadata(
         &self,
         branch_id: &str,
         storage_metadata: Option<&str>,
@@ -265,14 +599,282 @@ impl Store {
         Ok(())
     }
 
-    pub fn delete_branch(&self, branch_id: &str) -> anyhow::Result<()> {
+    fn update_branch_credentials(
+        &self,
+        branch_id: &str,
+        credentials: &Credentials,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET pg_user = ?1, pg_password = ?2, pg_db = ?3 WHERE id = ?4",
+                rusqlite::params![
+                    credentials.user,
+                    credentials.password,
+                    credentials.db,
+                    branch_id
+                ],
+            )
+            .context("failed to update branch credentials")?;
+        Ok(())
+    }
+
+    fn update_branch_pg_settings(
+        &self,
+        branch_id: &str,
+        settings: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let json = (!settings.is_empty())
+            .then(|| serde_json::to_string(settings))
+            .transpose()?;
+        self.conn
+            .execute(
+                "UPDATE branches SET pg_settings = ?1 WHERE id = ?2",
+                rusqlite::params![json, branch_id],
+            )
+            .context("failed to update branch settings")?;
+        Ok(())
+    }
+
+    fn touch_branch(&self, branch_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET last_used_at = ?1 WHERE id = ?2",
+                rusqlite::params![now_epoch_millis(), branch_id],
+            )
+            .context("failed to update branch last use")?;
+        Ok(())
+    }
+
+    fn update_branch_port(&self, branch_id: &str, port: u16) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET port = ?1 WHERE id = ?2",
+                rusqlite::params![port, branch_id],
+            )
+            .context("failed to update branch port")?;
+        Ok(())
+    }
+
+    fn update_branch_container_name(
+        &self,
+        branch_id: &str,
+        container_name: &str,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE branches SET container_name = ?1 WHERE id = ?2",
+                rusqlite::params![container_name, branch_id],
+            )
+            .context("failed to update branch container name")?;
+        Ok(())
+    }
+
+    fn delete_branch(&self, branch_id: &str) -> anyhow::Result<()> {
         self.conn
             .execute("DELETE FROM branches WHERE id = ?1", [branch_id])
             .context("failed to delete branch")?;
         Ok(())
     }
 
-    pub fn delete_project(&self, project_id: &str) -> anyhow::Result<()> {
+    fn create_snapshot(
+        &self,
+        id: &str,
+        branch_id: &str,
+        name: &str,
+        storage_metadata: Option<&str>,
+    ) -> anyhow::Result<Snapshot> {
+        let created_at = now_epoch_millis();
+
+        self.conn
+            .execute(
+                "INSERT INTO snapshots(id, branch_id, name, storage_metadata, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![id, branch_id, name, storage_metadata, created_at],
+            )
+            .context("failed to insert snapshot")?;
+
+        Ok(Snapshot {
+            id: id.to_string(),
+            branch_id: branch_id.to_string(),
+            name: name.to_string(),
+            storage_metadata: storage_metadata.map(str::to_string),
+            created_at,
+        })
+    }
+
+    fn list_snapshots(&self, branch_id: &str) -> anyhow::Result<Vec<Snapshot>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, branch_id, name, storage_metadata, created_at FROM snapshots WHERE branch_id = ?1 ORDER BY created_at ASC",
+        )?;
+
+        let rows = stmt.query_map([branch_id], map_snapshot_row)?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to list snapshots")
+    }
+
+    fn get_snapshot_by_name(
+        &self,
+        branch_id: &str,
+        name: &str,
+    ) -> anyhow::Result<Option<Snapshot>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, branch_id, name, storage_metadata, created_at FROM snapshots WHERE branch_id = ?1 AND name = ?2",
+        )?;
+
+        let mut rows = stmt.query(rusqlite::params![branch_id, name])?;
+        if let Some(row) = rows.next()? {
+            return Ok(Some(map_snapshot_row(row)?));
+        }
+
+        Ok(None)
+    }
+
+    fn get_snapshot(&self, snapshot_id: &str) -> anyhow::Result<Option<Snapshot>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, branch_id, name, storage_metadata, created_at FROM snapshots WHERE id = ?1",
+        )?;
+
+        let mut rows = stmt.query([snapshot_id])?;
+        if let Some(row) = rows.next()? {
+            return Ok(Some(map_snapshot_row(row)?));
+        }
+
+        Ok(None)
+    }
+
+    fn list_branches_pinned_to(&self, snapshot_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM branches WHERE parent_snapshot_id = ?1 ORDER BY name")?;
+        let rows = stmt.query_map([snapshot_id], |row| row.get(0))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to list pinned branches")
+    }
+
+    fn delete_snapshot(&self, snapshot_id: &str) -> anyhow::Result<()> {
+        self.conn
+            .execute("DELETE FROM snapshots WHERE id = ?1", [snapshot_id])
+            .context("failed to delete snapshot")?;
+        Ok(())
+    }
+
+    fn record_usage(
+        &self,
+        project_name: &str,
+        operation: &str,
+        storage_backend: Option<&str>,
+        detail: Option<&str>,
+        duration_ms: i64,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                r#"
+                INSERT INTO usage_events (project_name, operation, storage_backend, detail, duration_ms, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                "#,
+                rusqlite::params![
+                    project_name,
+                    operation,
+                    storage_backend,
+                    detail,
+                    duration_ms,
+                    now_epoch_millis()
+                ],
+            )
+            .context("failed to record usage")?;
+        Ok(())
+    }
+
+    fn usage_summary(&self, project_name: Option<&str>) -> anyhow::Result<Vec<UsageSummary>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT project_name, operation, storage_backend, detail, COUNT(*),
+                   CAST(AVG(duration_ms) AS INTEGER), MIN(duration_ms), MAX(duration_ms),
+                   SUM(duration_ms), MIN(created_at)
+            FROM usage_events
+            WHERE ?1 IS NULL OR project_name = ?1
+            GROUP BY project_name, operation, storage_backend, detail
+            ORDER BY project_name, operation, storage_backend, detail
+            "#,
+        )?;
+        let rows = stmt.query_map([project_name], |row| {
+            Ok(UsageSummary {
+                project_name: row.get(0)?,
+                operation: row.get(1)?,
+                storage_backend: row.get(2)?,
+                detail: row.get(3)?,
+                count: row.get(4)?,
+                avg_ms: row.get(5)?,
+                min_ms: row.get(6)?,
+                max_ms: row.get(7)?,
+                total_ms: row.get(8)?,
+                first_at: row.get(9)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .context("failed to summarize usage")
+    }
+
+    fn restore_records(
+        &self,
+        project: Option<&Project>,
+        branches: &[Branch],
+        snapshots: &[Snapshot],
+    ) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        if let Some(project) = project {
+            tx.execute(
+                "INSERT INTO projects(id, name, image, storage_backend, storage_config, created_at, owner) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                rusqlite::params![
+                    project.id, project.name, project.image, project.storage_backend.as_str(),
+                    project.storage_config, project.created_at, project.owner,
+                ],
+            )
+            .context("failed to insert project")?;
+        }
+        for branch in branches {
+            let pg_settings = (!branch.pg_settings.is_empty())
+                .then(|| serde_json::to_string(&branch.pg_settings))
+                .transpose()?;
+            tx.execute(
+                r#"
+                INSERT INTO branches(id, project_id, name, parent_branch_id, state, data_dir, container_name, port, storage_metadata, created_at,
+                                     pg_user, pg_password, pg_db, last_used_at, pg_settings, health)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+                "#,
+                rusqlite::params![
+                    branch.id, branch.project_id, branch.name, branch.parent_branch_id,
+                    branch.state.as_str(), branch.data_dir, branch.container_name, branch.port,
+                    branch.storage_metadata, branch.created_at,
+                    branch.pg_user, branch.pg_password, branch.pg_db, branch.last_used_at,
+                    pg_settings, branch.health,
+                ],
+            )
+            .with_context(|| format!("failed to insert branch '{}'", branch.name))?;
+        }
+        for snapshot in snapshots {
+            tx.execute(
+                "INSERT INTO snapshots(id, branch_id, name, storage_metadata, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    snapshot.id, snapshot.branch_id, snapshot.name, snapshot.storage_metadata,
+                    snapshot.created_at,
+                ],
+            )
+            .with_context(|| format!("failed to insert snapshot '{}'", snapshot.name))?;
+        }
+        for branch in branches {
+            if let Some(snapshot_id) = &branch.parent_snapshot_id {
+                tx.execute(
+                    "UPDATE branches SET parent_snapshot_id = ?1 WHERE id = ?2",
+                    rusqlite::params![snapshot_id, branch.id],
+                )
+                .context("failed to update branch snapshot")?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_project(&self, project_id: &str) -> anyhow::Result<()> {
         // ON DELETE CASCADE auto-removes all branch rows
         self.conn
             .execute("DELETE FROM projects WHERE id = ?1", [project_id])
@@ -296,6 +898,27 @@ fn map_branch_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Branch> {
         port: row.get(7)?,
         storage_metadata: row.get(8)?,
         created_at: row.get(9)?,
+        pg_user: row.get(10)?,
+        pg_password: row.get(11)?,
+        pg_db: row.get(12)?,
+        last_used_at: row.get(13)?,
+        // Stored as a JSON object; unreadable settings are dropped rather than failing the load
+        pg_settings: row
+            .get::<_, Option<String>>(14)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        health: row.get(15)?,
+        parent_snapshot_id: row.get(16)?,
+    })
+}
+
+fn map_snapshot_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Snapshot> {
+    Ok(Snapshot {
+        id: row.get(0)?,
+        branch_id: row.get(1)?,
+        name: row.get(2)?,
+        storage_metadata: row.get(3)?,
+        created_at: row.get(4)?,
     })
 }
 
@@ -318,3 +941,144 @@ fn ensure_column(
     conn.execute(&alter, [])?;
     Ok(())
 }
+
+/// `<dir>/backups/state-<label>-<millis>.db` next to the database at `path`.
+pub fn backup_path(path: &Path, label: &str) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("backups")
+        .join(format!("state-{}-{}.db", label, now_epoch_millis()))
+}
+
+fn migrate_v1(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS projects (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL UNIQUE,
+          image TEXT NOT NULL,
+          storage_backend TEXT NOT NULL DEFAULT 'copy',
+          storage_config TEXT NULL,
+          created_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS branches (
+          id TEXT PRIMARY KEY,
+          project_id TEXT NOT NULL,
+          name TEXT NOT NULL,
+          parent_branch_id TEXT NULL,
+          state TEXT NOT NULL,
+          data_dir TEXT NOT NULL,
+          container_name TEXT NOT NULL,
+          port INTEGER NOT NULL,
+          storage_metadata TEXT NULL,
+          created_at INTEGER NOT NULL,
+          UNIQUE(project_id, name),
+          FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE,
+          FOREIGN KEY(parent_branch_id) REFERENCES branches(id) ON DELETE SET NULL
+        );
+        "#,
+    )?;
+
+    // Columns added before schema versioning existed
+    ensure_column(
+        conn,
+        "projects",
+        "storage_backend",
+        "TEXT NOT NULL DEFAULT 'copy'",
+    )?;
+    ensure_column(conn, "projects", "storage_config", "TEXT NULL")?;
+    ensure_column(conn, "branches", "storage_metadata", "TEXT NULL")?;
+    Ok(())
+}
+
+fn migrate_v2(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS snapshots (
+          id TEXT PRIMARY KEY,
+          branch_id TEXT NOT NULL,
+          name TEXT NOT NULL,
+          storage_metadata TEXT NULL,
+          created_at INTEGER NOT NULL,
+          UNIQUE(branch_id, name),
+          FOREIGN KEY(branch_id) REFERENCES branches(id) ON DELETE CASCADE
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+fn migrate_v3(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_events (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          project_name TEXT NOT NULL,
+          operation TEXT NOT NULL,
+          storage_backend TEXT NULL,
+          detail TEXT NULL,
+          duration_ms INTEGER NOT NULL,
+          created_at INTEGER NOT NULL
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+fn migrate_v4(conn: &Connection) -> anyhow::Result<()> {
+    ensure_column(conn, "branches", "pg_user", "TEXT NULL")?;
+    ensure_column(conn, "branches", "pg_password", "TEXT NULL")?;
+    ensure_column(conn, "branches", "pg_db", "TEXT NULL")?;
+    Ok(())
+}
+
+fn migrate_v5(conn: &Connection) -> anyhow::Result<()> {
+    ensure_column(conn, "projects", "owner", "TEXT NULL")?;
+    Ok(())
+}
+
+fn migrate_v6(conn: &Connection) -> anyhow::Result<()> {
+    ensure_column(conn, "branches", "last_used_at", "INTEGER NULL")?;
+    Ok(())
+}
+
+fn migrate_v7(conn: &Connection) -> anyhow::Result<()> {
+    ensure_column(conn, "branches", "pg_settings", "TEXT NULL")?;
+    Ok(())
+}
+
+fn migrate_v8(conn: &Connection) -> anyhow::Result<()> {
+    ensure_column(conn, "branches", "health", "TEXT NULL")?;
+    Ok(())
+}
+
+fn migrate_v9(conn: &Connection) -> anyhow::Result<()> {
+    ensure_column(
+        conn,
+        "branches",
+        "parent_snapshot_id",
+        "TEXT NULL REFERENCES snapshots(id) ON DELETE SET NULL",
+    )?;
+    Ok(())
+}
+
+fn migrate_v10(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS drift_events (
+          id INTEGER PRIMARY KEY AUTOINCREMENT,
+          project_id TEXT NOT NULL,
+          branch_id TEXT NOT NULL,
+          branch_name TEXT NOT NULL,
+          from_state TEXT NOT NULL,
+          to_state TEXT NOT NULL,
+          reason TEXT NOT NULL,
+          detected_at INTEGER NOT NULL,
+          FOREIGN KEY(branch_id) REFERENCES branches(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS drift_events_project ON drift_events(project_id, detected_at);
+        "#,
+    )?;
+    Ok(())
+}
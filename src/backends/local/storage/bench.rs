@@ -0,0 +1,198 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{StorageCoordinator, ZfsProjectConfig};
+use crate::backends::local::model::{
+    now_epoch_millis, Branch, BranchState, Project, StorageBackend,
+};
+
+/// Synthetic relation files are written in segments of this size.
+const SEGMENT_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Clone cost of one storage backend, measured by `pgbranch bench-storage`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageBenchResult {
+    pub backend: String,
+    pub available: bool,
+    pub detail: String,
+    pub clone_ms: Option<u64>,
+    /// Drop in free space on the data filesystem caused by the clone
+    pub space_bytes: Option<u64>,
+}
+
+impl StorageCoordinator {
+    /// Write a synthetic data directory of `dataset_bytes` with every available
+    /// backend, then time one clone of it and measure the space the clone took.
+    /// Everything is created in a throwaway project and removed afterwards.
+    pub async fn bench(&self, dataset_bytes: u64) -> Vec<StorageBenchResult> {
+        let report = self.doctor().await;
+        let mut results = Vec::new();
+
+        for entry in report.entries {
+            let Some(backend) = StorageBackend::from_str(&entry.kind) else {
+                continue;
+            };
+            if !entry.available {
+                results.push(StorageBenchResult {
+                    backend: entry.kind,
+                    available: false,
+                    detail: entry.detail,
+                    clone_ms: None,
+                    space_bytes: None,
+                });
+                continue;
+            }
+
+            let result = match self.bench_config(backend).await {
+                Ok(config) => self.bench_backend(backend, config, dataset_bytes).await,
+                Err(e) => Err(e),
+            };
+            results.push(match result {
+                Ok((clone_ms, space_bytes)) => StorageBenchResult {
+                    backend: entry.kind,
+                    available: true,
+                    detail: entry.detail,
+                    clone_ms: Some(clone_ms),
+                    space_bytes: Some(space_bytes),
+                },
+                Err(e) => StorageBenchResult {
+                    backend: entry.kind,
+                    available: true,
+                    detail: format!("benchmark failed: {e:#}"),
+                    clone_ms: None,
+                    space_bytes: None,
+                },
+            });
+        }
+
+        results
+    }
+
+    async fn bench_config(&self, backend: StorageBackend) -> anyhow::Result<Option<String>> {
+        if backend != StorageBackend::Zfs {
+            return Ok(None);
+        }
+        let root_dataset = self
+            .zfs
+            .detect(&self.projects_root)
+            .await
+            .root_dataset
+            .ok_or_else(|| anyhow!("no ZFS dataset found for {}", self.projects_root.display()))?;
+        Ok(Some(serde_json::to_string(&ZfsProjectConfig {
+            root_dataset,
+        })?))
+    }
+
+    async fn bench_backend(
+        &self,
+        backend: StorageBackend,
+        storage_config: Option<String>,
+        dataset_bytes: u64,
+    ) -> anyhow::Result<(u64, u64)> {
+        let project = Project {
+            id: format!("bench-{}", Uuid::new_v4()),
+            name: "bench-storage".to_string(),
+            owner: None,
+            image: String::new(),
+            storage_backend: backend,
+            storage_config,
+            created_at: now_epoch_millis(),
+        };
+
+        let result = self.bench_project(&project, dataset_bytes).await;
+        if let Err(e) = self.delete_project_data(&project).await {
+            log::warn!("Failed to remove benchmark data of {}: {}", project.id, e);
+        }
+        result
+    }
+
+    async fn bench_project(
+        &self,
+        project: &Project,
+        dataset_bytes: u64,
+    ) -> anyhow::Result<(u64, u64)> {
+        let data_dir = |branch_id: &str| -> PathBuf {
+            self.projects_root
+                .join(&project.id)
+                .join("branches")
+                .join(branch_id)
+                .join("pgdata")
+        };
+
+        let parent_dir = data_dir("parent");
+        let storage_metadata = self
+            .create_empty_branch(project, "parent", &parent_dir)
+            .await?;
+        let dir = parent_dir.clone();
+        tokio::task::spawn_blocking(move || write_dataset(&dir, dataset_bytes)).await??;
+
+        let parent = Branch {
+            id: "parent".to_string(),
+            project_id: project.id.clone(),
+            name: "parent".to_string(),
+            parent_branch_id: None,
+            state: BranchState::Stopped,
+            data_dir: parent_dir.to_string_lossy().to_string(),
+            container_name: String::new(),
+            port: 0,
+            storage_metadata,
+            created_at: now_epoch_millis(),
+            pg_user: None,
+            pg_password: None,
+            pg_db: None,
+            last_used_at: None,
+            pg_settings: Default::default(),
+            health: None,
+            parent_snapshot_id: None,
+        };
+
+        let free_before = super::space::flushed_free_space(&self.projects_root).await?;
+        let started = Instant::now();
+        self.clone_branch_from_parent(project, &parent, "child", &data_dir("child"))
+            .await?;
+        let clone_ms = started.elapsed().as_millis() as u64;
+        let free_after = super::space::flushed_free_space(&self.projects_root).await?;
+
+        Ok((clone_ms, free_before.saturating_sub(free_after)))
+    }
+}
+
+/// Fill `data_dir` with incompressible segment files shaped like a PGDATA `base/` tree.
+fn write_dataset(data_dir: &Path, total_bytes: u64) -> anyhow::Result<()> {
+    let base = data_dir.join("base").join("16384");
+    std::fs::create_dir_all(&base)
+        .with_context(|| format!("failed to create '{}'", base.display()))?;
+
+    // xorshift keeps ZFS compression and dedup from flattering the numbers
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut chunk = vec![0u8; 1024 * 1024];
+    let mut remaining = total_bytes;
+    let mut segment = 0;
+    while remaining > 0 {
+        let path = base.join(format!("{}", 16385 + segment));
+        let mut file = std::fs::File::create(&path)
+            .with_context(|| format!("failed to create '{}'", path.display()))?;
+        let mut segment_left = remaining.min(SEGMENT_BYTES);
+        remaining -= segment_left;
+        while segment_left > 0 {
+            for word in chunk.chunks_exact_mut(8) {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                word.copy_from_slice(&state.to_le_bytes());
+            }
+            let n = segment_left.min(chunk.len() as u64) as usize;
+            file.write_all(&chunk[..n])?;
+            segment_left -= n as u64;
+        }
+        // Make sure the blocks are allocated before free space is sampled
+        file.sync_all()?;
+        segment += 1;
+    }
+    Ok(())
+}
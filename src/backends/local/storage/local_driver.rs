@@ -129,7 +129,16 @@ impl LocalDriver {
             .with_context(|| format!("source directory '{}' not found", source.display()))?;
 
         recreate_dir(target).await?;
+        self.copy_into(source, target, mode).await
+    }
 
+    /// Copy the contents of `source` into an existing `target` directory.
+    pub async fn copy_into(
+        &self,
+        source: &Path,
+        target: &Path,
+        mode: LocalMode,
+    ) -> anyhow::Result<()> {
         let source_dot = source.join(".");
         match mode {
             LocalMode::ApfsClone => {
@@ -187,6 +196,42 @@ impl LocalDriver {
         Ok(())
     }
 
+    /// The commands `clone_dir` runs, for `--dry-run`. Fallback copies are left out.
+    pub fn plan_clone_dir(
+        &self,
+        source: &Path,
+        target: &Path,
+        mode: LocalMode,
+    ) -> anyhow::Result<Vec<String>> {
+        Ok(vec![
+            self.plan_remove_dir(target)?,
+            format!("mkdir -p {}", target.display()),
+            self.plan_copy_into(source, target, mode),
+        ])
+    }
+
+    /// The command `copy_into` runs, for `--dry-run`.
+    pub fn plan_copy_into(&self, source: &Path, target: &Path, mode: LocalMode) -> String {
+        let flags = match mode {
+            LocalMode::ApfsClone => "-cR",
+            LocalMode::Reflink => "-a --reflink=auto",
+            LocalMode::Copy => "-a",
+        };
+        format!(
+            "cp {flags} {} {}",
+            source.join(".").display(),
+            target.display()
+        )
+    }
+
+    /// The command `remove_dir` runs, for `--dry-run`.
+    pub fn plan_remove_dir(&self, data_dir: &Path) -> anyhow::Result<String> {
+        Ok(format!(
+            "rm -rf {}",
+            branch_root_from_data_dir(data_dir)?.display()
+        ))
+    }
+
     pub async fn remove_dir(&self, data_dir: &Path) -> anyhow::Result<()> {
         let branch_root = branch_root_from_data_dir(data_dir)?;
         if tokio::fs::metadata(branch_root).await.is_ok() {
@@ -198,6 +243,58 @@ impl LocalDriver {
         }
         Ok(())
     }
+
+    /// Deallocate the zero-filled blocks of every file under `dir`, which
+    /// VACUUM and truncated relations leave behind. Linux only; the files
+    /// must not be written to meanwhile.
+    pub async fn dig_holes(&self, dir: &Path) -> anyhow::Result<()> {
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&current)
+                .await
+                .with_context(|| format!("failed to read directory '{}'", current.display()))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    pending.push(entry.path());
+                } else if file_type.is_file() {
+                    let output = Command::new("fallocate")
+                        .arg("--dig-holes")
+                        .arg(entry.path())
+                        .output()
+                        .await
+                        .context("failed to execute fallocate")?;
+                    if !output.status.success() {
+                        return Err(anyhow!(
+                            "fallocate --dig-holes failed: {}",
+                            String::from_utf8_lossy(&output.stderr).trim()
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Share the extents `dir` has in common with `parent_dir` again, after
+    /// rewrites broke them apart. The kernel compares the data before
+    /// sharing it, so this is safe while the parent runs.
+    pub async fn dedupe(&self, dir: &Path, parent_dir: &Path) -> anyhow::Result<()> {
+        let output = Command::new("duperemove")
+            .args(["-dr", "-q"])
+            .arg(dir)
+            .arg(parent_dir)
+            .output()
+            .await
+            .context("failed to execute duperemove")?;
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "duperemove failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
 }
 
 fn branch_root_from_data_dir(data_dir: &Path) -> anyhow::Result<&Path> {
@@ -234,3 +331,8 @@ async fn run_cp(args: Vec<OsString>) -> anyhow::Result<()> {
     let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
     Err(anyhow!("cp command failed: {stderr}"))
 }
+
+/// Whether `tool` can be run, so optional steps can be skipped without it.
+pub async fn tool_available(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().await.is_ok()
+}
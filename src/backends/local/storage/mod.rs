@@ -1,4 +1,6 @@
+pub mod bench;
 pub mod local_driver;
+pub mod space;
 pub mod zfs_driver;
 pub mod zfs_setup;
 
@@ -7,7 +9,8 @@ use std::path::Path;
 use anyhow::{anyhow, Context};
 use serde::{Deserialize, Serialize};
 
-use super::model::{Branch, Project, StorageBackend};
+use super::model::{Branch, Project, Snapshot, StorageBackend};
+use crate::backends::DoctorCheck;
 
 #[derive(Debug, Clone)]
 pub struct StorageSelection {
@@ -40,6 +43,11 @@ pub struct ZfsBranchMetadata {
     pub origin_snapshot: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZfsSnapshotMetadata {
+    pub snapshot: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct StorageCoordinator {
     projects_root: std::path::PathBuf,
@@ -56,6 +64,11 @@ impl StorageCoordinator {
         }
     }
 
+    pub fn with_zfs_limits(mut self, limits: crate::config::ZfsLimitsConfig) -> Self {
+        self.zfs = zfs_driver::ZfsDriver::with_limits(limits);
+        self
+    }
+
     pub async fn doctor(&self) -> StorageDoctorReport {
         let zfs_report = self.zfs.detect(&self.projects_root).await;
         let apfs_report = self.local.detect_apfs(&self.projects_root).await;
@@ -131,6 +144,202 @@ impl StorageCoordinator {
         }
     }
 
+    /// Bytes a clone of `source_data_dir` takes up front: all of it for a
+    /// plain copy, nothing for a copy-on-write clone.
+    pub async fn clone_size_estimate(
+        &self,
+        project: &Project,
+        source_data_dir: &Path,
+        cross_project: bool,
+    ) -> u64 {
+        let copies = match project.storage_backend {
+            StorageBackend::Copy => true,
+            // Branches of other projects are copied into a fresh dataset
+            StorageBackend::Zfs => cross_project,
+            StorageBackend::ApfsClone | StorageBackend::Reflink => false,
+        };
+        if !copies {
+            return 0;
+        }
+        space::dir_size(source_data_dir)
+            .await
+            .unwrap_or_else(|err| {
+                log::debug!("could not size '{}': {err:#}", source_data_dir.display());
+                0
+            })
+    }
+
+    /// Refuse to write `needed` more bytes for `project` when its filesystem
+    /// or ZFS pool can't take them with some headroom to spare. Checks that
+    /// can't run (no df or zpool) are skipped rather than blocking the write.
+    pub async fn ensure_space(&self, project: &Project, needed: u64) -> anyhow::Result<()> {
+        if project.storage_backend == StorageBackend::Zfs {
+            let config = parse_zfs_config(project)?;
+            match space::pool_capacity(&config.root_dataset).await {
+                Ok((pool, capacity)) if capacity >= space::POOL_FULL_PERCENT => {
+                    anyhow::bail!(
+                        "ZFS pool '{pool}' is {capacity}% full; free up space (delete branches and snapshots, or run 'pgbranch cleanup') before cloning or seeding"
+                    );
+                }
+                Ok(_) => {}
+                Err(err) => log::debug!("skipping ZFS pool capacity check: {err:#}"),
+            }
+        }
+
+        let required = needed + space::HEADROOM_BYTES;
+        match space::free_space(&self.projects_root).await {
+            Ok(free) if free < required => anyhow::bail!(
+                "Not enough free space in {}: {} available, about {} needed ({} of data plus {} headroom). Free up space, e.g. with 'pgbranch cleanup'",
+                self.projects_root.display(),
+                space::format_bytes(free),
+                space::format_bytes(required),
+                space::format_bytes(needed),
+                space::format_bytes(space::HEADROOM_BYTES)
+            ),
+            Ok(_) => {}
+            Err(err) => log::debug!("skipping free space check: {err:#}"),
+        }
+        Ok(())
+    }
+
+    /// Space used by the project and what its quota leaves; `None` unless
+    /// it's on ZFS, where that is cheap to find out.
+    pub async fn project_usage(&self, project: &Project) -> anyhow::Result<Option<space::Usage>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                Ok(Some(self.zfs.project_usage(project, &config).await?))
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => Ok(None),
+        }
+    }
+
+    /// Space used by a branch. Outside ZFS this is the size of its data
+    /// directory, which counts blocks it shares with its parent.
+    pub async fn branch_usage(
+        &self,
+        project: &Project,
+        branch: &Branch,
+    ) -> anyhow::Result<space::Usage> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.branch_usage(branch).await,
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                Ok(space::Usage {
+                    used_bytes: space::dir_size(Path::new(&branch.data_dir)).await?,
+                    quota_bytes: None,
+                    available_bytes: None,
+                })
+            }
+        }
+    }
+
+    /// Whether [`Self::compact_branch_data`] rewrites the branch's files, so
+    /// the branch has to be stopped first.
+    pub fn compact_needs_stop(&self, project: &Project) -> bool {
+        project.storage_backend != StorageBackend::Zfs && cfg!(target_os = "linux")
+    }
+
+    /// Give back space the branch's data doesn't need: zero-filled blocks and
+    /// extents it could share with its parent on file storage, freed blocks
+    /// of the pool on ZFS. Returns what was done or skipped, one line each.
+    pub async fn compact_branch_data(
+        &self,
+        project: &Project,
+        branch: &Branch,
+        parent: Option<&Branch>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut steps = Vec::new();
+        if project.storage_backend == StorageBackend::Zfs {
+            let pool = self.zfs.trim_pool(branch).await?;
+            steps.push(format!(
+                "Started a TRIM of ZFS pool '{pool}'; it runs in the background"
+            ));
+            return Ok(steps);
+        }
+        if !cfg!(target_os = "linux") {
+            steps.push("Skipped punching holes: only supported on Linux".to_string());
+            return Ok(steps);
+        }
+
+        let data_dir = Path::new(&branch.data_dir);
+        if local_driver::tool_available("fallocate").await {
+            self.local.dig_holes(data_dir).await?;
+            steps.push("Punched holes in zero-filled blocks".to_string());
+        } else {
+            steps.push("Skipped punching holes: fallocate is not installed".to_string());
+        }
+
+        match (project.storage_backend, parent) {
+            (StorageBackend::Reflink, Some(parent)) => {
+                if local_driver::tool_available("duperemove").await {
+                    self.local
+                        .dedupe(data_dir, Path::new(&parent.data_dir))
+                        .await?;
+                    steps.push(format!("Deduplicated against parent '{}'", parent.name));
+                } else {
+                    steps.push(format!(
+                        "Skipped deduplicating against parent '{}': duperemove is not installed",
+                        parent.name
+                    ));
+                }
+            }
+            (StorageBackend::Reflink, None) => {}
+            _ => steps.push(format!(
+                "Skipped deduplication: {} storage can't share blocks between branches",
+                project.storage_backend.as_str()
+            )),
+        }
+        Ok(steps)
+    }
+
+    /// Doctor checks for how full the data filesystem and ZFS pool are.
+    pub async fn space_checks(&self) -> Vec<DoctorCheck> {
+        let mut checks = Vec::new();
+        let Some(root) = self.projects_root.ancestors().find(|p| p.exists()) else {
+            return checks;
+        };
+        match space::free_space(root).await {
+            Ok(free) => checks.push(DoctorCheck {
+                name: "Free space".to_string(),
+                available: free >= space::HEADROOM_BYTES,
+                detail: format!(
+                    "{} available in {}",
+                    space::format_bytes(free),
+                    root.display()
+                ),
+            }),
+            Err(err) => checks.push(DoctorCheck {
+                name: "Free space".to_string(),
+                available: false,
+                detail: format!("could not check {}: {err:#}", root.display()),
+            }),
+        }
+
+        if let Some(dataset) = self.zfs.detect(&self.projects_root).await.root_dataset {
+            match space::pool_capacity(&dataset).await {
+                Ok((pool, capacity)) => checks.push(DoctorCheck {
+                    name: "ZFS pool capacity".to_string(),
+                    available: capacity < space::POOL_FULL_PERCENT,
+                    detail: if capacity >= space::POOL_WARN_PERCENT {
+                        format!(
+                            "pool '{pool}' is {capacity}% full; ZFS slows down past {}% and clones are refused at {}%",
+                            space::POOL_WARN_PERCENT,
+                            space::POOL_FULL_PERCENT
+                        )
+                    } else {
+                        format!("pool '{pool}' is {capacity}% full")
+                    },
+                }),
+                Err(err) => checks.push(DoctorCheck {
+                    name: "ZFS pool capacity".to_string(),
+                    available: false,
+                    detail: format!("could not check the pool of '{dataset}': {err:#}"),
+                }),
+            }
+        }
+        checks
+    }
+
     pub async fn create_empty_branch(
         &self,
         project: &Project,
@@ -212,6 +421,75 @@ impl StorageCoordinator {
         }
     }
 
+    /// Clone a snapshot of the parent instead of its current data, so every
+    /// branch created from it starts from the same state.
+    pub async fn clone_branch_from_snapshot(
+        &self,
+        project: &Project,
+        snapshot: &Snapshot,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Option<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                self.zfs
+                    .clone_from_snapshot(
+                        project,
+                        &config,
+                        snapshot,
+                        child_branch_id,
+                        child_data_dir,
+                    )
+                    .await
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local
+                    .clone_dir(
+                        &self.snapshot_data_dir(project, &snapshot.branch_id, &snapshot.id),
+                        child_data_dir,
+                        local_mode(project.storage_backend),
+                    )
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Clone an arbitrary data directory (e.g. a branch of another project)
+    /// into a new branch.
+    pub async fn clone_branch_from_dir(
+        &self,
+        project: &Project,
+        source_data_dir: &Path,
+        branch_id: &str,
+        data_dir: &Path,
+    ) -> anyhow::Result<Option<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                // Datasets of other projects can't be cloned by path, so copy
+                // into a fresh dataset (block-cloned on OpenZFS 2.2+)
+                let metadata = self
+                    .create_empty_branch(project, branch_id, data_dir)
+                    .await?;
+                self.local
+                    .copy_into(source_data_dir, data_dir, local_driver::LocalMode::Reflink)
+                    .await?;
+                Ok(metadata)
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local
+                    .clone_dir(
+                        source_data_dir,
+                        data_dir,
+                        local_mode(project.storage_backend),
+                    )
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
     pub async fn delete_branch_data(
         &self,
         project: &Project,
@@ -225,11 +503,112 @@ impl StorageCoordinator {
             StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
                 self.local
                     .remove_dir(std::path::PathBuf::from(&branch.data_dir).as_path())
+                    .await?;
+
+                let snapshots_dir = self
+                    .projects_root
+                    .join(&project.id)
+                    .join("snapshots")
+                    .join(&branch.id);
+                if tokio::fs::metadata(&snapshots_dir).await.is_ok() {
+                    tokio::fs::remove_dir_all(&snapshots_dir)
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "failed to remove snapshot directory '{}'",
+                                snapshots_dir.display()
+                            )
+                        })?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Reset a branch to the data it was cloned with without re-cloning.
+    /// Returns `false` when the storage backend can't, and the caller
+    /// should re-clone from the parent instead.
+    pub async fn reset_in_place(&self, project: &Project, branch: &Branch) -> anyhow::Result<bool> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.rollback_to_base(branch).await,
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => Ok(false),
+        }
+    }
+
+    /// Capture the branch's current data as a snapshot. The caller is
+    /// responsible for pausing the branch container first.
+    pub async fn snapshot_branch(
+        &self,
+        project: &Project,
+        branch: &Branch,
+        snapshot_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.snapshot_branch(branch, snapshot_id).await,
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local
+                    .clone_dir(
+                        std::path::PathBuf::from(&branch.data_dir).as_path(),
+                        &self.snapshot_data_dir(project, &branch.id, snapshot_id),
+                        local_mode(project.storage_backend),
+                    )
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Replace the branch's data with the snapshot contents. On ZFS this
+    /// also destroys any snapshots taken after the target.
+    pub async fn restore_snapshot(
+        &self,
+        project: &Project,
+        branch: &Branch,
+        snapshot: &Snapshot,
+    ) -> anyhow::Result<()> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.rollback_to_snapshot(snapshot).await,
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local
+                    .clone_dir(
+                        &self.snapshot_data_dir(project, &branch.id, &snapshot.id),
+                        std::path::PathBuf::from(&branch.data_dir).as_path(),
+                        local_mode(project.storage_backend),
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn delete_snapshot_data(
+        &self,
+        project: &Project,
+        snapshot: &Snapshot,
+    ) -> anyhow::Result<()> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.delete_snapshot(snapshot).await,
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local
+                    .remove_dir(&self.snapshot_data_dir(project, &snapshot.branch_id, &snapshot.id))
                     .await
             }
         }
     }
 
+    pub fn snapshot_data_dir(
+        &self,
+        project: &Project,
+        branch_id: &str,
+        snapshot_id: &str,
+    ) -> std::path::PathBuf {
+        self.projects_root
+            .join(&project.id)
+            .join("snapshots")
+            .join(branch_id)
+            .join(snapshot_id)
+            .join("pgdata")
+    }
+
     pub async fn delete_project_data(&self, project: &Project) -> anyhow::Result<()> {
         match project.storage_backend {
             StorageBackend::Zfs => {
@@ -281,6 +660,158 @@ impl StorageCoordinator {
         }
         Ok(())
     }
+
+    // Dry-run descriptions: the commands the operations above would run
+
+    pub fn plan_create_empty_branch(
+        &self,
+        project: &Project,
+        branch_id: &str,
+        data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                self.zfs
+                    .plan_create_empty(project, &config, branch_id, data_dir)
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                Ok(vec![format!("mkdir -p {}", data_dir.display())])
+            }
+        }
+    }
+
+    pub fn plan_clone_branch_from_parent(
+        &self,
+        project: &Project,
+        parent: &Branch,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                self.zfs.plan_clone_from_parent(
+                    project,
+                    &config,
+                    parent,
+                    child_branch_id,
+                    child_data_dir,
+                )
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local.plan_clone_dir(
+                    Path::new(&parent.data_dir),
+                    child_data_dir,
+                    local_mode(project.storage_backend),
+                )
+            }
+        }
+    }
+
+    pub fn plan_clone_branch_from_snapshot(
+        &self,
+        project: &Project,
+        snapshot: &Snapshot,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => {
+                let config = parse_zfs_config(project)?;
+                self.zfs.plan_clone_from_snapshot(
+                    project,
+                    &config,
+                    snapshot,
+                    child_branch_id,
+                    child_data_dir,
+                )
+            }
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local.plan_clone_dir(
+                    &self.snapshot_data_dir(project, &snapshot.branch_id, &snapshot.id),
+                    child_data_dir,
+                    local_mode(project.storage_backend),
+                )
+            }
+        }
+    }
+
+    pub fn plan_delete_branch_data(
+        &self,
+        project: &Project,
+        branch: &Branch,
+    ) -> anyhow::Result<Vec<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.plan_delete_branch(branch),
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                let snapshots_dir = self
+                    .projects_root
+                    .join(&project.id)
+                    .join("snapshots")
+                    .join(&branch.id);
+                Ok(vec![
+                    self.local.plan_remove_dir(Path::new(&branch.data_dir))?,
+                    format!("rm -rf {}", snapshots_dir.display()),
+                ])
+            }
+        }
+    }
+
+    /// `None` when the backend can't reset in place and the branch is re-cloned.
+    pub fn plan_reset_in_place(
+        &self,
+        project: &Project,
+        branch: &Branch,
+    ) -> anyhow::Result<Option<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.plan_rollback_to_base(branch).map(Some),
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => Ok(None),
+        }
+    }
+
+    pub fn plan_restore_snapshot(
+        &self,
+        project: &Project,
+        branch: &Branch,
+        snapshot: &Snapshot,
+    ) -> anyhow::Result<Vec<String>> {
+        match project.storage_backend {
+            StorageBackend::Zfs => Ok(vec![self.zfs.plan_rollback_to_snapshot(snapshot)?]),
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local.plan_clone_dir(
+                    &self.snapshot_data_dir(project, &branch.id, &snapshot.id),
+                    Path::new(&branch.data_dir),
+                    local_mode(project.storage_backend),
+                )
+            }
+        }
+    }
+
+    pub fn plan_delete_snapshot_data(
+        &self,
+        project: &Project,
+        snapshot: &Snapshot,
+    ) -> anyhow::Result<String> {
+        match project.storage_backend {
+            StorageBackend::Zfs => self.zfs.plan_delete_snapshot(snapshot),
+            StorageBackend::ApfsClone | StorageBackend::Reflink | StorageBackend::Copy => {
+                self.local.plan_remove_dir(&self.snapshot_data_dir(
+                    project,
+                    &snapshot.branch_id,
+                    &snapshot.id,
+                ))
+            }
+        }
+    }
+}
+
+fn local_mode(backend: StorageBackend) -> local_driver::LocalMode {
+    match backend {
+        StorageBackend::ApfsClone => local_driver::LocalMode::ApfsClone,
+        StorageBackend::Reflink => local_driver::LocalMode::Reflink,
+        StorageBackend::Zfs | StorageBackend::Copy => local_driver::LocalMode::Copy,
+    }
 }
 
 fn parse_zfs_config(project: &Project) -> anyhow::Result<ZfsProjectConfig> {
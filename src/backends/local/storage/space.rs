@@ -0,0 +1,101 @@
+//! Free space checks. A filesystem or ZFS pool that fills up mid-clone or
+//! mid-seed surfaces as baffling PostgreSQL errors, so clones and seeds
+//! check for room first and `doctor` reports how full the storage is.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use tokio::process::Command;
+
+pub use crate::backends::format_bytes;
+
+/// Room left over after a clone or seed for WAL, temp files and growth.
+pub const HEADROOM_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// ZFS gets slow well before a pool is full and fails writes once it is.
+pub const POOL_FULL_PERCENT: u64 = 90;
+pub const POOL_WARN_PERCENT: u64 = 80;
+
+/// Space taken by a project or branch.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub used_bytes: u64,
+    /// ZFS quota, if one is set
+    pub quota_bytes: Option<u64>,
+    /// How much more it can take, where that is known
+    pub available_bytes: Option<u64>,
+}
+
+/// Available bytes on the filesystem holding `path`, from `df`.
+pub async fn free_space(path: &Path) -> anyhow::Result<u64> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .await
+        .context("failed to run df")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "df failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| anyhow!("unexpected df output"))
+}
+
+/// Free space after flushing, so writes still sitting in the page cache count.
+pub async fn flushed_free_space(path: &Path) -> anyhow::Result<u64> {
+    let _ = Command::new("sync").status().await;
+    free_space(path).await
+}
+
+/// Bytes used by the files under `path`, from `du`.
+pub async fn dir_size(path: &Path) -> anyhow::Result<u64> {
+    let output = Command::new("du")
+        .arg("-sk")
+        .arg(path)
+        .output()
+        .await
+        .context("failed to run du")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "du failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| anyhow!("unexpected du output"))
+}
+
+/// The pool holding `dataset` and how full it is, in percent.
+pub async fn pool_capacity(dataset: &str) -> anyhow::Result<(String, u64)> {
+    let pool = dataset.split('/').next().unwrap_or(dataset).to_string();
+    let output = Command::new("zpool")
+        .args(["list", "-Hp", "-o", "capacity"])
+        .arg(&pool)
+        .output()
+        .await
+        .context("failed to run zpool")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "zpool list failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let capacity = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .trim_end_matches('%')
+        .parse::<u64>()
+        .map_err(|_| anyhow!("unexpected zpool output"))?;
+    Ok((pool, capacity))
+}
@@ -4,8 +4,12 @@ use anyhow::{anyhow, Context};
 use tokio::process::Command;
 use uuid::Uuid;
 
-use super::{ZfsBranchMetadata, ZfsProjectConfig};
-use crate::backends::local::model::{Branch, Project};
+use super::space::Usage;
+use super::{ZfsBranchMetadata, ZfsProjectConfig, ZfsSnapshotMetadata};
+use crate::backends::local::model::{Branch, Project, Snapshot};
+use crate::config::ZfsLimitsConfig;
+
+const BASE_SNAPSHOT: &str = "pgbranch_base";
 
 #[derive(Debug, Clone)]
 pub struct BackendDetection {
@@ -15,11 +19,17 @@ pub struct BackendDetection {
 }
 
 #[derive(Debug, Default, Clone)]
-pub struct ZfsDriver;
+pub struct ZfsDriver {
+    limits: ZfsLimitsConfig,
+}
 
 impl ZfsDriver {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    pub fn with_limits(limits: ZfsLimitsConfig) -> Self {
+        Self { limits }
     }
 
     pub async fn detect(&self, projects_root: &Path) -> BackendDetection {
@@ -149,10 +159,12 @@ impl ZfsDriver {
         let branch_dataset = branch_dataset_name(config, &project.id, branch_id);
 
         ensure_dataset_exists(&project_dataset).await?;
+        self.apply_project_limits(&project_dataset).await?;
         ensure_dataset_exists(&format!("{project_dataset}/branches")).await?;
         ensure_dataset_absent(&branch_dataset).await?;
 
         create_dataset_with_mountpoint(&branch_dataset, branch_root).await?;
+        self.apply_branch_limits(&branch_dataset).await?;
         tokio::fs::create_dir_all(data_dir)
             .await
             .with_context(|| format!("failed to create '{}'", data_dir.display()))?;
@@ -176,14 +188,15 @@ impl ZfsDriver {
         child_data_dir: &Path,
     ) -> anyhow::Result<Option<String>> {
         let parent_metadata = parse_zfs_branch_metadata(parent)?;
-        let child_branch_root = branch_root_from_data_dir(child_data_dir)?;
-
         let child_dataset = branch_dataset_name(config, &project.id, child_branch_id);
         ensure_dataset_absent(&child_dataset).await?;
 
         let snapshot_name = format!("pgbranch_{}", short_id(child_branch_id));
         let snapshot_full = format!("{}@{}", parent_metadata.dataset, snapshot_name);
 
+        // Left behind by the destroyed dataset when a branch is re-cloned
+        ensure_dataset_absent(&snapshot_full).await?;
+
         zfs_output_os(vec![
             OsString::from("snapshot"),
             OsString::from(snapshot_full.clone()),
@@ -192,16 +205,91 @@ impl ZfsDriver {
         .with_context(|| format!("failed to create ZFS snapshot '{snapshot_full}'"))?
         .success_or_stderr()?;
 
+        self.clone_snapshot(
+            project,
+            config,
+            &snapshot_full,
+            &child_dataset,
+            child_data_dir,
+        )
+        .await?;
+
+        let metadata = ZfsBranchMetadata {
+            dataset: child_dataset,
+            origin_snapshot: Some(snapshot_full),
+        };
+
+        Ok(Some(
+            serde_json::to_string(&metadata).context("failed to serialize ZFS branch metadata")?,
+        ))
+    }
+
+    /// Clone a named snapshot of the parent. The clone depends on the
+    /// snapshot rather than owning it, so deleting the branch leaves it be.
+    pub async fn clone_from_snapshot(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        snapshot: &Snapshot,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Option<String>> {
+        let snapshot_full = parse_zfs_snapshot_metadata(snapshot)?.snapshot;
+        let child_dataset = branch_dataset_name(config, &project.id, child_branch_id);
+        ensure_dataset_absent(&child_dataset).await?;
+
+        self.clone_snapshot(
+            project,
+            config,
+            &snapshot_full,
+            &child_dataset,
+            child_data_dir,
+        )
+        .await?;
+
+        let metadata = ZfsBranchMetadata {
+            dataset: child_dataset,
+            origin_snapshot: None,
+        };
+
+        Ok(Some(
+            serde_json::to_string(&metadata).context("failed to serialize ZFS branch metadata")?,
+        ))
+    }
+
+    async fn clone_snapshot(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        snapshot_full: &str,
+        child_dataset: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let child_branch_root = branch_root_from_data_dir(child_data_dir)?;
+
         zfs_output_os(vec![
             OsString::from("clone"),
             OsString::from("-o"),
             OsString::from(format!("mountpoint={}", child_branch_root.display())),
-            OsString::from(snapshot_full.clone()),
-            OsString::from(child_dataset.clone()),
+            OsString::from(snapshot_full),
+            OsString::from(child_dataset),
         ])
         .await
         .with_context(|| format!("failed to create ZFS clone '{child_dataset}'"))?
         .success_or_stderr()?;
+        self.apply_project_limits(&project_dataset_name(config, &project.id))
+            .await?;
+        self.apply_branch_limits(child_dataset).await?;
+
+        // Branch-local copy of the origin, so reset can roll back in place
+        let base_snapshot = format!("{child_dataset}@{BASE_SNAPSHOT}");
+        zfs_output_os(vec![
+            OsString::from("snapshot"),
+            OsString::from(base_snapshot.clone()),
+        ])
+        .await
+        .with_context(|| format!("failed to create ZFS snapshot '{base_snapshot}'"))?
+        .success_or_stderr()?;
 
         tokio::fs::create_dir_all(child_data_dir)
             .await
@@ -212,14 +300,7 @@ impl ZfsDriver {
                 )
             })?;
 
-        let metadata = ZfsBranchMetadata {
-            dataset: child_dataset,
-            origin_snapshot: Some(snapshot_full),
-        };
-
-        Ok(Some(
-            serde_json::to_string(&metadata).context("failed to serialize ZFS branch metadata")?,
-        ))
+        Ok(())
     }
 
     pub async fn delete_branch(
@@ -250,6 +331,304 @@ impl ZfsDriver {
 
         Ok(())
     }
+
+    /// Roll a cloned branch back to the state it was cloned with. Returns
+    /// `false` when the branch has no base snapshot (created empty, or by an
+    /// older pgbranch) so the caller can fall back to re-cloning.
+    pub async fn rollback_to_base(&self, branch: &Branch) -> anyhow::Result<bool> {
+        let metadata = parse_zfs_branch_metadata(branch)?;
+        let base_snapshot = format!("{}@{BASE_SNAPSHOT}", metadata.dataset);
+
+        if !dataset_exists(&base_snapshot).await? {
+            return Ok(false);
+        }
+
+        zfs_output_os(vec![
+            OsString::from("rollback"),
+            OsString::from("-r"),
+            OsString::from(base_snapshot.clone()),
+        ])
+        .await
+        .with_context(|| format!("failed to roll back to ZFS snapshot '{base_snapshot}'"))?
+        .success_or_stderr()?;
+
+        Ok(true)
+    }
+
+    pub async fn snapshot_branch(
+        &self,
+        branch: &Branch,
+        snapshot_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let metadata = parse_zfs_branch_metadata(branch)?;
+        let snapshot_full = format!(
+            "{}@pgbranch_snap_{}",
+            metadata.dataset,
+            short_id(snapshot_id)
+        );
+
+        zfs_output_os(vec![
+            OsString::from("snapshot"),
+            OsString::from(snapshot_full.clone()),
+        ])
+        .await
+        .with_context(|| format!("failed to create ZFS snapshot '{snapshot_full}'"))?
+        .success_or_stderr()?;
+
+        let metadata = ZfsSnapshotMetadata {
+            snapshot: snapshot_full,
+        };
+
+        Ok(Some(
+            serde_json::to_string(&metadata)
+                .context("failed to serialize ZFS snapshot metadata")?,
+        ))
+    }
+
+    /// Roll the branch dataset back to one of its snapshots. Newer snapshots
+    /// of the dataset are destroyed by ZFS.
+    pub async fn rollback_to_snapshot(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let metadata = parse_zfs_snapshot_metadata(snapshot)?;
+
+        zfs_output_os(vec![
+            OsString::from("rollback"),
+            OsString::from("-r"),
+            OsString::from(metadata.snapshot.clone()),
+        ])
+        .await
+        .with_context(|| {
+            format!(
+                "failed to roll back to ZFS snapshot '{}'",
+                metadata.snapshot
+            )
+        })?
+        .success_or_stderr()?;
+
+        Ok(())
+    }
+
+    pub async fn delete_snapshot(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let metadata = parse_zfs_snapshot_metadata(snapshot)?;
+
+        // Already gone if the dataset was rolled back past it or recreated
+        if !dataset_exists(&metadata.snapshot).await? {
+            return Ok(());
+        }
+
+        // Deferred, so a snapshot branches were created from goes once the last of them does
+        zfs_output_os(vec![
+            OsString::from("destroy"),
+            OsString::from("-d"),
+            OsString::from(metadata.snapshot.clone()),
+        ])
+        .await
+        .with_context(|| format!("failed to destroy ZFS snapshot '{}'", metadata.snapshot))?
+        .success_or_stderr()?;
+
+        Ok(())
+    }
+
+    /// The commands `create_empty` runs, for `--dry-run`.
+    pub fn plan_create_empty(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        branch_id: &str,
+        data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        let branch_root = branch_root_from_data_dir(data_dir)?;
+        let branch_dataset = branch_dataset_name(config, &project.id, branch_id);
+        let mut commands = self.plan_project_limits(&project_dataset_name(config, &project.id));
+        commands.push(format!(
+            "zfs create -p -o mountpoint={} {branch_dataset}",
+            branch_root.display()
+        ));
+        commands.extend(self.plan_branch_limits(&branch_dataset));
+        Ok(commands)
+    }
+
+    /// The commands `clone_from_parent` runs, for `--dry-run`.
+    pub fn plan_clone_from_parent(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        parent: &Branch,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        let parent_metadata = parse_zfs_branch_metadata(parent)?;
+        let snapshot_full = format!(
+            "{}@pgbranch_{}",
+            parent_metadata.dataset,
+            short_id(child_branch_id)
+        );
+        let mut commands = vec![format!("zfs snapshot {snapshot_full}")];
+        commands.extend(self.plan_clone_snapshot(
+            project,
+            config,
+            &snapshot_full,
+            child_branch_id,
+            child_data_dir,
+        )?);
+        Ok(commands)
+    }
+
+    /// The commands `clone_from_snapshot` runs, for `--dry-run`.
+    pub fn plan_clone_from_snapshot(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        snapshot: &Snapshot,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        let snapshot_full = parse_zfs_snapshot_metadata(snapshot)?.snapshot;
+        self.plan_clone_snapshot(
+            project,
+            config,
+            &snapshot_full,
+            child_branch_id,
+            child_data_dir,
+        )
+    }
+
+    fn plan_clone_snapshot(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+        snapshot_full: &str,
+        child_branch_id: &str,
+        child_data_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        let child_branch_root = branch_root_from_data_dir(child_data_dir)?;
+        let child_dataset = branch_dataset_name(config, &project.id, child_branch_id);
+        let mut commands = vec![format!(
+            "zfs clone -o mountpoint={} {snapshot_full} {child_dataset}",
+            child_branch_root.display()
+        )];
+        commands.extend(self.plan_project_limits(&project_dataset_name(config, &project.id)));
+        commands.extend(self.plan_branch_limits(&child_dataset));
+        commands.push(format!("zfs snapshot {child_dataset}@{BASE_SNAPSHOT}"));
+        Ok(commands)
+    }
+
+    /// Start a TRIM of the pool holding the branch, so blocks ZFS has freed
+    /// go back to the device (or the sparse file backing the pool). Returns
+    /// the pool's name; the trim carries on in the background.
+    pub async fn trim_pool(&self, branch: &Branch) -> anyhow::Result<String> {
+        let dataset = parse_zfs_branch_metadata(branch)?.dataset;
+        let pool = dataset.split('/').next().unwrap_or(&dataset).to_string();
+        let output = Command::new("zpool")
+            .args(["trim", &pool])
+            .output()
+            .await
+            .context("failed to execute zpool trim")?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // A trim still running from an earlier compact is just as good
+        if output.status.success() || stderr.contains("currently trimming") {
+            return Ok(pool);
+        }
+        Err(anyhow!("zpool trim {pool} failed: {}", stderr.trim()))
+    }
+
+    /// Space used by the project's dataset and what its quota leaves.
+    pub async fn project_usage(
+        &self,
+        project: &Project,
+        config: &ZfsProjectConfig,
+    ) -> anyhow::Result<Usage> {
+        dataset_usage(&project_dataset_name(config, &project.id)).await
+    }
+
+    /// Space used by a branch's dataset and what its quota leaves.
+    pub async fn branch_usage(&self, branch: &Branch) -> anyhow::Result<Usage> {
+        dataset_usage(&parse_zfs_branch_metadata(branch)?.dataset).await
+    }
+
+    // Set on every create so changes to the config reach existing projects
+    async fn apply_project_limits(&self, dataset: &str) -> anyhow::Result<()> {
+        set_properties(dataset, &self.project_limits()).await
+    }
+
+    async fn apply_branch_limits(&self, dataset: &str) -> anyhow::Result<()> {
+        set_properties(dataset, &self.branch_limits()).await
+    }
+
+    fn plan_project_limits(&self, dataset: &str) -> Vec<String> {
+        plan_properties(dataset, &self.project_limits())
+    }
+
+    fn plan_branch_limits(&self, dataset: &str) -> Vec<String> {
+        plan_properties(dataset, &self.branch_limits())
+    }
+
+    fn project_limits(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("quota", self.limits.quota.as_deref()),
+            ("reservation", self.limits.reservation.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(property, value)| Some((property, value?)))
+        .collect()
+    }
+
+    fn branch_limits(&self) -> Vec<(&'static str, &str)> {
+        self.limits
+            .branch_quota
+            .as_deref()
+            .map(|quota| ("quota", quota))
+            .into_iter()
+            .collect()
+    }
+
+    /// The commands `delete_branch` runs, for `--dry-run`.
+    pub fn plan_delete_branch(&self, branch: &Branch) -> anyhow::Result<Vec<String>> {
+        let metadata = parse_zfs_branch_metadata(branch)?;
+        let mut commands = vec![format!("zfs destroy -r {}", metadata.dataset)];
+        if let Some(snapshot) = metadata.origin_snapshot {
+            commands.push(format!("zfs destroy {snapshot}"));
+        }
+        commands.push(format!(
+            "rm -rf {}",
+            branch_root_from_data_dir(Path::new(&branch.data_dir))?.display()
+        ));
+        Ok(commands)
+    }
+
+    /// The command `rollback_to_base` runs, for `--dry-run`.
+    pub fn plan_rollback_to_base(&self, branch: &Branch) -> anyhow::Result<String> {
+        let metadata = parse_zfs_branch_metadata(branch)?;
+        Ok(format!(
+            "zfs rollback -r {}@{BASE_SNAPSHOT}",
+            metadata.dataset
+        ))
+    }
+
+    /// The command `rollback_to_snapshot` runs, for `--dry-run`.
+    pub fn plan_rollback_to_snapshot(&self, snapshot: &Snapshot) -> anyhow::Result<String> {
+        let metadata = parse_zfs_snapshot_metadata(snapshot)?;
+        Ok(format!("zfs rollback -r {}", metadata.snapshot))
+    }
+
+    /// The command `delete_snapshot` runs, for `--dry-run`.
+    pub fn plan_delete_snapshot(&self, snapshot: &Snapshot) -> anyhow::Result<String> {
+        let metadata = parse_zfs_snapshot_metadata(snapshot)?;
+        Ok(format!("zfs destroy -d {}", metadata.snapshot))
+    }
+}
+
+fn parse_zfs_snapshot_metadata(snapshot: &Snapshot) -> anyhow::Result<ZfsSnapshotMetadata> {
+    let raw = snapshot
+        .storage_metadata
+        .as_ref()
+        .ok_or_else(|| anyhow!("snapshot '{}' is missing ZFS storage metadata", snapshot.id))?;
+
+    serde_json::from_str(raw).with_context(|| {
+        format!(
+            "snapshot '{}' has invalid ZFS storage metadata: {}",
+            snapshot.id, raw
+        )
+    })
 }
 
 fn detect_dataset_from_mountpoints(projects_root: &Path, zfs_list_output: &str) -> Option<String> {
@@ -351,6 +730,53 @@ async fn create_dataset_with_mountpoint(dataset: &str, mountpoint: &Path) -> any
     Ok(())
 }
 
+async fn set_properties(dataset: &str, properties: &[(&str, &str)]) -> anyhow::Result<()> {
+    for (property, value) in properties {
+        zfs_output_os(vec![
+            OsString::from("set"),
+            OsString::from(format!("{property}={value}")),
+            OsString::from(dataset.to_string()),
+        ])
+        .await?
+        .success_or_stderr()
+        .with_context(|| format!("failed to set {property}={value} on ZFS dataset '{dataset}'"))?;
+    }
+    Ok(())
+}
+
+fn plan_properties(dataset: &str, properties: &[(&str, &str)]) -> Vec<String> {
+    properties
+        .iter()
+        .map(|(property, value)| format!("zfs set {property}={value} {dataset}"))
+        .collect()
+}
+
+/// `used`, `available` and `quota` of a dataset; `available` already
+/// accounts for the quota and the free space in the pool.
+async fn dataset_usage(dataset: &str) -> anyhow::Result<Usage> {
+    let output = zfs_output(["get", "-Hp", "-o", "value", "used,available,quota", dataset]).await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to read space of ZFS dataset '{dataset}': {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let values: Vec<u64> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().parse::<u64>())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("unexpected zfs get output for '{dataset}'"))?;
+    let [used, available, quota] = values[..] else {
+        return Err(anyhow!("unexpected zfs get output for '{dataset}'"));
+    };
+    Ok(Usage {
+        used_bytes: used,
+        // A quota of 0 means there is none
+        quota_bytes: (quota > 0).then_some(quota),
+        available_bytes: Some(available),
+    })
+}
+
 async fn ensure_dataset_absent(dataset: &str) -> anyhow::Result<()> {
     if !dataset_exists(dataset).await? {
         return Ok(());
@@ -0,0 +1,43 @@
+//! Warming up a branch that just started. Its shared buffers and the OS page
+//! cache are cold, so the first requests against it are slow; running the
+//! app's typical queries up front moves that wait into pgbranch.
+
+use futures_util::future::join_all;
+
+use super::{model, LocalBackend};
+
+impl LocalBackend {
+    /// Run the configured warmup queries on `connections` connections at once.
+    /// Failures only warn: a cold branch still works. `started` is when the
+    /// warmup phase began, for `--timings`.
+    pub(super) async fn warm_up(&self, branch: &model::Branch, started: std::time::Instant) {
+        let Some(ref warmup) = self.warmup else {
+            return;
+        };
+        let credentials = self.branch_credentials(branch);
+        let connections = warmup.connections.unwrap_or(1).max(1);
+
+        // One psql session per connection, running all queries in order
+        let sql = warmup
+            .queries
+            .iter()
+            .map(|query| query.trim().trim_end_matches(';'))
+            .collect::<Vec<_>>()
+            .join(";\n");
+        let sessions = (0..connections).map(|_| {
+            self.runtime.query(
+                &branch.container_name,
+                &credentials.user,
+                &credentials.db,
+                &sql,
+            )
+        });
+        for result in join_all(sessions).await {
+            if let Err(e) = result {
+                log::warn!("Warmup of branch '{}' failed: {:#}", branch.name, e);
+                break;
+            }
+        }
+        self.record_phase("warmup", started);
+    }
+}
@@ -0,0 +1,182 @@
+//! A backend without a database, for end-to-end tests of the CLI that can't
+//! count on Docker or PostgreSQL. Branches are plain records: creating one
+//! records it, connecting to one gives a made-up address. Its behavior is
+//! deterministic, down to the creation times, which start at a fixed date
+//! and go up a minute per branch, so outputs can be compared as they are.
+//!
+//! Each command is a new process, so the records are kept in a JSON file
+//! under the data directory, one per configured database. It's compiled in
+//! with the `backend-mock` feature and selected with `type: mock`.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport};
+use crate::config::Config;
+use crate::errors::coded;
+
+/// Creation time of the first branch; each later one is a minute after.
+const EPOCH: &str = "2024-01-01T00:00:00Z";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MockState {
+    /// Branches created so far, deleted ones included, for creation times
+    created: i64,
+    branches: Vec<BranchInfo>,
+}
+
+pub struct MockBackend {
+    config: Config,
+    path: PathBuf,
+    state: Mutex<MockState>,
+}
+
+impl MockBackend {
+    pub fn new(name: &str, config: &Config) -> Result<Self> {
+        let path = crate::paths::data_dir()
+            .join("mock")
+            .join(format!("{}.json", name));
+        let state = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => MockState::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            config: config.clone(),
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Change the records and write them back.
+    fn update<T>(&self, change: impl FnOnce(&mut MockState) -> Result<T>) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let result = change(&mut state)?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&*state)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(result)
+    }
+
+    fn find(&self, branch_name: &str) -> Result<BranchInfo> {
+        self.state
+            .lock()
+            .unwrap()
+            .branches
+            .iter()
+            .find(|b| b.name == branch_name)
+            .cloned()
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))
+    }
+}
+
+#[async_trait]
+impl DatabaseBranchingBackend for MockBackend {
+    async fn create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<BranchInfo> {
+        let database_name = self.config.get_database_name(branch_name);
+        self.update(|state| {
+            if state.branches.iter().any(|b| b.name == branch_name) {
+                return Err(coded!(
+                    BranchExists,
+                    "Branch '{}' already exists",
+                    branch_name
+                ));
+            }
+            if let Some(parent) = from_branch {
+                if !state.branches.iter().any(|b| b.name == parent) {
+                    return Err(coded!(
+                        BranchNotFound,
+                        "Parent branch '{}' not found",
+                        parent
+                    ));
+                }
+            }
+            let epoch: DateTime<Utc> = EPOCH.parse()?;
+            let branch = BranchInfo {
+                name: branch_name.to_string(),
+                created_at: Some(epoch + Duration::minutes(state.created)),
+                parent_branch: from_branch.map(str::to_string),
+                database_name,
+                state: Some("running".to_string()),
+                health: None,
+                last_used_at: None,
+            };
+            state.created += 1;
+            state.branches.push(branch.clone());
+            Ok(branch)
+        })
+    }
+
+    async fn delete_branch(&self, branch_name: &str) -> Result<()> {
+        self.update(|state| {
+            let before = state.branches.len();
+            state.branches.retain(|b| b.name != branch_name);
+            if state.branches.len() == before {
+                return Err(coded!(BranchNotFound, "Branch '{}' not found", branch_name));
+            }
+            Ok(())
+        })
+    }
+
+    async fn list_branches(&self) -> Result<Vec<BranchInfo>> {
+        Ok(self.state.lock().unwrap().branches.clone())
+    }
+
+    async fn branch_exists(&self, branch_name: &str) -> Result<bool> {
+        Ok(self.find(branch_name).is_ok())
+    }
+
+    async fn switch_to_branch(&self, branch_name: &str) -> Result<BranchInfo> {
+        self.find(branch_name)
+    }
+
+    async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo> {
+        let branch = self.find(branch_name)?;
+        Ok(ConnectionInfo {
+            host: "localhost".to_string(),
+            port: 5432,
+            connection_string: Some(format!(
+                "postgresql://postgres@localhost:5432/{}",
+                branch.database_name
+            )),
+            database: branch.database_name,
+            user: "postgres".to_string(),
+            password: None,
+        })
+    }
+
+    async fn doctor(&self) -> Result<DoctorReport> {
+        Ok(DoctorReport {
+            checks: vec![DoctorCheck {
+                name: "Mock state".to_string(),
+                available: true,
+                detail: self.path.display().to_string(),
+            }],
+        })
+    }
+
+    async fn test_connection(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "Mock"
+    }
+
+    fn backend_type(&self) -> &'static str {
+        "mock"
+    }
+}
@@ -3,6 +3,8 @@ pub mod dblab;
 pub mod factory;
 #[cfg(feature = "backend-local")]
 pub mod local;
+#[cfg(feature = "backend-mock")]
+pub mod mock;
 #[cfg(feature = "backend-neon")]
 pub mod neon;
 #[cfg(feature = "backend-postgres-template")]
@@ -14,6 +16,9 @@ use anyhow::Result;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::errors::coded;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BranchInfo {
@@ -23,6 +28,13 @@ pub struct BranchInfo {
     pub database_name: String,
     #[serde(default)]
     pub state: Option<String>,
+    /// Why a running branch's PostgreSQL isn't accepting connections, for
+    /// backends that check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<String>,
+    /// Last switch, connection or start, for backends that track it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +66,192 @@ pub struct DoctorCheck {
     pub detail: String,
 }
 
+/// Time spent in one step of an operation, for `--timings`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub duration_ms: u64,
+}
+
+/// One normalized statement from `pg_stat_statements`, for `pgbranch top`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryStat {
+    pub database: String,
+    pub calls: u64,
+    pub total_ms: f64,
+    pub mean_ms: f64,
+    pub rows: u64,
+    pub query: String,
+}
+
+/// Row count of a table in a branch, and its checksum if asked, for
+/// `pgbranch diff --data`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableData {
+    /// `schema.table`
+    pub table: String,
+    pub rows: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+/// SQL that loads a branch's rows again, for `pgbranch fixture export`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureExport {
+    pub sql: String,
+    /// Tables with exported rows, in the order they are loaded
+    pub tables: Vec<FixtureTable>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureTable {
+    pub table: String,
+    pub rows: usize,
+}
+
+/// A branch's container changing state outside pgbranch, e.g. stopped with
+/// `docker stop` or removed by a prune, for `status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftEvent {
+    pub branch: String,
+    pub from_state: String,
+    pub to_state: String,
+    pub reason: String,
+    /// When pgbranch noticed, which can be well after it happened
+    pub detected_at: DateTime<Utc>,
+}
+
+/// A program to run on this machine, e.g. the container engine's CLI to
+/// reach into a branch's container, for `pgbranch psql`.
+#[derive(Debug, Clone)]
+pub struct HostCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Set on top of the inherited environment
+    pub env: Vec<(String, String)>,
+}
+
+/// A named snapshot of a branch, for `pgbranch snapshot list`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub branch: String,
+    pub name: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Disk space taken by a project or one of its branches, for `status` and `du`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpaceUsage {
+    /// `None` for the project as a whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub used_bytes: u64,
+    /// ZFS quota, if one is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    /// How much more it can take within its quota and the pool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+}
+
+/// What `pgbranch compact` did to a branch and the space it gave back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactReport {
+    pub steps: Vec<String>,
+    pub used_before_bytes: u64,
+    pub used_after_bytes: u64,
+    /// Growth of the free space on the filesystem holding the branches
+    pub reclaimed_bytes: u64,
+}
+
+/// Something that happened to a branch, for `pgbranch events`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchEvent {
+    pub time: DateTime<Utc>,
+    /// The branch, if the event could be matched to one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// `docker` for engine events, `state` for changes to pgbranch's records
+    pub source: String,
+    pub message: String,
+}
+
+/// Bytes as MiB, or GiB from 1 GiB up.
+pub fn format_bytes(bytes: u64) -> String {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+    if bytes as f64 >= GIB {
+        format!("{:.1} GiB", bytes as f64 / GIB)
+    } else {
+        format!("{} MiB", bytes / (1024 * 1024))
+    }
+}
+
+/// One step of a mutating operation, printed by `--dry-run` instead of running it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedAction {
+    /// What the step acts on: `docker`, `storage`, `state`, `postgres`, `api` or
+    /// `post-commands`, or the backend type for a step that can't be planned in detail
+    pub target: String,
+    pub description: String,
+}
+
+impl PlannedAction {
+    pub fn new(target: &str, description: impl Into<String>) -> Self {
+        Self {
+            target: target.to_string(),
+            description: description.into(),
+        }
+    }
+}
+
+/// Memory and CPU limits of a branch's container; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<i64>,
+    /// Billionths of a CPU, as Docker counts them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nano_cpus: Option<i64>,
+}
+
+/// What `pgbranch adopt` recorded again from a destroy manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct AdoptReport {
+    pub project: String,
+    pub adopted: Vec<String>,
+    pub skipped: Vec<SkippedBranch>,
+}
+
+/// A branch of the manifest that wasn't adopted, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedBranch {
+    pub name: String,
+    pub reason: String,
+}
+
+/// How much of a project `destroy` removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DestroyScope {
+    /// Containers, data and the project's records
+    All,
+    /// Only the containers; `start` recreates them from the data left in place
+    Containers,
+    /// Containers and every branch's data; the project and its settings stay
+    Branches,
+}
+
+/// On-disk data of a local branch, used to copy it into another local project.
+#[derive(Debug, Clone)]
+pub struct BranchDataSource {
+    pub data_root: PathBuf,
+    pub data_dir: PathBuf,
+    pub container_name: String,
+    pub image: String,
+    pub pg_user: String,
+    pub pg_password: String,
+    pub pg_db: String,
+}
+
 #[async_trait]
 #[allow(dead_code)]
 pub trait DatabaseBranchingBackend: Send + Sync {
@@ -89,15 +287,111 @@ pub trait DatabaseBranchingBackend: Send + Sync {
     async fn stop_branch(&self, _branch_name: &str) -> Result<()> {
         Ok(())
     }
-    async fn reset_branch(&self, _branch_name: &str) -> Result<()> {
+    async fn reset_branch(
+        &self,
+        _branch_name: &str,
+        _to_snapshot: Option<&str>,
+        _keep_snapshots: bool,
+    ) -> Result<()> {
         Ok(())
     }
     fn supports_lifecycle(&self) -> bool {
         false
     }
+    /// Whether each branch is a server of its own, reachable from this machine
+    /// under the same database name and credentials, so `pgbranch proxy` can
+    /// switch between them by address alone.
+    fn supports_proxy(&self) -> bool {
+        false
+    }
+
+    // Named snapshots of a branch (local backend); `replace` retakes an existing one
+    async fn create_snapshot(
+        &self,
+        _branch_name: &str,
+        _snapshot_name: &str,
+        _replace: bool,
+    ) -> Result<()> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support snapshots"
+        ))
+    }
+    /// Snapshots of one branch, or of every branch of the database, oldest first.
+    async fn list_snapshots(&self, _branch_name: Option<&str>) -> Result<Vec<SnapshotInfo>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support snapshots"
+        ))
+    }
+    fn supports_snapshots(&self) -> bool {
+        false
+    }
+
+    /// Make a branch's database role use the configured password (local backend).
+    async fn set_branch_password(&self, _branch_name: &str) -> Result<()> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support changing branch passwords"
+        ))
+    }
+
+    /// Whether new sessions on the branch default to read-only transactions.
+    async fn branch_read_only(&self, _branch_name: &str) -> Result<bool> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support read-only branches"
+        ))
+    }
+    async fn set_branch_read_only(&self, _branch_name: &str, _read_only: bool) -> Result<()> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support read-only branches"
+        ))
+    }
+    fn supports_read_only(&self) -> bool {
+        false
+    }
+
+    /// Memory and CPU limits of a branch's container (local backend).
+    async fn branch_limits(&self, _branch_name: &str) -> Result<ContainerLimits> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support resource limits"
+        ))
+    }
+    async fn set_branch_limits(&self, _branch_name: &str, _limits: &ContainerLimits) -> Result<()> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support resource limits"
+        ))
+    }
+    fn supports_limits(&self) -> bool {
+        false
+    }
+    /// Change the PostgreSQL settings a branch's server starts with (local
+    /// backend). An empty value removes the override. A running branch is
+    /// restarted so they take effect.
+    async fn set_branch_pg_settings(
+        &self,
+        _branch_name: &str,
+        _settings: &[(String, String)],
+    ) -> Result<()> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support per-branch PostgreSQL settings"
+        ))
+    }
+
+    /// Per-phase timings of the operations since the last call, oldest first.
+    fn take_phase_timings(&self) -> Vec<PhaseTiming> {
+        Vec::new()
+    }
 
     // Cleanup
-    async fn cleanup_old_branches(&self, max_count: usize) -> Result<Vec<String>> {
+    /// Branches beyond the `max_count` most recently used (or created), which
+    /// cleanup deletes, least recently used last.
+    async fn cleanup_candidates(&self, max_count: usize) -> Result<Vec<String>> {
         if !self.supports_cleanup() {
             return Ok(vec![]);
         }
@@ -108,19 +402,73 @@ pub trait DatabaseBranchingBackend: Send + Sync {
             .filter(|b| b.name != "main" && b.name != "master")
             .collect();
 
-        sorted_branches.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        sorted_branches.sort_by_key(|b| std::cmp::Reverse(b.last_used_at.or(b.created_at)));
 
-        let mut deleted = Vec::new();
-        if sorted_branches.len() > max_count {
-            for branch in sorted_branches.into_iter().skip(max_count) {
-                match self.delete_branch(&branch.name).await {
-                    Ok(_) => deleted.push(branch.name),
-                    Err(e) => log::warn!("Failed to delete branch {}: {}", branch.name, e),
-                }
-            }
-        }
+        Ok(sorted_branches
+            .into_iter()
+            .skip(max_count)
+            .map(|b| b.name)
+            .collect())
+    }
 
-        Ok(deleted)
+    // Dry runs: the steps an operation would take, without taking them
+    async fn plan_create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        let description = match from_branch {
+            Some(parent) => format!(
+                "Create branch '{}' from '{}' through the {} API",
+                branch_name,
+                parent,
+                self.backend_name()
+            ),
+            None => format!(
+                "Create branch '{}' through the {} API",
+                branch_name,
+                self.backend_name()
+            ),
+        };
+        Ok(vec![PlannedAction::new("api", description)])
+    }
+    async fn plan_delete_branch(&self, branch_name: &str) -> Result<Vec<PlannedAction>> {
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!(
+                "Delete branch '{}' through the {} API",
+                branch_name,
+                self.backend_name()
+            ),
+        )])
+    }
+    async fn plan_reset_branch(
+        &self,
+        _branch_name: &str,
+        _to_snapshot: Option<&str>,
+        _keep_snapshots: bool,
+    ) -> Result<Vec<PlannedAction>> {
+        Ok(vec![])
+    }
+    async fn plan_seed_from_source(
+        &self,
+        _branch_name: &str,
+        _source: &str,
+    ) -> Result<Vec<PlannedAction>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support seeding from external sources"
+        ))
+    }
+    async fn plan_create_schema_only_branch(
+        &self,
+        _branch_name: &str,
+        _from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support schema-only branches"
+        ))
     }
 
     // Project destruction (local backend)
@@ -130,13 +478,215 @@ pub trait DatabaseBranchingBackend: Send + Sync {
     async fn destroy_preview(&self) -> Result<Option<(String, Vec<String>)>> {
         Ok(None)
     }
-    async fn destroy_project(&self) -> Result<Vec<String>> {
-        anyhow::bail!("This backend does not support project destruction")
+    /// Remove what `scope` covers and return the branches it affected.
+    async fn destroy_project(&self, _scope: DestroyScope) -> Result<Vec<String>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support project destruction"
+        ))
+    }
+    /// Save the project's records before destroying it, so `adopt_manifest`
+    /// can bring back branches whose data survived. `None` without a project.
+    async fn write_destroy_manifest(&self) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+    async fn adopt_manifest(&self, _path: &std::path::Path) -> Result<AdoptReport> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support adopting branches"
+        ))
+    }
+    /// Start again the branches that were running when their containers
+    /// were removed outside pgbranch, e.g. by `docker system prune`.
+    async fn adopt_pruned(&self) -> Result<AdoptReport> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support adopting branches"
+        ))
+    }
+    /// What could remove the branches' containers behind pgbranch's back,
+    /// for `pgbranch guard`.
+    async fn prune_guard(&self) -> Result<DoctorReport> {
+        Err(coded!(
+            Unsupported,
+            "This backend has no containers to guard"
+        ))
+    }
+
+    // Copying branches between local projects
+    async fn branch_data_source(&self, _branch_name: &str) -> Result<Option<BranchDataSource>> {
+        Ok(None)
+    }
+    /// Clone `source` directly. Returns `None` when the data can't be shared
+    /// (different storage root, image or user); callers fall back to pg_dump.
+    async fn create_branch_from_data(
+        &self,
+        _branch_name: &str,
+        _source: &BranchDataSource,
+    ) -> Result<Option<BranchInfo>> {
+        Ok(None)
     }
 
     // Data seeding
     async fn seed_from_source(&self, _branch_name: &str, _source: &str) -> Result<()> {
-        anyhow::bail!("This backend does not support seeding from external sources")
+        Err(coded!(
+            Unsupported,
+            "This backend does not support seeding from external sources"
+        ))
+    }
+    /// Create an empty branch and seed it from `source` (URL, dump file or
+    /// s3:// object), e.g. to copy a branch over from another backend.
+    async fn create_branch_from_source(
+        &self,
+        _branch_name: &str,
+        _source: &str,
+    ) -> Result<BranchInfo> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support creating branches from external sources"
+        ))
+    }
+    /// Clone `from_branch` (or the main branch) and empty every table but the
+    /// migration history, for migration testing without the data.
+    async fn create_schema_only_branch(
+        &self,
+        _branch_name: &str,
+        _from_branch: Option<&str>,
+    ) -> Result<BranchInfo> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support schema-only branches"
+        ))
+    }
+    /// Run the configured maintenance (e.g. ANALYZE) on a branch that was
+    /// just cloned or seeded. Returns the tasks that ran.
+    fn maintenance_tasks(&self) -> Vec<String> {
+        vec![]
+    }
+    async fn run_maintenance(&self, _branch_name: &str) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+    // Query statistics (local backend)
+    /// The `limit` statements with the most total execution time in the
+    /// branch's databases, optionally resetting the counters afterwards.
+    async fn top_queries(
+        &self,
+        _branch_name: &str,
+        _limit: usize,
+        _reset: bool,
+    ) -> Result<Vec<QueryStat>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support query statistics"
+        ))
+    }
+    /// Load pg_stat_statements in the branch, restarting it.
+    async fn enable_query_stats(&self, _branch_name: &str) -> Result<()> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support query statistics"
+        ))
+    }
+    // Data diffs (local backend)
+    /// Row counts of the tables in the branch's database, limited to `tables`
+    /// if any are given, with a checksum of their rows if asked.
+    async fn table_data(
+        &self,
+        _branch_name: &str,
+        _tables: &[String],
+        _checksum: bool,
+    ) -> Result<Vec<TableData>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support data diffs"
+        ))
+    }
+
+    /// INSERT statements for the rows of the branch's database, only those
+    /// its parent doesn't have with `since_parent`.
+    async fn export_fixture(
+        &self,
+        _branch_name: &str,
+        _since_parent: bool,
+    ) -> Result<FixtureExport> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support exporting fixtures"
+        ))
+    }
+
+    // Compaction (local backend)
+    /// Reclaim space in a branch, after a VACUUM FULL of its databases if asked.
+    async fn compact_branch(
+        &self,
+        _branch_name: &str,
+        _vacuum_full: bool,
+    ) -> Result<CompactReport> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support compacting branches"
+        ))
+    }
+
+    // SQL sessions (local backend)
+    /// Run SQL in the branch's database with psql and return its output. With
+    /// `record`, the SQL is appended to the branch's session script once it ran.
+    async fn exec_sql(&self, _branch_name: &str, _sql: &str, _record: bool) -> Result<String> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support running SQL"
+        ))
+    }
+    /// The script `exec --record` appends to for a branch.
+    async fn session_script(&self, _branch_name: &str) -> Result<std::path::PathBuf> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not support session recording"
+        ))
+    }
+
+    // Events (local backend)
+    /// Report what happens to the project's branches through `on_event`
+    /// until the event stream ends.
+    async fn watch_events(&self, _on_event: &mut (dyn FnMut(BranchEvent) + Send)) -> Result<()> {
+        Err(coded!(Unsupported, "This backend does not support events"))
+    }
+
+    // Drift (local backend)
+    /// Changes to the branches' containers made outside pgbranch and noticed
+    /// since `since`, oldest first.
+    async fn recent_drift(&self, _since: DateTime<Utc>) -> Result<Vec<DriftEvent>> {
+        Ok(vec![])
+    }
+
+    // Shell access (local backend)
+    /// The command that runs `psql` with `args` inside the branch's
+    /// container, for machines without a `psql` of their own.
+    async fn container_psql_command(
+        &self,
+        _branch_name: &str,
+        _args: &[String],
+    ) -> Result<Option<HostCommand>> {
+        Ok(None)
+    }
+
+    // Disk usage (local backend)
+    /// Space used by the project, where the backend can tell cheaply.
+    async fn project_space(&self) -> Result<Option<SpaceUsage>> {
+        Ok(None)
+    }
+    /// Space used by each branch, with its quota if it has one.
+    async fn branch_space(&self) -> Result<Vec<SpaceUsage>> {
+        Err(coded!(
+            Unsupported,
+            "This backend does not report disk usage"
+        ))
+    }
+    /// Clean up after a create, reset or seed of `branch_name` that was
+    /// interrupted part way: remove a branch that never finished provisioning
+    /// and undo anything left paused. Returns what was done, if anything.
+    async fn recover_interrupted(&self, _branch_name: &str) -> Result<Option<String>> {
+        Ok(None)
     }
 
     // Diagnostics
@@ -157,4 +707,7 @@ pub trait DatabaseBranchingBackend: Send + Sync {
 
     // Get backend display name
     fn backend_name(&self) -> &'static str;
+
+    // Get backend type identifier (matches the `type` key in config)
+    fn backend_type(&self) -> &'static str;
 }
@@ -1,4 +1,7 @@
-use super::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport};
+use super::{
+    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, PlannedAction,
+};
+use crate::errors::coded;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -109,6 +112,20 @@ impl NeonBackend {
             .with_context(|| "Failed to parse JSON response from Neon API")
     }
 
+    /// Path of a branch, for `DELETE` and friends; Neon addresses branches by ID.
+    async fn branch_path(&self, branch_name: &str) -> Result<String> {
+        let branches = self.list_branches().await?;
+        let branch = branches
+            .into_iter()
+            .find(|b| b.name == branch_name)
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+
+        Ok(format!(
+            "projects/{}/branches/{}",
+            self.project_id, branch.database_name
+        ))
+    }
+
     async fn get_branch_endpoint(&self, branch_name: &str) -> Result<NeonEndpoint> {
         let path = format!("projects/{}/endpoints", self.project_id);
         let response: ListEndpointsResponse = self
@@ -148,20 +165,13 @@ impl DatabaseBranchingBackend for NeonBackend {
             parent_branch: response.branch.parent_id,
             database_name: response.branch.id,
             state: Some("running".to_string()),
+            health: None,
+            last_used_at: None,
         })
     }
 
     async fn delete_branch(&self, branch_name: &str) -> Result<()> {
-        let branches = self.list_branches().await?;
-        let branch = branches
-            .into_iter()
-            .find(|b| b.name == branch_name)
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
-
-        let path = format!(
-            "projects/{}/branches/{}",
-            self.project_id, branch.database_name
-        );
+        let path = self.branch_path(branch_name).await?;
         let _: serde_json::Value = self
             .make_request(reqwest::Method::DELETE, &path, None::<&()>)
             .await?;
@@ -169,6 +179,31 @@ impl DatabaseBranchingBackend for NeonBackend {
         Ok(())
     }
 
+    async fn plan_create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        let parent = from_branch
+            .map(|p| format!(", parent_id: {}", p))
+            .unwrap_or_default();
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!(
+                "POST {}/projects/{}/branches (name: {}{})",
+                self.base_url, self.project_id, branch_name, parent
+            ),
+        )])
+    }
+
+    async fn plan_delete_branch(&self, branch_name: &str) -> Result<Vec<PlannedAction>> {
+        let path = self.branch_path(branch_name).await?;
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!("DELETE {}/{}", self.base_url, path),
+        )])
+    }
+
     async fn list_branches(&self) -> Result<Vec<BranchInfo>> {
         let path = format!("projects/{}/branches", self.project_id);
         let response: ListBranchesResponse = self
@@ -184,6 +219,8 @@ impl DatabaseBranchingBackend for NeonBackend {
                 parent_branch: branch.parent_id,
                 database_name: branch.id,
                 state: Some("running".to_string()),
+                health: None,
+                last_used_at: None,
             })
             .collect();
 
@@ -200,7 +237,7 @@ impl DatabaseBranchingBackend for NeonBackend {
         branches
             .into_iter()
             .find(|b| b.name == branch_name)
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' does not exist", branch_name))
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' does not exist", branch_name))
     }
 
     async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo> {
@@ -255,6 +292,10 @@ impl DatabaseBranchingBackend for NeonBackend {
         "Neon"
     }
 
+    fn backend_type(&self) -> &'static str {
+        "neon"
+    }
+
     fn supports_template_from_time(&self) -> bool {
         true
     }
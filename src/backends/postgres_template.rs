@@ -1,5 +1,7 @@
-use super::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport};
-use crate::config::Config;
+use super::{
+    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, PlannedAction,
+};
+use crate::config::{encode_userinfo, host_port, Config};
 use crate::database::DatabaseManager;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -42,6 +44,8 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
             parent_branch: _from_branch.map(|s| s.to_string()),
             database_name,
             state: Some("running".to_string()),
+            health: None,
+            last_used_at: None,
         })
     }
 
@@ -60,6 +64,8 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
                 parent_branch: None,
                 database_name: self.get_branch_database_name(&name),
                 state: Some("running".to_string()),
+                health: None,
+                last_used_at: None,
             })
             .collect();
 
@@ -81,6 +87,8 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
             parent_branch: None,
             database_name,
             state: Some("running".to_string()),
+            health: None,
+            last_used_at: None,
         })
     }
 
@@ -89,19 +97,17 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
 
         let connection_string = if let Some(ref password) = self.config.database.password {
             format!(
-                "postgresql://{}:{}@{}:{}/{}",
-                self.config.database.user,
-                password,
-                self.config.database.host,
-                self.config.database.port,
+                "postgresql://{}:{}@{}/{}",
+                encode_userinfo(&self.config.database.user),
+                encode_userinfo(password),
+                host_port(&self.config.database.host, self.config.database.port),
                 database_name
             )
         } else {
             format!(
-                "postgresql://{}@{}:{}/{}",
-                self.config.database.user,
-                self.config.database.host,
-                self.config.database.port,
+                "postgresql://{}@{}/{}",
+                encode_userinfo(&self.config.database.user),
+                host_port(&self.config.database.host, self.config.database.port),
                 database_name
             )
         };
@@ -116,9 +122,53 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
         })
     }
 
-    async fn cleanup_old_branches(&self, max_count: usize) -> Result<Vec<String>> {
-        self.db_manager.cleanup_old_branches(max_count).await?;
-        Ok(vec![])
+    async fn cleanup_candidates(&self, max_count: usize) -> Result<Vec<String>> {
+        self.db_manager.old_branches(max_count).await
+    }
+
+    async fn plan_create_branch(
+        &self,
+        branch_name: &str,
+        _from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        if self.branch_exists(branch_name).await? {
+            return Ok(vec![PlannedAction::new(
+                "postgres",
+                format!(
+                    "Nothing to do: database '{}' already exists",
+                    self.get_branch_database_name(branch_name)
+                ),
+            )]);
+        }
+        Ok(vec![
+            PlannedAction::new(
+                "postgres",
+                format!(
+                    "Terminate connections to template database '{}'",
+                    self.config.database.template_database
+                ),
+            ),
+            PlannedAction::new(
+                "postgres",
+                self.db_manager.plan_create_database_branch(branch_name),
+            ),
+        ])
+    }
+
+    async fn plan_delete_branch(&self, branch_name: &str) -> Result<Vec<PlannedAction>> {
+        if !self.branch_exists(branch_name).await? {
+            return Ok(vec![PlannedAction::new(
+                "postgres",
+                format!(
+                    "Nothing to do: database '{}' does not exist",
+                    self.get_branch_database_name(branch_name)
+                ),
+            )]);
+        }
+        Ok(vec![PlannedAction::new(
+            "postgres",
+            self.db_manager.plan_drop_database_branch(branch_name),
+        )])
     }
 
     async fn test_connection(&self) -> Result<()> {
@@ -135,8 +185,8 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
                 name: "PostgreSQL connection".to_string(),
                 available: true,
                 detail: format!(
-                    "Connected to {}:{}",
-                    self.config.database.host, self.config.database.port
+                    "Connected to {}",
+                    host_port(&self.config.database.host, self.config.database.port)
                 ),
             },
             Err(e) => DoctorCheck {
@@ -187,10 +237,28 @@ impl DatabaseBranchingBackend for PostgresTemplateBackend {
         "PostgreSQL Template"
     }
 
+    fn backend_type(&self) -> &'static str {
+        "postgres_template"
+    }
+
     fn supports_cleanup(&self) -> bool {
         true
     }
 
+    async fn branch_read_only(&self, branch_name: &str) -> Result<bool> {
+        self.db_manager.database_read_only(branch_name).await
+    }
+
+    async fn set_branch_read_only(&self, branch_name: &str, read_only: bool) -> Result<()> {
+        self.db_manager
+            .set_database_read_only(branch_name, read_only)
+            .await
+    }
+
+    fn supports_read_only(&self) -> bool {
+        true
+    }
+
     fn max_branch_name_length(&self) -> usize {
         63
     }
@@ -1,4 +1,7 @@
-use super::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport};
+use super::{
+    BranchInfo, ConnectionInfo, DatabaseBranchingBackend, DoctorCheck, DoctorReport, PlannedAction,
+};
+use crate::errors::coded;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -190,7 +193,7 @@ impl DatabaseBranchingBackend for XataBackend {
             let parent = self
                 .find_branch_by_name(from_name)
                 .await?
-                .ok_or_else(|| anyhow::anyhow!("Parent branch '{}' not found", from_name))?;
+                .ok_or_else(|| coded!(BranchNotFound, "Parent branch '{}' not found", from_name))?;
             Some(parent.id)
         } else {
             None
@@ -211,6 +214,8 @@ impl DatabaseBranchingBackend for XataBackend {
             parent_branch: from_branch.map(|s| s.to_string()),
             database_name: self.project_id.clone(),
             state: Some("running".to_string()),
+            health: None,
+            last_used_at: None,
         })
     }
 
@@ -218,12 +223,48 @@ impl DatabaseBranchingBackend for XataBackend {
         let branch = self
             .find_branch_by_name(branch_name)
             .await?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
         self.api_request_no_body(reqwest::Method::DELETE, &self.branch_url(&branch.id))
             .await
     }
 
+    async fn plan_create_branch(
+        &self,
+        branch_name: &str,
+        from_branch: Option<&str>,
+    ) -> Result<Vec<PlannedAction>> {
+        let parent = match from_branch {
+            Some(from_name) => {
+                let parent = self.find_branch_by_name(from_name).await?.ok_or_else(|| {
+                    coded!(BranchNotFound, "Parent branch '{}' not found", from_name)
+                })?;
+                format!(", parentID: {}", parent.id)
+            }
+            None => String::new(),
+        };
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!(
+                "POST {} (name: {}{})",
+                self.branches_url(),
+                Self::normalize_branch_name(branch_name),
+                parent
+            ),
+        )])
+    }
+
+    async fn plan_delete_branch(&self, branch_name: &str) -> Result<Vec<PlannedAction>> {
+        let branch = self
+            .find_branch_by_name(branch_name)
+            .await?
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
+        Ok(vec![PlannedAction::new(
+            "api",
+            format!("DELETE {}", self.branch_url(&branch.id)),
+        )])
+    }
+
     async fn list_branches(&self) -> Result<Vec<BranchInfo>> {
         let branches = self.fetch_branches().await?;
 
@@ -235,6 +276,8 @@ impl DatabaseBranchingBackend for XataBackend {
                 parent_branch: None,
                 database_name: self.project_id.clone(),
                 state: Some("running".to_string()),
+                health: None,
+                last_used_at: None,
             })
             .collect())
     }
@@ -249,14 +292,14 @@ impl DatabaseBranchingBackend for XataBackend {
         branches
             .into_iter()
             .find(|b| b.name == normalized_name)
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' does not exist", branch_name))
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' does not exist", branch_name))
     }
 
     async fn get_connection_info(&self, branch_name: &str) -> Result<ConnectionInfo> {
         let branch = self
             .find_branch_by_name(branch_name)
             .await?
-            .ok_or_else(|| anyhow::anyhow!("Branch '{}' not found", branch_name))?;
+            .ok_or_else(|| coded!(BranchNotFound, "Branch '{}' not found", branch_name))?;
 
         let creds_url = format!("{}/credentials", self.branch_url(&branch.id));
         let creds: BranchCredentials = self
@@ -309,6 +352,10 @@ impl DatabaseBranchingBackend for XataBackend {
         "Xata"
     }
 
+    fn backend_type(&self) -> &'static str {
+        "xata"
+    }
+
     fn max_branch_name_length(&self) -> usize {
         255
     }
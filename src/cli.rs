@@ -1,15 +1,178 @@
 use std::path::PathBuf;
+use std::process::ExitCode;
 
+use crate::audit::{self, AuditAction, AuditEvent};
 use crate::backends;
-use crate::config::{Config, EffectiveConfig};
+#[cfg(feature = "backend-postgres-template")]
+use crate::backends::{postgres_template::PostgresTemplateBackend, DatabaseBranchingBackend};
+use crate::config::{
+    encode_userinfo, host_port, Config, EffectiveConfig, PostCommand, PostCommandEvent,
+    SigningConfig, TemplateContext, WorktreeFileStrategy,
+};
 #[cfg(feature = "backend-postgres-template")]
 use crate::database::DatabaseManager;
 use crate::docker;
+use crate::errors::coded;
 use crate::git::GitRepository;
+use crate::hook_lock;
 use crate::local_state::LocalStateManager;
+use crate::output::print_json;
+use crate::plan::{self, BranchesFile, ChangeKind, Plan};
 use crate::post_commands::PostCommandExecutor;
-use anyhow::Result;
-use clap::Subcommand;
+use crate::project::check_branch_name;
+use crate::shutdown;
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "pgbranch")]
+#[command(about = "A tool for creating PostgreSQL database branches that sync with Git branches")]
+#[command(version = "0.2.0")]
+#[command(disable_help_subcommand = true)]
+#[command(help_template = "\
+{name} {version}
+{about}
+
+{usage-heading} {usage}
+
+Branch Management:
+  create              Create a new database branch
+  delete              Delete a database branch
+  list                List all database branches
+  switch              Switch to a database branch (creates if doesn't exist)
+  recent              List recently used database branches
+  cleanup             Clean up old database branches
+  prune               Delete branches unused for longer than the branch TTL
+  copy                Copy a branch into another configured database
+  seed                Load a dump or another database into a branch
+  plan                Show the changes needed to match the declared branches
+  apply (sync)        Create, update and delete branches to match them
+
+Branch Lifecycle (local backend):
+  start               Start a stopped database branch container
+  stop                Stop a running database branch container
+  reset               Reset a database branch to its parent state or a snapshot
+  snapshot create     Snapshot the current state of a database branch
+  snapshot list       List the snapshots of a branch, or of every branch
+  snapshot restore    Roll a database branch back to one of its snapshots
+  fixture export      Write a branch's rows (--since-parent: its changes) as SQL
+  branch set-password Apply the configured postgres_password to a branch
+  compact             Reclaim disk space held by a database branch
+  exec                Run SQL in a database branch (--record to keep it)
+  replay              Run a recorded session script against a branch
+  destroy             Destroy a database and all its branches
+  adopt               Record again destroyed branches, or restart pruned ones
+
+Info:
+  connection          Show connection info for a database branch
+  psql                Open psql on a database branch
+  proxy start         Serve the current branch on one fixed port
+  status              Show current project and backend status
+  top                 Show the heaviest queries run against a branch
+  diff --data         Compare two branches' tables by row count and checksum
+  du                  Show the disk space each branch takes
+  ps                  List running branch containers of every project
+  events              Follow container and state events of the branches
+  projects            List the pgbranch projects in this repository
+  schema              Print the JSON Schema of list/status/connection/doctor output
+  stats               Show recorded create/clone/seed/reset timings
+  bench-storage       Compare clone time and space of the storage backends
+  selftest            Check create/clone/reset/delete end to end in a sandbox
+
+Setup & Config:
+  quickstart          Set up everything in one go (Docker check, init, main, hooks)
+  init                Initialize pgbranch configuration
+  config              Show current configuration (-v for precedence details)
+  doctor              Run diagnostics and check system health
+  guard               Check for cron jobs that would prune the branch containers
+  upgrade             Update pgbranch to the latest release
+  state               Inspect, export, back up or restore local state
+  install-hooks       Install Git hooks
+  uninstall-hooks     Uninstall Git hooks
+  hooks lint          Check post-commands and replace rules for mistakes
+  git-hook --explain  Show what the Git hook would do on this branch
+  worktree-setup      Set up pgbranch in a Git worktree
+  worktree add        Create a Git worktree with its own database branch
+  project export      Write a sharable manifest of this project's setup
+  project import      Apply a manifest: config, databases, hooks
+  sign                Sign a dump, fixture or manifest for teammates
+  verify              Check a file against its signature
+  auth                Manage API credentials (login, logout, status)
+  completion context  Print the active branch for shell prompts
+
+Options:
+{options}")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Output in JSON format
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Output format: text, json (same as --json) or schema (the JSON Schema of the command's output, without running it)
+    #[arg(long = "output", global = true, value_name = "FORMAT", value_parser = ["text", "json", "schema"])]
+    output_format: Option<String>,
+
+    /// Non-interactive mode (skip prompts, use defaults)
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// Target a specific named database (from 'backends' config)
+    #[arg(short = 'd', long, global = true)]
+    database: Option<String>,
+
+    /// Keep the local backend's state and data here for this run (same as PGBRANCH_DATA_ROOT)
+    #[arg(long, global = true, value_name = "DIR")]
+    data_root: Option<PathBuf>,
+}
+
+/// The `pgbranch` binary.
+pub fn main() -> ExitCode {
+    env_logger::init();
+
+    let cli = Cli::parse();
+
+    // Set before the runtime starts any threads; everything that picks a
+    // data root reads it from there
+    if let Some(ref data_root) = cli.data_root {
+        std::env::set_var("PGBRANCH_DATA_ROOT", data_root);
+    }
+
+    let json = cli.json || cli.output_format.as_deref() == Some("json");
+    match run(cli, json) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            crate::errors::report(&e, json);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli, json: bool) -> Result<()> {
+    if cli.output_format.as_deref() == Some("schema") {
+        return match cli.command {
+            Some(ref cmd) => print_output_schema(cmd),
+            None => {
+                anyhow::bail!("--output schema needs a command, e.g. pgbranch list --output schema")
+            }
+        };
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        match cli.command {
+            Some(cmd) => {
+                handle_command(cmd, json, cli.non_interactive, cli.database.as_deref()).await?
+            }
+            None => {
+                // Print help when no command is provided
+                let mut cmd = Cli::command();
+                cmd.print_help()?;
+            }
+        }
+        Ok(())
+    })
+}
 
 #[derive(Subcommand)]
 pub enum Commands {
@@ -17,37 +180,144 @@ pub enum Commands {
     Create {
         #[arg(help = "Name of the branch to create")]
         branch_name: String,
-        #[arg(long, help = "Parent branch to clone from")]
+        #[arg(
+            long,
+            help = "Parent branch to clone from, <branch>@<snapshot> to clone one of its snapshots (local backend), or <database>:<branch> to copy from another configured database"
+        )]
         from: Option<String>,
+        #[arg(long, help = "Create the branch in every configured database")]
+        all_databases: bool,
+        #[arg(
+            long,
+            help = "Empty every table except the migration history, keeping only the schema (local backend)"
+        )]
+        schema_only: bool,
+        #[arg(
+            long,
+            help = "Skip the ANALYZE (or other configured maintenance) after cloning"
+        )]
+        no_maintenance: bool,
+        #[arg(
+            long = "pgconf",
+            value_name = "KEY=VALUE",
+            help = "PostgreSQL setting for this branch's server, e.g. log_statement=all (repeatable, local backend)"
+        )]
+        pgconf: Vec<String>,
+        #[arg(long, help = "Show how long each phase took")]
+        timings: bool,
+        #[arg(long, help = "Print what would be done without doing it")]
+        dry_run: bool,
     },
     #[command(about = "Delete a database branch")]
     Delete {
         #[arg(help = "Name of the branch to delete")]
         branch_name: String,
+        #[arg(long, help = "Delete the branch from every configured database")]
+        all_databases: bool,
+        #[arg(long, help = "Print what would be done without doing it")]
+        dry_run: bool,
+        #[arg(
+            long,
+            help = "Delete it even if it's the branch this working directory is on"
+        )]
+        force: bool,
     },
     #[command(about = "List all database branches")]
-    List,
+    List {
+        #[arg(short, long, help = "Show when each branch was created and last used")]
+        verbose: bool,
+    },
     #[command(about = "Initialize pgbranch configuration")]
     Init {
-        #[arg(help = "Database/backend name (defaults to project directory name)")]
+        #[arg(
+            help = "Database/backend name (defaults to --database, then the project directory name)"
+        )]
         name: Option<String>,
         #[arg(long, help = "Force overwrite existing configuration")]
         force: bool,
         #[arg(
             long,
-            help = "Backend type to use (local, postgres_template, neon, dblab, xata)"
+            help = backend_type_help()
+        )]
+        backend: Option<String>,
+        #[arg(
+            long,
+            help = "Seed main branch from source (PostgreSQL URL, file path, s3:// URL, or docker-compose)"
+        )]
+        from: Option<String>,
+        #[arg(
+            long,
+            help = "Don't integrate with Git: no hooks or worktrees, branches are managed by hand"
+        )]
+        standalone: bool,
+    },
+    #[command(
+        about = "Set up pgbranch in one go: check Docker, init, create main, install hooks and show how to connect"
+    )]
+    Quickstart {
+        #[arg(
+            help = "Database/backend name (defaults to --database, then the project directory name)"
+        )]
+        name: Option<String>,
+        #[arg(
+            long,
+            help = backend_type_help()
         )]
         backend: Option<String>,
         #[arg(
             long,
-            help = "Seed main branch from source (PostgreSQL URL, file path, or s3:// URL)"
+            help = "Seed main branch from source (PostgreSQL URL, file path, s3:// URL, or docker-compose)"
         )]
         from: Option<String>,
+        #[arg(long, help = "Don't install the Git hooks")]
+        no_hooks: bool,
     },
     #[command(about = "Clean up old database branches")]
     Cleanup {
         #[arg(long, help = "Maximum number of branches to keep")]
         max_count: Option<usize>,
+        #[arg(long, help = "Clean up every configured database")]
+        all_databases: bool,
+        #[arg(long, help = "Print what would be done without doing it")]
+        dry_run: bool,
+    },
+    #[command(about = "Delete branches unused for longer than the branch TTL")]
+    Prune {
+        #[arg(
+            long,
+            value_name = "AGE",
+            help = "Delete branches unused for this long (e.g. 7d), instead of behavior.branch_ttl"
+        )]
+        ttl: Option<String>,
+        #[arg(long, help = "Print what would be done without doing it")]
+        dry_run: bool,
+    },
+    #[command(about = "Show the changes needed to match the declared standing branches")]
+    Plan {
+        #[arg(
+            long,
+            help = "Branches file (defaults to the 'branches' section of .pgbranch.yml, then branches.yml)"
+        )]
+        file: Option<PathBuf>,
+        #[arg(long, help = "Save the plan to this file for 'pgbranch apply'")]
+        out: Option<PathBuf>,
+    },
+    #[command(
+        about = "Create, update and delete branches to match the declared ones or a saved plan",
+        visible_alias = "sync"
+    )]
+    Apply {
+        #[arg(
+            help = "Plan saved by 'pgbranch plan --out' (plans from the branches file if omitted)"
+        )]
+        plan: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Branches file (defaults to the 'branches' section of .pgbranch.yml, then branches.yml)"
+        )]
+        file: Option<PathBuf>,
+        #[arg(long, help = "Skip confirmation prompt")]
+        yes: bool,
     },
     #[command(about = "Show current configuration")]
     Config {
@@ -62,38 +332,130 @@ pub enum Commands {
     InstallHooks,
     #[command(about = "Uninstall Git hooks")]
     UninstallHooks,
+    #[command(about = "Check the post-commands and replace rules")]
+    Hooks {
+        #[command(subcommand)]
+        action: HooksCommands,
+    },
     #[command(about = "Handle Git hook execution", hide = true)]
     GitHook {
         #[arg(long, hide = true)]
         worktree: bool,
         #[arg(long, hide = true)]
         main_worktree_dir: Option<String>,
+        #[arg(long, hide = true, default_value = "post-checkout")]
+        event: String,
+        #[arg(
+            long,
+            help = "Print what the hook would do for the current Git branch, without doing it"
+        )]
+        explain: bool,
+        // Only this project: set when the hook runs for the other projects of a monorepo
+        #[arg(long, hide = true)]
+        nested: bool,
     },
     #[command(about = "Switch to a database branch (creates if doesn't exist)")]
     Switch {
         #[arg(
-            help = "Branch name to switch to (optional - if omitted, shows interactive selection)"
+            help = "Branch name to switch to, or '-' for the previous branch (optional - if omitted, shows interactive selection)"
         )]
         branch_name: Option<String>,
         #[arg(long, help = "Switch to main database (template/development database)")]
         template: bool,
         #[arg(long, help = "Simulate switching without database operations")]
         dry_run: bool,
+        #[arg(
+            long,
+            help = "Switch (creating where missing) in every configured database"
+        )]
+        all_databases: bool,
+    },
+    #[command(about = "List recently used database branches")]
+    Recent {
+        #[arg(
+            short = 'n',
+            long,
+            default_value = "10",
+            help = "Number of branches to show"
+        )]
+        limit: usize,
     },
     #[command(about = "Start a stopped database branch container (local backend)")]
     Start {
         #[arg(help = "Name of the branch to start")]
         branch_name: String,
+        #[arg(
+            long = "pgconf",
+            value_name = "KEY=VALUE",
+            help = "PostgreSQL setting to keep for this branch, e.g. log_statement=all; KEY= removes one (repeatable)"
+        )]
+        pgconf: Vec<String>,
     },
     #[command(about = "Stop a running database branch container (local backend)")]
     Stop {
         #[arg(help = "Name of the branch to stop")]
-        branch_name: String,
+        branch_name: Option<String>,
+        #[arg(
+            long,
+            value_name = "AGE",
+            conflicts_with = "branch_name",
+            help = "Stop every running branch not used for this long (e.g. 2h, 7d)"
+        )]
+        idle: Option<String>,
     },
     #[command(about = "Reset a database branch to its parent state (local backend)")]
     Reset {
         #[arg(help = "Name of the branch to reset")]
         branch_name: String,
+        #[arg(long, help = "Roll back to a named snapshot instead of the parent")]
+        to_snapshot: Option<String>,
+        #[arg(long, help = "Keep snapshots taken after the reset target")]
+        keep_snapshots: bool,
+        #[arg(long, help = "Show how long each phase took")]
+        timings: bool,
+        #[arg(long, help = "Print what would be done without doing it")]
+        dry_run: bool,
+    },
+    #[command(about = "Load data into an existing database branch")]
+    Seed {
+        #[arg(help = "Name of the branch to seed")]
+        branch_name: String,
+        #[arg(
+            long,
+            help = "Source to load (PostgreSQL URL, file path, or s3:// URL)"
+        )]
+        from: String,
+        #[arg(
+            long,
+            help = "Skip the ANALYZE (or other configured maintenance) after seeding"
+        )]
+        no_maintenance: bool,
+        #[arg(long, help = "Print what would be done without doing it")]
+        dry_run: bool,
+    },
+    #[command(about = "Manage named snapshots of a database branch (local backend)")]
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotCommands,
+    },
+    #[command(about = "Turn a branch's data into SQL fixtures (local backend)")]
+    Fixture {
+        #[command(subcommand)]
+        action: FixtureCommands,
+    },
+    #[command(about = "Manage a database branch's settings (local backend)")]
+    Branch {
+        #[command(subcommand)]
+        action: BranchCommands,
+    },
+    #[command(about = "Copy a branch from one configured database into another")]
+    Copy {
+        #[arg(help = "Name of the branch to copy (from --database or the default)")]
+        branch_name: String,
+        #[arg(long, help = "Database to copy the branch into")]
+        to_database: String,
+        #[arg(long, help = "Branch name in the target database (default: same name)")]
+        name: Option<String>,
     },
     #[command(about = "Run diagnostics and check system health")]
     Doctor,
@@ -104,18 +466,227 @@ pub enum Commands {
         #[arg(long, help = "Output format: uri, env, or json")]
         format: Option<String>,
     },
+    #[command(about = "Open psql on a database branch")]
+    Psql {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(last = true, help = "Arguments passed on to psql, after --")]
+        args: Vec<String>,
+    },
+    #[command(about = "Serve the current branch on one fixed local port (local backend)")]
+    Proxy {
+        #[command(subcommand)]
+        action: ProxyCommands,
+    },
     #[command(about = "Show current project and backend status")]
     Status,
+    #[command(about = "Show the heaviest queries run against a branch (local backend)")]
+    Top {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(long, default_value_t = 10, help = "How many queries to show")]
+        limit: usize,
+        #[arg(
+            long,
+            help = "Enable pg_stat_statements in the branch first (restarts it)"
+        )]
+        enable: bool,
+        #[arg(long, help = "Reset the statistics after showing them")]
+        reset: bool,
+    },
+    #[command(about = "Compare the data of two branches table by table (local backend)")]
+    Diff {
+        #[arg(help = "Branch to compare from, e.g. the parent")]
+        branch_a: String,
+        #[arg(help = "Branch to compare with it")]
+        branch_b: String,
+        #[arg(
+            long,
+            help = "Compare the tables' contents (row counts, --checksum for rows)"
+        )]
+        data: bool,
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_name = "TABLE",
+            help = "Only compare these tables, as schema.table or table (comma-separated)"
+        )]
+        tables: Vec<String>,
+        #[arg(
+            long,
+            help = "Also compare an md5 of each table's sorted rows (reads every row; narrow it with --tables)"
+        )]
+        checksum: bool,
+    },
+    #[command(about = "Reclaim disk space held by a branch (local backend)")]
+    Compact {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(
+            long,
+            help = "Run VACUUM FULL in the branch's databases first (locks each table while it runs)"
+        )]
+        vacuum_full: bool,
+    },
+    #[command(
+        about = "List the running branch containers of every project, like 'docker ps' (local backend)"
+    )]
+    Ps {
+        #[arg(short, long, help = "Include stopped containers")]
+        all: bool,
+    },
+    #[command(about = "Show the disk space each branch takes and its quota (local backend)")]
+    Du,
+    #[command(
+        about = "Follow container and state events of the branches as they happen (local backend)"
+    )]
+    Events,
+    #[command(about = "Run SQL in a branch, optionally recording it for replay (local backend)")]
+    Exec {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(help = "SQL to run")]
+        sql: Option<String>,
+        #[arg(short = 'f', long, help = "Run the SQL in this file instead")]
+        file: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Append the SQL to the branch's session script once it succeeded"
+        )]
+        record: bool,
+    },
+    #[command(about = "Run a recorded session script against a branch (local backend)")]
+    Replay {
+        #[arg(help = "Script file, or a branch whose recorded session to replay")]
+        script: String,
+        #[arg(long, help = "Branch to run it in, created if it doesn't exist")]
+        onto: String,
+        #[arg(long, help = "Parent to create the --onto branch from")]
+        from: Option<String>,
+    },
     #[command(about = "Destroy a database and all its branches (local backend)")]
     Destroy {
-        #[arg(long, help = "Skip confirmation prompt")]
+        #[arg(
+            long,
+            help = "Skip the confirmation prompt (needed with --non-interactive)"
+        )]
         force: bool,
+        #[arg(
+            long,
+            help = "Only show what would be destroyed (with --json, for tooling)"
+        )]
+        preview: bool,
+        #[arg(
+            long,
+            visible_alias = "keep-data",
+            conflicts_with = "keep_project",
+            help = "Only remove the containers; keep the data so 'start' can bring branches back"
+        )]
+        containers_only: bool,
+        #[arg(
+            long,
+            help = "Delete every branch with its data, but keep the project and its settings"
+        )]
+        keep_project: bool,
+    },
+    #[command(
+        about = "Record again the branches of a destroyed project, or restart pruned ones (local backend)"
+    )]
+    Adopt {
+        #[arg(
+            long,
+            value_name = "PATH",
+            required_unless_present = "pruned",
+            help = "Manifest that 'destroy' saved under the data root's destroyed/ folder"
+        )]
+        from_manifest: Option<PathBuf>,
+        #[arg(
+            long,
+            conflicts_with = "from_manifest",
+            help = "Restart the branches whose containers were removed while running, e.g. by docker system prune"
+        )]
+        pruned: bool,
     },
+    #[command(
+        about = "Check for cron jobs that would prune the branch containers (local backend)"
+    )]
+    Guard,
     #[command(
         name = "worktree-setup",
         about = "Set up pgbranch in a Git worktree (copy files, create DB branch)"
     )]
-    WorktreeSetup,
+    WorktreeSetup {
+        #[arg(
+            long,
+            help = "Only re-sync files from the main worktree, overwriting stale copies"
+        )]
+        refresh: bool,
+    },
+    #[command(about = "Manage Git worktrees with their own database branches")]
+    Worktree {
+        #[command(subcommand)]
+        action: WorktreeCommands,
+    },
+    #[command(about = "Manage API credentials for cloud backends")]
+    Auth {
+        #[command(subcommand)]
+        action: AuthCommands,
+    },
+    #[command(about = "List the pgbranch projects (config files) in this repository")]
+    Projects,
+    #[command(about = "Print the JSON Schema of a command's --json output")]
+    Schema {
+        #[arg(help = "Command whose output to describe: list, status, connection or doctor")]
+        command: Option<String>,
+    },
+    #[command(about = "Share this project's pgbranch setup with teammates")]
+    Project {
+        #[command(subcommand)]
+        action: ProjectCommands,
+    },
+    #[command(about = "Sign a dump, fixture or manifest with the key under 'signing'")]
+    Sign {
+        #[arg(help = "File to sign; the signature is written next to it")]
+        file: PathBuf,
+    },
+    #[command(about = "Check a file against its signature and the trusted keys")]
+    Verify {
+        #[arg(help = "File to check")]
+        file: PathBuf,
+    },
+    #[command(about = "Update pgbranch to the latest GitHub release")]
+    Upgrade {
+        #[arg(long, help = "Only report whether a newer release exists")]
+        check: bool,
+        #[arg(long, help = "Reinstall even if already on the latest release")]
+        force: bool,
+    },
+    #[command(about = "Summarize locally recorded branch operation timings")]
+    Stats {
+        #[arg(long, help = "Include every local project, not just this one")]
+        all: bool,
+    },
+    #[command(
+        name = "bench-storage",
+        about = "Measure clone time and space for each storage backend on this machine"
+    )]
+    BenchStorage {
+        #[arg(
+            long,
+            default_value_t = 256,
+            help = "Size of the synthetic dataset in MB"
+        )]
+        size_mb: u64,
+    },
+    #[command(
+        about = "Check the local backend end to end in a throwaway project (create, clone, isolate, reset, delete)"
+    )]
+    Selftest,
+    #[command(about = "Helpers for shell integration")]
+    Completion {
+        #[command(subcommand)]
+        action: CompletionCommands,
+    },
     #[command(
         name = "setup-zfs",
         about = "Set up a file-backed ZFS pool for Copy-on-Write storage (Linux)"
@@ -126,85 +697,422 @@ pub enum Commands {
         #[arg(long, default_value = "10G", help = "Pool image size (sparse file)")]
         size: Option<String>,
     },
+    #[command(about = "Back up or restore the local backend's state database")]
+    State {
+        #[command(subcommand)]
+        action: StateCommands,
+    },
 }
 
-pub async fn handle_command(
-    cmd: Commands,
-    json_output: bool,
-    _non_interactive: bool,
-    database_name: Option<&str>,
-) -> Result<()> {
-    // Commands that use the new backend system
-    let uses_backend = matches!(
-        cmd,
-        Commands::Create { .. }
-            | Commands::Delete { .. }
-            | Commands::List
-            | Commands::Start { .. }
-            | Commands::Stop { .. }
-            | Commands::Reset { .. }
-            | Commands::Doctor
-            | Commands::Connection { .. }
-            | Commands::Status
-            | Commands::Cleanup { .. }
-            | Commands::Destroy { .. }
-    );
-
-    // Commands that use the legacy direct-database approach
-    let uses_legacy = matches!(
-        cmd,
-        Commands::GitHook { .. } | Commands::Switch { .. } | Commands::WorktreeSetup
-    );
-
-    // Check if command requires configuration file
-    let requires_config = uses_backend || uses_legacy;
+/// `--backend` help listing the backend types this build includes.
+fn backend_type_help() -> String {
+    let names: Vec<&str> = backends::factory::BackendInfo::available()
+        .map(|info| info.name)
+        .collect();
+    format!("Backend type to use ({})", names.join(", "))
+}
 
-    // Load effective configuration (includes local config and environment overrides)
-    let (effective_config, config_path) = Config::load_effective_config_with_path_info()?;
+#[derive(Subcommand)]
+pub enum WorktreeCommands {
+    #[command(about = "Create a Git worktree, set it up and provision its database branch")]
+    Add {
+        #[arg(help = "Directory for the new worktree")]
+        path: String,
+        #[arg(help = "Git branch to check out (created if it doesn't exist)")]
+        branch: String,
+        #[arg(
+            long,
+            help = "Revision to create a new Git branch from (default: HEAD)"
+        )]
+        base: Option<String>,
+        #[arg(long, help = "Parent database branch to create from")]
+        from: Option<String>,
+        #[arg(
+            long,
+            default_value = ".env.pgbranch",
+            help = "File in the worktree to write connection variables to"
+        )]
+        env_file: String,
+    },
+}
 
-    // Early exit if pgbranch is disabled
-    if effective_config.should_exit_early()? {
-        if effective_config.is_disabled() {
-            log::debug!("pgbranch is globally disabled via configuration");
-        } else {
-            log::debug!("pgbranch is disabled for current branch");
-        }
-        return Ok(());
-    }
+#[derive(Subcommand)]
+pub enum ProxyCommands {
+    #[command(about = "Forward connections on a fixed port to the current branch, until Ctrl-C")]
+    Start {
+        #[arg(long, default_value_t = crate::proxy::DEFAULT_PORT, help = "Local port to listen on")]
+        port: u16,
+    },
+}
 
-    // Check for required config file after checking if disabled
-    if requires_config && config_path.is_none() {
+#[derive(Subcommand)]
+pub enum SnapshotCommands {
+    #[command(about = "Snapshot the current state of a branch")]
+    Create {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(help = "Name of the snapshot")]
+        name: String,
+        #[arg(
+            long,
+            help = "Take the snapshot again if one of that name exists, e.g. from a nightly job"
+        )]
+        replace: bool,
+    },
+    #[command(about = "List the snapshots of a branch, or of every branch")]
+    List {
+        #[arg(help = "Name of the branch (default: all branches)")]
+        branch_name: Option<String>,
+    },
+    #[command(about = "Roll a branch back to one of its snapshots")]
+    Restore {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(help = "Name of the snapshot")]
+        name: String,
+        #[arg(long, help = "Keep snapshots taken after this one")]
+        keep_snapshots: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FixtureCommands {
+    #[command(about = "Write the branch's rows as INSERT statements")]
+    Export {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+        #[arg(
+            long,
+            help = "Only rows added or changed compared to the parent branch as it is now"
+        )]
+        since_parent: bool,
+        #[arg(
+            short,
+            long,
+            value_name = "DIR",
+            help = "Directory to write <branch>.sql to (default: stdout)"
+        )]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BranchCommands {
+    #[command(
+        name = "set-password",
+        about = "Change the branch's database password to the configured postgres_password"
+    )]
+    SetPassword {
+        #[arg(help = "Name of the branch")]
+        branch_name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProjectCommands {
+    #[command(about = "Write a manifest of config, databases and hooks, without secrets")]
+    Export {
+        #[arg(help = "Output file (default: stdout)")]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Seed source teammates' local databases should start from"
+        )]
+        seed_from: Option<String>,
+    },
+    #[command(about = "Set up this checkout from a manifest")]
+    Import {
+        #[arg(help = "Manifest file written by 'pgbranch project export'")]
+        file: PathBuf,
+        #[arg(
+            long,
+            help = "Replace an existing .pgbranch.yml and configured databases"
+        )]
+        force: bool,
+        #[arg(long, help = "Only write configuration; don't create local databases")]
+        no_provision: bool,
+        #[arg(long, help = "Override the manifest's seed source")]
+        seed_from: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StateCommands {
+    #[command(about = "Write a copy of the state database")]
+    Backup {
+        #[arg(
+            help = "Destination file (default: <data_root>/backups/state-manual-<timestamp>.db)"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Replace the state database with a backup")]
+    Restore {
+        #[arg(help = "Backup file to restore")]
+        file: PathBuf,
+    },
+    #[command(about = "Summarize the recorded local state (--json for the raw dump)")]
+    Show {
+        #[arg(long, help = "Hide passwords, API keys and tokens")]
+        redact: bool,
+    },
+    #[command(about = "Dump the raw local state as JSON")]
+    Export {
+        #[arg(help = "Output file (default: stdout)")]
+        output: Option<PathBuf>,
+        #[arg(long, help = "Hide passwords, API keys and tokens")]
+        redact: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HooksCommands {
+    #[command(about = "Report mistakes in post_commands before a branch switch runs into them")]
+    Lint,
+}
+
+#[derive(Subcommand)]
+pub enum AuthCommands {
+    #[command(about = "Store an API key in the OS keychain (or a 0600 credentials file)")]
+    Login {
+        #[arg(help = "Provider: neon, xata, dblab or age")]
+        provider: String,
+        #[arg(
+            long,
+            help = "Store in the credentials file instead of the OS keychain"
+        )]
+        file: bool,
+        #[arg(long, help = "Skip validating the key against the API")]
+        no_validate: bool,
+    },
+    #[command(about = "Remove a stored API key")]
+    Logout {
+        #[arg(help = "Provider: neon, xata, dblab or age")]
+        provider: String,
+    },
+    #[command(about = "Show which providers have stored credentials")]
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum CompletionCommands {
+    #[command(
+        about = "Print the active database branch for shell prompts (reads local state only)"
+    )]
+    Context,
+}
+
+pub async fn handle_command(
+    cmd: Commands,
+    json_output: bool,
+    _non_interactive: bool,
+    database_name: Option<&str>,
+) -> Result<()> {
+    // Prompt segments run on every prompt, so skip config merging and backend setup entirely
+    if let Commands::Completion {
+        action: CompletionCommands::Context,
+    } = cmd
+    {
+        return print_prompt_context(json_output);
+    }
+
+    if let Commands::Projects = cmd {
+        return list_projects(json_output);
+    }
+
+    if let Commands::Schema { command } = cmd {
+        return print_schema(command.as_deref(), json_output);
+    }
+
+    // Hooks run at the repository root, so a monorepo's projects are fanned out to
+    if let Commands::GitHook {
+        explain: false,
+        nested: false,
+        ..
+    } = cmd
+    {
+        return handle_git_hook_for_all_projects(cmd, json_output, _non_interactive, database_name)
+            .await;
+    }
+
+    // Commands that use the new backend system
+    let uses_backend = matches!(
+        cmd,
+        Commands::Create { .. }
+            | Commands::Delete { .. }
+            | Commands::List { .. }
+            | Commands::Start { .. }
+            | Commands::Stop { .. }
+            | Commands::Reset { .. }
+            | Commands::Seed { .. }
+            | Commands::Snapshot { .. }
+            | Commands::Fixture { .. }
+            | Commands::Branch { .. }
+            | Commands::Copy { .. }
+            | Commands::Doctor
+            | Commands::Connection { .. }
+            | Commands::Psql { .. }
+            | Commands::Proxy { .. }
+            | Commands::Status
+            | Commands::Top { .. }
+            | Commands::Diff { .. }
+            | Commands::Du
+            | Commands::Events
+            | Commands::Exec { .. }
+            | Commands::Replay { .. }
+            | Commands::Compact { .. }
+            | Commands::Cleanup { .. }
+            | Commands::Prune { .. }
+            | Commands::Plan { .. }
+            | Commands::Apply { .. }
+            | Commands::Destroy { .. }
+            | Commands::Adopt { .. }
+            | Commands::Guard
+            | Commands::Worktree { .. }
+    );
+
+    // Commands that use the legacy direct-database approach
+    let uses_legacy = matches!(
+        cmd,
+        Commands::GitHook { .. }
+            | Commands::Switch { .. }
+            | Commands::WorktreeSetup { .. }
+            | Commands::Recent { .. }
+    );
+
+    // Check if command requires configuration file
+    let requires_config = uses_backend || uses_legacy;
+
+    // Load effective configuration (includes local config and environment overrides)
+    let (effective_config, config_path) = Config::load_effective_config_with_path_info()?;
+
+    // Early exit if pgbranch is disabled
+    // --explain reports a disabled pgbranch instead of silently exiting
+    let explain_hook = matches!(cmd, Commands::GitHook { explain: true, .. });
+    if !explain_hook && effective_config.should_exit_early()? {
+        if effective_config.is_disabled() {
+            log::debug!("pgbranch is globally disabled via configuration");
+        } else {
+            log::debug!("pgbranch is disabled for current branch");
+        }
+        return Ok(());
+    }
+
+    // Check for required config file after checking if disabled
+    if requires_config && config_path.is_none() {
         // For backend commands, we allow no config (will use local backend defaults)
         if uses_legacy {
-            anyhow::bail!(
+            return Err(coded!(
+                ConfigNotFound,
                 "No configuration file found. Please run 'pgbranch init' to create a .pgbranch.yml file first."
-            );
+            ));
         }
     }
 
     // Get the merged configuration for normal operations
     let mut config = effective_config.get_merged_config();
 
-    // Inject backends from state (state backends take precedence over committed)
-    let local_state_for_backends = if uses_backend || uses_legacy {
-        LocalStateManager::new().ok()
-    } else {
-        None
-    };
-    if let Some(ref state_manager) = local_state_for_backends {
-        if let Some(ref path) = config_path {
-            if let Some(state_backends) = state_manager.get_backends(path) {
-                config.backends = Some(state_backends);
-                config.backend = None;
+    // Standalone projects leave Git alone
+    if config.standalone {
+        match cmd {
+            Commands::GitHook { explain: false, .. } => {
+                log::debug!("Standalone project, ignoring the Git hook");
+                return Ok(());
             }
+            Commands::InstallHooks | Commands::WorktreeSetup { .. } | Commands::Worktree { .. } => {
+                anyhow::bail!(
+                    "This project is standalone ('standalone: true' in .pgbranch.yml), so it has no Git hooks or worktrees"
+                );
+            }
+            _ => {}
+        }
+    }
+
+    // Commands that run against one of the configured databases
+    let targets_database = uses_backend
+        || uses_legacy
+        || matches!(
+            cmd,
+            Commands::State { .. }
+                | Commands::Ps { .. }
+                | Commands::Stats { .. }
+                | Commands::BenchStorage { .. }
+                | Commands::Selftest
+        );
+
+    // Inject backends from state (state backends take precedence over committed)
+    if targets_database {
+        crate::project::use_state_backends(&mut config, config_path.as_deref());
+    }
+
+    // A mistyped --database must fail here, not quietly fall back to the default
+    if let (true, Some(name)) = (targets_database, database_name) {
+        crate::project::check_database_name(&config, name)?;
+    }
+
+    // --all-databases fans the command out over every configured backend
+    if matches!(
+        cmd,
+        Commands::Create {
+            all_databases: true,
+            ..
+        } | Commands::Delete {
+            all_databases: true,
+            ..
+        } | Commands::Switch {
+            all_databases: true,
+            ..
+        }
+    ) {
+        if database_name.is_some() {
+            anyhow::bail!("--all-databases cannot be combined with --database");
+        }
+        let mut local_state = LocalStateManager::new().ok();
+        return handle_all_databases_command(
+            cmd,
+            &config,
+            json_output,
+            &mut local_state,
+            &config_path,
+            None,
+            _non_interactive,
+        )
+        .await;
+    }
+
+    if let Commands::Cleanup {
+        max_count,
+        all_databases: true,
+        dry_run,
+    } = cmd
+    {
+        if database_name.is_some() {
+            anyhow::bail!("--all-databases cannot be combined with --database");
         }
+        return handle_cleanup_all_databases(&config, max_count, dry_run, json_output).await;
+    }
+
+    // The legacy switch only knows the template database; a named one goes
+    // through its backend instead
+    if let (Commands::Switch { .. }, Some(name)) = (&cmd, database_name) {
+        let mut local_state = LocalStateManager::new().ok();
+        return handle_all_databases_command(
+            cmd,
+            &config,
+            json_output,
+            &mut local_state,
+            &config_path,
+            Some(name),
+            _non_interactive,
+        )
+        .await;
     }
 
     // Handle backend-based commands
     if uses_backend {
         // For doctor, run config/git pre-checks before backend-specific checks
         if matches!(cmd, Commands::Doctor) && !json_output {
-            run_doctor_pre_checks(&config, &config_path);
+            let update = crate::upgrade::available_update().await;
+            run_doctor_pre_checks(&config, &config_path, update.as_deref());
         }
         return handle_backend_command(
             cmd,
@@ -233,224 +1141,35 @@ pub async fn handle_command(
             force,
             backend,
             from,
+            standalone,
         } => {
-            let config_path = std::env::current_dir()?.join(".pgbranch.yml");
-
-            // Resolve the name: if None, derive from current directory
-            let resolved_name = match name {
-                Some(n) => n,
-                None => std::env::current_dir()?
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_else(|| "default".to_string()),
-            };
-
-            let backend_type = backend.as_deref().unwrap_or("local").to_string();
-            let is_local = backends::factory::BackendType::is_local(&backend_type);
-            let is_postgres_template = matches!(
-                backend_type.as_str(),
-                "postgres_template" | "postgres" | "postgresql"
-            );
-
-            if config_path.exists() {
-                // --- Subsequent init: add a new backend to state (don't modify .pgbranch.yml) ---
-                let config = Config::from_file(&config_path)?;
-
-                // Build new named backend config
-                let named_cfg = crate::config::NamedBackendConfig {
-                    name: resolved_name.clone(),
-                    backend_type: backend_type.clone(),
-                    default: false,
-                    local: if is_local {
-                        Some(crate::config::LocalBackendConfig {
-                            image: None,
-                            data_root: None,
-                            storage: None,
-                            port_range_start: None,
-                            postgres_user: None,
-                            postgres_password: None,
-                            postgres_db: None,
-                        })
-                    } else {
-                        None
-                    },
-                    neon: None,
-                    dblab: None,
-                    xata: None,
-                };
-
-                // Store backend in local state instead of committed config
-                let mut state = LocalStateManager::new()?;
-                state.add_backend(&config_path, named_cfg.clone(), force)?;
-                println!("Added backend '{}' to local state", resolved_name);
-
-                // Create main branch for local backends
-                if is_local {
-                    // Build a config with the backend injected so the factory can find it
-                    let mut config_with_backend = config;
-                    if let Some(state_backends) = state.get_backends(&config_path) {
-                        config_with_backend.backends = Some(state_backends);
-                        config_with_backend.backend = None;
-                    }
-
-                    // On Linux, offer ZFS auto-setup before creating the main branch
-                    #[cfg(feature = "backend-local")]
-                    if cfg!(target_os = "linux") {
-                        if let Some(data_root) = attempt_zfs_auto_setup(_non_interactive).await {
-                            let mut updated_cfg = named_cfg.clone();
-                            if let Some(ref mut local) = updated_cfg.local {
-                                local.data_root = Some(data_root);
-                            }
-                            let _ = state.add_backend(&config_path, updated_cfg.clone(), true);
-                            if let Some(state_backends) = state.get_backends(&config_path) {
-                                config_with_backend.backends = Some(state_backends);
-                            }
-                            init_local_backend_main(
-                                &config_with_backend,
-                                &updated_cfg,
-                                from.as_deref(),
-                            )
-                            .await;
-                        } else {
-                            init_local_backend_main(
-                                &config_with_backend,
-                                &named_cfg,
-                                from.as_deref(),
-                            )
-                            .await;
-                        }
-                    } else {
-                        init_local_backend_main(&config_with_backend, &named_cfg, from.as_deref())
-                            .await;
-                    }
-                    #[cfg(not(feature = "backend-local"))]
-                    {
-                        init_local_backend_main(&config_with_backend, &named_cfg, from.as_deref())
-                            .await;
-                    }
-                }
-            } else {
-                // --- First-time init: create .pgbranch.yml ---
-                let mut config = Config::default();
-
-                // Auto-detect main Git branch
-                if let Ok(git_repo) = GitRepository::new(".") {
-                    if let Ok(Some(detected_main)) = git_repo.detect_main_branch() {
-                        config.git.main_branch = detected_main.clone();
-                        println!("Auto-detected main Git branch: {}", detected_main);
-                    } else {
-                        println!("Could not auto-detect main Git branch, using default: main");
-                    }
-                }
-
-                // For postgres_template backend, look for Docker Compose files
-                if is_postgres_template {
-                    let compose_files = docker::find_docker_compose_files();
-                    if !compose_files.is_empty() {
-                        println!("Found Docker Compose files: {}", compose_files.join(", "));
-
-                        if let Some(postgres_config) =
-                            docker::parse_postgres_config_from_files(&compose_files)?
-                        {
-                            if docker::prompt_user_for_config_usage(&postgres_config)? {
-                                if let Some(host) = postgres_config.host {
-                                    config.database.host = host;
-                                }
-                                if let Some(port) = postgres_config.port {
-                                    config.database.port = port;
-                                }
-                                if let Some(user) = postgres_config.user {
-                                    config.database.user = user;
-                                }
-                                if let Some(password) = postgres_config.password {
-                                    config.database.password = Some(password);
-                                }
-                                if let Some(database) = postgres_config.database {
-                                    config.database.template_database = database;
-                                }
-
-                                println!("Using PostgreSQL configuration from Docker Compose");
-                            }
-                        }
-                    }
-                }
-
-                // Build named backend config
-                let named_cfg = crate::config::NamedBackendConfig {
-                    name: resolved_name.clone(),
-                    backend_type: backend_type.clone(),
-                    default: true,
-                    local: if is_local {
-                        Some(crate::config::LocalBackendConfig {
-                            image: None,
-                            data_root: None,
-                            storage: None,
-                            port_range_start: None,
-                            postgres_user: None,
-                            postgres_password: None,
-                            postgres_db: None,
-                        })
-                    } else {
-                        None
-                    },
-                    neon: None,
-                    dblab: None,
-                    xata: None,
-                };
-
-                // Don't write backends to committed config — store in state
-                config.backends = None;
-                config.backend = None;
-                config.save_to_file(&config_path)?;
-                println!(
-                    "Initialized pgbranch configuration at: {}",
-                    config_path.display()
-                );
-
-                // Store backend in local state
-                let mut state = LocalStateManager::new()?;
-                state.set_backends(&config_path, vec![named_cfg.clone()])?;
-                println!("Stored backend '{}' in local state", resolved_name);
-
-                // Inject backends into config so init_local_backend_main can use them
-                config.backends = Some(vec![named_cfg.clone()]);
-
-                // Create main branch for local backends
-                if is_local {
-                    // On Linux, offer ZFS auto-setup before creating the main branch
-                    #[cfg(feature = "backend-local")]
-                    if cfg!(target_os = "linux") {
-                        if let Some(data_root) = attempt_zfs_auto_setup(_non_interactive).await {
-                            // Update the named backend config with the ZFS data_root
-                            let mut updated_cfg = named_cfg.clone();
-                            if let Some(ref mut local) = updated_cfg.local {
-                                local.data_root = Some(data_root);
-                            }
-                            // Update in state and injected config
-                            if let Ok(mut state) = LocalStateManager::new() {
-                                let _ = state.set_backends(&config_path, vec![updated_cfg.clone()]);
-                            }
-                            config.backends = Some(vec![updated_cfg.clone()]);
-                            init_local_backend_main(&config, &updated_cfg, from.as_deref()).await;
-                        } else {
-                            init_local_backend_main(&config, &named_cfg, from.as_deref()).await;
-                        }
-                    } else {
-                        init_local_backend_main(&config, &named_cfg, from.as_deref()).await;
-                    }
-                    #[cfg(not(feature = "backend-local"))]
-                    {
-                        init_local_backend_main(&config, &named_cfg, from.as_deref()).await;
-                    }
-                }
-
-                // Suggest adding local config to gitignore
-                let gitignore_path = std::env::current_dir()?.join(".gitignore");
-                if gitignore_path.exists() {
-                    println!("\nSuggestion: Add '.pgbranch.local.yml' to your .gitignore file:");
-                    println!("   echo '.pgbranch.local.yml' >> .gitignore");
-                }
-            }
+            handle_init(
+                name,
+                force,
+                backend,
+                from,
+                standalone,
+                database_name,
+                _non_interactive,
+            )
+            .await?;
+        }
+        Commands::Quickstart {
+            name,
+            backend,
+            from,
+            no_hooks,
+        } => {
+            handle_quickstart(
+                name,
+                backend,
+                from,
+                no_hooks,
+                database_name,
+                _non_interactive,
+                json_output,
+            )
+            .await?;
         }
         Commands::SetupZfs { pool_name, size } => {
             if !cfg!(target_os = "linux") {
@@ -460,7 +1179,7 @@ pub async fn handle_command(
             #[cfg(not(feature = "backend-local"))]
             {
                 let _ = (pool_name, size);
-                anyhow::bail!("Local backend not compiled. Rebuild with --features backend-local");
+                return Err(backends::factory::not_compiled("local"));
             }
 
             #[cfg(feature = "backend-local")]
@@ -495,34 +1214,142 @@ pub async fn handle_command(
                 println!("Run 'pgbranch init' to set up a project using this pool.");
             }
         }
-        Commands::Config { verbose } => {
-            if verbose {
-                show_effective_config(&effective_config)?;
-            } else {
-                println!("Current configuration:");
-                println!("{}", serde_yaml_ng::to_string(&config)?);
+        Commands::State { action } => {
+            #[cfg(not(feature = "backend-local"))]
+            {
+                let _ = action;
+                return Err(backends::factory::not_compiled("local"));
+            }
+
+            #[cfg(feature = "backend-local")]
+            handle_state_command(action, &config, json_output, database_name)?;
+        }
+        Commands::Ps { all } => {
+            #[cfg(not(feature = "backend-local"))]
+            {
+                let _ = all;
+                return Err(backends::factory::not_compiled("local"));
+            }
+
+            #[cfg(feature = "backend-local")]
+            {
+                let backends = config.resolve_backends();
+                let local_config = select_backend(&backends, database_name)?
+                    .filter(|b| backends::factory::BackendInfo::is_local(&b.backend_type))
+                    .and_then(|b| b.local.as_ref());
+                let containers =
+                    backends::local::LocalBackend::list_managed_containers(local_config, all)
+                        .await?;
+                if json_output {
+                    print_json(&containers)?;
+                } else if containers.is_empty() {
+                    println!(
+                        "No {}pgbranch containers",
+                        if all { "" } else { "running " }
+                    );
+                } else {
+                    println!(
+                        "{:<20} {:<24} {:<6} {:<20} STATUS",
+                        "PROJECT", "BRANCH", "PORT", "IMAGE"
+                    );
+                    for listing in &containers {
+                        println!(
+                            "{:<20} {:<24} {:<6} {:<20} {}",
+                            listing.project.as_deref().unwrap_or("-"),
+                            listing
+                                .branch
+                                .as_deref()
+                                .unwrap_or(listing.container.name.as_str()),
+                            listing
+                                .container
+                                .port
+                                .map(|port| port.to_string())
+                                .unwrap_or_else(|| "-".to_string()),
+                            listing.container.image,
+                            listing.container.status
+                        );
+                    }
+                }
+            }
+        }
+        Commands::Config { verbose } => {
+            if verbose {
+                show_effective_config(&effective_config)?;
+            } else {
+                println!("Current configuration:");
+                println!("{}", serde_yaml_ng::to_string(&config)?);
+            }
+        }
+        Commands::InstallHooks => {
+            let git_repo = GitRepository::new(".")?;
+            git_repo.install_hooks()?;
+            println!("Installed Git hooks");
+        }
+        Commands::UninstallHooks => {
+            let git_repo = GitRepository::new(".")?;
+            git_repo.uninstall_hooks()?;
+            println!("Uninstalled Git hooks");
+        }
+        Commands::Hooks {
+            action: HooksCommands::Lint,
+        } => {
+            let working_dir =
+                std::env::current_dir().context("Failed to get current working directory")?;
+            let problems = crate::post_commands::lint(&config, &working_dir);
+            if json_output {
+                print_json(&serde_json::json!({
+                    "post_commands": config.post_commands.len(),
+                    "problems": problems,
+                }))?;
+            } else if problems.is_empty() {
+                println!(
+                    "✅ {} post-command(s), no problems found",
+                    config.post_commands.len()
+                );
+            } else {
+                for problem in &problems {
+                    println!("❌ {}: {}", problem.command, problem.message);
+                }
+            }
+            if !problems.is_empty() {
+                anyhow::bail!("Found {} problem(s) in post_commands", problems.len());
             }
         }
-        Commands::InstallHooks => {
-            let git_repo = GitRepository::new(".")?;
-            git_repo.install_hooks()?;
-            println!("Installed Git hooks");
-        }
-        Commands::UninstallHooks => {
-            let git_repo = GitRepository::new(".")?;
-            git_repo.uninstall_hooks()?;
-            println!("Uninstalled Git hooks");
-        }
         Commands::GitHook {
             worktree,
             main_worktree_dir,
+            event,
+            explain,
+            ..
         } => {
+            if explain {
+                return explain_git_hook(&effective_config, &config, database_name, json_output)
+                    .await;
+            }
             if effective_config.should_skip_hooks() {
                 log::debug!("Git hooks are disabled via configuration");
                 return Ok(());
             }
+            if let (Some(name), false) = (database_name, event == "post-merge") {
+                if worktree {
+                    if let Some(ref main_dir) = main_worktree_dir {
+                        copy_worktree_files(&config, main_dir, false)?;
+                    }
+                }
+                return handle_database_git_hook(
+                    &config,
+                    name,
+                    json_output,
+                    _non_interactive,
+                    &mut local_state,
+                    &config_path,
+                )
+                .await;
+            }
             #[cfg(feature = "backend-postgres-template")]
-            {
+            if event == "post-merge" {
+                handle_post_merge_hook(&config, &db_manager, _non_interactive).await?;
+            } else {
                 handle_git_hook(
                     &mut config,
                     &db_manager,
@@ -535,11 +1362,27 @@ pub async fn handle_command(
             }
             #[cfg(not(feature = "backend-postgres-template"))]
             {
-                let _ = (worktree, main_worktree_dir);
+                let _ = (worktree, main_worktree_dir, event);
                 anyhow::bail!("Legacy git hook support requires the postgres-template backend. Rebuild with --features backend-postgres-template");
             }
         }
-        Commands::WorktreeSetup => {
+        Commands::WorktreeSetup { refresh: true } => {
+            refresh_worktree_files(&config)?;
+        }
+        Commands::WorktreeSetup { refresh: false } => {
+            if let Some(name) = database_name {
+                let main_dir = main_worktree_dir()?;
+                copy_worktree_files(&config, main_dir.to_str().unwrap_or(""), false)?;
+                return handle_database_git_hook(
+                    &config,
+                    name,
+                    json_output,
+                    _non_interactive,
+                    &mut local_state,
+                    &config_path,
+                )
+                .await;
+            }
             #[cfg(feature = "backend-postgres-template")]
             {
                 handle_worktree_setup(&mut config, &db_manager, &mut local_state, &config_path)
@@ -554,7 +1397,19 @@ pub async fn handle_command(
             branch_name,
             template,
             dry_run,
+            ..
         } => {
+            // `switch -` toggles back to the previously used branch
+            let (branch_name, template) = if branch_name.as_deref() == Some("-") {
+                match get_previous_branch(&local_state, &config_path) {
+                    Some(previous) if previous == "_main" => (None, true),
+                    Some(previous) => (Some(previous), template),
+                    None => anyhow::bail!("No previous branch to switch to"),
+                }
+            } else {
+                (branch_name, template)
+            };
+
             if dry_run {
                 if let Some(branch) = branch_name {
                     let normalized_branch = config.get_normalized_branch_name(&branch);
@@ -608,6 +1463,144 @@ pub async fn handle_command(
                 }
             }
         }
+        Commands::Project { action } => {
+            handle_project_command(action, &config, &config_path, json_output).await?;
+        }
+        Commands::Sign { file } => {
+            let signing = signing_config(&config)?;
+            let signature = crate::signing::sign(signing, &file)?;
+            if json_output {
+                print_json(&serde_json::json!({
+                    "file": file.display().to_string(),
+                    "signature": signature.display().to_string(),
+                }))?;
+            } else {
+                println!("Signed {}: {}", file.display(), signature.display());
+            }
+        }
+        Commands::Verify { file } => {
+            // An explicit check has nothing to fall back on
+            let signing = SigningConfig {
+                require: true,
+                ..signing_config(&config)?.clone()
+            };
+            let verification = crate::signing::verify(Some(&signing), &file)?;
+            let crate::signing::Verification::Verified { signer } = verification else {
+                unreachable!("verify allows unsigned files only when they aren't required");
+            };
+            if json_output {
+                print_json(&serde_json::json!({
+                    "file": file.display().to_string(),
+                    "verified": true,
+                    "signer": signer,
+                }))?;
+            } else {
+                println!("{}: good signature by {}", file.display(), signer);
+            }
+        }
+        Commands::Stats { all } => {
+            #[cfg(not(feature = "backend-local"))]
+            {
+                let _ = all;
+                return Err(backends::factory::not_compiled("local"));
+            }
+
+            #[cfg(feature = "backend-local")]
+            handle_stats_command(&config, json_output, database_name, all)?;
+        }
+        Commands::BenchStorage { size_mb } => {
+            #[cfg(not(feature = "backend-local"))]
+            {
+                let _ = size_mb;
+                return Err(backends::factory::not_compiled("local"));
+            }
+
+            #[cfg(feature = "backend-local")]
+            handle_bench_storage_command(&config, json_output, database_name, size_mb).await?;
+        }
+        Commands::Selftest => {
+            #[cfg(not(feature = "backend-local"))]
+            return Err(backends::factory::not_compiled("local"));
+
+            #[cfg(feature = "backend-local")]
+            handle_selftest_command(&config, json_output, database_name).await?;
+        }
+        Commands::Upgrade { check, force } => {
+            let outcome = crate::upgrade::upgrade(check, force).await?;
+            let current = crate::upgrade::CURRENT_VERSION;
+            if json_output {
+                print_json(&serde_json::json!({
+                    "current_version": current,
+                    "latest_version": outcome.latest_version,
+                    "installed": outcome.installed,
+                }))?;
+            } else if outcome.installed {
+                println!(
+                    "Upgraded pgbranch {} -> {}",
+                    current, outcome.latest_version
+                );
+            } else if outcome.latest_version == current {
+                println!("pgbranch {} is the latest release", current);
+            } else {
+                println!(
+                    "pgbranch {} (latest release: {})",
+                    current, outcome.latest_version
+                );
+                if check {
+                    println!("Run 'pgbranch upgrade' to install it.");
+                }
+            }
+        }
+        Commands::Auth { action } => {
+            handle_auth_command(action, &config, &config_path, _non_interactive, json_output)
+                .await?;
+        }
+        Commands::Recent { limit } => {
+            let recent = match (&local_state, &config_path) {
+                (Some(state_manager), Some(path)) => state_manager.get_recent_branches(path),
+                _ => Vec::new(),
+            };
+            let current = get_current_branch(&local_state, &config_path);
+            let recent: Vec<_> = recent.into_iter().take(limit).collect();
+
+            if json_output {
+                let entries: Vec<_> = recent
+                    .iter()
+                    .map(|r| {
+                        serde_json::json!({
+                            "name": r.name,
+                            "used_at": r.used_at,
+                            "current": current.as_deref() == Some(r.name.as_str()),
+                        })
+                    })
+                    .collect();
+                print_json(&entries)?;
+            } else if recent.is_empty() {
+                println!("No recently used branches");
+            } else {
+                println!("Recent branches:");
+                for r in &recent {
+                    let marker = if current.as_deref() == Some(r.name.as_str()) {
+                        " ★"
+                    } else {
+                        ""
+                    };
+                    let display_name = if r.name == "_main" {
+                        format!("{} (main)", config.database.template_database)
+                    } else {
+                        r.name.clone()
+                    };
+                    println!(
+                        "  {:<40} {}{}",
+                        display_name,
+                        r.used_at
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M"),
+                        marker
+                    );
+                }
+            }
+        }
         _ => unreachable!(),
     }
 
@@ -703,621 +1696,4751 @@ async fn attempt_zfs_auto_setup(non_interactive: bool) -> Option<String> {
     }
 }
 
-async fn init_local_backend_main(
-    config: &Config,
-    named_cfg: &crate::config::NamedBackendConfig,
-    from: Option<&str>,
-) {
-    match backends::factory::create_backend_from_named_config(config, named_cfg).await {
-        Ok(be) => {
-            match be.create_branch("main", None).await {
-                Ok(info) => {
-                    println!("Created main branch");
-                    if let Ok(conn) = be.get_connection_info("main").await {
-                        if let Some(ref uri) = conn.connection_string {
-                            println!("  Connection: {}", uri);
-                        }
-                    }
-                    if let Some(state) = &info.state {
-                        println!("  State: {}", state);
-                    }
+/// Look for a PostgreSQL service in Docker Compose files and ask whether to use its settings.
+fn detect_compose_postgres_config() -> Result<Option<docker::PostgresConfig>> {
+    let compose_files = docker::find_docker_compose_files();
+    if compose_files.is_empty() {
+        return Ok(None);
+    }
+    println!("Found Docker Compose files: {}", compose_files.join(", "));
 
-                    // Seed if --from specified
-                    if let Some(source) = from {
-                        println!("Seeding main branch from: {}", source);
-                        match be.seed_from_source("main", source).await {
-                            Ok(_) => println!("Seeding completed successfully"),
-                            Err(e) => eprintln!("Warning: seeding failed: {}", e),
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!(
-                        "Warning: could not create main branch for '{}': {}",
-                        named_cfg.name, e
-                    );
-                    eprintln!("  You can create it later with: pgbranch create main");
-                }
-            }
-        }
-        Err(e) => {
-            eprintln!(
-                "Warning: could not initialize backend '{}': {}",
-                named_cfg.name, e
-            );
-            eprintln!("  You can create the main branch later with: pgbranch create main");
+    match docker::parse_postgres_config_from_files(&compose_files)? {
+        Some(postgres_config) if docker::prompt_user_for_config_usage(&postgres_config)? => {
+            println!("Using PostgreSQL configuration from Docker Compose");
+            Ok(Some(postgres_config))
         }
+        _ => Ok(None),
     }
 }
 
-fn print_branch_tree(branches: &[backends::BranchInfo], indent: &str) {
-    use std::collections::HashMap;
+/// URL of the Compose database as seen from the host, checked to be reachable.
+fn compose_seed_url(postgres_config: &docker::PostgresConfig) -> Result<String> {
+    let user = postgres_config.user.as_deref().unwrap_or("postgres");
+    // The postgres image names the database after the user unless POSTGRES_DB is set
+    let database = postgres_config.database.as_deref().unwrap_or(user);
+    let port = postgres_config.port.unwrap_or(5432);
 
-    if branches.is_empty() {
-        println!("{}(none)", indent);
-        return;
+    let address = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    if std::net::TcpStream::connect_timeout(&address, std::time::Duration::from_secs(2)).is_err() {
+        anyhow::bail!(
+            "The Docker Compose database isn't reachable on {}. Start it with 'docker compose up -d' \
+             and make sure it publishes its port, then run init again",
+            address
+        );
     }
 
-    // Collect the set of known branch names for parent lookups
-    let known: std::collections::HashSet<&str> = branches.iter().map(|b| b.name.as_str()).collect();
-
-    // Group children by parent name
-    let mut children: HashMap<&str, Vec<&backends::BranchInfo>> = HashMap::new();
-    let mut roots: Vec<&backends::BranchInfo> = Vec::new();
+    let encode = |value: &str| -> String {
+        value
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    };
+    Ok(match postgres_config.password {
+        Some(ref password) => format!(
+            "postgresql://{}:{}@{}/{}",
+            encode(user),
+            encode(password),
+            address,
+            encode(database)
+        ),
+        None => format!(
+            "postgresql://{}@{}/{}",
+            encode(user),
+            address,
+            encode(database)
+        ),
+    })
+}
 
-    for b in branches {
-        match b.parent_branch.as_deref() {
-            Some(parent) if known.contains(parent) => {
-                children.entry(parent).or_default().push(b);
-            }
-            _ => roots.push(b),
-        }
+/// Replace actions that point `DATABASE_URL` in the app's env files at the current
+/// branch, plus the files they edit. Falls back to creating `.env`.
+fn compose_env_rewrites() -> (Vec<PostCommand>, Vec<String>) {
+    let mut files: Vec<String> = [
+        ".env",
+        ".env.local",
+        ".env.development",
+        ".env.development.local",
+    ]
+    .iter()
+    .filter(|file| {
+        std::fs::read_to_string(file)
+            .map(|content| content.lines().any(|l| l.starts_with("DATABASE_URL=")))
+            .unwrap_or(false)
+    })
+    .map(|file| file.to_string())
+    .collect();
+    if files.is_empty() {
+        files.push(".env".to_string());
     }
 
-    fn print_node(
-        branch: &backends::BranchInfo,
-        prefix: &str,
-        connector: &str,
-        children: &std::collections::HashMap<&str, Vec<&backends::BranchInfo>>,
-    ) {
-        let state_str = branch.state.as_deref().unwrap_or("unknown");
-        println!("{}{} [{}]", connector, branch.name, state_str);
+    let rewrites = files
+        .iter()
+        .map(|file| {
+            PostCommand::Replace(crate::config::ReplaceConfig {
+                action: "replace".to_string(),
+                name: Some(format!("Point DATABASE_URL in {} at the branch", file)),
+                file: file.clone(),
+                pattern: Some("DATABASE_URL=.*".to_string()),
+                replacement:
+                    "DATABASE_URL=postgresql://{db_user}:{db_password}@{db_host}:{db_port}/{db_name}"
+                        .to_string(),
+                key: None,
+                format: None,
+                create_if_missing: Some(true),
+                continue_on_error: None,
+                condition: None,
+                only_on: Vec::new(),
+                group: None,
+                depends_on: Vec::new(),
+            })
+        })
+        .collect();
+    (rewrites, files)
+}
 
-        if let Some(kids) = children.get(branch.name.as_str()) {
-            let count = kids.len();
-            for (i, child) in kids.iter().enumerate() {
-                let is_last = i == count - 1;
-                let child_connector = if is_last {
-                    format!("{}└─ ", prefix)
-                } else {
-                    format!("{}├─ ", prefix)
-                };
-                let child_prefix = if is_last {
-                    format!("{}   ", prefix)
-                } else {
-                    format!("{}│  ", prefix)
-                };
-                print_node(child, &child_prefix, &child_connector, children);
-            }
-        }
+/// Local backend settings for `init`, with credentials and databases from Docker Compose.
+fn local_backend_config(
+    compose: Option<&docker::PostgresConfig>,
+) -> crate::config::LocalBackendConfig {
+    crate::config::LocalBackendConfig {
+        image: None,
+        pull_policy: None,
+        runtime: None,
+        docker_host: None,
+        docker_context: None,
+        data_root: None,
+        storage: None,
+        port_range_start: None,
+        bind_address: None,
+        postgres_user: compose.and_then(|c| c.user.clone()),
+        postgres_password: compose.and_then(|c| c.password.clone()),
+        postgres_db: compose.and_then(|c| c.database.clone()),
+        extra_databases: compose
+            .map(|c| c.extra_databases.clone())
+            .filter(|dbs| !dbs.is_empty()),
+        pgpass: None,
+        hide_password: None,
+        capture_logs: None,
+        prune_protection: None,
+        shared: None,
+        state_store: None,
+        maintenance: None,
+        warmup: None,
+        zfs: None,
+        docker_retry: None,
     }
+}
 
-    for root in &roots {
-        print_node(root, indent, indent, &children);
-    }
+/// Local if this build has it, else the first backend type it does have.
+fn default_backend_type() -> String {
+    backends::factory::BackendInfo::available()
+        .next()
+        .map_or("local", |info| info.name)
+        .to_string()
 }
 
-async fn handle_backend_command(
-    cmd: Commands,
-    config: &mut Config,
-    json_output: bool,
-    non_interactive: bool,
+/// `pgbranch quickstart`: the steps of a first run, from checking Docker to
+/// a connection string the app can use. Each step is skipped if it's done.
+async fn handle_quickstart(
+    name: Option<String>,
+    backend: Option<String>,
+    from: Option<String>,
+    no_hooks: bool,
     database_name: Option<&str>,
-    config_path: &Option<std::path::PathBuf>,
+    non_interactive: bool,
+    json_output: bool,
 ) -> Result<()> {
-    // Aggregation commands (List, Status, Doctor) show all backends when no --database given
-    let is_aggregation = matches!(cmd, Commands::List | Commands::Status | Commands::Doctor);
-    let has_multiple_backends = config.resolve_backends().len() > 1;
+    let backend_type = backend.unwrap_or_else(default_backend_type);
+    let is_local = backends::factory::BackendInfo::is_local(&backend_type);
+
+    // 1. Docker, before anything is written
+    #[cfg(feature = "backend-local")]
+    if is_local {
+        let runtime = backends::local::docker::DockerRuntime::new(
+            crate::config::ContainerEngine::Docker,
+            None,
+            None,
+        )?;
+        let doctor = runtime.doctor().await;
+        if !doctor.available {
+            anyhow::bail!(
+                "Docker is not available: {}. Start Docker and run 'pgbranch quickstart' again",
+                doctor.detail
+            );
+        }
+        println!(
+            "✅ Docker is running{}",
+            doctor
+                .version
+                .map(|v| format!(" ({})", v))
+                .unwrap_or_default()
+        );
+    }
 
-    if is_aggregation && database_name.is_none() && has_multiple_backends {
-        return handle_multi_backend_command(cmd, config, json_output).await;
+    // 2. Configuration, storage and the main branch
+    let config_file = std::env::current_dir()?.join(".pgbranch.yml");
+    let initialized = !config_file.exists();
+    if initialized {
+        handle_init(
+            name,
+            false,
+            Some(backend_type.clone()),
+            from.clone(),
+            false,
+            database_name,
+            non_interactive,
+        )
+        .await?;
+    } else {
+        println!(
+            "✅ Already initialized ({}), keeping its settings",
+            config_file.display()
+        );
     }
 
-    let named = backends::factory::resolve_backend(config, database_name).await?;
+    let project = crate::project::Project::load()?;
+    let config = &project.config;
+    let named = project.backend(database_name).await?;
     let backend = named.backend;
-    let resolved_name = named.name;
-
-    // For mutation commands with multiple backends and no --database, print a note
-    if !is_aggregation && database_name.is_none() && has_multiple_backends {
-        eprintln!(
-            "note: using default database '{}'. Use --database to target a specific one.",
-            resolved_name
-        );
+    if is_local && !backend.branch_exists("main").await? {
+        backend.create_branch("main", None).await?;
+        println!("Created main branch");
+        if let Some(ref source) = from {
+            println!("Seeding main branch from: {}", source);
+            backend.seed_from_source("main", source).await?;
+        }
     }
 
-    match cmd {
-        Commands::Create { branch_name, from } => {
-            let info = backend.create_branch(&branch_name, from.as_deref()).await?;
-            if json_output {
-                println!("{}", serde_json::to_string_pretty(&info)?);
-            } else {
-                println!("Created database branch: {}", info.name);
-                if let Some(state) = &info.state {
-                    println!("  State: {}", state);
-                }
-                if let Some(parent) = &info.parent_branch {
-                    println!("  Parent: {}", parent);
-                }
-                // Show connection info
-                if let Ok(conn) = backend.get_connection_info(&branch_name).await {
-                    if let Some(ref uri) = conn.connection_string {
-                        println!("  Connection: {}", uri);
-                    }
-                }
+    // 3. Git hooks, so switching Git branches switches database branches
+    let hooks_installed = match GitRepository::new(".") {
+        Ok(_) if no_hooks || config.standalone => false,
+        Ok(git_repo) => {
+            let install = non_interactive
+                || inquire::Confirm::new(
+                    "Install Git hooks so checking out a Git branch switches its database branch?",
+                )
+                .with_default(true)
+                .prompt()?;
+            if install {
+                git_repo.install_hooks()?;
+                println!("✅ Installed Git hooks");
             }
+            install
+        }
+        Err(_) => {
+            println!("Not a Git repository; skipping the Git hooks");
+            false
+        }
+    };
+
+    // 4. How to connect. Only local databases have a main branch of their own
+    let url = if is_local {
+        let conn = backend.get_connection_info("main").await?;
+        Some(connection_uri(&conn))
+    } else {
+        None
+    };
+    if json_output {
+        print_json(&serde_json::json!({
+            "status": "ok",
+            "database": named.name,
+            "config": config_file,
+            "initialized": initialized,
+            "hooks_installed": hooks_installed,
+            "connection_string": url,
+        }))?;
+        return Ok(());
+    }
+    println!();
+    let url = match url {
+        Some(url) => {
+            println!("🎉 pgbranch is ready. Main branch:");
+            println!("  {}", url);
+            url
+        }
+        None => {
+            println!("🎉 pgbranch is ready. 'pgbranch connection <branch>' prints a branch's connection string.");
+            "<connection string>".to_string()
+        }
+    };
+    println!();
+    println!("Point your app at it:");
+    println!("  .env         DATABASE_URL={}", url);
+    println!("  Rails        config/database.yml: url: <%= ENV[\"DATABASE_URL\"] %>");
+    println!("  Django       DATABASES = {{\"default\": dj_database_url.config()}}");
+    println!("  Prisma       datasource db {{ url = env(\"DATABASE_URL\") }}");
+    println!("  Node (pg)    new Pool({{ connectionString: process.env.DATABASE_URL }})");
+    println!();
+    if hooks_installed {
+        println!("Next: check out a new Git branch and it gets its own database branch.");
+    } else {
+        println!("Next: 'pgbranch switch <branch>' creates a database branch and switches to it.");
+    }
+    Ok(())
+}
+
+/// `pgbranch init`: write `.pgbranch.yml` (or add a database to an existing
+/// one) and, for local databases, create and seed the main branch.
+async fn handle_init(
+    name: Option<String>,
+    force: bool,
+    backend: Option<String>,
+    from: Option<String>,
+    standalone: bool,
+    database_name: Option<&str>,
+    _non_interactive: bool,
+) -> Result<()> {
+    let config_path = std::env::current_dir()?.join(".pgbranch.yml");
+
+    // Resolve the name: the argument or --database, else derive from current directory
+    let resolved_name = match (name, database_name) {
+        (Some(n), Some(d)) if n != d => {
+            anyhow::bail!("Conflicting database names: '{}' and --database '{}'", n, d)
+        }
+        (Some(n), _) => n,
+        (None, Some(d)) => d.to_string(),
+        (None, None) => std::env::current_dir()?
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "default".to_string()),
+    };
+
+    let backend_type = backend.unwrap_or_else(default_backend_type);
+    // Refuse to write a config this build can't use
+    backends::factory::BackendInfo::resolve(&backend_type)?;
+    let is_local = backends::factory::BackendInfo::is_local(&backend_type);
+    let is_postgres_template =
+        backends::factory::BackendInfo::canonical_name(&backend_type) == "postgres_template";
+
+    // `--from docker-compose` migrates off the app's Compose database: keep its
+    // credentials, seed main from it and point the app's env files at branches
+    let compose_migration = if matches!(from.as_deref(), Some("docker-compose" | "compose")) {
+        if !is_local {
+            anyhow::bail!("--from docker-compose requires the local backend");
+        }
+        let compose_files = docker::find_docker_compose_files();
+        let postgres_config = docker::parse_postgres_config_from_files(&compose_files)?
+            .ok_or_else(|| {
+                anyhow::anyhow!("No PostgreSQL service found in Docker Compose files")
+            })?;
+        println!(
+            "Migrating from the PostgreSQL service in {}",
+            compose_files.join(", ")
+        );
+        Some(postgres_config)
+    } else {
+        None
+    };
+    let from = match compose_migration {
+        Some(ref postgres_config) => Some(compose_seed_url(postgres_config)?),
+        None => from,
+    };
+
+    if config_path.exists() {
+        // --- Subsequent init: add a new backend to state (don't modify .pgbranch.yml) ---
+        let config = Config::from_file(&config_path)?;
+        let compose = if compose_migration.is_some() {
+            compose_migration.clone()
+        } else if is_local {
+            detect_compose_postgres_config()?
+        } else {
+            None
+        };
+        if compose_migration.is_some() {
+            // .pgbranch.yml is left alone here, so only suggest the rewrites
+            let (rewrites, _) = compose_env_rewrites();
+            println!(
+                "Add these post_commands to {} to point the app at database branches:",
+                config_path.display()
+            );
+            println!(
+                "{}",
+                serde_yaml_ng::to_string(&serde_json::json!({ "post_commands": rewrites }))?
+            );
+        }
+
+        // Build new named backend config
+        let named_cfg = crate::config::NamedBackendConfig {
+            name: resolved_name.clone(),
+            backend_type: backend_type.clone(),
+            default: false,
+            local: is_local.then(|| local_backend_config(compose.as_ref())),
+            neon: None,
+            dblab: None,
+            xata: None,
+            branch_filter: None,
+        };
+
+        // Store backend in local state instead of committed config
+        let mut state = LocalStateManager::new()?;
+        state.add_backend(&config_path, named_cfg.clone(), force)?;
+        println!("Added backend '{}' to local state", resolved_name);
+
+        // Create main branch for local backends
+        if is_local {
+            // Build a config with the backend injected so the factory can find it
+            let mut config_with_backend = config;
+            if let Some(state_backends) = state.get_backends(&config_path) {
+                config_with_backend.backends = Some(state_backends);
+                config_with_backend.backend = None;
+            }
+
+            // On Linux, offer ZFS auto-setup before creating the main branch
+            #[cfg(feature = "backend-local")]
+            if cfg!(target_os = "linux") {
+                if let Some(data_root) = attempt_zfs_auto_setup(_non_interactive).await {
+                    let mut updated_cfg = named_cfg.clone();
+                    if let Some(ref mut local) = updated_cfg.local {
+                        local.data_root = Some(data_root);
+                    }
+                    let _ = state.add_backend(&config_path, updated_cfg.clone(), true);
+                    if let Some(state_backends) = state.get_backends(&config_path) {
+                        config_with_backend.backends = Some(state_backends);
+                    }
+                    init_local_backend_main(&config_with_backend, &updated_cfg, from.as_deref())
+                        .await;
+                } else {
+                    init_local_backend_main(&config_with_backend, &named_cfg, from.as_deref())
+                        .await;
+                }
+            } else {
+                init_local_backend_main(&config_with_backend, &named_cfg, from.as_deref()).await;
+            }
+            #[cfg(not(feature = "backend-local"))]
+            {
+                init_local_backend_main(&config_with_backend, &named_cfg, from.as_deref()).await;
+            }
+        }
+    } else {
+        // --- First-time init: create .pgbranch.yml ---
+        let mut config = Config {
+            standalone,
+            ..Config::default()
+        };
+
+        // Auto-detect main Git branch
+        if standalone {
+            println!("Standalone project: Git hooks and worktrees are off");
+        } else if let Ok(git_repo) = GitRepository::new(".") {
+            if let Ok(Some(detected_main)) = git_repo.detect_main_branch() {
+                config.git.main_branch = detected_main.clone();
+                println!("Auto-detected main Git branch: {}", detected_main);
+            } else {
+                println!("Could not auto-detect main Git branch, using default: main");
+            }
+        }
+
+        // Take credentials and database names from Docker Compose, if there is any
+        let compose = if compose_migration.is_some() {
+            compose_migration.clone()
+        } else if is_postgres_template || is_local {
+            detect_compose_postgres_config()?
+        } else {
+            None
+        };
+        if compose_migration.is_some() {
+            let (rewrites, files) = compose_env_rewrites();
+            config.post_commands.extend(rewrites);
+            println!(
+                "Added post-commands pointing DATABASE_URL in {} at the current branch",
+                files.join(", ")
+            );
+        }
+        if let (true, Some(postgres_config)) = (is_postgres_template, compose.clone()) {
+            if let Some(host) = postgres_config.host {
+                config.database.host = host;
+            }
+            if let Some(port) = postgres_config.port {
+                config.database.port = port;
+            }
+            if let Some(user) = postgres_config.user {
+                config.database.user = user;
+            }
+            if let Some(password) = postgres_config.password {
+                config.database.password = Some(password);
+            }
+            if let Some(database) = postgres_config.database {
+                config.database.template_database = database;
+            }
+        }
+
+        // Build named backend config
+        let named_cfg = crate::config::NamedBackendConfig {
+            name: resolved_name.clone(),
+            backend_type: backend_type.clone(),
+            default: true,
+            local: is_local.then(|| local_backend_config(compose.as_ref())),
+            neon: None,
+            dblab: None,
+            xata: None,
+            branch_filter: None,
+        };
+
+        // Don't write backends to committed config — store in state
+        config.backends = None;
+        config.backend = None;
+        config.save_to_file(&config_path)?;
+        println!(
+            "Initialized pgbranch configuration at: {}",
+            config_path.display()
+        );
+
+        // Store backend in local state
+        let mut state = LocalStateManager::new()?;
+        state.set_backends(&config_path, vec![named_cfg.clone()])?;
+        println!("Stored backend '{}' in local state", resolved_name);
+
+        // Inject backends into config so init_local_backend_main can use them
+        config.backends = Some(vec![named_cfg.clone()]);
+
+        // Create main branch for local backends
+        if is_local {
+            // On Linux, offer ZFS auto-setup before creating the main branch
+            #[cfg(feature = "backend-local")]
+            if cfg!(target_os = "linux") {
+                if let Some(data_root) = attempt_zfs_auto_setup(_non_interactive).await {
+                    // Update the named backend config with the ZFS data_root
+                    let mut updated_cfg = named_cfg.clone();
+                    if let Some(ref mut local) = updated_cfg.local {
+                        local.data_root = Some(data_root);
+                    }
+                    // Update in state and injected config
+                    if let Ok(mut state) = LocalStateManager::new() {
+                        let _ = state.set_backends(&config_path, vec![updated_cfg.clone()]);
+                    }
+                    config.backends = Some(vec![updated_cfg.clone()]);
+                    init_local_backend_main(&config, &updated_cfg, from.as_deref()).await;
+                } else {
+                    init_local_backend_main(&config, &named_cfg, from.as_deref()).await;
+                }
+            } else {
+                init_local_backend_main(&config, &named_cfg, from.as_deref()).await;
+            }
+            #[cfg(not(feature = "backend-local"))]
+            {
+                init_local_backend_main(&config, &named_cfg, from.as_deref()).await;
+            }
+        }
+
+        // Suggest adding local config to gitignore
+        let gitignore_path = std::env::current_dir()?.join(".gitignore");
+        if gitignore_path.exists() {
+            println!("\nSuggestion: Add '.pgbranch.local.yml' to your .gitignore file:");
+            println!("   echo '.pgbranch.local.yml' >> .gitignore");
+        }
+    }
+    Ok(())
+}
+
+async fn init_local_backend_main(
+    config: &Config,
+    named_cfg: &crate::config::NamedBackendConfig,
+    from: Option<&str>,
+) {
+    match backends::factory::create_backend_from_named_config(config, named_cfg).await {
+        Ok(be) => {
+            match be.create_branch("main", None).await {
+                Ok(info) => {
+                    println!("Created main branch");
+                    if let Ok(conn) = be.get_connection_info("main").await {
+                        if let Some(ref uri) = conn.connection_string {
+                            println!("  Connection: {}", uri);
+                        }
+                    }
+                    if let Some(state) = &info.state {
+                        println!("  State: {}", state);
+                    }
+
+                    // Seed if --from specified
+                    if let Some(source) = from {
+                        println!("Seeding main branch from: {}", source);
+                        let started = std::time::Instant::now();
+                        match be.seed_from_source("main", source).await {
+                            Ok(_) => println!(
+                                "Seeding completed successfully ({})",
+                                format_elapsed(started.elapsed())
+                            ),
+                            Err(e) => eprintln!("Warning: seeding failed: {}", e),
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: could not create main branch for '{}': {}",
+                        named_cfg.name, e
+                    );
+                    eprintln!("  You can create it later with: pgbranch create main");
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: could not initialize backend '{}': {}",
+                named_cfg.name, e
+            );
+            eprintln!("  You can create the main branch later with: pgbranch create main");
+        }
+    }
+}
+
+/// "12.0 GiB used of a 50.0 GiB quota, 38.0 GiB left", as far as it's known.
+/// How far back `status` reports drift.
+const DRIFT_WINDOW_HOURS: i64 = 24;
+
+/// Summary of what happened to branches outside pgbranch, then each change.
+fn print_drift(drift: &[backends::DriftEvent], indent: &str) {
+    if drift.is_empty() {
+        return;
+    }
+    let count = |state: &str| {
+        drift
+            .iter()
+            .filter(|d| d.to_state == state)
+            .map(|d| d.branch.as_str())
+            .collect::<std::collections::HashSet<_>>()
+            .len()
+    };
+    let mut parts: Vec<String> = Vec::new();
+    for (state, verb) in [
+        ("stopped", "stopped"),
+        ("failed", "lost"),
+        ("running", "started"),
+    ] {
+        let n = count(state);
+        if n == 0 {
+            continue;
+        }
+        parts.push(if parts.is_empty() {
+            format!(
+                "{} {} {}",
+                n,
+                if n == 1 {
+                    "branch was"
+                } else {
+                    "branches were"
+                },
+                verb
+            )
+        } else {
+            format!("{} {}", n, verb)
+        });
+    }
+    let summary = match parts.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => parts.join(""),
+    };
+    println!(
+        "{}Drift: {} outside pgbranch in the last {} hours",
+        indent, summary, DRIFT_WINDOW_HOURS
+    );
+    for event in drift {
+        println!(
+            "{}  {}: {} → {} ({}), noticed {}",
+            indent,
+            event.branch,
+            event.from_state,
+            event.to_state,
+            event.reason,
+            event
+                .detected_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+        );
+    }
+}
+
+fn describe_space(usage: &backends::SpaceUsage) -> String {
+    let mut text = format!("{} used", backends::format_bytes(usage.used_bytes));
+    if let Some(quota) = usage.quota_bytes {
+        text.push_str(&format!(" of a {} quota", backends::format_bytes(quota)));
+    }
+    if let Some(available) = usage.available_bytes {
+        text.push_str(&format!(", {} left", backends::format_bytes(available)));
+    }
+    text
+}
+
+fn print_branch_tree(branches: &[backends::BranchInfo], indent: &str, verbose: bool) {
+    use std::collections::HashMap;
+
+    if branches.is_empty() {
+        println!("{}(none)", indent);
+        return;
+    }
+
+    // Collect the set of known branch names for parent lookups
+    let known: std::collections::HashSet<&str> = branches.iter().map(|b| b.name.as_str()).collect();
+
+    // Group children by parent name
+    let mut children: HashMap<&str, Vec<&backends::BranchInfo>> = HashMap::new();
+    let mut roots: Vec<&backends::BranchInfo> = Vec::new();
+
+    for b in branches {
+        match b.parent_branch.as_deref() {
+            Some(parent) if known.contains(parent) => {
+                children.entry(parent).or_default().push(b);
+            }
+            _ => roots.push(b),
+        }
+    }
+
+    fn print_node(
+        branch: &backends::BranchInfo,
+        prefix: &str,
+        connector: &str,
+        children: &std::collections::HashMap<&str, Vec<&backends::BranchInfo>>,
+        verbose: bool,
+    ) {
+        let state = branch.state.as_deref().unwrap_or("unknown");
+        let state_str = match branch.health {
+            Some(_) => format!("{state}(unhealthy)"),
+            None => state.to_string(),
+        };
+        if verbose {
+            let format_time = |time: Option<chrono::DateTime<chrono::Utc>>| {
+                time.map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_else(|| "-".to_string())
+            };
+            println!(
+                "{}{} [{}]  created {}, last used {}",
+                connector,
+                branch.name,
+                state_str,
+                format_time(branch.created_at),
+                format_time(branch.last_used_at)
+            );
+            if let Some(reason) = &branch.health {
+                println!("{}   {}", prefix, reason);
+            }
+        } else {
+            println!("{}{} [{}]", connector, branch.name, state_str);
+        }
+
+        if let Some(kids) = children.get(branch.name.as_str()) {
+            let count = kids.len();
+            for (i, child) in kids.iter().enumerate() {
+                let is_last = i == count - 1;
+                let child_connector = if is_last {
+                    format!("{}└─ ", prefix)
+                } else {
+                    format!("{}├─ ", prefix)
+                };
+                let child_prefix = if is_last {
+                    format!("{}   ", prefix)
+                } else {
+                    format!("{}│  ", prefix)
+                };
+                print_node(child, &child_prefix, &child_connector, children, verbose);
+            }
+        }
+    }
+
+    for root in &roots {
+        print_node(root, indent, indent, &children, verbose);
+    }
+}
+
+async fn handle_backend_command(
+    cmd: Commands,
+    config: &mut Config,
+    json_output: bool,
+    non_interactive: bool,
+    database_name: Option<&str>,
+    config_path: &Option<std::path::PathBuf>,
+) -> Result<()> {
+    // Aggregation commands (List, Status, Doctor) show all backends when no --database given
+    let is_aggregation = matches!(
+        cmd,
+        Commands::List { .. } | Commands::Status | Commands::Doctor
+    );
+    let has_multiple_backends = config.resolve_backends().len() > 1;
+
+    if is_aggregation && database_name.is_none() && has_multiple_backends {
+        return handle_multi_backend_command(cmd, config, json_output).await;
+    }
+
+    let named = backends::factory::resolve_backend(config, database_name).await?;
+    let backend = named.backend;
+    let resolved_name = named.name;
+
+    // For mutation commands with multiple backends and no --database, print a note
+    if !is_aggregation && database_name.is_none() && has_multiple_backends {
+        eprintln!(
+            "note: using default database '{}'. Use --database to target a specific one.",
+            resolved_name
+        );
+    }
+
+    if let (true, Some(keep)) = (config.behavior.auto_prune, auto_prune_keeps(config, &cmd)) {
+        auto_prune(config, &resolved_name, backend.as_ref(), &keep, config_path).await;
+    }
+
+    match cmd {
+        Commands::Create {
+            branch_name,
+            from,
+            schema_only,
+            no_maintenance,
+            pgconf,
+            timings,
+            dry_run,
+            ..
+        } => {
+            check_branch_name(backend.as_ref(), &branch_name)?;
+            let pg_settings = parse_pg_settings(&pgconf)?;
+            if !pg_settings.is_empty() && !backend.supports_lifecycle() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support per-branch PostgreSQL settings",
+                    backend.backend_name()
+                ));
+            }
+            let started = std::time::Instant::now();
+            let remote_source = from
+                .as_deref()
+                .and_then(|f| split_database_qualified(config, f))
+                .filter(|(database, _)| *database != resolved_name);
+            if schema_only && remote_source.is_some() {
+                anyhow::bail!("--schema-only cannot be combined with --from <database>:<branch>");
+            }
+
+            if dry_run {
+                let mut actions = match remote_source {
+                    Some((source_db, source_branch)) => vec![backends::PlannedAction::new(
+                        "postgres",
+                        format!(
+                            "Create empty branch '{}' and copy {}:{} into it via pg_dump/pg_restore",
+                            branch_name, source_db, source_branch
+                        ),
+                    )],
+                    None => {
+                        let parent = from.as_deref().map(|f| {
+                            split_database_qualified(config, f)
+                                .map(|(_, branch)| branch)
+                                .unwrap_or(f)
+                        });
+                        if schema_only {
+                            backend
+                                .plan_create_schema_only_branch(&branch_name, parent)
+                                .await?
+                        } else {
+                            backend.plan_create_branch(&branch_name, parent).await?
+                        }
+                    }
+                };
+                if !pg_settings.is_empty() {
+                    actions.push(backends::PlannedAction::new(
+                        "docker",
+                        format!(
+                            "Recreate the container with {}",
+                            pg_settings
+                                .iter()
+                                .map(|(key, value)| format!("-c {}={}", key, value))
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                        ),
+                    ));
+                }
+                if !no_maintenance {
+                    actions.extend(plan_maintenance(backend.as_ref()));
+                }
+                if !config.post_commands.is_empty() {
+                    actions.push(backends::PlannedAction::new(
+                        "post-commands",
+                        format!("Run {} post-command(s)", config.post_commands.len()),
+                    ));
+                }
+                return print_plan(
+                    &format!("create branch '{}'", branch_name),
+                    &actions,
+                    json_output,
+                );
+            }
+
+            let info = if let Some((source_db, source_branch)) = remote_source {
+                let source_url = remote_branch_url(config, source_db, source_branch).await?;
+                if !json_output {
+                    println!(
+                        "Copying {}:{} into '{}' via pg_dump/pg_restore...",
+                        source_db, source_branch, resolved_name
+                    );
+                }
+                let mut info = run_interruptible(
+                    backend.as_ref(),
+                    &branch_name,
+                    backend.create_branch_from_source(&branch_name, &source_url),
+                )
+                .await?;
+                info.parent_branch = from.clone();
+                info
+            } else {
+                // "<this database>:<branch>" is just a local parent
+                let parent = from.as_deref().map(|f| {
+                    split_database_qualified(config, f)
+                        .map(|(_, branch)| branch)
+                        .unwrap_or(f)
+                });
+                if schema_only {
+                    run_interruptible(
+                        backend.as_ref(),
+                        &branch_name,
+                        backend.create_schema_only_branch(&branch_name, parent),
+                    )
+                    .await?
+                } else {
+                    run_interruptible(
+                        backend.as_ref(),
+                        &branch_name,
+                        backend.create_branch(&branch_name, parent),
+                    )
+                    .await?
+                }
+            };
+            if !pg_settings.is_empty() {
+                backend
+                    .set_branch_pg_settings(&branch_name, &pg_settings)
+                    .await?;
+            }
+            let maintenance = if no_maintenance {
+                vec![]
+            } else {
+                run_maintenance(backend.as_ref(), &branch_name).await
+            };
+            let elapsed = started.elapsed();
+            let phases = backend.take_phase_timings();
+            if json_output {
+                let mut value = serde_json::to_value(&info)?;
+                value["duration_ms"] = serde_json::json!(elapsed.as_millis() as u64);
+                value["maintenance"] = serde_json::json!(maintenance);
+                if timings {
+                    value["timings"] = serde_json::to_value(&phases)?;
+                }
+                print_json(&value)?;
+            } else {
+                println!(
+                    "Created database branch: {} ({})",
+                    info.name,
+                    format_elapsed(elapsed)
+                );
+                if timings {
+                    print_phase_timings(&phases);
+                }
+                if let Some(state) = &info.state {
+                    println!("  State: {}", state);
+                }
+                if let Some(parent) = &info.parent_branch {
+                    println!("  Parent: {}", parent);
+                }
+                if schema_only {
+                    println!("  Data: schema only (migration history kept)");
+                }
+                if !maintenance.is_empty() {
+                    println!("  Maintenance: {}", maintenance.join(", "));
+                }
+                // Show connection info
+                if let Ok(conn) = backend.get_connection_info(&branch_name).await {
+                    if let Some(ref uri) = conn.connection_string {
+                        println!("  Connection: {}", uri);
+                    }
+                }
+            }
+
+            // Execute post-commands
+            if !config.post_commands.is_empty() {
+                let mut executor =
+                    PostCommandExecutor::new(config, &branch_name, PostCommandEvent::Create)?;
+                if let Ok(conn) = backend.get_connection_info(&branch_name).await {
+                    executor = executor.with_connection_info(&conn, backend.backend_type());
+                }
+                executor.execute_all_post_commands().await?;
+            }
+
+            auto_cleanup(
+                config,
+                &resolved_name,
+                backend.as_ref(),
+                &branch_name,
+                non_interactive,
+                json_output,
+            )
+            .await;
+        }
+        Commands::Worktree {
+            action:
+                WorktreeCommands::Add {
+                    path,
+                    branch,
+                    base,
+                    from,
+                    env_file,
+                },
+        } => {
+            handle_worktree_add(
+                config,
+                backend.as_ref(),
+                &path,
+                &branch,
+                base.as_deref(),
+                from.as_deref(),
+                &env_file,
+                json_output,
+            )
+            .await?;
+        }
+        Commands::Delete {
+            branch_name,
+            dry_run,
+            force,
+            ..
+        } => {
+            if dry_run {
+                let actions = backend.plan_delete_branch(&branch_name).await?;
+                return print_plan(
+                    &format!("delete branch '{}'", branch_name),
+                    &actions,
+                    json_output,
+                );
+            }
+            let mut local_state = LocalStateManager::new().ok();
+            let was_current =
+                check_deleting_current_branch(&local_state, config_path, &branch_name, force)?;
+            backend.delete_branch(&branch_name).await?;
+            if was_current {
+                set_current_branch(&mut local_state, config_path, Some("_main".to_string()))?;
+            }
+            audit::record(
+                config,
+                AuditEvent {
+                    action: AuditAction::BranchDeleted,
+                    database: &resolved_name,
+                    backend: backend.backend_type(),
+                    branches: std::slice::from_ref(&branch_name),
+                },
+            )
+            .await;
+            if json_output {
+                print_json(&serde_json::json!({ "status": "ok", "deleted": branch_name }))?;
+            } else {
+                println!("Deleted database branch: {}", branch_name);
+            }
+        }
+        Commands::List { verbose } => {
+            let branches = backend.list_branches().await?;
+            if json_output {
+                print_json(&branches)?;
+            } else {
+                println!("Database branches ({}):", backend.backend_name());
+                print_branch_tree(&branches, "  ", verbose);
+            }
+        }
+        Commands::Start {
+            branch_name,
+            pgconf,
+        } => {
+            if !backend.supports_lifecycle() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support start/stop lifecycle",
+                    backend.backend_name()
+                ));
+            }
+            if !pgconf.is_empty() {
+                let settings = parse_pg_settings(&pgconf)?;
+                backend
+                    .set_branch_pg_settings(&branch_name, &settings)
+                    .await?;
+            }
+            let port = backend
+                .get_connection_info(&branch_name)
+                .await
+                .ok()
+                .map(|conn| conn.port);
+            backend.start_branch(&branch_name).await?;
+            // The backend moves a branch whose port was taken while it was stopped
+            let moved = match port {
+                Some(port) => backend
+                    .get_connection_info(&branch_name)
+                    .await
+                    .ok()
+                    .filter(|conn| conn.port != port)
+                    .map(|conn| (port, conn)),
+                None => None,
+            };
+            if json_output {
+                let mut output = serde_json::json!({ "status": "ok", "started": branch_name });
+                if let Some((previous_port, conn)) = &moved {
+                    output["previous_port"] = serde_json::json!(previous_port);
+                    output["connection"] = serde_json::to_value(conn)?;
+                }
+                print_json(&output)?;
+            } else {
+                println!("Started branch: {}", branch_name);
+                if let Some((previous_port, conn)) = &moved {
+                    println!(
+                        "Port {} was taken, so it now listens on port {}: {}",
+                        previous_port,
+                        conn.port,
+                        connection_uri(conn)
+                    );
+                }
+            }
+        }
+        Commands::Stop { branch_name, idle } => {
+            if !backend.supports_lifecycle() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support start/stop lifecycle",
+                    backend.backend_name()
+                ));
+            }
+            if let Some(idle) = idle {
+                let max_idle = crate::config::parse_age(&idle, &format!("--idle '{}'", idle))?;
+                let cutoff = chrono::Utc::now() - max_idle;
+                let idle_branches: Vec<String> = backend
+                    .list_branches()
+                    .await?
+                    .into_iter()
+                    .filter(|b| b.state.as_deref() == Some("running"))
+                    .filter(|b| b.last_used_at.or(b.created_at).is_some_and(|t| t < cutoff))
+                    .map(|b| b.name)
+                    .collect();
+                let mut stopped = Vec::new();
+                for name in idle_branches {
+                    match backend.stop_branch(&name).await {
+                        Ok(()) => stopped.push(name),
+                        Err(e) => eprintln!("Warning: failed to stop {}: {}", name, e),
+                    }
+                }
+                if json_output {
+                    print_json(&serde_json::json!({ "status": "ok", "stopped": stopped }))?;
+                } else if stopped.is_empty() {
+                    println!("No branches idle for {}", idle);
+                } else {
+                    println!(
+                        "Stopped {} idle branch(es): {}",
+                        stopped.len(),
+                        stopped.join(", ")
+                    );
+                }
+                return Ok(());
+            }
+            let branch_name = branch_name
+                .ok_or_else(|| anyhow::anyhow!("Give a branch name to stop, or --idle <age>"))?;
+            backend.stop_branch(&branch_name).await?;
+            if json_output {
+                print_json(&serde_json::json!({ "status": "ok", "stopped": branch_name }))?;
+            } else {
+                println!("Stopped branch: {}", branch_name);
+            }
+        }
+        Commands::Reset {
+            branch_name,
+            to_snapshot,
+            keep_snapshots,
+            timings,
+            dry_run,
+        } => {
+            if !backend.supports_lifecycle() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support reset",
+                    backend.backend_name()
+                ));
+            }
+            if to_snapshot.is_some() && !backend.supports_snapshots() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support snapshots",
+                    backend.backend_name()
+                ));
+            }
+            if dry_run {
+                let actions = backend
+                    .plan_reset_branch(&branch_name, to_snapshot.as_deref(), keep_snapshots)
+                    .await?;
+                return print_plan(
+                    &format!("reset branch '{}'", branch_name),
+                    &actions,
+                    json_output,
+                );
+            }
+            let started = std::time::Instant::now();
+            run_interruptible(
+                backend.as_ref(),
+                &branch_name,
+                backend.reset_branch(&branch_name, to_snapshot.as_deref(), keep_snapshots),
+            )
+            .await?;
+            let elapsed = started.elapsed();
+            let phases = backend.take_phase_timings();
+            if json_output {
+                let mut status = serde_json::json!({
+                    "status": "ok",
+                    "reset": branch_name,
+                    "duration_ms": elapsed.as_millis() as u64,
+                });
+                if let Some(ref snapshot) = to_snapshot {
+                    status["snapshot"] = serde_json::Value::String(snapshot.clone());
+                }
+                if timings {
+                    status["timings"] = serde_json::to_value(&phases)?;
+                }
+                print_json(&status)?;
+            } else {
+                match to_snapshot {
+                    Some(ref snapshot) => println!(
+                        "Reset branch {} to snapshot: {} ({})",
+                        branch_name,
+                        snapshot,
+                        format_elapsed(elapsed)
+                    ),
+                    None => println!(
+                        "Reset branch: {} ({})",
+                        branch_name,
+                        format_elapsed(elapsed)
+                    ),
+                }
+                if timings {
+                    print_phase_timings(&phases);
+                }
+            }
+        }
+        Commands::Seed {
+            branch_name,
+            from,
+            no_maintenance,
+            dry_run,
+        } => {
+            if dry_run {
+                let mut actions = backend.plan_seed_from_source(&branch_name, &from).await?;
+                if !no_maintenance {
+                    actions.extend(plan_maintenance(backend.as_ref()));
+                }
+                return print_plan(
+                    &format!("seed branch '{}'", branch_name),
+                    &actions,
+                    json_output,
+                );
+            }
+            let started = std::time::Instant::now();
+            run_interruptible(
+                backend.as_ref(),
+                &branch_name,
+                backend.seed_from_source(&branch_name, &from),
+            )
+            .await?;
+            let maintenance = if no_maintenance {
+                vec![]
+            } else {
+                run_maintenance(backend.as_ref(), &branch_name).await
+            };
+            let elapsed = started.elapsed();
+            if json_output {
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "seeded": branch_name,
+                    "duration_ms": elapsed.as_millis() as u64,
+                    "maintenance": maintenance,
+                }))?;
+            } else {
+                println!(
+                    "Seeded branch: {} ({})",
+                    branch_name,
+                    format_elapsed(elapsed)
+                );
+                if !maintenance.is_empty() {
+                    println!("  Maintenance: {}", maintenance.join(", "));
+                }
+            }
+        }
+        Commands::Snapshot { action } => {
+            if !backend.supports_snapshots() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support snapshots",
+                    backend.backend_name()
+                ));
+            }
+            handle_snapshot_command(backend.as_ref(), action, json_output).await?;
+        }
+        Commands::Fixture {
+            action:
+                FixtureCommands::Export {
+                    branch_name,
+                    since_parent,
+                    output,
+                },
+        } => {
+            let fixture = backend.export_fixture(&branch_name, since_parent).await?;
+            let rows: usize = fixture.tables.iter().map(|t| t.rows).sum();
+            let Some(dir) = output else {
+                print!("{}", fixture.sql);
+                return Ok(());
+            };
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            let path = dir.join(format!("{}.sql", branch_name.replace('/', "-")));
+            std::fs::write(&path, &fixture.sql)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            let signature = sign_export(config, &path)?;
+            if json_output {
+                let mut output = serde_json::json!({
+                    "branch": branch_name,
+                    "file": path.display().to_string(),
+                    "rows": rows,
+                    "tables": fixture.tables,
+                });
+                if let Some(ref signature) = signature {
+                    output["signature"] = serde_json::json!(signature.display().to_string());
+                }
+                print_json(&output)?;
+            } else {
+                println!(
+                    "Wrote {} row(s) from {} table(s) to {}",
+                    rows,
+                    fixture.tables.len(),
+                    path.display()
+                );
+                if let Some(ref signature) = signature {
+                    println!("Signed it: {}", signature.display());
+                }
+                for table in &fixture.tables {
+                    println!("  {:<40} {:>8}", table.table, table.rows);
+                }
+            }
+        }
+        Commands::Branch {
+            action: BranchCommands::SetPassword { branch_name },
+        } => {
+            backend.set_branch_password(&branch_name).await?;
+            if json_output {
+                print_json(&serde_json::json!({ "status": "ok", "password_set": branch_name }))?;
+            } else {
+                println!("Updated the database password of branch: {}", branch_name);
+            }
+        }
+        Commands::Copy {
+            branch_name,
+            to_database,
+            name,
+        } => {
+            if to_database == resolved_name {
+                anyhow::bail!(
+                    "Source and target database are both '{}'; use 'create --from' to branch within a database",
+                    resolved_name
+                );
+            }
+            let target_branch = name.unwrap_or_else(|| branch_name.clone());
+            let target = backends::factory::resolve_backend(config, Some(&to_database))
+                .await?
+                .backend;
+            check_branch_name(target.as_ref(), &target_branch)?;
+
+            if !backend.branch_exists(&branch_name).await? {
+                return Err(coded!(
+                    BranchNotFound,
+                    "Branch '{}' not found in database '{}'",
+                    branch_name,
+                    resolved_name
+                ));
+            }
+
+            // Clone the data directory when both sides share a storage root,
+            // otherwise go through pg_dump/pg_restore
+            let mut method = "storage clone";
+            let cloned = match backend.branch_data_source(&branch_name).await? {
+                Some(source) => {
+                    target
+                        .create_branch_from_data(&target_branch, &source)
+                        .await?
+                }
+                None => None,
+            };
+            let mut info = match cloned {
+                Some(info) => info,
+                None => {
+                    method = "pg_dump/pg_restore";
+                    let source_url =
+                        remote_branch_url(config, &resolved_name, &branch_name).await?;
+                    target
+                        .create_branch_from_source(&target_branch, &source_url)
+                        .await?
+                }
+            };
+            info.parent_branch = Some(format!("{}:{}", resolved_name, branch_name));
+
+            if json_output {
+                print_json(&serde_json::json!({
+                    "source_database": resolved_name,
+                    "target_database": to_database,
+                    "branch": info,
+                    "method": method,
+                }))?;
+            } else {
+                println!(
+                    "Copied {}:{} to {}:{} ({})",
+                    resolved_name, branch_name, to_database, target_branch, method
+                );
+                if let Ok(conn) = target.get_connection_info(&target_branch).await {
+                    if let Some(ref uri) = conn.connection_string {
+                        println!("  Connection: {}", uri);
+                    }
+                }
+            }
+        }
+        Commands::Doctor => {
+            let report = backend.doctor().await?;
+            if json_output {
+                print_json(&report)?;
+            } else {
+                println!("Doctor report ({}):", backend.backend_name());
+                for check in &report.checks {
+                    let icon = if check.available { "OK" } else { "FAIL" };
+                    println!("  [{}] {}: {}", icon, check.name, check.detail);
+                }
+            }
+        }
+        Commands::Guard => {
+            let report = backend.prune_guard().await?;
+            if json_output {
+                print_json(&report)?;
+            } else {
+                println!("Prune guard ({}):", backend.backend_name());
+                for check in &report.checks {
+                    let icon = if check.available { "OK" } else { "FAIL" };
+                    println!("  [{}] {}: {}", icon, check.name, check.detail);
+                }
+            }
+        }
+        Commands::Top {
+            branch_name,
+            limit,
+            enable,
+            reset,
+        } => {
+            if enable {
+                backend.enable_query_stats(&branch_name).await?;
+                if !json_output {
+                    println!(
+                        "pg_stat_statements is enabled in branch '{}'; queries are counted from now on",
+                        branch_name
+                    );
+                }
+            }
+            let stats = backend.top_queries(&branch_name, limit, reset).await?;
+            if json_output {
+                print_json(&stats)?;
+            } else if stats.is_empty() {
+                println!("No queries recorded in branch '{}' yet", branch_name);
+            } else {
+                println!(
+                    "{:>10} {:>12} {:>10} {:>10}  {:<12} QUERY",
+                    "CALLS", "TOTAL MS", "MEAN MS", "ROWS", "DATABASE"
+                );
+                for stat in &stats {
+                    let mut query = stat.query.clone();
+                    if query.chars().count() > 80 {
+                        query = query.chars().take(77).collect::<String>() + "...";
+                    }
+                    println!(
+                        "{:>10} {:>12.1} {:>10.2} {:>10}  {:<12} {}",
+                        stat.calls, stat.total_ms, stat.mean_ms, stat.rows, stat.database, query
+                    );
+                }
+            }
+            if reset && !json_output {
+                println!("Reset the query statistics of branch '{}'", branch_name);
+            }
+        }
+        Commands::Diff {
+            branch_a,
+            branch_b,
+            data,
+            tables,
+            checksum,
+        } => {
+            if !data {
+                anyhow::bail!(
+                    "Only data diffs are available; run 'pgbranch diff --data {} {}'",
+                    branch_a,
+                    branch_b
+                );
+            }
+            let in_a = backend.table_data(&branch_a, &tables, checksum).await?;
+            let in_b = backend.table_data(&branch_b, &tables, checksum).await?;
+            let diffs = diff_table_data(&in_a, &in_b);
+            let diverged: Vec<_> = diffs.iter().filter(|d| d.status != "same").collect();
+
+            if json_output {
+                let tables: Vec<serde_json::Value> = diffs
+                    .iter()
+                    .map(|d| {
+                        let mut table = serde_json::json!({
+                            "table": d.table,
+                            "status": d.status,
+                            "a_rows": d.a.map(|t| t.rows),
+                            "b_rows": d.b.map(|t| t.rows),
+                        });
+                        if checksum {
+                            table["a_checksum"] = d.a.and_then(|t| t.checksum.clone()).into();
+                            table["b_checksum"] = d.b.and_then(|t| t.checksum.clone()).into();
+                        }
+                        table
+                    })
+                    .collect();
+                print_json(&serde_json::json!({
+                    "a": branch_a,
+                    "b": branch_b,
+                    "checksum": checksum,
+                    "diverged": diverged.len(),
+                    "tables": tables,
+                }))?;
+            } else if diffs.is_empty() {
+                println!("No tables to compare");
+            } else if diverged.is_empty() {
+                println!(
+                    "No differences in {} table(s) between '{}' and '{}'{}",
+                    diffs.len(),
+                    branch_a,
+                    branch_b,
+                    if checksum {
+                        " (rows and checksums)"
+                    } else {
+                        " (row counts)"
+                    }
+                );
+            } else {
+                println!(
+                    "{} of {} table(s) differ between '{}' and '{}':",
+                    diverged.len(),
+                    diffs.len(),
+                    branch_a,
+                    branch_b
+                );
+                let rows = |t: Option<&backends::TableData>| {
+                    t.map_or_else(|| "-".to_string(), |t| t.rows.to_string())
+                };
+                for d in &diverged {
+                    let marker = match d.status {
+                        "added" => '+',
+                        "removed" => '-',
+                        _ => '~',
+                    };
+                    let note = match (d.a, d.b) {
+                        (Some(a), Some(b)) if a.rows == b.rows => "  (same count, rows differ)",
+                        _ => "",
+                    };
+                    println!(
+                        "  {} {:<40} {:>10} -> {:<10}{}",
+                        marker,
+                        d.table,
+                        rows(d.a),
+                        rows(d.b),
+                        note
+                    );
+                }
+            }
+        }
+        Commands::Compact {
+            branch_name,
+            vacuum_full,
+        } => {
+            let report = backend.compact_branch(&branch_name, vacuum_full).await?;
+            if json_output {
+                print_json(&report)?;
+            } else {
+                println!("Compacted branch '{}':", branch_name);
+                for step in &report.steps {
+                    println!("  - {}", step);
+                }
+                println!(
+                    "Data: {} -> {}",
+                    backends::format_bytes(report.used_before_bytes),
+                    backends::format_bytes(report.used_after_bytes)
+                );
+                println!(
+                    "Reclaimed: {}",
+                    backends::format_bytes(report.reclaimed_bytes)
+                );
+            }
+        }
+        Commands::Exec {
+            branch_name,
+            sql,
+            file,
+            record,
+        } => {
+            let sql = match (sql, file) {
+                (Some(sql), None) => sql,
+                (None, Some(path)) => std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("Give the SQL either as an argument or with --file, not both")
+                }
+                (None, None) => anyhow::bail!("Give the SQL to run as an argument or with --file"),
+            };
+            let output = backend.exec_sql(&branch_name, &sql, record).await?;
+            let script = if record {
+                Some(backend.session_script(&branch_name).await?)
+            } else {
+                None
+            };
+            if json_output {
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "branch": branch_name,
+                    "output": output,
+                    "recorded_in": script,
+                }))?;
+            } else {
+                print!("{}", output);
+                if let Some(script) = script {
+                    eprintln!("Recorded in {}", script.display());
+                }
+            }
+        }
+        Commands::Replay { script, onto, from } => {
+            let is_file = std::path::Path::new(&script).is_file();
+            let path = if is_file {
+                PathBuf::from(&script)
+            } else if backend.branch_exists(&script).await? {
+                backend.session_script(&script).await?
+            } else {
+                anyhow::bail!("No script file or branch named '{}'", script);
+            };
+            let sql = std::fs::read_to_string(&path).with_context(|| {
+                if is_file {
+                    format!("Failed to read {}", path.display())
+                } else {
+                    format!(
+                        "Branch '{}' has no recorded session; record one with 'pgbranch exec {} --record'",
+                        script, script
+                    )
+                }
+            })?;
+
+            let created = !backend.branch_exists(&onto).await?;
+            if created {
+                check_branch_name(backend.as_ref(), &onto)?;
+                run_interruptible(
+                    backend.as_ref(),
+                    &onto,
+                    backend.create_branch(&onto, from.as_deref()),
+                )
+                .await?;
+                if !json_output {
+                    println!("Created database branch: {}", onto);
+                }
+            }
+            let output = backend.exec_sql(&onto, &sql, false).await?;
+            if json_output {
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "script": path,
+                    "branch": onto,
+                    "created": created,
+                    "output": output,
+                }))?;
+            } else {
+                print!("{}", output);
+                println!("Replayed {} onto branch '{}'", path.display(), onto);
+            }
+        }
+        Commands::Events => {
+            if !json_output {
+                eprintln!("Following branch events; press Ctrl-C to stop");
+            }
+            let mut failed = None;
+            backend
+                .watch_events(&mut |event| {
+                    if json_output {
+                        // One compact object per line
+                        match crate::output::versioned(&event) {
+                            Ok(line) => println!("{}", line),
+                            Err(e) => failed = Some(e),
+                        }
+                    } else {
+                        let time = event.time.with_timezone(&chrono::Local).format("%H:%M:%S");
+                        match &event.branch {
+                            Some(branch) => {
+                                println!("{} branch {}: {}", time, branch, event.message)
+                            }
+                            None => println!("{} {}", time, event.message),
+                        }
+                    }
+                })
+                .await?;
+            if let Some(e) = failed {
+                return Err(e.into());
+            }
+        }
+        Commands::Du => {
+            let project = backend.project_space().await?;
+            let branches = backend.branch_space().await?;
+            if json_output {
+                print_json(&serde_json::json!({
+                    "project": project,
+                    "branches": branches,
+                }))?;
+            } else {
+                let size = |bytes: Option<u64>| {
+                    bytes
+                        .map(backends::format_bytes)
+                        .unwrap_or_else(|| "-".to_string())
+                };
+                println!(
+                    "{:<30} {:>12} {:>12} {:>12}",
+                    "BRANCH", "USED", "QUOTA", "AVAILABLE"
+                );
+                for usage in &branches {
+                    println!(
+                        "{:<30} {:>12} {:>12} {:>12}",
+                        usage.branch.as_deref().unwrap_or("-"),
+                        backends::format_bytes(usage.used_bytes),
+                        size(usage.quota_bytes),
+                        size(usage.available_bytes)
+                    );
+                }
+                if let Some(usage) = &project {
+                    println!(
+                        "{:<30} {:>12} {:>12} {:>12}",
+                        "(project total)",
+                        backends::format_bytes(usage.used_bytes),
+                        size(usage.quota_bytes),
+                        size(usage.available_bytes)
+                    );
+                }
+            }
+        }
+        Commands::Connection {
+            branch_name,
+            format,
+        } => {
+            if config.behavior.auto_create_on_connect
+                && !backend.branch_exists(&branch_name).await?
+            {
+                // Keep stdout clean: callers usually capture the connection string
+                eprintln!(
+                    "Branch '{}' does not exist yet, creating it from the default parent...",
+                    branch_name
+                );
+                check_branch_name(backend.as_ref(), &branch_name)?;
+                backend.create_branch(&branch_name, None).await?;
+
+                if !config.post_commands.is_empty() {
+                    let mut executor =
+                        PostCommandExecutor::new(config, &branch_name, PostCommandEvent::Create)?;
+                    if let Ok(conn) = backend.get_connection_info(&branch_name).await {
+                        executor = executor.with_connection_info(&conn, backend.backend_type());
+                    }
+                    executor.execute_all_post_commands().await?;
+                }
+            }
+
+            let conn = backend.get_connection_info(&branch_name).await?;
+            let fmt = format
+                .as_deref()
+                .unwrap_or(if json_output { "json" } else { "uri" });
+            match fmt {
+                "uri" => println!("{}", connection_uri(&conn)),
+                "env" => {
+                    for line in connection_env_lines(&conn) {
+                        println!("{}", line);
+                    }
+                }
+                _ => {
+                    print_json(&conn)?;
+                }
+            }
+        }
+        Commands::Psql { branch_name, args } => {
+            let conn = backend.get_connection_info(&branch_name).await?;
+            let mut psql = std::process::Command::new("psql");
+            psql.arg(connection_uri(&conn)).args(&args);
+            if let Some(ref password) = conn.password {
+                psql.env("PGPASSWORD", password);
+            }
+            match exec_replacing(psql) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    // No client here; the branch's own container has one
+                    let command = backend
+                        .container_psql_command(&branch_name, &args)
+                        .await?
+                        .ok_or_else(|| {
+                            coded!(
+                                Unsupported,
+                                "psql is not installed; install the PostgreSQL client, or connect another way with 'pgbranch connection {}'",
+                                branch_name
+                            )
+                        })?;
+                    log::info!("psql is not installed, running it in the branch's container");
+                    let mut container = std::process::Command::new(&command.program);
+                    container.args(&command.args).envs(command.env);
+                    exec_replacing(container)
+                        .with_context(|| format!("Failed to run {}", command.program))?;
+                }
+                Err(e) => return Err(e).context("Failed to run psql"),
+            }
+        }
+        Commands::Proxy { action } => match action {
+            ProxyCommands::Start { port } => {
+                if !backend.supports_proxy() {
+                    return Err(coded!(
+                        Unsupported,
+                        "Backend '{}' can't be proxied: its branches aren't servers of their own",
+                        backend.backend_name()
+                    ));
+                }
+                crate::proxy::serve(
+                    backend.into(),
+                    config,
+                    config_path.as_deref(),
+                    port,
+                    json_output,
+                )
+                .await?;
+            }
+        },
+        Commands::Status => {
+            let branches = backend.list_branches().await.unwrap_or_default();
+            let running = branches
+                .iter()
+                .filter(|b| b.state.as_deref() == Some("running"))
+                .count();
+            let stopped = branches
+                .iter()
+                .filter(|b| b.state.as_deref() == Some("stopped"))
+                .count();
+            let project_info = backend.project_info();
+            let space = backend.project_space().await.unwrap_or_else(|e| {
+                log::warn!("Failed to read disk usage: {:#}", e);
+                None
+            });
+            let drift = backend
+                .recent_drift(chrono::Utc::now() - chrono::Duration::hours(DRIFT_WINDOW_HOURS))
+                .await
+                .unwrap_or_else(|e| {
+                    log::warn!("Failed to read drift: {:#}", e);
+                    Vec::new()
+                });
+
+            if json_output {
+                let mut status = serde_json::json!({
+                    "backend": backend.backend_name(),
+                    "total_branches": branches.len(),
+                    "running": running,
+                    "stopped": stopped,
+                    "supports_lifecycle": backend.supports_lifecycle(),
+                });
+                if let Some(ref info) = project_info {
+                    status["project"] = serde_json::Value::String(info.name.clone());
+                    if let Some(ref storage) = info.storage_backend {
+                        status["storage"] = serde_json::Value::String(storage.clone());
+                    }
+                    if let Some(ref image) = info.image {
+                        status["image"] = serde_json::Value::String(image.clone());
+                    }
+                }
+                if let Some(ref usage) = space {
+                    status["space"] = serde_json::to_value(usage)?;
+                }
+                status["drift"] = serde_json::to_value(&drift)?;
+                print_json(&status)?;
+            } else {
+                println!("Backend: {}", backend.backend_name());
+                if let Some(ref info) = project_info {
+                    println!("Project: {}", info.name);
+                    if let Some(ref storage) = info.storage_backend {
+                        println!("Storage: {}", storage);
+                    }
+                    if let Some(ref image) = info.image {
+                        println!("Image: {}", image);
+                    }
+                }
+                if let Some(ref usage) = space {
+                    println!("Space: {}", describe_space(usage));
+                }
+                println!(
+                    "Branches: {} total ({} running, {} stopped)",
+                    branches.len(),
+                    running,
+                    stopped
+                );
+                if backend.supports_lifecycle() {
+                    println!("Lifecycle: supported (start/stop/reset)");
+                }
+                print_drift(&drift, "");
+            }
+        }
+        Commands::Cleanup {
+            max_count, dry_run, ..
+        } => {
+            let max = max_count.unwrap_or(config.behavior.max_branches.unwrap_or(10));
+            let candidates = cleanup_candidates(config, backend.as_ref(), max).await?;
+            if dry_run {
+                let mut actions = Vec::new();
+                for branch_name in &candidates {
+                    actions.extend(backend.plan_delete_branch(branch_name).await?);
+                }
+                return print_plan(
+                    &format!("clean up branches beyond the newest {}", max),
+                    &actions,
+                    json_output,
+                );
+            }
+            let deleted = delete_branches(backend.as_ref(), candidates).await;
+            if !deleted.is_empty() {
+                audit::record(
+                    config,
+                    AuditEvent {
+                        action: AuditAction::BranchesCleanedUp,
+                        database: &resolved_name,
+                        backend: backend.backend_type(),
+                        branches: &deleted,
+                    },
+                )
+                .await;
+            }
+            if json_output {
+                print_json(&deleted)?;
+            } else if deleted.is_empty() {
+                println!("No branches to clean up");
+            } else {
+                println!(
+                    "Cleaned up {} branches: {}",
+                    deleted.len(),
+                    deleted.join(", ")
+                );
+            }
+        }
+        Commands::Prune { ttl, dry_run } => {
+            let ttl = match ttl {
+                Some(ttl) => ttl,
+                None => config.behavior.branch_ttl.clone().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "No branch TTL set; set 'branch_ttl' under 'behavior' in .pgbranch.yml, or pass --ttl <age>"
+                    )
+                })?,
+            };
+            let max_age = crate::config::parse_age(&ttl, &format!("TTL '{}'", ttl))?;
+            let expired =
+                expired_branches(config, backend.as_ref(), max_age, &[], config_path).await?;
+            if dry_run {
+                let mut actions = Vec::new();
+                for branch_name in &expired {
+                    actions.extend(backend.plan_delete_branch(branch_name).await?);
+                }
+                return print_plan(
+                    &format!("prune branches unused for {}", ttl),
+                    &actions,
+                    json_output,
+                );
+            }
+            let pruned = delete_branches(backend.as_ref(), expired).await;
+            if !pruned.is_empty() {
+                audit::record(
+                    config,
+                    AuditEvent {
+                        action: AuditAction::BranchesPruned,
+                        database: &resolved_name,
+                        backend: backend.backend_type(),
+                        branches: &pruned,
+                    },
+                )
+                .await;
+            }
+            if json_output {
+                print_json(&serde_json::json!({ "status": "ok", "pruned": pruned }))?;
+            } else if pruned.is_empty() {
+                println!("No branches unused for {}", ttl);
+            } else {
+                println!(
+                    "Pruned {} branch(es) unused for {}: {}",
+                    pruned.len(),
+                    ttl,
+                    pruned.join(", ")
+                );
+            }
+        }
+        Commands::Plan { file, out } => {
+            let desired = load_declared_branches(file, config, config_path)?;
+            let plan = Plan::compute(&resolved_name, backend.as_ref(), &desired).await?;
+            if let Some(ref path) = out {
+                plan.save(path)?;
+            }
+            if json_output {
+                print_json(&plan)?;
+            } else {
+                print_branch_plan(&plan);
+                if let Some(ref path) = out {
+                    if !plan.changes.is_empty() {
+                        println!();
+                        println!(
+                            "Saved plan to {}. Run 'pgbranch apply {}' to carry it out.",
+                            path.display(),
+                            path.display()
+                        );
+                    }
+                }
+            }
+        }
+        Commands::Apply {
+            plan: plan_path,
+            file,
+            yes,
+        } => {
+            let plan = match plan_path {
+                Some(path) => {
+                    let saved = Plan::from_file(&path)?;
+                    if saved.database != resolved_name {
+                        anyhow::bail!(
+                            "Plan {} was made for database '{}', not '{}'",
+                            path.display(),
+                            saved.database,
+                            resolved_name
+                        );
+                    }
+                    let current =
+                        Plan::compute(&resolved_name, backend.as_ref(), &saved.desired).await?;
+                    if !saved.same_changes(&current) {
+                        anyhow::bail!(
+                            "Plan {} is out of date: the branches changed since it was made. Run 'pgbranch plan' again.",
+                            path.display()
+                        );
+                    }
+                    saved
+                }
+                None => {
+                    let desired = load_declared_branches(file, config, config_path)?;
+                    Plan::compute(&resolved_name, backend.as_ref(), &desired).await?
+                }
+            };
+
+            if plan.changes.is_empty() {
+                if json_output {
+                    print_json(&serde_json::json!({ "status": "ok", "applied": [] }))?;
+                } else {
+                    println!(
+                        "Branches of '{}' already match. Nothing to do.",
+                        resolved_name
+                    );
+                }
+                return Ok(());
+            }
+
+            let to_delete = plan.deleted_branches();
+            if !to_delete.is_empty() && !yes && !non_interactive {
+                print_branch_plan(&plan);
+                println!();
+                let confirm = inquire::Confirm::new(&format!(
+                    "Apply these changes? {} branch(es) will be deleted.",
+                    to_delete.len()
+                ))
+                .with_default(false)
+                .prompt()?;
+                if !confirm {
+                    println!("Aborted.");
+                    return Ok(());
+                }
+            }
+
+            let mut applied = Vec::new();
+            let result = plan
+                .apply(backend.as_ref(), |change| {
+                    if !json_output {
+                        match change.kind {
+                            ChangeKind::Create => println!("Created branch: {}", change.branch),
+                            ChangeKind::Seed => println!("Seeded branch: {}", change.branch),
+                            ChangeKind::SetReadOnly => println!(
+                                "Made branch {}: {}",
+                                if change.read_only == Some(true) {
+                                    "read-only"
+                                } else {
+                                    "writable"
+                                },
+                                change.branch
+                            ),
+                            ChangeKind::SetLimits => {
+                                println!("Updated resource limits of branch: {}", change.branch)
+                            }
+                            ChangeKind::Delete => println!("Deleted branch: {}", change.branch),
+                        }
+                    }
+                    applied.push(change.clone());
+                })
+                .await;
+
+            let deleted: Vec<String> = applied
+                .iter()
+                .filter(|c| c.kind == ChangeKind::Delete)
+                .map(|c| c.branch.clone())
+                .collect();
+            if !deleted.is_empty() {
+                audit::record(
+                    config,
+                    AuditEvent {
+                        action: AuditAction::BranchesCleanedUp,
+                        database: &resolved_name,
+                        backend: backend.backend_type(),
+                        branches: &deleted,
+                    },
+                )
+                .await;
+            }
+            result?;
+
+            if json_output {
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "applied": applied,
+                }))?;
+            } else {
+                println!("Applied {} change(s) to '{}'", applied.len(), resolved_name);
+            }
+        }
+        Commands::Destroy {
+            force,
+            preview,
+            containers_only,
+            keep_project,
+        } => {
+            if !backend.supports_destroy() {
+                return Err(coded!(
+                    Unsupported,
+                    "Backend '{}' does not support destroy. This command is only available for the local (Docker + CoW) backend.",
+                    backend.backend_name()
+                ));
+            }
+            let scope = if containers_only {
+                backends::DestroyScope::Containers
+            } else if keep_project {
+                backends::DestroyScope::Branches
+            } else {
+                backends::DestroyScope::All
+            };
+
+            let (project_name, branch_names) = match backend.destroy_preview().await? {
+                Some(p) => p,
+                None => {
+                    if json_output {
+                        print_json(
+                            &serde_json::json!({ "status": "ok", "message": "no project found" }),
+                        )?;
+                    } else {
+                        println!(
+                            "No project found for database '{}'. Nothing to destroy.",
+                            resolved_name
+                        );
+                    }
+                    return Ok(());
+                }
+            };
+
+            if preview {
+                let scope_name = match scope {
+                    backends::DestroyScope::All => "all",
+                    backends::DestroyScope::Containers => "containers",
+                    backends::DestroyScope::Branches => "branches",
+                };
+                if json_output {
+                    print_json(&serde_json::json!({
+                        "project": project_name,
+                        "scope": scope_name,
+                        "branches": branch_names,
+                        "removes_data": scope != backends::DestroyScope::Containers,
+                        "removes_project": scope == backends::DestroyScope::All,
+                    }))?;
+                } else {
+                    println!(
+                        "Destroy ({}) would affect project '{}'",
+                        scope_name, project_name
+                    );
+                    for name in &branch_names {
+                        println!("  - {}", name);
+                    }
+                }
+                return Ok(());
+            }
+
+            if !force && non_interactive {
+                return Err(coded!(
+                    ConfirmationRequired,
+                    "destroy needs --force to run with --non-interactive; check what it would remove with 'pgbranch destroy --preview'"
+                ));
+            }
+            if !force {
+                println!(
+                    "This will {} the following:",
+                    if scope == backends::DestroyScope::Containers {
+                        "remove the containers of"
+                    } else {
+                        "permanently destroy"
+                    }
+                );
+                println!("  Project: {}", project_name);
+                if branch_names.is_empty() {
+                    println!("  Branches: (none)");
+                } else {
+                    println!("  Branches ({}):", branch_names.len());
+                    for name in &branch_names {
+                        println!("    - {}", name);
+                    }
+                }
+                println!();
+                println!(
+                    "{}",
+                    match scope {
+                        backends::DestroyScope::All =>
+                            "All containers, storage data, and state will be removed.",
+                        backends::DestroyScope::Containers =>
+                            "The branches' data and state are kept; 'pgbranch start <branch>' recreates a container.",
+                        backends::DestroyScope::Branches =>
+                            "All containers and storage data will be removed; the project and its settings are kept.",
+                    }
+                );
+
+                let confirmed = if scope == backends::DestroyScope::Containers {
+                    // Nothing is lost, so a yes/no is enough
+                    inquire::Confirm::new("Remove these containers?")
+                        .with_default(false)
+                        .prompt()?
+                } else {
+                    let typed = inquire::Text::new(&format!(
+                        "Type the project name ({}) to confirm:",
+                        project_name
+                    ))
+                    .prompt()?;
+                    typed.trim() == project_name
+                };
+
+                if !confirmed {
+                    println!("Aborted.");
+                    return Ok(());
+                }
+            }
+
+            // Containers-only keeps the records, so there's nothing to adopt later
+            let manifest = if scope == backends::DestroyScope::Containers {
+                None
+            } else {
+                backend.write_destroy_manifest().await?
+            };
+            let destroyed = backend.destroy_project(scope).await?;
+            let action = match scope {
+                backends::DestroyScope::All => Some(AuditAction::ProjectDestroyed),
+                backends::DestroyScope::Branches => Some(AuditAction::BranchesCleanedUp),
+                backends::DestroyScope::Containers => None,
+            };
+            if let Some(action) = action {
+                audit::record(
+                    config,
+                    AuditEvent {
+                        action,
+                        database: &resolved_name,
+                        backend: backend.backend_type(),
+                        branches: &destroyed,
+                    },
+                )
+                .await;
+            }
+
+            if scope == backends::DestroyScope::All {
+                // Remove the backend entry from local state
+                if let Some(ref path) = config_path {
+                    if let Ok(mut state) = LocalStateManager::new() {
+                        let _ = state.remove_backend(path, &resolved_name);
+                    }
+                }
+
+                // Also remove from committed config for backward compat (legacy configs)
+                config.remove_backend(&resolved_name);
+                if let Some(path) = config_path {
+                    config.save_to_file(path)?;
+                }
+            }
+
+            if json_output {
+                let key = match scope {
+                    backends::DestroyScope::All => "destroyed_branches",
+                    backends::DestroyScope::Containers => "stopped_branches",
+                    backends::DestroyScope::Branches => "deleted_branches",
+                };
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "project": project_name,
+                    key: destroyed,
+                    "manifest": manifest,
+                }))?;
+            } else {
+                match scope {
+                    backends::DestroyScope::All => println!(
+                        "Destroyed project '{}' and {} branch(es)",
+                        project_name,
+                        destroyed.len()
+                    ),
+                    backends::DestroyScope::Containers => println!(
+                        "Removed the containers of {} branch(es) in project '{}'; their data is kept",
+                        destroyed.len(),
+                        project_name
+                    ),
+                    backends::DestroyScope::Branches => println!(
+                        "Deleted {} branch(es) of project '{}'; the project is kept",
+                        destroyed.len(),
+                        project_name
+                    ),
+                }
+                for name in &destroyed {
+                    println!("  - {}", name);
+                }
+                if let Some(path) = &manifest {
+                    println!(
+                        "Saved the branch records to {}; if the data is kept or restored, 'pgbranch adopt --from-manifest {}' records them again",
+                        path.display(),
+                        path.display()
+                    );
+                }
+            }
+        }
+        Commands::Adopt {
+            from_manifest: None,
+            ..
+        } => {
+            let report = backend.adopt_pruned().await?;
+            if json_output {
+                print_json(&report)?;
+            } else {
+                if report.adopted.is_empty() && report.skipped.is_empty() {
+                    println!("No running branch lost its container");
+                }
+                if !report.adopted.is_empty() {
+                    println!(
+                        "Restarted {} branch(es) whose containers were removed:",
+                        report.adopted.len()
+                    );
+                    for name in &report.adopted {
+                        println!("  - {}", name);
+                    }
+                }
+                if !report.skipped.is_empty() {
+                    println!("Couldn't restart {} branch(es):", report.skipped.len());
+                    for skipped in &report.skipped {
+                        println!("  - {}: {}", skipped.name, skipped.reason);
+                    }
+                }
+            }
+        }
+        Commands::Adopt {
+            from_manifest: Some(from_manifest),
+            ..
+        } => {
+            let report = backend.adopt_manifest(&from_manifest).await?;
+            if json_output {
+                print_json(&report)?;
+            } else {
+                println!(
+                    "Adopted {} branch(es) into project '{}'",
+                    report.adopted.len(),
+                    report.project
+                );
+                for name in &report.adopted {
+                    println!("  - {}", name);
+                }
+                if !report.skipped.is_empty() {
+                    println!("Skipped {} branch(es):", report.skipped.len());
+                    for skipped in &report.skipped {
+                        println!("  - {}: {}", skipped.name, skipped.reason);
+                    }
+                }
+                if !report.adopted.is_empty() {
+                    println!("They are stopped; 'pgbranch start <branch>' recreates a container.");
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Handle aggregation commands (List, Status, Doctor) across all backends.
+async fn handle_multi_backend_command(
+    cmd: Commands,
+    config: &Config,
+    json_output: bool,
+) -> Result<()> {
+    let all_backends = backends::factory::create_all_backends(config).await?;
+
+    match cmd {
+        Commands::List { verbose } => {
+            if json_output {
+                let mut map = serde_json::Map::new();
+                for named in &all_backends {
+                    let branches = named.backend.list_branches().await.unwrap_or_default();
+                    map.insert(named.name.clone(), serde_json::to_value(&branches)?);
+                }
+                print_json(&map)?;
+            } else {
+                for named in &all_backends {
+                    let branches = named.backend.list_branches().await.unwrap_or_default();
+                    println!("[{}] ({}):", named.name, named.backend.backend_name());
+                    print_branch_tree(&branches, "  ", verbose);
+                    println!();
+                }
+            }
+        }
+        Commands::Status => {
+            if json_output {
+                let mut map = serde_json::Map::new();
+                for named in &all_backends {
+                    let branches = named.backend.list_branches().await.unwrap_or_default();
+                    let running = branches
+                        .iter()
+                        .filter(|b| b.state.as_deref() == Some("running"))
+                        .count();
+                    let stopped = branches
+                        .iter()
+                        .filter(|b| b.state.as_deref() == Some("stopped"))
+                        .count();
+                    let project_info = named.backend.project_info();
+                    let space = named.backend.project_space().await.unwrap_or_else(|e| {
+                        log::warn!("Failed to read disk usage of '{}': {:#}", named.name, e);
+                        None
+                    });
+                    let drift = named
+                        .backend
+                        .recent_drift(
+                            chrono::Utc::now() - chrono::Duration::hours(DRIFT_WINDOW_HOURS),
+                        )
+                        .await
+                        .unwrap_or_else(|e| {
+                            log::warn!("Failed to read drift of '{}': {:#}", named.name, e);
+                            Vec::new()
+                        });
+
+                    let mut status = serde_json::json!({
+                        "backend": named.backend.backend_name(),
+                        "total_branches": branches.len(),
+                        "running": running,
+                        "stopped": stopped,
+                        "supports_lifecycle": named.backend.supports_lifecycle(),
+                    });
+                    if let Some(ref info) = project_info {
+                        status["project"] = serde_json::Value::String(info.name.clone());
+                        if let Some(ref storage) = info.storage_backend {
+                            status["storage"] = serde_json::Value::String(storage.clone());
+                        }
+                        if let Some(ref image) = info.image {
+                            status["image"] = serde_json::Value::String(image.clone());
+                        }
+                    }
+                    if let Some(ref usage) = space {
+                        status["space"] = serde_json::to_value(usage)?;
+                    }
+                    status["drift"] = serde_json::to_value(&drift)?;
+                    map.insert(named.name.clone(), status);
+                }
+                print_json(&map)?;
+            } else {
+                for named in &all_backends {
+                    let branches = named.backend.list_branches().await.unwrap_or_default();
+                    let running = branches
+                        .iter()
+                        .filter(|b| b.state.as_deref() == Some("running"))
+                        .count();
+                    let stopped = branches
+                        .iter()
+                        .filter(|b| b.state.as_deref() == Some("stopped"))
+                        .count();
+                    let project_info = named.backend.project_info();
+                    let space = named.backend.project_space().await.unwrap_or_else(|e| {
+                        log::warn!("Failed to read disk usage of '{}': {:#}", named.name, e);
+                        None
+                    });
+                    let drift = named
+                        .backend
+                        .recent_drift(
+                            chrono::Utc::now() - chrono::Duration::hours(DRIFT_WINDOW_HOURS),
+                        )
+                        .await
+                        .unwrap_or_else(|e| {
+                            log::warn!("Failed to read drift of '{}': {:#}", named.name, e);
+                            Vec::new()
+                        });
+
+                    println!("[{}] ({}):", named.name, named.backend.backend_name());
+                    if let Some(ref info) = project_info {
+                        println!("  Project: {}", info.name);
+                        if let Some(ref storage) = info.storage_backend {
+                            println!("  Storage: {}", storage);
+                        }
+                        if let Some(ref image) = info.image {
+                            println!("  Image: {}", image);
+                        }
+                    }
+                    if let Some(ref usage) = space {
+                        println!("  Space: {}", describe_space(usage));
+                    }
+                    println!(
+                        "  Branches: {} total ({} running, {} stopped)",
+                        branches.len(),
+                        running,
+                        stopped
+                    );
+                    if named.backend.supports_lifecycle() {
+                        println!("  Lifecycle: supported (start/stop/reset)");
+                    }
+                    print_drift(&drift, "  ");
+                    println!();
+                }
+            }
+        }
+        Commands::Doctor => {
+            if json_output {
+                let mut map = serde_json::Map::new();
+                for named in &all_backends {
+                    let report = named.backend.doctor().await?;
+                    map.insert(named.name.clone(), serde_json::to_value(&report)?);
+                }
+                print_json(&map)?;
+            } else {
+                for named in &all_backends {
+                    let report = named.backend.doctor().await?;
+                    println!(
+                        "[{}] Doctor report ({}):",
+                        named.name,
+                        named.backend.backend_name()
+                    );
+                    for check in &report.checks {
+                        let icon = if check.available { "OK" } else { "FAIL" };
+                        println!("  [{}] {}: {}", icon, check.name, check.detail);
+                    }
+                    println!();
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// `doctor`'s Git checks: a repository, and pgbranch's hooks in it.
+fn check_git_setup() {
+    // Git repository
+    match GitRepository::new(".") {
+        Ok(_) => println!("  [OK] Git repository: detected"),
+        Err(_) => println!("  [FAIL] Git repository: not found"),
+    }
+
+    // Git hooks
+    let hooks_dir = std::path::Path::new(".git/hooks");
+    let has_hooks = if hooks_dir.exists() {
+        let post_checkout = hooks_dir.join("post-checkout");
+        let post_merge = hooks_dir.join("post-merge");
+        if let Ok(git_repo) = GitRepository::new(".") {
+            (post_checkout.exists() && git_repo.is_pgbranch_hook(&post_checkout).unwrap_or(false))
+                || (post_merge.exists() && git_repo.is_pgbranch_hook(&post_merge).unwrap_or(false))
+        } else {
+            post_checkout.exists() || post_merge.exists()
+        }
+    } else {
+        false
+    };
+    if has_hooks {
+        println!("  [OK] Git hooks: installed");
+    } else {
+        println!("  [WARN] Git hooks: not installed (run 'pgbranch install-hooks')");
+    }
+}
+
+/// Run configuration and environment checks as part of `doctor`.
+fn run_doctor_pre_checks(
+    config: &Config,
+    config_path: &Option<std::path::PathBuf>,
+    available_update: Option<&str>,
+) {
+    println!("General:");
+
+    if let Some(version) = available_update {
+        println!(
+            "  [WARN] pgbranch {} is available (running {}; run 'pgbranch upgrade')",
+            version,
+            crate::upgrade::CURRENT_VERSION
+        );
+    }
+
+    // Config file
+    match config_path {
+        Some(path) => println!("  [OK] Config file: {}", path.display()),
+        None => println!("  [WARN] Config file: not found (using defaults)"),
+    }
+
+    if config.standalone {
+        println!("  [OK] Git integration: off (standalone project)");
+    } else {
+        check_git_setup();
+    }
+
+    // Backend types compiled into this build
+    let (compiled, missing): (Vec<_>, Vec<_>) = backends::factory::BACKENDS
+        .iter()
+        .partition(|info| info.is_compiled());
+    let names = |infos: &[&backends::factory::BackendInfo]| {
+        infos
+            .iter()
+            .map(|info| info.name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if missing.is_empty() {
+        println!("  [OK] Backends: {}", names(&compiled));
+    } else {
+        println!(
+            "  [OK] Backends: {} (not in this build: {})",
+            names(&compiled),
+            names(&missing)
+        );
+    }
+    // Configured backends this build can't create
+    for named in config.resolve_backends() {
+        if let Some(info) = backends::factory::BackendInfo::find(&named.backend_type)
+            .filter(|info| !info.is_compiled())
+        {
+            println!(
+                "  [FAIL] Backend '{}': {}",
+                named.name,
+                info.not_compiled_error()
+            );
+        }
+    }
+
+    // Branch filter regex, and the per-database ones
+    let mut regexes: Vec<(String, String)> = config
+        .git
+        .branch_filter_regex
+        .iter()
+        .map(|regex| ("Branch filter regex".to_string(), regex.clone()))
+        .collect();
+    for named in config.resolve_backends() {
+        if let Some(regex) = named.branch_filter.and_then(|f| f.branch_filter_regex) {
+            regexes.push((format!("Branch filter regex ({})", named.name), regex));
+        }
+    }
+    for (label, regex_pattern) in regexes {
+        match regex::Regex::new(&regex_pattern) {
+            Ok(_) => println!("  [OK] {}: valid", label),
+            Err(e) => println!("  [FAIL] {}: {}", label, e),
+        }
+    }
+
+    println!();
+}
+
+fn copy_worktree_files(config: &Config, main_worktree_dir: &str, refresh: bool) -> Result<()> {
+    let copy_files = match config.worktree {
+        Some(ref wt) => &wt.copy_files,
+        None => return Ok(()),
+    };
+
+    let main_dir = std::path::Path::new(main_worktree_dir);
+    let current_dir = std::env::current_dir()?;
+    let mut template_context = None;
+
+    for entry in copy_files {
+        let file = entry.path();
+        let source = main_dir.join(file);
+        let target = current_dir.join(file);
+
+        if !source.exists() {
+            log::debug!("Skipping {}: not found in main worktree", file);
+            continue;
+        }
+        // Never clobber a real file unless refreshing; an existing symlink is always re-checked
+        let target_is_symlink = target.symlink_metadata().is_ok_and(|m| m.is_symlink());
+        if target.exists() && !target_is_symlink && !refresh {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        match entry.strategy() {
+            WorktreeFileStrategy::Copy => {
+                let content = std::fs::read(&source)
+                    .with_context(|| format!("Failed to read {}", source.display()))?;
+                if write_worktree_file(&target, &content, target_is_symlink)? {
+                    println!("Copied {} from main worktree", file);
+                }
+            }
+            WorktreeFileStrategy::Template => {
+                if template_context.is_none() {
+                    let branch = GitRepository::new(".")?
+                        .get_current_branch()?
+                        .unwrap_or_else(|| config.git.main_branch.clone());
+                    template_context = Some(TemplateContext::new(config, &branch));
+                }
+                let content = std::fs::read_to_string(&source)
+                    .with_context(|| format!("Failed to read {}", source.display()))?;
+                let rendered = config.substitute_template_variables(
+                    &content,
+                    template_context
+                        .as_ref()
+                        .expect("template context initialized"),
+                );
+                if write_worktree_file(&target, rendered.as_bytes(), target_is_symlink)? {
+                    println!("Rendered {} from main worktree", file);
+                }
+            }
+            WorktreeFileStrategy::Symlink => {
+                if target_is_symlink && std::fs::read_link(&target)? == source {
+                    continue;
+                }
+                if target_is_symlink || target.exists() {
+                    std::fs::remove_file(&target)
+                        .with_context(|| format!("Failed to remove {}", target.display()))?;
+                }
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&source, &target)?;
+                #[cfg(windows)]
+                std::os::windows::fs::symlink_file(&source, &target)?;
+                println!("Linked {} to main worktree", file);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `content` to a worktree file, returning whether anything changed.
+fn write_worktree_file(
+    target: &std::path::Path,
+    content: &[u8],
+    replace_symlink: bool,
+) -> Result<bool> {
+    if replace_symlink {
+        std::fs::remove_file(target)
+            .with_context(|| format!("Failed to remove {}", target.display()))?;
+    } else if std::fs::read(target).is_ok_and(|existing| existing == content) {
+        return Ok(false);
+    }
+    std::fs::write(target, content)
+        .with_context(|| format!("Failed to write {}", target.display()))?;
+    Ok(true)
+}
+
+async fn handle_snapshot_command(
+    backend: &dyn backends::DatabaseBranchingBackend,
+    action: SnapshotCommands,
+    json_output: bool,
+) -> Result<()> {
+    match action {
+        SnapshotCommands::Create {
+            branch_name,
+            name,
+            replace,
+        } => {
+            backend
+                .create_snapshot(&branch_name, &name, replace)
+                .await?;
+            if json_output {
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "branch": branch_name,
+                    "snapshot": name,
+                }))?;
+            } else {
+                println!("Created snapshot {} of branch {}", name, branch_name);
+            }
+        }
+        SnapshotCommands::List { branch_name } => {
+            let snapshots = backend.list_snapshots(branch_name.as_deref()).await?;
+            if json_output {
+                print_json(&snapshots)?;
+            } else if snapshots.is_empty() {
+                println!("No snapshots");
+            } else {
+                for snapshot in &snapshots {
+                    println!(
+                        "{}@{}  {}",
+                        snapshot.branch,
+                        snapshot.name,
+                        snapshot
+                            .created_at
+                            .map(|t| t
+                                .with_timezone(&chrono::Local)
+                                .format("%Y-%m-%d %H:%M")
+                                .to_string())
+                            .unwrap_or_else(|| "-".to_string())
+                    );
+                }
+            }
+        }
+        // The same rollback as `reset --to-snapshot`
+        SnapshotCommands::Restore {
+            branch_name,
+            name,
+            keep_snapshots,
+        } => {
+            let started = std::time::Instant::now();
+            run_interruptible(
+                backend,
+                &branch_name,
+                backend.reset_branch(&branch_name, Some(&name), keep_snapshots),
+            )
+            .await?;
+            let elapsed = started.elapsed();
+            if json_output {
+                print_json(&serde_json::json!({
+                    "status": "ok",
+                    "branch": branch_name,
+                    "snapshot": name,
+                    "duration_ms": elapsed.as_millis() as u64,
+                }))?;
+            } else {
+                println!(
+                    "Restored branch {} to snapshot: {} ({})",
+                    branch_name,
+                    name,
+                    format_elapsed(elapsed)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_auth_command(
+    action: AuthCommands,
+    config: &Config,
+    config_path: &Option<std::path::PathBuf>,
+    non_interactive: bool,
+    json_output: bool,
+) -> Result<()> {
+    use crate::credentials;
+
+    match action {
+        AuthCommands::Login {
+            provider,
+            file,
+            no_validate,
+        } => {
+            let provider = provider.to_lowercase();
+            credentials::validate_provider(&provider)?;
+            let secret = read_secret(&provider, non_interactive)?;
+
+            // An age identity has no backend to validate against
+            if !no_validate && provider != "age" {
+                // Validate against a configured backend of this type, if there is one
+                let mut config = config.clone();
+                crate::project::use_state_backends(&mut config, config_path.as_deref());
+                let named = config.resolve_backends().into_iter().find(|b| {
+                    backends::factory::BackendInfo::canonical_name(&b.backend_type) == provider
+                });
+                match named {
+                    Some(mut named) => {
+                        if let Some(ref mut neon) = named.neon {
+                            neon.api_key = secret.clone();
+                        }
+                        if let Some(ref mut xata) = named.xata {
+                            xata.api_key = secret.clone();
+                        }
+                        if let Some(ref mut dblab) = named.dblab {
+                            dblab.auth_token = secret.clone();
+                        }
+                        let backend =
+                            backends::factory::create_backend_from_named_config(&config, &named)
+                                .await?;
+                        backend.test_connection().await.with_context(|| {
+                            format!("Failed to validate {} credentials", provider)
+                        })?;
+                        if !json_output {
+                            println!("Validated credentials against '{}'", named.name);
+                        }
+                    }
+                    None => {
+                        if !json_output {
+                            println!(
+                                "No {} backend configured in this project; skipping validation",
+                                provider
+                            );
+                        }
+                    }
+                }
+            }
+
+            let source = credentials::store(&provider, &secret, file)?;
+            if json_output {
+                print_json(
+                    &serde_json::json!({"status": "ok", "provider": provider, "stored_in": source.describe()}),
+                )?;
+            } else {
+                println!("Stored {} credentials in {}", provider, source.describe());
+                if provider == "age" {
+                    println!("It decrypts .pgbranch.local.yml.enc automatically");
+                } else {
+                    println!(
+                        "Backends read it automatically when the key is omitted from .pgbranch.yml"
+                    );
+                }
+            }
+        }
+        AuthCommands::Logout { provider } => {
+            let provider = provider.to_lowercase();
+            credentials::validate_provider(&provider)?;
+            let removed = credentials::remove(&provider)?;
+            if json_output {
+                print_json(
+                    &serde_json::json!({"status": "ok", "provider": provider, "removed": removed}),
+                )?;
+            } else if removed {
+                println!("Removed stored {} credentials", provider);
+            } else {
+                println!("No stored {} credentials", provider);
+            }
+        }
+        AuthCommands::Status => {
+            let mut entries = Vec::new();
+            for provider in credentials::PROVIDERS {
+                let source = credentials::get(provider)?.map(|(_, source)| source.describe());
+                entries.push((provider, source));
+            }
+            if json_output {
+                let map: serde_json::Map<String, serde_json::Value> = entries
+                    .into_iter()
+                    .map(|(provider, source)| (provider.to_string(), serde_json::json!(source)))
+                    .collect();
+                print_json(&map)?;
+            } else {
+                for (provider, source) in entries {
+                    match source {
+                        Some(source) => println!("{:<8} stored in {}", provider, source),
+                        None => println!("{:<8} not configured", provider),
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse `--pgconf key=value` flags. An empty value is kept: it removes the setting.
+fn parse_pg_settings(flags: &[String]) -> Result<Vec<(String, String)>> {
+    flags
+        .iter()
+        .map(|flag| {
+            let (key, value) = flag
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("--pgconf '{}' is not KEY=VALUE", flag))?;
+            let key = key.trim();
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            {
+                anyhow::bail!("--pgconf '{}' has an invalid setting name", flag);
+            }
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Run the backend's post-clone/post-seed maintenance. A failure only warns:
+/// the branch is usable, just with stale statistics.
+async fn run_maintenance(
+    backend: &dyn backends::DatabaseBranchingBackend,
+    branch_name: &str,
+) -> Vec<String> {
+    match backend.run_maintenance(branch_name).await {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            log::warn!("Maintenance of branch '{}' failed: {:#}", branch_name, e);
+            vec![]
+        }
+    }
+}
+
+fn plan_maintenance(
+    backend: &dyn backends::DatabaseBranchingBackend,
+) -> Option<backends::PlannedAction> {
+    let tasks = backend.maintenance_tasks();
+    (!tasks.is_empty()).then(|| {
+        backends::PlannedAction::new(
+            "postgres",
+            format!("Run {} in each database", tasks.join(", ").to_uppercase()),
+        )
+    })
+}
+
+fn read_secret(provider: &str, non_interactive: bool) -> Result<String> {
+    use std::io::IsTerminal;
+
+    let secret = if non_interactive || !std::io::stdin().is_terminal() {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line
+    } else {
+        let label = match provider {
+            "dblab" => "DBLab token",
+            "age" => "identity (AGE-SECRET-KEY-...)",
+            _ => "API key",
+        };
+        rpassword::prompt_password(format!("{} {}: ", provider, label))?
+    };
+
+    let secret = secret.trim().to_string();
+    if secret.is_empty() {
+        anyhow::bail!("No credentials provided");
+    }
+    Ok(secret)
+}
+
+async fn handle_project_command(
+    action: ProjectCommands,
+    merged_config: &Config,
+    config_path: &Option<PathBuf>,
+    json_output: bool,
+) -> Result<()> {
+    use crate::manifest::ProjectManifest;
+
+    match action {
+        ProjectCommands::Export { output, seed_from } => {
+            let Some(config_path) = config_path else {
+                return Err(coded!(
+                    ConfigNotFound,
+                    "No configuration file found. Run 'pgbranch init' first."
+                ));
+            };
+            // The committed file, not the merged view with personal overrides
+            let config = Config::from_file(config_path)?;
+            let backends = LocalStateManager::new()?
+                .get_backends(config_path)
+                .unwrap_or_else(|| config.resolve_backends());
+            let install_hooks = GitRepository::new(".")
+                .map(|repo| repo.hooks_installed())
+                .unwrap_or(false);
+
+            let manifest =
+                ProjectManifest::new(&config, backends, seed_from.as_deref(), install_hooks);
+            let content = if json_output {
+                serde_json::to_string_pretty(&manifest)? + "\n"
+            } else {
+                manifest.to_yaml()?
+            };
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, content)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    eprintln!("Wrote project manifest to {}", path.display());
+                    if let Some(signature) = sign_export(merged_config, &path)? {
+                        eprintln!("Signed it: {}", signature.display());
+                    }
+                }
+                None => print!("{}", content),
+            }
+        }
+        ProjectCommands::Import {
+            file,
+            force,
+            no_provision,
+            seed_from,
+        } => {
+            let verification = crate::signing::verify(merged_config.signing.as_ref(), &file)?;
+            if let crate::signing::Verification::Verified { ref signer } = verification {
+                if !json_output {
+                    println!("Verified the signature by {}", signer);
+                }
+            }
+            let manifest = ProjectManifest::from_file(&file)?;
+            let config_path = std::env::current_dir()?.join(".pgbranch.yml");
+
+            let mut state = LocalStateManager::new()?;
+            if !manifest.backends.is_empty()
+                && config_path.exists()
+                && state.get_backends(&config_path).is_some()
+                && !force
+            {
+                anyhow::bail!(
+                    "Databases are already configured for this project. Use --force to replace them."
+                );
+            }
+
+            let wrote_config = force || !config_path.exists();
+            if wrote_config {
+                manifest.config.save_to_file(&config_path)?;
+            }
+            let config = Config::from_file(&config_path)?;
+
+            if !manifest.backends.is_empty() {
+                state.set_backends(&config_path, manifest.backends.clone())?;
+            }
+
+            if manifest.install_hooks && !config.standalone {
+                GitRepository::new(".")?.install_hooks()?;
+            }
+
+            let seed_from = seed_from.or(manifest.seed_from.clone());
+            let mut provisioned = Vec::new();
+            if !no_provision {
+                let mut config_with_backends = config.clone();
+                if !manifest.backends.is_empty() {
+                    config_with_backends.backends = Some(manifest.backends.clone());
+                    config_with_backends.backend = None;
+                }
+                for backend in manifest
+                    .backends
+                    .iter()
+                    .filter(|b| backends::factory::BackendInfo::is_local(&b.backend_type))
+                {
+                    if !json_output {
+                        println!("Provisioning local database '{}'", backend.name);
+                    }
+                    init_local_backend_main(&config_with_backends, backend, seed_from.as_deref())
+                        .await;
+                    provisioned.push(backend.name.clone());
+                }
+            }
+            let needs_credentials: Vec<_> = manifest
+                .backends
+                .iter()
+                .filter_map(|b| {
+                    let provider = if b.neon.is_some() {
+                        "neon"
+                    } else if b.dblab.is_some() {
+                        "dblab"
+                    } else if b.xata.is_some() {
+                        "xata"
+                    } else {
+                        return None;
+                    };
+                    Some((b.name.clone(), provider))
+                })
+                .collect();
+
+            if json_output {
+                print_json(&serde_json::json!({
+                    "config": config_path.display().to_string(),
+                    "config_written": wrote_config,
+                    "backends": manifest.backends.iter().map(|b| &b.name).collect::<Vec<_>>(),
+                    "hooks_installed": manifest.install_hooks,
+                    "provisioned": provisioned,
+                    "needs_credentials": needs_credentials
+                        .iter()
+                        .map(|(name, provider)| serde_json::json!({"database": name, "provider": provider}))
+                        .collect::<Vec<_>>(),
+                }))?;
+            } else {
+                if wrote_config {
+                    println!("Wrote {}", config_path.display());
+                } else {
+                    println!(
+                        "Kept existing {} (use --force to replace it)",
+                        config_path.display()
+                    );
+                }
+                if !manifest.backends.is_empty() {
+                    println!(
+                        "Configured databases: {}",
+                        manifest
+                            .backends
+                            .iter()
+                            .map(|b| b.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                }
+                if manifest.install_hooks {
+                    println!("Installed Git hooks");
+                }
+                for (name, provider) in needs_credentials {
+                    println!(
+                        "Database '{}' needs credentials: run 'pgbranch auth login {}'",
+                        name, provider
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The declared branches: `--file` if given, else the `branches:` section of
+/// `.pgbranch.yml`, else `branches.yml` next to it.
+fn load_declared_branches(
+    file: Option<PathBuf>,
+    config: &Config,
+    config_path: &Option<PathBuf>,
+) -> Result<BranchesFile> {
+    if let Some(file) = file {
+        return BranchesFile::from_file(&file);
+    }
+    if let Some(ref branches) = config.branches {
+        return BranchesFile::from_config(branches);
+    }
+    let dir = match config_path.as_ref().and_then(|p| p.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    let path = dir.join("branches.yml");
+    if !path.exists() {
+        anyhow::bail!(
+            "No branches declared: add a 'branches:' section to .pgbranch.yml or create {}",
+            path.display()
+        );
+    }
+    BranchesFile::from_file(&path)
+}
+
+fn print_branch_plan(plan: &Plan) {
+    if plan.changes.is_empty() {
+        println!(
+            "Branches of '{}' already match. Nothing to do.",
+            plan.database
+        );
+        return;
+    }
+
+    println!("Plan for '{}':", plan.database);
+    for change in &plan.changes {
+        match change.kind {
+            ChangeKind::Create => match &change.from {
+                Some(from) => println!("  + create {} (from {})", change.branch, from),
+                None => println!("  + create {}", change.branch),
+            },
+            ChangeKind::Seed => println!(
+                "  ~ seed {} from {}",
+                change.branch,
+                change.source.as_deref().unwrap_or("?")
+            ),
+            ChangeKind::SetReadOnly => println!(
+                "  ~ make {} {}",
+                change.branch,
+                if change.read_only == Some(true) {
+                    "read-only"
+                } else {
+                    "writable"
+                }
+            ),
+            ChangeKind::SetLimits => println!(
+                "  ~ limit {} to {}",
+                change.branch,
+                plan::describe_limits(&change.limits.unwrap_or_default())
+            ),
+            ChangeKind::Delete => match &change.reason {
+                Some(reason) => println!("  - delete {} ({})", change.branch, reason),
+                None => println!("  - delete {}", change.branch),
+            },
+        }
+        for action in &change.actions {
+            println!("      [{}] {}", action.target, action.description);
+        }
+    }
+
+    let count = |kind| plan.changes.iter().filter(|c| c.kind == kind).count();
+    println!(
+        "{} to create, {} to seed, {} to reconfigure, {} to delete.",
+        count(ChangeKind::Create),
+        count(ChangeKind::Seed),
+        count(ChangeKind::SetReadOnly) + count(ChangeKind::SetLimits),
+        count(ChangeKind::Delete)
+    );
+}
+
+/// Print the steps a `--dry-run` would take, or say there are none.
+fn print_plan(
+    operation: &str,
+    actions: &[backends::PlannedAction],
+    json_output: bool,
+) -> Result<()> {
+    if json_output {
+        print_json(&serde_json::json!({
+            "dry_run": true,
+            "operation": operation,
+            "actions": actions,
+        }))?;
+    } else if actions.is_empty() {
+        println!("Dry run: nothing to do to {}", operation);
+    } else {
+        println!("Dry run: to {}, pgbranch would:", operation);
+        for (i, action) in actions.iter().enumerate() {
+            println!("  {}. [{}] {}", i + 1, action.target, action.description);
+        }
+    }
+    Ok(())
+}
+
+fn format_elapsed(elapsed: std::time::Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    if secs < 60.0 {
+        format!("{:.1}s", secs)
+    } else {
+        format!("{}m{:02}s", elapsed.as_secs() / 60, elapsed.as_secs() % 60)
+    }
+}
+
+fn print_phase_timings(phases: &[backends::PhaseTiming]) {
+    if phases.is_empty() {
+        println!("  Timings: not reported by this backend");
+        return;
+    }
+    println!("  Timings:");
+    for phase in phases {
+        println!(
+            "    {:<18} {}",
+            phase.phase,
+            format_elapsed(std::time::Duration::from_millis(phase.duration_ms))
+        );
+    }
+}
+
+/// The `--database` backend, else the default (or first) one; `None` when nothing is configured.
+#[cfg(feature = "backend-local")]
+fn select_backend<'a>(
+    backends: &'a [crate::config::NamedBackendConfig],
+    database_name: Option<&str>,
+) -> Result<Option<&'a crate::config::NamedBackendConfig>> {
+    match database_name {
+        Some(name) => backends
+            .iter()
+            .find(|b| b.name == name)
+            .map(Some)
+            .ok_or_else(|| {
+                coded!(
+                    DatabaseNotFound,
+                    "Database '{}' not found in configuration",
+                    name
+                )
+            }),
+        None => Ok(backends.iter().find(|b| b.default).or(backends.first())),
+    }
+}
+
+#[cfg(feature = "backend-local")]
+async fn handle_bench_storage_command(
+    config: &Config,
+    json_output: bool,
+    database_name: Option<&str>,
+    size_mb: u64,
+) -> Result<()> {
+    use crate::backends::local::LocalBackend;
+
+    if size_mb == 0 {
+        anyhow::bail!("--size-mb must be at least 1");
+    }
+    // Benchmark where the local backend keeps its data, if one is configured
+    let backends = config.resolve_backends();
+    let local_config = select_backend(&backends, database_name)?.and_then(|b| b.local.as_ref());
+    let storage = LocalBackend::storage_for(local_config).await?;
+
+    if !json_output {
+        println!(
+            "Cloning a {} MB synthetic dataset with each storage backend...",
+            size_mb
+        );
+    }
+    let results = storage.bench(size_mb * 1024 * 1024).await;
+
+    if json_output {
+        print_json(&serde_json::json!({ "dataset_mb": size_mb, "results": results }))?;
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:>12} {:>12}  Detail",
+        "Backend", "Clone time", "Space used"
+    );
+    for result in &results {
+        let clone = result
+            .clone_ms
+            .map(|ms| match ms {
+                0..=999 => format!("{} ms", ms),
+                _ => format_elapsed(std::time::Duration::from_millis(ms)),
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let space = result
+            .space_bytes
+            .map(|bytes| format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)))
+            .unwrap_or_else(|| "-".to_string());
+        let detail = if result.available {
+            result.detail.clone()
+        } else {
+            format!("unavailable: {}", result.detail)
+        };
+        println!(
+            "{:<12} {:>12} {:>12}  {}",
+            result.backend, clone, space, detail
+        );
+    }
+    if results
+        .iter()
+        .any(|r| r.backend == "zfs" && !r.available && cfg!(target_os = "linux"))
+    {
+        println!("\nZFS isn't set up; 'pgbranch setup-zfs' creates a file-backed pool to compare.");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "backend-local")]
+async fn handle_selftest_command(
+    config: &Config,
+    json_output: bool,
+    database_name: Option<&str>,
+) -> Result<()> {
+    use crate::backends::local::selftest::{self, StepStatus};
+
+    // Exercise the configured local setup (data root, image, ports) if there is one
+    let backends = config.resolve_backends();
+    let local_config = select_backend(&backends, database_name)?.and_then(|b| b.local.as_ref());
+
+    if !json_output {
+        println!("Running the local backend self-test in a throwaway project...");
+    }
+    let report = selftest::run(config, local_config, |step| {
+        if json_output {
+            return;
+        }
+        let label = match step.status {
+            StepStatus::Passed => "PASS",
+            StepStatus::Failed => "FAIL",
+            StepStatus::Skipped => "SKIP",
+        };
+        match step.status {
+            StepStatus::Skipped => println!("  [{}] {}", label, step.name),
+            _ => println!(
+                "  [{}] {} ({})",
+                label,
+                step.name,
+                format_elapsed(std::time::Duration::from_millis(step.elapsed_ms))
+            ),
+        }
+        if let Some(ref error) = step.error {
+            println!("         {}", error);
+        }
+    })
+    .await?;
+
+    if json_output {
+        print_json(&report)?;
+    }
+    if !report.passed {
+        anyhow::bail!(
+            "Self-test failed. 'pgbranch doctor' checks Docker and the storage setup in more detail."
+        );
+    }
+    if !json_output {
+        println!("Self-test passed: branching works on this machine.");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "backend-local")]
+fn handle_stats_command(
+    config: &Config,
+    json_output: bool,
+    database_name: Option<&str>,
+    all: bool,
+) -> Result<()> {
+    use crate::backends::local::LocalBackend;
+
+    let backends = config.resolve_backends();
+    let backend = select_backend(&backends, database_name)?;
+    if let Some(b) = backend.filter(|b| !backends::factory::BackendInfo::is_local(&b.backend_type))
+    {
+        anyhow::bail!(
+            "Database '{}' uses the {} backend; usage stats are only kept for local databases",
+            b.name,
+            b.backend_type
+        );
+    }
+    // Same fallback name as the factory uses without configured databases
+    let project_name = match backend {
+        Some(b) => LocalBackend::project_name(&b.name, b.local.as_ref()),
+        None => std::env::current_dir()
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+            .unwrap_or_else(|| "default".to_string()),
+    };
+    let summary = match LocalBackend::open_existing_store(backend.and_then(|b| b.local.as_ref()))? {
+        Some(store) => store.usage_summary((!all).then_some(project_name.as_str()))?,
+        None => Vec::new(),
+    };
+
+    if json_output {
+        print_json(&summary)?;
+        return Ok(());
+    }
+    if summary.is_empty() {
+        println!("No usage recorded yet for '{}'", project_name);
+        return Ok(());
+    }
+
+    let seconds = |ms: i64| format!("{:.1}s", ms as f64 / 1000.0);
+    let mut projects: Vec<&str> = summary.iter().map(|s| s.project_name.as_str()).collect();
+    projects.dedup();
+    for project in projects {
+        let rows: Vec<_> = summary
+            .iter()
+            .filter(|s| s.project_name == project)
+            .collect();
+        let since = rows.iter().map(|s| s.first_at).min().unwrap_or_default();
+        let since = chrono::DateTime::from_timestamp_millis(since)
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        let created: i64 = rows
+            .iter()
+            .filter(|s| s.operation == "create")
+            .map(|s| s.count)
+            .sum();
+        println!(
+            "{} (since {}): {} branches created",
+            project, since, created
+        );
+        for row in &rows {
+            let label = match row.detail {
+                Some(ref detail) => format!("{} ({})", row.operation, detail),
+                None => row.operation.clone(),
+            };
+            println!(
+                "  {:<20} {:<12} {:>5}x  avg {:>7}  min {:>7}  max {:>7}",
+                label,
+                row.storage_backend.as_deref().unwrap_or("-"),
+                row.count,
+                seconds(row.avg_ms),
+                seconds(row.min_ms),
+                seconds(row.max_ms)
+            );
+        }
+    }
+
+    // A project keeps one storage mode, so the comparison usually needs --all
+    let clone_avg = |cow: bool| {
+        summary
+            .iter()
+            .filter(|s| s.operation == "clone")
+            .filter(|s| (s.storage_backend.as_deref() != Some("copy")) == cow)
+            .map(|s| (s.total_ms, s.count))
+            .reduce(|a, b| (a.0 + b.0, a.1 + b.1))
+            .map(|(total, count)| (total / count, count))
+    };
+    if let (Some((cow_avg, cow_count)), Some((copy_avg, _))) = (clone_avg(true), clone_avg(false)) {
+        if copy_avg > cow_avg {
+            println!(
+                "Copy-on-write clones took {} on average vs {} for full copies, saving ~{} over {} clones",
+                seconds(cow_avg),
+                seconds(copy_avg),
+                seconds((copy_avg - cow_avg) * cow_count),
+                cow_count
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "backend-local")]
+fn handle_state_command(
+    action: StateCommands,
+    config: &Config,
+    json_output: bool,
+    database_name: Option<&str>,
+) -> Result<()> {
+    use crate::backends::local::state::{backup_path, StateStore, Store, SCHEMA_VERSION};
+    use crate::backends::local::LocalBackend;
+
+    let backends = config.resolve_backends();
+    let backend = select_backend(&backends, database_name)?;
+    if let Some(url) = backend
+        .and_then(|b| b.local.as_ref())
+        .and_then(|l| l.state_store.as_deref())
+    {
+        anyhow::bail!(
+            "Database '{}' keeps its state in {}, not a state database; back it up with the store's own versioning",
+            backend.map(|b| b.name.as_str()).unwrap_or_default(),
+            url
+        );
+    }
+    // Without configured databases the local backend runs with its defaults
+    let db_path = match backend {
+        Some(b) if !backends::factory::BackendInfo::is_local(&b.backend_type) => None,
+        _ => Some(LocalBackend::state_db_path(
+            backend.and_then(|b| b.local.as_ref()),
+        )),
+    };
+    let require_db_path = || {
+        db_path.clone().ok_or_else(|| {
+            let backend = backend.expect("non-local backend resolved");
+            anyhow::anyhow!(
+                "Database '{}' uses the {} backend; only the local backend keeps a state database",
+                backend.name,
+                backend.backend_type
+            )
+        })
+    };
 
-            // Execute post-commands
-            if !config.post_commands.is_empty() {
-                let executor = PostCommandExecutor::new(config, &branch_name)?;
-                executor.execute_all_post_commands().await?;
+    match action {
+        StateCommands::Backup { output } => {
+            let db_path = require_db_path()?;
+            if !db_path.exists() {
+                anyhow::bail!("No state database at {}", db_path.display());
             }
-        }
-        Commands::Delete { branch_name } => {
-            backend.delete_branch(&branch_name).await?;
+            let store = Store::open(&db_path)?;
+            let output = output.unwrap_or_else(|| backup_path(&db_path, "manual"));
+            store.backup_to(&output)?;
             if json_output {
-                println!("{{\"status\":\"ok\",\"deleted\":\"{}\"}}", branch_name);
+                print_json(&serde_json::json!({
+                    "state_db": db_path.display().to_string(),
+                    "backup": output.display().to_string(),
+                    "state_schema_version": store.schema_version()?,
+                }))?;
             } else {
-                println!("Deleted database branch: {}", branch_name);
+                println!("Backed up {} to {}", db_path.display(), output.display());
             }
         }
-        Commands::List => {
-            let branches = backend.list_branches().await?;
+        StateCommands::Restore { file } => {
+            let db_path = require_db_path()?;
+            let previous = Store::restore(&db_path, &file)?;
             if json_output {
-                println!("{}", serde_json::to_string_pretty(&branches)?);
+                print_json(&serde_json::json!({
+                    "state_db": db_path.display().to_string(),
+                    "restored_from": file.display().to_string(),
+                    "previous_backup": previous.as_ref().map(|p| p.display().to_string()),
+                    "state_schema_version": SCHEMA_VERSION,
+                }))?;
             } else {
-                println!("Database branches ({}):", backend.backend_name());
-                print_branch_tree(&branches, "  ");
+                println!("Restored {} from {}", db_path.display(), file.display());
+                if let Some(previous) = previous {
+                    println!("Previous state saved to {}", previous.display());
+                }
+                println!("Containers and data directories are not changed by a restore.");
             }
         }
-        Commands::Start { branch_name } => {
-            if !backend.supports_lifecycle() {
-                anyhow::bail!(
-                    "Backend '{}' does not support start/stop lifecycle",
-                    backend.backend_name()
-                );
-            }
-            backend.start_branch(&branch_name).await?;
+        StateCommands::Show { redact } => {
+            let dump = dump_state(db_path.as_deref(), redact)?;
             if json_output {
-                println!("{{\"status\":\"ok\",\"started\":\"{}\"}}", branch_name);
-            } else {
-                println!("Started branch: {}", branch_name);
+                print_json(&dump)?;
+                return Ok(());
             }
-        }
-        Commands::Stop { branch_name } => {
-            if !backend.supports_lifecycle() {
-                anyhow::bail!(
-                    "Backend '{}' does not support start/stop lifecycle",
-                    backend.backend_name()
-                );
+
+            match db_path.as_deref().filter(|p| p.exists()) {
+                Some(path) => {
+                    let store = Store::open(path)?;
+                    println!(
+                        "State database: {} (schema version {})",
+                        path.display(),
+                        store.schema_version()?
+                    );
+                    let branches = store.list_all_branches()?;
+                    let snapshots = store.list_all_snapshots()?;
+                    for project in store.list_projects()? {
+                        match &project.owner {
+                            Some(owner) => println!(
+                                "  {} [{}, {}, owner {}]",
+                                project.name,
+                                project.image,
+                                project.storage_backend.as_str(),
+                                owner
+                            ),
+                            None => println!(
+                                "  {} [{}, {}]",
+                                project.name,
+                                project.image,
+                                project.storage_backend.as_str()
+                            ),
+                        }
+                        for branch in branches.iter().filter(|b| b.project_id == project.id) {
+                            let snapshot_count = snapshots
+                                .iter()
+                                .filter(|s| s.branch_id == branch.id)
+                                .count();
+                            println!(
+                                "    {:<30} {:<12} port {:<6} {}{}",
+                                branch.name,
+                                branch.state.as_str(),
+                                branch.port,
+                                branch.container_name,
+                                if snapshot_count > 0 {
+                                    format!(" ({} snapshots)", snapshot_count)
+                                } else {
+                                    String::new()
+                                }
+                            );
+                        }
+                    }
+                }
+                None => println!("State database: none"),
             }
-            backend.stop_branch(&branch_name).await?;
-            if json_output {
-                println!("{{\"status\":\"ok\",\"stopped\":\"{}\"}}", branch_name);
-            } else {
-                println!("Stopped branch: {}", branch_name);
+
+            let state_manager = LocalStateManager::new()?;
+            println!("Local state: {}", state_manager.state_file_path().display());
+            let mut projects: Vec<_> = state_manager.state().projects.iter().collect();
+            projects.sort_by(|a, b| a.0.cmp(b.0));
+            for (path, project) in projects {
+                println!(
+                    "  {} (current: {}, {} recent, {} backends)",
+                    path,
+                    project.current_branch.as_deref().unwrap_or("-"),
+                    project.recent_branches.len(),
+                    project.backends.as_ref().map_or(0, |b| b.len())
+                );
             }
         }
-        Commands::Reset { branch_name } => {
-            if !backend.supports_lifecycle() {
-                anyhow::bail!(
-                    "Backend '{}' does not support reset",
-                    backend.backend_name()
-                );
+        StateCommands::Export { output, redact } => {
+            let dump = serde_json::to_string_pretty(&dump_state(db_path.as_deref(), redact)?)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, dump + "\n")
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    if !json_output {
+                        println!("Exported local state to {}", path.display());
+                    }
+                }
+                None => println!("{}", dump),
             }
-            backend.reset_branch(&branch_name).await?;
-            if json_output {
-                println!("{{\"status\":\"ok\",\"reset\":\"{}\"}}", branch_name);
-            } else {
-                println!("Reset branch: {}", branch_name);
+        }
+    }
+
+    Ok(())
+}
+
+/// The schema of `command`'s `--json` output, or the commands that have one.
+fn print_schema(command: Option<&str>, json_output: bool) -> Result<()> {
+    match command {
+        Some(command) => print!("{}", crate::output::schema(command)?),
+        None if json_output => print_json(&serde_json::json!({
+            "schemas": crate::output::schema_names(),
+        }))?,
+        None => {
+            println!("JSON Schemas (pgbranch schema <command>):");
+            for name in crate::output::schema_names() {
+                println!("  {}", name);
             }
         }
-        Commands::Doctor => {
-            let report = backend.doctor().await?;
-            if json_output {
-                println!("{}", serde_json::to_string_pretty(&report)?);
-            } else {
-                println!("Doctor report ({}):", backend.backend_name());
-                for check in &report.checks {
-                    let icon = if check.available { "OK" } else { "FAIL" };
-                    println!("  [{}] {}: {}", icon, check.name, check.detail);
+    }
+    Ok(())
+}
+
+/// For `--output schema`: the schema of `cmd`'s output instead of running it.
+pub fn print_output_schema(cmd: &Commands) -> Result<()> {
+    let command = match cmd {
+        Commands::List { .. } => "list",
+        Commands::Status => "status",
+        Commands::Connection { .. } => "connection",
+        Commands::Doctor => "doctor",
+        Commands::Schema { command } => return print_schema(command.as_deref(), false),
+        _ => anyhow::bail!(
+            "No published schema for this command's output; available: {}",
+            crate::output::schema_names().join(", ")
+        ),
+    };
+    print!("{}", crate::output::schema(command)?);
+    Ok(())
+}
+
+/// The `signing` section, for commands that can't do without it.
+fn signing_config(config: &Config) -> Result<&SigningConfig> {
+    config.signing.as_ref().ok_or_else(|| {
+        anyhow::anyhow!(
+            "No 'signing' section in .pgbranch.yml; add one with the key to sign with and the keys to trust"
+        )
+    })
+}
+
+/// Sign a file an export just wrote, when a signing key is configured.
+/// Returns where the signature went.
+fn sign_export(config: &Config, path: &std::path::Path) -> Result<Option<PathBuf>> {
+    match config.signing {
+        Some(ref signing) if signing.key.is_some() => {
+            Ok(Some(crate::signing::sign(signing, path)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Raw contents of the local backend's state database and of local_state.yml.
+#[cfg(feature = "backend-local")]
+fn dump_state(db_path: Option<&std::path::Path>, redact: bool) -> Result<serde_json::Value> {
+    use crate::backends::local::state::{StateStore, Store};
+
+    let state_db = match db_path.filter(|p| p.exists()) {
+        Some(path) => {
+            let store = Store::open(path)?;
+            serde_json::json!({
+                "path": path.display().to_string(),
+                "schema_version": store.schema_version()?,
+                "projects": store.list_projects()?,
+                "branches": store.list_all_branches()?,
+                "snapshots": store.list_all_snapshots()?,
+            })
+        }
+        None => serde_json::Value::Null,
+    };
+
+    let state_manager = LocalStateManager::new()?;
+    let mut dump = serde_json::json!({
+        "state_db": state_db,
+        "local_state": {
+            "path": state_manager.state_file_path().display().to_string(),
+            "projects": state_manager.state().projects,
+        },
+    });
+    if redact {
+        redact_secrets(&mut dump);
+    }
+    Ok(dump)
+}
+
+#[cfg(feature = "backend-local")]
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                let secret = ["password", "api_key", "token", "secret"]
+                    .iter()
+                    .any(|marker| key.contains(marker));
+                match value {
+                    serde_json::Value::String(s) if secret && !s.is_empty() => {
+                        *s = "********".to_string();
+                    }
+                    _ => redact_secrets(value),
                 }
             }
         }
-        Commands::Connection {
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+fn print_prompt_context(json_output: bool) -> Result<()> {
+    // Stay silent outside pgbranch projects so prompts can embed this unconditionally
+    let Some(config_path) = Config::find_config_file()? else {
+        return Ok(());
+    };
+    let state_manager = LocalStateManager::new()?;
+    let Some(branch) = state_manager.get_current_branch(&config_path) else {
+        return Ok(());
+    };
+    let config = Config::from_file(&config_path).ok();
+
+    let backend = state_manager
+        .get_backends(&config_path)
+        .or_else(|| config.as_ref().map(|c| c.resolve_backends()))
+        .and_then(|backends| {
+            backends
+                .iter()
+                .find(|b| b.default)
+                .or(backends.first())
+                .cloned()
+        });
+
+    let display_name = if branch == "_main" {
+        config
+            .as_ref()
+            .map(|c| c.git.main_branch.clone())
+            .unwrap_or_else(|| "main".to_string())
+    } else {
+        branch.clone()
+    };
+
+    #[cfg(feature = "backend-local")]
+    let branch_state = backend
+        .as_ref()
+        .filter(|b| backends::factory::BackendInfo::is_local(&b.backend_type))
+        .and_then(|b| {
+            backends::local::LocalBackend::cached_branch_state(&b.name, b.local.as_ref(), &branch)
+        });
+    #[cfg(not(feature = "backend-local"))]
+    let branch_state: Option<String> = None;
+
+    if json_output {
+        print_json(&serde_json::json!({
+            "branch": display_name,
+            "database": backend.as_ref().map(|b| &b.name),
+            "state": branch_state,
+        }))?;
+    } else {
+        match branch_state {
+            Some(state) if state != "running" => println!("{} ({})", display_name, state),
+            _ => println!("{}", display_name),
+        }
+    }
+
+    Ok(())
+}
+
+enum StackAction {
+    Create { from: Option<String> },
+    Switch,
+    Delete,
+}
+
+/// Run create/switch/delete for one branch name on every configured database,
+/// or only on `database_name`. Failures are reported per database; the
+/// command fails if any database did.
+async fn handle_all_databases_command(
+    cmd: Commands,
+    config: &Config,
+    json_output: bool,
+    local_state: &mut Option<LocalStateManager>,
+    config_path: &Option<std::path::PathBuf>,
+    database_name: Option<&str>,
+    non_interactive: bool,
+) -> Result<()> {
+    let scope = if database_name.is_some() {
+        "--database"
+    } else {
+        "--all-databases"
+    };
+    let requested_name = match &cmd {
+        Commands::Create { branch_name, .. } | Commands::Delete { branch_name, .. } => {
+            branch_name.clone()
+        }
+        Commands::Switch { branch_name, .. } => branch_name.clone().unwrap_or_default(),
+        _ => String::new(),
+    };
+    let prune_keep = auto_prune_keeps(config, &cmd).filter(|_| config.behavior.auto_prune);
+    let (action, branch_name) = match cmd {
+        Commands::Create { dry_run: true, .. } | Commands::Delete { dry_run: true, .. } => {
+            anyhow::bail!("--all-databases cannot be combined with --dry-run")
+        }
+        Commands::Create {
+            schema_only: true, ..
+        } => {
+            anyhow::bail!("{} cannot be combined with --schema-only", scope)
+        }
+        Commands::Create { ref pgconf, .. } if !pgconf.is_empty() => {
+            anyhow::bail!("{} cannot be combined with --pgconf", scope)
+        }
+        Commands::Create {
+            branch_name, from, ..
+        } => (StackAction::Create { from }, branch_name),
+        Commands::Delete {
+            branch_name, force, ..
+        } => {
+            check_deleting_current_branch(local_state, config_path, &branch_name, force)?;
+            (StackAction::Delete, branch_name)
+        }
+        Commands::Switch {
             branch_name,
-            format,
+            template,
+            dry_run,
+            ..
         } => {
-            let conn = backend.get_connection_info(&branch_name).await?;
-            let fmt = format.as_deref().unwrap_or("uri");
-            match fmt {
-                "uri" => {
-                    if let Some(ref uri) = conn.connection_string {
-                        println!("{}", uri);
-                    } else {
-                        println!(
-                            "postgresql://{}@{}:{}/{}",
-                            conn.user, conn.host, conn.port, conn.database
-                        );
-                    }
-                }
-                "env" => {
-                    println!("DATABASE_HOST={}", conn.host);
-                    println!("DATABASE_PORT={}", conn.port);
-                    println!("DATABASE_NAME={}", conn.database);
-                    println!("DATABASE_USER={}", conn.user);
-                    if let Some(ref password) = conn.password {
-                        println!("DATABASE_PASSWORD={}", password);
-                    }
-                    if let Some(ref uri) = conn.connection_string {
-                        println!("DATABASE_URL={}", uri);
+            if template || dry_run {
+                anyhow::bail!("{} cannot be combined with --template or --dry-run", scope);
+            }
+            let branch_name = match branch_name.as_deref() {
+                Some("-") if database_name.is_some() => {
+                    match get_previous_branch(local_state, config_path) {
+                        Some(previous) if previous != "_main" => previous,
+                        _ => anyhow::bail!("No previous branch to switch to"),
                     }
                 }
-                _ => {
-                    println!("{}", serde_json::to_string_pretty(&conn)?);
+                Some("-") | None => {
+                    anyhow::bail!("{} requires an explicit branch name", scope)
                 }
-            }
+                Some(name) => config.get_normalized_branch_name(name),
+            };
+            (StackAction::Switch, branch_name)
         }
-        Commands::Status => {
-            let branches = backend.list_branches().await.unwrap_or_default();
-            let running = branches
-                .iter()
-                .filter(|b| b.state.as_deref() == Some("running"))
-                .count();
-            let stopped = branches
-                .iter()
-                .filter(|b| b.state.as_deref() == Some("stopped"))
-                .count();
-            let project_info = backend.project_info();
+        _ => unreachable!("not an --all-databases command"),
+    };
+    // `switch -` is only resolved now
+    let prune_keep = prune_keep.map(|mut keep| {
+        keep.push(branch_name.clone());
+        keep
+    });
+    // Databases with their own branch_filter only get the branches it lets through
+    let filtered_out = |database: &str| {
+        let has_filter = config
+            .resolve_backends()
+            .iter()
+            .any(|b| b.name == database && b.branch_filter.is_some());
+        if !has_filter || database_name.is_some() || matches!(action, StackAction::Delete) {
+            return None;
+        }
+        config.branch_filter_reason(&requested_name, Some(database))
+    };
 
-            if json_output {
-                let mut status = serde_json::json!({
-                    "backend": backend.backend_name(),
-                    "total_branches": branches.len(),
-                    "running": running,
-                    "stopped": stopped,
-                    "supports_lifecycle": backend.supports_lifecycle(),
-                });
-                if let Some(ref info) = project_info {
-                    status["project"] = serde_json::Value::String(info.name.clone());
-                    if let Some(ref storage) = info.storage_backend {
-                        status["storage"] = serde_json::Value::String(storage.clone());
-                    }
-                    if let Some(ref image) = info.image {
-                        status["image"] = serde_json::Value::String(image.clone());
-                    }
+    let all_backends = match database_name {
+        Some(name) => vec![backends::factory::resolve_backend(config, Some(name)).await?],
+        None => backends::factory::create_all_backends(config).await?,
+    };
+    let mut results = serde_json::Map::new();
+    let mut failed = 0;
+    let mut skipped = 0;
+    let mut created_any = false;
+    // Post-commands run once, connected to the default database if it took
+    // part, else the first one that did
+    let default_database = config.default_backend_name();
+    let mut post_connection = None;
+
+    for named in &all_backends {
+        let backend = named.backend.as_ref();
+        if let Some(reason) = filtered_out(&named.name) {
+            skipped += 1;
+            if !json_output {
+                println!("[{}] Skipped: {}", named.name, reason);
+            }
+            results.insert(
+                named.name.clone(),
+                serde_json::json!({ "status": "skipped", "reason": reason }),
+            );
+            continue;
+        }
+        if let Some(keep) = &prune_keep {
+            auto_prune(config, &named.name, backend, keep, config_path).await;
+        }
+        let mut created = matches!(action, StackAction::Create { .. });
+        let outcome: Result<serde_json::Value> = match &action {
+            StackAction::Create { from } => {
+                async {
+                    check_branch_name(backend, &branch_name)?;
+                    let info = backend.create_branch(&branch_name, from.as_deref()).await?;
+                    Ok(serde_json::to_value(info)?)
                 }
-                println!("{}", serde_json::to_string_pretty(&status)?);
-            } else {
-                println!("Backend: {}", backend.backend_name());
-                if let Some(ref info) = project_info {
-                    println!("Project: {}", info.name);
-                    if let Some(ref storage) = info.storage_backend {
-                        println!("Storage: {}", storage);
-                    }
-                    if let Some(ref image) = info.image {
-                        println!("Image: {}", image);
+                .await
+            }
+            StackAction::Switch => {
+                async {
+                    let info = if backend.branch_exists(&branch_name).await? {
+                        backend.switch_to_branch(&branch_name).await?
+                    } else {
+                        check_branch_name(backend, &branch_name)?;
+                        created = true;
+                        backend.create_branch(&branch_name, None).await?
+                    };
+                    Ok(serde_json::to_value(info)?)
+                }
+                .await
+            }
+            StackAction::Delete => {
+                async {
+                    backend.delete_branch(&branch_name).await?;
+                    audit::record(
+                        config,
+                        AuditEvent {
+                            action: AuditAction::BranchDeleted,
+                            database: &named.name,
+                            backend: backend.backend_type(),
+                            branches: std::slice::from_ref(&branch_name),
+                        },
+                    )
+                    .await;
+                    Ok(serde_json::json!({ "name": branch_name }))
+                }
+                .await
+            }
+        };
+
+        match outcome {
+            Ok(value) => {
+                if !json_output {
+                    let verb = match action {
+                        StackAction::Create { .. } => "Created",
+                        StackAction::Switch => "Switched to",
+                        StackAction::Delete => "Deleted",
+                    };
+                    println!("[{}] {} database branch: {}", named.name, verb, branch_name);
+                }
+                results.insert(
+                    named.name.clone(),
+                    serde_json::json!({ "status": "ok", "branch": value }),
+                );
+                if !matches!(action, StackAction::Delete)
+                    && (post_connection.is_none() || default_database.as_ref() == Some(&named.name))
+                {
+                    if let Ok(conn) = backend.get_connection_info(&branch_name).await {
+                        post_connection = Some((conn, backend.backend_type()));
                     }
                 }
-                println!(
-                    "Branches: {} total ({} running, {} stopped)",
-                    branches.len(),
-                    running,
-                    stopped
+                if created {
+                    created_any = true;
+                    auto_cleanup(
+                        config,
+                        &named.name,
+                        backend,
+                        &branch_name,
+                        non_interactive,
+                        json_output,
+                    )
+                    .await;
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                if !json_output {
+                    println!("[{}] Failed: {}", named.name, e);
+                }
+                results.insert(
+                    named.name.clone(),
+                    serde_json::json!({ "status": "error", "error": e.to_string() }),
                 );
-                if backend.supports_lifecycle() {
-                    println!("Lifecycle: supported (start/stop/reset)");
+            }
+        }
+    }
+
+    if json_output {
+        print_json(&serde_json::json!({
+            "branch": branch_name,
+            "databases": results,
+            "failed": failed,
+        }))?;
+    }
+
+    let succeeded = all_backends.len() - failed - skipped;
+    if succeeded > 0 {
+        let event = match action {
+            StackAction::Create { .. } => Some(PostCommandEvent::Create),
+            StackAction::Switch if created_any => Some(PostCommandEvent::Create),
+            StackAction::Switch => Some(PostCommandEvent::Switch),
+            StackAction::Delete => None,
+        };
+        match action {
+            StackAction::Switch => {
+                set_current_branch(local_state, config_path, Some(branch_name.clone()))?;
+            }
+            StackAction::Delete
+                if get_current_branch(local_state, config_path).as_deref()
+                    == Some(branch_name.as_str()) =>
+            {
+                set_current_branch(local_state, config_path, Some("_main".to_string()))?;
+            }
+            _ => {}
+        }
+        if let Some(event) = event {
+            if !config.post_commands.is_empty() {
+                let mut executor = PostCommandExecutor::new(config, &branch_name, event)?;
+                if let Some((conn, backend_type)) = &post_connection {
+                    executor = executor.with_connection_info(conn, backend_type);
                 }
+                executor.execute_all_post_commands().await?;
             }
         }
-        Commands::Cleanup { max_count } => {
-            let max = max_count.unwrap_or(config.behavior.max_branches.unwrap_or(10));
-            let deleted = backend.cleanup_old_branches(max).await?;
-            if json_output {
-                println!("{}", serde_json::to_string_pretty(&deleted)?);
-            } else if deleted.is_empty() {
-                println!("No branches to clean up");
-            } else {
-                println!(
-                    "Cleaned up {} branches: {}",
-                    deleted.len(),
-                    deleted.join(", ")
+    }
+
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} databases failed for branch '{}'",
+            failed,
+            all_backends.len(),
+            branch_name
+        );
+    }
+
+    Ok(())
+}
+
+/// Apply the retention limit to every configured database. Each database keeps
+/// its own newest branches; failures are reported per database.
+async fn handle_cleanup_all_databases(
+    config: &Config,
+    max_count: Option<usize>,
+    dry_run: bool,
+    json_output: bool,
+) -> Result<()> {
+    let max = max_count.unwrap_or(config.behavior.max_branches.unwrap_or(10));
+    let all_backends = backends::factory::create_all_backends(config).await?;
+
+    if dry_run {
+        let mut actions = Vec::new();
+        for named in &all_backends {
+            let backend = named.backend.as_ref();
+            for branch_name in cleanup_candidates(config, backend, max).await? {
+                actions.extend(
+                    backend
+                        .plan_delete_branch(&branch_name)
+                        .await?
+                        .into_iter()
+                        .map(|action| {
+                            backends::PlannedAction::new(
+                                &action.target,
+                                format!("[{}] {}", named.name, action.description),
+                            )
+                        }),
                 );
             }
         }
-        Commands::Destroy { force } => {
-            if !backend.supports_destroy() {
-                anyhow::bail!(
-                    "Backend '{}' does not support destroy. This command is only available for the local (Docker + CoW) backend.",
-                    backend.backend_name()
-                );
-            }
+        return print_plan(
+            &format!(
+                "clean up branches beyond the newest {} in every database",
+                max
+            ),
+            &actions,
+            json_output,
+        );
+    }
 
-            let preview = backend.destroy_preview().await?;
-            let (project_name, branch_names) = match preview {
-                Some(p) => p,
-                None => {
-                    if json_output {
-                        println!("{{\"status\":\"ok\",\"message\":\"no project found\"}}");
+    let mut results = serde_json::Map::new();
+    let mut failed = 0;
+    for named in &all_backends {
+        let backend = named.backend.as_ref();
+        let outcome = match cleanup_candidates(config, backend, max).await {
+            Ok(candidates) => Ok(delete_branches(backend, candidates).await),
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(deleted) => {
+                if !deleted.is_empty() {
+                    audit::record(
+                        config,
+                        AuditEvent {
+                            action: AuditAction::BranchesCleanedUp,
+                            database: &named.name,
+                            backend: backend.backend_type(),
+                            branches: &deleted,
+                        },
+                    )
+                    .await;
+                }
+                if !json_output {
+                    if deleted.is_empty() {
+                        println!("[{}] No branches to clean up", named.name);
                     } else {
                         println!(
-                            "No project found for database '{}'. Nothing to destroy.",
-                            resolved_name
+                            "[{}] Cleaned up {} branches: {}",
+                            named.name,
+                            deleted.len(),
+                            deleted.join(", ")
                         );
                     }
-                    return Ok(());
-                }
-            };
-
-            if !force && !non_interactive {
-                println!("This will permanently destroy the following:");
-                println!("  Project: {}", project_name);
-                if branch_names.is_empty() {
-                    println!("  Branches: (none)");
-                } else {
-                    println!("  Branches ({}):", branch_names.len());
-                    for name in &branch_names {
-                        println!("    - {}", name);
-                    }
                 }
-                println!();
-                println!("All containers, storage data, and state will be removed.");
-
-                let confirm =
-                    inquire::Confirm::new("Are you sure you want to destroy this project?")
-                        .with_default(false)
-                        .prompt()?;
-
-                if !confirm {
-                    println!("Aborted.");
-                    return Ok(());
+                results.insert(
+                    named.name.clone(),
+                    serde_json::json!({ "status": "ok", "deleted": deleted }),
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                if !json_output {
+                    println!("[{}] Failed: {}", named.name, e);
                 }
+                results.insert(
+                    named.name.clone(),
+                    serde_json::json!({ "status": "error", "error": e.to_string() }),
+                );
             }
+        }
+    }
 
-            let destroyed = backend.destroy_project().await?;
+    if json_output {
+        print_json(&serde_json::json!({
+            "databases": results,
+            "failed": failed,
+        }))?;
+    }
 
-            // Remove the backend entry from local state
-            if let Some(ref path) = config_path {
-                if let Ok(mut state) = LocalStateManager::new() {
-                    let _ = state.remove_backend(path, &resolved_name);
-                }
-            }
+    if failed > 0 {
+        anyhow::bail!(
+            "Cleanup failed in {} of {} databases",
+            failed,
+            all_backends.len()
+        );
+    }
+    Ok(())
+}
 
-            // Also remove from committed config for backward compat (legacy configs)
-            config.remove_backend(&resolved_name);
-            if let Some(path) = config_path {
-                config.save_to_file(path)?;
+/// Branches cleanup would delete: those beyond the newest `max` and, with
+/// `behavior.max_branch_age`, those older than that, except protected ones.
+async fn cleanup_candidates(
+    config: &Config,
+    backend: &dyn backends::DatabaseBranchingBackend,
+    max: usize,
+) -> Result<Vec<String>> {
+    if !backend.supports_cleanup() {
+        return Ok(vec![]);
+    }
+    let mut candidates = backend.cleanup_candidates(max).await?;
+    if let Some(max_age) = config.behavior.max_branch_age()? {
+        let cutoff = chrono::Utc::now() - max_age;
+        for branch in backend.list_branches().await? {
+            let expired = branch.created_at.is_some_and(|created| created < cutoff);
+            if expired && !candidates.contains(&branch.name) {
+                candidates.push(branch.name);
             }
+        }
+    }
+    candidates.retain(|name| !config.is_protected_branch(name));
+    Ok(candidates)
+}
 
-            if json_output {
-                println!(
-                    "{}",
-                    serde_json::to_string_pretty(&serde_json::json!({
-                        "status": "ok",
-                        "project": project_name,
-                        "destroyed_branches": destroyed,
-                    }))?
-                );
-            } else {
-                println!(
-                    "Destroyed project '{}' and {} branch(es)",
-                    project_name,
-                    destroyed.len()
-                );
-                for name in &destroyed {
-                    println!("  - {}", name);
-                }
-            }
+/// Branches last used (or, if the backend doesn't track use, created) more
+/// than `max_age` ago, except protected ones, the current branch and `keep`.
+/// Branches the backend gives no times for never expire.
+async fn expired_branches(
+    config: &Config,
+    backend: &dyn backends::DatabaseBranchingBackend,
+    max_age: chrono::Duration,
+    keep: &[String],
+    config_path: &Option<std::path::PathBuf>,
+) -> Result<Vec<String>> {
+    let cutoff = chrono::Utc::now() - max_age;
+    let current = get_current_branch(&LocalStateManager::new().ok(), config_path);
+    Ok(backend
+        .list_branches()
+        .await?
+        .into_iter()
+        .filter(|b| b.last_used_at.or(b.created_at).is_some_and(|t| t < cutoff))
+        .map(|b| b.name)
+        .filter(|name| {
+            !config.is_protected_branch(name)
+                && current.as_ref() != Some(name)
+                && !keep.contains(name)
+        })
+        .collect())
+}
+
+/// The branches `cmd` works on, which pruning before it must leave alone,
+/// or `None` if `behavior.auto_prune` doesn't prune before it: only commands
+/// that go on to use a branch do, and not as a dry run. Read-only commands
+/// delete nothing, and the ones that delete branches do their own.
+fn auto_prune_keeps(config: &Config, cmd: &Commands) -> Option<Vec<String>> {
+    match cmd {
+        Commands::Create {
+            branch_name,
+            from,
+            dry_run: false,
+            ..
+        } => {
+            // Also the parent it's cloned from, without a <database>: or @<snapshot>
+            let parent = from.as_deref().map(|f| {
+                let branch = split_database_qualified(config, f)
+                    .map(|(_, branch)| branch)
+                    .unwrap_or(f);
+                branch.split('@').next().unwrap_or(branch).to_string()
+            });
+            Some(
+                [Some(branch_name.clone()), parent]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            )
         }
-        _ => unreachable!(),
+        Commands::Switch {
+            branch_name,
+            dry_run: false,
+            ..
+        } => Some(branch_name.iter().cloned().collect()),
+        Commands::Start { branch_name, .. }
+        | Commands::Reset {
+            branch_name,
+            dry_run: false,
+            ..
+        }
+        | Commands::Seed {
+            branch_name,
+            dry_run: false,
+            ..
+        } => Some(vec![branch_name.clone()]),
+        _ => None,
     }
+}
 
-    Ok(())
+/// With `behavior.auto_prune`, delete `database`'s branches that outlived
+/// `behavior.branch_ttl`, except `keep`, before running a command. Failures
+/// only warn, and the summary goes to stderr so stdout stays the command's own.
+async fn auto_prune(
+    config: &Config,
+    database: &str,
+    backend: &dyn backends::DatabaseBranchingBackend,
+    keep: &[String],
+    config_path: &Option<std::path::PathBuf>,
+) {
+    let expired = match config.behavior.branch_ttl() {
+        Ok(Some(max_age)) => expired_branches(config, backend, max_age, keep, config_path).await,
+        Ok(None) => return,
+        Err(e) => Err(e),
+    };
+    let expired = match expired {
+        Ok(expired) if !expired.is_empty() => expired,
+        Ok(_) => return,
+        Err(e) => {
+            eprintln!("Warning: auto-prune of '{}' failed: {:#}", database, e);
+            return;
+        }
+    };
+
+    let pruned = delete_branches(backend, expired).await;
+    if pruned.is_empty() {
+        return;
+    }
+    audit::record(
+        config,
+        AuditEvent {
+            action: AuditAction::BranchesPruned,
+            database,
+            backend: backend.backend_type(),
+            branches: &pruned,
+        },
+    )
+    .await;
+    eprintln!(
+        "Auto-prune removed {} branch(es) unused for {} from '{}': {}",
+        pruned.len(),
+        config.behavior.branch_ttl.as_deref().unwrap_or_default(),
+        database,
+        pruned.join(", ")
+    );
 }
 
-/// Handle aggregation commands (List, Status, Doctor) across all backends.
-async fn handle_multi_backend_command(
-    cmd: Commands,
+/// Delete cleanup candidates, returning the ones that were deleted.
+async fn delete_branches(
+    backend: &dyn backends::DatabaseBranchingBackend,
+    branch_names: Vec<String>,
+) -> Vec<String> {
+    let mut deleted = Vec::new();
+    for branch_name in branch_names {
+        match backend.delete_branch(&branch_name).await {
+            Ok(_) => deleted.push(branch_name),
+            Err(e) => log::warn!("Failed to delete branch {}: {}", branch_name, e),
+        }
+    }
+    deleted
+}
+
+/// With `behavior.auto_cleanup`, clean up `database` after `created` was
+/// created there. Interactive sessions confirm first; failures only warn, as
+/// the branch itself was created.
+async fn auto_cleanup(
     config: &Config,
+    database: &str,
+    backend: &dyn backends::DatabaseBranchingBackend,
+    created: &str,
+    non_interactive: bool,
     json_output: bool,
-) -> Result<()> {
-    let all_backends = backends::factory::create_all_backends(config).await?;
+) {
+    use std::io::IsTerminal;
 
-    match cmd {
-        Commands::List => {
-            if json_output {
-                let mut map = serde_json::Map::new();
-                for named in &all_backends {
-                    let branches = named.backend.list_branches().await.unwrap_or_default();
-                    map.insert(named.name.clone(), serde_json::to_value(&branches)?);
-                }
-                println!("{}", serde_json::to_string_pretty(&map)?);
-            } else {
-                for named in &all_backends {
-                    let branches = named.backend.list_branches().await.unwrap_or_default();
-                    println!("[{}] ({}):", named.name, named.backend.backend_name());
-                    print_branch_tree(&branches, "  ");
-                    println!();
-                }
-            }
+    if !config.behavior.auto_cleanup {
+        return;
+    }
+    let max = config.behavior.max_branches.unwrap_or(10);
+    let mut candidates = match cleanup_candidates(config, backend, max).await {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            eprintln!("Warning: auto-cleanup of '{}' failed: {:#}", database, e);
+            return;
         }
-        Commands::Status => {
-            if json_output {
-                let mut map = serde_json::Map::new();
-                for named in &all_backends {
-                    let branches = named.backend.list_branches().await.unwrap_or_default();
-                    let running = branches
-                        .iter()
-                        .filter(|b| b.state.as_deref() == Some("running"))
-                        .count();
-                    let stopped = branches
-                        .iter()
-                        .filter(|b| b.state.as_deref() == Some("stopped"))
-                        .count();
-                    let project_info = named.backend.project_info();
+    };
+    candidates.retain(|name| name != created);
+    if candidates.is_empty() {
+        return;
+    }
 
-                    let mut status = serde_json::json!({
-                        "backend": named.backend.backend_name(),
-                        "total_branches": branches.len(),
-                        "running": running,
-                        "stopped": stopped,
-                        "supports_lifecycle": named.backend.supports_lifecycle(),
-                    });
-                    if let Some(ref info) = project_info {
-                        status["project"] = serde_json::Value::String(info.name.clone());
-                        if let Some(ref storage) = info.storage_backend {
-                            status["storage"] = serde_json::Value::String(storage.clone());
-                        }
-                        if let Some(ref image) = info.image {
-                            status["image"] = serde_json::Value::String(image.clone());
-                        }
-                    }
-                    map.insert(named.name.clone(), status);
-                }
-                println!("{}", serde_json::to_string_pretty(&map)?);
-            } else {
-                for named in &all_backends {
-                    let branches = named.backend.list_branches().await.unwrap_or_default();
-                    let running = branches
-                        .iter()
-                        .filter(|b| b.state.as_deref() == Some("running"))
-                        .count();
-                    let stopped = branches
-                        .iter()
-                        .filter(|b| b.state.as_deref() == Some("stopped"))
-                        .count();
-                    let project_info = named.backend.project_info();
+    if !non_interactive && !json_output && std::io::stdin().is_terminal() {
+        let confirm = inquire::Confirm::new(&format!(
+            "Auto-cleanup: delete {} old branch(es) from '{}' ({})?",
+            candidates.len(),
+            database,
+            candidates.join(", ")
+        ))
+        .with_default(true)
+        .prompt();
+        if !matches!(confirm, Ok(true)) {
+            println!("Skipped auto-cleanup");
+            return;
+        }
+    }
 
-                    println!("[{}] ({}):", named.name, named.backend.backend_name());
-                    if let Some(ref info) = project_info {
-                        println!("  Project: {}", info.name);
-                        if let Some(ref storage) = info.storage_backend {
-                            println!("  Storage: {}", storage);
-                        }
-                        if let Some(ref image) = info.image {
-                            println!("  Image: {}", image);
-                        }
-                    }
-                    println!(
-                        "  Branches: {} total ({} running, {} stopped)",
-                        branches.len(),
-                        running,
-                        stopped
-                    );
-                    if named.backend.supports_lifecycle() {
-                        println!("  Lifecycle: supported (start/stop/reset)");
-                    }
-                    println!();
-                }
+    let deleted = delete_branches(backend, candidates).await;
+    if deleted.is_empty() {
+        return;
+    }
+    audit::record(
+        config,
+        AuditEvent {
+            action: AuditAction::BranchesCleanedUp,
+            database,
+            backend: backend.backend_type(),
+            branches: &deleted,
+        },
+    )
+    .await;
+    let summary = format!(
+        "Auto-cleanup removed {} old branch(es) from '{}': {}",
+        deleted.len(),
+        database,
+        deleted.join(", ")
+    );
+    // Keep stdout parseable for --json
+    if json_output {
+        eprintln!("{}", summary);
+    } else {
+        println!("{}", summary);
+    }
+}
+
+/// Run a create, reset or seed of `branch_name` so that Ctrl-C or SIGTERM
+/// stops it and rolls back what it left half done instead of exiting mid-way.
+async fn run_interruptible<T>(
+    backend: &dyn backends::DatabaseBranchingBackend,
+    branch_name: &str,
+    operation: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    let result = shutdown::interruptible(operation).await;
+    if let Err(ref e) = result {
+        if shutdown::is_interrupted(e) {
+            eprintln!("Interrupted; rolling back branch '{}'...", branch_name);
+            match backend.recover_interrupted(branch_name).await {
+                Ok(Some(outcome)) => eprintln!("{}", outcome),
+                Ok(None) => {}
+                Err(err) => eprintln!(
+                    "Warning: rollback failed: {:#}. Remove what's left with 'pgbranch delete {}'",
+                    err, branch_name
+                ),
             }
         }
-        Commands::Doctor => {
-            if json_output {
-                let mut map = serde_json::Map::new();
-                for named in &all_backends {
-                    let report = named.backend.doctor().await?;
-                    map.insert(named.name.clone(), serde_json::to_value(&report)?);
-                }
-                println!("{}", serde_json::to_string_pretty(&map)?);
-            } else {
-                for named in &all_backends {
-                    let report = named.backend.doctor().await?;
-                    println!(
-                        "[{}] Doctor report ({}):",
-                        named.name,
-                        named.backend.backend_name()
-                    );
-                    for check in &report.checks {
-                        let icon = if check.available { "OK" } else { "FAIL" };
-                        println!("  [{}] {}: {}", icon, check.name, check.detail);
-                    }
-                    println!();
-                }
+    }
+    result
+}
+
+/// One table in `diff --data`: "same", "changed", "added" (only in b) or
+/// "removed" (only in a).
+struct TableDiff<'a> {
+    table: &'a str,
+    a: Option<&'a backends::TableData>,
+    b: Option<&'a backends::TableData>,
+    status: &'static str,
+}
+
+fn diff_table_data<'a>(
+    in_a: &'a [backends::TableData],
+    in_b: &'a [backends::TableData],
+) -> Vec<TableDiff<'a>> {
+    let mut names: Vec<&str> = in_a.iter().chain(in_b).map(|t| t.table.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|table| {
+            let a = in_a.iter().find(|t| t.table == table);
+            let b = in_b.iter().find(|t| t.table == table);
+            let status = match (a, b) {
+                (Some(a), Some(b)) if a.rows == b.rows && a.checksum == b.checksum => "same",
+                (Some(_), Some(_)) => "changed",
+                (None, _) => "added",
+                (_, None) => "removed",
+            };
+            TableDiff {
+                table,
+                a,
+                b,
+                status,
             }
+        })
+        .collect()
+}
+
+/// Split `<database>:<branch>` when `<database>` names a configured backend.
+fn split_database_qualified<'a>(config: &Config, from: &'a str) -> Option<(&'a str, &'a str)> {
+    let (database, branch) = from.split_once(':')?;
+    if branch.is_empty() || branch.starts_with("//") {
+        return None;
+    }
+    config
+        .resolve_backends()
+        .iter()
+        .any(|b| b.name == database)
+        .then_some((database, branch))
+}
+
+/// PostgreSQL URL (with password) for a branch of another configured backend.
+async fn remote_branch_url(config: &Config, database: &str, branch: &str) -> Result<String> {
+    let source = backends::factory::resolve_backend(config, Some(database)).await?;
+    let source = source.backend;
+
+    if !source.branch_exists(branch).await? {
+        return Err(coded!(
+            BranchNotFound,
+            "Branch '{}' not found in database '{}'",
+            branch,
+            database
+        ));
+    }
+    if source.supports_lifecycle() {
+        // Make sure a stopped local container is up before pg_dump connects
+        source.switch_to_branch(branch).await?;
+    }
+
+    let conn = source.get_connection_info(branch).await?;
+    let userinfo = match conn.password {
+        Some(ref password) => format!(
+            "{}:{}",
+            encode_userinfo(&conn.user),
+            encode_userinfo(password)
+        ),
+        None => encode_userinfo(&conn.user),
+    };
+    Ok(format!(
+        "postgresql://{}@{}/{}",
+        userinfo,
+        host_port(&conn.host, conn.port),
+        conn.database
+    ))
+}
+
+fn connection_uri(conn: &backends::ConnectionInfo) -> String {
+    conn.connection_string.clone().unwrap_or_else(|| {
+        format!(
+            "postgresql://{}@{}/{}",
+            conn.user,
+            host_port(&conn.host, conn.port),
+            conn.database
+        )
+    })
+}
+
+/// Hand the terminal over to `command`. On Unix it replaces this process, so
+/// Ctrl-C and the exit status are its own; elsewhere it runs to completion
+/// and its exit status becomes ours. Only returns an error if it couldn't
+/// be started, or on Unix when it did.
+fn exec_replacing(mut command: std::process::Command) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(command.exec())
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command.status()?;
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
         }
-        _ => unreachable!(),
+        Ok(())
     }
+}
 
-    Ok(())
+fn connection_env_lines(conn: &backends::ConnectionInfo) -> Vec<String> {
+    let mut lines = vec![
+        format!("DATABASE_HOST={}", conn.host),
+        format!("DATABASE_PORT={}", conn.port),
+        format!("DATABASE_NAME={}", conn.database),
+        format!("DATABASE_USER={}", conn.user),
+    ];
+    if let Some(ref password) = conn.password {
+        lines.push(format!("DATABASE_PASSWORD={}", password));
+    }
+    if let Some(ref uri) = conn.connection_string {
+        lines.push(format!("DATABASE_URL={}", uri));
+    }
+    lines
 }
 
-/// Run configuration and environment checks as part of `doctor`.
-fn run_doctor_pre_checks(config: &Config, config_path: &Option<std::path::PathBuf>) {
-    println!("General:");
+#[allow(clippy::too_many_arguments)]
+async fn handle_worktree_add(
+    config: &Config,
+    backend: &dyn backends::DatabaseBranchingBackend,
+    path: &str,
+    branch: &str,
+    base: Option<&str>,
+    from: Option<&str>,
+    env_file: &str,
+    json_output: bool,
+) -> Result<()> {
+    // The database branch is named the way the Git hook would name it
+    let db_branch = config.get_normalized_branch_name(branch);
+    // Fail before touching Git if the database branch can't be created
+    check_branch_name(backend, &db_branch)?;
+
+    let git_repo = GitRepository::new(".")?;
+    let main_dir = match git_repo.get_main_worktree_dir() {
+        Some(dir) => dir,
+        None => git_repo.get_repo_root().to_path_buf(),
+    };
 
-    // Config file
-    match config_path {
-        Some(path) => println!("  [OK] Config file: {}", path.display()),
-        None => println!("  [WARN] Config file: not found (using defaults)"),
+    let worktree_dir = std::env::current_dir()?.join(path);
+    git_repo.add_worktree(&worktree_dir, branch, base)?;
+    if !json_output {
+        println!(
+            "Created worktree at {} ({})",
+            worktree_dir.display(),
+            branch
+        );
     }
 
-    // Git repository
-    match GitRepository::new(".") {
-        Ok(_) => println!("  [OK] Git repository: detected"),
-        Err(_) => println!("  [FAIL] Git repository: not found"),
-    }
+    // Everything below runs inside the new worktree, like `worktree-setup` would
+    std::env::set_current_dir(&worktree_dir)
+        .with_context(|| format!("Failed to enter {}", worktree_dir.display()))?;
+    copy_worktree_files(config, main_dir.to_str().unwrap_or(""), false)?;
 
-    // Git hooks
-    let hooks_dir = std::path::Path::new(".git/hooks");
-    let has_hooks = if hooks_dir.exists() {
-        let post_checkout = hooks_dir.join("post-checkout");
-        let post_merge = hooks_dir.join("post-merge");
-        if let Ok(git_repo) = GitRepository::new(".") {
-            (post_checkout.exists() && git_repo.is_pgbranch_hook(&post_checkout).unwrap_or(false))
-                || (post_merge.exists() && git_repo.is_pgbranch_hook(&post_merge).unwrap_or(false))
-        } else {
-            post_checkout.exists() || post_merge.exists()
-        }
+    let created = !backend.branch_exists(&db_branch).await?;
+    let info = if created {
+        backend.create_branch(&db_branch, from).await?
     } else {
-        false
+        backend.switch_to_branch(&db_branch).await?
     };
-    if has_hooks {
-        println!("  [OK] Git hooks: installed");
-    } else {
-        println!("  [WARN] Git hooks: not installed (run 'pgbranch install-hooks')");
+    let conn = backend.get_connection_info(&db_branch).await?;
+
+    let env_path = worktree_dir.join(env_file);
+    let mut env_content = connection_env_lines(&conn).join("\n");
+    env_content.push('\n');
+    std::fs::write(&env_path, env_content)
+        .with_context(|| format!("Failed to write {}", env_path.display()))?;
+
+    // Track the branch for the worktree's own project state
+    let worktree_config = worktree_dir.join(".pgbranch.yml");
+    if worktree_config.exists() {
+        if let Ok(mut state) = LocalStateManager::new() {
+            let _ = state.set_current_branch(&worktree_config, Some(db_branch.clone()));
+        }
     }
 
-    // Branch filter regex
-    if let Some(ref regex_pattern) = config.git.branch_filter_regex {
-        match regex::Regex::new(regex_pattern) {
-            Ok(_) => println!("  [OK] Branch filter regex: valid"),
-            Err(e) => println!("  [FAIL] Branch filter regex: {}", e),
+    if json_output {
+        print_json(&serde_json::json!({
+            "worktree": worktree_dir,
+            "branch": info,
+            "created": created,
+            "env_file": env_path,
+        }))?;
+    } else {
+        if created {
+            println!("Created database branch: {}", info.name);
+        } else {
+            println!("Using existing database branch: {}", info.name);
         }
+        println!("Wrote connection variables to {}", env_path.display());
     }
 
-    println!();
+    if !config.post_commands.is_empty() {
+        let event = if created {
+            PostCommandEvent::Create
+        } else {
+            PostCommandEvent::Switch
+        };
+        PostCommandExecutor::new(config, &db_branch, event)?
+            .with_connection_info(&conn, backend.backend_type())
+            .execute_all_post_commands()
+            .await?;
+    }
+
+    Ok(())
 }
 
-fn copy_worktree_files(config: &Config, main_worktree_dir: &str) -> Result<()> {
-    let copy_files = match config.worktree {
-        Some(ref wt) => &wt.copy_files,
-        None => return Ok(()),
-    };
+fn refresh_worktree_files(config: &Config) -> Result<()> {
+    let git_repo = GitRepository::new(".")?;
 
-    let main_dir = std::path::Path::new(main_worktree_dir);
-    let current_dir = std::env::current_dir()?;
+    if !git_repo.is_worktree() {
+        anyhow::bail!(
+            "Not inside a Git worktree. Use this command from within a worktree directory."
+        );
+    }
 
-    for file in copy_files {
-        let source = main_dir.join(file);
-        let target = current_dir.join(file);
+    let main_dir = git_repo
+        .get_main_worktree_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not determine main worktree directory"))?;
 
-        if source.exists() && !target.exists() {
-            if let Some(parent) = target.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            std::fs::copy(&source, &target)?;
-            println!("Copied {} from main worktree", file);
-        }
-    }
+    copy_worktree_files(config, main_dir.to_str().unwrap_or(""), true)?;
+    println!("Worktree files are up to date");
     Ok(())
 }
 
@@ -1328,6 +6451,19 @@ async fn handle_worktree_setup(
     local_state: &mut Option<LocalStateManager>,
     config_path: &Option<std::path::PathBuf>,
 ) -> Result<()> {
+    let main_dir = main_worktree_dir()?;
+
+    // Copy files from main worktree
+    copy_worktree_files(config, main_dir.to_str().unwrap_or(""), false)?;
+
+    // Run normal git-hook logic to create/switch DB branch
+    handle_git_hook(config, db_manager, local_state, config_path, false, None).await?;
+
+    Ok(())
+}
+
+/// Directory of the main worktree, when run from inside a linked worktree.
+fn main_worktree_dir() -> Result<PathBuf> {
     let git_repo = GitRepository::new(".")?;
 
     if !git_repo.is_worktree() {
@@ -1336,19 +6472,461 @@ async fn handle_worktree_setup(
         );
     }
 
-    let main_dir = git_repo
+    git_repo
         .get_main_worktree_dir()
-        .ok_or_else(|| anyhow::anyhow!("Could not determine main worktree directory"))?;
+        .ok_or_else(|| anyhow::anyhow!("Could not determine main worktree directory"))
+}
 
-    // Copy files from main worktree
-    copy_worktree_files(config, main_dir.to_str().unwrap_or(""))?;
+/// Run a Git hook for every project in the repository, one after the other
+/// under one hook lock: the project around the current directory here, and
+/// each other `.pgbranch.yml` in a child process started from its directory.
+async fn handle_git_hook_for_all_projects(
+    cmd: Commands,
+    json_output: bool,
+    non_interactive: bool,
+    database_name: Option<&str>,
+) -> Result<()> {
+    let Commands::GitHook {
+        worktree,
+        main_worktree_dir,
+        event,
+        ..
+    } = cmd
+    else {
+        unreachable!("not a git-hook command");
+    };
 
-    // Run normal git-hook logic to create/switch DB branch
-    handle_git_hook(config, db_manager, local_state, config_path, false, None).await?;
+    let git_repo = match GitRepository::new(".") {
+        Ok(git_repo) => git_repo,
+        // A standalone project needn't be in a repository at all
+        Err(_)
+            if Config::load_effective_config_with_path_info()?
+                .0
+                .get_merged_config()
+                .standalone =>
+        {
+            log::debug!("Standalone project, ignoring the Git hook");
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    // Overlapping checkouts queue up here; only the latest one switches
+    let _hook_lock = if event == "post-merge" {
+        None
+    } else {
+        match hook_lock::acquire(&git_repo).await? {
+            Some(lock) => Some(lock),
+            None => return Ok(()),
+        }
+    };
+
+    let own_config = Config::find_config_file()?;
+    let others: Vec<PathBuf> = Config::find_all_config_files()?
+        .into_iter()
+        .filter(|path| Some(path) != own_config.as_ref())
+        .collect();
+
+    let mut result = Ok(());
+    if own_config.is_some() || others.is_empty() {
+        let own = Commands::GitHook {
+            worktree,
+            main_worktree_dir: main_worktree_dir.clone(),
+            event: event.clone(),
+            explain: false,
+            nested: true,
+        };
+        result = Box::pin(handle_command(
+            own,
+            json_output,
+            non_interactive,
+            database_name,
+        ))
+        .await;
+    }
+
+    let exe = std::env::current_exe().context("Failed to find the pgbranch executable")?;
+    for config_path in others {
+        let Some(dir) = config_path.parent() else {
+            continue;
+        };
+        let mut child = tokio::process::Command::new(&exe);
+        child
+            .current_dir(dir)
+            .args(["git-hook", "--nested", "--event", &event]);
+        if non_interactive {
+            child.arg("--non-interactive");
+        }
+        if worktree {
+            child.arg("--worktree");
+            if let Some(ref main_dir) = main_worktree_dir {
+                let relative = dir.strip_prefix(git_repo.get_repo_root()).unwrap_or(dir);
+                child
+                    .arg("--main-worktree-dir")
+                    .arg(std::path::Path::new(main_dir).join(relative));
+            }
+        }
+        match child.status().await {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!(
+                "⚠️  pgbranch hook for {} failed ({})",
+                dir.display(),
+                status
+            ),
+            Err(e) => eprintln!(
+                "⚠️  Failed to run the pgbranch hook for {}: {}",
+                dir.display(),
+                e
+            ),
+        }
+    }
+    result
+}
+
+/// `pgbranch projects`: every config file in the repository, with its
+/// databases and current branch. The one commands here use is marked.
+fn list_projects(json_output: bool) -> Result<()> {
+    let configs = Config::find_all_config_files()?;
+    let active = Config::find_config_file()?;
+    let root = GitRepository::new(".")
+        .ok()
+        .map(|repo| repo.get_repo_root().to_path_buf());
+    let state = LocalStateManager::new().ok();
+
+    let mut projects = Vec::new();
+    for config_path in &configs {
+        let dir = config_path.parent().unwrap_or(config_path);
+        let relative = root
+            .as_deref()
+            .and_then(|root| dir.strip_prefix(root).ok())
+            .map(|p| p.display().to_string())
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| ".".to_string());
+        let databases = match state.as_ref().and_then(|s| s.get_backends(config_path)) {
+            Some(backends) => Ok(backends),
+            None => Config::from_file(config_path).map(|config| config.resolve_backends()),
+        };
+        projects.push(serde_json::json!({
+            "path": relative,
+            "config": config_path,
+            "active": Some(config_path) == active.as_ref(),
+            "databases": databases.as_ref().ok().map(|backends| backends
+                .iter()
+                .map(|b| serde_json::json!({ "name": b.name, "type": b.backend_type }))
+                .collect::<Vec<_>>()),
+            "error": databases.as_ref().err().map(|e| format!("{:#}", e)),
+            "current_branch": state.as_ref().and_then(|s| s.get_current_branch(config_path)),
+        }));
+    }
+
+    if json_output {
+        print_json(&projects)?;
+        return Ok(());
+    }
+    if projects.is_empty() {
+        println!("No pgbranch projects found. Run 'pgbranch init' to create one.");
+        return Ok(());
+    }
+    let width = projects
+        .iter()
+        .map(|p| p["path"].as_str().unwrap_or_default().len())
+        .max()
+        .unwrap_or(0);
+    for project in &projects {
+        let marker = if project["active"] == true { "*" } else { " " };
+        let databases = match project["databases"].as_array() {
+            Some(databases) if databases.is_empty() => "no databases".to_string(),
+            Some(databases) => databases
+                .iter()
+                .map(|d| {
+                    format!(
+                        "{} ({})",
+                        d["name"].as_str().unwrap_or_default(),
+                        d["type"].as_str().unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => format!(
+                "invalid config: {}",
+                project["error"].as_str().unwrap_or_default()
+            ),
+        };
+        let branch = project["current_branch"]
+            .as_str()
+            .map(|b| format!("  [on {}]", b))
+            .unwrap_or_default();
+        println!(
+            "{} {:width$}  {}{}",
+            marker,
+            project["path"].as_str().unwrap_or_default(),
+            databases,
+            branch,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Why `git.paths` keeps the hook from acting on `branch_name`: none of the
+/// files the branch changed since it forked from the main branch match them.
+fn paths_skip_reason(
+    config: &Config,
+    git_repo: &GitRepository,
+    branch_name: &str,
+) -> Option<String> {
+    if config.git.paths.is_empty() || branch_name == config.git.main_branch {
+        return None;
+    }
+    match git_repo.changed_files_since(&config.git.main_branch) {
+        Ok(Some(files)) if !config.touches_paths(&files) => Some(format!(
+            "none of the {} file(s) changed since '{}' are under git.paths",
+            files.len(),
+            config.git.main_branch
+        )),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!(
+                "Couldn't list the files this branch changed, ignoring git.paths: {:#}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// `git-hook --explain`: what the post-checkout hook would do for the current
+/// Git branch and why, without switching, creating or running anything.
+async fn explain_git_hook(
+    effective_config: &EffectiveConfig,
+    config: &Config,
+    database_name: Option<&str>,
+    json_output: bool,
+) -> Result<()> {
+    if config.standalone {
+        let reason = "the project is standalone, without Git integration";
+        if json_output {
+            print_json(&serde_json::json!({
+                "git_branch": null,
+                "action": "skip",
+                "reason": reason,
+            }))?;
+        } else {
+            println!("Action: nothing, {}", reason);
+        }
+        return Ok(());
+    }
+    let git_repo = GitRepository::new(".")?;
+    let git_branch = git_repo.get_current_branch()?;
+
+    let skip_reason = if effective_config.is_disabled() {
+        Some("pgbranch is disabled by configuration".to_string())
+    } else if let Some(ref branch) = git_branch {
+        if effective_config.is_current_branch_disabled()
+            || effective_config.is_branch_disabled(branch)
+        {
+            Some("the branch is disabled by local configuration or environment".to_string())
+        } else if effective_config.should_skip_hooks() {
+            Some("Git hooks are disabled by configuration".to_string())
+        } else {
+            config
+                .hook_skip_reason(branch, database_name)
+                .or_else(|| paths_skip_reason(config, &git_repo, branch))
+        }
+    } else {
+        Some("HEAD is not on a branch".to_string())
+    };
+
+    let (Some(git_branch), None) = (git_branch.as_deref(), skip_reason.as_deref()) else {
+        let reason = skip_reason.unwrap_or_default();
+        if json_output {
+            print_json(&serde_json::json!({
+                "git_branch": git_branch,
+                "action": "skip",
+                "reason": reason,
+            }))?;
+        } else {
+            println!("Git branch: {}", git_branch.as_deref().unwrap_or("(none)"));
+            println!("Action: nothing, {}", reason);
+        }
+        return Ok(());
+    };
+
+    // The legacy hook switches the template database to a "_main" marker for
+    // the main branch; --database always goes through the backend
+    let legacy_main = database_name.is_none() && git_branch == config.git.main_branch;
+    let branch_name = if legacy_main {
+        "_main".to_string()
+    } else {
+        config.get_normalized_branch_name(git_branch)
+    };
+
+    let (backend_label, exists, db_name) = match database_name {
+        Some(name) => match backends::factory::resolve_backend(config, Some(name)).await {
+            Ok(named) => {
+                let exists = named.backend.branch_exists(&branch_name).await;
+                let db_name = match exists {
+                    Ok(true) => named
+                        .backend
+                        .get_connection_info(&branch_name)
+                        .await
+                        .ok()
+                        .map(|conn| conn.database),
+                    _ => None,
+                };
+                (
+                    format!("{} ({})", named.name, named.backend.backend_type()),
+                    exists,
+                    db_name,
+                )
+            }
+            Err(e) => (name.to_string(), Err(e), None),
+        },
+        None => {
+            #[cfg(feature = "backend-postgres-template")]
+            let exists = if legacy_main {
+                Ok(true)
+            } else {
+                DatabaseManager::new(config.clone())
+                    .list_database_branches()
+                    .await
+                    .map(|branches| branches.contains(&branch_name))
+            };
+            #[cfg(not(feature = "backend-postgres-template"))]
+            let exists: Result<bool> = Err(anyhow::anyhow!(
+                "the legacy hook requires the postgres-template backend"
+            ));
+            (
+                "postgres_template (legacy hook)".to_string(),
+                exists,
+                Some(config.get_database_name(&branch_name)),
+            )
+        }
+    };
+
+    let action = match exists {
+        _ if legacy_main => "switch to the main database".to_string(),
+        Ok(true) => format!("switch to branch '{}'", branch_name),
+        Ok(false) => format!("create branch '{}'", branch_name),
+        Err(_) => format!("create or switch to branch '{}'", branch_name),
+    };
 
+    // Post-commands see a create event if the switch had to create the branch
+    let describe = |event| PostCommandExecutor::new(config, &branch_name, event)?.describe();
+    let post_commands: Vec<(String, Option<String>)> = match exists {
+        Ok(branch_exists) => describe(if branch_exists {
+            PostCommandEvent::Switch
+        } else {
+            PostCommandEvent::Create
+        })?
+        .into_iter()
+        .map(|(command, skip)| (command, skip.map(|reason| format!("skipped: {}", reason))))
+        .collect(),
+        Err(_) => describe(PostCommandEvent::Switch)?
+            .into_iter()
+            .zip(describe(PostCommandEvent::Create)?)
+            .map(|((command, on_switch), (_, on_create))| {
+                let note = match (on_switch, on_create) {
+                    (Some(reason), Some(_)) => Some(format!("skipped: {}", reason)),
+                    (Some(_), None) => Some("only if the branch is created".to_string()),
+                    (None, Some(_)) => Some("only if the branch already exists".to_string()),
+                    (None, None) => None,
+                };
+                (command, note)
+            })
+            .collect(),
+    };
+
+    if json_output {
+        let commands: Vec<serde_json::Value> = post_commands
+            .iter()
+            .map(|(command, note)| serde_json::json!({ "command": command, "note": note }))
+            .collect();
+        print_json(&serde_json::json!({
+            "git_branch": git_branch,
+            "action": match exists {
+                _ if legacy_main => "switch",
+                Ok(true) => "switch",
+                Ok(false) => "create",
+                Err(_) => "create_or_switch",
+            },
+            "backend": backend_label,
+            "branch": branch_name,
+            "database_name": db_name,
+            "error": exists.as_ref().err().map(|e| format!("{:#}", e)),
+            "post_commands": commands,
+        }))?;
+        return Ok(());
+    }
+
+    println!("Git branch: {}", git_branch);
+    println!("Action: {}", action);
+    if let Err(ref e) = exists {
+        println!("  (couldn't check whether the branch exists: {:#})", e);
+    }
+    println!("Backend: {}", backend_label);
+    if let Some(db_name) = db_name {
+        println!("Database: {}", db_name);
+    }
+    if post_commands.is_empty() {
+        println!("Post-commands: none");
+    } else {
+        println!("Post-commands:");
+        for (command, note) in &post_commands {
+            match note {
+                Some(note) => println!("  - {} ({})", command, note),
+                None => println!("  - {}", command),
+            }
+        }
+    }
     Ok(())
 }
 
+/// The post-checkout hook for a database named with --database: switch it to
+/// the checked-out branch (creating it if needed) through its backend.
+async fn handle_database_git_hook(
+    config: &Config,
+    database_name: &str,
+    json_output: bool,
+    non_interactive: bool,
+    local_state: &mut Option<LocalStateManager>,
+    config_path: &Option<std::path::PathBuf>,
+) -> Result<()> {
+    let git_repo = GitRepository::new(".")?;
+    let Some(current_git_branch) = git_repo.get_current_branch()? else {
+        return Ok(());
+    };
+    log::info!(
+        "Git hook triggered for branch {} in database '{}'",
+        current_git_branch,
+        database_name
+    );
+
+    if let Some(reason) = config
+        .hook_skip_reason(&current_git_branch, Some(database_name))
+        .or_else(|| paths_skip_reason(config, &git_repo, &current_git_branch))
+    {
+        log::info!("Git branch {} skipped: {}", current_git_branch, reason);
+        return Ok(());
+    }
+
+    let switch = Commands::Switch {
+        branch_name: Some(current_git_branch),
+        template: false,
+        dry_run: false,
+        all_databases: false,
+    };
+    handle_all_databases_command(
+        switch,
+        config,
+        json_output,
+        local_state,
+        config_path,
+        Some(database_name),
+        non_interactive,
+    )
+    .await
+}
+
 #[cfg(feature = "backend-postgres-template")]
 async fn handle_git_hook(
     config: &mut Config,
@@ -1361,7 +6939,7 @@ async fn handle_git_hook(
     // If called from a worktree, copy files first
     if worktree {
         if let Some(ref main_dir) = main_worktree_dir {
-            copy_worktree_files(config, main_dir)?;
+            copy_worktree_files(config, main_dir, false)?;
         }
     }
 
@@ -1377,7 +6955,9 @@ async fn handle_git_hook(
                 handle_switch_to_main(config, db_manager, local_state, config_path).await?;
             } else {
                 // For other branches, check if we should create them and switch
-                if config.should_create_branch(&current_git_branch) {
+                if let Some(reason) = paths_skip_reason(config, &git_repo, &current_git_branch) {
+                    log::info!("Git branch {} skipped: {}", current_git_branch, reason);
+                } else if config.should_create_branch(&current_git_branch) {
                     handle_switch_command(
                         config,
                         db_manager,
@@ -1404,6 +6984,71 @@ async fn handle_git_hook(
     Ok(())
 }
 
+#[cfg(feature = "backend-postgres-template")]
+async fn handle_post_merge_hook(
+    config: &Config,
+    db_manager: &DatabaseManager,
+    non_interactive: bool,
+) -> Result<()> {
+    let git_repo = GitRepository::new(".")?;
+    let current_git_branch = git_repo.get_current_branch()?;
+
+    // Merges into feature branches keep using the same database branch
+    if current_git_branch.as_deref() != Some(config.git.main_branch.as_str()) {
+        log::debug!("post-merge outside the main branch, nothing to do");
+        return Ok(());
+    }
+
+    let post_merge = match config.git.post_merge {
+        Some(ref post_merge) if !post_merge.is_empty() => post_merge,
+        _ => {
+            log::debug!("No post-merge actions configured");
+            return Ok(());
+        }
+    };
+
+    if post_merge.prompt {
+        use std::io::IsTerminal;
+
+        if non_interactive || !std::io::stdin().is_terminal() {
+            println!("⏭️  Skipping post-merge actions (confirmation required)");
+            return Ok(());
+        }
+        let confirmed = inquire::Confirm::new(&format!(
+            "'{}' was updated. Run post-merge actions?",
+            config.git.main_branch
+        ))
+        .with_default(true)
+        .prompt()?;
+        if !confirmed {
+            return Ok(());
+        }
+    }
+
+    if !post_merge.commands.is_empty() {
+        println!("🔀 Running post-merge commands on main database");
+        let mut merge_config = config.clone();
+        merge_config.post_commands = post_merge.commands.clone();
+        PostCommandExecutor::new(&merge_config, "_main", PostCommandEvent::Merge)?
+            .execute_all_post_commands()
+            .await?;
+    }
+
+    if post_merge.refresh_branches {
+        let branches = db_manager.list_database_branches().await?;
+        for branch in &branches {
+            db_manager.drop_database_branch(branch).await?;
+            db_manager.create_database_branch(branch).await?;
+            println!("🔄 Refreshed database branch: {}", branch);
+        }
+        if branches.is_empty() {
+            println!("ℹ️  No database branches to refresh");
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "backend-postgres-template")]
 async fn handle_interactive_switch(
     config: &mut Config,
@@ -1496,6 +7141,7 @@ async fn handle_interactive_switch(
     Ok(())
 }
 
+#[cfg(feature = "backend-postgres-template")]
 #[derive(Clone)]
 struct BranchItem {
     name: String,
@@ -1503,6 +7149,7 @@ struct BranchItem {
     is_current: bool,
 }
 
+#[cfg(feature = "backend-postgres-template")]
 fn run_interactive_selector(items: Vec<BranchItem>) -> Result<String, inquire::InquireError> {
     use inquire::Select;
 
@@ -1565,12 +7212,16 @@ async fn handle_switch_command(
     set_current_branch(local_state, config_path, Some(normalized_branch.clone()))?;
 
     // Try database operations (non-fatal if they fail)
+    let mut created = false;
     match db_manager.list_database_branches().await {
         Ok(db_branches) => {
             if !db_branches.contains(&normalized_branch) {
                 println!("📦 Creating database branch: {}", normalized_branch);
                 match db_manager.create_database_branch(&normalized_branch).await {
-                    Ok(_) => println!("✅ Created database branch: {}", normalized_branch),
+                    Ok(_) => {
+                        created = true;
+                        println!("✅ Created database branch: {}", normalized_branch)
+                    }
                     Err(e) => {
                         println!("⚠️  Failed to create database branch: {}", e);
                         println!(
@@ -1591,8 +7242,18 @@ async fn handle_switch_command(
     // Execute post-commands
     if !config.post_commands.is_empty() {
         println!("🔧 Executing post-commands for branch switch...");
-        let executor = PostCommandExecutor::new(config, &normalized_branch)?;
-        executor.execute_all_post_commands().await?;
+        // A switch that had to create the branch counts as a create event
+        let event = if created {
+            PostCommandEvent::Create
+        } else {
+            PostCommandEvent::Switch
+        };
+        let backend = PostgresTemplateBackend::new(config).await?;
+        let conn = backend.get_connection_info(&normalized_branch).await?;
+        PostCommandExecutor::new(config, &normalized_branch, event)?
+            .with_connection_info(&conn, backend.backend_type())
+            .execute_all_post_commands()
+            .await?;
     }
 
     Ok(())
@@ -1620,8 +7281,12 @@ async fn handle_switch_to_main(
     // Execute post-commands with main branch
     if !config.post_commands.is_empty() {
         println!("🔧 Executing post-commands for main switch...");
-        let executor = PostCommandExecutor::new(config, main_name)?;
-        executor.execute_all_post_commands().await?;
+        let backend = PostgresTemplateBackend::new(config).await?;
+        let conn = backend.get_connection_info(main_name).await?;
+        PostCommandExecutor::new(config, main_name, PostCommandEvent::Switch)?
+            .with_connection_info(&conn, backend.backend_type())
+            .execute_all_post_commands()
+            .await?;
     }
 
     Ok(())
@@ -1639,6 +7304,43 @@ fn get_current_branch(
     }
 }
 
+/// Refuse to delete the branch the working directory is on, unless `force`.
+/// Returns whether it is, so the caller can switch the record back to main.
+fn check_deleting_current_branch(
+    local_state: &Option<LocalStateManager>,
+    config_path: &Option<std::path::PathBuf>,
+    branch_name: &str,
+    force: bool,
+) -> Result<bool> {
+    if get_current_branch(local_state, config_path).as_deref() != Some(branch_name) {
+        return Ok(false);
+    }
+    if !force {
+        return Err(coded!(
+            ConfirmationRequired,
+            "'{}' is the branch this working directory is on; switch away first or pass --force",
+            branch_name
+        ));
+    }
+    eprintln!(
+        "Warning: deleting '{}', the branch this working directory is on; switching back to main",
+        branch_name
+    );
+    Ok(true)
+}
+
+fn get_previous_branch(
+    local_state: &Option<LocalStateManager>,
+    config_path: &Option<std::path::PathBuf>,
+) -> Option<String> {
+    if let (Some(state_manager), Some(path)) = (local_state, config_path) {
+        state_manager.get_previous_branch(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "backend-postgres-template")]
 fn get_current_branch_with_default(
     local_state: &Option<LocalStateManager>,
     config_path: &Option<std::path::PathBuf>,
@@ -1653,7 +7355,11 @@ fn get_current_branch_with_default(
     detect_default_current_branch(config)
 }
 
+#[cfg(feature = "backend-postgres-template")]
 fn detect_default_current_branch(config: &Config) -> Option<String> {
+    if config.standalone {
+        return Some("_main".to_string());
+    }
     // Try to get current Git branch to make intelligent default
     match GitRepository::new(".") {
         Ok(git_repo) => {
@@ -1764,7 +7470,10 @@ fn show_effective_config(effective_config: &EffectiveConfig) -> Result<()> {
         || effective_config.env_config.database_port.is_some()
         || effective_config.env_config.database_user.is_some()
         || effective_config.env_config.database_password.is_some()
-        || effective_config.env_config.database_prefix.is_some();
+        || effective_config.env_config.database_prefix.is_some()
+        || effective_config.env_config.backend_type.is_some()
+        || effective_config.env_config.database.is_some()
+        || effective_config.env_config.has_local_overrides();
 
     if !has_env_overrides {
         println!("  (none)");
@@ -1805,6 +7514,21 @@ fn show_effective_config(effective_config: &EffectiveConfig) -> Result<()> {
         if let Some(ref prefix) = effective_config.env_config.database_prefix {
             println!("  PGBRANCH_DATABASE_PREFIX: {}", prefix);
         }
+        if let Some(ref backend_type) = effective_config.env_config.backend_type {
+            println!("  PGBRANCH_BACKEND: {}", backend_type);
+        }
+        if let Some(ref database) = effective_config.env_config.database {
+            println!("  PGBRANCH_DATABASE: {}", database);
+        }
+        if let Some(ref image) = effective_config.env_config.local_image {
+            println!("  PGBRANCH_LOCAL_IMAGE: {}", image);
+        }
+        if let Some(ref data_root) = effective_config.env_config.data_root {
+            println!("  PGBRANCH_DATA_ROOT: {}", data_root);
+        }
+        if let Some(port) = effective_config.env_config.port_range_start {
+            println!("  PGBRANCH_PORT_RANGE_START: {}", port);
+        }
     }
 
     println!();
@@ -1812,7 +7536,7 @@ fn show_effective_config(effective_config: &EffectiveConfig) -> Result<()> {
     // Show local config overrides
     println!("📁 Local Config File Overrides:");
     if let Some(ref local_config) = effective_config.local_config {
-        println!("  ✅ Local config file found (.pgbranch.local.yml)");
+        println!("  ✅ Local config file found (.pgbranch.local.yml or .pgbranch.local.yml.enc)");
         if local_config.disabled.is_some()
             || local_config.disabled_branches.is_some()
             || local_config.database.is_some()
@@ -1,3 +1,4 @@
+use crate::backends::factory::BackendInfo;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::env;
@@ -8,6 +9,11 @@ use std::path::{Path, PathBuf};
 pub struct Config {
     #[serde(default, skip_serializing_if = "DatabaseConfig::is_default")]
     pub database: DatabaseConfig,
+    /// No Git integration at all: no hooks or worktrees, and branches are
+    /// only created and switched by hand
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub standalone: bool,
+    #[serde(default)]
     pub git: GitConfig,
     pub behavior: BehaviorConfig,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -20,6 +26,165 @@ pub struct Config {
     pub backends: Option<Vec<NamedBackendConfig>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub worktree: Option<WorktreeConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<NotificationsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditConfig>,
+    /// Standing branches that `pgbranch apply` creates and keeps in shape
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branches: Option<Vec<BranchDefinition>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<SigningConfig>,
+}
+
+/// Team-wide audit trail for destructive operations (delete, cleanup, destroy).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    pub webhook: String,
+}
+
+/// Signatures on the files pgbranch exports, checked on the ones it seeds
+/// branches from or imports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningConfig {
+    #[serde(default)]
+    pub method: SigningMethod,
+    /// Private key that exported files are signed with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Keys whose signatures are accepted: an `allowed_signers` file for
+    /// `ssh`, a public key for `minisign`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<String>,
+    /// Refuse files without a valid signature
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require: bool,
+}
+
+/// The tool that signs and verifies files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningMethod {
+    /// `ssh-keygen -Y`, with SSH keys
+    #[default]
+    Ssh,
+    Minisign,
+}
+
+/// A standing branch declared in `.pgbranch.yml` or `branches.yml`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BranchDefinition {
+    pub name: String,
+    /// Parent branch to clone from when the branch is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Data to load after creating the branch (PostgreSQL URL, file path or s3:// URL)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+    /// Recreate the branch once it's older than this, e.g. `12h` or `7d`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    /// Make new sessions default to read-only transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    /// Container limits (local backend)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<BranchResources>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BranchResources {
+    /// Memory limit, e.g. `512m` or `2g`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+}
+
+impl BranchDefinition {
+    pub fn ttl(&self) -> Result<Option<chrono::Duration>> {
+        self.ttl
+            .as_deref()
+            .map(|ttl| parse_age(ttl, &format!("ttl '{}' for branch '{}'", ttl, self.name)))
+            .transpose()
+    }
+}
+
+/// Parse an age like `90m`, `12h`, `7d` or `2w`; `what` names it in the error.
+pub fn parse_age(value: &str, what: &str) -> Result<chrono::Duration> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid {}: use a number followed by m, h, d or w (e.g. 7d)",
+            what
+        )
+    };
+    let trimmed = value.trim();
+    let unit = trimmed.chars().last().ok_or_else(invalid)?;
+    let amount: i64 = trimmed[..trimmed.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let duration = match unit {
+        'm' => chrono::Duration::try_minutes(amount),
+        'h' => chrono::Duration::try_hours(amount),
+        'd' => chrono::Duration::try_days(amount),
+        'w' => chrono::Duration::try_weeks(amount),
+        _ => None,
+    };
+    match duration {
+        Some(duration) if amount > 0 => Ok(duration),
+        _ => Err(invalid()),
+    }
+}
+
+impl BranchResources {
+    /// Memory limit in bytes.
+    pub fn memory_bytes(&self) -> Result<Option<i64>> {
+        let Some(memory) = self.memory.as_deref() else {
+            return Ok(None);
+        };
+        let memory = memory.trim().to_ascii_lowercase();
+        let invalid = || {
+            anyhow::anyhow!(
+                "Invalid memory limit '{}': use bytes or a number followed by k, m or g (e.g. 2g)",
+                memory
+            )
+        };
+        let (amount, multiplier) = match memory.strip_suffix('b').unwrap_or(&memory) {
+            m if m.ends_with('k') => (&m[..m.len() - 1], 1024.0),
+            m if m.ends_with('m') => (&m[..m.len() - 1], 1024.0 * 1024.0),
+            m if m.ends_with('g') => (&m[..m.len() - 1], 1024.0 * 1024.0 * 1024.0),
+            m => (m, 1.0),
+        };
+        let amount: f64 = amount.parse().map_err(|_| invalid())?;
+        if amount <= 0.0 {
+            return Err(invalid());
+        }
+        Ok(Some((amount * multiplier) as i64))
+    }
+
+    /// CPU limit in the billionths of a CPU Docker expects.
+    pub fn nano_cpus(&self) -> Result<Option<i64>> {
+        match self.cpus {
+            Some(cpus) if cpus <= 0.0 => {
+                anyhow::bail!("Invalid cpus limit '{}': must be above 0", cpus)
+            }
+            Some(cpus) => Ok(Some((cpus * 1e9) as i64)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct NotificationsConfig {
+    /// Show a desktop notification (notify-send on Linux, osascript on macOS)
+    #[serde(default)]
+    pub desktop: bool,
+    /// URL that receives a JSON `{"text": ...}` POST (Slack-compatible)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    /// Only notify about successful operations that took at least this long (default: 10)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_duration_secs: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +202,21 @@ pub struct NamedBackendConfig {
     pub dblab: Option<DBLabConfig>,
     #[serde(default, skip_serializing_if = "Option::is_none", alias = "xata_lite")]
     pub xata: Option<XataConfig>,
+    /// Which Git branches this database gets branches for, overriding `git`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_filter: Option<BranchFilterConfig>,
+}
+
+/// Per-database overrides of the `git` branch filters. Each one that is set
+/// replaces the `git` setting of the same name for that database.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BranchFilterConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_branches: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_branches: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_filter_regex: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,26 +237,165 @@ fn default_backend_type() -> String {
     "local".to_string()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LocalBackendConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
+    /// When to pull `image`: `if-not-present` (default), `always` or `never`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull_policy: Option<PullPolicy>,
+    /// Container engine: `docker` (default) or `podman`, rootless included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<ContainerEngine>,
+    /// Docker engine address (`unix:///path/docker.sock`, `tcp://host:2375`); found automatically if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker_host: Option<String>,
+    /// docker CLI context to use instead of the current one (`docker context ls`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker_context: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub data_root: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub storage: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub port_range_start: Option<u16>,
+    /// Address branch ports are published on: `127.0.0.1` (default), `::1`,
+    /// or `0.0.0.0`/`::` to reach branches from other machines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<std::net::IpAddr>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_user: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_password: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub postgres_db: Option<String>,
+    /// More databases to create in every branch, next to `postgres_db`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra_databases: Option<Vec<String>>,
+    /// Write per-branch entries to `~/.pgpass` (or `$PGPASSFILE`) and remove them on delete
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pgpass: Option<bool>,
+    /// Leave the password out of emitted connection info
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hide_password: Option<bool>,
+    /// Copy container logs to `logs/postgres.log` in each branch's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_logs: Option<bool>,
+    /// Create branch containers that restart with the engine and carry the
+    /// `pgbranch.protected` label, for prune filters to skip them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prune_protection: Option<bool>,
+    /// Keep projects in a `data_root` and state database shared by the users of this host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared: Option<bool>,
+    /// Keep the project/branch registry in a JSON document the team shares
+    /// (`s3://bucket/key.json`) instead of `state.db` under `data_root`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_store: Option<String>,
+    /// Run after cloning or seeding a branch; defaults to `[analyze]`, `[]` turns it off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Vec<MaintenanceTask>>,
+    /// Queries run once a branch is ready, so the first app requests find a warm cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<WarmupConfig>,
+    /// Space limits on the ZFS datasets of the project and its branches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zfs: Option<ZfsLimitsConfig>,
+    /// Retrying Docker API calls that fail for transient reasons
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker_retry: Option<DockerRetryConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WarmupConfig {
+    #[serde(default)]
+    pub queries: Vec<String>,
+    /// How many connections run the queries at the same time (default 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections: Option<usize>,
+}
+
+/// Sizes in ZFS notation (`50G`, `500M`); `none` clears a limit set earlier.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ZfsLimitsConfig {
+    /// Cap on the project's dataset, branches and snapshots included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<String>,
+    /// Pool space set aside for the project's dataset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reservation: Option<String>,
+    /// Cap on each branch's dataset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_quota: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DockerRetryConfig {
+    /// Tries per call, the first included (default 3; 1 turns retrying off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+    /// Seconds to wait for each try before giving up on it (default 60); image pulls aren't limited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// The container engine the local backend drives. Podman is driven through
+/// its Docker-compatible API socket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerEngine {
+    #[default]
+    Docker,
+    Podman,
+}
+
+impl ContainerEngine {
+    #[allow(dead_code)] // only used by the local backend
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Docker => "Docker",
+            Self::Podman => "Podman",
+        }
+    }
+}
+
+/// When the local backend pulls its PostgreSQL image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Pull only when the image isn't there yet
+    #[default]
+    IfNotPresent,
+    /// Pull before creating a container, to pick up a newer build of the tag
+    Always,
+    /// Never pull; fail when the image is missing (air-gapped machines)
+    Never,
+}
+
+impl PullPolicy {
+    #[allow(dead_code)] // only used by the local backend
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::IfNotPresent => "if-not-present",
+            Self::Always => "always",
+            Self::Never => "never",
+        }
+    }
+}
+
+/// A maintenance command run in each database of a freshly cloned or seeded branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceTask {
+    Analyze,
+    Vacuum,
+    Reindex,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NeonConfig {
+    /// Leave empty to use the key stored by `pgbranch auth login neon`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub api_key: String,
     pub project_id: String,
     #[serde(default = "default_neon_base_url")]
@@ -90,11 +409,15 @@ fn default_neon_base_url() -> String {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DBLabConfig {
     pub api_url: String,
+    /// Leave empty to use the token stored by `pgbranch auth login dblab`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub auth_token: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct XataConfig {
+    /// Leave empty to use the key stored by `pgbranch auth login xata`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub api_key: String,
     pub organization_id: String,
     pub project_id: String,
@@ -109,7 +432,48 @@ fn default_xata_base_url() -> String {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct WorktreeConfig {
     #[serde(default)]
-    pub copy_files: Vec<String>,
+    pub copy_files: Vec<WorktreeFile>,
+}
+
+/// A file brought over from the main worktree, either as a plain path
+/// (copied) or with an explicit strategy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WorktreeFile {
+    Path(String),
+    Detailed {
+        path: String,
+        #[serde(default)]
+        strategy: WorktreeFileStrategy,
+    },
+}
+
+impl WorktreeFile {
+    pub fn path(&self) -> &str {
+        match self {
+            WorktreeFile::Path(path) => path,
+            WorktreeFile::Detailed { path, .. } => path,
+        }
+    }
+
+    pub fn strategy(&self) -> WorktreeFileStrategy {
+        match self {
+            WorktreeFile::Path(_) => WorktreeFileStrategy::Copy,
+            WorktreeFile::Detailed { strategy, .. } => *strategy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorktreeFileStrategy {
+    /// Copy the file once
+    #[default]
+    Copy,
+    /// Symlink to the file in the main worktree
+    Symlink,
+    /// Copy the file, substituting template variables for the worktree's branch
+    Template,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -168,9 +532,38 @@ pub struct PostCommandConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub continue_on_error: Option<bool>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub condition: Option<String>,
+    pub condition: Option<PostCommandCondition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_on: Vec<PostCommandEvent>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub environment: Option<std::collections::HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Kill the command after this many seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Number of additional attempts after a failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<CaptureMode>,
+    /// Log file used with `capture: file` (default: `.pgbranch-post-commands.log`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_file: Option<String>,
+}
+
+/// How a post-command's output is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureMode {
+    /// Stream output straight to the terminal
+    Inherit,
+    /// Discard output unless the command fails
+    Quiet,
+    /// Append output to `capture_file`
+    File,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -178,15 +571,156 @@ pub struct ReplaceConfig {
     pub action: String, // Must be "replace"
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Target file, or a glob pattern matching several files
     pub file: String,
-    pub pattern: String,
+    /// Regex to replace (required unless `key` is set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
     pub replacement: String,
+    /// Dotted key path to set in a structured file, e.g. `database.url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// File format used with `key` (default: inferred from the extension)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ReplaceFormat>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub create_if_missing: Option<bool>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub continue_on_error: Option<bool>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub condition: Option<String>,
+    pub condition: Option<PostCommandCondition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_on: Vec<PostCommandEvent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+}
+
+/// Format of a file edited by a structured replace action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplaceFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ReplaceFormat {
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yml" | "yaml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+}
+
+impl PostCommand {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            PostCommand::Simple(_) => None,
+            PostCommand::Complex(config) => config.name.as_deref(),
+            PostCommand::Replace(config) => config.name.as_deref(),
+        }
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            PostCommand::Simple(_) => None,
+            PostCommand::Complex(config) => config.group.as_deref(),
+            PostCommand::Replace(config) => config.group.as_deref(),
+        }
+    }
+
+    pub fn depends_on(&self) -> &[String] {
+        match self {
+            PostCommand::Simple(_) => &[],
+            PostCommand::Complex(config) => &config.depends_on,
+            PostCommand::Replace(config) => &config.depends_on,
+        }
+    }
+
+    pub fn continue_on_error(&self) -> bool {
+        match self {
+            PostCommand::Simple(_) => false,
+            PostCommand::Complex(config) => config.continue_on_error.unwrap_or(false),
+            PostCommand::Replace(config) => config.continue_on_error.unwrap_or(false),
+        }
+    }
+}
+
+/// A post-command condition: either a `kind:value` expression string
+/// (e.g. `file_exists:manage.py`) or a structured set of checks that must all hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PostCommandCondition {
+    Expression(String),
+    Structured(ConditionConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ConditionConfig {
+    /// Backend type the command applies to (e.g. `local`, `neon`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Regex the branch name must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_matches: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_exists: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_exists: Option<String>,
+    /// Environment variable that must be set and non-empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_set: Option<String>,
+}
+
+impl std::fmt::Display for PostCommandCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostCommandCondition::Expression(expr) => write!(f, "{}", expr),
+            PostCommandCondition::Structured(config) => {
+                let mut parts = Vec::new();
+                if let Some(ref backend) = config.backend {
+                    parts.push(format!("backend:{}", backend));
+                }
+                if let Some(ref pattern) = config.branch_matches {
+                    parts.push(format!("branch_matches:{}", pattern));
+                }
+                if let Some(ref path) = config.file_exists {
+                    parts.push(format!("file_exists:{}", path));
+                }
+                if let Some(ref path) = config.dir_exists {
+                    parts.push(format!("dir_exists:{}", path));
+                }
+                if let Some(ref var) = config.env_set {
+                    parts.push(format!("env_set:{}", var));
+                }
+                write!(f, "{}", parts.join(" && "))
+            }
+        }
+    }
+}
+
+/// Operation that triggered the post-commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostCommandEvent {
+    Create,
+    Switch,
+    Merge,
+}
+
+impl PostCommandEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Switch => "switch",
+            Self::Merge => "merge",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -205,7 +739,47 @@ pub struct GitConfig {
         alias = "branch_filter_regex"
     )]
     pub branch_filter_regex: Option<String>,
+    /// Branch names or glob patterns (`feature/*`) the hooks ignore
     pub exclude_branches: Vec<String>,
+    /// If set, the hooks only act on branches matching one of these names or globs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_branches: Vec<String>,
+    /// If set, the hooks only act on branches that changed files matching one of these globs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_merge: Option<PostMergeConfig>,
+}
+
+/// Actions run by the post-merge hook after pulling into the main branch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PostMergeConfig {
+    /// Post-commands run against the main database (e.g. migrations)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<PostCommand>,
+    /// Recreate existing branch databases from the updated main database
+    #[serde(default)]
+    pub refresh_branches: bool,
+    /// Ask for confirmation before running anything
+    #[serde(default)]
+    pub prompt: bool,
+}
+
+impl PostMergeConfig {
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty() && !self.refresh_branches
+    }
+}
+
+/// Whether `name` (a branch or a file path) is `pattern` or matches it as a
+/// glob, where `*` stays within one path segment (`feature/*`) and `**`
+/// spans several.
+pub fn matches_glob(name: &str, pattern: &str) -> bool {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    name == pattern || glob::Pattern::new(pattern).is_ok_and(|p| p.matches_with(name, options))
 }
 
 fn default_true() -> bool {
@@ -222,6 +796,37 @@ pub struct BehaviorConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_branches: Option<usize>,
     pub naming_strategy: NamingStrategy,
+    /// Create missing branches from the default parent when `pgbranch connection` asks for them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_create_on_connect: bool,
+    /// With `auto_cleanup`, also delete branches older than this (e.g. `30d`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_branch_age: Option<String>,
+    /// Branches cleanup never deletes, besides `main`, `master` and `git.main_branch`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected_branches: Vec<String>,
+    /// `pgbranch prune` deletes branches unused for this long (e.g. `7d`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_ttl: Option<String>,
+    /// Prune expired branches before the commands that go on to use a branch
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto_prune: bool,
+}
+
+impl BehaviorConfig {
+    pub fn max_branch_age(&self) -> Result<Option<chrono::Duration>> {
+        self.max_branch_age
+            .as_deref()
+            .map(|age| parse_age(age, &format!("max_branch_age '{}'", age)))
+            .transpose()
+    }
+
+    pub fn branch_ttl(&self) -> Result<Option<chrono::Duration>> {
+        self.branch_ttl
+            .as_deref()
+            .map(|ttl| parse_age(ttl, &format!("branch_ttl '{}'", ttl)))
+            .transpose()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -244,6 +849,7 @@ pub struct LocalConfig {
     pub disabled: Option<bool>,
     pub disabled_branches: Option<Vec<String>>,
     pub worktree: Option<WorktreeConfig>,
+    pub notifications: Option<NotificationsConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -273,6 +879,9 @@ pub struct LocalGitConfig {
     pub auto_create_branch_filter: Option<String>,
     pub branch_filter_regex: Option<String>,
     pub exclude_branches: Option<Vec<String>>,
+    pub include_branches: Option<Vec<String>>,
+    pub paths: Option<Vec<String>>,
+    pub post_merge: Option<PostMergeConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -280,6 +889,11 @@ pub struct LocalBehaviorConfig {
     pub auto_cleanup: Option<bool>,
     pub max_branches: Option<usize>,
     pub naming_strategy: Option<NamingStrategy>,
+    pub auto_create_on_connect: Option<bool>,
+    pub max_branch_age: Option<String>,
+    pub protected_branches: Option<Vec<String>>,
+    pub branch_ttl: Option<String>,
+    pub auto_prune: Option<bool>,
 }
 
 // Environment variable configuration
@@ -297,6 +911,11 @@ pub struct EnvConfig {
     pub database_user: Option<String>,
     pub database_password: Option<String>,
     pub database_prefix: Option<String>,
+    pub backend_type: Option<String>,
+    pub database: Option<String>,
+    pub local_image: Option<String>,
+    pub data_root: Option<String>,
+    pub port_range_start: Option<u16>,
 }
 
 // The effective configuration after merging all sources
@@ -310,6 +929,22 @@ pub struct EffectiveConfig {
     pub current_branch_disabled: bool,
 }
 
+impl Default for GitConfig {
+    fn default() -> Self {
+        GitConfig {
+            auto_create_on_branch: true,
+            auto_switch_on_branch: true,
+            main_branch: "main".to_string(),
+            auto_create_branch_filter: None,
+            branch_filter_regex: None,
+            exclude_branches: vec!["main".to_string(), "master".to_string()],
+            include_branches: vec![],
+            paths: vec![],
+            post_merge: None,
+        }
+    }
+}
+
 impl Default for DatabaseConfig {
     fn default() -> Self {
         DatabaseConfig {
@@ -350,24 +985,27 @@ impl Default for Config {
     fn default() -> Self {
         Config {
             database: DatabaseConfig::default(),
-            git: GitConfig {
-                auto_create_on_branch: true,
-                auto_switch_on_branch: true,
-                main_branch: "main".to_string(),
-                auto_create_branch_filter: None,
-                branch_filter_regex: None,
-                exclude_branches: vec!["main".to_string(), "master".to_string()],
-            },
+            standalone: false,
+            git: GitConfig::default(),
             behavior: BehaviorConfig {
                 auto_cleanup: false,
                 max_branches: Some(10),
                 naming_strategy: NamingStrategy::Prefix,
+                auto_create_on_connect: false,
+                max_branch_age: None,
+                protected_branches: vec![],
+                branch_ttl: None,
+                auto_prune: false,
             },
             post_commands: vec![],
             current_branch: None, // Deprecated field, always None for new configs
             backend: None,
             backends: None,
             worktree: None,
+            notifications: None,
+            audit: None,
+            branches: None,
+            signing: None,
         }
     }
 }
@@ -429,6 +1067,30 @@ impl Config {
         Ok(None)
     }
 
+    /// Every config file in the Git repository around the current directory,
+    /// for monorepos with one per project. Outside a repository, just the one
+    /// `find_config_file` finds.
+    pub fn find_all_config_files() -> Result<Vec<PathBuf>> {
+        let names = [".pgbranch.yml", ".pgbranch.yaml"];
+        let Ok(git_repo) = crate::git::GitRepository::new(".") else {
+            return Ok(Self::find_config_file()?.into_iter().collect());
+        };
+        let mut configs = git_repo.find_files_named(&names);
+        // Like find_config_file, prefer .pgbranch.yml when a directory has both
+        let dirs_with_yml: Vec<PathBuf> = configs
+            .iter()
+            .filter(|p| p.file_name().is_some_and(|n| n == names[0]))
+            .filter_map(|p| p.parent().map(Path::to_path_buf))
+            .collect();
+        configs.retain(|p| {
+            p.file_name().is_some_and(|n| n == names[0])
+                || !p
+                    .parent()
+                    .is_some_and(|dir| dirs_with_yml.iter().any(|d| d == dir))
+        });
+        Ok(configs)
+    }
+
     pub fn get_database_name(&self, branch_name: &str) -> String {
         // For main branch marker, use the template database name directly
         if branch_name == "_main" {
@@ -513,26 +1175,25 @@ impl Config {
         (hasher.finish() as u32) & 0xFFFF // Use 16 bits for shorter hash
     }
 
-    pub fn should_create_branch(&self, branch_name: &str) -> bool {
-        if !self.git.auto_create_on_branch {
-            return false;
-        }
-
-        if self.git.exclude_branches.contains(&branch_name.to_string()) {
-            return false;
-        }
+    /// Whether cleanup must leave a branch alone: the main branches, those listed
+    /// in `behavior.protected_branches` and the declared standing branches.
+    pub fn is_protected_branch(&self, branch_name: &str) -> bool {
+        matches!(branch_name, "main" | "master")
+            || branch_name == self.git.main_branch
+            || self
+                .behavior
+                .protected_branches
+                .iter()
+                .any(|b| b == branch_name)
+            || self
+                .branches
+                .iter()
+                .flatten()
+                .any(|b| b.name == branch_name)
+    }
 
-        if let Some(filter) = &self.git.branch_filter_regex {
-            match regex::Regex::new(filter) {
-                Ok(re) => re.is_match(branch_name),
-                Err(_) => {
-                    log::warn!("Invalid regex filter: {}", filter);
-                    false
-                }
-            }
-        } else {
-            true
-        }
+    pub fn should_create_branch(&self, branch_name: &str) -> bool {
+        self.git.auto_create_on_branch && self.branch_filter_reason(branch_name, None).is_none()
     }
 
     pub fn should_switch_on_branch(&self, branch_name: &str) -> bool {
@@ -545,21 +1206,104 @@ impl Config {
             return true;
         }
 
-        if self.git.exclude_branches.contains(&branch_name.to_string()) {
-            return false;
+        self.branch_filter_reason(branch_name, None).is_none()
+    }
+
+    /// Why the Git hook leaves `branch_name` alone, or `None` if it switches
+    /// to it. Mirrors `should_switch_on_branch` and `should_create_branch`,
+    /// with the filter overrides of `backend` if one is given.
+    pub fn hook_skip_reason(&self, branch_name: &str, backend: Option<&str>) -> Option<String> {
+        if !self.git.auto_switch_on_branch {
+            return Some("git.auto_switch_on_branch is off".to_string());
+        }
+        if branch_name == self.git.main_branch {
+            return None;
+        }
+        if let Some(reason) = self.branch_filter_reason(branch_name, backend) {
+            return Some(reason);
+        }
+        if !self.git.auto_create_on_branch {
+            return Some("git.auto_create_on_branch is off".to_string());
+        }
+        None
+    }
+
+    /// Whether any of `files` (relative to the repository root) is under
+    /// `git.paths`. An entry without glob characters names a directory.
+    pub fn touches_paths(&self, files: &[String]) -> bool {
+        files.iter().any(|file| {
+            self.git.paths.iter().any(|pattern| {
+                let pattern = pattern.trim_start_matches("./");
+                if pattern.contains(['*', '?', '[']) {
+                    matches_glob(file, pattern)
+                } else {
+                    let dir = pattern.trim_end_matches('/');
+                    file == dir || file.starts_with(&format!("{}/", dir))
+                }
+            })
+        })
+    }
+
+    /// Why `branch_name` is filtered out by the include/exclude lists and
+    /// `branch_filter_regex`, or `None` if it passes. A named backend's
+    /// `branch_filter` replaces whichever of these it sets.
+    pub fn branch_filter_reason(&self, branch_name: &str, backend: Option<&str>) -> Option<String> {
+        let overrides = backend.and_then(|name| {
+            self.resolve_backends()
+                .into_iter()
+                .find(|b| b.name == name)
+                .and_then(|b| b.branch_filter)
+        });
+        let overrides = overrides.unwrap_or_default();
+        let setting = |field: &str, overridden: bool| match (overridden, backend) {
+            (true, Some(name)) => format!("backends[{}].branch_filter.{}", name, field),
+            _ => format!("git.{}", field),
+        };
+
+        let exclude = overrides
+            .exclude_branches
+            .as_ref()
+            .unwrap_or(&self.git.exclude_branches);
+        if let Some(pattern) = exclude.iter().find(|p| matches_glob(branch_name, p)) {
+            return Some(format!(
+                "matches '{}' in {}",
+                pattern,
+                setting("exclude_branches", overrides.exclude_branches.is_some())
+            ));
         }
 
-        if let Some(filter) = &self.git.branch_filter_regex {
+        let include = overrides
+            .include_branches
+            .as_ref()
+            .unwrap_or(&self.git.include_branches);
+        if !include.is_empty() && !include.iter().any(|p| matches_glob(branch_name, p)) {
+            return Some(format!(
+                "not in {}",
+                setting("include_branches", overrides.include_branches.is_some())
+            ));
+        }
+
+        let regex = overrides
+            .branch_filter_regex
+            .as_ref()
+            .or(self.git.branch_filter_regex.as_ref());
+        if let Some(filter) = regex {
+            let name = setting(
+                "branch_filter_regex",
+                overrides.branch_filter_regex.is_some(),
+            );
             match regex::Regex::new(filter) {
-                Ok(re) => re.is_match(branch_name),
+                Ok(re) if !re.is_match(branch_name) => {
+                    return Some(format!("does not match {} '{}'", name, filter))
+                }
+                Ok(_) => {}
                 Err(_) => {
                     log::warn!("Invalid regex filter: {}", filter);
-                    false
+                    return Some(format!("{} '{}' is invalid", name, filter));
                 }
             }
-        } else {
-            true
         }
+        None
     }
 
     pub fn substitute_template_variables(
@@ -614,6 +1358,7 @@ impl Config {
                 neon: backend.neon.clone(),
                 dblab: backend.dblab.clone(),
                 xata: backend.xata.clone(),
+                branch_filter: None,
             }]
         } else {
             vec![]
@@ -621,7 +1366,6 @@ impl Config {
     }
 
     /// Return the name of the default backend (the one with `default: true`, or the first).
-    #[allow(dead_code)]
     pub fn default_backend_name(&self) -> Option<String> {
         let backends = self.resolve_backends();
         if backends.is_empty() {
@@ -683,6 +1427,7 @@ impl Config {
                 neon: backend.neon,
                 dblab: backend.dblab,
                 xata: backend.xata,
+                branch_filter: None,
             }]);
             true
         } else {
@@ -726,9 +1471,15 @@ impl Config {
     ) -> Result<(EffectiveConfig, Option<std::path::PathBuf>)> {
         // Load main config
         let (config, config_path) = Self::load_with_path_info()?;
+        let effective_config = Self::layer_overrides(config, config_path.as_deref())?;
+        Ok((effective_config, config_path))
+    }
 
+    /// `config`, loaded from `config_path`, under the local config file and
+    /// environment overrides that apply to it.
+    pub fn layer_overrides(config: Config, config_path: Option<&Path>) -> Result<EffectiveConfig> {
         // Load local config if it exists - check in current directory if no main config path
-        let local_config = if let Some(ref path) = config_path {
+        let local_config = if let Some(path) = config_path {
             let mut lc = LocalConfig::load_from_project_dir(path.parent().unwrap())?;
             // If no local config found and we're in a worktree, try the main worktree
             if lc.is_none() {
@@ -749,40 +1500,86 @@ impl Config {
         // Load environment config
         let env_config = EnvConfig::load_from_env()?;
 
-        // Create effective config
-        let effective_config = EffectiveConfig::new(config, local_config, env_config)?;
-
-        Ok((effective_config, config_path))
+        EffectiveConfig::new(config, local_config, env_config)
     }
 }
 
 impl LocalConfig {
+    /// Load `.pgbranch.local.yml`, on top of the decrypted
+    /// `.pgbranch.local.yml.enc` if the project commits one.
     pub fn load_from_project_dir(project_dir: &Path) -> Result<Option<Self>> {
         let local_config_path = project_dir.join(".pgbranch.local.yml");
-
-        if !local_config_path.exists() {
-            return Ok(None);
+        let encrypted_path = project_dir.join(".pgbranch.local.yml.enc");
+
+        let mut merged: Option<serde_yaml_ng::Value> = None;
+        if encrypted_path.exists() {
+            let content = crate::decrypt::decrypt_file(&encrypted_path)?;
+            let value = serde_yaml_ng::from_str(&content).with_context(|| {
+                format!(
+                    "Failed to parse decrypted local config file: {}",
+                    encrypted_path.display()
+                )
+            })?;
+            log::debug!(
+                "Loaded encrypted local config from: {}",
+                encrypted_path.display()
+            );
+            merged = Some(value);
         }
 
-        let content = fs::read_to_string(&local_config_path).with_context(|| {
-            format!(
-                "Failed to read local config file: {}",
-                local_config_path.display()
-            )
-        })?;
+        if local_config_path.exists() {
+            let content = fs::read_to_string(&local_config_path).with_context(|| {
+                format!(
+                    "Failed to read local config file: {}",
+                    local_config_path.display()
+                )
+            })?;
+            let value = serde_yaml_ng::from_str(&content).with_context(|| {
+                format!(
+                    "Failed to parse local config file: {}",
+                    local_config_path.display()
+                )
+            })?;
+            log::debug!("Loaded local config from: {}", local_config_path.display());
+            merged = Some(match merged {
+                Some(mut base) => {
+                    merge_yaml(&mut base, value);
+                    base
+                }
+                None => value,
+            });
+        }
 
-        let local_config: LocalConfig = serde_yaml_ng::from_str(&content).with_context(|| {
+        let Some(merged) = merged else {
+            return Ok(None);
+        };
+        let local_config: LocalConfig = serde_yaml_ng::from_value(merged).with_context(|| {
             format!(
                 "Failed to parse local config file: {}",
                 local_config_path.display()
             )
         })?;
-
-        log::debug!("Loaded local config from: {}", local_config_path.display());
         Ok(Some(local_config))
     }
 }
 
+/// Merge `overlay` into `base`: mappings key by key, anything else replaced.
+fn merge_yaml(base: &mut serde_yaml_ng::Value, overlay: serde_yaml_ng::Value) {
+    match (base, overlay) {
+        (serde_yaml_ng::Value::Mapping(base), serde_yaml_ng::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
 impl EnvConfig {
     pub fn load_from_env() -> Result<Self> {
         let env_config = EnvConfig {
@@ -802,11 +1599,47 @@ impl EnvConfig {
             disabled_branches: env::var("PGBRANCH_DISABLED_BRANCHES")
                 .ok()
                 .map(|s| s.split(',').map(|s| s.trim().to_string()).collect()),
+            backend_type: env::var("PGBRANCH_BACKEND").ok(),
+            database: env::var("PGBRANCH_DATABASE").ok(),
+            local_image: env::var("PGBRANCH_LOCAL_IMAGE").ok(),
+            data_root: env::var("PGBRANCH_DATA_ROOT").ok(),
+            port_range_start: match env::var("PGBRANCH_PORT_RANGE_START") {
+                Ok(value) => Some(value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid port for PGBRANCH_PORT_RANGE_START: '{}'", value)
+                })?),
+                Err(_) => None,
+            },
         };
 
         Ok(env_config)
     }
 
+    /// Whether any of the local backend settings is overridden.
+    pub fn has_local_overrides(&self) -> bool {
+        self.local_image.is_some() || self.data_root.is_some() || self.port_range_start.is_some()
+    }
+
+    /// `local` with the local backend settings from the environment applied.
+    pub fn apply_local_overrides(
+        &self,
+        local: Option<LocalBackendConfig>,
+    ) -> Option<LocalBackendConfig> {
+        if !self.has_local_overrides() {
+            return local;
+        }
+        let mut local = local.unwrap_or_default();
+        if let Some(ref image) = self.local_image {
+            local.image = Some(image.clone());
+        }
+        if let Some(ref data_root) = self.data_root {
+            local.data_root = Some(data_root.clone());
+        }
+        if let Some(port) = self.port_range_start {
+            local.port_range_start = Some(port);
+        }
+        Some(local)
+    }
+
     fn parse_bool_env(key: &str) -> Result<Option<bool>> {
         match env::var(key) {
             Ok(value) => match value.to_lowercase().as_str() {
@@ -843,6 +1676,29 @@ impl EffectiveConfig {
         // Determine current branch disabled state
         let current_branch_disabled = env_config.current_branch_disabled.unwrap_or(false);
 
+        if let Some(ref name) = env_config.database {
+            if !config.resolve_backends().iter().any(|b| &b.name == name) {
+                anyhow::bail!(
+                    "PGBRANCH_DATABASE is '{}', but no database by that name is configured",
+                    name
+                );
+            }
+        }
+        if let Some(ref backend_type) = env_config.backend_type {
+            BackendInfo::resolve(backend_type)?;
+            if let Some(ref backends) = config.backends {
+                if !backends
+                    .iter()
+                    .any(|b| same_backend_type(&b.backend_type, backend_type))
+                {
+                    anyhow::bail!(
+                        "PGBRANCH_BACKEND is '{}', but no database of that type is configured",
+                        backend_type
+                    );
+                }
+            }
+        }
+
         Ok(EffectiveConfig {
             config,
             local_config,
@@ -905,6 +1761,9 @@ impl EffectiveConfig {
         if self.is_current_branch_disabled() {
             return Ok(true);
         }
+        if self.config.standalone {
+            return Ok(false);
+        }
 
         // Get current Git branch and check if it's disabled
         match crate::git::GitRepository::new(".") {
@@ -986,6 +1845,15 @@ impl EffectiveConfig {
                 if let Some(ref exclude_branches) = local_git.exclude_branches {
                     merged.git.exclude_branches = exclude_branches.clone();
                 }
+                if let Some(ref include_branches) = local_git.include_branches {
+                    merged.git.include_branches = include_branches.clone();
+                }
+                if let Some(ref paths) = local_git.paths {
+                    merged.git.paths = paths.clone();
+                }
+                if let Some(ref post_merge) = local_git.post_merge {
+                    merged.git.post_merge = Some(post_merge.clone());
+                }
             }
 
             if let Some(ref local_behavior) = local_config.behavior {
@@ -998,6 +1866,21 @@ impl EffectiveConfig {
                 if let Some(ref naming_strategy) = local_behavior.naming_strategy {
                     merged.behavior.naming_strategy = naming_strategy.clone();
                 }
+                if let Some(auto_create) = local_behavior.auto_create_on_connect {
+                    merged.behavior.auto_create_on_connect = auto_create;
+                }
+                if let Some(ref max_age) = local_behavior.max_branch_age {
+                    merged.behavior.max_branch_age = Some(max_age.clone());
+                }
+                if let Some(ref protected) = local_behavior.protected_branches {
+                    merged.behavior.protected_branches = protected.clone();
+                }
+                if let Some(ref ttl) = local_behavior.branch_ttl {
+                    merged.behavior.branch_ttl = Some(ttl.clone());
+                }
+                if let Some(auto_prune) = local_behavior.auto_prune {
+                    merged.behavior.auto_prune = auto_prune;
+                }
             }
 
             if let Some(ref post_commands) = local_config.post_commands {
@@ -1007,6 +1890,10 @@ impl EffectiveConfig {
             if let Some(ref worktree) = local_config.worktree {
                 merged.worktree = Some(worktree.clone());
             }
+
+            if let Some(ref notifications) = local_config.notifications {
+                merged.notifications = Some(notifications.clone());
+            }
         }
 
         // Apply environment config overrides
@@ -1034,9 +1921,55 @@ impl EffectiveConfig {
         if let Some(ref regex) = self.env_config.branch_filter_regex {
             merged.git.branch_filter_regex = Some(regex.clone());
         }
+        self.apply_backend_env_overrides(&mut merged);
 
         merged
     }
+
+    /// Apply PGBRANCH_BACKEND, PGBRANCH_DATABASE and the local backend
+    /// settings. Without any configured backend they are left to the
+    /// auto-detected default backend.
+    fn apply_backend_env_overrides(&self, merged: &mut Config) {
+        let env = &self.env_config;
+        if let Some(ref mut backend) = merged.backend {
+            if let Some(ref backend_type) = env.backend_type {
+                backend.backend_type = backend_type.clone();
+            }
+            if BackendInfo::is_local(&backend.backend_type) {
+                backend.local = env.apply_local_overrides(backend.local.take());
+            }
+        }
+
+        if let Some(ref mut backends) = merged.backends {
+            for backend in backends.iter_mut() {
+                if BackendInfo::is_local(&backend.backend_type) {
+                    backend.local = env.apply_local_overrides(backend.local.take());
+                }
+            }
+
+            // The named database wins over the first one of the requested type
+            let selected = env
+                .database
+                .as_ref()
+                .and_then(|name| backends.iter().position(|b| &b.name == name))
+                .or_else(|| {
+                    env.backend_type.as_ref().and_then(|backend_type| {
+                        backends
+                            .iter()
+                            .position(|b| same_backend_type(&b.backend_type, backend_type))
+                    })
+                });
+            if let Some(selected) = selected {
+                for (i, backend) in backends.iter_mut().enumerate() {
+                    backend.default = i == selected;
+                }
+            }
+        }
+    }
+}
+
+fn same_backend_type(a: &str, b: &str) -> bool {
+    BackendInfo::canonical_name(a) == BackendInfo::canonical_name(b)
 }
 
 #[derive(Debug, Clone)]
@@ -1049,6 +1982,7 @@ pub struct TemplateContext {
     pub db_password: Option<String>,
     pub template_db: String,
     pub prefix: String,
+    pub backend: String,
 }
 
 impl TemplateContext {
@@ -1062,6 +1996,51 @@ impl TemplateContext {
             db_password: config.database.password.clone(),
             template_db: config.database.template_database.clone(),
             prefix: config.database.database_prefix.clone(),
+            backend: "postgres_template".to_string(),
         }
     }
+
+    /// Build a PostgreSQL connection URL for the branch database.
+    pub fn database_url(&self) -> String {
+        match self.db_password {
+            Some(ref password) => format!(
+                "postgresql://{}:{}@{}/{}",
+                encode_userinfo(&self.db_user),
+                encode_userinfo(password),
+                host_port(&self.db_host, self.db_port),
+                self.db_name
+            ),
+            None => format!(
+                "postgresql://{}@{}/{}",
+                encode_userinfo(&self.db_user),
+                host_port(&self.db_host, self.db_port),
+                self.db_name
+            ),
+        }
+    }
+}
+
+/// `host:port`, with an IPv6 address in brackets (`[::1]:5432`) as URLs
+/// and socket addresses need it.
+pub(crate) fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Percent-encode a user or password for the userinfo part of a URL, so
+/// `@`, `:` or `/` in it don't end it early.
+pub(crate) fn encode_userinfo(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
 }
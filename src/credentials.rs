@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Providers whose API credentials can be stored with `pgbranch auth login`.
+/// `age` is the identity that decrypts `.pgbranch.local.yml.enc`.
+pub const PROVIDERS: &[&str] = &["neon", "xata", "dblab", "age"];
+
+const KEYCHAIN_SERVICE: &str = "pgbranch";
+
+/// Where a credential was found or stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialSource {
+    Keychain,
+    File,
+}
+
+impl CredentialSource {
+    pub fn describe(self) -> String {
+        match self {
+            Self::Keychain => "OS keychain".to_string(),
+            Self::File => credentials_file_path()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| "credentials file".to_string()),
+        }
+    }
+}
+
+pub fn validate_provider(provider: &str) -> Result<()> {
+    if !PROVIDERS.contains(&provider) {
+        anyhow::bail!(
+            "Unknown provider '{}'. Expected one of: {}",
+            provider,
+            PROVIDERS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Store a credential in the OS keychain, falling back to the credentials file.
+pub fn store(provider: &str, secret: &str, file_only: bool) -> Result<CredentialSource> {
+    if !file_only {
+        match keychain_store(provider, secret) {
+            Ok(()) => return Ok(CredentialSource::Keychain),
+            Err(e) => log::debug!("Keychain unavailable, using credentials file: {}", e),
+        }
+    }
+    let mut credentials = load_file()?;
+    credentials.insert(provider.to_string(), secret.to_string());
+    save_file(&credentials)?;
+    Ok(CredentialSource::File)
+}
+
+/// Look up a credential, checking the OS keychain before the credentials file.
+pub fn get(provider: &str) -> Result<Option<(String, CredentialSource)>> {
+    if let Some(secret) = keychain_get(provider) {
+        return Ok(Some((secret, CredentialSource::Keychain)));
+    }
+    Ok(load_file()?
+        .remove(provider)
+        .map(|secret| (secret, CredentialSource::File)))
+}
+
+/// Remove a credential from every store, returning whether anything was removed.
+pub fn remove(provider: &str) -> Result<bool> {
+    let removed_keychain = keychain_remove(provider);
+    let mut credentials = load_file()?;
+    let removed_file = credentials.remove(provider).is_some();
+    if removed_file {
+        save_file(&credentials)?;
+    }
+    Ok(removed_keychain || removed_file)
+}
+
+fn credentials_file_path() -> Result<PathBuf> {
+    Ok(crate::paths::config_dir().join("credentials.yml"))
+}
+
+fn load_file() -> Result<BTreeMap<String, String>> {
+    let path = credentials_file_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read credentials file: {}", path.display()))?;
+    serde_yaml_ng::from_str(&content)
+        .with_context(|| format!("Failed to parse credentials file: {}", path.display()))
+}
+
+fn save_file(credentials: &BTreeMap<String, String>) -> Result<()> {
+    let path = credentials_file_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content = serde_yaml_ng::to_string(credentials)?;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&path)
+        .with_context(|| format!("Failed to write credentials file: {}", path.display()))?;
+    // mode() only applies on creation, so tighten permissions of pre-existing files too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+// The keychain is driven through the platform CLI tools (`security` on macOS,
+// `secret-tool` from libsecret on Linux) to avoid linking against native libraries.
+
+fn keychain_store(provider: &str, secret: &str) -> Result<()> {
+    // The secret goes in on stdin, never in arguments other users can see with `ps`
+    let (mut command, input) = if cfg!(target_os = "macos") {
+        // `add-generic-password -w` without a value reads the password from the
+        // terminal, not stdin, so the whole command line goes to `security -i`
+        if secret.contains(['\n', '\r']) {
+            anyhow::bail!("A secret stored in the keychain can't span lines");
+        }
+        let mut command = Command::new("security");
+        command.arg("-i");
+        let line = [
+            "add-generic-password",
+            "-U",
+            "-s",
+            KEYCHAIN_SERVICE,
+            "-a",
+            provider,
+            "-w",
+            secret,
+        ]
+        .map(security_quote)
+        .join(" ");
+        (command, format!("{}\n", line))
+    } else if cfg!(target_os = "linux") {
+        let mut command = Command::new("secret-tool");
+        command.args([
+            "store",
+            &format!("--label=pgbranch {} API key", provider),
+            "service",
+            KEYCHAIN_SERVICE,
+            "account",
+            provider,
+        ]);
+        (command, secret.to_string())
+    } else {
+        anyhow::bail!("No supported keychain on this platform");
+    };
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open the keychain tool's stdin")?
+        .write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    // `security -i` reports a failed command on stderr but still exits 0
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || (cfg!(target_os = "macos") && !stderr.trim().is_empty()) {
+        anyhow::bail!(
+            "keychain tool exited with {}: {}",
+            output.status,
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
+
+/// `value` as one word of a `security -i` command line.
+fn security_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn keychain_get(provider: &str) -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args([
+                "find-generic-password",
+                "-s",
+                KEYCHAIN_SERVICE,
+                "-a",
+                provider,
+                "-w",
+            ])
+            .stderr(Stdio::null())
+            .output()
+            .ok()?
+    } else if cfg!(target_os = "linux") {
+        Command::new("secret-tool")
+            .args(["lookup", "service", KEYCHAIN_SERVICE, "account", provider])
+            .stderr(Stdio::null())
+            .output()
+            .ok()?
+    } else {
+        return None;
+    };
+
+    let secret = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !secret.is_empty()).then_some(secret)
+}
+
+fn keychain_remove(provider: &str) -> bool {
+    let status = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args([
+                "delete-generic-password",
+                "-s",
+                KEYCHAIN_SERVICE,
+                "-a",
+                provider,
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+    } else if cfg!(target_os = "linux") {
+        // secret-tool clear exits 0 even when nothing matched, so check first
+        if keychain_get(provider).is_none() {
+            return false;
+        }
+        Command::new("secret-tool")
+            .args(["clear", "service", KEYCHAIN_SERVICE, "account", provider])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+    } else {
+        return false;
+    };
+    status.is_ok_and(|s| s.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_security_quote() {
+        assert_eq!(security_quote("neon"), "\"neon\"");
+        assert_eq!(security_quote(r#"a b"c\d"#), r#""a b\"c\\d""#);
+    }
+
+    /// Writes to the login keychain, so it only runs on request:
+    /// `cargo test keychain_round_trip -- --ignored` on macOS.
+    #[cfg(target_os = "macos")]
+    #[test]
+    #[ignore]
+    fn test_keychain_round_trip() {
+        let provider = "pgbranch-test";
+        let secret = r#"tok en"with\quotes"#;
+        keychain_store(provider, secret).unwrap();
+        assert_eq!(keychain_get(provider).as_deref(), Some(secret));
+        assert!(keychain_remove(provider));
+    }
+}
@@ -66,6 +66,23 @@ impl DatabaseManager {
         Ok(())
     }
 
+    /// The SQL `create_database_branch` runs, for `--dry-run`.
+    pub fn plan_create_database_branch(&self, branch_name: &str) -> String {
+        format!(
+            "CREATE DATABASE {} WITH TEMPLATE {}",
+            escape_identifier(&self.config.get_database_name(branch_name)),
+            escape_identifier(&self.config.database.template_database)
+        )
+    }
+
+    /// The SQL `drop_database_branch` runs, for `--dry-run`.
+    pub fn plan_drop_database_branch(&self, branch_name: &str) -> String {
+        format!(
+            "DROP DATABASE {}",
+            escape_identifier(&self.config.get_database_name(branch_name))
+        )
+    }
+
     pub async fn drop_database_branch(&self, branch_name: &str) -> Result<()> {
         let client = self.connect().await?;
         let db_name = self.config.get_database_name(branch_name);
@@ -86,6 +103,39 @@ impl DatabaseManager {
         Ok(())
     }
 
+    /// Whether new sessions on the branch's database default to read-only transactions.
+    pub async fn database_read_only(&self, branch_name: &str) -> Result<bool> {
+        let client = self.connect().await?;
+        let db_name = self.config.get_database_name(branch_name);
+        let row = client
+            .query_one(
+                "SELECT EXISTS (SELECT FROM pg_db_role_setting s \
+                 JOIN pg_database d ON d.oid = s.setdatabase \
+                 WHERE d.datname = $1 AND s.setrole = 0 \
+                 AND 'default_transaction_read_only=on' = ANY (s.setconfig))",
+                &[&db_name],
+            )
+            .await
+            .with_context(|| format!("Failed to read settings of database {}", db_name))?;
+        Ok(row.get(0))
+    }
+
+    pub async fn set_database_read_only(&self, branch_name: &str, read_only: bool) -> Result<()> {
+        let client = self.connect().await?;
+        let db_name = self.config.get_database_name(branch_name);
+        let setting = if read_only {
+            "SET default_transaction_read_only = on"
+        } else {
+            "RESET default_transaction_read_only"
+        };
+        let query = format!("ALTER DATABASE {} {}", escape_identifier(&db_name), setting);
+        client
+            .execute(&query, &[])
+            .await
+            .with_context(|| format!("Failed to change read-only mode of database {}", db_name))?;
+        Ok(())
+    }
+
     pub async fn list_database_branches(&self) -> Result<Vec<String>> {
         let client = self.connect().await?;
         let prefix = &self.config.database.database_prefix;
@@ -181,7 +231,8 @@ impl DatabaseManager {
         Ok(())
     }
 
-    pub async fn cleanup_old_branches(&self, max_count: usize) -> Result<()> {
+    /// Branches beyond the newest `max_count`, which cleanup would drop.
+    pub async fn old_branches(&self, max_count: usize) -> Result<Vec<String>> {
         let client = self.connect().await?;
         let prefix = &self.config.database.database_prefix;
 
@@ -199,14 +250,10 @@ impl DatabaseManager {
             .await
             .context("Failed to query old branches for cleanup")?;
 
-        for row in rows {
-            let db_name: String = row.get(0);
-            if let Some(branch_name) = self.extract_branch_name(&db_name) {
-                self.drop_database_branch(&branch_name).await?;
-            }
-        }
-
-        Ok(())
+        Ok(rows
+            .iter()
+            .filter_map(|row| self.extract_branch_name(&row.get::<_, String>(0)))
+            .collect())
     }
 
     async fn get_password(&self) -> Result<Option<String>> {
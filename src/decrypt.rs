@@ -0,0 +1,130 @@
+//! Decryption of committed secrets, such as `.pgbranch.local.yml.enc`.
+//! Files encrypted with age are decrypted by the `age` CLI, anything else
+//! is handed to `sops`. The age identity comes from `PGBRANCH_AGE_KEY`, a
+//! key file in `PGBRANCH_AGE_KEY_FILE` or `SOPS_AGE_KEY_FILE`, or the one
+//! stored with `pgbranch auth login age`.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+const AGE_ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+const AGE_BINARY_HEADER: &[u8] = b"age-encryption.org/";
+
+/// Where the age identity comes from.
+enum AgeIdentity {
+    Key(String),
+    File(String),
+}
+
+/// Decrypt `path` and return the plaintext.
+pub fn decrypt_file(path: &Path) -> Result<String> {
+    let content =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let plaintext =
+        if content.starts_with(AGE_ARMOR_HEADER) || content.starts_with(AGE_BINARY_HEADER) {
+            decrypt_age(path)?
+        } else {
+            decrypt_sops(path)?
+        };
+    String::from_utf8(plaintext)
+        .with_context(|| format!("Decrypted {} is not valid UTF-8", path.display()))
+}
+
+fn decrypt_age(path: &Path) -> Result<Vec<u8>> {
+    let identity = age_identity()?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "No age key to decrypt {}. Set PGBRANCH_AGE_KEY or PGBRANCH_AGE_KEY_FILE, or run 'pgbranch auth login age'",
+            path.display()
+        )
+    })?;
+
+    let mut command = Command::new("age");
+    command.arg("--decrypt");
+    match identity {
+        AgeIdentity::File(ref file) => command.args(["--identity", file]),
+        // Keys never touch the disk: age reads identities from stdin for "-"
+        AgeIdentity::Key(_) => command.args(["--identity", "-"]),
+    };
+    command.arg(path);
+    let stdin = match identity {
+        AgeIdentity::Key(key) => Some(key),
+        AgeIdentity::File(_) => None,
+    };
+    run_decrypter(command, "age", stdin, path)
+}
+
+fn decrypt_sops(path: &Path) -> Result<Vec<u8>> {
+    let mut command = Command::new("sops");
+    command.args(["--decrypt", "--input-type", "yaml", "--output-type", "yaml"]);
+    command.arg(path);
+    // sops finds its own keys (KMS, PGP, SOPS_AGE_KEY_FILE, ...); only pass
+    // on an age key it wouldn't see otherwise
+    if std::env::var_os("SOPS_AGE_KEY").is_none() && std::env::var_os("SOPS_AGE_KEY_FILE").is_none()
+    {
+        match age_identity()? {
+            Some(AgeIdentity::Key(key)) => {
+                command.env("SOPS_AGE_KEY", key);
+            }
+            Some(AgeIdentity::File(file)) => {
+                command.env("SOPS_AGE_KEY_FILE", file);
+            }
+            None => {}
+        }
+    }
+    run_decrypter(command, "sops", None, path)
+}
+
+fn age_identity() -> Result<Option<AgeIdentity>> {
+    if let Ok(key) = std::env::var("PGBRANCH_AGE_KEY") {
+        return Ok(Some(AgeIdentity::Key(key)));
+    }
+    for var in ["PGBRANCH_AGE_KEY_FILE", "SOPS_AGE_KEY_FILE"] {
+        if let Ok(file) = std::env::var(var) {
+            return Ok(Some(AgeIdentity::File(file)));
+        }
+    }
+    Ok(crate::credentials::get("age")?.map(|(key, _)| AgeIdentity::Key(key)))
+}
+
+fn run_decrypter(
+    mut command: Command,
+    tool: &str,
+    stdin: Option<String>,
+    path: &Path,
+) -> Result<Vec<u8>> {
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().with_context(|| {
+        format!(
+            "Failed to run '{}' to decrypt {}. Is it installed?",
+            tool,
+            path.display()
+        )
+    })?;
+    if let Some(input) = stdin {
+        child
+            .stdin
+            .take()
+            .with_context(|| format!("Failed to open {} stdin", tool))?
+            .write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to decrypt {} with {}: {}",
+            path.display(),
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
@@ -11,6 +11,8 @@ pub struct PostgresConfig {
     pub user: Option<String>,
     pub password: Option<String>,
     pub database: Option<String>,
+    /// Databases created besides `database`, e.g. from `POSTGRES_MULTIPLE_DATABASES`
+    pub extra_databases: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -87,6 +89,7 @@ pub fn parse_postgres_config_from_files(filenames: &[String]) -> Result<Option<P
         user: None,
         password: None,
         database: None,
+        extra_databases: Vec::new(),
     };
 
     let mut found_any = false;
@@ -111,6 +114,9 @@ pub fn parse_postgres_config_from_files(filenames: &[String]) -> Result<Option<P
             if config.database.is_some() {
                 combined_config.database = config.database;
             }
+            if !config.extra_databases.is_empty() {
+                combined_config.extra_databases = config.extra_databases;
+            }
         }
     }
 
@@ -193,6 +199,7 @@ fn parse_postgres_config_from_file(filename: &str) -> Result<Option<PostgresConf
                 || postgres_config.user.is_some()
                 || postgres_config.password.is_some()
                 || postgres_config.database.is_some()
+                || !postgres_config.extra_databases.is_empty()
             {
                 log::debug!(
                     "Successfully extracted PostgreSQL config from service '{}'",
@@ -286,6 +293,7 @@ fn extract_postgres_config_from_env(env_vars: &HashMap<String, String>) -> Postg
         user: None,
         password: None,
         database: None,
+        extra_databases: Vec::new(),
     };
 
     // Host mappings
@@ -340,6 +348,21 @@ fn extract_postgres_config_from_env(env_vars: &HashMap<String, String>) -> Postg
         }
     }
 
+    // Init scripts commonly create more databases from a list like "app,app_test"
+    for dbs_key in ["POSTGRES_MULTIPLE_DATABASES", "POSTGRES_DATABASES"] {
+        if let Some(value) = env_vars.get(dbs_key) {
+            config.extra_databases = value
+                .split([',', ' '])
+                // Some scripts accept "database:user" pairs
+                .filter_map(|entry| entry.split(':').next())
+                .map(str::trim)
+                .filter(|name| !name.is_empty() && Some(*name) != config.database.as_deref())
+                .map(str::to_string)
+                .collect();
+            break;
+        }
+    }
+
     config
 }
 
@@ -419,6 +442,12 @@ pub fn prompt_user_for_config_usage(postgres_config: &PostgresConfig) -> Result<
     if let Some(ref database) = postgres_config.database {
         println!("  Database: {}", database);
     }
+    if !postgres_config.extra_databases.is_empty() {
+        println!(
+            "  Additional databases: {}",
+            postgres_config.extra_databases.join(", ")
+        );
+    }
 
     print!("\nWould you like to use these settings? (y/N): ");
     std::io::Write::flush(&mut std::io::stdout()).unwrap();
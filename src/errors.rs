@@ -0,0 +1,194 @@
+//! Stable codes for the errors users run into, so wrappers and docs can tell
+//! them apart without matching on messages. An error gets its code from
+//! [`coded!`]; anything else is reported as `E0000`.
+//!
+//! Each code comes with a hint on what to do about it. The hints can be
+//! replaced, e.g. translated, with a YAML catalog of `code: hint` entries in
+//! `PGBRANCH_MESSAGES` or `<config dir>/pgbranch/messages.yml`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Other,
+    BranchNotFound,
+    #[allow(dead_code)] // only raised by the local and mock backends
+    BranchExists,
+    InvalidBranchName,
+    DatabaseNotFound,
+    ConfigNotFound,
+    Unsupported,
+    UnknownBackend,
+    #[allow(dead_code)] // only raised by the cloud backends
+    CredentialsMissing,
+    ConfirmationRequired,
+    SignatureInvalid,
+}
+
+impl ErrorCode {
+    /// Never reused for another error once published.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::Other => "E0000",
+            ErrorCode::BranchNotFound => "E0001",
+            ErrorCode::BranchExists => "E0002",
+            ErrorCode::InvalidBranchName => "E0003",
+            ErrorCode::DatabaseNotFound => "E0004",
+            ErrorCode::ConfigNotFound => "E0005",
+            ErrorCode::Unsupported => "E0006",
+            ErrorCode::UnknownBackend => "E0007",
+            ErrorCode::CredentialsMissing => "E0008",
+            ErrorCode::ConfirmationRequired => "E0009",
+            ErrorCode::SignatureInvalid => "E0010",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::Other => "Other",
+            ErrorCode::BranchNotFound => "BranchNotFound",
+            ErrorCode::BranchExists => "BranchExists",
+            ErrorCode::InvalidBranchName => "InvalidBranchName",
+            ErrorCode::DatabaseNotFound => "DatabaseNotFound",
+            ErrorCode::ConfigNotFound => "ConfigNotFound",
+            ErrorCode::Unsupported => "Unsupported",
+            ErrorCode::UnknownBackend => "UnknownBackend",
+            ErrorCode::CredentialsMissing => "CredentialsMissing",
+            ErrorCode::ConfirmationRequired => "ConfirmationRequired",
+            ErrorCode::SignatureInvalid => "SignatureInvalid",
+        }
+    }
+
+    fn default_hint(self) -> Option<&'static str> {
+        match self {
+            ErrorCode::Other => None,
+            ErrorCode::BranchNotFound => {
+                Some("Check the name with 'pgbranch list', or create it with 'pgbranch create'")
+            }
+            ErrorCode::BranchExists => {
+                Some("Pick another name, or switch to the existing branch with 'pgbranch switch'")
+            }
+            ErrorCode::InvalidBranchName => Some("Use the suggested name, or shorten the name"),
+            ErrorCode::DatabaseNotFound => Some(
+                "See the configured databases with 'pgbranch config', or add one with 'pgbranch init -d <name>'",
+            ),
+            ErrorCode::ConfigNotFound => Some("Run 'pgbranch init' in the project first"),
+            ErrorCode::Unsupported => {
+                Some("Use a backend that supports it, e.g. target another database with -d")
+            }
+            ErrorCode::UnknownBackend => Some("See 'pgbranch init --help' for the backend types"),
+            ErrorCode::CredentialsMissing => Some("Run 'pgbranch auth login <provider>'"),
+            ErrorCode::ConfirmationRequired => {
+                Some("Pass --force to go ahead without being asked")
+            }
+            ErrorCode::SignatureInvalid => Some(
+                "Get a freshly signed copy from its author, or add their key to 'trusted' under 'signing'",
+            ),
+        }
+    }
+
+    /// What to do about it, from the message catalog if it has one.
+    pub fn hint(self) -> Option<String> {
+        catalog()
+            .get(self.code())
+            .cloned()
+            .or_else(|| self.default_hint().map(str::to_string))
+    }
+}
+
+/// An error with a code. Build it with [`coded!`].
+#[derive(Debug)]
+pub struct Error {
+    pub code: ErrorCode,
+    message: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        Self { code, message }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// `anyhow::anyhow!` with an [`ErrorCode`]: `coded!(BranchNotFound, "Branch '{}' not found", name)`.
+macro_rules! coded {
+    ($code:ident, $($arg:tt)+) => {
+        anyhow::Error::from($crate::errors::Error::new(
+            $crate::errors::ErrorCode::$code,
+            format!($($arg)+),
+        ))
+    };
+}
+pub(crate) use coded;
+
+/// The code of the first coded error in `err`'s chain, so context added on
+/// the way up doesn't hide it.
+pub fn code_of(err: &anyhow::Error) -> ErrorCode {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<Error>())
+        .map_or(ErrorCode::Other, |e| e.code)
+}
+
+#[derive(Serialize)]
+struct ErrorReport {
+    code: &'static str,
+    name: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+}
+
+/// Print a failed command's error to stderr, as JSON with `--json`.
+pub fn report(err: &anyhow::Error, json_output: bool) {
+    let code = code_of(err);
+    if json_output {
+        let report = ErrorReport {
+            code: code.code(),
+            name: code.name(),
+            message: format!("{:#}", err),
+            hint: code.hint(),
+        };
+        match crate::output::versioned(&serde_json::json!({ "error": report })) {
+            Ok(value) => eprintln!("{}", value),
+            Err(_) => eprintln!("Error [{}]: {:#}", code.code(), err),
+        }
+        return;
+    }
+
+    if code == ErrorCode::Other {
+        eprintln!("Error: {:?}", err);
+    } else {
+        eprintln!("Error [{}]: {:?}", code.code(), err);
+    }
+    if let Some(hint) = code.hint() {
+        eprintln!("hint: {}", hint);
+    }
+}
+
+/// Hints by code from `PGBRANCH_MESSAGES` or `messages.yml`, read once.
+fn catalog() -> &'static HashMap<String, String> {
+    static CATALOG: OnceLock<HashMap<String, String>> = OnceLock::new();
+    CATALOG.get_or_init(|| {
+        let path = std::env::var_os("PGBRANCH_MESSAGES")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| crate::paths::config_dir().join("messages.yml"));
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return HashMap::new();
+        };
+        serde_yaml_ng::from_str(&text).unwrap_or_else(|e| {
+            log::warn!("Ignoring message catalog {}: {}", path.display(), e);
+            HashMap::new()
+        })
+    })
+}
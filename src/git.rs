@@ -9,7 +9,8 @@ pub struct GitRepository {
 
 impl GitRepository {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let repo = Repository::open(path).context("Failed to open Git repository")?;
+        // Discover, so commands also work from a subdirectory, e.g. a project in a monorepo
+        let repo = Repository::discover(path).context("Failed to open Git repository")?;
 
         Ok(GitRepository { repo })
     }
@@ -145,6 +146,45 @@ impl GitRepository {
         Ok(branch_names)
     }
 
+    /// Files the current branch changed since it forked from `base`, or since
+    /// ORIG_HEAD if `base` doesn't exist. `None` if there's nothing to compare with.
+    pub fn changed_files_since(&self, base: &str) -> Result<Option<Vec<String>>> {
+        let head = self
+            .repo
+            .head()
+            .context("Failed to get HEAD reference")?
+            .peel_to_commit()?;
+        let base_commit = match self.repo.revparse_single(base) {
+            Ok(object) => {
+                let base_id = object.peel_to_commit()?.id();
+                let fork_point = self
+                    .repo
+                    .merge_base(base_id, head.id())
+                    .with_context(|| format!("Failed to find where HEAD forked from '{}'", base))?;
+                self.repo.find_commit(fork_point)?
+            }
+            Err(_) => match self.repo.revparse_single("ORIG_HEAD") {
+                Ok(orig_head) => orig_head.peel_to_commit()?,
+                Err(_) => return Ok(None),
+            },
+        };
+
+        let diff =
+            self.repo
+                .diff_tree_to_tree(Some(&base_commit.tree()?), Some(&head.tree()?), None)?;
+        let mut files = Vec::new();
+        for delta in diff.deltas() {
+            for file in [delta.old_file(), delta.new_file()] {
+                if let Some(path) = file.path().and_then(|p| p.to_str()) {
+                    if !files.iter().any(|f| f == path) {
+                        files.push(path.to_string());
+                    }
+                }
+            }
+        }
+        Ok(Some(files))
+    }
+
     pub fn install_hooks(&self) -> Result<()> {
         let hooks_dir = self.repo.path().join("hooks");
         fs::create_dir_all(&hooks_dir).context("Failed to create hooks directory")?;
@@ -165,7 +205,8 @@ impl GitRepository {
         }
 
         let post_merge_hook = hooks_dir.join("post-merge");
-        fs::write(&post_merge_hook, &hook_script).context("Failed to write post-merge hook")?;
+        fs::write(&post_merge_hook, self.generate_post_merge_hook_script())
+            .context("Failed to write post-merge hook")?;
 
         #[cfg(unix)]
         {
@@ -240,6 +281,26 @@ fi
         .to_string()
     }
 
+    fn generate_post_merge_hook_script(&self) -> String {
+        r#"#!/bin/sh
+# pgbranch auto-generated hook
+# This hook runs the configured git.post_merge actions after merging into the main branch
+# Parameters: $1=1 if this was a squash merge
+
+if command -v pgbranch >/dev/null 2>&1; then
+    pgbranch git-hook --event post-merge
+else
+    echo "pgbranch not found in PATH, skipping post-merge actions"
+fi
+"#
+        .to_string()
+    }
+
+    pub fn hooks_installed(&self) -> bool {
+        let post_checkout_hook = self.repo.path().join("hooks").join("post-checkout");
+        self.is_pgbranch_hook(&post_checkout_hook).unwrap_or(false)
+    }
+
     pub fn is_pgbranch_hook(&self, hook_path: &Path) -> Result<bool> {
         if !hook_path.exists() {
             return Ok(false);
@@ -250,15 +311,112 @@ fi
         Ok(content.contains("pgbranch auto-generated hook"))
     }
 
-    #[allow(dead_code)]
     pub fn get_repo_root(&self) -> &Path {
         self.repo.workdir().unwrap_or_else(|| self.repo.path())
     }
 
+    /// This worktree's own `.git` directory (`.git/worktrees/<name>` in a linked worktree).
+    pub fn git_dir(&self) -> &Path {
+        self.repo.path()
+    }
+
+    /// The `.git` directory shared by every worktree of the repository.
+    pub fn common_dir(&self) -> &Path {
+        self.repo.commondir()
+    }
+
+    /// Files named one of `names` anywhere in the working tree, sorted by
+    /// path. Skips `.git`, nested repositories and directories Git ignores.
+    pub fn find_files_named(&self, names: &[&str]) -> Vec<PathBuf> {
+        let Some(root) = self.repo.workdir() else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+                if !is_dir {
+                    if names.iter().any(|name| entry.file_name() == *name) {
+                        found.push(path);
+                    }
+                    continue;
+                }
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                if entry.file_name() == ".git"
+                    || path.join(".git").exists()
+                    || self.repo.is_path_ignored(relative).unwrap_or(false)
+                {
+                    continue;
+                }
+                dirs.push(path);
+            }
+        }
+        found.sort();
+        found
+    }
+
     pub fn is_worktree(&self) -> bool {
         self.repo.is_worktree()
     }
 
+    /// Create a linked worktree at `path` checked out to `branch_name`,
+    /// creating the branch from `base` (or HEAD) if it does not exist yet.
+    pub fn add_worktree(&self, path: &Path, branch_name: &str, base: Option<&str>) -> Result<()> {
+        if path.exists() {
+            anyhow::bail!("Worktree path already exists: {}", path.display());
+        }
+
+        let branch = match self.repo.find_branch(branch_name, git2::BranchType::Local) {
+            Ok(branch) => branch,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {
+                let commit = match base {
+                    Some(base) => self
+                        .repo
+                        .revparse_single(base)
+                        .with_context(|| format!("Failed to resolve base revision '{}'", base))?
+                        .peel_to_commit()?,
+                    None => self
+                        .repo
+                        .head()
+                        .context("Failed to get HEAD reference")?
+                        .peel_to_commit()?,
+                };
+                self.repo
+                    .branch(branch_name, &commit, false)
+                    .with_context(|| format!("Failed to create branch '{}'", branch_name))?
+            }
+            Err(e) => return Err(anyhow::anyhow!("Error checking branch: {}", e)),
+        };
+
+        // Worktree names live under .git/worktrees/, so they must be a single path component
+        let name: String = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| branch_name.to_string())
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+
+        let mut options = git2::WorktreeAddOptions::new();
+        options.reference(Some(branch.get()));
+        self.repo
+            .worktree(&name, path, Some(&options))
+            .with_context(|| format!("Failed to add worktree at {}", path.display()))?;
+
+        Ok(())
+    }
+
     pub fn get_main_worktree_dir(&self) -> Option<PathBuf> {
         if !self.repo.is_worktree() {
             return None;
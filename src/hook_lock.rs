@@ -0,0 +1,86 @@
+//! Serializes git-hook runs. A rebase or a quick `git checkout -` fires
+//! several post-checkout hooks at once; without a lock they race each other
+//! and whichever finishes last decides the database branch. Each run queues
+//! for a lock shared by all worktrees, and a run that finds a newer one
+//! queued behind it in the same worktree steps aside, so only the latest
+//! checkout does any work.
+
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::git::GitRepository;
+
+const LOCK_FILE: &str = "pgbranch-hook.lock";
+const PENDING_FILE: &str = "pgbranch-hook.pending";
+
+/// How long a hook waits for the one before it before giving up.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Held while a hook runs; dropping it lets the next one in.
+#[derive(Debug)]
+pub struct HookLock {
+    _file: File,
+}
+
+/// Wait for earlier hooks of the repository to finish. Returns `None` if a
+/// newer hook was queued in this worktree meanwhile, since that one will
+/// switch to the branch that is checked out now.
+pub async fn acquire(git_repo: &GitRepository) -> Result<Option<HookLock>> {
+    let lock_path = git_repo.common_dir().join(LOCK_FILE);
+    let pending_path = git_repo.git_dir().join(PENDING_FILE);
+
+    let ticket = format!(
+        "{}-{}",
+        std::process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    );
+    std::fs::write(&pending_path, &ticket)
+        .with_context(|| format!("failed to write {}", pending_path.display()))?;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("failed to open {}", lock_path.display()))?;
+    wait_for_lock(&file, &lock_path).await?;
+
+    let latest = std::fs::read_to_string(&pending_path).unwrap_or_default();
+    if latest != ticket {
+        log::info!("A newer git hook is queued; leaving the switch to it");
+        return Ok(None);
+    }
+    Ok(Some(HookLock { _file: file }))
+}
+
+async fn wait_for_lock(file: &File, lock_path: &Path) -> Result<()> {
+    let started = std::time::Instant::now();
+    let mut announced = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(()),
+            Err(std::fs::TryLockError::WouldBlock) => {}
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("failed to lock {}", lock_path.display()))
+            }
+        }
+        if !announced {
+            log::info!("Waiting for another pgbranch git hook to finish");
+            announced = true;
+        }
+        if started.elapsed() >= LOCK_TIMEOUT {
+            anyhow::bail!(
+                "Another pgbranch git hook has been running for over {}s; stop it or run 'pgbranch switch' yourself",
+                LOCK_TIMEOUT.as_secs()
+            );
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
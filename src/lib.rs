@@ -0,0 +1,58 @@
+//! pgbranch as a library, for test harnesses and tools that create and
+//! destroy database branches from Rust instead of running the `pgbranch`
+//! binary. A [`Project`] loads `.pgbranch.yml` the way the CLI does and
+//! creates, switches, lists and deletes branches of its databases:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let project = pgbranch::Project::load()?;
+//! let branch = project.create_branch(None, "test-run-42", Some("main")).await?;
+//! let database = project.backend(None).await?;
+//! let connection = database.backend.get_connection_info(&branch.name).await?;
+//! // ... run the tests against `connection` ...
+//! project.delete_branch(None, &branch.name).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Project::backend`] hands out a database's [`DatabaseBranchingBackend`]
+//! for everything else.
+//!
+//! Errors are `anyhow` errors; [`errors::code_of`] tells the ones with a
+//! stable [`errors::ErrorCode`], such as a missing branch, from the rest.
+
+mod audit;
+mod backends;
+mod cli;
+mod config;
+mod credentials;
+#[cfg(feature = "backend-postgres-template")]
+mod database;
+mod decrypt;
+mod docker;
+pub mod errors;
+mod git;
+mod hook_lock;
+mod local_state;
+mod manifest;
+#[cfg(feature = "backend-local")]
+mod notifier;
+mod output;
+mod paths;
+mod plan;
+mod post_commands;
+mod project;
+mod proxy;
+mod secret_ref;
+mod shutdown;
+mod signing;
+mod upgrade;
+mod webhook;
+
+pub use backends::factory::NamedBackend;
+pub use backends::{BranchInfo, ConnectionInfo, DatabaseBranchingBackend};
+pub use project::Project;
+
+/// The `pgbranch` binary's entry point, not part of the library API.
+#[doc(hidden)]
+pub use cli::main;
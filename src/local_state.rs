@@ -16,8 +16,20 @@ pub struct ProjectState {
     pub last_updated: chrono::DateTime<chrono::Utc>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub backends: Option<Vec<NamedBackendConfig>>,
+    /// Most recently used branches, newest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recent_branches: Vec<RecentBranch>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentBranch {
+    pub name: String,
+    pub used_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Maximum number of entries kept in a project's recent branch stack.
+const MAX_RECENT_BRANCHES: usize = 20;
+
 pub struct LocalStateManager {
     state_file_path: PathBuf,
     state: LocalState,
@@ -34,6 +46,16 @@ impl LocalStateManager {
         })
     }
 
+    #[cfg(feature = "backend-local")]
+    pub fn state(&self) -> &LocalState {
+        &self.state
+    }
+
+    #[cfg(feature = "backend-local")]
+    pub fn state_file_path(&self) -> &Path {
+        &self.state_file_path
+    }
+
     pub fn get_current_branch(&self, project_path: &Path) -> Option<String> {
         let project_key = self.get_project_key(project_path)?;
         self.state
@@ -54,25 +76,43 @@ impl LocalStateManager {
             )
         })?;
 
-        // Preserve existing backends when updating current branch
-        let existing_backends = self
-            .state
-            .projects
-            .get(&project_key)
-            .and_then(|p| p.backends.clone());
-
-        let project_state = ProjectState {
-            current_branch: branch,
-            last_updated: chrono::Utc::now(),
-            backends: existing_backends,
-        };
-
-        self.state.projects.insert(project_key, project_state);
+        let now = chrono::Utc::now();
+        let project_state = self.project_entry(project_key);
+        if let Some(ref name) = branch {
+            project_state.recent_branches.retain(|r| &r.name != name);
+            project_state.recent_branches.insert(
+                0,
+                RecentBranch {
+                    name: name.clone(),
+                    used_at: now,
+                },
+            );
+            project_state.recent_branches.truncate(MAX_RECENT_BRANCHES);
+        }
+        project_state.current_branch = branch;
+        project_state.last_updated = now;
         self.save_state()?;
 
         Ok(())
     }
 
+    /// Recently used branches for a project, newest first.
+    pub fn get_recent_branches(&self, project_path: &Path) -> Vec<RecentBranch> {
+        self.get_project_key(project_path)
+            .and_then(|key| self.state.projects.get(&key))
+            .map(|project| project.recent_branches.clone())
+            .unwrap_or_default()
+    }
+
+    /// The most recently used branch other than the current one (like `cd -`).
+    pub fn get_previous_branch(&self, project_path: &Path) -> Option<String> {
+        let current = self.get_current_branch(project_path);
+        self.get_recent_branches(project_path)
+            .into_iter()
+            .map(|r| r.name)
+            .find(|name| Some(name) != current.as_ref())
+    }
+
     pub fn get_backends(&self, project_path: &Path) -> Option<Vec<NamedBackendConfig>> {
         let project_key = self.get_project_key(project_path)?;
         self.state
@@ -93,16 +133,9 @@ impl LocalStateManager {
             )
         })?;
 
-        let existing = self.state.projects.get(&project_key);
-        let current_branch = existing.and_then(|p| p.current_branch.clone());
-
-        let project_state = ProjectState {
-            current_branch,
-            last_updated: chrono::Utc::now(),
-            backends: Some(backends),
-        };
-
-        self.state.projects.insert(project_key, project_state);
+        let project_state = self.project_entry(project_key);
+        project_state.backends = Some(backends);
+        project_state.last_updated = chrono::Utc::now();
         self.save_state()?;
         Ok(())
     }
@@ -120,9 +153,10 @@ impl LocalStateManager {
             )
         })?;
 
-        let existing = self.state.projects.get(&project_key);
-        let current_branch = existing.and_then(|p| p.current_branch.clone());
-        let mut backends = existing
+        let mut backends = self
+            .state
+            .projects
+            .get(&project_key)
             .and_then(|p| p.backends.clone())
             .unwrap_or_default();
 
@@ -143,13 +177,9 @@ impl LocalStateManager {
             backends.push(backend);
         }
 
-        let project_state = ProjectState {
-            current_branch,
-            last_updated: chrono::Utc::now(),
-            backends: Some(backends),
-        };
-
-        self.state.projects.insert(project_key, project_state);
+        let project_state = self.project_entry(project_key);
+        project_state.backends = Some(backends);
+        project_state.last_updated = chrono::Utc::now();
         self.save_state()?;
         Ok(())
     }
@@ -208,6 +238,18 @@ impl LocalStateManager {
         Ok(())
     }
 
+    fn project_entry(&mut self, project_key: String) -> &mut ProjectState {
+        self.state
+            .projects
+            .entry(project_key)
+            .or_insert_with(|| ProjectState {
+                current_branch: None,
+                last_updated: chrono::Utc::now(),
+                backends: None,
+                recent_branches: Vec::new(),
+            })
+    }
+
     fn get_project_key(&self, project_path: &Path) -> Option<String> {
         // Use the canonical path of the directory containing .pgbranch.yml as the project key
         project_path
@@ -217,9 +259,7 @@ impl LocalStateManager {
     }
 
     fn get_state_file_path() -> Result<PathBuf> {
-        let config_dir = dirs::config_dir()
-            .context("Failed to get user config directory")?
-            .join("pgbranch");
+        let config_dir = crate::paths::config_dir();
 
         // Ensure the config directory exists
         fs::create_dir_all(&config_dir).with_context(|| {
@@ -323,4 +363,30 @@ mod tests {
         manager.set_current_branch(&config_path, None).unwrap();
         assert_eq!(manager.get_current_branch(&config_path), None);
     }
+
+    #[test]
+    fn test_recent_branches_and_previous() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join(".pgbranch.yml");
+
+        let mut manager = LocalStateManager::new().unwrap();
+        assert_eq!(manager.get_previous_branch(&config_path), None);
+
+        for branch in ["feature_a", "feature_b", "feature_a"] {
+            manager
+                .set_current_branch(&config_path, Some(branch.to_string()))
+                .unwrap();
+        }
+
+        let recent: Vec<String> = manager
+            .get_recent_branches(&config_path)
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(recent, vec!["feature_a", "feature_b"]);
+        assert_eq!(
+            manager.get_previous_branch(&config_path),
+            Some("feature_b".to_string())
+        );
+    }
 }
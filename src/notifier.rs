@@ -0,0 +1,92 @@
+use crate::config::{Config, NotificationsConfig};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Operations that finish faster than this don't notify unless they fail.
+const DEFAULT_MIN_DURATION: Duration = Duration::from_secs(10);
+
+/// Sends desktop and webhook notifications when long-running operations finish.
+pub struct Notifier {
+    config: NotificationsConfig,
+}
+
+impl Notifier {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let notifications = config.notifications.clone()?;
+        if !notifications.desktop && notifications.webhook.is_none() {
+            return None;
+        }
+        Some(Self {
+            config: notifications,
+        })
+    }
+
+    /// Report the outcome of an operation started at `started`.
+    ///
+    /// Delivery problems are logged and never affect the operation's result.
+    pub async fn operation_finished<T>(
+        &self,
+        operation: &str,
+        branch_name: &str,
+        started: Instant,
+        result: &anyhow::Result<T>,
+    ) {
+        let elapsed = started.elapsed();
+        let min_duration = self
+            .config
+            .min_duration_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_MIN_DURATION);
+
+        let message = match result {
+            Ok(_) if elapsed < min_duration => return,
+            Ok(_) => format!(
+                "{} '{}' finished in {}s",
+                operation,
+                branch_name,
+                elapsed.as_secs()
+            ),
+            Err(e) => format!("{} '{}' failed: {}", operation, branch_name, e),
+        };
+
+        if self.config.desktop {
+            if let Err(e) = send_desktop(&message).await {
+                log::warn!("Failed to send desktop notification: {}", e);
+            }
+        }
+        if let Some(ref url) = self.config.webhook {
+            if let Err(e) = send_webhook(url, &message).await {
+                log::warn!("Failed to send webhook notification: {}", e);
+            }
+        }
+    }
+}
+
+async fn send_desktop(message: &str) -> anyhow::Result<()> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification \"{}\" with title \"pgbranch\"",
+            message.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        let mut cmd = Command::new("osascript");
+        cmd.args(["-e", &script]);
+        cmd
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["pgbranch", message]);
+        cmd
+    };
+
+    let status = cmd.status().await?;
+    if !status.success() {
+        anyhow::bail!("notifier exited with {}", status);
+    }
+    Ok(())
+}
+
+async fn send_webhook(url: &str, message: &str) -> anyhow::Result<()> {
+    let text = format!("pgbranch: {}", message);
+    // `text` is read by Slack/Mattermost, `content` by Discord
+    let body = serde_json::json!({ "text": text, "content": text });
+    crate::webhook::post_json(url, &body).await
+}
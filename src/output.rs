@@ -0,0 +1,73 @@
+//! `--json` output. Every document carries `schema_version` so tooling can
+//! tell which shape it's reading, and the JSON Schemas in `schemas/` (also
+//! printed by `pgbranch schema` and `--output schema`) describe the outputs
+//! of `list`, `status`, `connection` and `doctor`.
+//!
+//! `schema_version` only goes up when an output changes incompatibly: a field
+//! is removed or renamed, or its type or meaning changes. New fields can
+//! appear in any release without a bump.
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Version of the `--json` output shapes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The published schemas, by the command whose output they describe.
+const SCHEMAS: &[(&str, &str)] = &[
+    ("list", include_str!("../schemas/list.json")),
+    ("status", include_str!("../schemas/status.json")),
+    ("connection", include_str!("../schemas/connection.json")),
+    ("doctor", include_str!("../schemas/doctor.json")),
+];
+
+/// `value` as JSON with `schema_version` first in it. A list gets it in each
+/// of its objects instead, so it stays a list.
+pub fn versioned<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<Value> {
+    Ok(match serde_json::to_value(value)? {
+        Value::Object(map) => Value::Object(with_version(map)),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| match item {
+                    Value::Object(map) => Value::Object(with_version(map)),
+                    other => other,
+                })
+                .collect(),
+        ),
+        other => other,
+    })
+}
+
+fn with_version(map: Map<String, Value>) -> Map<String, Value> {
+    let mut versioned = Map::with_capacity(map.len() + 1);
+    versioned.insert("schema_version".to_string(), Value::from(SCHEMA_VERSION));
+    versioned.extend(map.into_iter().filter(|(key, _)| key != "schema_version"));
+    versioned
+}
+
+/// Print `value` as the command's `--json` output.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&versioned(value)?)?);
+    Ok(())
+}
+
+/// The JSON Schema of the named command's `--json` output.
+pub fn schema(command: &str) -> Result<&'static str> {
+    SCHEMAS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, schema)| *schema)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No published schema for '{}'; available: {}",
+                command,
+                schema_names().join(", ")
+            )
+        })
+}
+
+pub fn schema_names() -> Vec<&'static str> {
+    SCHEMAS.iter().map(|(name, _)| *name).collect()
+}
@@ -0,0 +1,35 @@
+//! Per-user directories pgbranch keeps its files in. `XDG_CONFIG_HOME` and
+//! `XDG_DATA_HOME` are honored on every platform when set, so one setting
+//! moves `local_state.yml`, `credentials.yml` and the local backend's data
+//! together, e.g. for tests; otherwise the platform's usual place is used.
+
+use std::path::PathBuf;
+
+/// `pgbranch` under the user's config directory (`~/.config` on Linux).
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME")
+        .or_else(dirs::config_dir)
+        .unwrap_or_else(|| home().join(".config"))
+        .join("pgbranch")
+}
+
+/// `pgbranch` under the user's data directory (`~/.local/share` on Linux).
+#[allow(dead_code)] // only the local and mock backends keep data
+pub fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME")
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(|| home().join(".local").join("share"))
+        .join("pgbranch")
+}
+
+/// The directory in `var`, if it's set to an absolute path as the XDG spec
+/// requires; relative ones are ignored.
+fn xdg_dir(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+fn home() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from("."))
+}
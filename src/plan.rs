@@ -0,0 +1,452 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::backends::{BranchInfo, ContainerLimits, DatabaseBranchingBackend, PlannedAction};
+use crate::config::BranchDefinition;
+use crate::manifest::strip_url_password;
+
+const BRANCHES_FILE_VERSION: u32 = 1;
+const PLAN_VERSION: u32 = 1;
+
+/// Standing branches a team wants every database to have, read from
+/// `branches.yml` (or the `branches:` section of `.pgbranch.yml`) by
+/// `pgbranch plan` and `pgbranch apply`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BranchesFile {
+    #[serde(default = "default_branches_file_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub branches: Vec<BranchDefinition>,
+    /// Delete branches that aren't declared
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prune: bool,
+}
+
+fn default_branches_file_version() -> u32 {
+    BRANCHES_FILE_VERSION
+}
+
+impl BranchesFile {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read branches file: {}", path.display()))?;
+        let file: Self = serde_yaml_ng::from_str(&content)
+            .with_context(|| format!("Failed to parse branches file: {}", path.display()))?;
+        if file.version > BRANCHES_FILE_VERSION {
+            anyhow::bail!(
+                "Branches file {} has version {}; this pgbranch supports up to {}",
+                path.display(),
+                file.version,
+                BRANCHES_FILE_VERSION
+            );
+        }
+        file.validate()
+            .with_context(|| format!("Invalid branches file: {}", path.display()))?;
+        Ok(file)
+    }
+
+    /// The `branches:` section of `.pgbranch.yml`, which never prunes.
+    pub fn from_config(branches: &[BranchDefinition]) -> Result<Self> {
+        let file = Self {
+            version: BRANCHES_FILE_VERSION,
+            branches: branches.to_vec(),
+            prune: false,
+        };
+        file.validate()
+            .context("Invalid 'branches' section in .pgbranch.yml")?;
+        Ok(file)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut declared = HashSet::new();
+        for branch in &self.branches {
+            if !declared.insert(branch.name.as_str()) {
+                anyhow::bail!("branch '{}' is declared twice", branch.name);
+            }
+            branch.ttl()?;
+            limits(branch)?;
+        }
+        // Parents have to be created first, so they must come first
+        let mut seen = HashSet::new();
+        for branch in &self.branches {
+            if let Some(parent) = &branch.from {
+                if declared.contains(parent.as_str()) && !seen.contains(parent.as_str()) {
+                    anyhow::bail!(
+                        "branch '{}' is created from '{}', which must be declared before it",
+                        branch.name,
+                        parent
+                    );
+                }
+            }
+            seen.insert(branch.name.as_str());
+        }
+        Ok(())
+    }
+}
+
+fn limits(branch: &BranchDefinition) -> Result<Option<ContainerLimits>> {
+    let Some(resources) = &branch.resources else {
+        return Ok(None);
+    };
+    let with_branch = || format!("Invalid resources for branch '{}'", branch.name);
+    Ok(Some(ContainerLimits {
+        memory_bytes: resources.memory_bytes().with_context(with_branch)?,
+        nano_cpus: resources.nano_cpus().with_context(with_branch)?,
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Create,
+    Seed,
+    SetReadOnly,
+    SetLimits,
+    Delete,
+}
+
+/// One step towards the declared branches, with what it will do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub branch: String,
+    /// Why an existing branch is deleted, when it's declared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<ContainerLimits>,
+    pub actions: Vec<PlannedAction>,
+}
+
+impl Change {
+    fn new(kind: ChangeKind, branch: &str, actions: Vec<PlannedAction>) -> Self {
+        Self {
+            kind,
+            branch: branch.to_string(),
+            reason: None,
+            from: None,
+            source: None,
+            read_only: None,
+            limits: None,
+            actions,
+        }
+    }
+}
+
+/// The changes `pgbranch plan --out` saves and `pgbranch apply <plan>` carries out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub database: String,
+    /// The declared branches the plan was computed from, to detect a stale plan
+    pub desired: BranchesFile,
+    pub changes: Vec<Change>,
+}
+
+impl Plan {
+    /// Compare the declared branches with the ones `backend` has.
+    ///
+    /// Missing branches are created, seeded and configured. Existing ones keep
+    /// their data unless they've outlived their `ttl`, in which case they're
+    /// recreated; their read-only mode and limits are brought in line (read-only
+    /// mode only while they're running). Undeclared branches are only deleted
+    /// when `prune` is set.
+    pub async fn compute(
+        database: &str,
+        backend: &dyn DatabaseBranchingBackend,
+        desired: &BranchesFile,
+    ) -> Result<Self> {
+        let existing: HashMap<String, BranchInfo> = backend
+            .list_branches()
+            .await?
+            .into_iter()
+            .map(|b| (b.name.clone(), b))
+            .collect();
+        let now = Utc::now();
+
+        let mut changes = Vec::new();
+        let mut pending = HashSet::new();
+        for branch in &desired.branches {
+            if branch.read_only.is_some() && !backend.supports_read_only() {
+                anyhow::bail!(
+                    "Branch '{}' sets read_only, but backend '{}' does not support read-only branches",
+                    branch.name,
+                    backend.backend_name()
+                );
+            }
+            let limits = limits(branch)?;
+            if limits.is_some() && !backend.supports_limits() {
+                anyhow::bail!(
+                    "Branch '{}' sets resources, but backend '{}' does not support resource limits",
+                    branch.name,
+                    backend.backend_name()
+                );
+            }
+
+            if let Some(info) = existing.get(&branch.name) {
+                let expired = match (branch.ttl()?, info.created_at) {
+                    (Some(ttl), Some(created_at)) => created_at + ttl <= now,
+                    _ => false,
+                };
+                if !expired {
+                    let running = info.state.as_deref().is_none_or(|s| s == "running");
+                    if let Some(read_only) = branch.read_only {
+                        if running && backend.branch_read_only(&branch.name).await? != read_only {
+                            changes.push(read_only_change(&branch.name, read_only));
+                        }
+                    }
+                    if let Some(limits) = limits {
+                        if backend.branch_limits(&branch.name).await? != limits {
+                            changes.push(limits_change(&branch.name, limits));
+                        }
+                    }
+                    continue;
+                }
+
+                let mut change = Change::new(
+                    ChangeKind::Delete,
+                    &branch.name,
+                    backend.plan_delete_branch(&branch.name).await?,
+                );
+                change.reason = Some(format!(
+                    "older than its ttl of {}",
+                    branch.ttl.as_deref().unwrap_or_default()
+                ));
+                changes.push(change);
+            }
+
+            let actions = match &branch.from {
+                // The parent is created by this plan, so the backend can't plan the clone yet
+                Some(parent) if pending.contains(parent.as_str()) => vec![PlannedAction::new(
+                    backend.backend_type(),
+                    format!(
+                        "Clone '{}' from '{}' once it has been created",
+                        branch.name, parent
+                    ),
+                )],
+                // Recreating: the old branch is gone by the time this runs
+                from if existing.contains_key(&branch.name) => vec![PlannedAction::new(
+                    backend.backend_type(),
+                    match from {
+                        Some(parent) => {
+                            format!("Create '{}' again from '{}'", branch.name, parent)
+                        }
+                        None => format!("Create '{}' again", branch.name),
+                    },
+                )],
+                from => {
+                    backend
+                        .plan_create_branch(&branch.name, from.as_deref())
+                        .await?
+                }
+            };
+            let mut change = Change::new(ChangeKind::Create, &branch.name, actions);
+            change.from = branch.from.clone();
+            changes.push(change);
+            pending.insert(branch.name.as_str());
+
+            if let Some(source) = &branch.seed {
+                let source = strip_url_password(source);
+                let mut change = Change::new(
+                    ChangeKind::Seed,
+                    &branch.name,
+                    vec![PlannedAction::new(
+                        "postgres",
+                        format!("Load {} into '{}'", source, branch.name),
+                    )],
+                );
+                change.source = Some(source);
+                changes.push(change);
+            }
+            // Seeding needs the branch writable, so read-only mode comes after it
+            if branch.read_only == Some(true) {
+                changes.push(read_only_change(&branch.name, true));
+            }
+            if let Some(limits) = limits {
+                changes.push(limits_change(&branch.name, limits));
+            }
+        }
+
+        if desired.prune {
+            let declared: HashSet<&str> =
+                desired.branches.iter().map(|b| b.name.as_str()).collect();
+            let mut undeclared: Vec<&String> = existing
+                .keys()
+                .filter(|name| !declared.contains(name.as_str()))
+                .collect();
+            undeclared.sort();
+            for name in undeclared {
+                changes.push(Change::new(
+                    ChangeKind::Delete,
+                    name,
+                    backend.plan_delete_branch(name).await?,
+                ));
+            }
+        }
+
+        Ok(Self {
+            version: PLAN_VERSION,
+            database: database.to_string(),
+            desired: desired.clone(),
+            changes,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan: {}", path.display()))?;
+        let plan: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse plan: {}", path.display()))?;
+        if plan.version > PLAN_VERSION {
+            anyhow::bail!(
+                "Plan {} has version {}; this pgbranch supports up to {}",
+                path.display(),
+                plan.version,
+                PLAN_VERSION
+            );
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content + "\n")
+            .with_context(|| format!("Failed to write plan: {}", path.display()))
+    }
+
+    /// Whether `other` makes the same changes, ignoring how they were described.
+    pub fn same_changes(&self, other: &Plan) -> bool {
+        let key = |c: &Change| (c.kind, c.branch.clone());
+        self.changes
+            .iter()
+            .map(key)
+            .eq(other.changes.iter().map(key))
+    }
+
+    /// Carry out the changes in order, reporting each one as it finishes.
+    /// Stops at the first failure; changes already made stay made.
+    pub async fn apply(
+        &self,
+        backend: &dyn DatabaseBranchingBackend,
+        mut on_change: impl FnMut(&Change),
+    ) -> Result<()> {
+        for change in &self.changes {
+            match change.kind {
+                ChangeKind::Create => {
+                    backend
+                        .create_branch(&change.branch, change.from.as_deref())
+                        .await
+                        .with_context(|| format!("Failed to create branch '{}'", change.branch))?;
+                }
+                ChangeKind::Seed => {
+                    // The saved source has its password masked; seed from the declared one
+                    let source = self
+                        .desired
+                        .branches
+                        .iter()
+                        .find(|b| b.name == change.branch)
+                        .and_then(|b| b.seed.as_deref())
+                        .with_context(|| {
+                            format!("No seed source declared for branch '{}'", change.branch)
+                        })?;
+                    backend
+                        .seed_from_source(&change.branch, source)
+                        .await
+                        .with_context(|| format!("Failed to seed branch '{}'", change.branch))?;
+                }
+                ChangeKind::SetReadOnly => {
+                    backend
+                        .set_branch_read_only(&change.branch, change.read_only == Some(true))
+                        .await
+                        .with_context(|| {
+                            format!("Failed to change read-only mode of '{}'", change.branch)
+                        })?;
+                }
+                ChangeKind::SetLimits => {
+                    backend
+                        .set_branch_limits(&change.branch, &change.limits.unwrap_or_default())
+                        .await
+                        .with_context(|| {
+                            format!("Failed to change resource limits of '{}'", change.branch)
+                        })?;
+                }
+                ChangeKind::Delete => {
+                    backend
+                        .delete_branch(&change.branch)
+                        .await
+                        .with_context(|| format!("Failed to delete branch '{}'", change.branch))?;
+                }
+            }
+            on_change(change);
+        }
+        Ok(())
+    }
+
+    pub fn deleted_branches(&self) -> Vec<String> {
+        self.changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Delete)
+            .map(|c| c.branch.clone())
+            .collect()
+    }
+}
+
+fn read_only_change(branch_name: &str, read_only: bool) -> Change {
+    let description = if read_only {
+        format!(
+            "Make new sessions on '{}' read-only (default_transaction_read_only = on)",
+            branch_name
+        )
+    } else {
+        format!(
+            "Make new sessions on '{}' writable (reset default_transaction_read_only)",
+            branch_name
+        )
+    };
+    let mut change = Change::new(
+        ChangeKind::SetReadOnly,
+        branch_name,
+        vec![PlannedAction::new("postgres", description)],
+    );
+    change.read_only = Some(read_only);
+    change
+}
+
+fn limits_change(branch_name: &str, limits: ContainerLimits) -> Change {
+    let mut change = Change::new(
+        ChangeKind::SetLimits,
+        branch_name,
+        vec![PlannedAction::new(
+            "docker",
+            format!(
+                "Limit the container of '{}' to {}",
+                branch_name,
+                describe_limits(&limits)
+            ),
+        )],
+    );
+    change.limits = Some(limits);
+    change
+}
+
+pub fn describe_limits(limits: &ContainerLimits) -> String {
+    let memory = match limits.memory_bytes {
+        Some(bytes) => format!("{} MiB memory", bytes / (1024 * 1024)),
+        None => "unlimited memory".to_string(),
+    };
+    let cpus = match limits.nano_cpus {
+        Some(nano_cpus) => format!("{} CPUs", nano_cpus as f64 / 1e9),
+        None => "unlimited CPUs".to_string(),
+    };
+    format!("{}, {}", memory, cpus)
+}
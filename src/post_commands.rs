@@ -1,16 +1,26 @@
-use crate::config::{Config, PostCommand, ReplaceConfig, TemplateContext};
+use crate::backends::ConnectionInfo;
+use crate::config::{
+    CaptureMode, ConditionConfig, Config, PostCommand, PostCommandCondition, PostCommandConfig,
+    PostCommandEvent, ReplaceConfig, ReplaceFormat, TemplateContext,
+};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::process::Command;
+use futures_util::future::join_all;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+const DEFAULT_CAPTURE_FILE: &str = ".pgbranch-post-commands.log";
 
 pub struct PostCommandExecutor<'a> {
     config: &'a Config,
     context: TemplateContext,
     working_dir: std::path::PathBuf,
+    connection_string: Option<String>,
+    event: PostCommandEvent,
 }
 
 impl<'a> PostCommandExecutor<'a> {
-    pub fn new(config: &'a Config, branch_name: &str) -> Result<Self> {
+    pub fn new(config: &'a Config, branch_name: &str, event: PostCommandEvent) -> Result<Self> {
         let context = TemplateContext::new(config, branch_name);
         let working_dir =
             std::env::current_dir().context("Failed to get current working directory")?;
@@ -19,9 +29,41 @@ impl<'a> PostCommandExecutor<'a> {
             config,
             context,
             working_dir,
+            connection_string: None,
+            event,
         })
     }
 
+    /// Use the connection details reported by a backend instead of the
+    /// `database` section of the config.
+    pub fn with_connection_info(mut self, conn: &ConnectionInfo, backend_type: &str) -> Self {
+        self.context.db_name = conn.database.clone();
+        self.context.db_host = conn.host.clone();
+        self.context.db_port = conn.port;
+        self.context.db_user = conn.user.clone();
+        self.context.db_password = conn.password.clone();
+        self.context.backend = backend_type.to_string();
+        self.connection_string = conn.connection_string.clone();
+        self
+    }
+
+    /// Variables injected into the environment of every post-command.
+    fn pgbranch_environment(&self) -> Vec<(&'static str, String)> {
+        let db_url = self
+            .connection_string
+            .clone()
+            .unwrap_or_else(|| self.context.database_url());
+        vec![
+            ("PGBRANCH_BRANCH", self.context.branch_name.clone()),
+            ("PGBRANCH_BACKEND", self.context.backend.clone()),
+            ("PGBRANCH_DB_NAME", self.context.db_name.clone()),
+            ("PGBRANCH_DB_HOST", self.context.db_host.clone()),
+            ("PGBRANCH_PORT", self.context.db_port.to_string()),
+            ("PGBRANCH_DB_USER", self.context.db_user.clone()),
+            ("PGBRANCH_DB_URL", db_url),
+        ]
+    }
+
     pub async fn execute_all_post_commands(&self) -> Result<()> {
         if self.config.post_commands.is_empty() {
             log::debug!("No post-commands configured");
@@ -30,24 +72,51 @@ impl<'a> PostCommandExecutor<'a> {
 
         println!("🔧 Executing post-commands...");
 
-        for (index, post_command) in self.config.post_commands.iter().enumerate() {
-            match self.execute_post_command(post_command, index).await {
-                Ok(_) => {}
-                Err(e) => {
-                    let continue_on_error = match post_command {
-                        PostCommand::Simple(_) => false,
-                        PostCommand::Complex(config) => config.continue_on_error.unwrap_or(false),
-                        PostCommand::Replace(config) => config.continue_on_error.unwrap_or(false),
-                    };
-
-                    if continue_on_error {
-                        log::warn!("Post-command {} failed but continuing: {}", index + 1, e);
-                        println!("⚠️  Command {} failed but continuing: {}", index + 1, e);
-                    } else {
-                        return Err(e);
-                    }
+        let commands = &self.config.post_commands;
+        for wave in plan_waves(commands)? {
+            if wave.len() > 1 {
+                println!("⏩ Running {} post-commands in parallel", wave.len());
+            }
+
+            let results = join_all(wave.iter().map(|&index| async move {
+                (
+                    index,
+                    self.execute_post_command(&commands[index], index).await,
+                )
+            }))
+            .await;
+
+            let mut failures = Vec::new();
+            for (index, result) in results {
+                let Err(e) = result else { continue };
+                if commands[index].continue_on_error() {
+                    log::warn!("Post-command {} failed but continuing: {}", index + 1, e);
+                    println!("⚠️  Command {} failed but continuing: {}", index + 1, e);
+                } else {
+                    failures.push((index, e));
                 }
             }
+
+            if failures.len() == 1 {
+                return Err(failures.remove(0).1);
+            }
+            if !failures.is_empty() {
+                let details: Vec<String> = failures
+                    .iter()
+                    .map(|(index, e)| {
+                        let label = commands[*index]
+                            .name()
+                            .map(|n| n.to_string())
+                            .unwrap_or_else(|| format!("command {}", index + 1));
+                        format!("  - {}: {}", label, e)
+                    })
+                    .collect();
+                anyhow::bail!(
+                    "{} post-commands failed:\n{}",
+                    failures.len(),
+                    details.join("\n")
+                );
+            }
         }
 
         println!("✅ All post-commands completed successfully");
@@ -60,55 +129,36 @@ impl<'a> PostCommandExecutor<'a> {
                 let substituted_command = self
                     .config
                     .substitute_template_variables(command_str, &self.context);
-                self.execute_command_string(&substituted_command, None, None, index)
+                self.execute_command_string(&substituted_command, None, index)
                     .await
             }
             PostCommand::Complex(config) => {
+                if !self.should_run(
+                    config.name.as_deref(),
+                    config.condition.as_ref(),
+                    &config.only_on,
+                    "command",
+                    index,
+                )? {
+                    return Ok(());
+                }
+
                 let substituted_command = self
                     .config
                     .substitute_template_variables(&config.command, &self.context);
 
-                // Check condition if specified
-                if let Some(ref condition) = config.condition {
-                    if !self.evaluate_condition(condition)? {
-                        log::debug!(
-                            "Skipping command {} due to condition: {}",
-                            index + 1,
-                            condition
-                        );
-                        if let Some(ref name) = config.name {
-                            println!("⏭️  Skipped: {}", name);
-                        } else {
-                            println!("⏭️  Skipped command {}", index + 1);
-                        }
-                        return Ok(());
-                    }
-                }
-
-                self.execute_command_string(
-                    &substituted_command,
-                    config.working_dir.as_deref(),
-                    config.environment.as_ref(),
-                    index,
-                )
-                .await
+                self.execute_command_string(&substituted_command, Some(config), index)
+                    .await
             }
             PostCommand::Replace(config) => {
-                // Check condition if specified
-                if let Some(ref condition) = config.condition {
-                    if !self.evaluate_condition(condition)? {
-                        log::debug!(
-                            "Skipping replace {} due to condition: {}",
-                            index + 1,
-                            condition
-                        );
-                        if let Some(ref name) = config.name {
-                            println!("⏭️  Skipped: {}", name);
-                        } else {
-                            println!("⏭️  Skipped replace {}", index + 1);
-                        }
-                        return Ok(());
-                    }
+                if !self.should_run(
+                    config.name.as_deref(),
+                    config.condition.as_ref(),
+                    &config.only_on,
+                    "replace",
+                    index,
+                )? {
+                    return Ok(());
                 }
 
                 self.execute_replace_action(config, index).await
@@ -116,21 +166,117 @@ impl<'a> PostCommandExecutor<'a> {
         }
     }
 
+    /// Check the `only_on` event filter and `condition` of a post-command,
+    /// printing a skip notice when it should not run.
+    fn should_run(
+        &self,
+        name: Option<&str>,
+        condition: Option<&PostCommandCondition>,
+        only_on: &[PostCommandEvent],
+        kind: &str,
+        index: usize,
+    ) -> Result<bool> {
+        let Some(reason) = self.skip_reason(condition, only_on)? else {
+            return Ok(true);
+        };
+
+        log::debug!("Skipping {} {} due to {}", kind, index + 1, reason);
+        if let Some(name) = name {
+            println!("⏭️  Skipped: {}", name);
+        } else {
+            println!("⏭️  Skipped {} {}", kind, index + 1);
+        }
+        Ok(false)
+    }
+
+    fn skip_reason(
+        &self,
+        condition: Option<&PostCommandCondition>,
+        only_on: &[PostCommandEvent],
+    ) -> Result<Option<String>> {
+        if !only_on.is_empty() && !only_on.contains(&self.event) {
+            return Ok(Some(format!(
+                "only_on does not include '{}'",
+                self.event.as_str()
+            )));
+        }
+        Ok(match condition {
+            Some(condition) if !self.evaluate_condition(condition)? => {
+                Some(format!("condition: {}", condition))
+            }
+            _ => None,
+        })
+    }
+
+    /// Each post-command as it would run, with the reason it would be
+    /// skipped if it would be. Nothing is executed.
+    pub fn describe(&self) -> Result<Vec<(String, Option<String>)>> {
+        self.config
+            .post_commands
+            .iter()
+            .map(|post_command| {
+                Ok(match post_command {
+                    PostCommand::Simple(command) => (
+                        self.config
+                            .substitute_template_variables(command, &self.context),
+                        None,
+                    ),
+                    PostCommand::Complex(config) => (
+                        self.config
+                            .substitute_template_variables(&config.command, &self.context),
+                        self.skip_reason(config.condition.as_ref(), &config.only_on)?,
+                    ),
+                    PostCommand::Replace(config) => (
+                        format!("replace in {}", config.file),
+                        self.skip_reason(config.condition.as_ref(), &config.only_on)?,
+                    ),
+                })
+            })
+            .collect()
+    }
+
     async fn execute_command_string(
         &self,
         command: &str,
-        working_dir: Option<&str>,
-        environment: Option<&HashMap<String, String>>,
+        options: Option<&PostCommandConfig>,
         index: usize,
     ) -> Result<()> {
-        let cmd_working_dir = if let Some(wd) = working_dir {
+        let attempts = options.and_then(|o| o.retries).unwrap_or(0) + 1;
+
+        log::info!("Executing post-command {}: {}", index + 1, command);
+        println!("▶️  Executing: {}", command);
+
+        let mut attempt = 1;
+        loop {
+            match self.run_command_once(command, options).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < attempts => {
+                    log::warn!(
+                        "Post-command {} attempt {}/{} failed: {}",
+                        index + 1,
+                        attempt,
+                        attempts,
+                        e
+                    );
+                    println!("🔁 Retrying ({}/{}): {}", attempt + 1, attempts, command);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn run_command_once(
+        &self,
+        command: &str,
+        options: Option<&PostCommandConfig>,
+    ) -> Result<()> {
+        let cmd_working_dir = if let Some(wd) = options.and_then(|o| o.working_dir.as_deref()) {
             self.working_dir.join(wd)
         } else {
             self.working_dir.clone()
         };
-
-        log::info!("Executing post-command {}: {}", index + 1, command);
-        println!("▶️  Executing: {}", command);
+        let capture = options.and_then(|o| o.capture);
 
         let mut cmd = if cfg!(target_os = "windows") {
             let mut cmd = Command::new("cmd");
@@ -143,25 +289,65 @@ impl<'a> PostCommandExecutor<'a> {
         };
 
         cmd.current_dir(&cmd_working_dir);
+        // Ensure a timed-out command does not outlive pgbranch
+        cmd.kill_on_drop(true);
+
+        if capture == Some(CaptureMode::Inherit) {
+            cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        } else {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+
+        // Computed branch variables come first so explicit `environment` entries can override them
+        for (key, value) in self.pgbranch_environment() {
+            cmd.env(key, value);
+        }
 
         // Set environment variables
-        if let Some(env_vars) = environment {
+        if let Some(env_vars) = options.and_then(|o| o.environment.as_ref()) {
             for (key, value) in env_vars {
                 let substituted_value = self
                     .config
                     .substitute_template_variables(value, &self.context);
-                cmd.env(key, substituted_value);
+                // Secrets are read only now, so they stay out of the config
+                let resolved = crate::secret_ref::resolve(&substituted_value)
+                    .with_context(|| format!("Failed to resolve environment variable {}", key))?;
+                cmd.env(key, resolved);
             }
         }
 
-        let output = cmd
-            .output()
+        let child = cmd
+            .spawn()
             .with_context(|| format!("Failed to execute command: {}", command))?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
+        let output = match options.and_then(|o| o.timeout) {
+            Some(secs) => tokio::time::timeout(Duration::from_secs(secs), child.wait_with_output())
+                .await
+                .map_err(|_| anyhow::anyhow!("Command timed out after {}s: {}", secs, command))?,
+            None => child.wait_with_output().await,
+        }
+        .with_context(|| format!("Failed to execute command: {}", command))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
 
+        let log_path = if capture == Some(CaptureMode::File) {
+            let path = self.capture_file_path(options);
+            self.append_to_capture_file(&path, command, &output.status, &stdout, &stderr)?;
+            Some(path)
+        } else {
+            None
+        };
+
+        if !output.status.success() {
+            if let Some(path) = log_path {
+                return Err(anyhow::anyhow!(
+                    "Command failed with exit code {}: {} (output in {})",
+                    output.status.code().unwrap_or(-1),
+                    command,
+                    path.display()
+                ));
+            }
             return Err(anyhow::anyhow!(
                 "Command failed with exit code {}: {}\nStdout: {}\nStderr: {}",
                 output.status.code().unwrap_or(-1),
@@ -172,27 +358,69 @@ impl<'a> PostCommandExecutor<'a> {
         }
 
         // Print command output if it's not empty
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if !stdout.trim().is_empty() {
+        if capture.is_none() && !stdout.trim().is_empty() {
             println!("{}", stdout.trim());
         }
 
         Ok(())
     }
 
-    fn evaluate_condition(&self, condition: &str) -> Result<bool> {
+    fn capture_file_path(&self, options: Option<&PostCommandConfig>) -> std::path::PathBuf {
+        let file = options
+            .and_then(|o| o.capture_file.as_deref())
+            .unwrap_or(DEFAULT_CAPTURE_FILE);
+        self.resolve_path(file)
+    }
+
+    fn append_to_capture_file(
+        &self,
+        path: &std::path::Path,
+        command: &str,
+        status: &std::process::ExitStatus,
+        stdout: &str,
+        stderr: &str,
+    ) -> Result<()> {
+        use std::io::Write;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open capture file: {}", path.display()))?;
+        writeln!(
+            file,
+            "=== {} [{}] {} (exit {}) ===",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            self.context.branch_name,
+            command,
+            status.code().unwrap_or(-1)
+        )?;
+        file.write_all(stdout.as_bytes())?;
+        file.write_all(stderr.as_bytes())?;
+        Ok(())
+    }
+
+    fn evaluate_condition(&self, condition: &PostCommandCondition) -> Result<bool> {
+        match condition {
+            PostCommandCondition::Expression(expr) => self.evaluate_expression(expr),
+            PostCommandCondition::Structured(config) => self.evaluate_structured(config),
+        }
+    }
+
+    fn evaluate_expression(&self, condition: &str) -> Result<bool> {
         if let Some(file_path) = condition.strip_prefix("file_exists:") {
-            let substituted_path = self
-                .config
-                .substitute_template_variables(file_path, &self.context);
-            let full_path = self.working_dir.join(substituted_path);
-            Ok(full_path.exists())
+            Ok(self.resolve_path(file_path).exists())
         } else if let Some(dir_path) = condition.strip_prefix("dir_exists:") {
-            let substituted_path = self
-                .config
-                .substitute_template_variables(dir_path, &self.context);
-            let full_path = self.working_dir.join(substituted_path);
-            Ok(full_path.is_dir())
+            Ok(self.resolve_path(dir_path).is_dir())
+        } else if let Some(backend) = condition.strip_prefix("backend:") {
+            Ok(self.backend_matches(backend))
+        } else if let Some(pattern) = condition.strip_prefix("branch_matches:") {
+            self.branch_matches(pattern)
+        } else if let Some(var) = condition.strip_prefix("env_set:") {
+            Ok(env_var_set(var))
         } else if condition == "always" {
             Ok(true)
         } else if condition == "never" {
@@ -202,13 +430,57 @@ impl<'a> PostCommandExecutor<'a> {
         }
     }
 
+    fn evaluate_structured(&self, config: &ConditionConfig) -> Result<bool> {
+        if let Some(ref backend) = config.backend {
+            if !self.backend_matches(backend) {
+                return Ok(false);
+            }
+        }
+        if let Some(ref pattern) = config.branch_matches {
+            if !self.branch_matches(pattern)? {
+                return Ok(false);
+            }
+        }
+        if let Some(ref file_path) = config.file_exists {
+            if !self.resolve_path(file_path).exists() {
+                return Ok(false);
+            }
+        }
+        if let Some(ref dir_path) = config.dir_exists {
+            if !self.resolve_path(dir_path).is_dir() {
+                return Ok(false);
+            }
+        }
+        if let Some(ref var) = config.env_set {
+            if !env_var_set(var) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn resolve_path(&self, path: &str) -> std::path::PathBuf {
+        let substituted_path = self
+            .config
+            .substitute_template_variables(path, &self.context);
+        self.working_dir.join(substituted_path)
+    }
+
+    fn backend_matches(&self, backend: &str) -> bool {
+        crate::backends::factory::BackendInfo::canonical_name(backend)
+            == crate::backends::factory::BackendInfo::canonical_name(&self.context.backend)
+    }
+
+    fn branch_matches(&self, pattern: &str) -> Result<bool> {
+        let re = regex::Regex::new(pattern)
+            .with_context(|| format!("Invalid branch_matches regex: {}", pattern))?;
+        Ok(re.is_match(&self.context.branch_name))
+    }
+
     async fn execute_replace_action(&self, config: &ReplaceConfig, _index: usize) -> Result<()> {
-        let file_path = self
+        let file_pattern = self
             .config
             .substitute_template_variables(&config.file, &self.context);
-        let pattern = self
-            .config
-            .substitute_template_variables(&config.pattern, &self.context);
         let replacement = self
             .config
             .substitute_template_variables(&config.replacement, &self.context);
@@ -216,42 +488,559 @@ impl<'a> PostCommandExecutor<'a> {
         if let Some(ref name) = config.name {
             println!("🔄 Replacing: {}", name);
         } else {
-            println!("🔄 Replacing in file: {}", file_path);
+            println!("🔄 Replacing in file: {}", file_pattern);
         }
 
-        let file_exists = std::path::Path::new(&file_path).exists();
+        let edit = match config.key {
+            Some(ref key) => FileEdit::Key {
+                path: self
+                    .config
+                    .substitute_template_variables(key, &self.context),
+                format: config.format,
+            },
+            None => {
+                let pattern = config.pattern.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("Replace action needs either 'pattern' or 'key'")
+                })?;
+                let pattern = self
+                    .config
+                    .substitute_template_variables(pattern, &self.context);
+                FileEdit::Regex(
+                    regex::Regex::new(&pattern)
+                        .with_context(|| format!("Invalid regex pattern: {}", pattern))?,
+                )
+            }
+        };
 
-        if !file_exists {
-            if config.create_if_missing.unwrap_or(false) {
-                // Create file with the replacement content
-                std::fs::write(&file_path, &replacement)
-                    .with_context(|| format!("Failed to create file: {}", file_path))?;
-                println!("✅ Created file: {}", file_path);
+        if !is_glob(&file_pattern) {
+            let file_path = std::path::Path::new(&file_pattern);
+            if !file_path.exists() {
+                if !config.create_if_missing.unwrap_or(false) {
+                    return Err(anyhow::anyhow!("File does not exist: {}", file_pattern));
+                }
+                let content = match edit {
+                    // Create file with the replacement content
+                    FileEdit::Regex(_) => replacement,
+                    FileEdit::Key { .. } => edit.apply(file_path, "", &replacement)?,
+                };
+                std::fs::write(file_path, content)
+                    .with_context(|| format!("Failed to create file: {}", file_pattern))?;
+                println!("✅ Created file: {}", file_pattern);
                 return Ok(());
-            } else {
-                return Err(anyhow::anyhow!("File does not exist: {}", file_path));
             }
+            return self.edit_file(file_path, &edit, &replacement);
         }
 
-        // Read the file
-        let content = std::fs::read_to_string(&file_path)
-            .with_context(|| format!("Failed to read file: {}", file_path))?;
+        let files: Vec<std::path::PathBuf> = glob::glob(&file_pattern)
+            .with_context(|| format!("Invalid glob pattern: {}", file_pattern))?
+            .filter_map(|entry| entry.ok())
+            .filter(|path| path.is_file())
+            .collect();
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("No files match: {}", file_pattern));
+        }
+        for file_path in &files {
+            self.edit_file(file_path, &edit, &replacement)?;
+        }
+
+        Ok(())
+    }
 
-        // Use regex for pattern matching
-        let re = regex::Regex::new(&pattern)
-            .with_context(|| format!("Invalid regex pattern: {}", pattern))?;
+    fn edit_file(
+        &self,
+        file_path: &std::path::Path,
+        edit: &FileEdit,
+        replacement: &str,
+    ) -> Result<()> {
+        let display = file_path.display();
+        let content = std::fs::read_to_string(file_path)
+            .with_context(|| format!("Failed to read file: {}", display))?;
 
-        let new_content = re.replace_all(&content, replacement.as_str());
+        let new_content = edit.apply(file_path, &content, replacement)?;
 
         // Write back only if content changed
         if new_content != content {
-            std::fs::write(&file_path, new_content.as_ref())
-                .with_context(|| format!("Failed to write file: {}", file_path))?;
-            println!("✅ Updated file: {}", file_path);
+            std::fs::write(file_path, new_content)
+                .with_context(|| format!("Failed to write file: {}", display))?;
+            println!("✅ Updated file: {}", display);
         } else {
-            println!("ℹ️  No changes needed in: {}", file_path);
+            println!("ℹ️  No changes needed in: {}", display);
         }
 
         Ok(())
     }
 }
+
+/// How a replace action rewrites each target file.
+enum FileEdit {
+    Regex(regex::Regex),
+    Key {
+        path: String,
+        format: Option<ReplaceFormat>,
+    },
+}
+
+impl FileEdit {
+    fn apply(&self, file_path: &std::path::Path, content: &str, value: &str) -> Result<String> {
+        match self {
+            FileEdit::Regex(re) => Ok(re.replace_all(content, value).into_owned()),
+            FileEdit::Key { path, format } => {
+                let format = format
+                    .or_else(|| ReplaceFormat::from_path(file_path))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Cannot infer format of {}; set 'format' to yaml, json or toml",
+                            file_path.display()
+                        )
+                    })?;
+                let keys: Vec<&str> = path.split('.').collect();
+                if keys.iter().any(|k| k.is_empty()) {
+                    anyhow::bail!("Invalid key path: {}", path);
+                }
+                set_key(format, content, &keys, value)
+                    .with_context(|| format!("Failed to set '{}' in {}", path, file_path.display()))
+            }
+        }
+    }
+}
+
+/// Set a string value at a key path, creating intermediate tables as needed.
+fn set_key(format: ReplaceFormat, content: &str, keys: &[&str], value: &str) -> Result<String> {
+    let (parents, last) = keys.split_at(keys.len() - 1);
+    let last = last[0];
+
+    match format {
+        ReplaceFormat::Yaml => {
+            use serde_yaml_ng::{Mapping, Value};
+
+            let mut doc: Value = if content.trim().is_empty() {
+                Value::Mapping(Mapping::new())
+            } else {
+                serde_yaml_ng::from_str(content)?
+            };
+            let mut node = &mut doc;
+            for key in parents {
+                let map = node
+                    .as_mapping_mut()
+                    .ok_or_else(|| anyhow::anyhow!("'{}' is not inside a mapping", key))?;
+                node = map
+                    .entry(Value::from(*key))
+                    .or_insert_with(|| Value::Mapping(Mapping::new()));
+            }
+            node.as_mapping_mut()
+                .ok_or_else(|| anyhow::anyhow!("'{}' is not inside a mapping", last))?
+                .insert(Value::from(last), Value::from(value));
+            Ok(serde_yaml_ng::to_string(&doc)?)
+        }
+        ReplaceFormat::Json => {
+            use serde_json::{Map, Value};
+
+            let mut doc: Value = if content.trim().is_empty() {
+                Value::Object(Map::new())
+            } else {
+                serde_json::from_str(content)?
+            };
+            let mut node = &mut doc;
+            for key in parents {
+                let map = node
+                    .as_object_mut()
+                    .ok_or_else(|| anyhow::anyhow!("'{}' is not inside an object", key))?;
+                node = map
+                    .entry(key.to_string())
+                    .or_insert_with(|| Value::Object(Map::new()));
+            }
+            node.as_object_mut()
+                .ok_or_else(|| anyhow::anyhow!("'{}' is not inside an object", last))?
+                .insert(last.to_string(), Value::from(value));
+            Ok(format!("{}\n", serde_json::to_string_pretty(&doc)?))
+        }
+        ReplaceFormat::Toml => {
+            // toml_edit keeps comments and formatting of the untouched parts
+            let mut doc: toml_edit::DocumentMut = content.parse()?;
+            let mut table = doc.as_table_mut();
+            for key in parents {
+                table = table
+                    .entry(key)
+                    .or_insert_with(toml_edit::table)
+                    .as_table_mut()
+                    .ok_or_else(|| anyhow::anyhow!("'{}' is not a table", key))?;
+            }
+            let mut item = toml_edit::value(value);
+            // Keep any inline comment attached to the previous value
+            if let (Some(new), Some(old)) = (
+                item.as_value_mut(),
+                table.get(last).and_then(|i| i.as_value()),
+            ) {
+                *new.decor_mut() = old.decor().clone();
+            }
+            table[last] = item;
+            Ok(doc.to_string())
+        }
+    }
+}
+
+/// Placeholders filled in by `Config::substitute_template_variables`.
+const TEMPLATE_VARIABLES: &[&str] = &[
+    "branch_name",
+    "db_name",
+    "db_host",
+    "db_port",
+    "db_user",
+    "db_password",
+    "template_db",
+    "prefix",
+];
+
+/// A mistake in the post-commands found by [`lint`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LintProblem {
+    /// The command's name, or its position
+    pub command: String,
+    pub message: String,
+}
+
+/// Check the post-commands without running them: placeholders, regexes,
+/// globs, conditions, files and working directories they refer to, and
+/// `depends_on`. Paths are relative to `working_dir`.
+pub fn lint(config: &Config, working_dir: &std::path::Path) -> Vec<LintProblem> {
+    let mut problems = Vec::new();
+    for (index, post_command) in config.post_commands.iter().enumerate() {
+        let label = match post_command.name() {
+            Some(name) => format!("'{}'", name),
+            None => format!("post-command {}", index + 1),
+        };
+        let mut report = |message: String| {
+            problems.push(LintProblem {
+                command: label.clone(),
+                message,
+            })
+        };
+
+        match post_command {
+            PostCommand::Simple(command) => check_placeholders("command", command, &mut report),
+            PostCommand::Complex(command) => {
+                check_placeholders("command", &command.command, &mut report);
+                for (key, value) in command.environment.iter().flatten() {
+                    check_placeholders(&format!("environment {}", key), value, &mut report);
+                }
+                if let Some(ref dir) = command.working_dir {
+                    if !working_dir.join(dir).is_dir() {
+                        report(format!("working_dir '{}' does not exist", dir));
+                    }
+                }
+                if let Some(ref condition) = command.condition {
+                    check_condition(condition, &mut report);
+                }
+            }
+            PostCommand::Replace(replace) => {
+                if replace.action != "replace" {
+                    report(format!(
+                        "unknown action '{}'; the only action is 'replace'",
+                        replace.action
+                    ));
+                }
+                check_placeholders("file", &replace.file, &mut report);
+                check_placeholders("replacement", &replace.replacement, &mut report);
+                match (&replace.pattern, &replace.key) {
+                    (None, None) => report("needs either 'pattern' or 'key'".to_string()),
+                    (Some(_), Some(_)) => {
+                        report("has both 'pattern' and 'key'; 'pattern' is ignored".to_string())
+                    }
+                    _ => {}
+                }
+                if let Some(ref key) = replace.key {
+                    check_placeholders("key", key, &mut report);
+                    if replace.format.is_none()
+                        && ReplaceFormat::from_path(std::path::Path::new(&replace.file)).is_none()
+                    {
+                        report(format!(
+                            "can't tell the format of '{}' from its extension; set 'format'",
+                            replace.file
+                        ));
+                    }
+                } else if let Some(ref pattern) = replace.pattern {
+                    check_placeholders("pattern", pattern, &mut report);
+                    // Placeholders are filled in before compiling, so compile what they leave
+                    let sample = fill_placeholders(pattern);
+                    if let Err(e) = regex::Regex::new(&sample) {
+                        report(format!("pattern does not compile: {}", e));
+                    }
+                }
+                // Files named after the branch can't be checked ahead of time
+                if !has_placeholder(&replace.file) {
+                    let file = working_dir.join(&replace.file);
+                    if is_glob(&replace.file) {
+                        match glob::glob(&file.to_string_lossy()) {
+                            Err(e) => report(format!("file glob is invalid: {}", e)),
+                            Ok(mut paths) => {
+                                if paths.next().is_none() {
+                                    report(format!("no files match '{}'", replace.file));
+                                }
+                            }
+                        }
+                    } else if !file.exists() && !replace.create_if_missing.unwrap_or(false) {
+                        report(format!(
+                            "file '{}' does not exist (set create_if_missing to create it)",
+                            replace.file
+                        ));
+                    }
+                }
+                if let Some(ref condition) = replace.condition {
+                    check_condition(condition, &mut report);
+                }
+            }
+        }
+    }
+
+    if let Err(e) = plan_waves(&config.post_commands) {
+        problems.push(LintProblem {
+            command: "depends_on".to_string(),
+            message: e.to_string(),
+        });
+    }
+    problems
+}
+
+/// `{name}` placeholders in `text`, leaving out shell `${VAR}` expansions.
+fn placeholders(text: &str) -> Vec<String> {
+    let re = regex::Regex::new(r"(^|[^$])\{([a-z_]+)\}").expect("valid placeholder regex");
+    re.captures_iter(text)
+        .map(|captures| captures[2].to_string())
+        .collect()
+}
+
+fn has_placeholder(text: &str) -> bool {
+    placeholders(text)
+        .iter()
+        .any(|name| TEMPLATE_VARIABLES.contains(&name.as_str()))
+}
+
+fn fill_placeholders(text: &str) -> String {
+    TEMPLATE_VARIABLES
+        .iter()
+        .fold(text.to_string(), |text, name| {
+            text.replace(&format!("{{{}}}", name), "sample")
+        })
+}
+
+fn check_placeholders(field: &str, text: &str, report: &mut impl FnMut(String)) {
+    for name in placeholders(text) {
+        if !TEMPLATE_VARIABLES.contains(&name.as_str()) {
+            report(format!(
+                "{} uses unknown variable {{{}}} (known: {})",
+                field,
+                name,
+                TEMPLATE_VARIABLES.join(", ")
+            ));
+        }
+    }
+}
+
+fn check_condition(condition: &PostCommandCondition, report: &mut impl FnMut(String)) {
+    let check_backend = |backend: &str, report: &mut dyn FnMut(String)| {
+        if crate::backends::factory::BackendInfo::find(backend).is_none() {
+            report(format!("condition names unknown backend '{}'", backend));
+        }
+    };
+    let check_regex = |pattern: &str, report: &mut dyn FnMut(String)| {
+        if let Err(e) = regex::Regex::new(pattern) {
+            report(format!("branch_matches does not compile: {}", e));
+        }
+    };
+    match condition {
+        PostCommandCondition::Expression(expr) => {
+            if let Some(backend) = expr.strip_prefix("backend:") {
+                check_backend(backend, report);
+            } else if let Some(pattern) = expr.strip_prefix("branch_matches:") {
+                check_regex(pattern, report);
+            } else if let Some(value) = ["file_exists:", "dir_exists:", "env_set:"]
+                .iter()
+                .find_map(|prefix| expr.strip_prefix(prefix))
+            {
+                if value.is_empty() {
+                    report(format!("condition '{}' is missing its value", expr));
+                }
+            } else if expr != "always" && expr != "never" {
+                report(format!("unknown condition '{}'", expr));
+            }
+        }
+        PostCommandCondition::Structured(config) => {
+            if let Some(ref backend) = config.backend {
+                check_backend(backend, report);
+            }
+            if let Some(ref pattern) = config.branch_matches {
+                check_regex(pattern, report);
+            }
+        }
+    }
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+fn env_var_set(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| !value.is_empty())
+        .unwrap_or(false)
+}
+
+/// Split post-commands into waves that can run concurrently.
+///
+/// Without any `group`/`depends_on` declarations every command runs on its own,
+/// in config order. Otherwise a command waits for the names or groups listed in
+/// `depends_on`; without it, an ungrouped command waits for everything before it
+/// and a grouped command waits for the ungrouped commands before it.
+fn plan_waves(commands: &[PostCommand]) -> Result<Vec<Vec<usize>>> {
+    let uses_graph = commands
+        .iter()
+        .any(|c| c.group().is_some() || !c.depends_on().is_empty());
+    if !uses_graph {
+        return Ok((0..commands.len()).map(|i| vec![i]).collect());
+    }
+
+    let mut dependencies: Vec<Vec<usize>> = Vec::with_capacity(commands.len());
+    for (index, command) in commands.iter().enumerate() {
+        let deps = if !command.depends_on().is_empty() {
+            let mut deps = Vec::new();
+            for target in command.depends_on() {
+                let matches: Vec<usize> = commands
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, c)| {
+                        *other != index
+                            && (c.name() == Some(target.as_str())
+                                || c.group() == Some(target.as_str()))
+                    })
+                    .map(|(other, _)| other)
+                    .collect();
+                if matches.is_empty() {
+                    anyhow::bail!(
+                        "Post-command {} depends on unknown command or group '{}'",
+                        index + 1,
+                        target
+                    );
+                }
+                deps.extend(matches);
+            }
+            deps
+        } else if command.group().is_some() {
+            (0..index)
+                .filter(|&other| commands[other].group().is_none())
+                .collect()
+        } else {
+            (0..index).collect()
+        };
+        dependencies.push(deps);
+    }
+
+    let mut done = vec![false; commands.len()];
+    let mut waves = Vec::new();
+    while done.iter().any(|d| !d) {
+        let wave: Vec<usize> = (0..commands.len())
+            .filter(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]))
+            .collect();
+        if wave.is_empty() {
+            anyhow::bail!("Post-command dependencies contain a cycle");
+        }
+        for &i in &wave {
+            done[i] = true;
+        }
+        waves.push(wave);
+    }
+
+    Ok(waves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(yaml: &str) -> Vec<PostCommand> {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_plan_waves_sequential_only() {
+        let commands = commands(
+            r#"
+- echo one
+- name: two
+  command: echo two
+- echo three
+"#,
+        );
+
+        let waves = plan_waves(&commands).unwrap();
+        assert_eq!(waves, vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn test_plan_waves_mixed_parallel_and_sequential() {
+        let commands = commands(
+            r#"
+- name: install
+  command: npm install
+- name: migrate
+  command: npm run migrate
+  group: setup
+- name: seed
+  command: npm run seed
+  group: setup
+- name: env
+  command: echo env
+- name: smoke
+  command: npm test
+  depends_on: [setup]
+"#,
+        );
+
+        let waves = plan_waves(&commands).unwrap();
+        assert_eq!(waves, vec![vec![0], vec![1, 2], vec![3, 4]]);
+    }
+
+    #[test]
+    fn test_plan_waves_trailing_parallel_group() {
+        let commands = commands(
+            r#"
+- echo first
+- echo second
+- command: echo a
+  group: finish
+- command: echo b
+  group: finish
+- command: echo c
+  group: finish
+"#,
+        );
+
+        let waves = plan_waves(&commands).unwrap();
+        assert_eq!(waves, vec![vec![0], vec![1], vec![2, 3, 4]]);
+    }
+
+    #[test]
+    fn test_db_url_brackets_ipv6_hosts() {
+        let mut context = TemplateContext::new(&Config::default(), "main");
+        context.db_host = "::1".to_string();
+        context.db_port = 55432;
+        context.db_user = "app".to_string();
+        context.db_password = Some("p@ss".to_string());
+        context.db_name = "app_main".to_string();
+        assert_eq!(
+            context.database_url(),
+            "postgresql://app:p%40ss@[::1]:55432/app_main"
+        );
+
+        context.db_host = "127.0.0.1".to_string();
+        context.db_password = None;
+        assert_eq!(
+            context.database_url(),
+            "postgresql://app@127.0.0.1:55432/app_main"
+        );
+        assert_eq!(crate::config::host_port("[::1]", 5432), "[::1]:5432");
+        assert_eq!(
+            crate::config::host_port("db.internal", 5432),
+            "db.internal:5432"
+        );
+    }
+}
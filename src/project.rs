@@ -0,0 +1,220 @@
+//! A project's configuration, loaded the way every `pgbranch` command loads
+//! it, and the databases it configures. This is the entry point for using
+//! pgbranch as a library: load a project, then create, switch, list and
+//! delete branches of its databases, or drive a database's backend through
+//! [`DatabaseBranchingBackend`].
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::backends::factory::{self, NamedBackend};
+use crate::backends::{BranchInfo, DatabaseBranchingBackend};
+use crate::config::Config;
+use crate::errors::coded;
+use crate::local_state::LocalStateManager;
+
+/// A project's merged configuration and the `.pgbranch.yml` it came from.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub(crate) config: Config,
+    /// `None` when no `.pgbranch.yml` was found and the defaults apply
+    pub config_path: Option<PathBuf>,
+}
+
+impl Project {
+    /// The project of the current directory: the nearest `.pgbranch.yml`
+    /// up from it, under `.pgbranch.local.yml` and `PGBRANCH_*` overrides.
+    pub fn load() -> Result<Self> {
+        let (effective_config, config_path) = Config::load_effective_config_with_path_info()?;
+        let mut config = effective_config.get_merged_config();
+        use_state_backends(&mut config, config_path.as_deref());
+        Ok(Self {
+            config,
+            config_path,
+        })
+    }
+
+    /// The project configured by `path`, a `.pgbranch.yml` anywhere, with
+    /// the same overrides as [`Project::load`].
+    pub fn open(path: &Path) -> Result<Self> {
+        let config_path = std::path::absolute(path)?;
+        let effective_config =
+            Config::layer_overrides(Config::from_file(&config_path)?, Some(&config_path))?;
+        let mut config = effective_config.get_merged_config();
+        use_state_backends(&mut config, Some(&config_path));
+        Ok(Self {
+            config,
+            config_path: Some(config_path),
+        })
+    }
+
+    /// The names of the configured databases, in order.
+    pub fn databases(&self) -> Vec<String> {
+        self.config
+            .resolve_backends()
+            .into_iter()
+            .map(|b| b.name)
+            .collect()
+    }
+
+    /// The backend of the database named `database`, or of the default one.
+    pub async fn backend(&self, database: Option<&str>) -> Result<NamedBackend> {
+        if let Some(name) = database {
+            check_database_name(&self.config, name)?;
+        }
+        factory::resolve_backend(&self.config, database).await
+    }
+
+    /// Create `branch` in `database` (the default one if `None`), cloned
+    /// from `from` if given. The name is checked as `pgbranch create` checks it.
+    pub async fn create_branch(
+        &self,
+        database: Option<&str>,
+        branch: &str,
+        from: Option<&str>,
+    ) -> Result<BranchInfo> {
+        let named = self.backend(database).await?;
+        check_branch_name(named.backend.as_ref(), branch)?;
+        named.backend.create_branch(branch, from).await
+    }
+
+    /// Start `branch` if it's stopped and make it the project's current
+    /// branch, the one `pgbranch status` and the proxy use.
+    pub async fn switch_branch(&self, database: Option<&str>, branch: &str) -> Result<BranchInfo> {
+        let named = self.backend(database).await?;
+        let info = named.backend.switch_to_branch(branch).await?;
+        self.set_current_branch(Some(branch.to_string()))?;
+        Ok(info)
+    }
+
+    /// The branches of `database`.
+    pub async fn list_branches(&self, database: Option<&str>) -> Result<Vec<BranchInfo>> {
+        self.backend(database).await?.backend.list_branches().await
+    }
+
+    /// Delete `branch` from `database`. If it was the current branch, the
+    /// project goes back to main, as with `pgbranch delete --force`.
+    pub async fn delete_branch(&self, database: Option<&str>, branch: &str) -> Result<()> {
+        let named = self.backend(database).await?;
+        named.backend.delete_branch(branch).await?;
+        if self.current_branch().as_deref() == Some(branch) {
+            self.set_current_branch(Some("_main".to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn current_branch(&self) -> Option<String> {
+        let path = self.config_path.as_deref()?;
+        LocalStateManager::new().ok()?.get_current_branch(path)
+    }
+
+    fn set_current_branch(&self, branch: Option<String>) -> Result<()> {
+        if let Some(path) = &self.config_path {
+            LocalStateManager::new()?.set_current_branch(path, branch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replace the committed databases with the ones `pgbranch init` recorded
+/// in local state for the project at `config_path`, if any.
+pub(crate) fn use_state_backends(config: &mut Config, config_path: Option<&Path>) {
+    let Some(path) = config_path else {
+        return;
+    };
+    if let Some(state_backends) = LocalStateManager::new()
+        .ok()
+        .and_then(|state_manager| state_manager.get_backends(path))
+    {
+        config.backends = Some(state_backends);
+        config.backend = None;
+    }
+}
+
+/// Fail on a database name that isn't configured, listing the ones that are.
+pub(crate) fn check_database_name(config: &Config, name: &str) -> Result<()> {
+    let configured: Vec<String> = config
+        .resolve_backends()
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+    if configured.is_empty() {
+        return Err(coded!(
+            DatabaseNotFound,
+            "--database '{}' given, but no databases are configured. Add it with 'pgbranch init {}'",
+            name,
+            name
+        ));
+    }
+    if !configured.iter().any(|c| c == name) {
+        return Err(coded!(
+            DatabaseNotFound,
+            "Database '{}' not found in configuration. Configured databases: {}",
+            name,
+            configured.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Reject branch names the backend can't store before calling it, with a
+/// suggested replacement instead of an obscure API error.
+pub(crate) fn check_branch_name(backend: &dyn DatabaseBranchingBackend, name: &str) -> Result<()> {
+    let max_len = backend.max_branch_name_length();
+    let len = name.chars().count();
+
+    let problem = if name.trim().is_empty() {
+        "must not be empty".to_string()
+    } else if name.starts_with('-') || name.starts_with('.') {
+        "must not start with '-' or '.'".to_string()
+    } else if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        "must not contain whitespace or control characters".to_string()
+    } else if len > max_len {
+        format!(
+            "is {} characters long, {} allows at most {}",
+            len,
+            backend.backend_name(),
+            max_len
+        )
+    } else {
+        return Ok(());
+    };
+
+    let suggestion = suggest_branch_name(name, max_len);
+    if suggestion.is_empty() {
+        return Err(coded!(
+            InvalidBranchName,
+            "Branch name '{}' {}",
+            name,
+            problem
+        ));
+    }
+    Err(coded!(
+        InvalidBranchName,
+        "Branch name '{}' {}. Try '{}' instead.",
+        name,
+        problem,
+        suggestion
+    ))
+}
+
+fn suggest_branch_name(name: &str, max_len: usize) -> String {
+    let mut suggestion = String::with_capacity(name.len());
+    for c in name.chars() {
+        let c = if c.is_whitespace() || c.is_control() {
+            '-'
+        } else {
+            c
+        };
+        if !(c == '-' && suggestion.ends_with('-')) {
+            suggestion.push(c);
+        }
+    }
+    let suggestion: String = suggestion
+        .trim_start_matches(['-', '.'])
+        .chars()
+        .take(max_len)
+        .collect();
+    suggestion.trim_end_matches('-').to_string()
+}
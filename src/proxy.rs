@@ -0,0 +1,225 @@
+//! `pgbranch proxy start`: one fixed local port that leads to whatever
+//! branch is current, so an app's `DATABASE_URL` can point at the proxy
+//! and keep working across `pgbranch switch` without post-commands
+//! rewriting `.env` files.
+//!
+//! The proxy forwards bytes and nothing else. Each new connection goes to
+//! the branch that's current when it's accepted; open connections stay on
+//! the branch they started on until the client closes them. TLS, auth and
+//! cancel requests pass through untouched, which is why it only works with
+//! backends whose branches are servers of their own that share a database
+//! name and credentials, like the local backend's containers.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::backends::DatabaseBranchingBackend;
+use crate::config::{host_port, Config};
+use crate::local_state::LocalStateManager;
+
+/// Just below the local backend's port range, which starts at 55432.
+pub const DEFAULT_PORT: u16 = 55431;
+/// Request codes of the 8-byte messages asking for an encrypted connection.
+const SSL_REQUEST_CODE: u32 = 80877103;
+const GSSENC_REQUEST_CODE: u32 = 80877104;
+/// How long a refused client gets to send its startup message.
+const STARTUP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// A connection handed to a branch, for the proxy's output.
+#[derive(Debug, Serialize)]
+struct Forwarded {
+    time: chrono::DateTime<chrono::Utc>,
+    client: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Accept connections on `127.0.0.1:<port>` and forward each to the current
+/// branch, until Ctrl-C.
+pub async fn serve(
+    backend: Arc<dyn DatabaseBranchingBackend>,
+    config: &Config,
+    config_path: Option<&Path>,
+    port: u16,
+    json_output: bool,
+) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("Failed to listen on 127.0.0.1:{}", port))?;
+    if !json_output {
+        eprintln!(
+            "Forwarding 127.0.0.1:{} to the current branch; press Ctrl-C to stop",
+            port
+        );
+    }
+
+    let config = Arc::new(config.clone());
+    let config_path = config_path.map(Path::to_path_buf);
+    loop {
+        let (client, peer) = listener
+            .accept()
+            .await
+            .context("Failed to accept a connection")?;
+        let time = chrono::Utc::now();
+        let backend = Arc::clone(&backend);
+        let config = Arc::clone(&config);
+        let config_path = config_path.clone();
+        // Finding the branch asks the backend, which mustn't hold up the next accept
+        tokio::spawn(async move {
+            let target = current_target(backend.as_ref(), &config, config_path.as_deref()).await;
+            let mut forwarded = Forwarded {
+                time,
+                client: peer,
+                branch: None,
+                target: None,
+                error: None,
+            };
+            match target {
+                Ok((branch, host, port)) => {
+                    forwarded.branch = Some(branch.clone());
+                    forwarded.target = Some(host_port(&host, port));
+                    report(&forwarded, json_output);
+                    if let Err(e) = forward(client, &branch, &host, port).await {
+                        log::warn!("Connection from {} to '{}': {:#}", peer, branch, e);
+                    }
+                }
+                Err(e) => {
+                    let message = format!("{:#}", e);
+                    forwarded.error = Some(message.clone());
+                    report(&forwarded, json_output);
+                    reject(client, message).await;
+                }
+            }
+        });
+    }
+}
+
+fn report(forwarded: &Forwarded, json_output: bool) {
+    if json_output {
+        // One compact object per line
+        match crate::output::versioned(forwarded) {
+            Ok(line) => println!("{}", line),
+            Err(e) => log::warn!("Failed to report a connection: {:#}", e),
+        }
+        return;
+    }
+    let time = forwarded
+        .time
+        .with_timezone(&chrono::Local)
+        .format("%H:%M:%S");
+    match (&forwarded.branch, &forwarded.target, &forwarded.error) {
+        (Some(branch), Some(target), _) => println!(
+            "{} {} → branch {} ({})",
+            time, forwarded.client, branch, target
+        ),
+        (_, _, Some(error)) => println!("{} {} refused: {}", time, forwarded.client, error),
+        _ => {}
+    }
+}
+
+/// The current branch and the host and port it listens on. Read on every
+/// connection, so a switch applies from the next one.
+async fn current_target(
+    backend: &dyn DatabaseBranchingBackend,
+    config: &Config,
+    config_path: Option<&Path>,
+) -> Result<(String, String, u16)> {
+    let current = config_path
+        .and_then(|path| LocalStateManager::new().ok()?.get_current_branch(path))
+        .ok_or_else(|| {
+            anyhow::anyhow!("No current branch; switch to one with 'pgbranch switch <branch>'")
+        })?;
+    // The main marker stands for the configured main branch
+    let branch = if current == "_main" {
+        config.git.main_branch.clone()
+    } else {
+        current
+    };
+    let conn = backend.get_connection_info(&branch).await?;
+    Ok((branch, conn.host, conn.port))
+}
+
+async fn forward(mut client: TcpStream, branch: &str, host: &str, port: u16) -> Result<()> {
+    // A bracketed IPv6 address doesn't resolve; the bare one does
+    let address = (host.trim_start_matches('[').trim_end_matches(']'), port);
+    let mut server = match TcpStream::connect(address).await {
+        Ok(server) => server,
+        Err(e) => {
+            reject(
+                client,
+                format!(
+                    "Branch '{}' doesn't accept connections at {} ({}); start it with 'pgbranch start {}'",
+                    branch,
+                    host_port(host, port),
+                    e,
+                    branch
+                ),
+            )
+            .await;
+            return Ok(());
+        }
+    };
+    tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+    Ok(())
+}
+
+/// Tell a client why it can't be served, as a PostgreSQL error so `psql`
+/// and drivers show the message, then hang up.
+async fn reject(mut client: TcpStream, message: String) {
+    // Clients that get an error in answer to their TLS request only report
+    // a failed TLS exchange, so decline it and answer the startup message
+    let declined = tokio::time::timeout(STARTUP_TIMEOUT, decline_encryption(&mut client)).await;
+    if matches!(declined, Ok(Ok(()))) {
+        let _ = client.write_all(&error_response(&message)).await;
+    }
+    let _ = client.shutdown().await;
+}
+
+/// Read the client's first messages up to its startup message, answering
+/// `N` (not supported) to requests for TLS or GSSAPI encryption.
+async fn decline_encryption(client: &mut TcpStream) -> std::io::Result<()> {
+    loop {
+        let mut header = [0u8; 8];
+        client.read_exact(&mut header).await?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let code = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if length == 8 && (code == SSL_REQUEST_CODE || code == GSSENC_REQUEST_CODE) {
+            client.write_all(b"N").await?;
+        } else {
+            // The startup message, whose parameters don't matter here
+            return Ok(());
+        }
+    }
+}
+
+/// An `ErrorResponse` message: severity, SQLSTATE 08001 (unable to
+/// establish connection) and the text.
+fn error_response(message: &str) -> Vec<u8> {
+    let mut fields = Vec::new();
+    for (code, value) in [
+        (b'S', "FATAL"),
+        (b'V', "FATAL"),
+        (b'C', "08001"),
+        (b'M', message),
+    ] {
+        fields.push(code);
+        fields.extend_from_slice(value.as_bytes());
+        fields.push(0);
+    }
+    fields.push(0);
+
+    let mut packet = vec![b'E'];
+    packet.extend_from_slice(&(fields.len() as u32 + 4).to_be_bytes());
+    packet.extend_from_slice(&fields);
+    packet
+}
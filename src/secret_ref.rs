@@ -0,0 +1,79 @@
+//! References to secrets kept in an external secret manager, so they never
+//! have to be written into `.pgbranch.yml`. A value that is a whole
+//! reference is swapped for the secret when it's used:
+//!
+//! - `op://vault/item/field` is read with the 1Password CLI (`op read`)
+//! - `vault:kv/path#key` is read with the HashiCorp Vault CLI
+//!   (`vault kv get -field=key kv/path`)
+//!
+//! Any other value is returned unchanged.
+
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+const ONEPASSWORD_PREFIX: &str = "op://";
+const VAULT_PREFIX: &str = "vault:";
+
+/// Whether `value` names a secret in an external secret manager.
+pub fn is_reference(value: &str) -> bool {
+    let value = value.trim();
+    value.starts_with(ONEPASSWORD_PREFIX) || value.starts_with(VAULT_PREFIX)
+}
+
+/// The secret `value` refers to, or `value` itself if it isn't a reference.
+pub fn resolve(value: &str) -> Result<String> {
+    let reference = value.trim();
+    if reference.starts_with(ONEPASSWORD_PREFIX) {
+        let mut command = Command::new("op");
+        command.args(["read", "--no-newline", reference]);
+        run_provider(command, "op", reference)
+    } else if let Some(path) = reference.strip_prefix(VAULT_PREFIX) {
+        let (path, key) = path.rsplit_once('#').ok_or_else(|| {
+            anyhow::anyhow!(
+                "Vault reference '{}' needs a key after '#', e.g. vault:kv/app#password",
+                reference
+            )
+        })?;
+        if path.is_empty() || key.is_empty() {
+            anyhow::bail!(
+                "Vault reference '{}' needs a path and a key, e.g. vault:kv/app#password",
+                reference
+            );
+        }
+        let mut command = Command::new("vault");
+        command.args(["kv", "get", &format!("-field={}", key), path]);
+        run_provider(command, "vault", reference)
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+fn run_provider(mut command: Command, tool: &str, reference: &str) -> Result<String> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = command.output().with_context(|| {
+        format!(
+            "Failed to run '{}' to read {}. Is it installed?",
+            tool, reference
+        )
+    })?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to read {} with {}: {}",
+            reference,
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let secret = String::from_utf8(output.stdout).with_context(|| {
+        format!(
+            "{} returned a secret for {} that isn't UTF-8",
+            tool, reference
+        )
+    })?;
+    // The CLIs end their output with a line break that isn't part of the secret
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
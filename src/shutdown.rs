@@ -0,0 +1,106 @@
+//! Ctrl-C and SIGTERM during long operations. While an operation runs
+//! through [`interruptible`], a shutdown signal abandons it so the caller can
+//! roll back instead of leaving a half-made branch behind. A signal outside
+//! such an operation, including a second one during the rollback, exits
+//! right away.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Once, OnceLock};
+
+use tokio::sync::Notify;
+
+/// Exit status of a process stopped by SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static LISTENER: Once = Once::new();
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+fn interrupt() -> &'static Notify {
+    static INTERRUPT: OnceLock<Notify> = OnceLock::new();
+    INTERRUPT.get_or_init(Notify::new)
+}
+
+/// Error of an operation abandoned because of a shutdown signal.
+#[derive(Debug)]
+pub struct Interrupted {
+    signal: &'static str,
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "interrupted by {}", self.signal)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Run `operation`, abandoning it at its next await point if the process is
+/// asked to stop. Abandoning it drops the future, so whatever it started
+/// (containers, data directories, state rows) stays behind for the caller
+/// to roll back.
+pub async fn interruptible<T>(
+    operation: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    listen();
+
+    let notified = interrupt().notified();
+    tokio::pin!(notified);
+    notified.as_mut().enable();
+
+    ACTIVE.fetch_add(1, Ordering::SeqCst);
+    let result = tokio::select! {
+        result = operation => result,
+        _ = notified => Err(Interrupted { signal: last_signal() }.into()),
+    };
+    ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+/// Whether `error` comes from an operation abandoned by [`interruptible`].
+pub fn is_interrupted(error: &anyhow::Error) -> bool {
+    error.is::<Interrupted>()
+}
+
+fn last_signal() -> &'static str {
+    if TERMINATED.load(Ordering::SeqCst) {
+        "SIGTERM"
+    } else {
+        "Ctrl-C"
+    }
+}
+
+/// Handle shutdown signals from now on. Installing a handler replaces the
+/// default of exiting, so signals outside an operation still exit.
+fn listen() {
+    LISTENER.call_once(|| {
+        tokio::spawn(async {
+            loop {
+                let terminated = wait_for_signal().await;
+                TERMINATED.store(terminated, Ordering::SeqCst);
+                if ACTIVE.load(Ordering::SeqCst) == 0 {
+                    std::process::exit(INTERRUPTED_EXIT_CODE);
+                }
+                interrupt().notify_waiters();
+            }
+        });
+    });
+}
+
+/// Wait for Ctrl-C or, on Unix, SIGTERM. Returns whether it was SIGTERM.
+async fn wait_for_signal() -> bool {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = tokio::signal::ctrl_c() => false,
+                _ = term.recv() => true,
+            };
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    false
+}
@@ -0,0 +1,224 @@
+//! Detached signatures on the files teams hand each other to set up
+//! branches: dumps and fixtures that branches are seeded from, and project
+//! manifests. A signature sits next to its file, as `<file>.sig` for
+//! `ssh-keygen -Y` or `<file>.minisig` for minisign, and covers every byte,
+//! so a tampered or truncated file fails verification before anything is
+//! restored from it.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+use crate::config::{Config, SigningConfig, SigningMethod};
+use crate::errors::coded;
+
+/// What `ssh-keygen -Y` signatures are made for, so a signature made with
+/// the same key for something else (a Git commit, say) isn't accepted.
+const NAMESPACE: &str = "pgbranch";
+
+/// The outcome of checking a file that was allowed through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// Signed by `signer`: the principal from `allowed_signers`, or the
+    /// minisign public key file
+    Verified { signer: String },
+    /// No signature to check, and none required
+    Unsigned,
+}
+
+/// Where the signature of `path` goes.
+pub fn signature_path(signing: &SigningConfig, path: &Path) -> PathBuf {
+    let extension = match signing.method {
+        SigningMethod::Ssh => "sig",
+        SigningMethod::Minisign => "minisig",
+    };
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".");
+    signature.push(extension);
+    PathBuf::from(signature)
+}
+
+/// Sign `path` with `signing.key`, replacing an older signature. Returns
+/// where the signature was written.
+pub fn sign(signing: &SigningConfig, path: &Path) -> Result<PathBuf> {
+    let key = signing.key.as_deref().ok_or_else(|| {
+        anyhow::anyhow!("Set 'key' under 'signing' in .pgbranch.yml to sign files")
+    })?;
+    let key = resolve_path(key);
+    let signature = signature_path(signing, path);
+    match signing.method {
+        SigningMethod::Ssh => {
+            // Signing stdin writes the signature to stdout, where `-Y sign
+            // <file>` would stop to ask before replacing an existing one
+            let input = std::fs::File::open(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let output = std::fs::File::create(&signature)
+                .with_context(|| format!("Failed to write {}", signature.display()))?;
+            let result = Command::new("ssh-keygen")
+                .args(["-Y", "sign", "-n", NAMESPACE, "-f"])
+                .arg(&key)
+                .arg("-")
+                .stdin(input)
+                .stdout(output)
+                .output()
+                .map_err(|e| tool_error(signing.method, e))?;
+            if !result.status.success() {
+                let _ = std::fs::remove_file(&signature);
+                anyhow::bail!(
+                    "ssh-keygen couldn't sign {} with {}: {}",
+                    path.display(),
+                    key.display(),
+                    String::from_utf8_lossy(&result.stderr).trim()
+                );
+            }
+        }
+        SigningMethod::Minisign => {
+            let status = Command::new("minisign")
+                .arg("-S")
+                .arg("-s")
+                .arg(&key)
+                .arg("-m")
+                .arg(path)
+                .arg("-x")
+                .arg(&signature)
+                .stdout(Stdio::null())
+                .status()
+                .map_err(|e| tool_error(signing.method, e))?;
+            if !status.success() {
+                anyhow::bail!(
+                    "minisign couldn't sign {} with {}",
+                    path.display(),
+                    key.display()
+                );
+            }
+        }
+    }
+    Ok(signature)
+}
+
+/// Check `path` against its signature before it's used. A bad signature
+/// always fails; a missing one, or one there are no trusted keys to check
+/// against, only fails with `signing.require`.
+pub fn verify(signing: Option<&SigningConfig>, path: &Path) -> Result<Verification> {
+    let Some(signing) = signing else {
+        return Ok(Verification::Unsigned);
+    };
+    let signature = signature_path(signing, path);
+    let trusted = signing.trusted.as_deref().map(resolve_path);
+    let (true, Some(trusted)) = (signature.exists(), trusted) else {
+        if !signing.require {
+            return Ok(Verification::Unsigned);
+        }
+        return Err(if signing.trusted.is_none() {
+            coded!(
+                SignatureInvalid,
+                "Signatures are required, but no keys are trusted; set 'trusted' under 'signing'"
+            )
+        } else {
+            coded!(
+                SignatureInvalid,
+                "{} isn't signed (no {})",
+                path.display(),
+                signature.display()
+            )
+        });
+    };
+
+    let signer = match signing.method {
+        SigningMethod::Ssh => verify_ssh(path, &signature, &trusted)?,
+        SigningMethod::Minisign => {
+            let status = Command::new("minisign")
+                .arg("-V")
+                .arg("-q")
+                .arg("-p")
+                .arg(&trusted)
+                .arg("-m")
+                .arg(path)
+                .arg("-x")
+                .arg(&signature)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .map_err(|e| tool_error(signing.method, e))?;
+            status.success().then(|| trusted.display().to_string())
+        }
+    };
+    match signer {
+        Some(signer) => Ok(Verification::Verified { signer }),
+        None => Err(coded!(
+            SignatureInvalid,
+            "{} doesn't match its signature {} by a key in {}; it may have been changed or cut short",
+            path.display(),
+            signature.display(),
+            trusted.display()
+        )),
+    }
+}
+
+/// The principal in `allowed_signers` whose key made a good signature of
+/// `path`, if any.
+fn verify_ssh(path: &Path, signature: &Path, allowed_signers: &Path) -> Result<Option<String>> {
+    let found = Command::new("ssh-keygen")
+        .args(["-Y", "find-principals", "-s"])
+        .arg(signature)
+        .arg("-f")
+        .arg(allowed_signers)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| tool_error(SigningMethod::Ssh, e))?;
+    let principals = String::from_utf8_lossy(&found.stdout);
+    let Some(principal) = principals.lines().next().filter(|_| found.status.success()) else {
+        return Ok(None);
+    };
+
+    let input =
+        std::fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let status = Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-n", NAMESPACE, "-I", principal, "-s"])
+        .arg(signature)
+        .arg("-f")
+        .arg(allowed_signers)
+        .stdin(input)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| tool_error(SigningMethod::Ssh, e))?;
+    Ok(status.success().then(|| principal.to_string()))
+}
+
+/// `~/` expanded; other relative paths are taken from the project's
+/// directory, where a team would commit its `allowed_signers`.
+fn resolve_path(value: &str) -> PathBuf {
+    if let Some(rest) = value.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    }
+    let path = PathBuf::from(value);
+    if path.is_relative() {
+        if let Ok(Some(config_path)) = Config::find_config_file() {
+            if let Some(project_dir) = config_path.parent() {
+                return project_dir.join(path);
+            }
+        }
+    }
+    path
+}
+
+fn tool_error(method: SigningMethod, e: std::io::Error) -> anyhow::Error {
+    let (tool, package) = match method {
+        SigningMethod::Ssh => ("ssh-keygen", "OpenSSH 8.1 or later"),
+        SigningMethod::Minisign => ("minisign", "minisign"),
+    };
+    if e.kind() == std::io::ErrorKind::NotFound {
+        anyhow::anyhow!(
+            "{} not found; install {} to sign and verify files",
+            tool,
+            package
+        )
+    } else {
+        anyhow::Error::new(e).context(format!("Failed to run {}", tool))
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::Result;
+
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Outcome of `pgbranch upgrade`.
+pub struct UpgradeOutcome {
+    pub latest_version: String,
+    pub installed: bool,
+}
+
+/// Release asset holding the binary for this platform, e.g. `pgbranch-linux-x86_64`.
+#[cfg_attr(not(feature = "self-update"), allow(dead_code))]
+fn asset_name() -> String {
+    format!(
+        "pgbranch-{}-{}{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// `v1.2.3` / `1.2.3-rc.1` -> (1, 2, 3); pre-release suffixes are ignored.
+#[cfg_attr(not(feature = "self-update"), allow(dead_code))]
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.trim_start_matches('v').split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    Some((
+        parts.next()??,
+        parts.next()??,
+        parts.next().unwrap_or(Some(0))?,
+    ))
+}
+
+#[cfg(feature = "self-update")]
+mod github {
+    use anyhow::{Context, Result};
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Debug, Deserialize)]
+    pub struct Release {
+        pub tag_name: String,
+        #[serde(default)]
+        pub assets: Vec<Asset>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Asset {
+        pub name: String,
+        pub browser_download_url: String,
+    }
+
+    fn client(timeout: Duration) -> Result<reqwest::Client> {
+        reqwest::Client::builder()
+            .user_agent(concat!("pgbranch/", env!("CARGO_PKG_VERSION")))
+            .timeout(timeout)
+            .build()
+            .context("Failed to build HTTP client")
+    }
+
+    pub async fn latest_release(timeout: Duration) -> Result<Release> {
+        let repo = env!("CARGO_PKG_REPOSITORY").trim_start_matches("https://github.com/");
+        let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+        client(timeout)?
+            .get(&url)
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await
+            .with_context(|| format!("Failed to query {}", url))?
+            .error_for_status()
+            .context("GitHub releases API returned an error")?
+            .json()
+            .await
+            .context("Failed to parse GitHub release")
+    }
+
+    pub async fn download(url: &str) -> Result<Vec<u8>> {
+        let bytes = client(Duration::from_secs(300))?
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", url))?
+            .bytes()
+            .await?;
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Newer released version, if any. Quiet and quick: offline machines just get `None`.
+#[cfg(feature = "self-update")]
+pub async fn available_update() -> Option<String> {
+    if std::env::var("PGBRANCH_NO_UPDATE_CHECK").is_ok_and(|v| !v.is_empty() && v != "0") {
+        return None;
+    }
+    let release = github::latest_release(std::time::Duration::from_secs(3))
+        .await
+        .map_err(|e| log::debug!("update check failed: {:#}", e))
+        .ok()?;
+    let latest = parse_version(&release.tag_name)?;
+    (latest > parse_version(CURRENT_VERSION)?)
+        .then(|| release.tag_name.trim_start_matches('v').to_string())
+}
+
+#[cfg(not(feature = "self-update"))]
+pub async fn available_update() -> Option<String> {
+    None
+}
+
+/// Replace the running executable with the latest release for this platform,
+/// after checking it against the release's SHA-256 checksum. The checksum is
+/// published next to the binary, so it catches a broken download, not a
+/// replaced one; releases aren't signed.
+#[cfg(feature = "self-update")]
+pub async fn upgrade(check_only: bool, force: bool) -> Result<UpgradeOutcome> {
+    use anyhow::Context;
+    use sha2::{Digest, Sha256};
+
+    let release = github::latest_release(std::time::Duration::from_secs(30)).await?;
+    let latest_version = release.tag_name.trim_start_matches('v').to_string();
+    let newer = match (
+        parse_version(&latest_version),
+        parse_version(CURRENT_VERSION),
+    ) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => anyhow::bail!("Unrecognized release version '{}'", release.tag_name),
+    };
+    if check_only || (!newer && !force) {
+        return Ok(UpgradeOutcome {
+            latest_version,
+            installed: false,
+        });
+    }
+
+    let name = asset_name();
+    let asset = release
+        .assets
+        .iter()
+        .find(|a| a.name == name)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Release {} has no binary for this platform ({})",
+                release.tag_name,
+                name
+            )
+        })?;
+
+    // Either `<asset>.sha256` or a combined `SHA256SUMS` in `sha256sum` format
+    let checksum_name = format!("{}.sha256", name);
+    let checksum_asset = release
+        .assets
+        .iter()
+        .find(|a| a.name == checksum_name)
+        .or_else(|| release.assets.iter().find(|a| a.name == "SHA256SUMS"))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Release {} publishes no checksum for {}; refusing to install it",
+                release.tag_name,
+                name
+            )
+        })?;
+    let checksums =
+        String::from_utf8(github::download(&checksum_asset.browser_download_url).await?)
+            .context("Checksum file is not valid UTF-8")?;
+    let expected = checksums
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let hash = fields.next()?;
+            match fields.next() {
+                Some(file) if file.trim_start_matches('*') != name => None,
+                _ => Some(hash.to_ascii_lowercase()),
+            }
+        })
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No checksum for {} in {}", name, checksum_asset.name))?;
+
+    let binary = github::download(&asset.browser_download_url).await?;
+    let actual: String = Sha256::digest(&binary)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if actual != expected {
+        anyhow::bail!(
+            "Checksum mismatch for {} (expected {}, got {}); not installing",
+            name,
+            expected,
+            actual
+        );
+    }
+
+    replace_current_exe(&binary)?;
+    Ok(UpgradeOutcome {
+        latest_version,
+        installed: true,
+    })
+}
+
+#[cfg(not(feature = "self-update"))]
+pub async fn upgrade(_check_only: bool, _force: bool) -> Result<UpgradeOutcome> {
+    anyhow::bail!("Self-update not compiled. Rebuild with --features self-update")
+}
+
+#[cfg(feature = "self-update")]
+fn replace_current_exe(binary: &[u8]) -> Result<()> {
+    use anyhow::Context;
+
+    let exe = std::env::current_exe()?
+        .canonicalize()
+        .context("Failed to locate the running executable")?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Executable {} has no parent directory", exe.display()))?;
+
+    // Write next to the target so the final rename stays on one filesystem
+    let staged = dir.join(format!(".pgbranch-upgrade-{}", std::process::id()));
+    std::fs::write(&staged, binary).with_context(|| {
+        format!(
+            "Cannot write to {}; re-run with permission to replace {}",
+            dir.display(),
+            exe.display()
+        )
+    })?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Windows can't overwrite a running executable, but it can rename it
+    #[cfg(windows)]
+    std::fs::rename(&exe, exe.with_extension("old.exe"))
+        .with_context(|| format!("Failed to move {} aside", exe.display()))?;
+
+    if let Err(e) = std::fs::rename(&staged, &exe) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(e).with_context(|| format!("Failed to replace {}", exe.display()));
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+/// POST a JSON payload to a webhook URL.
+#[cfg(feature = "notify-webhook")]
+pub async fn post_json(url: &str, body: &serde_json::Value) -> anyhow::Result<()> {
+    reqwest::Client::new()
+        .post(url)
+        .timeout(std::time::Duration::from_secs(10))
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "notify-webhook"))]
+pub async fn post_json(_url: &str, _body: &serde_json::Value) -> anyhow::Result<()> {
+    anyhow::bail!("webhook support not compiled. Rebuild with --features notify-webhook")
+}
@@ -0,0 +1,353 @@
+//! End-to-end tests of the CLI against the mock backend, each in a throwaway
+//! standalone project with its own config and data directories. Run them
+//! with `cargo test --features backend-mock`.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+struct Project {
+    home: TempDir,
+}
+
+impl Project {
+    /// An empty directory, not in a Git repository, to run `init` in.
+    fn empty() -> Self {
+        let home = TempDir::new().unwrap();
+        std::fs::create_dir(home.path().join("project")).unwrap();
+        Self { home }
+    }
+
+    /// A standalone project with one mock database, `app`.
+    fn new() -> Self {
+        let project = Self::empty();
+        project.ok(&[
+            "--non-interactive",
+            "init",
+            "app",
+            "--standalone",
+            "--backend",
+            "mock",
+        ]);
+        project
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.home.path().join("project")
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let home = self.home.path();
+        Command::new(env!("CARGO_BIN_EXE_pgbranch"))
+            .args(args)
+            .current_dir(self.dir())
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", home)
+            .env("XDG_CONFIG_HOME", home.join("config"))
+            .env("XDG_DATA_HOME", home.join("data"))
+            // Nothing here may depend on a Docker daemon being around
+            .env(
+                "DOCKER_HOST",
+                format!("unix://{}", home.join("docker.sock").display()),
+            )
+            .env("RUST_BACKTRACE", "0")
+            .output()
+            .unwrap()
+    }
+
+    /// Run a command that has to succeed, returning its stdout.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "pgbranch {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn json(&self, args: &[&str]) -> Value {
+        let mut args = args.to_vec();
+        args.push("--json");
+        serde_json::from_str(&self.ok(&args)).unwrap()
+    }
+
+    /// Run a command that has to fail, returning its `--json` error.
+    fn error(&self, args: &[&str]) -> Value {
+        let mut args = args.to_vec();
+        args.push("--json");
+        let output = self.run(&args);
+        assert!(
+            !output.status.success(),
+            "pgbranch {} succeeded",
+            args.join(" ")
+        );
+        serde_json::from_slice(&output.stderr).unwrap()
+    }
+
+    fn branch_names(&self) -> Vec<String> {
+        self.json(&["list"])
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|branch| branch["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+}
+
+#[test]
+fn init_sets_up_the_mock_database() {
+    let project = Project::new();
+    assert!(project.dir().join(".pgbranch.yml").exists());
+
+    let doctor = project.json(&["doctor"]);
+    assert_eq!(doctor["checks"][0]["name"], "Mock state");
+    assert_eq!(doctor["checks"][0]["available"], true);
+}
+
+#[test]
+fn create_and_list() {
+    let project = Project::new();
+    project.ok(&["create", "main"]);
+    project.ok(&["create", "feature", "--from", "main"]);
+
+    let list = project.json(&["list"]);
+    let branches = list.as_array().unwrap();
+    assert_eq!(branches.len(), 2);
+    assert_eq!(branches[1]["schema_version"], 1);
+    assert_eq!(branches[1]["name"], "feature");
+    assert_eq!(branches[1]["parent_branch"], "main");
+    assert_eq!(branches[1]["created_at"], "2024-01-01T00:01:00Z");
+    assert_eq!(branches[1]["state"], "running");
+}
+
+#[test]
+fn create_existing_branch_fails_with_its_code() {
+    let project = Project::new();
+    project.ok(&["create", "feature"]);
+
+    let error = project.error(&["create", "feature"]);
+    assert_eq!(error["schema_version"], 1);
+    assert_eq!(error["error"]["code"], "E0002");
+    assert_eq!(error["error"]["name"], "BranchExists");
+}
+
+#[test]
+fn create_from_missing_parent_fails() {
+    let project = Project::new();
+    let error = project.error(&["create", "feature", "--from", "nope"]);
+    assert_eq!(error["error"]["code"], "E0001");
+    assert!(project.branch_names().is_empty());
+}
+
+#[test]
+fn delete_removes_the_branch() {
+    let project = Project::new();
+    project.ok(&["create", "feature"]);
+
+    let deleted = project.json(&["delete", "feature", "--force"]);
+    assert_eq!(deleted["status"], "ok");
+    assert_eq!(deleted["deleted"], "feature");
+    assert!(project.branch_names().is_empty());
+
+    let error = project.error(&["delete", "feature", "--force"]);
+    assert_eq!(error["error"]["code"], "E0001");
+}
+
+#[test]
+fn switch_creates_and_records_the_branch() {
+    let project = Project::new();
+    project.ok(&["create", "main"]);
+
+    let switched = project.json(&["-d", "app", "switch", "feature"]);
+    assert_eq!(switched["branch"], "feature");
+    assert_eq!(switched["failed"], 0);
+    assert_eq!(switched["databases"]["app"]["status"], "ok");
+    assert_eq!(project.branch_names(), ["main", "feature"]);
+
+    let context = project.json(&["completion", "context"]);
+    assert_eq!(context["branch"], "feature");
+}
+
+#[test]
+fn connection_of_a_branch() {
+    let project = Project::new();
+    project.ok(&["create", "feature"]);
+
+    let connection = project.json(&["connection", "feature"]);
+    assert_eq!(connection["host"], "localhost");
+    assert_eq!(connection["port"], 5432);
+    assert_eq!(
+        connection["connection_string"],
+        format!(
+            "postgresql://postgres@localhost:5432/{}",
+            connection["database"].as_str().unwrap()
+        )
+    );
+
+    let error = project.error(&["connection", "nope"]);
+    assert_eq!(error["error"]["code"], "E0001");
+}
+
+#[test]
+fn cleanup_keeps_the_newest_branches() {
+    let project = Project::new();
+    for branch in ["main", "first", "second", "third"] {
+        project.ok(&["create", branch]);
+    }
+
+    let mut removed: Vec<String> = project
+        .json(&["cleanup", "--max-count", "1"])
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|name| name.as_str().unwrap().to_string())
+        .collect();
+    removed.sort();
+    assert_eq!(removed, ["first", "second"]);
+    assert_eq!(project.branch_names(), ["main", "third"]);
+}
+
+#[test]
+fn prune_deletes_expired_branches() {
+    let project = Project::new();
+    for branch in ["main", "first", "second"] {
+        project.ok(&["create", branch]);
+    }
+    project.ok(&["-d", "app", "switch", "second"]);
+
+    // Mock branches were created in 2024, so any TTL has run out
+    let plan = project.json(&["prune", "--ttl", "7d", "--dry-run"]);
+    assert_eq!(plan["actions"].as_array().unwrap().len(), 1);
+    assert_eq!(project.branch_names().len(), 3);
+
+    // Neither main nor the current branch expires
+    let pruned = project.json(&["prune", "--ttl", "7d"]);
+    assert_eq!(pruned["pruned"], serde_json::json!(["first"]));
+    assert_eq!(project.branch_names(), ["main", "second"]);
+
+    let error = project.error(&["prune"]);
+    assert_eq!(error["error"]["code"], "E0000");
+}
+
+#[test]
+fn status_counts_the_branches() {
+    let project = Project::new();
+    project.ok(&["create", "main"]);
+    project.ok(&["create", "feature"]);
+
+    let status = project.json(&["status"]);
+    assert_eq!(status["backend"], "Mock");
+    assert_eq!(status["total_branches"], 2);
+}
+
+#[test]
+fn verify_refuses_an_unsigned_file() {
+    let project = Project::new();
+    let config = project.dir().join(".pgbranch.yml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str("signing:\n  trusted: allowed_signers\n");
+    std::fs::write(&config, text).unwrap();
+    std::fs::write(project.dir().join("dump.sql"), "SELECT 1;\n").unwrap();
+
+    let error = project.error(&["verify", "dump.sql"]);
+    assert_eq!(error["error"]["code"], "E0010");
+}
+
+#[test]
+fn psql_on_a_missing_branch_fails() {
+    let project = Project::new();
+    let error = project.error(&["psql", "nope"]);
+    assert_eq!(error["error"]["code"], "E0001");
+}
+
+#[test]
+fn proxy_needs_branches_with_their_own_server() {
+    let project = Project::new();
+    let error = project.error(&["proxy", "start"]);
+    assert_eq!(error["error"]["code"], "E0006");
+}
+
+#[test]
+fn auto_prune_keeps_the_branch_a_command_is_for() {
+    let project = Project::new();
+    for branch in ["main", "first", "second", "third"] {
+        project.ok(&["create", branch]);
+    }
+    project.ok(&["-d", "app", "switch", "second"]);
+    std::fs::write(
+        project.dir().join(".pgbranch.local.yml"),
+        "behavior:\n  branch_ttl: 7d\n  auto_prune: true\n",
+    )
+    .unwrap();
+
+    // Read-only commands and dry runs delete nothing
+    project.ok(&["list"]);
+    project.ok(&["create", "fourth", "--dry-run"]);
+    assert_eq!(project.branch_names().len(), 4);
+
+    // `first` expired like `third`, but it's the branch being switched to
+    project.ok(&["-d", "app", "switch", "first"]);
+    assert_eq!(project.branch_names(), ["main", "first", "second"]);
+}
+
+/// A standalone project set up without `--backend`, so none is configured
+/// and the default local one can't reach Docker.
+fn standalone_without_backend() -> Project {
+    let project = Project::empty();
+    let stdout = project.ok(&["--non-interactive", "init", "app", "--standalone"]);
+    assert!(stdout.contains("Standalone project: Git hooks and worktrees are off"));
+    project
+}
+
+#[test]
+fn standalone_init_needs_no_repository_or_backend() {
+    let project = standalone_without_backend();
+    let config = std::fs::read_to_string(project.dir().join(".pgbranch.yml")).unwrap();
+    assert!(config.contains("standalone: true"));
+}
+
+#[test]
+fn standalone_refuses_hooks_and_worktrees() {
+    let project = standalone_without_backend();
+    for command in ["install-hooks", "worktree-setup"] {
+        let error = project.error(&[command]);
+        assert_eq!(error["error"]["code"], "E0000", "{command}");
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("This project is standalone"),
+            "{command}: {error}"
+        );
+    }
+    assert!(!project.dir().join(".git").exists());
+}
+
+#[test]
+fn standalone_git_hook_does_nothing() {
+    let project = standalone_without_backend();
+    assert_eq!(project.ok(&["git-hook"]), "");
+
+    let explain = project.json(&["git-hook", "--explain"]);
+    assert_eq!(explain["action"], "skip");
+    assert_eq!(explain["git_branch"], Value::Null);
+}
+
+#[test]
+fn standalone_doctor_skips_the_git_checks() {
+    let project = standalone_without_backend();
+    let output = project.run(&["doctor"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[OK] Git integration: off (standalone project)"));
+    assert!(!stdout.contains("Git repository"));
+    assert!(!stdout.contains("Git hooks"));
+
+    // The general checks pass; only the default local backend's Docker is missing
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Docker"));
+}
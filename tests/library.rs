@@ -0,0 +1,69 @@
+//! Using pgbranch as a library against the mock backend, the way a test
+//! harness would. Run it with `cargo test --features backend-mock`.
+
+use pgbranch::errors::{code_of, ErrorCode};
+use pgbranch::Project;
+use tempfile::TempDir;
+
+const CONFIG: &str = "\
+standalone: true
+git:
+  auto_create_on_branch: true
+  auto_switch_on_branch: true
+  main_branch: main
+  exclude_branches:
+  - main
+behavior:
+  auto_cleanup: false
+  max_branches: 10
+  naming_strategy: prefix
+backends:
+- name: app
+  type: mock
+";
+
+#[tokio::test]
+async fn create_switch_list_and_delete_branches_from_rust() {
+    let home = TempDir::new().unwrap();
+    std::env::set_var("XDG_CONFIG_HOME", home.path().join("config"));
+    std::env::set_var("XDG_DATA_HOME", home.path().join("data"));
+    let config_path = home.path().join(".pgbranch.yml");
+    std::fs::write(&config_path, CONFIG).unwrap();
+
+    let project = Project::open(&config_path).unwrap();
+    assert_eq!(project.databases(), vec!["app".to_string()]);
+
+    let branch = project.create_branch(None, "test-run", None).await.unwrap();
+    let database = project.backend(None).await.unwrap();
+    assert_eq!(database.name, "app");
+    let connection = database
+        .backend
+        .get_connection_info(&branch.name)
+        .await
+        .unwrap();
+    assert_eq!(connection.database, branch.database_name);
+
+    let switched = project.switch_branch(None, "test-run").await.unwrap();
+    assert_eq!(switched.name, "test-run");
+    let names: Vec<_> = project
+        .list_branches(Some("app"))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+    assert!(names.contains(&"test-run".to_string()), "{names:?}");
+
+    let error = project
+        .create_branch(None, "-bad name", None)
+        .await
+        .unwrap_err();
+    assert_eq!(code_of(&error), ErrorCode::InvalidBranchName);
+
+    project.delete_branch(None, "test-run").await.unwrap();
+    let error = project.delete_branch(None, "test-run").await.unwrap_err();
+    assert_eq!(code_of(&error), ErrorCode::BranchNotFound);
+
+    let error = project.backend(Some("nope")).await.err().unwrap();
+    assert_eq!(code_of(&error), ErrorCode::DatabaseNotFound);
+}